@@ -3,20 +3,51 @@
 layout(location = 0) in vec2 oCoords;
 
 layout(binding = 0) uniform sampler2D inputImage;
+layout(binding = 1) uniform sampler2D depthSampler;
+
+layout(push_constant) uniform Config {
+    vec2 direction;
+    int radius;
+    float zNear;
+    float zFar;
+    uint reverseZ;
+    uint orthographic;
+} config;
 
 layout(location = 0) out vec4 finalColor;
 
+// 深度差占中心深度的比例，超过后权重迅速衰减
+const float DEPTH_SHARPNESS = 20.0;
+
+float linearDepth(vec2 uv) {
+    float depth = texture(depthSampler, uv).r;
+    // 正交投影的深度与距离成线性关系
+    if (config.orthographic == 1) {
+        float d = config.reverseZ == 1 ? 1.0 - depth : depth;
+        return config.zNear + d * (config.zFar - config.zNear);
+    }
+    if (config.reverseZ == 1) {
+        return (config.zNear * config.zFar) / (config.zNear + depth * (config.zFar - config.zNear));
+    }
+    return (config.zNear * config.zFar) / (config.zFar + depth * (config.zNear - config.zFar));
+}
+
+// 可分离的双边模糊，按方向执行两次，深度相差大的采样不参与平均
 void main() {
-	const int blurRange = 2;
-	int n = 0;
-	vec2 texelSize = 1.0 / vec2(textureSize(inputImage, 0));
-	float result = 0.0;
-	for (int x = -blurRange; x < blurRange; x++) {
-		for (int y = -blurRange; y < blurRange; y++) {
-			vec2 offset = vec2(float(x), float(y)) * texelSize;
-			result += texture(inputImage, oCoords + offset).r;
-			n++;
-		}
-	}
-	finalColor = vec4(vec3(result / float(n)), 1.0);
+    vec2 texelSize = 1.0 / vec2(textureSize(inputImage, 0));
+    float centerDepth = linearDepth(oCoords);
+    float sigma = max(float(config.radius) * 0.5, 1.0);
+
+    float result = 0.0;
+    float totalWeight = 0.0;
+    for (int i = -config.radius; i <= config.radius; i++) {
+        vec2 coords = oCoords + config.direction * texelSize * float(i);
+        float depth = linearDepth(coords);
+        float spatialWeight = exp(-float(i * i) / (2.0 * sigma * sigma));
+        float depthWeight = exp(-abs(depth - centerDepth) / centerDepth * DEPTH_SHARPNESS);
+        float weight = spatialWeight * depthWeight;
+        result += texture(inputImage, coords).r * weight;
+        totalWeight += weight;
+    }
+    finalColor = vec4(vec3(result / totalWeight), 1.0);
 }
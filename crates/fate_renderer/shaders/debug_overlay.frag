@@ -0,0 +1,20 @@
+#version 450
+
+layout(location = 0) in vec2 oCoords;
+
+layout(binding = 0) uniform sampler2D inputImage;
+
+layout(location = 0) out vec4 finalColor;
+
+layout(push_constant) uniform Constants {
+    uint singleChannel;
+} c;
+
+void main() {
+    vec4 color = texture(inputImage, oCoords);
+    if (c.singleChannel == 1) {
+        finalColor = vec4(vec3(color.r), 1.0);
+    } else {
+        finalColor = vec4(color.rgb, 1.0);
+    }
+}
@@ -6,13 +6,25 @@ layout(binding = 0) uniform sampler2D srcTexture;
 
 layout(push_constant) uniform Constants {
     vec2 srcResolution;
-} c;
+    float threshold;
+    uint prefilter;
+} params;
 
 layout(location = 0) out vec3 downsample;
 
+// 亮度低于阈值的像素不参与bloom，阈值附近用二次曲线平滑过渡
+vec3 applyThreshold(vec3 color) {
+    float brightness = max(color.r, max(color.g, color.b));
+    float knee = params.threshold * 0.5;
+    float soft = clamp(brightness - params.threshold + knee, 0.0, 2.0 * knee);
+    soft = soft * soft / (4.0 * knee + 0.0001);
+    float contribution = max(soft, brightness - params.threshold) / max(brightness, 0.0001);
+    return color * contribution;
+}
+
 void main() {
 
-    vec2 srcTexelSize = 1.0 / c.srcResolution;
+    vec2 srcTexelSize = 1.0 / params.srcResolution;
     float x = srcTexelSize.x;
     float y = srcTexelSize.y;
 
@@ -37,4 +49,9 @@ void main() {
     downsample += (a+c+g+i)*0.03125;
     downsample += (b+d+f+h)*0.0625;
     downsample += (j+k+l+m)*0.125;
+
+    // 只在从场景颜色降采样的第一级做阈值处理
+    if (params.prefilter == 1) {
+        downsample = applyThreshold(downsample);
+    }
 }
@@ -15,6 +15,8 @@ const uint TONE_MAP_MODE_ACES = 3;
 
 layout(push_constant) uniform Constants {
     float bloomStrength;
+    float exposure;
+    float whitePoint;
 } c;
 
 const float GAMMA = 2.2;
@@ -35,9 +37,8 @@ vec3 toneMapUncharted2Impl(vec3 color) {
 }
 
 vec3 toneMapUncharted(vec3 color) {
-    const float W = 11.2;
     color = toneMapUncharted2Impl(color * 2.0);
-    vec3 whiteScale = 1.0 / toneMapUncharted2Impl(vec3(W));
+    vec3 whiteScale = 1.0 / toneMapUncharted2Impl(vec3(c.whitePoint));
     return LINEARtoSRGB(color * whiteScale);
 }
 
@@ -61,9 +62,10 @@ vec3 defaultToneMap(vec3 color) {
 }
 
 void main() {
-    vec3 color = texture(inputImage, oCoords).rgb;
+    vec4 sceneColor = texture(inputImage, oCoords);
+    vec3 color = sceneColor.rgb;
     vec3 bloom = texture(bloomImage, oCoords).rgb;
-    vec3 bloomed = mix(color, bloom, c.bloomStrength);
+    vec3 bloomed = mix(color, bloom, c.bloomStrength) * c.exposure;
 
     if (TONE_MAP_MODE == TONE_MAP_MODE_DEFAULT) {
         color = defaultToneMap(bloomed);
@@ -77,5 +79,6 @@ void main() {
         color = LINEARtoSRGB(bloomed);
     }
 
-    finalColor = vec4(color, 1.0);
+    //保留场景的alpha，背景色透明时输出透明背景
+    finalColor = vec4(color, sceneColor.a);
 }
@@ -19,6 +19,7 @@ layout(push_constant) uniform Constants {
     float AbsoluteLuminanceThreshold;
     float RelativeLuminanceThreshold;
     float SubpixelBlending;
+    float Sharpness;
 } c;
 
 float linearRgbToLuminance(vec3 linearRgb)
@@ -186,7 +187,22 @@ float getEdgeBlendFactor (LuminanceNeighborhood luminance, FXAAEdge edge, vec2 u
 	}
 }
 
+//反锐化掩模，补偿FXAA带来的模糊
+vec4 sharpen(vec4 color, vec2 uv) {
+    if (c.Sharpness <= 0.0) {
+        return color;
+    }
+    vec2 texelSize = 1.0 / textureSize(srcImage, 0).xy;
+    vec3 blur = getSource(uv + vec2(texelSize.x, 0.0)).rgb;
+    blur += getSource(uv - vec2(texelSize.x, 0.0)).rgb;
+    blur += getSource(uv + vec2(0.0, texelSize.y)).rgb;
+    blur += getSource(uv - vec2(0.0, texelSize.y)).rgb;
+    blur *= 0.25;
+    return vec4(clamp(color.rgb + (color.rgb - blur) * c.Sharpness, 0.0, 1.0), color.a);
+}
+
 void main() {
+    vec4 color = getSource(oCoords);
     if (FXAA_MODE == FXAA_QUALITY)
     {
         LuminanceNeighborhood luminance = getLuminanceNeighborhood(oCoords);
@@ -203,16 +219,9 @@ void main() {
 	        else {
 	        	blendUV.x += blendFactor * edge.pixelStep;
 	        }
-            outColor = getSource(blendUV);
+            color = getSource(blendUV);
 	    }
-        else
-        {
-	    	outColor = getSource(oCoords);
-        }
     }
-    else 
-    {
-        //FXAA_CONSOLE先当关闭用，没实现速度版本，只有高质量版本
-	    outColor = getSource(oCoords);
-    }
-}
\ No newline at end of file
+    //FXAA_CONSOLE先当关闭用，没实现速度版本，只有高质量版本
+    outColor = sharpen(color, oCoords);
+}
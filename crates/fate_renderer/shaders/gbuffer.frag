@@ -8,23 +8,30 @@ layout(location = 0) in vec3 oViewSpaceNormal;
 layout(location = 1) in vec2 oTexcoords0;
 layout(location = 2) in vec2 oTexcoords1;
 layout(location = 3) in float oAlpha;
+layout(location = 4) flat in uint oPickId;
+layout(location = 5) in vec4 oCurrentClip;
+layout(location = 6) in vec4 oPreviousClip;
 
 layout(push_constant) uniform MaterialUniform {
     float alpha;
     uint colorTextureChannel;
     uint alphaMode;
     float alphaCutoff;
+    //KHR_texture_transform，前一个vec4是2x2矩阵的两行，后一个的xy是平移
+    vec4 colorTransformRotationScale;
+    vec4 colorTransformOffset;
 } material;
 
 layout(binding = 3, set = 1) uniform sampler2D colorSampler;
 
 layout(location = 0) out vec4 outNormals;
+layout(location = 1) out uint outPickId;
+layout(location = 2) out vec2 outMotion;
 
 vec2 getUV(uint texChannel) {
-    if (texChannel == 0) {
-        return oTexcoords0;
-    }
-    return oTexcoords1;
+    vec2 uv = texChannel == 0 ? oTexcoords0 : oTexcoords1;
+    vec4 rotationScale = material.colorTransformRotationScale;
+    return vec2(dot(rotationScale.xy, uv), dot(rotationScale.zw, uv)) + material.colorTransformOffset.xy;
 }
 
 float getAlpha(uint textureChannel) {
@@ -47,5 +54,14 @@ void main() {
         discard;
     }
 
-    outNormals = vec4(normalize(oViewSpaceNormal), 0.0);
+    //双面材质的背面需要翻转法线
+    vec3 normal = normalize(oViewSpaceNormal);
+    if (!gl_FrontFacing) {
+        normal *= -1.0;
+    }
+
+    outNormals = vec4(normal, 0.0);
+    outPickId = oPickId;
+    //NDC下当前帧减上一帧的位移，按像素插值后再做透视除法
+    outMotion = oCurrentClip.xy / oCurrentClip.w - oPreviousClip.xy / oPreviousClip.w;
 }
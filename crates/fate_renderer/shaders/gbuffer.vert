@@ -16,6 +16,9 @@ layout(binding = 0, set = 0) uniform CameraUBO {
     vec4 eye;
     float zNear;
     float zFar;
+    uint reverseZ;
+    mat4 envRotation;
+    mat4 previousViewProj;
 } cameraUBO;
 
 layout(binding = 1, set = 0) uniform TransformUBO {
@@ -26,24 +29,65 @@ layout(binding = 2, set = 0) uniform SkinUBO {
     mat4 jointMatrices[512];
 } skin;
 
+layout(binding = 4, set = 0) readonly buffer InstanceTransforms {
+    mat4 matrices[];
+} instances;
+
+layout(binding = 5, set = 0) readonly buffer InstancePickIds {
+    uint ids[];
+} instancePickIds;
+
+//上一帧的变换，用于计算运动矢量
+layout(binding = 6, set = 0) uniform PreviousTransformUBO {
+    mat4 matrix;
+} previousTransform;
+
+layout(binding = 7, set = 0) uniform PreviousSkinUBO {
+    mat4 jointMatrices[512];
+} previousSkin;
+
+layout(binding = 8, set = 0) readonly buffer PreviousInstanceTransforms {
+    mat4 matrices[];
+} previousInstances;
+
+//前48字节是片元着色器的材质参数
+layout(push_constant) uniform InstanceConstants {
+    layout(offset = 48) uint instanced;
+    //实例化绘制时只包含模型序号，节点ID从instancePickIds读取
+    uint pickId;
+} instance;
+
 layout(location = 0) out vec3 oViewSpaceNormal;
 layout(location = 1) out vec2 oTexcoords0;
 layout(location = 2) out vec2 oTexcoords1;
 layout(location = 3) out float oAlpha;
+layout(location = 4) flat out uint oPickId;
+layout(location = 5) out vec4 oCurrentClip;
+layout(location = 6) out vec4 oPreviousClip;
 
 void main() {
-    mat4 world = transform.matrix;
+    //实例化绘制时按gl_InstanceIndex读取变换
+    mat4 world = instance.instanced != 0 ? instances.matrices[gl_InstanceIndex] : transform.matrix;
+    mat4 previousWorld = instance.instanced != 0 ? previousInstances.matrices[gl_InstanceIndex] : previousTransform.matrix;
+    //计算着色器蒙皮后权重被清零，此时只能得到节点和相机的运动
     if (vWeights != vec4(0.0)) {
         world *= vWeights.x * skin.jointMatrices[vJoints.x]
             + vWeights.y * skin.jointMatrices[vJoints.y]
             + vWeights.z * skin.jointMatrices[vJoints.z]
             + vWeights.w * skin.jointMatrices[vJoints.w];
+        previousWorld *= vWeights.x * previousSkin.jointMatrices[vJoints.x]
+            + vWeights.y * previousSkin.jointMatrices[vJoints.y]
+            + vWeights.z * previousSkin.jointMatrices[vJoints.z]
+            + vWeights.w * previousSkin.jointMatrices[vJoints.w];
     }
 
     oViewSpaceNormal = normalize((cameraUBO.view * world * vec4(vNormals, 0.0)).xyz);
     oTexcoords0 = vTexcoords0;
     oTexcoords1 = vTexcoords1;
     oAlpha = vColors.a;
+    oPickId = instance.instanced != 0 ? instance.pickId | instancePickIds.ids[gl_InstanceIndex] : instance.pickId;
 
     gl_Position = cameraUBO.proj * cameraUBO.view * world * vec4(vPositions, 1.0);
+    oCurrentClip = gl_Position;
+    oPreviousClip = cameraUBO.previousViewProj * previousWorld * vec4(vPositions, 1.0);
 }
@@ -0,0 +1,31 @@
+#version 450
+
+layout(binding = 0) uniform CameraUBO {
+    mat4 view;
+    mat4 proj;
+    mat4 invertedProj;
+    vec4 eye;
+    float zNear;
+    float zFar;
+} cameraUBO;
+
+layout(push_constant) uniform Constants {
+    float cellSize;
+    float fadeDistance;
+    uint halfLineCount;
+} c;
+
+layout(location = 0) in vec3 oWorldPos;
+layout(location = 1) in vec4 oColor;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    float distance = length(oWorldPos.xz - cameraUBO.eye.xz);
+    float fade = 1.0 - smoothstep(c.fadeDistance * 0.5, c.fadeDistance, distance);
+    if (fade <= 0.0) {
+        discard;
+    }
+
+    outColor = vec4(oColor.rgb, oColor.a * fade);
+}
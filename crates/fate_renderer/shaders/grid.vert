@@ -0,0 +1,57 @@
+#version 450
+
+layout(binding = 0) uniform CameraUBO {
+    mat4 view;
+    mat4 proj;
+    mat4 invertedProj;
+    vec4 eye;
+    float zNear;
+    float zFar;
+} cameraUBO;
+
+layout(push_constant) uniform Constants {
+    float cellSize;
+    float fadeDistance;
+    uint halfLineCount;
+} c;
+
+layout(location = 0) out vec3 oWorldPos;
+layout(location = 1) out vec4 oColor;
+
+const vec4 GRID_COLOR = vec4(0.5, 0.5, 0.5, 0.6);
+const vec3 AXIS_COLORS[3] = vec3[](vec3(0.9, 0.2, 0.2), vec3(0.2, 0.9, 0.2), vec3(0.2, 0.4, 0.9));
+
+//前面是网格线，每条线两个顶点，沿X和Z各2*halfLineCount+1条；最后6个顶点是坐标轴
+void main() {
+    uint lineCount = 2 * c.halfLineCount + 1;
+    uint gridVertexCount = lineCount * 4;
+    float halfExtent = float(c.halfLineCount) * c.cellSize;
+
+    vec3 position;
+    if (gl_VertexIndex < gridVertexCount) {
+        //网格跟随相机，按格子大小对齐避免移动时网格滑动
+        vec2 center = floor(cameraUBO.eye.xz / c.cellSize) * c.cellSize;
+
+        uint lineIndex = gl_VertexIndex / 2;
+        float side = (gl_VertexIndex % 2 == 0) ? -halfExtent : halfExtent;
+        float offset = (float(lineIndex % lineCount) - float(c.halfLineCount)) * c.cellSize;
+
+        if (lineIndex < lineCount) {
+            position = vec3(center.x + offset, 0.0, center.y + side);
+        } else {
+            position = vec3(center.x + side, 0.0, center.y + offset);
+        }
+        oColor = GRID_COLOR;
+    } else {
+        uint axisVertex = gl_VertexIndex - gridVertexCount;
+        uint axis = axisVertex / 2;
+        position = vec3(0.0);
+        if (axisVertex % 2 == 1) {
+            position[axis] = halfExtent;
+        }
+        oColor = vec4(AXIS_COLORS[axis], 1.0);
+    }
+
+    oWorldPos = position;
+    gl_Position = cameraUBO.proj * cameraUBO.view * vec4(position, 1.0);
+}
@@ -1,8 +1,10 @@
 #version 450
 #define FOG_LINEAR
 
-layout(constant_id = 0) const uint MAX_LIGHT_COUNT = 1;
+layout(constant_id = 0) const uint MAX_LIGHTS_PER_CLUSTER = 1;
 layout(constant_id = 1) const uint MAX_REFLECTION_LOD = 1;
+//只有MASK管线做alpha测试，其它管线不含discard以保留early-z
+layout(constant_id = 2) const bool ALPHA_TEST = false;
 
 const uint OUTPUT_MODE_FINAL = 0;
 const uint OUTPUT_MODE_COLOR = 1;
@@ -16,6 +18,11 @@ const uint OUTPUT_MODE_ALPHA = 8;
 const uint OUTPUT_MODE_UVS0 = 9;
 const uint OUTPUT_MODE_UVS1 = 10;
 const uint OUTPUT_MODE_SSAO = 11;
+const uint OUTPUT_MODE_VERTEX_COLOR = 12;
+const uint OUTPUT_MODE_UNLIT = 13;
+const uint OUTPUT_MODE_IRRADIANCE = 14;
+const uint OUTPUT_MODE_PRE_FILTERED = 15;
+const uint OUTPUT_MODE_BRDF_LOOKUP = 16;
 
 const vec3 DIELECTRIC_SPECULAR = vec3(0.04);
 const vec3 BLACK = vec3(0.0);
@@ -32,15 +39,27 @@ const uint POINT_LIGHT_TYPE = 1;
 const uint SPOT_LIGHT_TYPE = 2;
 
 const uint UNLIT_FLAG_UNLIT = 1;
+const uint VERTEX_COLORS_FLAG = 2;
+const uint DERIVATIVE_TANGENTS_FLAG = 4;
 
 const uint METALLIC_ROUGHNESS_WORKFLOW = 0;
 
+const uint MAX_SHADOW_CASCADES = 4;
+const uint MAX_PUNCTUAL_SHADOW_LAYERS = 12;
+//级联边界处混合的范围，占当前级联长度的比例
+const float CASCADE_BLEND_RANGE = 0.1;
+
+const uint SHADOW_FILTER_HARD = 0;
+const uint SHADOW_FILTER_PCF = 1;
+const uint SHADOW_FILTER_PCSS = 2;
+
 struct TextureChannels {
     uint color;
     uint material;
     uint emissive;
     uint normal;
     uint occlusion;
+    uint height;
 };
 
 struct Light {
@@ -53,6 +72,8 @@ struct Light {
     float angleScale;
     float angleOffset;
     uint type;
+    //点光源/聚光灯shadow map的第一层，-1表示不投射阴影
+    int shadowLayer;
 };
 
 struct PbrInfo {
@@ -85,13 +106,26 @@ layout(push_constant) uniform MaterialUniform {
     uint colorMetallicRoughnessEmissiveNormalTextureChannels;
     // [0-7] Occlusion通道数
     // [8-15] Alpha mode
-    // [16-23] Unlit flag
+    // [16-23] Unlit flag | 顶点色flag | 导数切线flag
     // [24-31] Workflow
     uint occlusionTextureChannelAlphaModeUnlitFlagAndWorkflow;
     float alphaCutoff;
+    // [0-7] Occlusion读取的颜色通道
+    // [8-15] Roughness读取的颜色通道
+    // [16-23] Metallic读取的颜色通道
+    // [24-31] Height通道数
+    uint textureSwizzle;
+    float heightScale;
+    //切线空间法线xy分量的缩放
+    float normalScale;
     uint lightCount;
     uint outputMode;
     float emissiveIntensity;
+    float iblDebugRoughness;
+    // [0-7] x [8-15] y [16-23] z，0表示不分簇
+    uint lightClusterGrid;
+    //视差遮蔽映射的最大步进次数，0表示关闭
+    uint parallaxSteps;
 } material;
 
 layout(binding = 0, set = 0) uniform Camera {
@@ -101,20 +135,36 @@ layout(binding = 0, set = 0) uniform Camera {
     vec4 eye;
     float zNear;
     float zFar;
+    uint reverseZ;
+    mat4 envRotation;
 } cameraUBO;
 
-layout(binding = 1, set = 0) uniform Lights {
-    Light lights[MAX_LIGHT_COUNT];
+layout(binding = 1, set = 0) readonly buffer Lights {
+    Light lights[];
 } lights;
 
+//每个簇MAX_LIGHTS_PER_CLUSTER + 1个uint，第一个是灯数
+layout(binding = 17, set = 0) readonly buffer LightClusters {
+    uint data[];
+} lightClusters;
+
 layout(binding = 4, set = 0) uniform RenderData {
-    mat4 mainLightSpaceMatrix;
+    mat4 cascadeMatrices[MAX_SHADOW_CASCADES];
+    mat4 punctualShadowMatrices[MAX_PUNCTUAL_SHADOW_LAYERS];
+    vec4 cascadeSplits;
+    vec4 cascadeTexelSizes;
     vec4 mainLightPosition;
     vec4 mainLightDirection;
     vec4 mainLightColor;
     vec4 fogParams;
     vec4 fogColor;
     float mainLightIntensity;
+    float ambientIntensity;
+    uint cascadeCount;
+    uint shadowFilterMode;
+    float shadowDepthBias;
+    float shadowNormalBias;
+    uint shadowPcfKernelSize;
 } renderData;
 
 layout(binding = 5, set = 1) uniform samplerCube irradianceMapSampler;
@@ -125,8 +175,25 @@ layout(binding = 9, set = 2) uniform sampler2D normalsSampler;
 layout(binding = 10, set = 2) uniform sampler2D materialSampler;
 layout(binding = 11, set = 2) uniform sampler2D occlusionSampler;
 layout(binding = 12, set = 2) uniform sampler2D emissiveSampler;
-layout(binding = 13, set = 3) uniform sampler2D shadowMapSampler;
+layout(binding = 18, set = 2) uniform sampler2D heightSampler;
+
+//KHR_texture_transform，rotationScale是2x2矩阵的两行，offset.xy是平移
+struct TextureTransform {
+    vec4 rotationScale;
+    vec4 offset;
+};
+
+layout(binding = 16, set = 2) uniform TextureTransforms {
+    TextureTransform color;
+    TextureTransform material;
+    TextureTransform emissive;
+    TextureTransform normal;
+    TextureTransform occlusion;
+    TextureTransform height;
+} textureTransforms;
+layout(binding = 13, set = 3) uniform sampler2DArray shadowMapSampler;
 layout(binding = 14, set = 3) uniform sampler2D aoMapSampler;
+layout(binding = 19, set = 3) uniform sampler2DArray punctualShadowMapSampler;
 
 layout(location = 0) out vec4 outColor;
 
@@ -164,95 +231,129 @@ void poissonDiskSamples(const in vec2 randomSeed) {
   }
 }
 
-float findBlock(vec2 uv, float depth, float bias){
+//未被遮挡返回1，被遮挡返回0
+float sampleShadow(vec2 uv, float depth, uint cascade) {
+    float closestDepth = texture(shadowMapSampler, vec3(uv, cascade)).r;
+    return depth > closestDepth ? 0.0 : 1.0;
+}
+
+float findBlock(vec2 uv, float depth, uint cascade){
     float texturesize = textureSize(shadowMapSampler, 0).x;
     float stride = 10.0;
     float filterRange = stride / texturesize;
-    poissonDiskSamples(uv);
 
     float avgDepth = 0.0;
     int count = 0;
     for(int i = 0;i < BLOCKER_SEARCH_NUM_SAMPLES; ++i){
         vec2 sampleUV = uv + filterRange * poissonDisk[i];
-        float blockDepth = texture(shadowMapSampler, sampleUV).r;
-        float pcfBias = bias * (1 + distance(sampleUV, uv));
-        if(depth - pcfBias > blockDepth){
+        float blockDepth = texture(shadowMapSampler, vec3(sampleUV, cascade)).r;
+        if(depth > blockDepth){
             avgDepth += blockDepth;
             ++count;
         }    
     }
+    //没有遮挡物
+    if (count == 0) {
+        return -1.0;
+    }
     return avgDepth/float(count);
 }
 
-float calculateShadowPCSS()
+float filterShadowPCSS(vec2 uv, float depth, uint cascade)
 {
     float texturesize = textureSize(shadowMapSampler, 0).x;
     float filterSize = 1.0;
+    poissonDiskSamples(uv);
 
-    vec4 fragPosLightSpace = renderData.mainLightSpaceMatrix * vec4(oPositions, 1.0);
-    float currentDepth = fragPosLightSpace.z;
-    vec3 projCoords = fragPosLightSpace.xyz / fragPosLightSpace.w;
-    projCoords = projCoords * 0.5 + 0.5;
-    poissonDiskSamples(projCoords.xy);
-
-    vec3 lightDir = renderData.mainLightDirection.xyz;
-    vec3 normal = normalize(oNormals);
-    float bias = max(0.05 * (1.0 - dot(normal, lightDir)), 0.005);
-
-    float block = findBlock(projCoords.xy, currentDepth, bias);
-    float penumbra = (currentDepth - block)/block;
+    float block = findBlock(uv, depth, cascade);
+    if (block < 0.0) {
+        return 1.0;
+    }
+    float penumbra = (depth - block)/block;
     float filterRange = (penumbra * filterSize) / texturesize;
 
     float shadow = 0.0;
     for(int i = 0; i < NUM_SAMPLES; ++i){
-        vec2 sampleUV = projCoords.xy + filterRange * poissonDisk[i];
-        float closestDepth = texture(shadowMapSampler, sampleUV).r;
-        //TODO：这里还是计算的不太对，缺了frustumSize/(shadowMapSize*2)项
-        //目前是透视投影的shadowmap，先不加，后续改成正交投影的时候加上
-        float pcfBias = bias * (1 + distance(sampleUV, projCoords.xy));
-        shadow += currentDepth - pcfBias > closestDepth  ? 0.0 : 1.0;
-    }
-    shadow /= float(NUM_SAMPLES);
-    
-    if(projCoords.z > 1.0)
-    {
-        shadow = 0.0;
+        shadow += sampleShadow(uv + filterRange * poissonDisk[i], depth, cascade);
     }
-    return shadow;
+    return shadow / float(NUM_SAMPLES);
 }
 
-float calculateShadow()
+float filterShadowPCF(vec2 uv, float depth, uint cascade)
 {
-    float texturesize = textureSize(shadowMapSampler, 0).x;
-    float stride = 5.0;
-    float filterRange = stride/texturesize;
+    vec2 texelSize = 1.0 / vec2(textureSize(shadowMapSampler, 0).xy);
+    int halfKernel = int(renderData.shadowPcfKernelSize) / 2;
 
-    vec4 fragPosLightSpace = renderData.mainLightSpaceMatrix * vec4(oPositions, 1.0);
-    float currentDepth = fragPosLightSpace.z;
-    vec3 projCoords = fragPosLightSpace.xyz / fragPosLightSpace.w;
-    projCoords = projCoords * 0.5 + 0.5;
-    poissonDiskSamples(projCoords.xy);
+    float shadow = 0.0;
+    for (int x = -halfKernel; x <= halfKernel; ++x) {
+        for (int y = -halfKernel; y <= halfKernel; ++y) {
+            shadow += sampleShadow(uv + vec2(x, y) * texelSize, depth, cascade);
+        }
+    }
+    float kernelSize = float(2 * halfKernel + 1);
+    return shadow / (kernelSize * kernelSize);
+}
 
-    vec3 lightDir = renderData.mainLightDirection.xyz;
+float calculateCascadeShadow(uint cascade)
+{
+    vec3 lightDir = normalize(renderData.mainLightDirection.xyz);
     vec3 normal = normalize(oNormals);
-    float bias = max(0.05 * (1.0 - dot(normal, lightDir)), 0.005);
+    float NdotL = clamp(dot(normal, -lightDir), 0.0, 1.0);
 
-    float shadow = 0.0;
-    for(int i = 0; i < NUM_SAMPLES; ++i){
-        vec2 sampleUV = projCoords.xy + filterRange * poissonDisk[i];
-        float closestDepth = texture(shadowMapSampler, sampleUV).r;
-        //TODO：这里还是计算的不太对，缺了frustumSize/(shadowMapSize*2)项
-        //目前是透视投影的shadowmap，先不加，后续改成正交投影的时候加上
-        float pcfBias = bias * (1 + distance(sampleUV, projCoords.xy));
-        shadow += currentDepth - pcfBias > closestDepth  ? 1.0 : 0.0;
-    }
-    shadow /= float(NUM_SAMPLES);
-    
+    //沿法线偏移采样点，偏移量按级联的texel大小缩放
+    float normalOffset = renderData.shadowNormalBias * renderData.cascadeTexelSizes[cascade];
+    vec3 position = oPositions + normal * normalOffset * (1.0 - NdotL);
+
+    vec4 fragPosLightSpace = renderData.cascadeMatrices[cascade] * vec4(position, 1.0);
+    vec3 projCoords = fragPosLightSpace.xyz / fragPosLightSpace.w;
     if(projCoords.z > 1.0)
     {
-        shadow = 0.0;
+        return 1.0;
+    }
+    vec2 uv = projCoords.xy * 0.5 + 0.5;
+
+    //掠射角下需要更大的深度偏移
+    float bias = max(renderData.shadowDepthBias * 10.0 * (1.0 - NdotL), renderData.shadowDepthBias);
+    float depth = projCoords.z - bias;
+
+    if (renderData.shadowFilterMode == SHADOW_FILTER_PCSS) {
+        return filterShadowPCSS(uv, depth, cascade);
     }
-    return 1.0 - shadow;
+    if (renderData.shadowFilterMode == SHADOW_FILTER_PCF) {
+        return filterShadowPCF(uv, depth, cascade);
+    }
+    return sampleShadow(uv, depth, cascade);
+}
+
+//按观察空间深度选择级联，边界附近和下一级联混合
+float calculateShadow()
+{
+    if (renderData.cascadeCount == 0) {
+        return 1.0;
+    }
+
+    float depth = -(cameraUBO.view * vec4(oPositions, 1.0)).z;
+    uint cascade = renderData.cascadeCount - 1;
+    for (uint i = 0; i < renderData.cascadeCount; ++i) {
+        if (depth < renderData.cascadeSplits[i]) {
+            cascade = i;
+            break;
+        }
+    }
+
+    float shadow = calculateCascadeShadow(cascade);
+
+    if (cascade + 1 < renderData.cascadeCount) {
+        float splitNear = cascade == 0 ? cameraUBO.zNear : renderData.cascadeSplits[cascade - 1];
+        float splitFar = renderData.cascadeSplits[cascade];
+        float blendStart = splitFar - (splitFar - splitNear) * CASCADE_BLEND_RANGE;
+        if (depth > blendStart) {
+            float t = (depth - blendStart) / (splitFar - blendStart);
+            shadow = mix(shadow, calculateCascadeShadow(cascade + 1), t);
+        }
+    }
+
+    return shadow;
 }
 
 
@@ -262,31 +363,47 @@ TextureChannels getTextureChannels() {
         (material.colorMetallicRoughnessEmissiveNormalTextureChannels >> 16) & 255,
         (material.colorMetallicRoughnessEmissiveNormalTextureChannels >> 8) & 255,
         material.colorMetallicRoughnessEmissiveNormalTextureChannels & 255,
-        (material.occlusionTextureChannelAlphaModeUnlitFlagAndWorkflow >> 24) & 255
+        (material.occlusionTextureChannelAlphaModeUnlitFlagAndWorkflow >> 24) & 255,
+        material.textureSwizzle & 255
     );
 }
 
-vec2 getUV(uint texChannel) {
-    if (texChannel == 0) {
-        return oTexcoords0;
-    }
-    return oTexcoords1;
+bool useVertexColors() {
+    uint flags = (material.occlusionTextureChannelAlphaModeUnlitFlagAndWorkflow >> 8) & 255;
+    return (flags & VERTEX_COLORS_FLAG) != 0;
+}
+
+bool useDerivativeTangents() {
+    uint flags = (material.occlusionTextureChannelAlphaModeUnlitFlagAndWorkflow >> 8) & 255;
+    return (flags & DERIVATIVE_TANGENTS_FLAG) != 0;
+}
+
+//视差遮蔽映射偏移后的纹理坐标，所有贴图都从这里采样
+vec2 texcoords0;
+vec2 texcoords1;
+
+vec2 getUV(uint texChannel, TextureTransform transform) {
+    vec2 uv = texChannel == 0 ? texcoords0 : texcoords1;
+    return vec2(dot(transform.rotationScale.xy, uv), dot(transform.rotationScale.zw, uv)) + transform.offset.xy;
 }
 
 vec4 getBaseColor(TextureChannels textureChannels) {
     vec4 color = material.color;
     if(textureChannels.color != NO_TEXTURE_ID) {
-        vec2 uv = getUV(textureChannels.color);
+        vec2 uv = getUV(textureChannels.color, textureTransforms.color);
         color *= texture(colorSampler, uv);
     }
-    return color * oColors;
+    if (useVertexColors()) {
+        color *= oColors;
+    }
+    return color;
 }
 
 float getMetallic(TextureChannels textureChannels) {
     float metallic = material.metallicSpecularAndOcclusion.r;
     if(textureChannels.material != NO_TEXTURE_ID) {
-        vec2 uv = getUV(textureChannels.material);
-        metallic *= texture(materialSampler, uv).b;
+        vec2 uv = getUV(textureChannels.material, textureTransforms.material);
+        metallic *= texture(materialSampler, uv)[(material.textureSwizzle >> 8) & 255];
     }
     return metallic;
 }
@@ -294,7 +411,7 @@ float getMetallic(TextureChannels textureChannels) {
 vec3 getSpecular(TextureChannels textureChannels) {
     vec3 specular = material.metallicSpecularAndOcclusion.rgb;
     if(textureChannels.material != NO_TEXTURE_ID) {
-        vec2 uv = getUV(textureChannels.material);
+        vec2 uv = getUV(textureChannels.material, textureTransforms.material);
         specular *= texture(materialSampler, uv).rgb;
     }
     return specular;
@@ -317,9 +434,9 @@ float convertMetallic(vec3 diffuse, vec3 specular, float maxSpecular) {
 float getRoughness(TextureChannels textureChannels, bool metallicRoughnessWorkflow) {
     float roughness = material.emissiveAndRoughnessGlossiness.a;
     if(textureChannels.material != NO_TEXTURE_ID) {
-        vec2 uv = getUV(textureChannels.material);
+        vec2 uv = getUV(textureChannels.material, textureTransforms.material);
         if (metallicRoughnessWorkflow) {
-            roughness *= texture(materialSampler, uv).g;
+            roughness *= texture(materialSampler, uv)[(material.textureSwizzle >> 16) & 255];
         } else {
             roughness *= texture(materialSampler, uv).a;
         }
@@ -334,18 +451,43 @@ float getRoughness(TextureChannels textureChannels, bool metallicRoughnessWorkfl
 vec3 getEmissiveColor(TextureChannels textureChannels) {
     vec3 emissive = material.emissiveAndRoughnessGlossiness.rgb;
     if(textureChannels.emissive != NO_TEXTURE_ID) {
-        vec2 uv = getUV(textureChannels.emissive);
+        vec2 uv = getUV(textureChannels.emissive, textureTransforms.emissive);
         emissive *= texture(emissiveSampler, uv).rgb;
     }
     return emissive * material.emissiveIntensity;
 }
 
+//顶点切线不可用时用位置和UV的屏幕空间导数求解切线空间，UV退化时返回false
+bool getDerivativeTBN(vec3 normal, vec2 uv, out mat3 tbn) {
+    vec3 dp1 = dFdx(oPositions);
+    vec3 dp2 = dFdy(oPositions);
+    vec2 duv1 = dFdx(uv);
+    vec2 duv2 = dFdy(uv);
+
+    vec3 dp2perp = cross(dp2, normal);
+    vec3 dp1perp = cross(normal, dp1);
+    vec3 tangent = dp2perp * duv1.x + dp1perp * duv2.x;
+    vec3 bitangent = dp2perp * duv1.y + dp1perp * duv2.y;
+
+    float maxLength2 = max(dot(tangent, tangent), dot(bitangent, bitangent));
+    if (maxLength2 <= 1e-20) {
+        return false;
+    }
+    float invMaxLength = inversesqrt(maxLength2);
+    tbn = mat3(tangent * invMaxLength, bitangent * invMaxLength, normal);
+    return true;
+}
+
 vec3 getNormal(TextureChannels textureChannels) {
     vec3 normal = normalize(oNormals);
     if (textureChannels.normal != NO_TEXTURE_ID) {
-        vec2 uv = getUV(textureChannels.normal);
+        vec2 uv = getUV(textureChannels.normal, textureTransforms.normal);
         vec3 normalMap = texture(normalsSampler, uv).rgb * 2.0 - 1.0;
-        normal = normalize(oTBN * normalMap);
+        normalMap.xy *= material.normalScale;
+        mat3 tbn = oTBN;
+        if (!useDerivativeTangents() || getDerivativeTBN(normal, uv, tbn)) {
+            normal = normalize(tbn * normalMap);
+        }
     }
     
     if (!gl_FrontFacing) {
@@ -355,6 +497,67 @@ vec3 getNormal(TextureChannels textureChannels) {
     return normal;
 }
 
+//循环里不能用隐式导数，按纹理变换换算梯度后显式指定
+float sampleHeight(vec2 texcoords, vec2 dx, vec2 dy) {
+    mat2 rotationScale = transpose(mat2(textureTransforms.height.rotationScale));
+    vec2 uv = rotationScale * texcoords + textureTransforms.height.offset.xy;
+    return textureGrad(heightSampler, uv, rotationScale * dx, rotationScale * dy).r;
+}
+
+//在切线空间沿视线逐层步进高度图，找到与高度场的交点后在最后两层之间线性插值
+vec2 parallaxOcclusionMapping(vec2 texcoords, vec3 viewTS) {
+    vec2 dx = dFdx(texcoords);
+    vec2 dy = dFdy(texcoords);
+
+    //掠射角时需要更多的层
+    float layerCount = mix(float(material.parallaxSteps), max(float(material.parallaxSteps) * 0.25, 1.0), viewTS.z);
+    float layerDepth = 1.0 / layerCount;
+    vec2 deltaTexcoords = viewTS.xy / max(viewTS.z, 0.05) * material.heightScale * layerDepth;
+
+    vec2 currentTexcoords = texcoords;
+    float currentLayerDepth = 0.0;
+    float currentDepth = 1.0 - sampleHeight(currentTexcoords, dx, dy);
+    for (uint i = 0; i < material.parallaxSteps && currentLayerDepth < currentDepth; i++) {
+        currentTexcoords -= deltaTexcoords;
+        currentDepth = 1.0 - sampleHeight(currentTexcoords, dx, dy);
+        currentLayerDepth += layerDepth;
+    }
+
+    vec2 previousTexcoords = currentTexcoords + deltaTexcoords;
+    float after = currentDepth - currentLayerDepth;
+    float before = 1.0 - sampleHeight(previousTexcoords, dx, dy) - currentLayerDepth + layerDepth;
+    float weight = clamp(after / min(after - before, -1e-5), 0.0, 1.0);
+    return mix(currentTexcoords, previousTexcoords, weight);
+}
+
+//没有高度图、全局关闭或切线空间不可用时保持原来的纹理坐标
+void applyParallax(TextureChannels textureChannels) {
+    texcoords0 = oTexcoords0;
+    texcoords1 = oTexcoords1;
+    if (textureChannels.height == NO_TEXTURE_ID || material.parallaxSteps == 0) {
+        return;
+    }
+
+    vec2 texcoords = textureChannels.height == 0 ? oTexcoords0 : oTexcoords1;
+    vec3 normal = normalize(oNormals);
+    mat3 tbn = oTBN;
+    if (useDerivativeTangents() && !getDerivativeTBN(normal, texcoords, tbn)) {
+        return;
+    }
+
+    vec3 viewTS = normalize(transpose(tbn) * (cameraUBO.eye.xyz - oPositions));
+    if (viewTS.z <= 0.0) {
+        return;
+    }
+
+    texcoords = parallaxOcclusionMapping(texcoords, viewTS);
+    if (textureChannels.height == 0) {
+        texcoords0 = texcoords;
+    } else {
+        texcoords1 = texcoords;
+    }
+}
+
 float sampleAOMap() {
     ivec2 size = textureSize(aoMapSampler, 0);
     vec2 coords = vec2(float(gl_FragCoord.x) / float(size.x), float(gl_FragCoord.y) / float(size.y));
@@ -365,8 +568,8 @@ vec3 occludeAmbientColor(vec3 ambientColor, TextureChannels textureChannels) {
     float aoMapSample = sampleAOMap();
     float sampledOcclusion = 0.0;
     if (textureChannels.occlusion != NO_TEXTURE_ID) {
-        vec2 uv = getUV(textureChannels.occlusion);
-        sampledOcclusion = texture(occlusionSampler, uv).r;
+        vec2 uv = getUV(textureChannels.occlusion, textureTransforms.occlusion);
+        sampledOcclusion = texture(occlusionSampler, uv)[(material.textureSwizzle >> 24) & 255];
     }
     return mix(ambientColor, ambientColor * sampledOcclusion, material.metallicSpecularAndOcclusion.a) * aoMapSample;
 }
@@ -376,7 +579,7 @@ uint getAlphaMode() {
 }
 
 bool isMasked(vec4 baseColor) {
-    return getAlphaMode() == ALPHA_MODE_MASK && baseColor.a + ALPHA_CUTOFF_BIAS < material.alphaCutoff;
+    return ALPHA_TEST && getAlphaMode() == ALPHA_MODE_MASK && baseColor.a + ALPHA_CUTOFF_BIAS < material.alphaCutoff;
 }
 
 float getAlpha(vec4 baseColor) {
@@ -388,7 +591,7 @@ float getAlpha(vec4 baseColor) {
 
 bool isUnlit() {
     uint unlitFlag = (material.occlusionTextureChannelAlphaModeUnlitFlagAndWorkflow >> 8) & 255;
-    if (unlitFlag == UNLIT_FLAG_UNLIT) {
+    if ((unlitFlag & UNLIT_FLAG_UNLIT) != 0) {
         return true;
     }
     return false;
@@ -471,6 +674,65 @@ vec3 computeColor(
     }
     return color;
 }
+//点光源和聚光灯的shadow map存的是透视除法之前的z，与到光源的距离近似成线性关系
+float samplePunctualShadow(vec2 uv, float depth, uint layer) {
+    float closestDepth = texture(punctualShadowMapSampler, vec3(uv, layer)).r;
+    return depth > closestDepth ? 0.0 : 1.0;
+}
+
+//聚光灯只有一层，点光源按cube的六层排列：+X -X +Y -Y +Z -Z，按方向的主轴选面
+float calculatePunctualShadow(Light light)
+{
+    if (light.shadowLayer < 0) {
+        return 1.0;
+    }
+
+    vec3 fromLight = oPositions - light.position.xyz;
+    uint layer = uint(light.shadowLayer);
+    if (light.type == POINT_LIGHT_TYPE) {
+        vec3 axis = abs(fromLight);
+        if (axis.x >= axis.y && axis.x >= axis.z) {
+            layer += fromLight.x > 0.0 ? 0 : 1;
+        } else if (axis.y >= axis.z) {
+            layer += fromLight.y > 0.0 ? 2 : 3;
+        } else {
+            layer += fromLight.z > 0.0 ? 4 : 5;
+        }
+    }
+
+    vec3 normal = normalize(oNormals);
+    float NdotL = clamp(dot(normal, -normalize(fromLight)), 0.0, 1.0);
+
+    //texel的世界空间大小随到光源的距离增大，按90度视场估算
+    float shadowMapSize = float(textureSize(punctualShadowMapSampler, 0).x);
+    float texelSize = 2.0 * length(fromLight) / shadowMapSize;
+    vec3 position = oPositions + normal * renderData.shadowNormalBias * texelSize * (1.0 - NdotL);
+
+    vec4 fragPosLightSpace = renderData.punctualShadowMatrices[layer] * vec4(position, 1.0);
+    if (fragPosLightSpace.w <= 0.0) {
+        return 1.0;
+    }
+    vec2 uv = fragPosLightSpace.xy / fragPosLightSpace.w * 0.5 + 0.5;
+    if (any(lessThan(uv, vec2(0.0))) || any(greaterThan(uv, vec2(1.0)))) {
+        return 1.0;
+    }
+    float depth = fragPosLightSpace.z - texelSize * (2.0 - NdotL);
+
+    if (renderData.shadowFilterMode == SHADOW_FILTER_HARD) {
+        return samplePunctualShadow(uv, depth, layer);
+    }
+    //PCSS只用于主光源，其余灯光退化为PCF
+    vec2 texelUV = vec2(1.0 / shadowMapSize);
+    int halfKernel = int(renderData.shadowPcfKernelSize) / 2;
+    float kernelSize = float(halfKernel * 2 + 1);
+    float shadow = 0.0;
+    for (int x = -halfKernel; x <= halfKernel; ++x) {
+        for (int y = -halfKernel; y <= halfKernel; ++y) {
+            shadow += samplePunctualShadow(uv + vec2(x, y) * texelUV, depth, layer);
+        }
+    }
+    return shadow / (kernelSize * kernelSize);
+}
 
 vec3 computerenderData(vec3 direction, vec3 color, float intensity, PbrInfo pbrInfo, vec3 n, vec3 v) {
     vec3 l = -normalize(direction);
@@ -512,6 +774,35 @@ vec3 computeSpotLight(Light light, PbrInfo pbrInfo, vec3 n, vec3 v) {
     return computeColor(pbrInfo, n, l, v, h, light.color.rgb, light.intensity * attenuation * angularAttenuation);
 }
 
+vec3 computeLight(Light light, PbrInfo pbrInfo, vec3 n, vec3 v) {
+    if (light.type == DIRECTIONAL_LIGHT_TYPE) {
+        return computeDirectionalLight(light, pbrInfo, n, v);
+    } else if (light.type == POINT_LIGHT_TYPE) {
+        return computePointLight(light, pbrInfo, n, v) * calculatePunctualShadow(light);
+    } else if (light.type == SPOT_LIGHT_TYPE) {
+        return computeSpotLight(light, pbrInfo, n, v) * calculatePunctualShadow(light);
+    }
+    return vec3(0.0);
+}
+
+//x、y按NDC均匀划分，z在近远平面之间按指数划分，与light_culling.comp一致
+uint getLightCluster() {
+    uvec3 grid = uvec3(material.lightClusterGrid & 0xFF,
+                       (material.lightClusterGrid >> 8) & 0xFF,
+                       (material.lightClusterGrid >> 16) & 0xFF);
+
+    vec4 viewPosition = cameraUBO.view * vec4(oPositions, 1.0);
+    vec4 clipPosition = cameraUBO.proj * viewPosition;
+    vec2 ndc = clipPosition.xy / clipPosition.w;
+    uvec2 tile = uvec2(clamp((ndc * 0.5 + 0.5) * vec2(grid.xy), vec2(0.0), vec2(grid.xy - 1)));
+
+    float depth = max(-viewPosition.z, cameraUBO.zNear);
+    float slice = log(depth / cameraUBO.zNear) / log(cameraUBO.zFar / cameraUBO.zNear);
+    uint z = uint(clamp(slice * float(grid.z), 0.0, float(grid.z - 1)));
+
+    return tile.x + tile.y * grid.x + z * grid.x * grid.y;
+}
+
 vec3 prefilteredReflectionLinear(vec3 R, float roughness) {
 	float lod = roughness * MAX_REFLECTION_LOD;
 	float lodf = floor(lod);
@@ -537,11 +828,12 @@ vec3 computeIBL(PbrInfo pbrInfo, vec3 v, vec3 n) {
     vec3 kD = 1.0 - f;
     kD *= 1.0 - pbrInfo.metallic;
 
-    vec3 irradiance = texture(irradianceMapSampler, n).rgb;
+    mat3 envRotation = mat3(cameraUBO.envRotation);
+    vec3 irradiance = texture(irradianceMapSampler, envRotation * n).rgb;
     vec3 diffuse = irradiance * pbrInfo.baseColor;
 
     vec3 r = normalize(reflect(-v, n));
-    vec3 reflection = prefilteredReflection(r, pbrInfo.roughness);
+    vec3 reflection = prefilteredReflection(envRotation * r, pbrInfo.roughness);
     vec2 envBRDF = texture(brdfLookupSampler, vec2(max(dot(n, v), 0.0), pbrInfo.roughness)).rg;
     vec3 specular = reflection * (f * envBRDF.x + envBRDF.y);
 
@@ -576,6 +868,7 @@ vec3 applyFog(vec3 color)
 
 void main() {
     TextureChannels textureChannels = getTextureChannels();
+    applyParallax(textureChannels);
 
     vec4 baseColor = getBaseColor(textureChannels);
     if (isMasked(baseColor)) {
@@ -583,7 +876,8 @@ void main() {
     }
     float alpha = getAlpha(baseColor);
 
-    if (isUnlit()) {
+    //KHR_materials_unlit：直接输出基础色，不计算BRDF、IBL和阴影
+    if (isUnlit() && material.outputMode == OUTPUT_MODE_FINAL) {
         outColor = vec4(baseColor.rgb, alpha);
         return;
     }
@@ -612,22 +906,22 @@ void main() {
     color *= mainLightShadow;
 
     vec3 additionalLightColor = vec3(0.0);
-    for (int i = 0; i < material.lightCount; i++) {
-        Light light = lights.lights[i];
-        uint lightType = light.type;
-
-        if (lightType == DIRECTIONAL_LIGHT_TYPE) {
-            additionalLightColor += computeDirectionalLight(light, pbrInfo, n, v);
-        } else if (lightType == POINT_LIGHT_TYPE) {
-            additionalLightColor += computePointLight(light, pbrInfo, n, v);
-        } else if (lightType == SPOT_LIGHT_TYPE) {
-            additionalLightColor += computeSpotLight(light, pbrInfo, n, v);
+    if (material.lightClusterGrid != 0) {
+        uint clusterOffset = getLightCluster() * (MAX_LIGHTS_PER_CLUSTER + 1);
+        uint clusterLightCount = lightClusters.data[clusterOffset];
+        for (uint i = 0; i < clusterLightCount; i++) {
+            Light light = lights.lights[lightClusters.data[clusterOffset + 1 + i]];
+            additionalLightColor += computeLight(light, pbrInfo, n, v);
+        }
+    } else {
+        for (uint i = 0; i < material.lightCount; i++) {
+            additionalLightColor += computeLight(lights.lights[i], pbrInfo, n, v);
         }
     }
     color += additionalLightColor;
 
     vec3 ambient = computeIBL(pbrInfo, v, n);
-    ambient *= clamp(mainLightShadow, 0.4, 1.0);
+    ambient *= clamp(mainLightShadow, 0.4, 1.0) * renderData.ambientIntensity;
 
     color += emissive + occludeAmbientColor(ambient, textureChannels);
     color = applyFog(color);
@@ -657,5 +951,18 @@ void main() {
     } else if (material.outputMode == OUTPUT_MODE_SSAO) {
         float ao = sampleAOMap();
         outColor = vec4(vec3(ao), 1.0);
+    } else if (material.outputMode == OUTPUT_MODE_VERTEX_COLOR) {
+        outColor = vec4(oColors.rgb, 1.0);
+    } else if (material.outputMode == OUTPUT_MODE_UNLIT) {
+        outColor = isUnlit() ? vec4(1.0) : vec4(vec3(0.0), 1.0);
+    } else if (material.outputMode == OUTPUT_MODE_IRRADIANCE) {
+        vec3 irradiance = texture(irradianceMapSampler, mat3(cameraUBO.envRotation) * n).rgb;
+        outColor = vec4(irradiance, 1.0);
+    } else if (material.outputMode == OUTPUT_MODE_PRE_FILTERED) {
+        vec3 r = mat3(cameraUBO.envRotation) * normalize(reflect(-v, n));
+        outColor = vec4(prefilteredReflection(r, material.iblDebugRoughness), 1.0);
+    } else if (material.outputMode == OUTPUT_MODE_BRDF_LOOKUP) {
+        vec2 envBRDF = texture(brdfLookupSampler, vec2(max(dot(n, v), 0.0), roughness)).rg;
+        outColor = vec4(envBRDF, 0.0, 1.0);
     }
 }
@@ -26,6 +26,15 @@ layout(binding = 3, set = 0) uniform SkinUBO {
     mat4 jointMatrices[512];
 } skin;
 
+layout(binding = 15, set = 0) readonly buffer InstanceTransforms {
+    mat4 matrices[];
+} instances;
+
+//前96字节是片元着色器的材质和配置参数
+layout(push_constant) uniform InstanceConstants {
+    layout(offset = 96) uint instanced;
+} instance;
+
 layout(location = 0) out vec3 oNormals;
 layout(location = 1) out vec2 oTexcoords0;
 layout(location = 2) out vec2 oTexcoords1;
@@ -33,8 +42,12 @@ layout(location = 3) out vec3 oPositions;
 layout(location = 4) out vec4 oColors;
 layout(location = 5) out mat3 oTBN;
 
+//深度预pass和光照pass用不同的片元着色器链接，位置必须完全一致才能用EQUAL比较深度
+invariant gl_Position;
+
 void main() {
-    mat4 world = transform.matrix;
+    //实例化绘制时按gl_InstanceIndex读取变换
+    mat4 world = instance.instanced != 0 ? instances.matrices[gl_InstanceIndex] : transform.matrix;
     if (vWeights != vec4(0.0)) {
         world *= vWeights.x * skin.jointMatrices[vJoints.x]
             + vWeights.y * skin.jointMatrices[vJoints.y]
@@ -45,7 +58,10 @@ void main() {
     vec3 normal = normalize((world * vec4(vNormals, 0.0)).xyz);
     vec3 tangent = normalize((world * vec4(vTangents.xyz, 0.0)).xyz);
     tangent = normalize(tangent - dot(tangent, normal)*normal);
-    vec3 bitangent = cross(normal, tangent) * vTangents.w;
+    //镜像变换(行列式为负)会翻转叉乘结果，需要同时翻转切线手性
+    float handedness = vTangents.w < 0.0 ? -1.0 : 1.0;
+    handedness *= determinant(mat3(world)) < 0.0 ? -1.0 : 1.0;
+    vec3 bitangent = cross(normal, tangent) * handedness;
 
     oNormals = normal;
     oTexcoords0 = vTexcoords0;
@@ -0,0 +1,5 @@
+#version 450
+
+//只写深度，用于遮挡查询和深度预pass，颜色写入被关闭
+void main() {
+}
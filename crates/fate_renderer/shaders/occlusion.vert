@@ -0,0 +1,33 @@
+#version 450
+
+layout(binding = 0) uniform CameraUBO {
+    mat4 view;
+    mat4 proj;
+    mat4 invertedProj;
+    vec4 eye;
+    float zNear;
+    float zFar;
+} cameraUBO;
+
+layout(push_constant) uniform Constants {
+    mat4 model;
+    vec4 aabbMin;
+    vec4 aabbMax;
+} c;
+
+//包围盒12个三角形，角点序号的三个位分别表示取x、y、z的最大值
+const int CORNERS[36] = int[](
+    0, 2, 6, 0, 6, 4,
+    1, 5, 7, 1, 7, 3,
+    0, 4, 5, 0, 5, 1,
+    2, 3, 7, 2, 7, 6,
+    0, 1, 3, 0, 3, 2,
+    4, 6, 7, 4, 7, 5
+);
+
+void main() {
+    int corner = CORNERS[gl_VertexIndex];
+    vec3 t = vec3(corner & 1, (corner >> 1) & 1, (corner >> 2) & 1);
+    vec3 position = mix(c.aabbMin.xyz, c.aabbMax.xyz, t);
+    gl_Position = cameraUBO.proj * cameraUBO.view * c.model * vec4(position, 1.0);
+}
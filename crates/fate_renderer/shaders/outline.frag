@@ -0,0 +1,47 @@
+#version 450
+
+layout(location = 0) in vec2 oCoords;
+
+layout(binding = 0) uniform usampler2D pickIds;
+
+layout(location = 0) out vec4 finalColor;
+
+layout(push_constant) uniform Constants {
+    //线性空间的颜色，输出前做gamma校正
+    vec3 color;
+    //选中节点的拾取ID
+    uint selectedId;
+    //描边宽度，单位为ID缓冲的像素
+    int thickness;
+} c;
+
+const float GAMMA = 2.2;
+const float INV_GAMMA = 1.0 / GAMMA;
+
+bool isSelected(ivec2 texel, ivec2 size) {
+    texel = clamp(texel, ivec2(0), size - 1);
+    return texelFetch(pickIds, texel, 0).r == c.selectedId;
+}
+
+void main() {
+    ivec2 size = textureSize(pickIds, 0);
+    ivec2 center = ivec2(oCoords * vec2(size));
+
+    //只描物体外侧，内部保持原样
+    if (isSelected(center, size)) {
+        discard;
+    }
+
+    for (int y = -c.thickness; y <= c.thickness; y++) {
+        for (int x = -c.thickness; x <= c.thickness; x++) {
+            if (x * x + y * y > c.thickness * c.thickness) {
+                continue;
+            }
+            if (isSelected(center + ivec2(x, y), size)) {
+                finalColor = vec4(pow(c.color, vec3(INV_GAMMA)), 1.0);
+                return;
+            }
+        }
+    }
+    discard;
+}
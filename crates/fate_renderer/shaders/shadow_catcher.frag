@@ -0,0 +1,113 @@
+#version 450
+
+const uint MAX_SHADOW_CASCADES = 4;
+//平面外圈这一比例的范围内淡出，避免出现硬边
+const float EDGE_FADE_RANGE = 0.2;
+
+layout(binding = 0) uniform CameraUBO {
+    mat4 view;
+    mat4 proj;
+    mat4 invertedProj;
+    vec4 eye;
+    float zNear;
+    float zFar;
+    uint reverseZ;
+    mat4 envRotation;
+    mat4 previousViewProj;
+} cameraUBO;
+
+layout(binding = 1) uniform ShadowCatcherUBO {
+    mat4 cascadeMatrices[MAX_SHADOW_CASCADES];
+    vec4 cascadeSplits;
+    vec4 cascadeTexelSizes;
+    vec4 lightDirection;
+    vec4 color;
+    //xz为中心，y为高度，w为半边长
+    vec4 plane;
+    uint cascadeCount;
+    float shadowDepthBias;
+    float shadowNormalBias;
+    uint shadowPcfKernelSize;
+} catcher;
+
+layout(binding = 2) uniform sampler2DArray shadowMapSampler;
+layout(binding = 3) uniform sampler2D aoMapSampler;
+
+layout(location = 0) in vec3 oWorldPos;
+layout(location = 1) in vec2 oPlaneCoords;
+layout(location = 2) in vec4 oCurrentClip;
+layout(location = 3) in vec4 oPreviousClip;
+
+layout(location = 0) out vec4 outColor;
+
+const vec3 NORMAL = vec3(0.0, 1.0, 0.0);
+
+float sampleShadow(vec2 uv, float depth, uint cascade) {
+    float closestDepth = texture(shadowMapSampler, vec3(uv, cascade)).r;
+    return depth > closestDepth ? 0.0 : 1.0;
+}
+
+float calculateCascadeShadow(uint cascade) {
+    vec3 lightDir = normalize(catcher.lightDirection.xyz);
+    float NdotL = clamp(dot(NORMAL, -lightDir), 0.0, 1.0);
+
+    float normalOffset = catcher.shadowNormalBias * catcher.cascadeTexelSizes[cascade];
+    vec3 position = oWorldPos + NORMAL * normalOffset * (1.0 - NdotL);
+
+    vec4 fragPosLightSpace = catcher.cascadeMatrices[cascade] * vec4(position, 1.0);
+    vec3 projCoords = fragPosLightSpace.xyz / fragPosLightSpace.w;
+    if (projCoords.z > 1.0) {
+        return 1.0;
+    }
+    vec2 uv = projCoords.xy * 0.5 + 0.5;
+
+    float bias = max(catcher.shadowDepthBias * 10.0 * (1.0 - NdotL), catcher.shadowDepthBias);
+    float depth = projCoords.z - bias;
+
+    vec2 texelSize = 1.0 / vec2(textureSize(shadowMapSampler, 0).xy);
+    int halfKernel = int(catcher.shadowPcfKernelSize) / 2;
+    float shadow = 0.0;
+    for (int x = -halfKernel; x <= halfKernel; ++x) {
+        for (int y = -halfKernel; y <= halfKernel; ++y) {
+            shadow += sampleShadow(uv + vec2(x, y) * texelSize, depth, cascade);
+        }
+    }
+    float kernelSize = float(2 * halfKernel + 1);
+    return shadow / (kernelSize * kernelSize);
+}
+
+//只接收主光源的级联阴影，未被遮挡返回1
+float calculateShadow() {
+    if (catcher.cascadeCount == 0) {
+        return 1.0;
+    }
+
+    float depth = -(cameraUBO.view * vec4(oWorldPos, 1.0)).z;
+    uint cascade = catcher.cascadeCount - 1;
+    for (uint i = 0; i < catcher.cascadeCount; ++i) {
+        if (depth < catcher.cascadeSplits[i]) {
+            cascade = i;
+            break;
+        }
+    }
+    return calculateCascadeShadow(cascade);
+}
+
+float sampleAOMap() {
+    vec2 size = vec2(textureSize(aoMapSampler, 0));
+    return texture(aoMapSampler, gl_FragCoord.xy / size).r;
+}
+
+//不画平面本身，只把阴影和AO压暗的部分叠加到背景上
+void main() {
+    float visibility = calculateShadow() * sampleAOMap();
+
+    float edge = max(abs(oPlaneCoords.x), abs(oPlaneCoords.y));
+    float fade = 1.0 - smoothstep(1.0 - EDGE_FADE_RANGE, 1.0, edge);
+
+    float alpha = (1.0 - visibility) * catcher.color.a * fade;
+    if (alpha <= 0.0) {
+        discard;
+    }
+    outColor = vec4(catcher.color.rgb, alpha);
+}
@@ -0,0 +1,52 @@
+#version 450
+
+const uint MAX_SHADOW_CASCADES = 4;
+
+layout(binding = 0) uniform CameraUBO {
+    mat4 view;
+    mat4 proj;
+    mat4 invertedProj;
+    vec4 eye;
+    float zNear;
+    float zFar;
+    uint reverseZ;
+    mat4 envRotation;
+    mat4 previousViewProj;
+} cameraUBO;
+
+layout(binding = 1) uniform ShadowCatcherUBO {
+    mat4 cascadeMatrices[MAX_SHADOW_CASCADES];
+    vec4 cascadeSplits;
+    vec4 cascadeTexelSizes;
+    vec4 lightDirection;
+    vec4 color;
+    //xz为中心，y为高度，w为半边长
+    vec4 plane;
+    uint cascadeCount;
+    float shadowDepthBias;
+    float shadowNormalBias;
+    uint shadowPcfKernelSize;
+} catcher;
+
+layout(location = 0) out vec3 oWorldPos;
+layout(location = 1) out vec2 oPlaneCoords;
+layout(location = 2) out vec4 oCurrentClip;
+layout(location = 3) out vec4 oPreviousClip;
+
+//两个三角形，顶点由gl_VertexIndex生成
+const vec2 CORNERS[6] = vec2[](
+    vec2(-1.0, -1.0), vec2(-1.0, 1.0), vec2(1.0, 1.0),
+    vec2(-1.0, -1.0), vec2(1.0, 1.0), vec2(1.0, -1.0)
+);
+
+void main() {
+    vec2 corner = CORNERS[gl_VertexIndex];
+    vec2 xz = catcher.plane.xz + corner * catcher.plane.w;
+    vec3 position = vec3(xz.x, catcher.plane.y, xz.y);
+
+    oWorldPos = position;
+    oPlaneCoords = corner;
+    oCurrentClip = cameraUBO.proj * cameraUBO.view * vec4(position, 1.0);
+    oPreviousClip = cameraUBO.previousViewProj * vec4(position, 1.0);
+    gl_Position = oCurrentClip;
+}
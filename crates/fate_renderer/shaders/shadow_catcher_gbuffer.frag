@@ -0,0 +1,34 @@
+#version 450
+
+layout(binding = 0) uniform CameraUBO {
+    mat4 view;
+    mat4 proj;
+    mat4 invertedProj;
+    vec4 eye;
+    float zNear;
+    float zFar;
+    uint reverseZ;
+    mat4 envRotation;
+    mat4 previousViewProj;
+} cameraUBO;
+
+layout(location = 0) in vec3 oWorldPos;
+layout(location = 1) in vec2 oPlaneCoords;
+layout(location = 2) in vec4 oCurrentClip;
+layout(location = 3) in vec4 oPreviousClip;
+
+layout(location = 0) out vec4 outNormals;
+layout(location = 1) out uint outPickId;
+layout(location = 2) out vec2 outMotion;
+
+//只写法线和深度给SSAO使用，拾取ID为0，点击地面等同于点击背景
+void main() {
+    vec3 normal = normalize(mat3(cameraUBO.view) * vec3(0.0, 1.0, 0.0));
+    if (!gl_FrontFacing) {
+        normal *= -1.0;
+    }
+
+    outNormals = vec4(normal, 0.0);
+    outPickId = 0u;
+    outMotion = oCurrentClip.xy / oCurrentClip.w - oPreviousClip.xy / oPreviousClip.w;
+}
@@ -15,6 +15,9 @@ layout(push_constant) uniform MaterialUniform {
     uint colorTextureChannel;
     uint alphaMode;
     float alphaCutoff;
+    //KHR_texture_transform，前一个vec4是2x2矩阵的两行，后一个的xy是平移
+    vec4 colorTransformRotationScale;
+    vec4 colorTransformOffset;
 } material;
 
 layout(binding = 3, set = 1) uniform sampler2D colorSampler;
@@ -22,10 +25,9 @@ layout(binding = 3, set = 1) uniform sampler2D colorSampler;
 layout(location = 0) out vec4 outColor;
 
 vec2 getUV(uint texChannel) {
-    if (texChannel == 0) {
-        return oTexcoords0;
-    }
-    return oTexcoords1;
+    vec2 uv = texChannel == 0 ? oTexcoords0 : oTexcoords1;
+    vec4 rotationScale = material.colorTransformRotationScale;
+    return vec2(dot(rotationScale.xy, uv), dot(rotationScale.zw, uv)) + material.colorTransformOffset.xy;
 }
 
 float getAlpha(uint textureChannel) {
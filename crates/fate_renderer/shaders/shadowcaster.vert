@@ -9,14 +9,13 @@ layout(location = 5) in vec4 vWeights;
 layout(location = 6) in uvec4 vJoints;
 layout(location = 7) in vec4 vColors;
 
-layout(binding = 0, set = 0) uniform CameraUBO {
-    mat4 view;
-    mat4 proj;
-    mat4 invertedProj;
-    vec4 eye;
-    float zNear;
-    float zFar;
-} cameraUBO;
+const uint MAX_SHADOW_CASCADES = 4;
+const uint MAX_PUNCTUAL_SHADOW_LAYERS = 12;
+
+//级联矩阵之后是点光源和聚光灯各层的矩阵
+layout(binding = 0, set = 0) uniform ShadowUBO {
+    mat4 cascadeMatrices[MAX_SHADOW_CASCADES + MAX_PUNCTUAL_SHADOW_LAYERS];
+} shadowUBO;
 
 layout(binding = 1, set = 0) uniform TransformUBO {
     mat4 matrix;
@@ -26,6 +25,16 @@ layout(binding = 2, set = 0) uniform SkinUBO {
     mat4 jointMatrices[512];
 } skin;
 
+layout(binding = 4, set = 0) readonly buffer InstanceTransforms {
+    mat4 matrices[];
+} instances;
+
+//前48字节是片元着色器的材质参数
+layout(push_constant) uniform CascadeConstants {
+    layout(offset = 48) uint cascadeIndex;
+    uint instanced;
+} cascade;
+
 //layout(location = 0) out vec3 oViewSpaceNormal;
 layout(location = 0) out vec2 oTexcoords0;
 layout(location = 1) out vec2 oTexcoords1;
@@ -33,7 +42,8 @@ layout(location = 2) out float oAlpha;
 layout(location = 3) out vec4 clipPos;
 
 void main() {
-    mat4 world = transform.matrix;
+    //实例化绘制时按gl_InstanceIndex读取变换
+    mat4 world = cascade.instanced != 0 ? instances.matrices[gl_InstanceIndex] : transform.matrix;
     if (vWeights != vec4(0.0)) {
         world *= vWeights.x * skin.jointMatrices[vJoints.x]
             + vWeights.y * skin.jointMatrices[vJoints.y]
@@ -41,11 +51,10 @@ void main() {
             + vWeights.w * skin.jointMatrices[vJoints.w];
     }
 
-    //oViewSpaceNormal = normalize((cameraUBO.view * world * vec4(vNormals, 0.0)).xyz);
     oTexcoords0 = vTexcoords0;
     oTexcoords1 = vTexcoords1;
     oAlpha = vColors.a;
 
-    gl_Position = cameraUBO.proj * cameraUBO.view * world * vec4(vPositions, 1.0);
+    gl_Position = shadowUBO.cascadeMatrices[cascade.cascadeIndex] * world * vec4(vPositions, 1.0);
     clipPos = gl_Position;
 }
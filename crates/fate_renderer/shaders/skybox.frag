@@ -12,11 +12,22 @@ layout(binding = 0) uniform CameraUBO {
 } cameraUBO;
 
 layout(binding = 1) uniform samplerCube cubemapSampler;
+layout(binding = 2) uniform samplerCube preFilteredSampler;
+
+layout(push_constant) uniform Constants {
+    float exposure;
+    float lod;
+} c;
 
 layout(location = 0) out vec4 outColor;
 
 void main() {
-    vec3 color = texture(cubemapSampler, oPositions).rgb;
+    vec3 color;
+    if (c.lod > 0.0) {
+        color = textureLod(preFilteredSampler, oPositions, c.lod).rgb;
+    } else {
+        color = texture(cubemapSampler, oPositions).rgb;
+    }
 
-    outColor = vec4(color, 1.0);
+    outColor = vec4(color * c.exposure, 1.0);
 }
@@ -9,6 +9,8 @@ layout(binding = 0) uniform CameraUBO {
     vec4 eye;
     float zNear;
     float zFar;
+    uint reverseZ;
+    mat4 envRotation;
 } cameraUBO;
 
 layout(location = 0) out vec3 oPositions;
@@ -22,7 +24,7 @@ mat4 getViewAtOrigin() {
 }
 
 void main() {
-    oPositions = vPositions;
+    oPositions = mat3(cameraUBO.envRotation) * vPositions;
 
     mat4 view = getViewAtOrigin();
 
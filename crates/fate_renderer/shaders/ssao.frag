@@ -5,10 +5,10 @@ layout (constant_id = 0) const uint SSAO_KERNEL_SIZE = 32;
 layout(push_constant) uniform Config {
     float ssaoRadius;
     float ssaoStrength;
+    uint rangeCheck;
 } config;
 
 layout(location = 0) in vec2 oCoords;
-layout(location = 1) in vec3 oViewRay;
 
 layout(binding = 0, set = 0) uniform sampler2D normalsSampler;
 layout(binding = 1, set = 0) uniform sampler2D depthSampler;
@@ -25,19 +25,20 @@ layout(binding = 4, set = 2) uniform CameraUBO {
     vec4 eye;
     float zNear;
     float zFar;
+    uint reverseZ;
 } cameraUBO;
 
 layout(location = 0) out float finalColor;
 
-float linearDepth(vec2 uv) {
-    float near = cameraUBO.zNear;
-    float far = cameraUBO.zFar;
+// 用逆投影矩阵还原观察空间位置，透视、正交和reverse-Z都适用
+vec3 viewPosition(vec2 uv) {
     float depth = texture(depthSampler, uv).r;
-    return (near * far) / (far + depth * (near - far));
+    vec4 position = cameraUBO.invertedProj * vec4(uv * 2.0 - 1.0, depth, 1.0);
+    return position.xyz / position.w;
 }
 
 void main() {
-    vec3 position = oViewRay * linearDepth(oCoords);
+    vec3 position = viewPosition(oCoords);
 
     vec3 normal = normalize(texture(normalsSampler, oCoords).xyz);
 
@@ -61,9 +62,12 @@ void main() {
         offset.xy /= offset.w;
         offset.xy = offset.xy * 0.5 + 0.5;
         
-        float depth = -linearDepth(offset.xy);
+        float depth = viewPosition(offset.xy).z;
 
-        float rangeCheck = smoothstep(0.0f, 1.0f, config.ssaoRadius / abs(depth - position.z));
+        // 深度差超过采样半径的遮挡物不计入，避免物体边缘出现暗边
+        float rangeCheck = config.rangeCheck == 1
+            ? smoothstep(0.0f, 1.0f, config.ssaoRadius / abs(depth - position.z))
+            : 1.0f;
 		occlusion += (depth >= kSample.z + bias ? 1.0f : 0.0f) * rangeCheck;
     }
     occlusion = 1.0 - (occlusion / float(SSAO_KERNEL_SIZE));
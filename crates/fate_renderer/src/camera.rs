@@ -1,20 +1,155 @@
 use crate::inputsystem::*;
-use rendering::cgmath::{InnerSpace, Matrix4, Point3, Vector3};
+use rendering::cgmath::{Deg, EuclideanSpace, InnerSpace, Matrix4, Point3, Vector3};
 use rendering::math::clamp;
+use rendering::Aabb;
+use serde::{Deserialize, Serialize};
 
 const MIN_ORBITAL_CAMERA_DISTANCE: f32 = 0.5;
 const TARGET_MOVEMENT_SPEED: f32 = 0.003;
+const LOOK_SENSITIVITY: f32 = 0.2;
+const MIN_PITCH: f32 = -89.0;
+const MAX_PITCH: f32 = 89.0;
+pub const DEFAULT_MOVE_SPEED: f32 = 5.0;
+pub const FOV: f32 = 45.0;
+//没有场景包围球时的默认近远平面
+pub const Z_NEAR: f32 = 0.01;
+pub const Z_FAR: f32 = 100.0;
+pub const MIN_Z_NEAR: f32 = 0.0001;
+pub const MAX_Z_FAR: f32 = 100000.0;
+//自动近远平面时远近之比的上限，相机在包围球内时近平面按这个比例取，保证深度精度
+const MAX_AUTO_FAR_NEAR_RATIO: f32 = 10000.0;
+//包围球前后各留一点余量，表面不会刚好落在近远平面上被裁掉
+const AUTO_CLIP_MARGIN: f32 = 1.05;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CameraMode {
+    Orbit = 0,
+    Fly,
+    FirstPerson,
+}
+
+impl CameraMode {
+    pub fn all() -> [CameraMode; 3] {
+        use CameraMode::*;
+        [Orbit, Fly, FirstPerson]
+    }
+
+    pub fn from_value(value: usize) -> Option<Self> {
+        use CameraMode::*;
+        match value {
+            0 => Some(Orbit),
+            1 => Some(Fly),
+            2 => Some(FirstPerson),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Projection {
+    //fovy为垂直视角，单位为度
+    Perspective { fovy: f32 },
+    //height为视口对应的世界空间高度，与距离无关
+    Orthographic { height: f32 },
+}
+
+impl Default for Projection {
+    fn default() -> Self {
+        Projection::Perspective { fovy: FOV }
+    }
+}
+
+impl Projection {
+    pub fn matrix(&self, aspect: f32, near: f32, far: f32, reverse_z: bool) -> Matrix4<f32> {
+        use rendering::math::*;
+        match (*self, reverse_z) {
+            (Projection::Perspective { fovy }, false) => perspective(Deg(fovy), aspect, near, far),
+            (Projection::Perspective { fovy }, true) => {
+                perspective_reverse_z(Deg(fovy), aspect, near, far)
+            }
+            (Projection::Orthographic { height }, false) => orthographic(height, aspect, near, far),
+            (Projection::Orthographic { height }, true) => {
+                orthographic_reverse_z(height, aspect, near, far)
+            }
+        }
+    }
+
+    //距相机distance处视锥截面的半高
+    pub fn half_height_at(&self, distance: f32) -> f32 {
+        match *self {
+            Projection::Perspective { fovy } => distance * (fovy.to_radians() * 0.5).tan(),
+            Projection::Orthographic { height } => height * 0.5,
+        }
+    }
+
+    pub fn is_orthographic(&self) -> bool {
+        matches!(self, Projection::Orthographic { .. })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum ClipPlanes {
+    Fixed {
+        near: f32,
+        far: f32,
+    },
+    //按场景包围球和相机位置每帧计算，整个场景都在近远平面之间
+    #[default]
+    Auto,
+}
+
+impl ClipPlanes {
+    //近平面必须大于0且小于远平面
+    pub fn is_valid(&self) -> bool {
+        match *self {
+            ClipPlanes::Fixed { near, far } => near > 0.0 && far > near && far.is_finite(),
+            ClipPlanes::Auto => true,
+        }
+    }
+}
+
+fn auto_near_far(eye: Point3<f32>, center: Point3<f32>, radius: f32) -> (f32, f32) {
+    let distance = (center - eye).magnitude();
+    let far = ((distance + radius) * AUTO_CLIP_MARGIN).clamp(Z_NEAR, MAX_Z_FAR);
+    //相机在包围球内时distance - radius为负，近平面取远平面允许的最小值
+    let near = ((distance - radius) / AUTO_CLIP_MARGIN).max(far / MAX_AUTO_FAR_NEAR_RATIO);
+    (near.max(MIN_Z_NEAR), far)
+}
+
+pub trait CameraController {
+    fn position(&self) -> Point3<f32>;
+
+    fn target(&self) -> Point3<f32>;
+
+    fn update(&mut self, input: &InputSystem, delta_time: f32);
+
+    fn look_at(&mut self, position: Point3<f32>, target: Point3<f32>);
+}
 
 #[derive(Clone, Copy)]
-pub struct Camera {
+pub struct Orbit {
     theta: f32,
     phi: f32,
     r: f32,
     target: Point3<f32>,
 }
 
-impl Camera {
-    pub fn position(&self) -> Point3<f32> {
+impl Orbit {
+    fn rotate(&mut self, theta: f32, phi: f32) {
+        self.theta += theta;
+        let phi = self.phi + phi;
+        self.phi = clamp(phi, 10.0_f32.to_radians(), 170.0_f32.to_radians());
+    }
+
+    fn forward(&mut self, r: f32) {
+        if (self.r - r).abs() > MIN_ORBITAL_CAMERA_DISTANCE {
+            self.r -= r;
+        }
+    }
+}
+
+impl CameraController for Orbit {
+    fn position(&self) -> Point3<f32> {
         Point3::new(
             self.target[0] + self.r * self.phi.sin() * self.theta.sin(),
             self.target[1] + self.r * self.phi.cos(),
@@ -22,14 +157,12 @@ impl Camera {
         )
     }
 
-    pub fn target(&self) -> Point3<f32> {
+    fn target(&self) -> Point3<f32> {
         self.target
     }
-}
 
-impl Camera {
-    pub fn update(&mut self, input: &InputSystem) {
-        if input.is_left_clicked() {
+    fn update(&mut self, input: &InputSystem, _delta_time: f32) {
+        if input.is_active(Action::CameraPan) {
             let position = self.position();
             let forward = (self.target - position).normalize();
             let up = Vector3::new(0.0, 1.0, 0.0);
@@ -45,38 +178,415 @@ impl Camera {
             }
         }
 
-        if input.is_right_clicked() {
+        if input.is_active(Action::CameraRotate) {
             let delta = input.cursor_delta();
-            let theta = delta[0] as f32 * (-0.2_f32).to_radians();
-            let phi = delta[1] as f32 * (-0.2_f32).to_radians();
+            let theta = delta[0] as f32 * (-LOOK_SENSITIVITY).to_radians();
+            let phi = delta[1] as f32 * (-LOOK_SENSITIVITY).to_radians();
             self.rotate(theta, phi);
         }
 
         self.forward(input.wheel_delta() * self.r * 0.2);
     }
 
-    fn rotate(&mut self, theta: f32, phi: f32) {
-        self.theta += theta;
-        let phi = self.phi + phi;
-        self.phi = clamp(phi, 10.0_f32.to_radians(), 170.0_f32.to_radians());
+    fn look_at(&mut self, position: Point3<f32>, target: Point3<f32>) {
+        let offset = position - target;
+        let r = offset.magnitude().max(MIN_ORBITAL_CAMERA_DISTANCE);
+        self.target = target;
+        self.r = r;
+        self.phi = clamp(
+            (offset.y / r).acos(),
+            10.0_f32.to_radians(),
+            170.0_f32.to_radians(),
+        );
+        self.theta = offset.x.atan2(offset.z);
     }
+}
 
-    fn forward(&mut self, r: f32) {
-        if (self.r - r).abs() > MIN_ORBITAL_CAMERA_DISTANCE {
-            self.r -= r;
+impl Default for Orbit {
+    fn default() -> Self {
+        Orbit {
+            theta: 0.0_f32.to_radians(),
+            phi: 90.0_f32.to_radians(),
+            r: 10.0,
+            target: Point3::new(0.0, 0.0, 0.0),
+        }
+    }
+}
+
+//yaw/pitch为角度，yaw为0时朝向-Z
+#[derive(Clone, Copy)]
+struct FreeLook {
+    position: Point3<f32>,
+    yaw: f32,
+    pitch: f32,
+}
+
+impl FreeLook {
+    fn direction(&self) -> Vector3<f32> {
+        let yaw = self.yaw.to_radians();
+        let pitch = self.pitch.to_radians();
+        Vector3::new(
+            -yaw.sin() * pitch.cos(),
+            pitch.sin(),
+            -yaw.cos() * pitch.cos(),
+        )
+    }
+
+    fn look(&mut self, input: &InputSystem) {
+        if input.is_active(Action::CameraRotate) {
+            let delta = input.cursor_delta();
+            self.yaw -= delta[0] * LOOK_SENSITIVITY;
+            self.pitch = clamp(
+                self.pitch - delta[1] * LOOK_SENSITIVITY,
+                MIN_PITCH,
+                MAX_PITCH,
+            );
+        }
+    }
+
+    fn look_at(&mut self, position: Point3<f32>, target: Point3<f32>) {
+        self.position = position;
+        let direction = target - position;
+        if direction.magnitude2() > f32::EPSILON {
+            let direction = direction.normalize();
+            self.yaw = (-direction.x).atan2(-direction.z).to_degrees();
+            self.pitch = clamp(direction.y.asin().to_degrees(), MIN_PITCH, MAX_PITCH);
+        }
+    }
+}
+
+fn movement_axis(input: &InputSystem) -> Vector3<f32> {
+    let mut axis = Vector3::new(0.0, 0.0, 0.0);
+    if input.is_active(Action::MoveForward) {
+        axis.z += 1.0;
+    }
+    if input.is_active(Action::MoveBackward) {
+        axis.z -= 1.0;
+    }
+    if input.is_active(Action::MoveRight) {
+        axis.x += 1.0;
+    }
+    if input.is_active(Action::MoveLeft) {
+        axis.x -= 1.0;
+    }
+    if input.is_active(Action::MoveUp) {
+        axis.y += 1.0;
+    }
+    if input.is_active(Action::MoveDown) {
+        axis.y -= 1.0;
+    }
+    axis
+}
+
+#[derive(Clone, Copy)]
+pub struct Fly {
+    look: FreeLook,
+    speed: f32,
+}
+
+impl CameraController for Fly {
+    fn position(&self) -> Point3<f32> {
+        self.look.position
+    }
+
+    fn target(&self) -> Point3<f32> {
+        self.look.position + self.look.direction()
+    }
+
+    fn update(&mut self, input: &InputSystem, delta_time: f32) {
+        self.look.look(input);
+
+        let forward = self.look.direction();
+        let right = forward.cross(Vector3::unit_y()).normalize();
+        let up = right.cross(forward);
+
+        let axis = movement_axis(input);
+        let velocity = forward * axis.z + right * axis.x + up * axis.y;
+        if velocity.magnitude2() > 0.0 {
+            self.look.position += velocity.normalize() * self.speed * delta_time;
+        }
+    }
+
+    fn look_at(&mut self, position: Point3<f32>, target: Point3<f32>) {
+        self.look.look_at(position, target);
+    }
+}
+
+impl Default for Fly {
+    fn default() -> Self {
+        Fly {
+            look: FreeLook {
+                position: Point3::new(0.0, 0.0, 10.0),
+                yaw: 0.0,
+                pitch: 0.0,
+            },
+            speed: DEFAULT_MOVE_SPEED,
+        }
+    }
+}
+
+//第一人称只在水平面移动，Q/E调整高度
+#[derive(Clone, Copy)]
+pub struct FirstPerson {
+    look: FreeLook,
+    speed: f32,
+}
+
+impl CameraController for FirstPerson {
+    fn position(&self) -> Point3<f32> {
+        self.look.position
+    }
+
+    fn target(&self) -> Point3<f32> {
+        self.look.position + self.look.direction()
+    }
+
+    fn update(&mut self, input: &InputSystem, delta_time: f32) {
+        self.look.look(input);
+
+        let yaw = self.look.yaw.to_radians();
+        let forward = Vector3::new(-yaw.sin(), 0.0, -yaw.cos());
+        let right = forward.cross(Vector3::unit_y()).normalize();
+
+        let axis = movement_axis(input);
+        let velocity = forward * axis.z + right * axis.x + Vector3::unit_y() * axis.y;
+        if velocity.magnitude2() > 0.0 {
+            self.look.position += velocity.normalize() * self.speed * delta_time;
+        }
+    }
+
+    fn look_at(&mut self, position: Point3<f32>, target: Point3<f32>) {
+        self.look.look_at(position, target);
+    }
+}
+
+impl Default for FirstPerson {
+    fn default() -> Self {
+        FirstPerson {
+            look: Fly::default().look,
+            speed: DEFAULT_MOVE_SPEED,
         }
     }
 }
 
+#[derive(Clone, Copy)]
+pub struct Camera {
+    mode: CameraMode,
+    projection: Projection,
+    clip_planes: ClipPlanes,
+    //自动近远平面使用的场景包围球(球心, 半径)
+    scene_sphere: Option<(Point3<f32>, f32)>,
+    orbit: Orbit,
+    fly: Fly,
+    first_person: FirstPerson,
+    //观察矩阵的上方向，交互控制始终绕世界Y轴旋转
+    up: Vector3<f32>,
+}
+
+impl Camera {
+    pub fn new(mode: CameraMode) -> Self {
+        let mut camera = Self::default();
+        camera.set_mode(mode);
+        camera
+    }
+
+    fn controller(&self) -> &dyn CameraController {
+        match self.mode {
+            CameraMode::Orbit => &self.orbit,
+            CameraMode::Fly => &self.fly,
+            CameraMode::FirstPerson => &self.first_person,
+        }
+    }
+
+    fn controller_mut(&mut self) -> &mut dyn CameraController {
+        match self.mode {
+            CameraMode::Orbit => &mut self.orbit,
+            CameraMode::Fly => &mut self.fly,
+            CameraMode::FirstPerson => &mut self.first_person,
+        }
+    }
+
+    pub fn position(&self) -> Point3<f32> {
+        self.controller().position()
+    }
+
+    pub fn target(&self) -> Point3<f32> {
+        self.controller().target()
+    }
+
+    pub fn up(&self) -> Vector3<f32> {
+        self.up
+    }
+
+    pub fn view_matrix(&self) -> Matrix4<f32> {
+        Matrix4::look_at_rh(self.position(), self.target(), self.up)
+    }
+
+    pub fn projection_matrix(&self, aspect: f32) -> Matrix4<f32> {
+        let (near, far) = self.near_far();
+        self.projection.matrix(aspect, near, far, false)
+    }
+
+    pub fn reverse_z_projection_matrix(&self, aspect: f32) -> Matrix4<f32> {
+        let (near, far) = self.near_far();
+        self.projection.matrix(aspect, near, far, true)
+    }
+
+    pub fn clip_planes(&self) -> ClipPlanes {
+        self.clip_planes
+    }
+
+    pub fn set_clip_planes(&mut self, clip_planes: ClipPlanes) {
+        if !clip_planes.is_valid() {
+            log::warn!(
+                "无效的近远平面{:?}，近平面需要大于0且小于远平面",
+                clip_planes
+            );
+            return;
+        }
+        self.clip_planes = clip_planes;
+    }
+
+    //载入或移除模型后更新，自动近远平面根据它计算
+    pub fn set_scene_bounds(&mut self, aabb: Option<Aabb<f32>>) {
+        self.scene_sphere = aabb.map(|aabb| {
+            let radius = (aabb.max() - aabb.min()).magnitude() * 0.5;
+            (Point3::from_vec(aabb.get_center()), radius)
+        });
+    }
+
+    //当前使用的(近平面, 远平面)
+    pub fn near_far(&self) -> (f32, f32) {
+        match (self.clip_planes, self.scene_sphere) {
+            (ClipPlanes::Fixed { near, far }, _) => (near, far),
+            (ClipPlanes::Auto, Some((center, radius))) => {
+                auto_near_far(self.position(), center, radius)
+            }
+            (ClipPlanes::Auto, None) => (Z_NEAR, Z_FAR),
+        }
+    }
+
+    pub fn projection(&self) -> Projection {
+        self.projection
+    }
+
+    pub fn set_projection(&mut self, projection: Projection) {
+        self.projection = projection;
+    }
+
+    pub fn mode(&self) -> CameraMode {
+        self.mode
+    }
+
+    //切换模式时保持当前视角
+    pub fn set_mode(&mut self, mode: CameraMode) {
+        if self.mode != mode {
+            let position = self.position();
+            let target = self.target();
+            self.mode = mode;
+            self.controller_mut().look_at(position, target);
+        }
+    }
+
+    pub fn set_move_speed(&mut self, speed: f32) {
+        self.fly.speed = speed;
+        self.first_person.speed = speed;
+    }
+
+    pub fn look_at(&mut self, position: Point3<f32>, target: Point3<f32>) {
+        self.controller_mut().look_at(position, target);
+    }
+
+    //直接设置相机位姿，用于脚本、截图和书签
+    //上方向为零或与视线平行时退回世界Y轴。俯仰角仍受当前控制模式的限制，
+    //正上方或正下方的视角会被夹到最大俯仰角。
+    pub fn set_pose(&mut self, position: Point3<f32>, target: Point3<f32>, up: Vector3<f32>) {
+        let direction = target - position;
+        let valid_up = up.magnitude2() > f32::EPSILON
+            && up.cross(direction).magnitude2() > f32::EPSILON * direction.magnitude2();
+        self.up = if valid_up {
+            up.normalize()
+        } else {
+            log::warn!("无效的相机上方向{:?}，使用Y轴", up);
+            Vector3::unit_y()
+        };
+        self.look_at(position, target);
+    }
+
+    pub fn frame(&mut self, aabb: Aabb<f32>, fov: f32) {
+        let radius = aabb.get_larger_side_size() * 0.5 * 3.0_f32.sqrt();
+        let distance = (radius / (fov.to_radians() * 0.5).sin()).max(MIN_ORBITAL_CAMERA_DISTANCE);
+        let target = Point3::from_vec(aabb.get_center());
+        let direction = (self.position() - self.target()).normalize();
+        self.look_at(target + direction * distance, target);
+    }
+
+    pub fn update(&mut self, input: &InputSystem, delta_time: f32) {
+        self.controller_mut().update(input, delta_time);
+    }
+}
+
 impl Default for Camera {
     fn default() -> Self {
         Camera {
-            theta: 0.0_f32.to_radians(),
-            phi: 90.0_f32.to_radians(),
-            r: 10.0,
-            target: Point3::new(0.0, 0.0, 0.0),
+            mode: CameraMode::Orbit,
+            projection: Projection::default(),
+            clip_planes: ClipPlanes::default(),
+            scene_sphere: None,
+            orbit: Orbit::default(),
+            fly: Fly::default(),
+            first_person: FirstPerson::default(),
+            up: Vector3::unit_y(),
+        }
+    }
+}
+
+//命名的相机位姿，保存在配置文件中
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CameraBookmark {
+    name: String,
+    position: [f32; 3],
+    target: [f32; 3],
+    #[serde(default = "default_up")]
+    up: [f32; 3],
+}
+
+impl CameraBookmark {
+    pub fn new(name: String, camera: &Camera) -> Self {
+        let position = camera.position();
+        let target = camera.target();
+        let up = camera.up();
+        Self {
+            name,
+            position: [position.x, position.y, position.z],
+            target: [target.x, target.y, target.z],
+            up: [up.x, up.y, up.z],
         }
     }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.position
+            .iter()
+            .chain(self.target.iter())
+            .chain(self.up.iter())
+            .all(|v| v.is_finite())
+    }
+
+    pub fn apply(&self, camera: &mut Camera) {
+        camera.set_pose(
+            Point3::from(self.position),
+            Point3::from(self.target),
+            Vector3::from(self.up),
+        );
+    }
+}
+
+fn default_up() -> [f32; 3] {
+    [0.0, 1.0, 0.0]
 }
 
 #[derive(Clone, Copy)]
@@ -89,6 +599,10 @@ pub struct CameraUBO {
     padding: f32,
     z_near: f32,
     z_far: f32,
+    reverse_z: u32,
+    env_padding: u32,
+    env_rotation: Matrix4<f32>,
+    previous_view_proj: Matrix4<f32>,
 }
 
 impl CameraUBO {
@@ -99,6 +613,9 @@ impl CameraUBO {
         eye: Point3<f32>,
         z_near: f32,
         z_far: f32,
+        reverse_z: bool,
+        env_rotation: Matrix4<f32>,
+        previous_view_proj: Matrix4<f32>,
     ) -> Self {
         Self {
             view,
@@ -108,6 +625,10 @@ impl CameraUBO {
             padding: 0.0,
             z_near,
             z_far,
+            reverse_z: reverse_z as u32,
+            env_padding: 0,
+            env_rotation,
+            previous_view_proj,
         }
     }
 }
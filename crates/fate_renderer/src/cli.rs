@@ -0,0 +1,321 @@
+use crate::config::{ConfigOverrides, DEFAULT_CONFIG_PATH};
+use clap::{Arg, ArgMatches, Command};
+use rendering::fscene::FSCENE_EXTENSION;
+use std::path::PathBuf;
+use vulkan::DevicePreference;
+
+const DEFAULT_MODEL_PATH: &str = "assets/models/FlightHelmet/glTF/FlightHelmet.gltf";
+
+//命令行参数，没给出的项使用配置文件中的值
+pub struct Args {
+    pub config_path: PathBuf,
+    pub model_path: Option<PathBuf>,
+    //场景清单，给出时不加载默认模型
+    pub scene_path: Option<PathBuf>,
+    pub enable_debug: bool,
+    pub device: DevicePreference,
+    pub list_devices: bool,
+    pub headless: Option<HeadlessArgs>,
+    pub convert: Option<ConvertArgs>,
+    pub compare: Option<CompareArgs>,
+    pub overrides: ConfigOverrides,
+}
+
+pub struct HeadlessArgs {
+    pub model_path: PathBuf,
+    pub output_path: PathBuf,
+}
+
+pub struct ConvertArgs {
+    pub input_path: PathBuf,
+    pub output_path: PathBuf,
+}
+
+pub struct CompareArgs {
+    pub model_path: PathBuf,
+    //光栅化、路径追踪和差异图都写到这个目录下
+    pub output_dir: PathBuf,
+    pub samples_per_pixel: usize,
+}
+
+impl Args {
+    pub fn parse() -> Result<Self, String> {
+        Self::from_matches(&command().get_matches())
+    }
+
+    fn from_matches(matches: &ArgMatches) -> Result<Self, String> {
+        let resolution = match matches.value_of("size") {
+            Some(size) => Some(parse_size(size).ok_or_else(|| format!("无法解析分辨率{}", size))?),
+            None => None,
+        };
+        let env_resolution = match matches.value_of("env-resolution") {
+            Some(value) => Some(
+                value
+                    .parse::<u32>()
+                    .map_err(|_| format!("无法解析环境贴图分辨率{}", value))?,
+            ),
+            None => None,
+        };
+
+        let max_fps = match matches.value_of("max-fps") {
+            Some(value) => Some(
+                value
+                    .parse::<u32>()
+                    .map_err(|_| format!("无法解析帧率上限{}", value))?,
+            ),
+            None => None,
+        };
+
+        let overrides = ConfigOverrides {
+            resolution,
+            fullscreen: flag(matches, "fullscreen", "windowed"),
+            vsync: flag(matches, "vsync", "no-vsync"),
+            max_fps,
+            reverse_z: flag(matches, "reverse-z", "no-reverse-z"),
+            env_path: matches.value_of("env").map(String::from),
+            env_resolution,
+        };
+
+        //纯数字按序号匹配，否则按名称匹配
+        let mut device = match matches.value_of("gpu") {
+            Some(value) => match value.parse::<usize>() {
+                Ok(index) => DevicePreference {
+                    index: Some(index),
+                    ..Default::default()
+                },
+                Err(_) => DevicePreference {
+                    name: Some(value.to_string()),
+                    ..Default::default()
+                },
+            },
+            None => DevicePreference::default(),
+        };
+        device.disable_dynamic_rendering = matches.is_present("no-dynamic-rendering");
+
+        let headless = matches.value_of("render").map(|model_path| HeadlessArgs {
+            model_path: PathBuf::from(model_path),
+            output_path: PathBuf::from(matches.value_of("out").unwrap()),
+        });
+
+        //没给出输出路径时与输入同名，扩展名换成fscene
+        let convert = matches.value_of("convert").map(|input_path| {
+            let input_path = PathBuf::from(input_path);
+            let output_path = matches
+                .value_of("convert-out")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| input_path.with_extension(FSCENE_EXTENSION));
+            ConvertArgs {
+                input_path,
+                output_path,
+            }
+        });
+
+        let compare = match matches.value_of("compare") {
+            Some(model_path) => {
+                let spp = matches.value_of("spp").unwrap();
+                let samples_per_pixel = spp
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|spp| *spp > 0)
+                    .ok_or_else(|| format!("无法解析采样数{}", spp))?;
+                Some(CompareArgs {
+                    model_path: PathBuf::from(model_path),
+                    output_dir: PathBuf::from(matches.value_of("compare-out").unwrap()),
+                    samples_per_pixel,
+                })
+            }
+            None => None,
+        };
+
+        let scene_path = matches.value_of("scene").map(PathBuf::from);
+        let model_path = if matches.is_present("no-model") || scene_path.is_some() {
+            None
+        } else {
+            Some(PathBuf::from(
+                matches.value_of("model").unwrap_or(DEFAULT_MODEL_PATH),
+            ))
+        };
+
+        Ok(Self {
+            config_path: PathBuf::from(matches.value_of("config").unwrap()),
+            model_path,
+            scene_path,
+            enable_debug: !matches.is_present("no-validation"),
+            device,
+            list_devices: matches.is_present("list-gpus"),
+            headless,
+            convert,
+            compare,
+            overrides,
+        })
+    }
+}
+
+fn command() -> Command<'static> {
+    Command::new("fate")
+        .arg(
+            Arg::new("model")
+                .value_name("MODEL")
+                .help("启动时加载的glTF文件"),
+        )
+        .arg(
+            Arg::new("no-model")
+                .long("no-model")
+                .conflicts_with("model")
+                .help("启动时不加载模型"),
+        )
+        .arg(
+            Arg::new("scene")
+                .long("scene")
+                .value_name("MANIFEST")
+                .takes_value(true)
+                .conflicts_with("model")
+                .help("启动时按场景清单(TOML/JSON)加载多个模型，修改清单后自动重新加载"),
+        )
+        .arg(
+            Arg::new("config")
+                .long("config")
+                .value_name("TOML")
+                .takes_value(true)
+                .default_value(DEFAULT_CONFIG_PATH),
+        )
+        .arg(
+            Arg::new("env")
+                .long("env")
+                .value_name("HDR")
+                .takes_value(true)
+                .help("环境贴图路径"),
+        )
+        .arg(
+            Arg::new("env-resolution")
+                .long("env-resolution")
+                .value_name("SIZE")
+                .takes_value(true)
+                .help("环境cubemap分辨率"),
+        )
+        .arg(
+            Arg::new("size")
+                .long("size")
+                .value_name("WIDTHxHEIGHT")
+                .takes_value(true)
+                .help("窗口或输出图片的分辨率"),
+        )
+        .arg(Arg::new("vsync").long("vsync").conflicts_with("no-vsync"))
+        .arg(Arg::new("no-vsync").long("no-vsync"))
+        .arg(
+            Arg::new("max-fps")
+                .long("max-fps")
+                .value_name("FPS")
+                .takes_value(true)
+                .help("帧率上限，0表示不限制"),
+        )
+        .arg(
+            Arg::new("fullscreen")
+                .long("fullscreen")
+                .conflicts_with("windowed"),
+        )
+        .arg(Arg::new("windowed").long("windowed"))
+        .arg(
+            Arg::new("reverse-z")
+                .long("reverse-z")
+                .conflicts_with("no-reverse-z")
+                .help("主相机使用reverse-Z深度缓冲"),
+        )
+        .arg(Arg::new("no-reverse-z").long("no-reverse-z"))
+        .arg(
+            Arg::new("no-validation")
+                .long("no-validation")
+                .help("关闭vulkan验证层"),
+        )
+        .arg(
+            Arg::new("gpu")
+                .long("gpu")
+                .value_name("INDEX|NAME")
+                .takes_value(true)
+                .help("使用的显卡序号或名称"),
+        )
+        .arg(
+            Arg::new("no-dynamic-rendering")
+                .long("no-dynamic-rendering")
+                .help("不使用dynamic rendering，改用render pass渲染（不显示ui）"),
+        )
+        .arg(
+            Arg::new("list-gpus")
+                .long("list-gpus")
+                .help("列出可用的显卡后退出"),
+        )
+        .arg(
+            Arg::new("render")
+                .long("render")
+                .value_name("MODEL")
+                .takes_value(true)
+                .help("不创建窗口，把模型渲染成PNG后退出"),
+        )
+        .arg(
+            Arg::new("out")
+                .long("out")
+                .value_name("PNG")
+                .takes_value(true)
+                .default_value("out.png")
+                .requires("render"),
+        )
+        .arg(
+            Arg::new("convert")
+                .long("convert")
+                .value_name("GLTF")
+                .takes_value(true)
+                .conflicts_with("render")
+                .help("把glTF/glb预处理成.fscene后退出"),
+        )
+        .arg(
+            Arg::new("convert-out")
+                .long("convert-out")
+                .value_name("FSCENE")
+                .takes_value(true)
+                .requires("convert"),
+        )
+        .arg(
+            Arg::new("compare")
+                .long("compare")
+                .value_name("MODEL")
+                .takes_value(true)
+                .conflicts_with_all(&["render", "convert"])
+                .help("用光栅化和fate_rt路径追踪渲染同一画面，输出差异图和MSE/SSIM后退出"),
+        )
+        .arg(
+            Arg::new("compare-out")
+                .long("compare-out")
+                .value_name("DIR")
+                .takes_value(true)
+                .default_value("compare")
+                .requires("compare"),
+        )
+        .arg(
+            Arg::new("spp")
+                .long("spp")
+                .value_name("SAMPLES")
+                .takes_value(true)
+                .default_value("64")
+                .requires("compare")
+                .help("路径追踪每像素采样数"),
+        )
+}
+
+//成对的开关参数，都没给出时为None
+fn flag(matches: &ArgMatches, on: &str, off: &str) -> Option<bool> {
+    if matches.is_present(on) {
+        Some(true)
+    } else if matches.is_present(off) {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+//"1920x1080"
+fn parse_size(value: &str) -> Option<[u32; 2]> {
+    let (width, height) = value.split_once(['x', 'X'])?;
+    let width = width.trim().parse().ok().filter(|w| *w > 0)?;
+    let height = height.trim().parse().ok().filter(|h| *h > 0)?;
+    Some([width, height])
+}
@@ -0,0 +1,114 @@
+use image::{Rgb, RgbImage};
+
+//差异图放大倍数，细小的着色误差也能看清
+const DIFF_SCALE: f32 = 4.0;
+//SSIM按不重叠的8x8窗口统计亮度
+const SSIM_WINDOW: u32 = 8;
+const SSIM_C1: f64 = 0.01 * 0.01;
+const SSIM_C2: f64 = 0.03 * 0.03;
+//fate_rt输出gamma 2.0编码，最终pass输出gamma 2.2编码
+const PATH_TRACER_GAMMA: f32 = 2.0;
+const RASTER_GAMMA: f32 = 2.2;
+
+pub struct ImageDiff {
+    //RGB三个通道归一化到[0,1]后的均方误差
+    pub mse: f64,
+    //亮度的平均SSIM，1表示完全一致
+    pub ssim: f64,
+    pub diff: RgbImage,
+}
+
+//光栅化输出的RGBA8去掉alpha
+pub fn rgb_from_rgba(width: u32, height: u32, rgba: &[u8]) -> RgbImage {
+    let rgb = rgba
+        .chunks_exact(4)
+        .flat_map(|pixel| [pixel[0], pixel[1], pixel[2]])
+        .collect();
+    RgbImage::from_raw(width, height, rgb).expect("像素数量与分辨率不一致")
+}
+
+//把fate_rt的输出换成与光栅化相同的gamma编码，否则中间调会整体偏亮
+pub fn path_traced_to_raster_gamma(width: u32, height: u32, rgb: &[u8]) -> RgbImage {
+    let exponent = PATH_TRACER_GAMMA / RASTER_GAMMA;
+    let rgb = rgb
+        .iter()
+        .map(|&v| ((v as f32 / 255.0).powf(exponent) * 255.0).round() as u8)
+        .collect();
+    RgbImage::from_raw(width, height, rgb).expect("像素数量与分辨率不一致")
+}
+
+pub fn compare(reference: &RgbImage, image: &RgbImage) -> ImageDiff {
+    assert_eq!(
+        reference.dimensions(),
+        image.dimensions(),
+        "对比的两张图片尺寸不同"
+    );
+    let (width, height) = reference.dimensions();
+
+    let mut squared_error = 0.0;
+    let mut diff = RgbImage::new(width, height);
+    for (x, y, pixel) in diff.enumerate_pixels_mut() {
+        let a = reference.get_pixel(x, y);
+        let b = image.get_pixel(x, y);
+        let mut channels = [0; 3];
+        for c in 0..3 {
+            let error = (a[c] as f32 - b[c] as f32).abs() / 255.0;
+            squared_error += (error * error) as f64;
+            channels[c] = (error * DIFF_SCALE * 255.0).min(255.0) as u8;
+        }
+        *pixel = Rgb(channels);
+    }
+    let mse = squared_error / (width as f64 * height as f64 * 3.0);
+
+    ImageDiff {
+        mse,
+        ssim: ssim(reference, image),
+        diff,
+    }
+}
+
+fn luminance(pixel: &Rgb<u8>) -> f64 {
+    (0.2126 * pixel[0] as f64 + 0.7152 * pixel[1] as f64 + 0.0722 * pixel[2] as f64) / 255.0
+}
+
+fn ssim(reference: &RgbImage, image: &RgbImage) -> f64 {
+    let (width, height) = reference.dimensions();
+    let mut total = 0.0;
+    let mut windows = 0;
+
+    for window_y in (0..height).step_by(SSIM_WINDOW as usize) {
+        for window_x in (0..width).step_by(SSIM_WINDOW as usize) {
+            let x_end = (window_x + SSIM_WINDOW).min(width);
+            let y_end = (window_y + SSIM_WINDOW).min(height);
+
+            let mut samples = Vec::with_capacity((SSIM_WINDOW * SSIM_WINDOW) as usize);
+            for y in window_y..y_end {
+                for x in window_x..x_end {
+                    samples.push((
+                        luminance(reference.get_pixel(x, y)),
+                        luminance(image.get_pixel(x, y)),
+                    ));
+                }
+            }
+
+            let n = samples.len() as f64;
+            let mean_a = samples.iter().map(|s| s.0).sum::<f64>() / n;
+            let mean_b = samples.iter().map(|s| s.1).sum::<f64>() / n;
+            let (mut var_a, mut var_b, mut covariance) = (0.0, 0.0, 0.0);
+            for (a, b) in samples.iter() {
+                var_a += (a - mean_a) * (a - mean_a);
+                var_b += (b - mean_b) * (b - mean_b);
+                covariance += (a - mean_a) * (b - mean_b);
+            }
+            var_a /= n;
+            var_b /= n;
+            covariance /= n;
+
+            total += ((2.0 * mean_a * mean_b + SSIM_C1) * (2.0 * covariance + SSIM_C2))
+                / ((mean_a * mean_a + mean_b * mean_b + SSIM_C1) * (var_a + var_b + SSIM_C2));
+            windows += 1;
+        }
+    }
+
+    total / windows as f64
+}
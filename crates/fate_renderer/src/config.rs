@@ -1,15 +1,258 @@
+use crate::camera::{Camera, CameraBookmark, CameraMode, ClipPlanes, DEFAULT_MOVE_SPEED};
+use crate::gui::{SHADOW_PCF_KERNEL_SIZES, SSAO_KERNEL_SIZES};
+use crate::inputsystem::InputBindings;
+use crate::renderer::{
+    RendererSettings, BLOOM_MIP_LEVELS, DEFAULT_FRAMES_IN_FLIGHT, MAX_EXPOSURE,
+    MAX_FRAMES_IN_FLIGHT, MAX_LIGHT_CLUSTER_GRID, MAX_OUTLINE_THICKNESS, MAX_PARALLAX_STEPS,
+    MAX_RENDER_SCALE, MAX_SHADOW_CASCADES, MAX_SHADOW_CASTING_LIGHTS, MAX_SHADOW_CATCHER_SIZE,
+    MAX_SSAO_BLUR_RADIUS, MAX_WHITE_POINT, MIN_EXPOSURE, MIN_RENDER_SCALE, MIN_WHITE_POINT,
+};
+use rendering::cgmath::Point3;
+use serde::{Deserialize, Serialize};
+use std::{error::Error, fs, path::Path};
 use vulkan::MsaaSamples;
 
-#[derive(Clone)]
+pub const DEFAULT_CONFIG_PATH: &str = "config.toml";
+const DEFAULT_BACKGROUND_FPS: u32 = 10;
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Config {
     resolution: Resolution,
     fullscreen: bool,
     vsync: Option<bool>,
+    //帧率上限，与vsync无关，None时不限制
+    max_fps: Option<u32>,
+    //窗口失去焦点或被遮挡时的帧率上限，最小化时不渲染
+    background_fps: Option<u32>,
+    #[serde(with = "msaa_samples")]
     msaa: MsaaSamples,
+    //主相机使用reverse-Z深度，需要重启生效
+    reverse_z: bool,
+    //同时在GPU上排队的帧数，越大吞吐越高但延迟也越高，还会被限制在交换链image数以内
+    frames_in_flight: u32,
     env: EnvironmentConfig,
+    camera: Option<CameraConfig>,
+    renderer: RendererSettings,
+    input: InputBindings,
+    //命名的相机位姿，在相机详情窗口中保存和跳转
+    camera_bookmarks: Vec<CameraBookmark>,
 }
 
 impl Config {
+    //文件不存在或解析失败时使用默认配置
+    pub fn load<P: AsRef<Path>>(path: P) -> Self {
+        let path = path.as_ref();
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(_) => {
+                log::info!("未找到配置文件{:?}，使用默认配置", path);
+                return Self::default();
+            }
+        };
+
+        match toml::from_str::<Config>(&content) {
+            Ok(mut config) => {
+                config.validate();
+                config
+            }
+            Err(err) => {
+                log::warn!("配置文件{:?}解析失败，使用默认配置: {}", path, err);
+                Self::default()
+            }
+        }
+    }
+
+    //命令行参数覆盖的项，覆盖后重新校验
+    pub fn apply_overrides(&mut self, overrides: &ConfigOverrides) {
+        if let Some([width, height]) = overrides.resolution {
+            self.resolution = Resolution { width, height };
+        }
+        if let Some(fullscreen) = overrides.fullscreen {
+            self.fullscreen = fullscreen;
+        }
+        if let Some(vsync) = overrides.vsync {
+            self.vsync = Some(vsync);
+        }
+        if let Some(max_fps) = overrides.max_fps {
+            self.max_fps = Some(max_fps);
+        }
+        if let Some(reverse_z) = overrides.reverse_z {
+            self.reverse_z = reverse_z;
+        }
+        if let Some(env_path) = overrides.env_path.as_ref() {
+            self.env.path = env_path.clone();
+        }
+        if let Some(env_resolution) = overrides.env_resolution {
+            self.env.resolution = Some(env_resolution);
+        }
+        self.validate();
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        let content = toml::to_string_pretty(self)?;
+        fs::write(path, content)?;
+        Ok(())
+    }
+
+    fn validate(&mut self) {
+        if self.resolution.width == 0 || self.resolution.height == 0 {
+            log::warn!(
+                "非法分辨率{}x{}，使用默认分辨率",
+                self.resolution.width,
+                self.resolution.height
+            );
+            self.resolution = Default::default();
+        }
+
+        //0表示不限制帧率
+        if self.max_fps == Some(0) {
+            self.max_fps = None;
+        }
+        if self.background_fps == Some(0) {
+            self.background_fps = None;
+        }
+
+        if !(1..=MAX_FRAMES_IN_FLIGHT).contains(&self.frames_in_flight) {
+            log::warn!("非法在途帧数{}", self.frames_in_flight);
+            self.frames_in_flight = DEFAULT_FRAMES_IN_FLIGHT;
+        }
+
+        if self.env.path.is_empty() {
+            self.env.path = String::from(EnvironmentConfig::SKYBOX_DEFAULT_PATH);
+        }
+        if self.env.resolution == Some(0) {
+            self.env.resolution = None;
+        }
+
+        if let Some(camera) = self.camera {
+            let valid = camera.position.iter().all(|v| v.is_finite())
+                && camera.target.iter().all(|v| v.is_finite())
+                && camera.position != camera.target;
+            if !valid {
+                log::warn!("非法相机位姿，忽略");
+                self.camera = None;
+            } else {
+                let mut camera = camera;
+                if camera.move_speed.is_nan() || camera.move_speed <= 0.0 {
+                    camera.move_speed = DEFAULT_MOVE_SPEED;
+                }
+                if !camera.clip_planes.is_valid() {
+                    log::warn!(
+                        "非法近远平面{:?}，近平面需要大于0且小于远平面，使用自动近远平面",
+                        camera.clip_planes
+                    );
+                    camera.clip_planes = ClipPlanes::Auto;
+                }
+                self.camera = Some(camera);
+            }
+        }
+
+        self.camera_bookmarks.retain(|bookmark| {
+            let valid = bookmark.is_valid();
+            if !valid {
+                log::warn!("非法相机书签{}，忽略", bookmark.name());
+            }
+            valid
+        });
+
+        let defaults = RendererSettings::default();
+        let settings = &mut self.renderer;
+        if !SSAO_KERNEL_SIZES.contains(&settings.ssao_kernel_size) {
+            log::warn!("非法ssao kernel大小{}", settings.ssao_kernel_size);
+            settings.ssao_kernel_size = defaults.ssao_kernel_size;
+        }
+        if !(1..=MAX_SSAO_BLUR_RADIUS).contains(&settings.ssao_blur_radius) {
+            log::warn!("非法ssao模糊半径{}", settings.ssao_blur_radius);
+            settings.ssao_blur_radius = defaults.ssao_blur_radius;
+        }
+        if settings.bloom_threshold.is_nan() || settings.bloom_threshold < 0.0 {
+            settings.bloom_threshold = defaults.bloom_threshold;
+        }
+        if !(1..=BLOOM_MIP_LEVELS).contains(&settings.bloom_mip_levels) {
+            log::warn!("非法bloom层级数{}", settings.bloom_mip_levels);
+            settings.bloom_mip_levels = defaults.bloom_mip_levels;
+        }
+        if !(MIN_EXPOSURE..=MAX_EXPOSURE).contains(&settings.exposure) {
+            log::warn!("非法曝光{}EV", settings.exposure);
+            settings.exposure = defaults.exposure;
+        }
+        if !(MIN_WHITE_POINT..=MAX_WHITE_POINT).contains(&settings.white_point) {
+            log::warn!("非法白点{}", settings.white_point);
+            settings.white_point = defaults.white_point;
+        }
+        if settings.fog_start > settings.fog_end {
+            settings.fog_start = defaults.fog_start;
+            settings.fog_end = defaults.fog_end;
+        }
+
+        if !(1..=MAX_SHADOW_CASCADES).contains(&settings.shadow_cascade_count) {
+            log::warn!("非法阴影级联数{}", settings.shadow_cascade_count);
+            settings.shadow_cascade_count = defaults.shadow_cascade_count;
+        }
+        if !(0.0..=1.0).contains(&settings.shadow_split_lambda) {
+            settings.shadow_split_lambda = defaults.shadow_split_lambda;
+        }
+        if !(0.0..=1.0).contains(&settings.sharpness) {
+            settings.sharpness = defaults.sharpness;
+        }
+        if !SHADOW_PCF_KERNEL_SIZES.contains(&settings.shadow_pcf_kernel_size) {
+            log::warn!("非法PCF kernel大小{}", settings.shadow_pcf_kernel_size);
+            settings.shadow_pcf_kernel_size = defaults.shadow_pcf_kernel_size;
+        }
+        if settings.shadow_depth_bias < 0.0 {
+            settings.shadow_depth_bias = defaults.shadow_depth_bias;
+        }
+        if settings.shadow_normal_bias < 0.0 {
+            settings.shadow_normal_bias = defaults.shadow_normal_bias;
+        }
+        if settings.shadow_casting_lights > MAX_SHADOW_CASTING_LIGHTS {
+            log::warn!("非法阴影灯光数{}", settings.shadow_casting_lights);
+            settings.shadow_casting_lights = defaults.shadow_casting_lights;
+        }
+        if !settings.clear_color.iter().all(|c| c.is_finite()) {
+            log::warn!("非法背景色{:?}", settings.clear_color);
+            settings.clear_color = defaults.clear_color;
+        }
+        settings.clear_color = settings.clear_color.map(|c| c.clamp(0.0, 1.0));
+        if !(MIN_RENDER_SCALE..=MAX_RENDER_SCALE).contains(&settings.render_scale) {
+            log::warn!("非法渲染缩放{}", settings.render_scale);
+            settings.render_scale = defaults.render_scale;
+        }
+        if !(0.0..=MAX_SHADOW_CATCHER_SIZE).contains(&settings.shadow_catcher_size) {
+            log::warn!("非法阴影接收地面大小{}", settings.shadow_catcher_size);
+            settings.shadow_catcher_size = defaults.shadow_catcher_size;
+        }
+        if !settings.shadow_catcher_color.iter().all(|c| c.is_finite()) {
+            log::warn!("非法阴影颜色{:?}", settings.shadow_catcher_color);
+            settings.shadow_catcher_color = defaults.shadow_catcher_color;
+        }
+        settings.shadow_catcher_color = settings.shadow_catcher_color.map(|c| c.clamp(0.0, 1.0));
+        if settings.outline_thickness == 0 || settings.outline_thickness > MAX_OUTLINE_THICKNESS {
+            log::warn!("非法描边宽度{}", settings.outline_thickness);
+            settings.outline_thickness = defaults.outline_thickness;
+        }
+        if !settings.outline_color.iter().all(|c| c.is_finite()) {
+            log::warn!("非法描边颜色{:?}", settings.outline_color);
+            settings.outline_color = defaults.outline_color;
+        }
+        settings.outline_color = settings.outline_color.map(|c| c.clamp(0.0, 1.0));
+        if !settings
+            .light_cluster_grid
+            .iter()
+            .all(|size| (1..=MAX_LIGHT_CLUSTER_GRID).contains(size))
+        {
+            log::warn!("非法灯光分簇网格{:?}", settings.light_cluster_grid);
+            settings.light_cluster_grid = defaults.light_cluster_grid;
+        }
+        if !(1..=MAX_PARALLAX_STEPS).contains(&settings.parallax_steps) {
+            log::warn!("非法视差步进次数{}", settings.parallax_steps);
+            settings.parallax_steps = defaults.parallax_steps;
+        }
+
+        self.input.validate();
+    }
+
     pub fn resolution(&self) -> Resolution {
         self.resolution
     }
@@ -22,13 +265,65 @@ impl Config {
         self.vsync.unwrap_or(false)
     }
 
+    pub fn max_fps(&self) -> Option<u32> {
+        self.max_fps
+    }
+
+    pub fn background_fps(&self) -> Option<u32> {
+        self.background_fps
+    }
+
     pub fn msaa(&self) -> MsaaSamples {
         self.msaa
     }
 
+    pub fn reverse_z(&self) -> bool {
+        self.reverse_z
+    }
+
+    pub fn frames_in_flight(&self) -> u32 {
+        self.frames_in_flight
+    }
+
     pub fn env(&self) -> &EnvironmentConfig {
         &self.env
     }
+
+    pub fn camera(&self) -> Option<CameraConfig> {
+        self.camera
+    }
+
+    pub fn set_camera(&mut self, camera: &Camera, move_speed: f32) {
+        let position = camera.position();
+        let target = camera.target();
+        self.camera = Some(CameraConfig {
+            mode: camera.mode(),
+            position: [position.x, position.y, position.z],
+            target: [target.x, target.y, target.z],
+            move_speed,
+            clip_planes: camera.clip_planes(),
+        });
+    }
+
+    pub fn camera_bookmarks(&self) -> &[CameraBookmark] {
+        &self.camera_bookmarks
+    }
+
+    pub fn set_camera_bookmarks(&mut self, bookmarks: Vec<CameraBookmark>) {
+        self.camera_bookmarks = bookmarks;
+    }
+
+    pub fn input_bindings(&self) -> &InputBindings {
+        &self.input
+    }
+
+    pub fn renderer_settings(&self) -> RendererSettings {
+        self.renderer
+    }
+
+    pub fn set_renderer_settings(&mut self, settings: RendererSettings) {
+        self.renderer = settings;
+    }
 }
 
 impl Default for Config {
@@ -37,13 +332,33 @@ impl Default for Config {
             resolution: Default::default(),
             fullscreen: false,
             vsync: Some(false),
+            max_fps: None,
+            background_fps: Some(DEFAULT_BACKGROUND_FPS),
             msaa: MsaaSamples::S1,
+            reverse_z: false,
+            frames_in_flight: DEFAULT_FRAMES_IN_FLIGHT,
             env: Default::default(),
+            camera: None,
+            renderer: Default::default(),
+            input: Default::default(),
+            camera_bookmarks: Vec::new(),
         }
     }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Clone, Default)]
+pub struct ConfigOverrides {
+    pub resolution: Option<[u32; 2]>,
+    pub fullscreen: Option<bool>,
+    pub vsync: Option<bool>,
+    pub max_fps: Option<u32>,
+    pub reverse_z: Option<bool>,
+    pub env_path: Option<String>,
+    pub env_resolution: Option<u32>,
+}
+
+#[derive(Copy, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Resolution {
     width: u32,
     height: u32,
@@ -68,7 +383,8 @@ impl Default for Resolution {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct EnvironmentConfig {
     path: String,
     resolution: Option<u32>,
@@ -95,3 +411,74 @@ impl Default for EnvironmentConfig {
         }
     }
 }
+
+#[derive(Copy, Clone, Serialize, Deserialize)]
+pub struct CameraConfig {
+    mode: CameraMode,
+    position: [f32; 3],
+    target: [f32; 3],
+    #[serde(default = "default_move_speed")]
+    move_speed: f32,
+    #[serde(default)]
+    clip_planes: ClipPlanes,
+}
+
+impl CameraConfig {
+    pub fn mode(&self) -> CameraMode {
+        self.mode
+    }
+
+    pub fn move_speed(&self) -> f32 {
+        self.move_speed
+    }
+
+    pub fn clip_planes(&self) -> ClipPlanes {
+        self.clip_planes
+    }
+
+    pub fn to_camera(self) -> Camera {
+        let mut camera = Camera::new(self.mode);
+        camera.set_move_speed(self.move_speed);
+        camera.set_clip_planes(self.clip_planes);
+        camera.look_at(Point3::from(self.position), Point3::from(self.target));
+        camera
+    }
+}
+
+fn default_move_speed() -> f32 {
+    DEFAULT_MOVE_SPEED
+}
+
+//MSAA以采样数形式存储
+mod msaa_samples {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+    use vulkan::MsaaSamples;
+
+    pub fn serialize<S: Serializer>(msaa: &MsaaSamples, serializer: S) -> Result<S::Ok, S::Error> {
+        let samples = match msaa {
+            MsaaSamples::S1 => 1,
+            MsaaSamples::S2 => 2,
+            MsaaSamples::S4 => 4,
+            MsaaSamples::S8 => 8,
+            MsaaSamples::S16 => 16,
+            MsaaSamples::S32 => 32,
+            MsaaSamples::S64 => 64,
+        };
+        serializer.serialize_u32(samples)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<MsaaSamples, D::Error> {
+        match u32::deserialize(deserializer)? {
+            1 => Ok(MsaaSamples::S1),
+            2 => Ok(MsaaSamples::S2),
+            4 => Ok(MsaaSamples::S4),
+            8 => Ok(MsaaSamples::S8),
+            16 => Ok(MsaaSamples::S16),
+            32 => Ok(MsaaSamples::S32),
+            64 => Ok(MsaaSamples::S64),
+            samples => Err(D::Error::custom(format!("非法MSAA采样数{}", samples))),
+        }
+    }
+}
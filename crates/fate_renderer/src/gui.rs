@@ -1,16 +1,39 @@
-use crate::camera::Camera;
+use crate::camera::{
+    Camera, CameraBookmark, CameraMode, ClipPlanes, Projection, DEFAULT_MOVE_SPEED, FOV, MAX_Z_FAR,
+    MIN_Z_NEAR, Z_FAR, Z_NEAR,
+};
+use crate::inputsystem::{Action, InputBindings};
+use crate::loader::LoadStatus;
+use crate::logger::{LogRecord, LOG_CAPTURE_CAPACITY, LOG_LEVELS};
 use crate::renderer::{
-    FXAAMode, OutputMode, RendererSettings, ToneMapMode, DEFAULT_BLOOM_STRENGTH,
+    AntiAliasingMode, CullingStats, DebugAttachment, FXAAMode, GpuPass, OutputMode, PolygonMode,
+    RendererSettings, SceneStats, ShadowFilterMode, ShadowMapResolution, ToneMapMode,
+    BLOOM_MIP_LEVELS, DEFAULT_BLOOM_STRENGTH, DEFAULT_BLOOM_THRESHOLD, DEFAULT_LIGHT_CLUSTER_GRID,
+    DEFAULT_OUTLINE_COLOR, DEFAULT_OUTLINE_THICKNESS, DEFAULT_PARALLAX_STEPS,
+    DEFAULT_SHADOW_CATCHER_COLOR, DEFAULT_SSAO_BLUR_RADIUS, DEFAULT_WHITE_POINT, MAX_EXPOSURE,
+    MAX_LIGHT_CLUSTER_GRID, MAX_OUTLINE_THICKNESS, MAX_PARALLAX_STEPS, MAX_RENDER_SCALE,
+    MAX_SHADOW_CASCADES, MAX_SHADOW_CASTING_LIGHTS, MAX_SHADOW_CATCHER_SIZE, MAX_SSAO_BLUR_RADIUS,
+    MAX_WHITE_POINT, MIN_EXPOSURE, MIN_RENDER_SCALE, MIN_WHITE_POINT,
 };
-use egui::{ClippedPrimitive, Context, Label, Sense, TexturesDelta, Ui, ViewportId, Widget};
+use egui::{ClippedPrimitive, Context, Label, Sense, TexturesDelta, Ui, ViewportId};
 use egui_winit::State as EguiWinit;
 use gltf_loader::model::Model;
 use rendering::animation::PlaybackState;
+use rendering::cgmath::{
+    Deg, EuclideanSpace, Euler, InnerSpace, Matrix4, Point3, Quaternion, Rad, Rotation3,
+    SquareMatrix, Transform, Vector3,
+};
+use rendering::light::{LightType, MAX_COLOR_TEMPERATURE, MIN_COLOR_TEMPERATURE};
+use rendering::material::MaterialOverride;
 use rendering::metadata::{Metadata, Node, NodeKind};
+use rendering::node::NodeUid;
 use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
 use std::rc::{Rc, Weak};
+use std::time::{Duration, Instant};
 use vulkan::winit::event::WindowEvent;
 use vulkan::winit::window::Window as WinitWindow;
+use vulkan::{ValidationMessage, ValidationSeverity, VALIDATION_CAPTURE_CAPACITY};
 
 pub struct RenderData {
     pub pixels_per_point: f32,
@@ -18,16 +41,44 @@ pub struct RenderData {
     pub clipped_primitives: Vec<ClippedPrimitive>,
 }
 
+struct GuiModel {
+    metadata: Metadata,
+    model: Weak<RefCell<Model>>,
+}
+
 pub struct Gui {
     egui: Context,
     egui_winit: EguiWinit,
-    model_metadata: Option<Metadata>,
-    model: Weak<RefCell<Model>>,
+    models: Vec<GuiModel>,
     animation_playback_state: Option<PlaybackState>,
     camera: Option<Camera>,
+    camera_bookmarks: Vec<CameraBookmark>,
+    load_status: Option<LoadStatus>,
+    load_errors: Vec<(String, Instant)>,
+    input_bindings: Vec<(Action, String)>,
+    performance: PerformanceStats,
+    validation_available: bool,
+    validation_messages: VecDeque<ValidationMessage>,
+    log_records: VecDeque<LogRecord>,
     state: State,
 }
 
+const ERROR_TOAST_DURATION: Duration = Duration::from_secs(8);
+const FRAME_TIME_HISTORY: usize = 240;
+const FRAME_TIME_GRAPH_SIZE: [f32; 2] = [240.0, 60.0];
+const DEFAULT_ORTHO_HEIGHT: f32 = 5.0;
+const DEFAULT_LIGHT_TEMPERATURE: f32 = 6500.0;
+
+#[derive(Default)]
+struct PerformanceStats {
+    //单位都是毫秒
+    frame_times: VecDeque<f32>,
+    cpu_time: f32,
+    gpu_timings: Vec<(GpuPass, f32)>,
+    culling: CullingStats,
+    scene: SceneStats,
+}
+
 impl Gui {
     pub fn new(window: &WinitWindow, renderer_settings: RendererSettings) -> Self {
         let (egui, egui_winit) = init_egui(window);
@@ -35,10 +86,17 @@ impl Gui {
         Self {
             egui,
             egui_winit,
-            model_metadata: None,
-            model: Weak::new(),
+            models: Vec::new(),
             animation_playback_state: None,
             camera: None,
+            camera_bookmarks: Vec::new(),
+            load_status: None,
+            load_errors: Vec::new(),
+            input_bindings: Vec::new(),
+            performance: Default::default(),
+            validation_available: false,
+            validation_messages: VecDeque::new(),
+            log_records: VecDeque::new(),
             state: State::new(renderer_settings),
         }
     }
@@ -51,6 +109,8 @@ impl Gui {
         let raw_input = self.egui_winit.take_egui_input(window);
 
         let previous_state = self.state.clone();
+        self.state.scrub_animation_time = None;
+        self.state.jump_camera_bookmark = None;
 
         let egui::FullOutput {
             platform_output,
@@ -62,31 +122,75 @@ impl Gui {
             egui::Window::new("菜单")
                 .default_open(true)
                 .show(ctx, |ui| {
-                    build_camera_details_window(ui, &mut self.state, self.camera);
+                    build_camera_details_window(
+                        ui,
+                        &mut self.state,
+                        self.camera,
+                        &mut self.camera_bookmarks,
+                    );
+                    build_input_bindings_window(ui, &self.input_bindings);
                     ui.separator();
-                    build_renderer_settings_window(ui, &mut self.state);
+                    build_renderer_settings_window(ui, &mut self.state, self.validation_available);
                 });
 
             egui::Window::new("Hierarchy")
                 .default_open(true)
                 .show(ctx, |ui| {
-                    if let Some(metadata) = self.model_metadata.as_ref() {
+                    for (index, gui_model) in self.models.iter().enumerate() {
+                        let metadata = &gui_model.metadata;
                         if metadata.node_count() > 0 {
-                            build_model_hierarchy(ui, &mut self.state, metadata.nodes());
+                            egui::CollapsingHeader::new(format!(
+                                "Model {}: {}",
+                                index,
+                                metadata.name()
+                            ))
+                            .id_source(index)
+                            .default_open(self.models.len() == 1)
+                            .show(ui, |ui| {
+                                let model = gui_model.model.upgrade().expect("模型已被释放！");
+                                let visibility = model
+                                    .borrow()
+                                    .nodes()
+                                    .nodes()
+                                    .iter()
+                                    .map(|n| (n.uid(), n.is_visible()))
+                                    .collect::<HashMap<_, _>>();
+                                let edit = build_model_hierarchy(
+                                    ui,
+                                    &mut self.state,
+                                    index,
+                                    metadata.nodes(),
+                                    &visibility,
+                                );
+                                if let Some(edit) = edit {
+                                    edit.apply(&mut model.borrow_mut());
+                                }
+                            });
                         }
                     }
                 });
 
+            egui::Window::new("Lights")
+                .default_open(false)
+                .show(ctx, |ui| {
+                    build_lights_window(ui, &mut self.state, &self.models);
+                });
+
             egui::Window::new("Inspector")
                 .default_open(true)
                 .show(ctx, |ui| {
-                    if let Some(metadata) = self.model_metadata.as_ref() {
+                    if let Some(gui_model) = self.models.get(self.state.selected_model) {
+                        let metadata = &gui_model.metadata;
                         if metadata.node_count() > 0 {
-                            let model = &self.model.upgrade().expect("模型已被释放！");
-                            let model = model.borrow();
-                            let mesh_nodes = model.nodes().nodes();
-                            let mesh_meshes = model.meshes();
-                            build_inspector_window(ui, &mut self.state, mesh_nodes, mesh_meshes);
+                            let model = &gui_model.model.upgrade().expect("模型已被释放！");
+                            let (edit, material_edit) =
+                                build_inspector_window(ui, &mut self.state, &model.borrow());
+                            if let Some(edit) = edit {
+                                edit.apply(&mut model.borrow_mut());
+                            }
+                            if let Some(edit) = material_edit {
+                                edit.apply(&mut model.borrow_mut());
+                            }
                         }
                         if metadata.animation_count() > 0 {
                             if let Some(node) = &self.state.select_node {
@@ -96,7 +200,7 @@ impl Gui {
                                         build_animation_player_window(
                                             ui,
                                             &mut self.state,
-                                            self.model_metadata.as_ref(),
+                                            Some(metadata),
                                             self.animation_playback_state,
                                         );
                                     }
@@ -106,11 +210,106 @@ impl Gui {
                         }
                     }
                 });
+
+            let selected_node = self.get_selected_node_uid();
+            if let (Some(camera), Some(gui_model), Some(uid)) = (
+                self.camera,
+                self.models.get(self.state.selected_model),
+                selected_node,
+            ) {
+                let model = &gui_model.model.upgrade().expect("模型已被释放！");
+                let edit = {
+                    let model = model.borrow();
+                    model.node_by_uid(uid).and_then(|(_, real_node)| {
+                        build_transform_gizmo(ctx, &mut self.state, camera, real_node)
+                    })
+                };
+                if let Some(edit) = edit {
+                    edit.apply(&mut model.borrow_mut());
+                }
+            } else {
+                self.state.gizmo_drag_axis = None;
+            }
+
+            if self.state.show_performance {
+                egui::Window::new("性能")
+                    .anchor(egui::Align2::RIGHT_TOP, [-10.0, 10.0])
+                    .resizable(false)
+                    .collapsible(false)
+                    .show(ctx, |ui| {
+                        build_performance_window(ui, &self.performance);
+                    });
+            }
+
+            if self.state.show_validation_console {
+                let mut open = true;
+                egui::Window::new("验证层")
+                    .open(&mut open)
+                    .default_pos([10.0, 400.0])
+                    .default_size([600.0, 240.0])
+                    .show(ctx, |ui| {
+                        build_validation_console_window(
+                            ui,
+                            &mut self.state,
+                            &mut self.validation_messages,
+                        );
+                    });
+                self.state.show_validation_console = open;
+            }
+
+            if self.state.show_log_console {
+                let mut open = true;
+                egui::Window::new("日志")
+                    .open(&mut open)
+                    .default_pos([10.0, 660.0])
+                    .default_size([600.0, 240.0])
+                    .show(ctx, |ui| {
+                        build_log_console_window(ui, &mut self.state, &mut self.log_records);
+                    });
+                self.state.show_log_console = open;
+            }
+
+            if let Some(LoadStatus::Loading(path)) = self.load_status.as_ref() {
+                egui::Window::new("加载")
+                    .anchor(egui::Align2::LEFT_BOTTOM, [10.0, -10.0])
+                    .resizable(false)
+                    .collapsible(false)
+                    .show(ctx, |ui| {
+                        ui.horizontal(|ui| {
+                            ui.spinner();
+                            ui.label(format!("{}", path.display()));
+                        });
+                    });
+            }
+
+            self.load_errors
+                .retain(|(_, time)| time.elapsed() < ERROR_TOAST_DURATION);
+            if !self.load_errors.is_empty() {
+                let mut closed = None;
+                egui::Window::new("错误")
+                    .anchor(egui::Align2::RIGHT_BOTTOM, [-10.0, -10.0])
+                    .resizable(false)
+                    .collapsible(false)
+                    .show(ctx, |ui| {
+                        for (index, (message, _)) in self.load_errors.iter().enumerate() {
+                            ui.horizontal(|ui| {
+                                ui.colored_label(egui::Color32::RED, message);
+                                if ui.small_button("x").clicked() {
+                                    closed = Some(index);
+                                }
+                            });
+                        }
+                    });
+                if let Some(index) = closed {
+                    self.load_errors.remove(index);
+                }
+            }
         });
 
         self.state.check_renderer_settings_changed(&previous_state);
 
-        self.state.hovered = self.egui.is_pointer_over_area();
+        self.state.hovered =
+            self.egui.is_pointer_over_area() || self.state.gizmo_drag_axis.is_some();
 
         self.egui_winit
             .handle_platform_output(window, platform_output);
@@ -124,14 +323,36 @@ impl Gui {
         }
     }
 
-    pub fn set_model_metadata(&mut self, metadata: Metadata) {
-        self.model_metadata.replace(metadata);
+    //新加入的模型成为当前选中模型
+    pub fn add_model(&mut self, model: &Rc<RefCell<Model>>) {
+        self.models.push(GuiModel {
+            metadata: model.borrow().metadata().clone(),
+            model: Rc::downgrade(model),
+        });
         self.animation_playback_state = None;
         self.state = self.state.reset();
+        self.state.selected_model = self.models.len() - 1;
     }
 
-    pub fn set_model(&mut self, model: &Rc<RefCell<Model>>) {
-        self.model = Rc::downgrade(model);
+    pub fn clear_models(&mut self) {
+        self.models.clear();
+        self.animation_playback_state = None;
+        self.state = self.state.reset();
+    }
+
+    pub fn get_selected_model(&self) -> Option<usize> {
+        (self.state.selected_model < self.models.len()).then_some(self.state.selected_model)
+    }
+
+    pub fn set_load_status(&mut self, status: LoadStatus) {
+        match status {
+            LoadStatus::Loading(_) => self.load_status = Some(status),
+            LoadStatus::Done(_) => self.load_status = None,
+            LoadStatus::Error(message) => {
+                self.load_status = None;
+                self.load_errors.push((message, Instant::now()));
+            }
+        }
     }
 
     pub fn set_animation_playback_state(
@@ -141,6 +362,68 @@ impl Gui {
         self.animation_playback_state = animation_playback_state;
     }
 
+    pub fn is_performance_overlay_visible(&self) -> bool {
+        self.state.show_performance
+    }
+
+    //隐藏时不记录，frame_time和cpu_time单位为秒
+    pub fn set_frame_timings(&mut self, frame_time: f32, cpu_time: f32) {
+        if !self.state.show_performance {
+            self.performance.frame_times.clear();
+            return;
+        }
+        let frame_times = &mut self.performance.frame_times;
+        if frame_times.len() == FRAME_TIME_HISTORY {
+            frame_times.pop_front();
+        }
+        frame_times.push_back(frame_time * 1000.0);
+        self.performance.cpu_time = cpu_time * 1000.0;
+    }
+
+    //未开启验证层时无法捕获，设置面板中禁用控制台开关
+    pub fn set_validation_available(&mut self, available: bool) {
+        self.validation_available = available;
+        if !available {
+            self.state.show_validation_console = false;
+        }
+    }
+
+    pub fn is_validation_console_visible(&self) -> bool {
+        self.state.show_validation_console
+    }
+
+    pub fn add_validation_messages(&mut self, messages: Vec<ValidationMessage>) {
+        for message in messages {
+            if self.validation_messages.len() == VALIDATION_CAPTURE_CAPACITY {
+                self.validation_messages.pop_front();
+            }
+            self.validation_messages.push_back(message);
+        }
+    }
+
+    //面板关闭时也保留最近的日志，打开后能看到启动阶段的输出
+    pub fn add_log_records(&mut self, records: Vec<LogRecord>) {
+        for record in records {
+            if self.log_records.len() == LOG_CAPTURE_CAPACITY {
+                self.log_records.pop_front();
+            }
+            self.log_records.push_back(record);
+        }
+    }
+
+    pub fn set_gpu_timings(&mut self, timings: &[(GpuPass, f32)]) {
+        self.performance.gpu_timings.clear();
+        self.performance.gpu_timings.extend_from_slice(timings);
+    }
+
+    pub fn set_culling_stats(&mut self, stats: CullingStats) {
+        self.performance.culling = stats;
+    }
+
+    pub fn set_scene_stats(&mut self, stats: SceneStats) {
+        self.performance.scene = stats;
+    }
+
     pub fn set_camera(&mut self, camera: Option<Camera>) {
         self.camera = camera;
     }
@@ -149,6 +432,10 @@ impl Gui {
         self.state.selected_animation
     }
 
+    pub fn get_animation_fade_time(&self) -> f32 {
+        self.state.animation_fade_time
+    }
+
     pub fn is_infinite_animation_checked(&self) -> bool {
         self.state.infinite_animation
     }
@@ -165,6 +452,11 @@ impl Gui {
         self.state.reset_animation
     }
 
+    //本帧拖动时间轴后的动画时间
+    pub fn get_animation_scrub_time(&self) -> Option<f32> {
+        self.state.scrub_animation_time
+    }
+
     pub fn get_animation_speed(&self) -> f32 {
         self.state.animation_speed
     }
@@ -173,6 +465,112 @@ impl Gui {
         self.state.reset_camera
     }
 
+    pub fn should_frame_selected(&self) -> bool {
+        self.state.frame_selected
+    }
+
+    //本帧点击跳转的相机书签
+    pub fn get_camera_bookmark_jump(&self) -> Option<&CameraBookmark> {
+        self.state
+            .jump_camera_bookmark
+            .and_then(|index| self.camera_bookmarks.get(index))
+    }
+
+    pub fn set_camera_bookmarks(&mut self, bookmarks: Vec<CameraBookmark>) {
+        self.camera_bookmarks = bookmarks;
+    }
+
+    pub fn camera_bookmarks(&self) -> &[CameraBookmark] {
+        &self.camera_bookmarks
+    }
+
+    //层级面板中选中的节点，选中场景根时为None
+    pub fn get_selected_node_uid(&self) -> Option<NodeUid> {
+        self.state
+            .select_node
+            .as_ref()
+            .and_then(|n| match n.kind() {
+                NodeKind::Node(_) => Some(n.uid()),
+                _ => None,
+            })
+    }
+
+    //选中节点当前在模型中的序号，拾取ID等按序号索引的数据使用
+    pub fn get_selected_node(&self) -> Option<usize> {
+        let uid = self.get_selected_node_uid()?;
+        let model = self
+            .models
+            .get(self.state.selected_model)?
+            .model
+            .upgrade()?;
+        let index = model.borrow().node_by_uid(uid).map(|(index, _)| index);
+        index
+    }
+
+    //视口中拾取到的节点，点中背景时清空选择
+    pub fn set_picked_node(&mut self, picked: Option<(usize, usize)>) {
+        let node = picked.and_then(|(model_index, node_index)| {
+            let gui_model = self.models.get(model_index)?;
+            let node = find_node(gui_model.metadata.nodes(), node_index)?;
+            Some((model_index, node.clone()))
+        });
+        match node {
+            Some((model_index, node)) => select_node(&mut self.state, model_index, &node),
+            None => self.state.select_node = None,
+        }
+    }
+
+    pub fn get_camera_mode(&self) -> CameraMode {
+        CameraMode::from_value(self.state.selected_camera_mode).expect("未知相机模式!")
+    }
+
+    pub fn get_camera_move_speed(&self) -> f32 {
+        self.state.camera_move_speed
+    }
+
+    pub fn get_camera_projection(&self) -> Projection {
+        if self.state.orthographic {
+            Projection::Orthographic {
+                height: self.state.ortho_height,
+            }
+        } else {
+            Projection::Perspective { fovy: FOV }
+        }
+    }
+
+    pub fn get_camera_clip_planes(&self) -> ClipPlanes {
+        if self.state.auto_clip_planes {
+            ClipPlanes::Auto
+        } else {
+            ClipPlanes::Fixed {
+                near: self.state.z_near,
+                far: self.state.z_far,
+            }
+        }
+    }
+
+    pub fn set_input_bindings(&mut self, bindings: &InputBindings) {
+        self.input_bindings = Action::all()
+            .into_iter()
+            .map(|action| (action, bindings.get(action).join(", ")))
+            .collect();
+    }
+
+    pub fn set_camera_settings(
+        &mut self,
+        mode: CameraMode,
+        move_speed: f32,
+        clip_planes: ClipPlanes,
+    ) {
+        self.state.selected_camera_mode = mode as _;
+        self.state.camera_move_speed = move_speed;
+        self.state.auto_clip_planes = clip_planes == ClipPlanes::Auto;
+        if let ClipPlanes::Fixed { near, far } = clip_planes {
+            self.state.z_near = near;
+            self.state.z_far = far;
+        }
+    }
+
     pub fn get_new_renderer_settings(&self) -> Option<RendererSettings> {
         if self.state.renderer_settings_changed {
             Some(RendererSettings {
@@ -181,13 +579,25 @@ impl Gui {
                 ssao_kernel_size: SSAO_KERNEL_SIZES[self.state.ssao_kernel_size_index],
                 ssao_radius: self.state.ssao_radius,
                 ssao_strength: self.state.ssao_strength,
+                ssao_blur_enabled: self.state.ssao_blur_enabled,
+                ssao_blur_radius: self.state.ssao_blur_radius,
+                ssao_range_check_enabled: self.state.ssao_range_check_enabled,
                 tone_map_mode: ToneMapMode::from_value(self.state.selected_tone_map_mode)
                     .expect("未知tone map模式!"),
+                anti_aliasing_mode: AntiAliasingMode::from_value(
+                    self.state.selected_anti_aliasing_mode,
+                )
+                .expect("未知抗锯齿模式!"),
+                sharpness: self.state.sharpness,
                 fxaa_mode: FXAAMode::from_value(self.state.selected_fxaa_mode)
                     .expect("未知fxaa模式!"),
                 output_mode: OutputMode::from_value(self.state.selected_output_mode)
                     .expect("未知输出模式!"),
+                polygon_mode: PolygonMode::from_value(self.state.selected_polygon_mode)
+                    .expect("未知多边形模式!"),
                 bloom_strength: self.state.bloom_strength as f32 / 100f32,
+                bloom_threshold: self.state.bloom_threshold,
+                bloom_mip_levels: self.state.bloom_mip_levels,
                 absolute_luminance_threshold: self.state.absolute_luminance_threshold as f32
                     / 100f32,
                 relative_luminance_threshold: self.state.relative_luminance_threshold as f32
@@ -197,6 +607,49 @@ impl Gui {
                 fog_end: self.state.fog_end,
                 fog_start: self.state.fog_start,
                 fog_color: self.state.fog_color,
+                exposure: self.state.exposure,
+                white_point: self.state.white_point,
+                ambient_intensity: self.state.ambient_intensity,
+                shadow_enabled: self.state.shadow_enabled,
+                bloom_enabled: self.state.bloom_enabled,
+                debug_attachment: DebugAttachment::from_value(self.state.selected_debug_attachment)
+                    .expect("未知调试视图!"),
+                shadow_cascade_count: self.state.shadow_cascade_count,
+                shadow_split_lambda: self.state.shadow_split_lambda,
+                shadow_map_resolution: ShadowMapResolution::from_value(
+                    self.state.selected_shadow_map_resolution,
+                )
+                .expect("未知阴影分辨率!"),
+                shadow_filter_mode: ShadowFilterMode::from_value(
+                    self.state.selected_shadow_filter_mode,
+                )
+                .expect("未知阴影过滤模式!"),
+                shadow_pcf_kernel_size: SHADOW_PCF_KERNEL_SIZES
+                    [self.state.shadow_pcf_kernel_size_index],
+                shadow_depth_bias: self.state.shadow_depth_bias,
+                shadow_normal_bias: self.state.shadow_normal_bias,
+                shadow_casting_lights: self.state.shadow_casting_lights,
+                grid_enabled: self.state.grid_enabled,
+                axes_enabled: self.state.axes_enabled,
+                shadow_catcher_enabled: self.state.shadow_catcher_enabled,
+                shadow_catcher_size: self.state.shadow_catcher_size,
+                shadow_catcher_color: self.state.shadow_catcher_color,
+                gpu_skinning_enabled: self.state.gpu_skinning_enabled,
+                occlusion_culling_enabled: self.state.occlusion_culling_enabled,
+                environment_yaw: self.state.environment_yaw,
+                skybox_exposure: self.state.skybox_exposure,
+                skybox_blur: self.state.skybox_blur,
+                skybox_enabled: self.state.skybox_enabled,
+                clear_color: self.state.clear_color,
+                ibl_debug_roughness: self.state.ibl_debug_roughness,
+                light_cluster_grid: self.state.light_cluster_grid,
+                parallax_enabled: self.state.parallax_enabled,
+                parallax_steps: self.state.parallax_steps,
+                depth_prepass_enabled: self.state.depth_prepass_enabled,
+                render_scale: self.state.render_scale,
+                outline_enabled: self.state.outline_enabled,
+                outline_color: self.state.outline_color,
+                outline_thickness: self.state.outline_thickness,
             })
         } else {
             None
@@ -240,13 +693,74 @@ fn load_global_font(context: &Context) {
     context.set_fonts(fonts);
 }
 
-fn build_model_hierarchy(ui: &mut Ui, state: &mut State, nodes: &[Node]) {
+struct NodeVisibilityEdit {
+    uid: NodeUid,
+    visible: bool,
+}
+
+impl NodeVisibilityEdit {
+    fn apply(&self, model: &mut Model) {
+        if let Some((index, _)) = model.node_by_uid(self.uid) {
+            model.set_node_visible(index, self.visible);
+        }
+    }
+}
+
+fn build_model_hierarchy(
+    ui: &mut Ui,
+    state: &mut State,
+    model_index: usize,
+    nodes: &[Node],
+    visibility: &HashMap<NodeUid, bool>,
+) -> Option<NodeVisibilityEdit> {
+    let mut edit = None;
     for node in nodes {
-        build_model_hierarchy_tree(ui, state, node);
+        build_model_hierarchy_tree(ui, state, model_index, node, visibility, &mut edit);
     }
+    edit
 }
 
-fn build_model_hierarchy_tree(ui: &mut Ui, state: &mut State, node: &Node) {
+//节点名前的显示开关，隐藏父节点时子节点一起隐藏
+fn build_visibility_toggle(
+    ui: &mut Ui,
+    uid: NodeUid,
+    visibility: &HashMap<NodeUid, bool>,
+    edit: &mut Option<NodeVisibilityEdit>,
+) {
+    let mut visible = visibility.get(&uid).copied().unwrap_or(true);
+    let text = if visible { "👁" } else { "—" };
+    if ui
+        .toggle_value(&mut visible, text)
+        .on_hover_text("显示/隐藏")
+        .changed()
+    {
+        *edit = Some(NodeVisibilityEdit { uid, visible });
+    }
+}
+
+fn select_node(state: &mut State, model_index: usize, node: &Node) {
+    if state.selected_model != model_index {
+        state.selected_model = model_index;
+        state.selected_animation = 0;
+    }
+    state.select_node = Some(node.clone());
+}
+
+fn find_node(nodes: &[Node], index: usize) -> Option<&Node> {
+    nodes.iter().find_map(|node| match node.kind() {
+        NodeKind::Node(_) if node.index() == index => Some(node),
+        _ => find_node(node.children(), index),
+    })
+}
+
+fn build_model_hierarchy_tree(
+    ui: &mut Ui,
+    state: &mut State,
+    model_index: usize,
+    node: &Node,
+    visibility: &HashMap<NodeUid, bool>,
+    edit: &mut Option<NodeVisibilityEdit>,
+) {
     let name = match node.kind() {
         NodeKind::Scene => format!("Scene: {}", node.name().unwrap_or("Unknown")),
         NodeKind::Node(..) => format!("{}", node.name().unwrap_or("Unknown")),
@@ -255,36 +769,46 @@ fn build_model_hierarchy_tree(ui: &mut Ui, state: &mut State, node: &Node) {
     match node.kind() {
         NodeKind::Scene => {
             if egui::CollapsingHeader::new(name)
+                .id_source((model_index, node.uid()))
                 .default_open(false)
                 .show(ui, |ui| {
                     for child in node.children() {
-                        build_model_hierarchy_tree(ui, state, child);
+                        build_model_hierarchy_tree(ui, state, model_index, child, visibility, edit);
                     }
                 })
                 .header_response
                 .clicked()
             {
-                state.select_node = Some(node.clone());
+                select_node(state, model_index, node);
             }
         }
         NodeKind::Node(node_data) => {
             if node_data.leaf {
-                if ui.add(Label::new(name).sense(Sense::click())).clicked() {
-                    state.select_node = Some(node.clone());
-                }
+                ui.horizontal(|ui| {
+                    build_visibility_toggle(ui, node.uid(), visibility, edit);
+                    if ui.add(Label::new(name).sense(Sense::click())).clicked() {
+                        select_node(state, model_index, node);
+                    }
+                });
             } else {
-                if egui::CollapsingHeader::new(name)
-                    .default_open(false)
-                    .show(ui, |ui| {
-                        for child in node.children() {
-                            build_model_hierarchy_tree(ui, state, child);
-                        }
-                    })
-                    .header_response
-                    .clicked()
-                {
-                    state.select_node = Some(node.clone());
-                }
+                //CollapsingHeader的标题里放不下其它控件，改用CollapsingState自定义标题
+                let id = ui.make_persistent_id((model_index, node.uid()));
+                egui::collapsing_header::CollapsingState::load_with_default_open(
+                    ui.ctx(),
+                    id,
+                    false,
+                )
+                .show_header(ui, |ui| {
+                    build_visibility_toggle(ui, node.uid(), visibility, edit);
+                    if ui.add(Label::new(name).sense(Sense::click())).clicked() {
+                        select_node(state, model_index, node);
+                    }
+                })
+                .body(|ui| {
+                    for child in node.children() {
+                        build_model_hierarchy_tree(ui, state, model_index, child, visibility, edit);
+                    }
+                });
             }
         }
         _ => {}
@@ -306,7 +830,12 @@ fn build_animation_player_window(
                     .iter()
                     .map(|a| {
                         let name = a.name.as_ref().map_or("Unknown", |n| n);
-                        format!("{}: {}", a.index, name)
+                        let morph = if a.has_morph_weights() {
+                            " [Morph]"
+                        } else {
+                            ""
+                        };
+                        format!("{}: {}{}", a.index, name, morph)
                     })
                     .collect::<Vec<_>>();
 
@@ -319,6 +848,21 @@ fn build_animation_player_window(
                     );
                     ui.checkbox(&mut state.infinite_animation, "Loop");
                 });
+                if let Some(animation) = metadata.animations().get(state.selected_animation) {
+                    let kinds = animation
+                        .channel_kinds
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect::<Vec<_>>();
+                    ui.label(format!("Channels: {}", kinds.join(", ")));
+                }
+
+                ui.horizontal(|ui| {
+                    ui.label("Fade");
+                    ui.add(
+                        egui::Slider::new(&mut state.animation_fade_time, 0.0..=2.0).suffix("s"),
+                    );
+                });
             }
 
             if let Some(playback_state) = animation_playback_state {
@@ -341,33 +885,149 @@ fn build_animation_player_window(
                     ui.add(egui::Slider::new(&mut state.animation_speed, 0.05..=10.0));
                 });
 
-                let progress = playback_state.time / playback_state.total_time;
-                egui::ProgressBar::new(progress).ui(ui);
+                //拖动时间轴时暂停播放，由外部把时间写回动画
+                let mut time = playback_state.time;
+                ui.horizontal(|ui| {
+                    let response = ui.add(
+                        egui::Slider::new(&mut time, 0.0..=playback_state.total_time)
+                            .show_value(false),
+                    );
+                    if response.changed() {
+                        state.scrub_animation_time = Some(time);
+                    }
+                    ui.label(format!(
+                        "{:.2}s / {:.2}s",
+                        playback_state.time, playback_state.total_time
+                    ));
+                });
             }
         });
 }
 
-fn build_camera_details_window(ui: &mut Ui, state: &mut State, camera: Option<Camera>) {
+fn build_camera_details_window(
+    ui: &mut Ui,
+    state: &mut State,
+    camera: Option<Camera>,
+    bookmarks: &mut Vec<CameraBookmark>,
+) {
     egui::CollapsingHeader::new("Camera")
         .default_open(false)
         .show(ui, |ui| {
+            let camera_modes = CameraMode::all();
+            egui::ComboBox::from_label("相机模式").show_index(
+                ui,
+                &mut state.selected_camera_mode,
+                camera_modes.len(),
+                |i| format!("{:?}", camera_modes[i]),
+            );
+            if state.selected_camera_mode != CameraMode::Orbit as usize {
+                ui.add(
+                    egui::Slider::new(&mut state.camera_move_speed, 0.1..=50.0).text("移动速度"),
+                );
+            }
+            ui.checkbox(&mut state.orthographic, "正交投影");
+            if state.orthographic {
+                ui.add(
+                    egui::Slider::new(&mut state.ortho_height, 0.1..=100.0)
+                        .logarithmic(true)
+                        .text("视口高度"),
+                );
+            }
+            ui.checkbox(&mut state.auto_clip_planes, "自动近远平面");
+            if !state.auto_clip_planes {
+                ui.horizontal(|ui| {
+                    ui.label("近平面");
+                    ui.add(
+                        egui::DragValue::new(&mut state.z_near)
+                            .speed(0.001)
+                            .clamp_range(MIN_Z_NEAR..=MAX_Z_FAR * 0.5),
+                    );
+                    ui.label("远平面");
+                    //远平面始终大于近平面
+                    ui.add(
+                        egui::DragValue::new(&mut state.z_far)
+                            .speed(1.0)
+                            .clamp_range(state.z_near * 1.001..=MAX_Z_FAR),
+                    );
+                });
+            }
+
             if let Some(camera) = camera {
+                let (near, far) = camera.near_far();
+                ui.label(format!("Near: {:.4} Far: {:.2}", near, far));
                 let p = camera.position();
                 let t = camera.target();
                 ui.label(format!("Position: {:.3}, {:.3}, {:.3}", p.x, p.y, p.z));
                 ui.label(format!("Target: {:.3}, {:.3}, {:.3}", t.x, t.y, t.z));
-                state.reset_camera = ui.button("Reset").clicked();
+                ui.horizontal(|ui| {
+                    state.reset_camera = ui.button("Reset").clicked();
+                    state.frame_selected = ui.button("Frame").clicked();
+                });
+
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ui.add(
+                        egui::TextEdit::singleline(&mut state.camera_bookmark_name)
+                            .hint_text("书签名")
+                            .desired_width(120.0),
+                    );
+                    let name = state.camera_bookmark_name.trim();
+                    if ui
+                        .add_enabled(!name.is_empty(), egui::Button::new("保存书签"))
+                        .clicked()
+                    {
+                        let bookmark = CameraBookmark::new(name.to_string(), &camera);
+                        //同名书签直接覆盖
+                        match bookmarks.iter_mut().find(|b| b.name() == bookmark.name()) {
+                            Some(existing) => *existing = bookmark,
+                            None => bookmarks.push(bookmark),
+                        }
+                        state.camera_bookmark_name.clear();
+                    }
+                });
+                let mut removed = None;
+                for (index, bookmark) in bookmarks.iter().enumerate() {
+                    ui.horizontal(|ui| {
+                        if ui.button(bookmark.name()).clicked() {
+                            state.jump_camera_bookmark = Some(index);
+                        }
+                        if ui.small_button("删除").clicked() {
+                            removed = Some(index);
+                        }
+                    });
+                }
+                if let Some(index) = removed {
+                    bookmarks.remove(index);
+                    state.jump_camera_bookmark = None;
+                }
             }
         });
 }
 
+fn build_input_bindings_window(ui: &mut Ui, bindings: &[(Action, String)]) {
+    egui::CollapsingHeader::new("按键")
+        .default_open(false)
+        .show(ui, |ui| {
+            egui::Grid::new("input_bindings")
+                .striped(true)
+                .show(ui, |ui| {
+                    for (action, keys) in bindings {
+                        ui.label(action.to_string());
+                        ui.label(if keys.is_empty() { "-" } else { keys.as_str() });
+                        ui.end_row();
+                    }
+                });
+        });
+}
+
 fn build_inspector_window(
     ui: &mut Ui,
     state: &mut State,
-    model_nodes: &[rendering::node::Node],
-    model_meshes: &[gltf_loader::mesh::Mesh],
-) {
-    if let Some(node) = &state.select_node {
+    model: &Model,
+) -> (Option<NodeTransformEdit>, Option<MaterialEdit>) {
+    let mut edit = None;
+    let mut material_edit = None;
+    if let Some(node) = state.select_node.clone() {
         ui.label(format!(
             "ID: {} Name: {}",
             node.uid(),
@@ -393,26 +1053,44 @@ fn build_inspector_window(
             }
         };
         ui.label(format!("Type: {}", type_name));
+        if let Some(extras) = node.extras() {
+            build_extras_tree(ui, "Extras", extras);
+        }
 
-        let real_node = &model_nodes[node.index()];
-        let local_transform = real_node.local_transform().clone();
-        let (position, rotation, scale) = local_transform.decomposed();
-        ui.label(format!(
-            "Position: {:.3}, {:.3}, {:.3}",
-            position[0], position[1], position[2]
-        ));
-        ui.label(format!(
-            "Rotation: {:.3}, {:.3}, {:.3}, {:.3}",
-            rotation[0], rotation[1], rotation[2], rotation[3]
-        ));
-        ui.label(format!(
-            "Scale: {:.3}, {:.3}, {:.3}",
-            scale[0], scale[1], scale[2]
-        ));
+        //场景根没有对应的渲染节点
+        let Some((_, real_node)) = model.node_by_uid(node.uid()) else {
+            return (edit, material_edit);
+        };
+        if let NodeKind::Node(_) = node.kind() {
+            let gizmo_modes = GizmoMode::all();
+            egui::ComboBox::from_label("Gizmo").show_index(
+                ui,
+                &mut state.selected_gizmo_mode,
+                gizmo_modes.len(),
+                |i| format!("{:?}", gizmo_modes[i]),
+            );
+            edit = build_node_transform_editor(ui, state, real_node);
+        } else {
+            let local_transform = real_node.local_transform().clone();
+            let (position, rotation, scale) = local_transform.decomposed();
+            ui.label(format!(
+                "Position: {:.3}, {:.3}, {:.3}",
+                position[0], position[1], position[2]
+            ));
+            ui.label(format!(
+                "Rotation: {:.3}, {:.3}, {:.3}, {:.3}",
+                rotation[0], rotation[1], rotation[2], rotation[3]
+            ));
+            ui.label(format!(
+                "Scale: {:.3}, {:.3}, {:.3}",
+                scale[0], scale[1], scale[2]
+            ));
+        }
 
         if is_mesh {
-            let mesh = &model_meshes[real_node.mesh_index().unwrap()];
-            for primitive in mesh.primitives().iter() {
+            let mesh_index = real_node.mesh_index().unwrap();
+            let mesh = model.mesh(mesh_index);
+            for (primitive_index, primitive) in mesh.primitives().iter().enumerate() {
                 let material = primitive.material();
                 ui.separator();
                 ui.label(format!(
@@ -426,30 +1104,538 @@ fn build_inspector_window(
                         }
                     }
                 ));
+                let mut material_override = material.get_override();
+                let mut override_changed = false;
+                ui.horizontal(|ui| {
+                    ui.label("Color");
+                    override_changed |= ui
+                        .color_edit_button_rgba_unmultiplied(&mut material_override.color)
+                        .changed();
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Emissive");
+                    for value in material_override.emissive.iter_mut() {
+                        override_changed |= ui
+                            .add(
+                                egui::DragValue::new(value)
+                                    .speed(0.01)
+                                    .clamp_range(0.0..=f32::MAX),
+                            )
+                            .changed();
+                    }
+                });
+                if let rendering::material::PBRWorkflow::MetallicRoughness(_) =
+                    material.get_workflow()
+                {
+                    override_changed |= ui
+                        .add(
+                            egui::Slider::new(&mut material_override.metallic, 0.0..=1.0)
+                                .text("Metallic"),
+                        )
+                        .changed();
+                    override_changed |= ui
+                        .add(
+                            egui::Slider::new(&mut material_override.roughness, 0.0..=1.0)
+                                .text("Roughness"),
+                        )
+                        .changed();
+                }
+                if override_changed {
+                    material_edit = Some(MaterialEdit {
+                        mesh_index,
+                        primitive_index,
+                        kind: MaterialEditKind::Override(material_override),
+                    });
+                }
+                if primitive.is_material_overridden() && ui.button("恢复资源值").clicked() {
+                    material_edit = Some(MaterialEdit {
+                        mesh_index,
+                        primitive_index,
+                        kind: MaterialEditKind::Reset,
+                    });
+                }
                 ui.label(format!(
-                    "Color: {:.3}, {:.3}, {:.3}, {:.3}",
-                    material.get_color()[0],
-                    material.get_color()[1],
-                    material.get_color()[2],
-                    material.get_color()[3]
-                ));
-                ui.label(format!(
-                    "Emissive: {:.3}, {:.3}, {:.3}",
-                    material.get_emissive()[0],
-                    material.get_emissive()[1],
-                    material.get_emissive()[2]
+                    "Occlusion Strength: {:.3}",
+                    material.get_occlusion()
                 ));
-                ui.label(format!("Occlusion: {:.3}", material.get_occlusion()));
+                if material.get_normals_texture().is_some() {
+                    ui.label(format!("Normal Scale: {:.3}", material.get_normal_scale()));
+                }
                 ui.label(format!("Alpha Mode: {}", material.get_alpha_mode()));
                 ui.label(format!("Alpha Cutoff: {:.3}", material.get_alpha_cutoff()));
                 ui.label(format!("Double Sided: {}", material.is_double_sided()));
                 ui.label(format!("Is Unlit: {}", material.is_unlit()));
+                ui.label(format!("Vertex Colors: {}", material.use_vertex_colors()));
+                if let Some(extras) = material_extras(&node, primitive_index) {
+                    let label = format!("Material {} Extras", primitive_index);
+                    build_extras_tree(ui, &label, extras);
+                }
+                if material.get_height_texture().is_some() {
+                    ui.label(format!("Height Scale: {:.3}", material.get_height_scale()));
+                    let mut enabled = material.is_parallax_enabled();
+                    if ui.checkbox(&mut enabled, "视差遮蔽映射").changed() {
+                        material_edit = Some(MaterialEdit {
+                            mesh_index,
+                            primitive_index,
+                            kind: MaterialEditKind::Parallax(enabled),
+                        });
+                    }
+                }
             }
         }
     }
+    (edit, material_edit)
+}
+
+fn material_extras(node: &Node, primitive_index: usize) -> Option<&serde_json::Value> {
+    match node.kind() {
+        NodeKind::Node(node_data) => node_data
+            .mesh
+            .as_ref()?
+            .primitives
+            .get(primitive_index)?
+            .material
+            .extras
+            .as_ref(),
+        _ => None,
+    }
+}
+
+//只读的JSON树，对象和数组可以折叠
+fn build_extras_tree(ui: &mut Ui, label: &str, value: &serde_json::Value) {
+    use serde_json::Value;
+    match value {
+        Value::Object(map) => {
+            egui::CollapsingHeader::new(label)
+                .default_open(true)
+                .show(ui, |ui| {
+                    for (key, value) in map {
+                        build_extras_tree(ui, key, value);
+                    }
+                });
+        }
+        Value::Array(values) => {
+            egui::CollapsingHeader::new(format!("{} [{}]", label, values.len())).show(ui, |ui| {
+                for (index, value) in values.iter().enumerate() {
+                    build_extras_tree(ui, &index.to_string(), value);
+                }
+            });
+        }
+        value => {
+            ui.label(format!("{}: {}", label, value));
+        }
+    }
+}
+
+struct MaterialEdit {
+    mesh_index: usize,
+    primitive_index: usize,
+    kind: MaterialEditKind,
+}
+
+enum MaterialEditKind {
+    Parallax(bool),
+    Override(MaterialOverride),
+    //恢复为资源中的参数
+    Reset,
+}
+
+impl MaterialEdit {
+    fn apply(&self, model: &mut Model) {
+        match self.kind {
+            MaterialEditKind::Parallax(enabled) => {
+                model.set_parallax_enabled(self.mesh_index, self.primitive_index, enabled)
+            }
+            MaterialEditKind::Override(material_override) => model.set_material_override(
+                self.mesh_index,
+                self.primitive_index,
+                material_override,
+            ),
+            MaterialEditKind::Reset => {
+                model.reset_material_override(self.mesh_index, self.primitive_index)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GizmoMode {
+    Translate = 0,
+    Rotate,
+    Scale,
+}
+
+impl GizmoMode {
+    fn all() -> [GizmoMode; 3] {
+        use GizmoMode::*;
+        [Translate, Rotate, Scale]
+    }
+
+    fn from_value(value: usize) -> Option<Self> {
+        use GizmoMode::*;
+        match value {
+            0 => Some(Translate),
+            1 => Some(Rotate),
+            2 => Some(Scale),
+            _ => None,
+        }
+    }
+}
+
+struct NodeTransformEdit {
+    uid: NodeUid,
+    translation: Vector3<f32>,
+    rotation: Quaternion<f32>,
+    scale: Vector3<f32>,
+}
+
+impl NodeTransformEdit {
+    fn from_node(node: &rendering::node::Node) -> Self {
+        let (translation, rotation, scale) = node.local_transform().clone().decomposed();
+        Self {
+            uid: node.uid(),
+            translation: translation.into(),
+            rotation: Quaternion::new(rotation[3], rotation[0], rotation[1], rotation[2]),
+            scale: scale.into(),
+        }
+    }
+
+    fn local_matrix(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.translation)
+            * Matrix4::from(self.rotation)
+            * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+
+    fn apply(&self, model: &mut Model) {
+        if let Some((index, _)) = model.node_by_uid(self.uid) {
+            model.set_node_local_transform(index, self.translation, self.rotation, self.scale);
+        }
+    }
+}
+
+fn quaternion_to_array(q: Quaternion<f32>) -> [f32; 4] {
+    [q.v.x, q.v.y, q.v.z, q.s]
+}
+
+fn build_node_transform_editor(
+    ui: &mut Ui,
+    state: &mut State,
+    node: &rendering::node::Node,
+) -> Option<NodeTransformEdit> {
+    let mut edit = NodeTransformEdit::from_node(node);
+
+    //欧拉角缓存在界面状态中，只有四元数被外部修改时才重新计算，避免来回转换产生跳变
+    let rotation = quaternion_to_array(edit.rotation);
+    if state.node_euler_source != Some((edit.uid, rotation)) {
+        let euler = Euler::from(edit.rotation);
+        state.node_euler = [
+            Deg::from(euler.x).0,
+            Deg::from(euler.y).0,
+            Deg::from(euler.z).0,
+        ];
+        state.node_euler_source = Some((edit.uid, rotation));
+    }
+
+    let mut changed = false;
+    ui.horizontal(|ui| {
+        ui.label("Position");
+        for value in [
+            &mut edit.translation.x,
+            &mut edit.translation.y,
+            &mut edit.translation.z,
+        ] {
+            changed |= ui.add(egui::DragValue::new(value).speed(0.01)).changed();
+        }
+    });
+    let mut rotation_changed = false;
+    ui.horizontal(|ui| {
+        ui.label("Rotation");
+        for value in state.node_euler.iter_mut() {
+            rotation_changed |= ui
+                .add(egui::DragValue::new(value).speed(0.5).suffix("°"))
+                .changed();
+        }
+    });
+    ui.horizontal(|ui| {
+        ui.label("Scale");
+        for value in [&mut edit.scale.x, &mut edit.scale.y, &mut edit.scale.z] {
+            changed |= ui
+                .add(
+                    egui::DragValue::new(value)
+                        .speed(0.01)
+                        .clamp_range(0.001..=f32::MAX),
+                )
+                .changed();
+        }
+    });
+
+    if rotation_changed {
+        let [x, y, z] = state.node_euler;
+        edit.rotation = Quaternion::from(Euler::new(Deg(x), Deg(y), Deg(z)));
+        state.node_euler_source = Some((edit.uid, quaternion_to_array(edit.rotation)));
+    }
+
+    (changed || rotation_changed).then_some(edit)
+}
+
+const GIZMO_SCREEN_FACTOR: f32 = 0.15;
+const GIZMO_PICK_DISTANCE: f32 = 8.0;
+const GIZMO_ROTATE_SPEED: f32 = 0.01;
+
+//在选中节点上绘制屏幕空间变换Gizmo，拖拽坐标轴修改节点的局部变换
+fn build_transform_gizmo(
+    ctx: &Context,
+    state: &mut State,
+    camera: Camera,
+    node: &rendering::node::Node,
+) -> Option<NodeTransformEdit> {
+    let screen = ctx.screen_rect();
+    if screen.width() <= 0.0 || screen.height() <= 0.0 {
+        return None;
+    }
+    let view_proj =
+        camera.projection_matrix(screen.width() / screen.height()) * camera.view_matrix();
+    let project = |p: Point3<f32>| -> Option<egui::Pos2> {
+        let clip = view_proj * p.to_homogeneous();
+        if clip.w <= f32::EPSILON {
+            return None;
+        }
+        let ndc = clip.truncate() / clip.w;
+        Some(egui::pos2(
+            screen.min.x + (ndc.x * 0.5 + 0.5) * screen.width(),
+            screen.min.y + (ndc.y * 0.5 + 0.5) * screen.height(),
+        ))
+    };
+
+    let global = node.transform();
+    let origin = Point3::from_vec(global.w.truncate());
+    let origin_screen = project(origin)?;
+    let length = (camera.position() - origin).magnitude() * GIZMO_SCREEN_FACTOR;
+    let unit_axes = [Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z()];
+    let axes = [global.x, global.y, global.z].map(|c| c.truncate());
+    let axes = [0, 1, 2].map(|i| {
+        if axes[i].magnitude2() > f32::EPSILON {
+            axes[i].normalize()
+        } else {
+            unit_axes[i]
+        }
+    });
+    let colors = [
+        egui::Color32::from_rgb(230, 60, 60),
+        egui::Color32::from_rgb(60, 200, 60),
+        egui::Color32::from_rgb(60, 100, 230),
+    ];
+    let ends = axes.map(|axis| project(origin + axis * length));
+
+    let mode = GizmoMode::from_value(state.selected_gizmo_mode).expect("未知Gizmo模式!");
+    let (hover_pos, pressed, down, delta) = ctx.input(|i| {
+        (
+            i.pointer.hover_pos(),
+            i.pointer.primary_pressed(),
+            i.pointer.primary_down(),
+            i.pointer.delta(),
+        )
+    });
+    let over_gui = ctx.is_pointer_over_area();
+
+    if !down {
+        state.gizmo_drag_axis = None;
+    }
+    if pressed && !over_gui {
+        if let Some(pos) = hover_pos {
+            state.gizmo_drag_axis = (0..3).find(|&i| {
+                ends[i].is_some_and(|end| {
+                    distance_to_segment(pos, origin_screen, end) < GIZMO_PICK_DISTANCE
+                })
+            });
+        }
+    }
+
+    let painter = ctx.layer_painter(egui::LayerId::new(
+        egui::Order::Foreground,
+        egui::Id::new("transform_gizmo"),
+    ));
+    for i in 0..3 {
+        if let Some(end) = ends[i] {
+            let width = if state.gizmo_drag_axis == Some(i) {
+                4.0
+            } else {
+                2.0
+            };
+            let stroke = egui::Stroke::new(width, colors[i]);
+            painter.line_segment([origin_screen, end], stroke);
+            match mode {
+                GizmoMode::Translate => painter.circle_filled(end, 5.0, colors[i]),
+                GizmoMode::Rotate => painter.circle_stroke(end, 6.0, stroke),
+                GizmoMode::Scale => painter.rect_filled(
+                    egui::Rect::from_center_size(end, egui::vec2(9.0, 9.0)),
+                    0.0,
+                    colors[i],
+                ),
+            }
+        }
+    }
+
+    let axis_index = state.gizmo_drag_axis?;
+    let end = ends[axis_index]?;
+    let axis_screen = end - origin_screen;
+    let axis_screen_length = axis_screen.length();
+    if axis_screen_length < 1.0 || delta == egui::Vec2::ZERO {
+        return None;
+    }
+    let along = delta.dot(axis_screen) / axis_screen_length;
+    let across = (axis_screen.x * delta.y - axis_screen.y * delta.x) / axis_screen_length;
+    let factor = along / axis_screen_length;
+
+    let mut edit = NodeTransformEdit::from_node(node);
+    match mode {
+        GizmoMode::Translate => {
+            //世界空间位移转换到父节点空间
+            let parent = global * edit.local_matrix().invert()?;
+            let world_delta = axes[axis_index] * length * factor;
+            edit.translation += parent.invert()?.transform_vector(world_delta);
+        }
+        GizmoMode::Rotate => {
+            let rotation = Quaternion::from_axis_angle(
+                unit_axes[axis_index],
+                Rad(across * GIZMO_ROTATE_SPEED),
+            );
+            edit.rotation = (edit.rotation * rotation).normalize();
+        }
+        GizmoMode::Scale => {
+            edit.scale[axis_index] = (edit.scale[axis_index] * (1.0 + factor)).max(0.001);
+        }
+    }
+    Some(edit)
+}
+
+fn distance_to_segment(p: egui::Pos2, a: egui::Pos2, b: egui::Pos2) -> f32 {
+    let ab = b - a;
+    let t = ((p - a).dot(ab) / ab.length_sq().max(f32::EPSILON)).clamp(0.0, 1.0);
+    (p - (a + ab * t)).length()
+}
+
+fn build_lights_window(ui: &mut Ui, state: &mut State, models: &[GuiModel]) {
+    ui.add(egui::Slider::new(&mut state.ambient_intensity, 0.0..=5.0).text("环境光强度"));
+    ui.add(egui::Slider::new(&mut state.environment_yaw, -180.0..=180.0).text("环境旋转"));
+    ui.add(egui::Slider::new(&mut state.skybox_exposure, 0.0..=4.0).text("天空盒曝光"));
+    ui.add(egui::Slider::new(&mut state.skybox_blur, 0.0..=1.0).text("天空盒模糊"));
+
+    for (model_index, gui_model) in models.iter().enumerate() {
+        let model = &gui_model.model.upgrade().expect("模型已被释放！");
+        let mut lights = model.borrow().lights().to_vec();
+        if lights.is_empty() {
+            continue;
+        }
+
+        ui.separator();
+        ui.label(format!(
+            "Model {}: {}",
+            model_index,
+            gui_model.metadata.name()
+        ));
+        let mut changed = false;
+        for (index, light) in lights.iter_mut().enumerate() {
+            let type_name = match light.light_type() {
+                LightType::DirectionalLight => "Directional",
+                LightType::PointLight => "Point",
+                LightType::SpotLight { .. } => "Spot",
+            };
+            egui::CollapsingHeader::new(format!("Light {}: {}", index, type_name))
+                .id_source(("light", model_index, index))
+                .default_open(false)
+                .show(ui, |ui| {
+                    let mut use_temperature = light.temperature().is_some();
+                    if ui.checkbox(&mut use_temperature, "色温").changed() {
+                        if use_temperature {
+                            light.set_temperature(DEFAULT_LIGHT_TEMPERATURE);
+                        } else {
+                            light.set_color(light.color());
+                        }
+                        changed = true;
+                    }
+                    if let Some(mut kelvin) = light.temperature() {
+                        if ui
+                            .add(
+                                egui::Slider::new(
+                                    &mut kelvin,
+                                    MIN_COLOR_TEMPERATURE..=MAX_COLOR_TEMPERATURE,
+                                )
+                                .suffix("K"),
+                            )
+                            .changed()
+                        {
+                            light.set_temperature(kelvin);
+                            changed = true;
+                        }
+                    } else {
+                        let mut color = light.color();
+                        ui.horizontal(|ui| {
+                            ui.label("Color");
+                            if ui.color_edit_button_rgb(&mut color).changed() {
+                                light.set_color(color);
+                                changed = true;
+                            }
+                        });
+                    }
+
+                    let mut intensity = light.intensity();
+                    if ui
+                        .add(
+                            egui::DragValue::new(&mut intensity)
+                                .speed(0.1)
+                                .clamp_range(0.0..=f32::MAX)
+                                .prefix("Intensity: "),
+                        )
+                        .changed()
+                    {
+                        light.set_intensity(intensity);
+                        changed = true;
+                    }
+
+                    if let Some(mut range) = light.range() {
+                        if ui
+                            .add(
+                                egui::DragValue::new(&mut range)
+                                    .speed(0.1)
+                                    .clamp_range(0.0..=f32::MAX)
+                                    .prefix("Range: "),
+                            )
+                            .changed()
+                        {
+                            light.set_range(Some(range));
+                            changed = true;
+                        }
+                    }
+
+                    if let LightType::SpotLight {
+                        inner_cone_angle,
+                        outer_cone_angle,
+                    } = light.light_type()
+                    {
+                        let mut inner = inner_cone_angle.to_degrees();
+                        let mut outer = outer_cone_angle.to_degrees();
+                        let inner_changed = ui
+                            .add(egui::Slider::new(&mut inner, 0.0..=90.0).text("内锥角"))
+                            .changed();
+                        let outer_changed = ui
+                            .add(egui::Slider::new(&mut outer, 0.0..=90.0).text("外锥角"))
+                            .changed();
+                        if inner_changed || outer_changed {
+                            light.set_cone_angles(inner.to_radians(), outer.to_radians());
+                            changed = true;
+                        }
+                    }
+                });
+        }
+
+        if changed {
+            model.borrow_mut().lights_mut().copy_from_slice(&lights);
+        }
+    }
 }
 
-fn build_renderer_settings_window(ui: &mut Ui, state: &mut State) {
+fn build_renderer_settings_window(ui: &mut Ui, state: &mut State, validation_available: bool) {
     egui::CollapsingHeader::new("渲染设置")
         .default_open(true)
         .show(ui, |ui| {
@@ -485,6 +1671,130 @@ fn build_renderer_settings_window(ui: &mut Ui, state: &mut State) {
 
                 ui.color_edit_button_rgba_unmultiplied(&mut state.fog_color);
 
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut state.shadow_enabled, "阴影");
+                    ui.checkbox(&mut state.bloom_enabled, "Bloom");
+                });
+                if state.bloom_enabled {
+                    ui.add(
+                        egui::Slider::new(&mut state.bloom_threshold, 0.0..=10.0).text("Bloom阈值"),
+                    );
+                    ui.add(
+                        egui::Slider::new(&mut state.bloom_mip_levels, 1..=BLOOM_MIP_LEVELS)
+                            .text("Bloom层级"),
+                    );
+                }
+                if state.shadow_enabled {
+                    ui.add(
+                        egui::Slider::new(&mut state.shadow_cascade_count, 1..=MAX_SHADOW_CASCADES)
+                            .text("阴影级联数"),
+                    );
+                    ui.add_enabled(
+                        state.shadow_cascade_count > 1,
+                        egui::Slider::new(&mut state.shadow_split_lambda, 0.0..=1.0)
+                            .text("级联划分系数"),
+                    );
+
+                    let resolutions = ShadowMapResolution::all();
+                    egui::ComboBox::from_label("阴影分辨率").show_index(
+                        ui,
+                        &mut state.selected_shadow_map_resolution,
+                        resolutions.len(),
+                        |i| format!("{}", resolutions[i].size()),
+                    );
+                    let filter_modes = ShadowFilterMode::all();
+                    egui::ComboBox::from_label("阴影过滤").show_index(
+                        ui,
+                        &mut state.selected_shadow_filter_mode,
+                        filter_modes.len(),
+                        |i| format!("{:?}", filter_modes[i]),
+                    );
+                    if state.selected_shadow_filter_mode == ShadowFilterMode::PCF as usize {
+                        egui::ComboBox::from_label("PCF Kernel").show_index(
+                            ui,
+                            &mut state.shadow_pcf_kernel_size_index,
+                            SHADOW_PCF_KERNEL_SIZES.len(),
+                            |i| format!("{0}x{0}", SHADOW_PCF_KERNEL_SIZES[i]),
+                        );
+                    }
+                    ui.add(
+                        egui::Slider::new(&mut state.shadow_depth_bias, 0.0..=0.01)
+                            .logarithmic(true)
+                            .text("深度偏移"),
+                    );
+                    ui.add(
+                        egui::Slider::new(&mut state.shadow_normal_bias, 0.0..=5.0)
+                            .text("法线偏移"),
+                    );
+                    ui.add(
+                        egui::Slider::new(
+                            &mut state.shadow_casting_lights,
+                            0..=MAX_SHADOW_CASTING_LIGHTS,
+                        )
+                        .text("点光源/聚光灯阴影数"),
+                    );
+                }
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut state.grid_enabled, "网格");
+                    ui.checkbox(&mut state.axes_enabled, "坐标轴");
+                });
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut state.outline_enabled, "选中描边");
+                    if state.outline_enabled {
+                        ui.color_edit_button_rgb(&mut state.outline_color);
+                    }
+                });
+                if state.outline_enabled {
+                    ui.add(
+                        egui::Slider::new(&mut state.outline_thickness, 1..=MAX_OUTLINE_THICKNESS)
+                            .text("描边宽度"),
+                    );
+                }
+                ui.checkbox(&mut state.shadow_catcher_enabled, "阴影接收地面");
+                if state.shadow_catcher_enabled {
+                    ui.add(
+                        egui::Slider::new(
+                            &mut state.shadow_catcher_size,
+                            0.0..=MAX_SHADOW_CATCHER_SIZE,
+                        )
+                        .logarithmic(true)
+                        .text("地面大小(0为无限)"),
+                    );
+                    ui.horizontal(|ui| {
+                        ui.label("阴影颜色");
+                        ui.color_edit_button_rgba_unmultiplied(&mut state.shadow_catcher_color);
+                    });
+                }
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut state.skybox_enabled, "天空盒");
+                    ui.label("背景色");
+                    ui.color_edit_button_rgba_unmultiplied(&mut state.clear_color);
+                });
+                ui.checkbox(&mut state.gpu_skinning_enabled, "计算着色器蒙皮");
+                ui.checkbox(&mut state.occlusion_culling_enabled, "遮挡剔除");
+                ui.checkbox(&mut state.depth_prepass_enabled, "深度预pass");
+                ui.checkbox(&mut state.parallax_enabled, "视差遮蔽映射");
+                if state.parallax_enabled {
+                    ui.add(
+                        egui::Slider::new(&mut state.parallax_steps, 1..=MAX_PARALLAX_STEPS)
+                            .text("视差步进次数"),
+                    );
+                }
+                ui.horizontal(|ui| {
+                    ui.label("灯光分簇");
+                    for size in state.light_cluster_grid.iter_mut() {
+                        ui.add(egui::DragValue::new(size).clamp_range(1..=MAX_LIGHT_CLUSTER_GRID));
+                    }
+                });
+
+                let debug_attachments = DebugAttachment::all();
+                egui::ComboBox::from_label("调试视图").show_index(
+                    ui,
+                    &mut state.selected_debug_attachment,
+                    debug_attachments.len(),
+                    |i| format!("{:?}", debug_attachments[i]),
+                );
+
                 ui.checkbox(&mut state.ssao_enabled, "SSAO");
                 if state.ssao_enabled {
                     egui::ComboBox::from_label("SSAO Kernel").show_index(
@@ -495,27 +1805,59 @@ fn build_renderer_settings_window(ui: &mut Ui, state: &mut State) {
                     );
                     ui.add(egui::Slider::new(&mut state.ssao_radius, 0.01..=1.0).text("SSAO半径"));
                     ui.add(egui::Slider::new(&mut state.ssao_strength, 0.5..=5.0).text("SSAO强度"));
+                    ui.checkbox(&mut state.ssao_range_check_enabled, "SSAO范围检测");
+                    ui.checkbox(&mut state.ssao_blur_enabled, "SSAO模糊");
+                    if state.ssao_blur_enabled {
+                        ui.add(
+                            egui::Slider::new(
+                                &mut state.ssao_blur_radius,
+                                1..=MAX_SSAO_BLUR_RADIUS,
+                            )
+                            .text("SSAO模糊半径"),
+                        );
+                    }
                 }
 
-                let fxaa_modes = FXAAMode::all();
-                egui::ComboBox::from_label("FXAA").show_index(
-                    ui,
-                    &mut state.selected_fxaa_mode,
-                    fxaa_modes.len(),
-                    |i| format!("{:?}", fxaa_modes[i]),
-                );
-
+                //每次改变都要重建attachment，按固定步长取值
                 ui.add(
-                    egui::Slider::new(&mut state.absolute_luminance_threshold, 0..=50)
-                        .text("绝对亮度阈值"),
+                    egui::Slider::new(&mut state.render_scale, MIN_RENDER_SCALE..=MAX_RENDER_SCALE)
+                        .step_by(0.05)
+                        .text("渲染缩放"),
                 );
 
-                ui.add(
-                    egui::Slider::new(&mut state.relative_luminance_threshold, 0..=50)
-                        .text("相对亮度阈值"),
+                let anti_aliasing_modes = AntiAliasingMode::all();
+                egui::ComboBox::from_label("抗锯齿").show_index(
+                    ui,
+                    &mut state.selected_anti_aliasing_mode,
+                    anti_aliasing_modes.len(),
+                    |i| format!("{:?}", anti_aliasing_modes[i]),
                 );
 
-                ui.add(egui::Slider::new(&mut state.subpixel_blending, 0..=100).text("子像素混合"));
+                if state.selected_anti_aliasing_mode == AntiAliasingMode::FXAA as usize {
+                    let fxaa_modes = FXAAMode::all();
+                    egui::ComboBox::from_label("FXAA").show_index(
+                        ui,
+                        &mut state.selected_fxaa_mode,
+                        fxaa_modes.len(),
+                        |i| format!("{:?}", fxaa_modes[i]),
+                    );
+
+                    ui.add(
+                        egui::Slider::new(&mut state.absolute_luminance_threshold, 0..=50)
+                            .text("绝对亮度阈值"),
+                    );
+
+                    ui.add(
+                        egui::Slider::new(&mut state.relative_luminance_threshold, 0..=50)
+                            .text("相对亮度阈值"),
+                    );
+
+                    ui.add(
+                        egui::Slider::new(&mut state.subpixel_blending, 0..=100).text("子像素混合"),
+                    );
+
+                    ui.add(egui::Slider::new(&mut state.sharpness, 0.0..=1.0).text("锐化"));
+                }
             }
 
             {
@@ -529,6 +1871,16 @@ fn build_renderer_settings_window(ui: &mut Ui, state: &mut State) {
                     tone_map_modes.len(),
                     |i| format!("{:?}", tone_map_modes[i]),
                 );
+                ui.add(
+                    egui::Slider::new(&mut state.exposure, MIN_EXPOSURE..=MAX_EXPOSURE)
+                        .suffix(" EV")
+                        .text("曝光"),
+                );
+                ui.add_enabled(
+                    tone_map_modes[state.selected_tone_map_mode].uses_white_point(),
+                    egui::Slider::new(&mut state.white_point, MIN_WHITE_POINT..=MAX_WHITE_POINT)
+                        .text("白点"),
+                );
             }
 
             {
@@ -542,10 +1894,181 @@ fn build_renderer_settings_window(ui: &mut Ui, state: &mut State) {
                     output_modes.len(),
                     |i| format!("{:?}", output_modes[i]),
                 );
+                if output_modes[state.selected_output_mode] == OutputMode::PreFiltered {
+                    ui.add(
+                        egui::Slider::new(&mut state.ibl_debug_roughness, 0.0..=1.0)
+                            .text("预滤波粗糙度"),
+                    );
+                }
+
+                let polygon_modes = PolygonMode::all();
+                egui::ComboBox::from_label("多边形模式").show_index(
+                    ui,
+                    &mut state.selected_polygon_mode,
+                    polygon_modes.len(),
+                    |i| format!("{:?}", polygon_modes[i]),
+                );
+
+                ui.checkbox(&mut state.show_performance, "性能统计");
+                ui.add_enabled_ui(validation_available, |ui| {
+                    ui.checkbox(&mut state.show_validation_console, "验证层控制台")
+                        .on_disabled_hover_text("验证层未开启");
+                });
+                ui.checkbox(&mut state.show_log_console, "日志");
+            }
+        });
+}
+
+fn build_validation_console_window(
+    ui: &mut Ui,
+    state: &mut State,
+    messages: &mut VecDeque<ValidationMessage>,
+) {
+    let severities = ValidationSeverity::all();
+    ui.horizontal(|ui| {
+        egui::ComboBox::from_label("最低级别").show_index(
+            ui,
+            &mut state.validation_min_severity,
+            severities.len(),
+            |i| format!("{:?}", severities[i]),
+        );
+        if ui.button("清空").clicked() {
+            messages.clear();
+        }
+    });
+    ui.separator();
+
+    let min_severity = severities[state.validation_min_severity];
+    egui::ScrollArea::vertical()
+        .auto_shrink([false; 2])
+        .stick_to_bottom(true)
+        .show(ui, |ui| {
+            for message in messages.iter().filter(|m| m.severity >= min_severity) {
+                let color = match message.severity {
+                    ValidationSeverity::Verbose => egui::Color32::GRAY,
+                    ValidationSeverity::Info => egui::Color32::LIGHT_GRAY,
+                    ValidationSeverity::Warning => egui::Color32::YELLOW,
+                    ValidationSeverity::Error => egui::Color32::RED,
+                };
+                ui.colored_label(
+                    color,
+                    format!(
+                        "[{:?}] {} - {}",
+                        message.severity, message.message_type, message.message
+                    ),
+                );
             }
         });
 }
 
+fn build_log_console_window(ui: &mut Ui, state: &mut State, records: &mut VecDeque<LogRecord>) {
+    ui.horizontal(|ui| {
+        egui::ComboBox::from_label("最低级别").show_index(
+            ui,
+            &mut state.log_max_level,
+            LOG_LEVELS.len(),
+            |i| format!("{}", LOG_LEVELS[i]),
+        );
+        ui.checkbox(&mut state.log_autoscroll, "自动滚动");
+        if ui.button("清空").clicked() {
+            records.clear();
+        }
+    });
+    ui.separator();
+
+    let max_level = LOG_LEVELS[state.log_max_level];
+    egui::ScrollArea::vertical()
+        .auto_shrink([false; 2])
+        .stick_to_bottom(state.log_autoscroll)
+        .show(ui, |ui| {
+            for record in records.iter().filter(|r| r.level <= max_level) {
+                let color = match record.level {
+                    log::Level::Error => egui::Color32::RED,
+                    log::Level::Warn => egui::Color32::YELLOW,
+                    log::Level::Info => egui::Color32::LIGHT_GRAY,
+                    log::Level::Debug | log::Level::Trace => egui::Color32::GRAY,
+                };
+                ui.colored_label(
+                    color,
+                    format!("[{}] {} - {}", record.level, record.target, record.message),
+                );
+            }
+        });
+}
+
+fn build_performance_window(ui: &mut Ui, performance: &PerformanceStats) {
+    let frame_times = &performance.frame_times;
+    let frame_time = frame_times.back().copied().unwrap_or(0.0);
+    let average = if frame_times.is_empty() {
+        0.0
+    } else {
+        frame_times.iter().sum::<f32>() / frame_times.len() as f32
+    };
+    let fps = if average > 0.0 { 1000.0 / average } else { 0.0 };
+
+    ui.label(format!("FPS: {:.1}", fps));
+    ui.label(format!("帧时间: {:.2} ms", frame_time));
+    ui.label(format!("CPU: {:.2} ms", performance.cpu_time));
+    let culling = &performance.culling;
+    ui.label(format!(
+        "绘制节点: {} 视锥剔除: {} 遮挡剔除: {}",
+        culling.drawn, culling.frustum_culled, culling.occluded
+    ));
+
+    ui.separator();
+    let scene = &performance.scene;
+    ui.label(format!("顶点: {}", culling.vertices));
+    ui.label(format!(
+        "三角形: {} / {}",
+        culling.triangles, culling.total_triangles
+    ));
+    ui.label(format!(
+        "绘制调用: {} / {}",
+        culling.draw_calls, culling.total_draw_calls
+    ));
+    ui.label(format!("纹理: {}", scene.texture_count));
+    ui.label(format!(
+        "显存: {:.1} MB",
+        scene.allocated_memory as f64 / (1024.0 * 1024.0)
+    ));
+
+    if !performance.gpu_timings.is_empty() {
+        ui.separator();
+        let total = performance.gpu_timings.iter().map(|(_, t)| t).sum::<f32>();
+        ui.label(format!("GPU: {:.2} ms", total));
+        for (pass, time) in performance.gpu_timings.iter() {
+            ui.label(format!("  {:?}: {:.2} ms", pass, time));
+        }
+    }
+
+    ui.separator();
+    //纵轴至少显示到30fps，超过的尖峰会撑高范围
+    let max_time = frame_times.iter().copied().fold(1000.0 / 30.0, f32::max);
+    let (rect, _) = ui.allocate_exact_size(FRAME_TIME_GRAPH_SIZE.into(), Sense::hover());
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 0.0, egui::Color32::from_black_alpha(128));
+
+    let to_y = |time: f32| rect.bottom() - time / max_time * rect.height();
+    let target_y = to_y(1000.0 / 60.0);
+    painter.hline(
+        rect.x_range(),
+        target_y,
+        egui::Stroke::new(1.0, egui::Color32::DARK_GREEN),
+    );
+
+    let step = rect.width() / (FRAME_TIME_HISTORY - 1) as f32;
+    let offset = FRAME_TIME_HISTORY - frame_times.len();
+    let points = frame_times
+        .iter()
+        .enumerate()
+        .map(|(i, time)| egui::pos2(rect.left() + (offset + i) as f32 * step, to_y(*time)))
+        .collect::<Vec<_>>();
+    painter.add(egui::Shape::line(
+        points,
+        egui::Stroke::new(1.0, egui::Color32::LIGHT_GREEN),
+    ));
+}
+
 #[derive(Clone)]
 struct State {
     selected_animation: usize,
@@ -553,19 +2076,50 @@ struct State {
     reset_animation: bool,
     toggle_animation: bool,
     stop_animation: bool,
+    scrub_animation_time: Option<f32>,
     animation_speed: f32,
+    animation_fade_time: f32,
 
     reset_camera: bool,
+    frame_selected: bool,
+    jump_camera_bookmark: Option<usize>,
+    camera_bookmark_name: String,
+    selected_camera_mode: usize,
+    selected_gizmo_mode: usize,
+    gizmo_drag_axis: Option<usize>,
+    node_euler: [f32; 3],
+    node_euler_source: Option<(NodeUid, [f32; 4])>,
+    camera_move_speed: f32,
+    orthographic: bool,
+    ortho_height: f32,
+    auto_clip_planes: bool,
+    z_near: f32,
+    z_far: f32,
+
+    show_performance: bool,
+    show_validation_console: bool,
+    validation_min_severity: usize,
+    show_log_console: bool,
+    log_max_level: usize,
+    log_autoscroll: bool,
 
     selected_output_mode: usize,
+    selected_polygon_mode: usize,
     selected_tone_map_mode: usize,
+    selected_anti_aliasing_mode: usize,
     selected_fxaa_mode: usize,
+    sharpness: f32,
     emissive_intensity: f32,
     ssao_enabled: bool,
     ssao_radius: f32,
     ssao_strength: f32,
+    ssao_blur_enabled: bool,
+    ssao_blur_radius: u32,
+    ssao_range_check_enabled: bool,
     ssao_kernel_size_index: usize,
     bloom_strength: u32,
+    bloom_threshold: f32,
+    bloom_mip_levels: u32,
     absolute_luminance_threshold: u32,
     relative_luminance_threshold: u32,
     subpixel_blending: u32,
@@ -573,10 +2127,46 @@ struct State {
     fog_end: f32,
     fog_start: f32,
     fog_color: [f32; 4],
+    exposure: f32,
+    white_point: f32,
+    ambient_intensity: f32,
+    shadow_enabled: bool,
+    bloom_enabled: bool,
+    selected_debug_attachment: usize,
+    shadow_cascade_count: u32,
+    shadow_split_lambda: f32,
+    selected_shadow_map_resolution: usize,
+    selected_shadow_filter_mode: usize,
+    shadow_pcf_kernel_size_index: usize,
+    shadow_depth_bias: f32,
+    shadow_normal_bias: f32,
+    shadow_casting_lights: u32,
+    grid_enabled: bool,
+    axes_enabled: bool,
+    shadow_catcher_enabled: bool,
+    shadow_catcher_size: f32,
+    shadow_catcher_color: [f32; 4],
+    gpu_skinning_enabled: bool,
+    occlusion_culling_enabled: bool,
+    environment_yaw: f32,
+    skybox_exposure: f32,
+    skybox_blur: f32,
+    skybox_enabled: bool,
+    clear_color: [f32; 4],
+    ibl_debug_roughness: f32,
+    light_cluster_grid: [u32; 3],
+    parallax_enabled: bool,
+    parallax_steps: u32,
+    depth_prepass_enabled: bool,
+    render_scale: f32,
+    outline_enabled: bool,
+    outline_color: [f32; 3],
+    outline_thickness: u32,
     renderer_settings_changed: bool,
 
     hovered: bool,
 
+    selected_model: usize,
     select_node: Option<Node>,
 }
 
@@ -584,39 +2174,157 @@ impl State {
     fn new(renderer_settings: RendererSettings) -> Self {
         Self {
             selected_output_mode: renderer_settings.output_mode as _,
+            selected_polygon_mode: renderer_settings.polygon_mode as _,
             selected_tone_map_mode: renderer_settings.tone_map_mode as _,
+            selected_anti_aliasing_mode: renderer_settings.anti_aliasing_mode as _,
             selected_fxaa_mode: renderer_settings.fxaa_mode as _,
+            sharpness: renderer_settings.sharpness,
             emissive_intensity: renderer_settings.emissive_intensity,
             ssao_enabled: renderer_settings.ssao_enabled,
             ssao_radius: renderer_settings.ssao_radius,
             ssao_strength: renderer_settings.ssao_strength,
+            ssao_blur_enabled: renderer_settings.ssao_blur_enabled,
+            ssao_blur_radius: renderer_settings.ssao_blur_radius,
+            ssao_range_check_enabled: renderer_settings.ssao_range_check_enabled,
             ssao_kernel_size_index: get_kernel_size_index(renderer_settings.ssao_kernel_size),
+            bloom_strength: (renderer_settings.bloom_strength * 100f32) as _,
+            bloom_threshold: renderer_settings.bloom_threshold,
+            bloom_mip_levels: renderer_settings.bloom_mip_levels,
+            absolute_luminance_threshold: (renderer_settings.absolute_luminance_threshold * 100f32)
+                as _,
+            relative_luminance_threshold: (renderer_settings.relative_luminance_threshold * 100f32)
+                as _,
+            subpixel_blending: (renderer_settings.subpixel_blending * 100f32) as _,
+            fog_density: renderer_settings.fog_density,
+            fog_end: renderer_settings.fog_end,
+            fog_start: renderer_settings.fog_start,
+            fog_color: renderer_settings.fog_color,
+            exposure: renderer_settings.exposure,
+            white_point: renderer_settings.white_point,
+            ambient_intensity: renderer_settings.ambient_intensity,
+            shadow_enabled: renderer_settings.shadow_enabled,
+            bloom_enabled: renderer_settings.bloom_enabled,
+            selected_debug_attachment: renderer_settings.debug_attachment as _,
+            shadow_cascade_count: renderer_settings.shadow_cascade_count,
+            shadow_split_lambda: renderer_settings.shadow_split_lambda,
+            selected_shadow_map_resolution: renderer_settings.shadow_map_resolution as _,
+            selected_shadow_filter_mode: renderer_settings.shadow_filter_mode as _,
+            shadow_pcf_kernel_size_index: get_pcf_kernel_size_index(
+                renderer_settings.shadow_pcf_kernel_size,
+            ),
+            shadow_depth_bias: renderer_settings.shadow_depth_bias,
+            shadow_normal_bias: renderer_settings.shadow_normal_bias,
+            shadow_casting_lights: renderer_settings.shadow_casting_lights,
+            grid_enabled: renderer_settings.grid_enabled,
+            axes_enabled: renderer_settings.axes_enabled,
+            shadow_catcher_enabled: renderer_settings.shadow_catcher_enabled,
+            shadow_catcher_size: renderer_settings.shadow_catcher_size,
+            shadow_catcher_color: renderer_settings.shadow_catcher_color,
+            gpu_skinning_enabled: renderer_settings.gpu_skinning_enabled,
+            occlusion_culling_enabled: renderer_settings.occlusion_culling_enabled,
+            environment_yaw: renderer_settings.environment_yaw,
+            skybox_exposure: renderer_settings.skybox_exposure,
+            skybox_blur: renderer_settings.skybox_blur,
+            skybox_enabled: renderer_settings.skybox_enabled,
+            clear_color: renderer_settings.clear_color,
+            ibl_debug_roughness: renderer_settings.ibl_debug_roughness,
+            light_cluster_grid: renderer_settings.light_cluster_grid,
+            parallax_enabled: renderer_settings.parallax_enabled,
+            parallax_steps: renderer_settings.parallax_steps,
+            depth_prepass_enabled: renderer_settings.depth_prepass_enabled,
+            render_scale: renderer_settings.render_scale,
+            outline_enabled: renderer_settings.outline_enabled,
+            outline_color: renderer_settings.outline_color,
+            outline_thickness: renderer_settings.outline_thickness,
             ..Default::default()
         }
     }
 
     fn reset(&self) -> Self {
         Self {
+            selected_camera_mode: self.selected_camera_mode,
+            camera_move_speed: self.camera_move_speed,
+            orthographic: self.orthographic,
+            ortho_height: self.ortho_height,
+            auto_clip_planes: self.auto_clip_planes,
+            z_near: self.z_near,
+            z_far: self.z_far,
+            selected_gizmo_mode: self.selected_gizmo_mode,
+            show_performance: self.show_performance,
+            show_validation_console: self.show_validation_console,
+            validation_min_severity: self.validation_min_severity,
+            show_log_console: self.show_log_console,
+            log_max_level: self.log_max_level,
+            log_autoscroll: self.log_autoscroll,
             selected_output_mode: self.selected_output_mode,
+            selected_polygon_mode: self.selected_polygon_mode,
             selected_tone_map_mode: self.selected_tone_map_mode,
+            selected_anti_aliasing_mode: self.selected_anti_aliasing_mode,
             selected_fxaa_mode: self.selected_fxaa_mode,
+            sharpness: self.sharpness,
             emissive_intensity: self.emissive_intensity,
             ssao_radius: self.ssao_radius,
             ssao_strength: self.ssao_strength,
+            ssao_blur_enabled: self.ssao_blur_enabled,
+            ssao_blur_radius: self.ssao_blur_radius,
+            ssao_range_check_enabled: self.ssao_range_check_enabled,
             ssao_kernel_size_index: self.ssao_kernel_size_index,
             ssao_enabled: self.ssao_enabled,
+            exposure: self.exposure,
+            white_point: self.white_point,
+            ambient_intensity: self.ambient_intensity,
+            shadow_enabled: self.shadow_enabled,
+            bloom_enabled: self.bloom_enabled,
+            bloom_threshold: self.bloom_threshold,
+            bloom_mip_levels: self.bloom_mip_levels,
+            selected_debug_attachment: self.selected_debug_attachment,
+            shadow_cascade_count: self.shadow_cascade_count,
+            shadow_split_lambda: self.shadow_split_lambda,
+            selected_shadow_map_resolution: self.selected_shadow_map_resolution,
+            selected_shadow_filter_mode: self.selected_shadow_filter_mode,
+            shadow_pcf_kernel_size_index: self.shadow_pcf_kernel_size_index,
+            shadow_depth_bias: self.shadow_depth_bias,
+            shadow_normal_bias: self.shadow_normal_bias,
+            shadow_casting_lights: self.shadow_casting_lights,
+            grid_enabled: self.grid_enabled,
+            axes_enabled: self.axes_enabled,
+            shadow_catcher_enabled: self.shadow_catcher_enabled,
+            shadow_catcher_size: self.shadow_catcher_size,
+            shadow_catcher_color: self.shadow_catcher_color,
+            gpu_skinning_enabled: self.gpu_skinning_enabled,
+            occlusion_culling_enabled: self.occlusion_culling_enabled,
+            environment_yaw: self.environment_yaw,
+            skybox_exposure: self.skybox_exposure,
+            skybox_blur: self.skybox_blur,
+            skybox_enabled: self.skybox_enabled,
+            clear_color: self.clear_color,
+            ibl_debug_roughness: self.ibl_debug_roughness,
+            light_cluster_grid: self.light_cluster_grid,
+            parallax_enabled: self.parallax_enabled,
+            parallax_steps: self.parallax_steps,
+            depth_prepass_enabled: self.depth_prepass_enabled,
+            render_scale: self.render_scale,
+            outline_enabled: self.outline_enabled,
+            outline_color: self.outline_color,
+            outline_thickness: self.outline_thickness,
             ..Default::default()
         }
     }
 
     fn check_renderer_settings_changed(&mut self, other: &Self) {
         self.renderer_settings_changed = self.selected_output_mode != other.selected_output_mode
+            || self.selected_polygon_mode != other.selected_polygon_mode
             || self.selected_tone_map_mode != other.selected_tone_map_mode
+            || self.selected_anti_aliasing_mode != other.selected_anti_aliasing_mode
             || self.selected_fxaa_mode != other.selected_fxaa_mode
+            || self.sharpness != other.sharpness
             || self.emissive_intensity != other.emissive_intensity
             || self.ssao_enabled != other.ssao_enabled
             || self.ssao_radius != other.ssao_radius
             || self.ssao_strength != other.ssao_strength
+            || self.ssao_blur_enabled != other.ssao_blur_enabled
+            || self.ssao_blur_radius != other.ssao_blur_radius
+            || self.ssao_range_check_enabled != other.ssao_range_check_enabled
             || self.ssao_kernel_size_index != other.ssao_kernel_size_index
             || self.absolute_luminance_threshold != other.absolute_luminance_threshold
             || self.relative_luminance_threshold != other.relative_luminance_threshold
@@ -625,7 +2333,44 @@ impl State {
             || self.fog_end != other.fog_end
             || self.fog_start != other.fog_start
             || self.fog_color != other.fog_color
-            || self.bloom_strength != other.bloom_strength;
+            || self.exposure != other.exposure
+            || self.white_point != other.white_point
+            || self.ambient_intensity != other.ambient_intensity
+            || self.shadow_enabled != other.shadow_enabled
+            || self.bloom_enabled != other.bloom_enabled
+            || self.selected_debug_attachment != other.selected_debug_attachment
+            || self.shadow_cascade_count != other.shadow_cascade_count
+            || self.shadow_split_lambda != other.shadow_split_lambda
+            || self.selected_shadow_map_resolution != other.selected_shadow_map_resolution
+            || self.selected_shadow_filter_mode != other.selected_shadow_filter_mode
+            || self.shadow_pcf_kernel_size_index != other.shadow_pcf_kernel_size_index
+            || self.shadow_depth_bias != other.shadow_depth_bias
+            || self.shadow_normal_bias != other.shadow_normal_bias
+            || self.shadow_casting_lights != other.shadow_casting_lights
+            || self.grid_enabled != other.grid_enabled
+            || self.axes_enabled != other.axes_enabled
+            || self.shadow_catcher_enabled != other.shadow_catcher_enabled
+            || self.shadow_catcher_size != other.shadow_catcher_size
+            || self.shadow_catcher_color != other.shadow_catcher_color
+            || self.gpu_skinning_enabled != other.gpu_skinning_enabled
+            || self.occlusion_culling_enabled != other.occlusion_culling_enabled
+            || self.environment_yaw != other.environment_yaw
+            || self.skybox_exposure != other.skybox_exposure
+            || self.skybox_blur != other.skybox_blur
+            || self.skybox_enabled != other.skybox_enabled
+            || self.clear_color != other.clear_color
+            || self.ibl_debug_roughness != other.ibl_debug_roughness
+            || self.light_cluster_grid != other.light_cluster_grid
+            || self.parallax_enabled != other.parallax_enabled
+            || self.parallax_steps != other.parallax_steps
+            || self.depth_prepass_enabled != other.depth_prepass_enabled
+            || self.render_scale != other.render_scale
+            || self.outline_enabled != other.outline_enabled
+            || self.outline_color != other.outline_color
+            || self.outline_thickness != other.outline_thickness
+            || self.bloom_strength != other.bloom_strength
+            || self.bloom_threshold != other.bloom_threshold
+            || self.bloom_mip_levels != other.bloom_mip_levels;
     }
 }
 
@@ -637,19 +2382,51 @@ impl Default for State {
             reset_animation: false,
             toggle_animation: false,
             stop_animation: false,
+            scrub_animation_time: None,
             animation_speed: 1.0,
+            animation_fade_time: 0.0,
 
             reset_camera: false,
+            frame_selected: false,
+            jump_camera_bookmark: None,
+            camera_bookmark_name: String::new(),
+            selected_camera_mode: CameraMode::Orbit as _,
+            selected_gizmo_mode: GizmoMode::Translate as _,
+            gizmo_drag_axis: None,
+            node_euler: [0.0; 3],
+            node_euler_source: None,
+            camera_move_speed: DEFAULT_MOVE_SPEED,
+            orthographic: false,
+            ortho_height: DEFAULT_ORTHO_HEIGHT,
+            auto_clip_planes: true,
+            z_near: Z_NEAR,
+            z_far: Z_FAR,
+
+            show_performance: false,
+            show_validation_console: false,
+            validation_min_severity: ValidationSeverity::Warning as _,
+            show_log_console: false,
+            //默认显示到Info
+            log_max_level: 2,
+            log_autoscroll: true,
 
             selected_output_mode: 0,
+            selected_polygon_mode: 0,
             selected_tone_map_mode: 0,
+            selected_anti_aliasing_mode: AntiAliasingMode::FXAA as _,
             selected_fxaa_mode: 0,
+            sharpness: 0.0,
             emissive_intensity: 1.0,
             ssao_enabled: true,
             ssao_radius: 0.15,
             ssao_strength: 1.0,
+            ssao_blur_enabled: true,
+            ssao_blur_radius: DEFAULT_SSAO_BLUR_RADIUS,
+            ssao_range_check_enabled: true,
             ssao_kernel_size_index: 1,
             bloom_strength: (DEFAULT_BLOOM_STRENGTH * 100f32) as _,
+            bloom_threshold: DEFAULT_BLOOM_THRESHOLD,
+            bloom_mip_levels: BLOOM_MIP_LEVELS,
             absolute_luminance_threshold: (0.1 * 100f32) as _,
             relative_luminance_threshold: (0.1 * 100f32) as _,
             subpixel_blending: (0.75 * 100f32) as _,
@@ -657,15 +2434,52 @@ impl Default for State {
             fog_end: 100.0,
             fog_start: 0.1,
             fog_color: [1.0, 1.0, 1.0, 1.0],
+            exposure: 0.0,
+            white_point: DEFAULT_WHITE_POINT,
+            ambient_intensity: 1.0,
+            shadow_enabled: true,
+            bloom_enabled: true,
+            selected_debug_attachment: DebugAttachment::None as _,
+            shadow_cascade_count: MAX_SHADOW_CASCADES,
+            shadow_split_lambda: 0.75,
+            selected_shadow_map_resolution: ShadowMapResolution::Medium as _,
+            selected_shadow_filter_mode: ShadowFilterMode::PCF as _,
+            shadow_pcf_kernel_size_index: 1,
+            shadow_depth_bias: 0.0005,
+            shadow_normal_bias: 1.0,
+            shadow_casting_lights: 1,
+            grid_enabled: true,
+            axes_enabled: true,
+            shadow_catcher_enabled: false,
+            shadow_catcher_size: 0.0,
+            shadow_catcher_color: DEFAULT_SHADOW_CATCHER_COLOR,
+            gpu_skinning_enabled: true,
+            occlusion_culling_enabled: false,
+            environment_yaw: 0.0,
+            skybox_exposure: 1.0,
+            skybox_blur: 0.0,
+            skybox_enabled: true,
+            clear_color: [0.0, 0.0, 0.0, 1.0],
+            ibl_debug_roughness: 0.0,
+            light_cluster_grid: DEFAULT_LIGHT_CLUSTER_GRID,
+            parallax_enabled: true,
+            parallax_steps: DEFAULT_PARALLAX_STEPS,
+            depth_prepass_enabled: false,
+            render_scale: 1.0,
+            outline_enabled: true,
+            outline_color: DEFAULT_OUTLINE_COLOR,
+            outline_thickness: DEFAULT_OUTLINE_THICKNESS,
             renderer_settings_changed: false,
 
             hovered: false,
+            selected_model: 0,
             select_node: None,
         }
     }
 }
 
-const SSAO_KERNEL_SIZES: [u32; 4] = [16, 32, 64, 128];
+pub(crate) const SSAO_KERNEL_SIZES: [u32; 4] = [16, 32, 64, 128];
+pub(crate) const SHADOW_PCF_KERNEL_SIZES: [u32; 4] = [1, 3, 5, 7];
 fn get_kernel_size_index(size: u32) -> usize {
     SSAO_KERNEL_SIZES
         .iter()
@@ -677,3 +2491,15 @@ fn get_kernel_size_index(size: u32) -> usize {
             )
         })
 }
+
+fn get_pcf_kernel_size_index(size: u32) -> usize {
+    SHADOW_PCF_KERNEL_SIZES
+        .iter()
+        .position(|&v| v == size)
+        .unwrap_or_else(|| {
+            panic!(
+                "非法PCF kernel大小{:?}，应该是{:?}中的一个",
+                size, SHADOW_PCF_KERNEL_SIZES
+            )
+        })
+}
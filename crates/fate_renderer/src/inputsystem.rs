@@ -1,3 +1,5 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
 use std::fmt::{self, Debug};
 
 use vulkan::winit::{
@@ -5,90 +7,82 @@ use vulkan::winit::{
     keyboard::{Key, ModifiersState},
 };
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Clone, Debug)]
 pub struct InputSystem {
-    is_left_clicked: bool,
-    is_right_clicked: bool,
-    is_control_w_clicked: bool,
-    is_control_a_clicked: bool,
-    is_control_s_clicked: bool,
-    is_control_d_clicked: bool,
+    bindings: Vec<Binding>,
+    active_actions: HashSet<Action>,
+    triggered_actions: HashSet<Action>,
     cursor_delta: [f32; 2],
     wheel_delta: f32,
     modifiers: ModifiersState,
+    //窗口内的光标位置，单位为物理像素
+    cursor_position: [f32; 2],
+    left_press_position: Option<[f32; 2]>,
+    click_position: Option<[f32; 2]>,
 }
 
+//按下和松开之间移动超过该像素数视为拖动而不是点击
+const CLICK_MAX_DISTANCE: f32 = 4.0;
+
 impl InputSystem {
-    pub fn update(mut self, event: &Event<()>) -> Self {
-        let mut is_left_clicked = None;
-        let mut is_right_clicked = None;
-        let mut wheel_delta = self.wheel_delta;
-        let mut cursor_delta = self.cursor_delta;
+    pub fn new(bindings: &InputBindings) -> Self {
+        Self {
+            bindings: bindings.parse(),
+            active_actions: HashSet::new(),
+            triggered_actions: HashSet::new(),
+            cursor_delta: [0.0, 0.0],
+            wheel_delta: 0.0,
+            modifiers: Default::default(),
+            cursor_position: [0.0, 0.0],
+            left_press_position: None,
+            click_position: None,
+        }
+    }
 
+    pub fn update(&mut self, event: &Event<()>) {
         if let Event::NewEvents(_) = event {
-            return Self {
-                cursor_delta: [0.0, 0.0],
-                wheel_delta: 0.0,
-                ..self
-            };
+            self.triggered_actions.clear();
+            self.cursor_delta = [0.0, 0.0];
+            self.wheel_delta = 0.0;
+            self.click_position = None;
+            return;
         }
         if let Event::WindowEvent { event, .. } = event {
             match event {
                 WindowEvent::ModifiersChanged(modifiers) => {
                     self.modifiers = modifiers.state();
-                    //println!("{:?}", self.modifiers);
                 }
                 WindowEvent::KeyboardInput {
                     event,
                     is_synthetic: false,
                     ..
                 } => {
-                    let mods = self.modifiers;
-
-                    if event.state.is_pressed() {
-                        let action = if let Key::Character(ch) = event.logical_key.as_ref() {
-                            process_key_binding(&ch.to_uppercase(), &mods)
-                        } else {
-                            None
-                        };
-
-                        if let Some(action) = action {
-                            self.handle_action(action, true);
-                        }
-                    } else {
-                        let action = if let Key::Character(ch) = event.logical_key.as_ref() {
-                            process_key_binding(&ch.to_uppercase(), &mods)
-                        } else {
-                            None
-                        };
-
-                        if let Some(action) = action {
-                            self.handle_action(action, false);
+                    let trigger = match event.logical_key.as_ref() {
+                        Key::Character(ch) => Some(Trigger::Key(ch.to_uppercase())),
+                        Key::Named(named) => Some(Trigger::Key(format!("{named:?}"))),
+                        _ => None,
+                    };
+                    if let Some(trigger) = trigger {
+                        //按住时的重复事件不算新的触发
+                        if !event.repeat || !event.state.is_pressed() {
+                            self.handle_trigger(&trigger, event.state.is_pressed());
                         }
                     }
                 }
                 WindowEvent::MouseInput { button, state, .. } => {
-                    if *state == ElementState::Pressed {
-                        if *button == MouseButton::Left {
-                            is_left_clicked = Some(true);
-                        }
-                        if *button == MouseButton::Right {
-                            is_right_clicked = Some(true)
-                        }
-                    } else {
-                        if *button == MouseButton::Left {
-                            is_left_clicked = Some(false);
-                        }
-                        if *button == MouseButton::Right {
-                            is_right_clicked = Some(false)
-                        }
+                    self.handle_trigger(&Trigger::Mouse(*button), *state == ElementState::Pressed);
+                    if *button == MouseButton::Left {
+                        self.handle_left_click(*state == ElementState::Pressed);
                     }
                 }
+                WindowEvent::CursorMoved { position, .. } => {
+                    self.cursor_position = [position.x as f32, position.y as f32];
+                }
                 WindowEvent::MouseWheel {
                     delta: MouseScrollDelta::LineDelta(_, v_lines),
                     ..
                 } => {
-                    wheel_delta += v_lines;
+                    self.wheel_delta += v_lines;
                 }
                 _ => {}
             }
@@ -99,57 +93,53 @@ impl InputSystem {
             ..
         } = event
         {
-            cursor_delta[0] += *x as f32;
-            cursor_delta[1] += *y as f32;
+            self.cursor_delta[0] += *x as f32;
+            self.cursor_delta[1] += *y as f32;
         }
+    }
 
-        Self {
-            is_left_clicked: is_left_clicked.unwrap_or(self.is_left_clicked),
-            is_right_clicked: is_right_clicked.unwrap_or(self.is_right_clicked),
-            is_control_w_clicked: self.is_control_w_clicked,
-            is_control_a_clicked: self.is_control_a_clicked,
-            is_control_s_clicked: self.is_control_s_clicked,
-            is_control_d_clicked: self.is_control_d_clicked,
-            cursor_delta,
-            wheel_delta,
-            modifiers: self.modifiers,
+    fn handle_left_click(&mut self, press: bool) {
+        if press {
+            self.left_press_position = Some(self.cursor_position);
+            return;
+        }
+        if let Some([x, y]) = self.left_press_position.take() {
+            let [cx, cy] = self.cursor_position;
+            if (cx - x).hypot(cy - y) <= CLICK_MAX_DISTANCE {
+                self.click_position = Some(self.cursor_position);
+            }
         }
     }
 
-    fn handle_action(&mut self, action: Action, press: bool) {
-        println!("{action:?} {press:?}");
-        match action {
-            Action::ControlW => self.is_control_w_clicked = press,
-            Action::ControlA => self.is_control_a_clicked = press,
-            Action::ControlS => self.is_control_s_clicked = press,
-            Action::ControlD => self.is_control_d_clicked = press,
+    //按下时要求修饰键完全匹配，松开时不管修饰键状态，避免先松开Ctrl导致动作卡住
+    fn handle_trigger(&mut self, trigger: &Trigger, press: bool) {
+        let mods = self.modifiers;
+        for binding in &self.bindings {
+            if press && binding.is_triggered_by(trigger, &mods) {
+                log::debug!("触发{} {}", binding.action, binding);
+                if self.active_actions.insert(binding.action) {
+                    self.triggered_actions.insert(binding.action);
+                }
+            } else if !press && &binding.trigger == trigger {
+                self.active_actions.remove(&binding.action);
+            }
         }
     }
 }
 
 impl InputSystem {
-    pub fn is_left_clicked(&self) -> bool {
-        self.is_left_clicked
+    //对应的按键是否处于按下状态
+    pub fn is_active(&self, action: Action) -> bool {
+        self.active_actions.contains(&action)
     }
 
-    pub fn is_right_clicked(&self) -> bool {
-        self.is_right_clicked
+    //本帧是否刚刚按下
+    pub fn was_triggered(&self, action: Action) -> bool {
+        self.triggered_actions.contains(&action)
     }
 
-    pub fn is_control_w_clicked(&self) -> bool {
-        self.is_control_w_clicked
-    }
-
-    pub fn _is_control_a_clicked(&self) -> bool {
-        self.is_control_a_clicked
-    }
-
-    pub fn is_control_s_clicked(&self) -> bool {
-        self.is_control_s_clicked
-    }
-
-    pub fn _is_control_d_clicked(&self) -> bool {
-        self.is_control_d_clicked
+    pub fn is_shift_pressed(&self) -> bool {
+        self.modifiers.shift_key()
     }
 
     pub fn cursor_delta(&self) -> [f32; 2] {
@@ -159,69 +149,207 @@ impl InputSystem {
     pub fn wheel_delta(&self) -> f32 {
         self.wheel_delta
     }
+
+    //本帧左键点击（按下后没有拖动就松开）的位置
+    pub fn click_position(&self) -> Option<[f32; 2]> {
+        self.click_position
+    }
 }
 
 impl Default for InputSystem {
     fn default() -> Self {
-        Self {
-            is_left_clicked: false,
-            is_right_clicked: false,
-            is_control_w_clicked: false,
-            is_control_a_clicked: false,
-            is_control_s_clicked: false,
-            is_control_d_clicked: false,
-            cursor_delta: [0.0, 0.0],
-            wheel_delta: 0.0,
-            modifiers: Default::default(),
+        Self::new(&InputBindings::default())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Action {
+    CameraPan,
+    CameraRotate,
+    MoveForward,
+    MoveBackward,
+    MoveLeft,
+    MoveRight,
+    MoveUp,
+    MoveDown,
+    ModelForward,
+    ModelBackward,
+    ModelLeft,
+    ModelRight,
+    ToggleAnimation,
+    ResetCamera,
+    FrameSelected,
+    Screenshot,
+}
+
+impl Action {
+    pub fn all() -> [Action; 16] {
+        use Action::*;
+        [
+            CameraPan,
+            CameraRotate,
+            MoveForward,
+            MoveBackward,
+            MoveLeft,
+            MoveRight,
+            MoveUp,
+            MoveDown,
+            ModelForward,
+            ModelBackward,
+            ModelLeft,
+            ModelRight,
+            ToggleAnimation,
+            ResetCamera,
+            FrameSelected,
+            Screenshot,
+        ]
+    }
+
+    fn default_bindings(self) -> &'static [&'static str] {
+        use Action::*;
+        match self {
+            CameraPan => &["MouseLeft"],
+            CameraRotate => &["MouseRight"],
+            MoveForward => &["W"],
+            MoveBackward => &["S"],
+            MoveLeft => &["A"],
+            MoveRight => &["D"],
+            MoveUp => &["E"],
+            MoveDown => &["Q"],
+            ModelForward => &["Ctrl+W"],
+            ModelBackward => &["Ctrl+S"],
+            ModelLeft => &["Ctrl+A"],
+            ModelRight => &["Ctrl+D"],
+            ToggleAnimation => &["Space"],
+            ResetCamera => &["R"],
+            FrameSelected => &["F"],
+            Screenshot => &["F12"],
         }
     }
 }
 
-struct Binding<T: Eq> {
-    trigger: T,
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Debug::fmt(&self, f)
+    }
+}
+
+//动作到按键的映射，按键格式为"Ctrl+Shift+W"、"Space"、"F12"、"MouseLeft"等
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct InputBindings {
+    bindings: BTreeMap<Action, Vec<String>>,
+}
+
+impl InputBindings {
+    pub fn get(&self, action: Action) -> &[String] {
+        self.bindings.get(&action).map_or(&[], Vec::as_slice)
+    }
+
+    //配置文件中缺少的动作使用默认按键，写空列表可以解除绑定
+    pub fn validate(&mut self) {
+        for action in Action::all() {
+            self.bindings.entry(action).or_insert_with(|| {
+                action
+                    .default_bindings()
+                    .iter()
+                    .map(|s| s.to_string())
+                    .collect()
+            });
+        }
+    }
+
+    fn parse(&self) -> Vec<Binding> {
+        let mut bindings = Vec::new();
+        for (action, keys) in &self.bindings {
+            for key in keys {
+                match Binding::parse(key, *action) {
+                    Some(binding) => bindings.push(binding),
+                    None => log::warn!("无法解析{}的按键绑定\"{}\"", action, key),
+                }
+            }
+        }
+        bindings
+    }
+}
+
+impl Default for InputBindings {
+    fn default() -> Self {
+        let mut bindings = Self {
+            bindings: BTreeMap::new(),
+        };
+        bindings.validate();
+        bindings
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Trigger {
+    Key(String),
+    Mouse(MouseButton),
+}
+
+#[derive(Clone, Debug)]
+struct Binding {
+    trigger: Trigger,
     mods: ModifiersState,
     action: Action,
 }
 
-impl<T: Eq> Binding<T> {
-    const fn new(trigger: T, mods: ModifiersState, action: Action) -> Self {
-        Self {
+impl Binding {
+    fn parse(value: &str, action: Action) -> Option<Self> {
+        let mut parts = value.split('+').map(str::trim).collect::<Vec<_>>();
+        let key = parts.pop().filter(|key| !key.is_empty())?;
+
+        let mut mods = ModifiersState::empty();
+        for part in parts {
+            match part.to_lowercase().as_str() {
+                "ctrl" | "control" => mods |= ModifiersState::CONTROL,
+                "shift" => mods |= ModifiersState::SHIFT,
+                "alt" => mods |= ModifiersState::ALT,
+                "super" | "cmd" => mods |= ModifiersState::SUPER,
+                _ => return None,
+            }
+        }
+
+        let trigger = match key {
+            "MouseLeft" => Trigger::Mouse(MouseButton::Left),
+            "MouseRight" => Trigger::Mouse(MouseButton::Right),
+            "MouseMiddle" => Trigger::Mouse(MouseButton::Middle),
+            key if key.chars().count() == 1 => Trigger::Key(key.to_uppercase()),
+            key => Trigger::Key(key.to_string()),
+        };
+
+        Some(Self {
             trigger,
             mods,
             action,
-        }
+        })
     }
 
-    fn is_triggered_by(&self, trigger: &T, mods: &ModifiersState) -> bool {
-        &self.trigger == trigger && &self.mods == mods
+    //鼠标按键不区分修饰键，方便Shift+拖拽等操作
+    fn is_triggered_by(&self, trigger: &Trigger, mods: &ModifiersState) -> bool {
+        &self.trigger == trigger && (matches!(trigger, Trigger::Mouse(_)) || &self.mods == mods)
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum Action {
-    ControlW,
-    ControlA,
-    ControlS,
-    ControlD,
-}
-
-impl fmt::Display for Action {
+impl fmt::Display for Binding {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        Debug::fmt(&self, f)
+        if self.mods.control_key() {
+            write!(f, "Ctrl+")?;
+        }
+        if self.mods.shift_key() {
+            write!(f, "Shift+")?;
+        }
+        if self.mods.alt_key() {
+            write!(f, "Alt+")?;
+        }
+        if self.mods.super_key() {
+            write!(f, "Super+")?;
+        }
+        match &self.trigger {
+            Trigger::Key(key) => write!(f, "{}", key),
+            Trigger::Mouse(button) => write!(f, "Mouse{:?}", button),
+        }
     }
 }
-
-const KEY_BINDINGS: &[Binding<&'static str>] = &[
-    Binding::new("W", ModifiersState::CONTROL, Action::ControlW),
-    Binding::new("A", ModifiersState::CONTROL, Action::ControlA),
-    Binding::new("S", ModifiersState::CONTROL, Action::ControlS),
-    Binding::new("D", ModifiersState::CONTROL, Action::ControlD),
-];
-
-fn process_key_binding(key: &str, mods: &ModifiersState) -> Option<Action> {
-    KEY_BINDINGS.iter().find_map(|binding| {
-        binding
-            .is_triggered_by(&key, mods)
-            .then_some(binding.action)
-    })
-}
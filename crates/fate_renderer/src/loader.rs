@@ -1,5 +1,10 @@
-use gltf_loader::model::{Model, ModelStagingResources};
+use gltf_loader::model::{is_fscene_file, Model, ModelStagingResources};
+use rendering::environment::Environment;
+use std::any::Any;
 use std::error::Error;
+use std::fs::File;
+use std::io::Read;
+use std::panic::{self, AssertUnwindSafe};
 use std::path::{Path, PathBuf};
 use std::sync::mpsc;
 use std::sync::mpsc::{Receiver, Sender};
@@ -7,11 +12,21 @@ use std::sync::Arc;
 use std::thread;
 use std::thread::JoinHandle;
 use vulkan::ash::vk;
-use vulkan::{Context, PreLoadedResource};
+use vulkan::{debug_name, Context, PreLoadedResource};
+
+//加载耗时取决于glTF内容，无法预估进度，界面只显示加载中
+#[derive(Clone, Debug)]
+pub enum LoadStatus {
+    Loading(PathBuf),
+    Done(PathBuf),
+    Error(String),
+}
 
 pub struct Loader {
     message_sender: Sender<Message>,
-    model_receiver: Receiver<PreLoadedResource<Model, ModelStagingResources>>,
+    model_receiver: Receiver<(PathBuf, PreLoadedResource<Model, ModelStagingResources>)>,
+    status_sender: Sender<LoadStatus>,
+    status_receiver: Receiver<LoadStatus>,
     thread_handle: Option<JoinHandle<()>>,
 }
 
@@ -19,21 +34,34 @@ impl Loader {
     pub fn new(context: Arc<Context>) -> Self {
         let (message_sender, message_receiver) = mpsc::channel();
         let (model_sender, model_receiver) = mpsc::channel();
+        let (status_sender, status_receiver) = mpsc::channel();
 
+        let thread_status_sender = status_sender.clone();
         let thread_handle = Some(thread::spawn(move || loop {
             let message = message_receiver.recv().expect("接收路径错误！");
             match message {
                 Message::Load(path) => {
                     log::info!("{}加载中...", path.as_path().display());
-                    let pre_loaded_model = pre_load_model(&context, path.as_path());
+                    let _ = thread_status_sender.send(LoadStatus::Loading(path.clone()));
+
+                    //glTF导入过程中的panic也作为加载错误上报
+                    let pre_loaded_model = panic::catch_unwind(AssertUnwindSafe(|| {
+                        pre_load_model(&context, path.as_path())
+                    }))
+                    .unwrap_or_else(|payload| Err(panic_message(payload).into()));
 
                     match pre_loaded_model {
                         Ok(pre_loaded_model) => {
                             log::info!("{}加载成功", path.as_path().display());
-                            model_sender.send(pre_loaded_model).unwrap();
+                            model_sender.send((path, pre_loaded_model)).unwrap();
                         }
                         Err(error) => {
                             log::error!("{}载入失败，由于:{}", path.as_path().display(), error);
+                            let _ = thread_status_sender.send(LoadStatus::Error(format!(
+                                "{}载入失败: {}",
+                                path.as_path().display(),
+                                error
+                            )));
                         }
                     }
                 }
@@ -44,6 +72,8 @@ impl Loader {
         Self {
             message_sender,
             model_receiver,
+            status_sender,
+            status_receiver,
             thread_handle,
         }
     }
@@ -56,16 +86,84 @@ impl Loader {
 
     pub fn get_model(&self) -> Option<Model> {
         match self.model_receiver.try_recv() {
-            Ok(mut pre_loaded_model) => Some(pre_loaded_model.finish()),
+            Ok((path, mut pre_loaded_model)) => {
+                let model = pre_loaded_model.finish();
+                let _ = self.status_sender.send(LoadStatus::Done(path));
+                Some(model)
+            }
             _ => None,
         }
     }
+
+    pub fn get_status(&self) -> Option<LoadStatus> {
+        self.status_receiver.try_recv().ok()
+    }
+}
+
+//在当前线程加载模型并等待上传完成，用于无窗口渲染
+pub fn load_model<P: AsRef<Path>>(
+    context: &Arc<Context>,
+    path: P,
+) -> Result<Model, Box<dyn Error>> {
+    let mut pre_loaded_model = pre_load_model(context, path)?;
+    Ok(pre_loaded_model.finish())
+}
+
+//拖入文件时按扩展名区分模型和环境贴图
+pub fn is_environment_file<P: AsRef<Path>>(path: P) -> bool {
+    path.as_ref()
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("hdr") || ext.eq_ignore_ascii_case("exr"))
+}
+
+//glb文件头部的魔数
+const GLB_MAGIC: &[u8; 4] = b"glTF";
+
+//按扩展名判断是否为glTF/glb/fscene，扩展名不认识时再检查glb魔数
+pub fn is_model_file<P: AsRef<Path>>(path: P) -> bool {
+    let path = path.as_ref();
+    let known_extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("gltf") || ext.eq_ignore_ascii_case("glb"));
+    known_extension || is_fscene_file(path) || has_glb_magic(path)
+}
+
+fn has_glb_magic(path: &Path) -> bool {
+    let mut magic = [0u8; 4];
+    File::open(path)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .is_ok_and(|_| &magic == GLB_MAGIC)
+}
+
+//包括IBL预计算，会阻塞到GPU执行完毕
+pub fn load_environment<P: AsRef<Path>>(
+    context: &Arc<Context>,
+    path: P,
+    resolution: u32,
+) -> Result<Environment, Box<dyn Error>> {
+    Ok(Environment::new(context, path, resolution)?)
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        String::from("未知错误")
+    }
 }
 
 fn pre_load_model<P: AsRef<Path>>(
     context: &Arc<Context>,
     path: P,
 ) -> Result<PreLoadedResource<Model, ModelStagingResources>, Box<dyn Error>> {
+    if !is_model_file(&path) {
+        return Err(format!("不支持的模型格式: {}", path.as_ref().display()).into());
+    }
+
     let device = context.device();
 
     let command_buffer = {
@@ -76,6 +174,7 @@ fn pre_load_model<P: AsRef<Path>>(
 
         unsafe { device.allocate_command_buffers(&allocate_info).unwrap()[0] }
     };
+    debug_name!(context, command_buffer, "ModelLoad.CommandBuffer");
 
     {
         let inheritance_info = vk::CommandBufferInheritanceInfo::builder().build();
@@ -112,3 +211,42 @@ enum Message {
     Load(PathBuf),
     Stop,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HELMET_GLB: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/../../assets/models/DamagedHelmet/glTF-Binary/DamagedHelmet.glb"
+    );
+
+    //测试文件写到临时目录，文件名带进程号避免并行测试冲突
+    fn temp_file(name: &str, data: &[u8]) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("fate_loader_{}_{}", std::process::id(), name));
+        std::fs::write(&path, data).unwrap();
+        path
+    }
+
+    #[test]
+    fn model_files_are_recognized_by_extension() {
+        assert!(is_model_file(HELMET_GLB));
+        //扩展名不区分大小写，文件不存在也能判断
+        assert!(is_model_file("scene.GLB"));
+        assert!(is_model_file("scene.Gltf"));
+        assert!(!is_model_file("scene.hdr"));
+        assert!(!is_model_file("missing.bin"));
+    }
+
+    #[test]
+    fn glb_without_extension_is_recognized_by_magic() {
+        let glb = std::fs::read(HELMET_GLB).unwrap();
+        let renamed = temp_file("helmet.bin", &glb);
+        let text = temp_file("notes.bin", b"not a model");
+        assert!(is_model_file(&renamed));
+        assert!(!is_model_file(&text));
+        std::fs::remove_file(renamed).unwrap();
+        std::fs::remove_file(text).unwrap();
+    }
+}
@@ -0,0 +1,76 @@
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::{collections::VecDeque, sync::Mutex};
+
+pub const LOG_CAPTURE_CAPACITY: usize = 1024;
+
+//日志面板可选的级别，从严重到详细
+pub const LOG_LEVELS: [Level; 5] = [
+    Level::Error,
+    Level::Warn,
+    Level::Info,
+    Level::Debug,
+    Level::Trace,
+];
+
+//trace每帧都会输出，面板只捕获到debug
+const CAPTURE_MAX_LEVEL: LevelFilter = LevelFilter::Debug;
+
+static LOG_CAPTURE: Mutex<VecDeque<LogRecord>> = Mutex::new(VecDeque::new());
+
+#[derive(Debug, Clone)]
+pub struct LogRecord {
+    pub level: Level,
+    pub target: String,
+    pub message: String,
+}
+
+//终端输出仍交给env_logger按RUST_LOG过滤，同时把记录写入环形缓冲供日志面板显示
+struct Logger {
+    terminal: env_logger::Logger,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= CAPTURE_MAX_LEVEL || self.terminal.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.terminal.matches(record) {
+            self.terminal.log(record);
+        }
+        if record.level() > CAPTURE_MAX_LEVEL {
+            return;
+        }
+        // 可能在任意线程触发，锁被污染时直接放弃捕获
+        let Ok(mut capture) = LOG_CAPTURE.lock() else {
+            return;
+        };
+        if capture.len() >= LOG_CAPTURE_CAPACITY {
+            capture.pop_front();
+        }
+        capture.push_back(LogRecord {
+            level: record.level(),
+            target: record.target().to_string(),
+            message: record.args().to_string(),
+        });
+    }
+
+    fn flush(&self) {
+        self.terminal.flush();
+    }
+}
+
+pub fn init() {
+    let terminal = env_logger::Builder::from_default_env().build();
+    let max_level = terminal.filter().max(CAPTURE_MAX_LEVEL);
+    log::set_boxed_logger(Box::new(Logger { terminal })).expect("日志系统初始化失败！");
+    log::set_max_level(max_level);
+}
+
+//取出自上次调用以来捕获的所有日志
+pub fn drain_log_records() -> Vec<LogRecord> {
+    LOG_CAPTURE
+        .lock()
+        .map(|mut capture| capture.drain(..).collect())
+        .unwrap_or_default()
+}
@@ -1,41 +1,238 @@
 mod camera;
+mod cli;
+mod compare;
 mod config;
 mod gui;
 mod inputsystem;
 mod loader;
+mod logger;
+mod manifest;
 mod renderer;
 
+use crate::manifest::{LoadedScene, Scene};
 use crate::{camera::*, config::Config, gui::Gui, inputsystem::*, loader::*, renderer::*};
+use gltf_loader::fscene::convert_gltf_to_fscene;
 use gltf_loader::model::Model;
-use log::LevelFilter;
-use rendering::cgmath::Vector3;
+use rendering::animation::PlaybackMode;
+use rendering::cgmath::{Point3, Vector3};
 use rendering::environment::Environment;
-use rendering::{animation::PlaybackMode};
-use std::{cell::RefCell, error::Error, path::PathBuf, rc::Rc, sync::Arc, time::Instant};
+use rendering::Aabb;
+use std::{
+    cell::RefCell,
+    error::Error,
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 use vulkan::*;
 use winit::{
     dpi::PhysicalSize,
     event::{Event, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
-    window::{Fullscreen, WindowBuilder},
+    window::{Fullscreen, Window, WindowBuilder},
 };
 
 fn main() -> Result<(), Box<dyn Error>> {
-    env_logger::init();
-    log::set_max_level(LevelFilter::Error);
+    logger::init();
     log::info!("Fate初始化开始...");
 
-    let config = Config::default();
-    let enable_debug = true;
-    let file_path = Some(PathBuf::from(
-        "assets/models/FlightHelmet/glTF/FlightHelmet.gltf",
-    ));
-    run(config, enable_debug, file_path);
+    let args = match cli::Args::parse() {
+        Ok(args) => args,
+        Err(message) => {
+            log::error!("{}", message);
+            return Err(message.into());
+        }
+    };
+
+    if args.list_devices {
+        for device in Context::enumerate_devices()? {
+            println!(
+                "[{}] {} ({:?})",
+                device.index, device.name, device.device_type
+            );
+        }
+        return Ok(());
+    }
+
+    if let Some(convert) = args.convert {
+        return convert_gltf_to_fscene(convert.input_path, convert.output_path);
+    }
+
+    let mut config = Config::load(&args.config_path);
+    config.apply_overrides(&args.overrides);
+
+    if let Some(compare) = args.compare {
+        let size = [config.resolution().width(), config.resolution().height()];
+        return run_compare(config, args.enable_debug, &args.device, compare, size);
+    }
+
+    if let Some(headless) = args.headless {
+        let size = [config.resolution().width(), config.resolution().height()];
+        return run_headless(
+            config,
+            args.enable_debug,
+            &args.device,
+            headless.model_path,
+            headless.output_path,
+            size,
+        );
+    }
+
+    run(
+        config,
+        args.config_path,
+        args.enable_debug,
+        &args.device,
+        args.model_path,
+        args.scene_path,
+    )
+}
+
+fn run_headless(
+    config: Config,
+    enable_debug: bool,
+    device: &DevicePreference,
+    path: PathBuf,
+    output: PathBuf,
+    size: [u32; 2],
+) -> Result<(), Box<dyn Error>> {
+    log::info!("无窗口渲染{}到{}", path.display(), output.display());
+    let (_, pixels) = render_headless(
+        &config,
+        config.renderer_settings(),
+        enable_debug,
+        device,
+        &path,
+        size,
+    )?;
+    image::save_buffer(&output, &pixels, size[0], size[1], image::ColorType::Rgba8)?;
+    log::info!("已保存{}", output.display());
+
+    Ok(())
+}
+
+//返回使用的相机和读回的RGBA8像素
+fn render_headless(
+    config: &Config,
+    settings: RendererSettings,
+    enable_debug: bool,
+    device: &DevicePreference,
+    path: &Path,
+    size: [u32; 2],
+) -> Result<(Camera, Vec<u8>), Box<dyn Error>> {
+    let context = Arc::new(Context::new_headless(enable_debug, device)?);
+
+    let environment = Environment::new(&context, config.env().path(), config.env().resolution())?;
+    let mut renderer =
+        Renderer::create_headless(Arc::clone(&context), config, settings, environment, size);
+
+    let model = Rc::new(RefCell::new(load_model(&context, path)?));
+    renderer.add_model(&model);
+
+    //总是自动对焦，保证同一个模型每次输出的画面一致
+    let mut camera = Camera::default();
+    let bounds = model.borrow().bounds();
+    if let Some(bounds) = bounds {
+        camera.frame(bounds, camera::FOV);
+    }
+    camera.set_scene_bounds(bounds);
+
+    renderer.set_gpu_profiling_enabled(true);
+    let (frame, pixels) = renderer.render_offscreen_frame(camera);
+    renderer.wait_idle_gpu();
+    log::info!("CPU耗时{:.2}ms", frame.cpu_time);
+    for (pass, time) in frame.gpu_timings.iter() {
+        log::info!("GPU {:?}耗时{:.3}ms", pass, time);
+    }
+
+    Ok((camera, pixels))
+}
+
+//同一个模型、环境贴图和相机分别用光栅化和fate_rt渲染，以路径追踪结果为参考计算差异
+//fate_rt只有环境光照明，glTF中的灯光和自发光不参与对比
+fn run_compare(
+    config: Config,
+    enable_debug: bool,
+    device: &DevicePreference,
+    args: cli::CompareArgs,
+    size: [u32; 2],
+) -> Result<(), Box<dyn Error>> {
+    log::info!("对比光栅化与路径追踪: {}", args.model_path.display());
+    std::fs::create_dir_all(&args.output_dir)?;
+
+    //fate_rt只支持ACES和不做色调映射
+    let mut settings = config.renderer_settings();
+    let tone_map = match settings.tone_map_mode {
+        ToneMapMode::None => fate_rt::camera::ToneMap::None,
+        ToneMapMode::Aces => fate_rt::camera::ToneMap::Aces,
+        mode => {
+            log::warn!("路径追踪不支持{:?}色调映射，两边都改用ACES", mode);
+            settings.tone_map_mode = ToneMapMode::Aces;
+            fate_rt::camera::ToneMap::Aces
+        }
+    };
+
+    let (camera, pixels) = render_headless(
+        &config,
+        settings,
+        enable_debug,
+        device,
+        &args.model_path,
+        size,
+    )?;
+    let raster = compare::rgb_from_rgba(size[0], size[1], &pixels);
+
+    let fovy = match camera.projection() {
+        Projection::Perspective { fovy } => fovy,
+        Projection::Orthographic { .. } => return Err("路径追踪只支持透视相机".into()),
+    };
+    let position = camera.position();
+    let target = camera.target();
+    let view = fate_rt::renderer::View {
+        lookfrom: Point3::new(position.x as f64, position.y as f64, position.z as f64),
+        lookat: Point3::new(target.x as f64, target.y as f64, target.z as f64),
+        vup: Vector3::new(0.0, 1.0, 0.0),
+        vfov: fovy as f64,
+        width: size[0] as usize,
+        height: size[1] as usize,
+    };
+    let path_tracer =
+        fate_rt::renderer::Renderer::new_with_settings(fate_rt::renderer::RenderSettings {
+            samples_per_pixel: args.samples_per_pixel,
+            tonemap: tone_map,
+            ..Default::default()
+        })?;
+    let start = Instant::now();
+    let traced = path_tracer.render_gltf_to_buffer(
+        &args.model_path,
+        Path::new(config.env().path()),
+        1.0,
+        &view,
+    )?;
+    log::info!("路径追踪耗时{:.2}s", start.elapsed().as_secs_f32());
+    let traced = compare::path_traced_to_raster_gamma(size[0], size[1], &traced);
+
+    let result = compare::compare(&traced, &raster);
+    raster.save(args.output_dir.join("raster.png"))?;
+    traced.save(args.output_dir.join("path_traced.png"))?;
+    result.diff.save(args.output_dir.join("diff.png"))?;
+    log::info!("MSE {:.6} SSIM {:.4}", result.mse, result.ssim);
+    println!("mse={:.6} ssim={:.4}", result.mse, result.ssim);
+    log::info!("已保存到{}", args.output_dir.display());
 
     Ok(())
 }
 
-fn run(config: Config, enable_debug: bool, path: Option<PathBuf>) {
+fn run(
+    config: Config,
+    config_path: PathBuf,
+    enable_debug: bool,
+    device: &DevicePreference,
+    path: Option<PathBuf>,
+    scene_path: Option<PathBuf>,
+) -> Result<(), Box<dyn Error>> {
     let event_loop = EventLoop::new().unwrap();
     event_loop.set_control_flow(ControlFlow::Poll);
     let window = WindowBuilder::new()
@@ -48,71 +245,297 @@ fn run(config: Config, enable_debug: bool, path: Option<PathBuf>) {
         .build(&event_loop)
         .unwrap();
 
-    let context = Arc::new(Context::new(&window, enable_debug));
-
-    let renderer_settings = RendererSettings::default();
+    let device = device.clone();
+    let renderer_settings = config.renderer_settings();
+    let mut environment_path = PathBuf::from(config.env().path());
 
-    let environment = Environment::new(&context, config.env().path(), config.env().resolution());
-    let mut gui = Gui::new(&window, renderer_settings);
-    let mut renderer = Renderer::create(
-        Arc::clone(&context),
+    let mut resources = Some(DeviceResources::create(
+        &window,
         &config,
+        enable_debug,
+        &device,
         renderer_settings,
-        environment,
-    );
+        &environment_path,
+    )?);
+    let mut gui = Gui::new(&window, renderer_settings);
+    gui.set_validation_available(enable_debug);
+    gui.set_camera_bookmarks(config.camera_bookmarks().to_vec());
 
-    let mut model: Option<Rc<RefCell<Model>>> = None;
-    let loader = Loader::new(Arc::new(context.new_thread()));
+    let mut models: Vec<Rc<RefCell<Model>>> = Vec::new();
+    //拖入文件时按住Shift则替换当前场景
+    let mut replace_scene = false;
+    //IBL预计算会阻塞，先渲染一帧显示加载状态再开始
+    let mut pending_environment: Option<(PathBuf, bool)> = None;
     if let Some(p) = path {
-        loader.load(p);
+        resources.as_ref().unwrap().loader.load(p);
+    }
+    let mut loaded_scene: Option<LoadedScene> = None;
+    if let Some(scene_path) = scene_path {
+        match Scene::load_manifest(&scene_path) {
+            Ok(scene) => {
+                for model in scene.models() {
+                    resources
+                        .as_ref()
+                        .unwrap()
+                        .loader
+                        .load(model.path().to_path_buf());
+                }
+                pending_environment = scene.environment().map(|p| (p.to_path_buf(), false));
+                loaded_scene = Some(LoadedScene::new(scene_path, scene));
+            }
+            Err(error) => {
+                log::error!("场景清单{}加载失败: {}", scene_path.display(), error);
+                gui.set_load_status(LoadStatus::Error(format!(
+                    "场景清单{}加载失败: {}",
+                    scene_path.display(),
+                    error
+                )));
+            }
+        }
     }
 
     let mut camera = Camera::default();
-    let mut input_state = InputSystem::default();
+    //配置中保存了相机位姿时，首次加载模型不自动对焦
+    let mut frame_on_load = true;
+    if let Some(camera_config) = config.camera() {
+        camera = camera_config.to_camera();
+        gui.set_camera_settings(
+            camera_config.mode(),
+            camera_config.move_speed(),
+            camera_config.clip_planes(),
+        );
+        frame_on_load = false;
+    }
+    let mut input_state = InputSystem::new(config.input_bindings());
+    gui.set_input_bindings(config.input_bindings());
     let mut time = Instant::now();
+    let mut cpu_time = 0.0;
     let mut dirty_swapchain = false;
+    let mut device_lost = false;
+    let mut device_lost_count = 0;
+    //失去焦点或被遮挡时降低帧率，最小化时停止渲染
+    let mut focused = true;
+    let mut occluded = false;
 
     log::debug!("Fate初始化完毕");
     event_loop
         .run(move |event, elwt| {
-            input_state = input_state.update(&event);
+            input_state.update(&event);
+
+            //先销毁旧设备上的所有对象，再用当前的模型和环境贴图重建
+            if device_lost {
+                device_lost = false;
+                device_lost_count += 1;
+                if device_lost_count > MAX_DEVICE_LOST_RECOVERIES {
+                    log::error!("GPU设备多次丢失，退出Fate");
+                    elwt.exit();
+                    return;
+                }
+                log::error!("GPU设备丢失，重新创建渲染设备");
+
+                let model_paths = models
+                    .iter()
+                    .map(|m| PathBuf::from(m.borrow().metadata().path()))
+                    .collect::<Vec<_>>();
+                let renderer_settings = resources.as_ref().unwrap().renderer.settings();
+                let move_speed = gui.get_camera_move_speed();
+                let camera_bookmarks = gui.camera_bookmarks().to_vec();
+
+                models.clear();
+                gui.clear_models();
+                resources = None;
+
+                let new_resources = match DeviceResources::create(
+                    &window,
+                    &config,
+                    enable_debug,
+                    &device,
+                    renderer_settings,
+                    &environment_path,
+                ) {
+                    Ok(new_resources) => new_resources,
+                    Err(error) => {
+                        log::error!("重新创建渲染设备失败，退出Fate: {}", error);
+                        elwt.exit();
+                        return;
+                    }
+                };
+                for path in model_paths {
+                    new_resources.loader.load(path);
+                }
+                resources = Some(new_resources);
+
+                //UI的字体纹理在旧设备上，需要重新上传
+                gui = Gui::new(&window, renderer_settings);
+                gui.set_camera_settings(camera.mode(), move_speed, camera.clip_planes());
+                gui.set_input_bindings(config.input_bindings());
+                gui.set_validation_available(enable_debug);
+                gui.set_camera_bookmarks(camera_bookmarks);
+                gui.set_load_status(LoadStatus::Error(
+                    "GPU设备丢失，已重新创建渲染设备并重新加载场景".to_string(),
+                ));
+                replace_scene = false;
+                pending_environment = None;
+                frame_on_load = false;
+                dirty_swapchain = false;
+            }
+
+            //设备重建失败后只等待事件循环退出
+            let Some(DeviceResources {
+                context,
+                renderer,
+                loader,
+            }) = resources.as_mut()
+            else {
+                return;
+            };
 
             match event {
                 Event::NewEvents(_) => {}
                 Event::AboutToWait => {
+                    let PhysicalSize { width, height } = window.inner_size();
+                    //最小化时交换链尺寸为0，不提交任何命令，阻塞等待窗口恢复的事件
+                    if width == 0 || height == 0 {
+                        elwt.set_control_flow(ControlFlow::Wait);
+                        //恢复后的第一帧不把最小化的时长算进动画
+                        time = Instant::now();
+                        return;
+                    }
+                    elwt.set_control_flow(ControlFlow::Poll);
+
+                    let background = !focused || occluded;
+                    let max_fps = if background {
+                        match (config.max_fps(), config.background_fps()) {
+                            (Some(max_fps), Some(background_fps)) => {
+                                Some(max_fps.min(background_fps))
+                            }
+                            (max_fps, background_fps) => max_fps.or(background_fps),
+                        }
+                    } else {
+                        config.max_fps()
+                    };
+                    if let Some(max_fps) = max_fps {
+                        wait_for_next_frame(time, max_fps);
+                    }
+
                     let new_time = Instant::now();
                     let delta_s = (new_time - time).as_secs_f64();
                     time = new_time;
+                    gui.set_frame_timings(delta_s as f32, cpu_time);
 
-                    if let Some(loaded_model) = loader.get_model() {
-                        gui.set_model_metadata(loaded_model.metadata().clone());
-                        model.take();
+                    //只在控制台打开时捕获验证层消息
+                    set_validation_capture(gui.is_validation_console_visible());
+                    gui.add_validation_messages(drain_validation_messages());
+                    gui.add_log_records(logger::drain_log_records());
+
+                    while let Some(status) = loader.get_status() {
+                        gui.set_load_status(status);
+                    }
 
+                    match pending_environment.take() {
+                        Some((path, true)) => {
+                            match load_environment(&context, &path, config.env().resolution()) {
+                                Ok(environment) => {
+                                    renderer.set_environment(environment);
+                                    environment_path = path.clone();
+                                    gui.set_load_status(LoadStatus::Done(path));
+                                }
+                                Err(error) => {
+                                    log::error!("{}载入失败，由于:{}", path.display(), error);
+                                    gui.set_load_status(LoadStatus::Error(format!(
+                                        "{}载入失败: {}",
+                                        path.display(),
+                                        error
+                                    )));
+                                }
+                            }
+                        }
+                        Some((path, false)) => pending_environment = Some((path, true)),
+                        None => {}
+                    }
+
+                    if let Some(changes) = loaded_scene.as_mut().and_then(|s| s.poll_changes()) {
+                        if changes.reload_models {
+                            context.graphics_queue_wait_idle();
+                            models.clear();
+                            renderer.clear_models();
+                            gui.clear_models();
+                            let scene = loaded_scene.as_ref().unwrap().scene();
+                            for model in scene.models() {
+                                loader.load(model.path().to_path_buf());
+                            }
+                        }
+                        if let Some(path) = changes.environment {
+                            gui.set_load_status(LoadStatus::Loading(path.clone()));
+                            pending_environment = Some((path, false));
+                        }
+                    }
+
+                    if let Some(loaded_model) = loader.get_model() {
                         context.graphics_queue_wait_idle();
+
+                        if replace_scene {
+                            models.clear();
+                            renderer.clear_models();
+                            gui.clear_models();
+                            replace_scene = false;
+                        }
+
                         let loaded_model = Rc::new(RefCell::new(loaded_model));
-                        renderer.set_model(&loaded_model);
-                        gui.set_model(&loaded_model);
-                        model = Some(loaded_model);
+                        let placed = loaded_scene
+                            .as_mut()
+                            .is_some_and(|s| s.place_model(&loaded_model));
+
+                        //新模型沿X轴摆放在已有模型旁边，清单中的模型按清单摆放
+                        if !placed {
+                            let bounds = loaded_model.borrow().bounds();
+                            if let (Some(scene_bounds), Some(bounds)) =
+                                (scene_bounds(&models), bounds)
+                            {
+                                let mut loaded_model = loaded_model.borrow_mut();
+                                let offset = scene_bounds.max().x - bounds.min().x + MODEL_SPACING;
+                                loaded_model.translate(Vector3::new(offset, 0.0, 0.0));
+                                loaded_model.update_transform();
+                            }
+                        }
+
+                        renderer.add_model(&loaded_model);
+                        gui.add_model(&loaded_model);
+                        models.push(loaded_model);
+                        camera.set_scene_bounds(scene_bounds(&models));
+
+                        if frame_on_load {
+                            if let Some(scene_bounds) = scene_bounds(&models) {
+                                camera.frame(scene_bounds, camera::FOV);
+                            }
+                        }
+                        frame_on_load = true;
                     }
 
-                    if let Some(model) = model.as_ref() {
+                    let selected_model = gui.get_selected_model();
+                    for (index, model) in models.iter().enumerate() {
                         let mut model: std::cell::RefMut<'_, Model> = model.borrow_mut();
-
-                        if input_state.is_control_w_clicked() {
-                            model.translate(Vector3::new(0.0, 0.0, -0.01));
-                            model.update_transform();
+                        if selected_model != Some(index) {
+                            model.update(delta_s as f32);
+                            continue;
                         }
-                        if input_state.is_control_s_clicked() {
-                            model.translate(Vector3::new(0.0, 0.0, 0.01));
+
+                        let model_offset = model_movement(&input_state);
+                        if model_offset != Vector3::new(0.0, 0.0, 0.0) {
+                            model.translate(model_offset);
                             model.update_transform();
                         }
 
-                        if gui.should_toggle_animation() {
+                        if gui.should_toggle_animation()
+                            || input_state.was_triggered(Action::ToggleAnimation)
+                        {
                             model.toggle_animation();
                         } else if gui.should_stop_animation() {
                             model.stop_animation();
                         } else if gui.should_reset_animation() {
                             model.reset_animation();
+                        } else if let Some(time) = gui.get_animation_scrub_time() {
+                            model.set_animation_time(time);
                         } else {
                             let playback_mode = if gui.is_infinite_animation_checked() {
                                 PlaybackMode::Loop
@@ -121,7 +544,10 @@ fn run(config: Config, enable_debug: bool, path: Option<PathBuf>) {
                             };
 
                             model.set_animation_playback_mode(playback_mode);
-                            model.set_current_animation(gui.get_selected_animation());
+                            model.blend_to_animation(
+                                gui.get_selected_animation(),
+                                gui.get_animation_fade_time(),
+                            );
                         }
                         gui.set_animation_playback_state(model.get_animation_playback_state());
 
@@ -130,33 +556,79 @@ fn run(config: Config, enable_debug: bool, path: Option<PathBuf>) {
                     }
 
                     {
-                        if gui.should_reset_camera() {
-                            camera = Default::default();
+                        if gui.should_reset_camera()
+                            || input_state.was_triggered(Action::ResetCamera)
+                        {
+                            camera = Camera::new(camera.mode());
+                            camera.set_scene_bounds(scene_bounds(&models));
+                        }
+
+                        if gui.should_frame_selected()
+                            || input_state.was_triggered(Action::FrameSelected)
+                        {
+                            if let Some(bounds) =
+                                selected_bounds(&models, selected_model, gui.get_selected_node())
+                            {
+                                camera.frame(bounds, camera::FOV);
+                            }
                         }
 
+                        if let Some(bookmark) = gui.get_camera_bookmark_jump() {
+                            bookmark.apply(&mut camera);
+                        }
+
+                        camera.set_mode(gui.get_camera_mode());
+                        camera.set_move_speed(gui.get_camera_move_speed());
+                        camera.set_projection(gui.get_camera_projection());
+                        camera.set_clip_planes(gui.get_camera_clip_planes());
+
                         if !gui.is_hovered() {
-                            camera.update(&input_state);
+                            camera.update(&input_state, delta_s as f32);
                             gui.set_camera(Some(camera));
                         }
                     }
 
+                    if input_state.was_triggered(Action::Screenshot) {
+                        renderer.request_screenshot(screenshot_path());
+                    }
+
                     if let Some(renderer_settings) = gui.get_new_renderer_settings() {
                         renderer.update_settings(renderer_settings);
                     }
 
                     if dirty_swapchain {
-                        let PhysicalSize { width, height } = window.inner_size();
-                        if width > 0 && height > 0 {
-                            renderer.recreate_swapchain(window.inner_size().into(), config.vsync());
-                        } else {
+                        if let Err(error) =
+                            renderer.recreate_swapchain(window.inner_size().into(), config.vsync())
+                        {
+                            log::error!("重建交换链失败: {}", error);
+                            device_lost = true;
                             return;
                         }
                     }
 
-                    dirty_swapchain = matches!(
-                        renderer.render(&window, camera, &mut gui),
-                        Err(RenderError::DirtySwapchain)
-                    );
+                    if let Some([x, y]) = input_state.click_position() {
+                        if !gui.is_hovered() {
+                            renderer.request_pick(x as u32, y as u32);
+                        }
+                    }
+
+                    renderer.set_gpu_profiling_enabled(gui.is_performance_overlay_visible());
+                    gui.set_culling_stats(renderer.culling_stats());
+                    gui.set_scene_stats(renderer.scene_stats());
+                    renderer
+                        .set_selected_node(gui.get_selected_model().zip(gui.get_selected_node()));
+
+                    let frame = renderer.render_frame(&window, camera, &mut gui);
+                    gui.set_gpu_timings(&frame.gpu_timings);
+                    match frame.result {
+                        Ok(()) => dirty_swapchain = false,
+                        Err(RenderError::DirtySwapchain) => dirty_swapchain = true,
+                        Err(RenderError::DeviceLost) => device_lost = true,
+                    }
+                    if let Some(picked) = renderer.take_picked_node() {
+                        gui.set_picked_node(picked);
+                    }
+                    cpu_time = new_time.elapsed().as_secs_f32();
                 }
 
                 Event::WindowEvent { event, .. } => {
@@ -164,7 +636,15 @@ fn run(config: Config, enable_debug: bool, path: Option<PathBuf>) {
                     match event {
                         WindowEvent::DroppedFile(path) => {
                             log::debug!("已拖入文件{:?}", path);
-                            loader.load(path);
+                            if is_environment_file(&path) {
+                                gui.set_load_status(LoadStatus::Loading(path.clone()));
+                                pending_environment = Some((path, false));
+                            } else {
+                                if input_state.is_shift_pressed() {
+                                    replace_scene = true;
+                                }
+                                loader.load(path);
+                            }
                         }
 
                         WindowEvent::Resized(new_size) => {
@@ -172,6 +652,14 @@ fn run(config: Config, enable_debug: bool, path: Option<PathBuf>) {
                             dirty_swapchain = true;
                         }
 
+                        WindowEvent::Focused(is_focused) => {
+                            focused = is_focused;
+                        }
+
+                        WindowEvent::Occluded(is_occluded) => {
+                            occluded = is_occluded;
+                        }
+
                         WindowEvent::CloseRequested => {
                             elwt.exit();
                         }
@@ -181,9 +669,171 @@ fn run(config: Config, enable_debug: bool, path: Option<PathBuf>) {
                 Event::LoopExiting => {
                     log::info!("退出Fate");
                     renderer.wait_idle_gpu();
+
+                    //重新读取配置文件，命令行覆盖的参数不写回
+                    let mut saved_config = Config::load(&config_path);
+                    saved_config.set_camera(&camera, gui.get_camera_move_speed());
+                    saved_config.set_camera_bookmarks(gui.camera_bookmarks().to_vec());
+                    saved_config.set_renderer_settings(renderer.settings());
+                    if let Err(err) = saved_config.save(&config_path) {
+                        log::error!("保存配置文件失败: {}", err);
+                    }
                 }
                 _ => (),
             }
         })
         .unwrap();
+    Ok(())
+}
+
+//依赖vulkan设备的对象，设备丢失时整体销毁后重建
+struct DeviceResources {
+    renderer: Renderer,
+    loader: Loader,
+    context: Arc<Context>,
+}
+
+impl DeviceResources {
+    fn create(
+        window: &Window,
+        config: &Config,
+        enable_debug: bool,
+        device: &DevicePreference,
+        renderer_settings: RendererSettings,
+        environment_path: &Path,
+    ) -> Result<Self, Box<dyn Error>> {
+        let context = Arc::new(Context::new(window, enable_debug, device)?);
+        let environment = Environment::new(&context, environment_path, config.env().resolution())?;
+        let renderer =
+            Renderer::create(Arc::clone(&context), config, renderer_settings, environment)?;
+        let loader = Loader::new(Arc::new(context.new_thread()?));
+
+        Ok(Self {
+            renderer,
+            loader,
+            context,
+        })
+    }
+}
+
+//累计丢失超过这个次数时不再重建，避免驱动异常时反复重启
+const MAX_DEVICE_LOST_RECOVERIES: u32 = 3;
+
+//sleep的精度不够，最后一段时间自旋等待
+const FRAME_SPIN_DURATION: Duration = Duration::from_millis(1);
+const MODEL_SPACING: f32 = 1.0;
+const MODEL_MOVE_STEP: f32 = 0.01;
+
+//从上一帧开始计时，等待到目标帧间隔
+fn wait_for_next_frame(frame_start: Instant, max_fps: u32) {
+    let deadline = frame_start + Duration::from_secs_f64(1.0 / max_fps as f64);
+    loop {
+        let now = Instant::now();
+        if now >= deadline {
+            break;
+        }
+        let remaining = deadline - now;
+        if remaining > FRAME_SPIN_DURATION {
+            thread::sleep(remaining - FRAME_SPIN_DURATION);
+        } else {
+            std::hint::spin_loop();
+        }
+    }
+}
+
+//保存在工作目录，以毫秒时间戳命名，连续截图不会覆盖
+fn screenshot_path() -> PathBuf {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_millis());
+    PathBuf::from(format!("screenshot_{}.png", millis))
+}
+
+fn model_movement(input: &InputSystem) -> Vector3<f32> {
+    let mut offset = Vector3::new(0.0, 0.0, 0.0);
+    if input.is_active(Action::ModelForward) {
+        offset.z -= MODEL_MOVE_STEP;
+    }
+    if input.is_active(Action::ModelBackward) {
+        offset.z += MODEL_MOVE_STEP;
+    }
+    if input.is_active(Action::ModelLeft) {
+        offset.x -= MODEL_MOVE_STEP;
+    }
+    if input.is_active(Action::ModelRight) {
+        offset.x += MODEL_MOVE_STEP;
+    }
+    offset
+}
+
+//选中了节点时聚焦到节点，否则聚焦到选中的模型
+fn selected_bounds(
+    models: &[Rc<RefCell<Model>>],
+    selected_model: Option<usize>,
+    selected_node: Option<usize>,
+) -> Option<Aabb<f32>> {
+    let model = models.get(selected_model?)?.borrow();
+    selected_node
+        .and_then(|index| model.node_bounds(index))
+        .or_else(|| model.bounds())
+}
+
+fn scene_bounds(models: &[Rc<RefCell<Model>>]) -> Option<Aabb<f32>> {
+    let aabbs = models
+        .iter()
+        .filter_map(|m| m.borrow().bounds())
+        .collect::<Vec<_>>();
+    Aabb::union(&aabbs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigOverrides;
+
+    const HELMET_GLB: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/../../assets/models/DamagedHelmet/glTF-Binary/DamagedHelmet.glb"
+    );
+
+    //需要Vulkan设备，用cargo test -p fate_renderer -- --ignored运行
+    #[test]
+    #[ignore = "需要Vulkan设备"]
+    fn depth_prepass_renders_identical_pixels() {
+        //着色器和天空盒按工作区根目录的相对路径加载
+        std::env::set_current_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/../..")).unwrap();
+
+        let size = [256, 256];
+        let mut config = Config::default();
+        config.apply_overrides(&ConfigOverrides {
+            resolution: Some(size),
+            env_resolution: Some(256),
+            ..Default::default()
+        });
+
+        let render = |depth_prepass_enabled| {
+            let settings = RendererSettings {
+                depth_prepass_enabled,
+                ..config.renderer_settings()
+            };
+            let device = DevicePreference::default();
+            let (_, pixels) = render_headless(
+                &config,
+                settings,
+                false,
+                &device,
+                Path::new(HELMET_GLB),
+                size,
+            )
+            .unwrap();
+            pixels
+        };
+
+        let without = render(false);
+        let with = render(true);
+        assert_eq!(without.len(), (size[0] * size[1] * 4) as usize);
+        //深度相等测试要求两个pipeline的深度逐位一致，任何像素差异都说明预pass有问题
+        let different = without.iter().zip(&with).filter(|(a, b)| a != b).count();
+        assert_eq!(different, 0);
+    }
 }
@@ -0,0 +1,329 @@
+use gltf_loader::model::Model;
+use rendering::cgmath::Vector3;
+use rendering::light::{Light, MAX_COLOR_TEMPERATURE, MIN_COLOR_TEMPERATURE};
+use serde::Deserialize;
+use std::{
+    cell::RefCell,
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    rc::{Rc, Weak},
+    time::{Duration, Instant, SystemTime},
+};
+
+//检查清单文件是否被修改的间隔
+const MANIFEST_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+//场景清单，一次加载多个glTF并指定各自的摆放、灯光参数和环境贴图
+//扩展名为json时按JSON解析，否则按TOML解析，示例见assets/scenes/example.toml
+//清单中的相对路径按清单所在目录解析
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Scene {
+    environment: Option<PathBuf>,
+    models: Vec<SceneModel>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct SceneModel {
+    path: PathBuf,
+    //相对于加载时自动居中的位置的偏移
+    position: [f32; 3],
+    //欧拉角，单位为度
+    rotation: [f32; 3],
+    //在自动缩放到统一大小的基础上再缩放
+    scale: f32,
+    lights: Vec<LightOverride>,
+}
+
+impl Default for SceneModel {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::new(),
+            position: [0.0; 3],
+            rotation: [0.0; 3],
+            scale: 1.0,
+            lights: Vec::new(),
+        }
+    }
+}
+
+//没给出的项保持glTF中的值
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+pub struct LightOverride {
+    //KHR_lights_punctual中的灯光序号
+    index: usize,
+    color: Option<[f32; 3]>,
+    //单位为开尔文，同时给出color时以色温为准
+    temperature: Option<f32>,
+    intensity: Option<f32>,
+    range: Option<f32>,
+}
+
+impl Scene {
+    pub fn load_manifest<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)
+            .map_err(|err| format!("无法读取场景清单{}: {}", path.display(), err))?;
+
+        let is_json = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("json"));
+        let mut scene: Scene = if is_json {
+            serde_json::from_str(&content)?
+        } else {
+            toml::from_str(&content)?
+        };
+        scene.validate()?;
+
+        let base = path.parent().unwrap_or_else(|| Path::new(""));
+        if let Some(environment) = scene.environment.as_mut() {
+            *environment = base.join(&*environment);
+        }
+        for model in scene.models.iter_mut() {
+            model.path = base.join(&model.path);
+        }
+
+        Ok(scene)
+    }
+
+    fn validate(&self) -> Result<(), Box<dyn Error>> {
+        for (index, model) in self.models.iter().enumerate() {
+            if model.path.as_os_str().is_empty() {
+                return Err(format!("第{}个模型没有给出路径", index).into());
+            }
+            let finite = model.position.iter().all(|v| v.is_finite())
+                && model.rotation.iter().all(|v| v.is_finite());
+            if !finite {
+                return Err(format!("{}的变换非法", model.path.display()).into());
+            }
+            if !model.scale.is_finite() || model.scale <= 0.0 {
+                return Err(format!("{}的缩放非法: {}", model.path.display(), model.scale).into());
+            }
+            for light in model.lights.iter() {
+                light.validate().map_err(|err| {
+                    format!("{}的灯光{}: {}", model.path.display(), light.index, err)
+                })?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn environment(&self) -> Option<&Path> {
+        self.environment.as_deref()
+    }
+
+    pub fn models(&self) -> &[SceneModel] {
+        &self.models
+    }
+
+    //模型列表相同时只需原地更新摆放和灯光
+    fn has_same_models(&self, other: &Scene) -> bool {
+        self.models.len() == other.models.len()
+            && self
+                .models
+                .iter()
+                .zip(other.models.iter())
+                .all(|(a, b)| a.path == b.path)
+    }
+}
+
+impl SceneModel {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn apply(&self, model: &mut Model, base: &ModelBase) {
+        model.set_position(base.position * self.scale + Vector3::from(self.position));
+        model.set_rotation(Vector3::from(self.rotation));
+        model.set_scale(base.scale * self.scale);
+        model.update_transform();
+
+        //先恢复glTF中的参数，清单中删掉的覆盖项才能生效
+        let lights = model.lights_mut();
+        lights.copy_from_slice(&base.lights);
+        for light_override in self.lights.iter() {
+            match lights.get_mut(light_override.index) {
+                Some(light) => light_override.apply(light),
+                None => log::warn!(
+                    "{}没有序号为{}的灯光",
+                    self.path.display(),
+                    light_override.index
+                ),
+            }
+        }
+    }
+}
+
+impl LightOverride {
+    fn validate(&self) -> Result<(), String> {
+        if let Some(color) = self.color {
+            if !color.iter().all(|c| c.is_finite() && *c >= 0.0) {
+                return Err(format!("非法颜色{:?}", color));
+            }
+        }
+        if let Some(temperature) = self.temperature {
+            if !(MIN_COLOR_TEMPERATURE..=MAX_COLOR_TEMPERATURE).contains(&temperature) {
+                return Err(format!("非法色温{}", temperature));
+            }
+        }
+        if let Some(intensity) = self.intensity {
+            if !intensity.is_finite() || intensity < 0.0 {
+                return Err(format!("非法强度{}", intensity));
+            }
+        }
+        if let Some(range) = self.range {
+            if !range.is_finite() || range <= 0.0 {
+                return Err(format!("非法范围{}", range));
+            }
+        }
+        Ok(())
+    }
+
+    fn apply(&self, light: &mut Light) {
+        if let Some(color) = self.color {
+            light.set_color(color);
+        }
+        if let Some(temperature) = self.temperature {
+            light.set_temperature(temperature);
+        }
+        if let Some(intensity) = self.intensity {
+            light.set_intensity(intensity);
+        }
+        if let Some(range) = self.range {
+            light.set_range(Some(range));
+        }
+    }
+}
+
+//模型刚加载时的自动摆放和灯光参数，每次应用清单都以此为基准
+struct ModelBase {
+    position: Vector3<f32>,
+    scale: Vector3<f32>,
+    lights: Vec<Light>,
+}
+
+impl ModelBase {
+    fn capture(model: &Model) -> Self {
+        let transform = model.transform();
+        Self {
+            position: transform.position(),
+            scale: transform.scale(),
+            lights: model.lights().to_vec(),
+        }
+    }
+}
+
+//清单重新加载后需要在主循环中处理的变化
+pub struct SceneChanges {
+    //模型列表变化时清空场景后按清单重新加载
+    pub reload_models: bool,
+    pub environment: Option<PathBuf>,
+}
+
+//通过清单加载的场景，记录清单中每一项对应的模型，清单文件修改后自动重新应用
+pub struct LoadedScene {
+    path: PathBuf,
+    scene: Scene,
+    modified: Option<SystemTime>,
+    last_poll: Instant,
+    models: Vec<Option<(Weak<RefCell<Model>>, ModelBase)>>,
+}
+
+impl LoadedScene {
+    pub fn new(path: PathBuf, scene: Scene) -> Self {
+        let modified = modified_time(&path);
+        let models = scene.models.iter().map(|_| None).collect();
+        Self {
+            path,
+            scene,
+            modified,
+            last_poll: Instant::now(),
+            models,
+        }
+    }
+
+    pub fn scene(&self) -> &Scene {
+        &self.scene
+    }
+
+    //加载线程送回的模型属于清单时按清单摆放，返回false表示不是清单中的模型
+    //同一个文件出现多次时按顺序填入还没有模型的项
+    pub fn place_model(&mut self, model: &Rc<RefCell<Model>>) -> bool {
+        let path = PathBuf::from(model.borrow().metadata().path());
+        let slot = self
+            .scene
+            .models
+            .iter()
+            .zip(self.models.iter())
+            .position(|(entry, slot)| {
+                entry.path == path
+                    && slot
+                        .as_ref()
+                        .is_none_or(|(loaded, _)| loaded.strong_count() == 0)
+            });
+        let Some(index) = slot else {
+            return false;
+        };
+
+        let base = ModelBase::capture(&model.borrow());
+        self.scene.models[index].apply(&mut model.borrow_mut(), &base);
+        self.models[index] = Some((Rc::downgrade(model), base));
+        true
+    }
+
+    //清单文件的修改时间变化时重新解析并应用，模型列表不变时原地更新
+    //解析失败时保留当前场景，直到文件再次被修改
+    pub fn poll_changes(&mut self) -> Option<SceneChanges> {
+        if self.last_poll.elapsed() < MANIFEST_POLL_INTERVAL {
+            return None;
+        }
+        self.last_poll = Instant::now();
+
+        let modified = modified_time(&self.path);
+        if modified == self.modified {
+            return None;
+        }
+        self.modified = modified;
+
+        let scene = match Scene::load_manifest(&self.path) {
+            Ok(scene) => scene,
+            Err(error) => {
+                log::error!("场景清单{}重新加载失败: {}", self.path.display(), error);
+                return None;
+            }
+        };
+        log::info!("场景清单{}已修改，重新加载", self.path.display());
+
+        let environment = (scene.environment != self.scene.environment)
+            .then(|| scene.environment.clone())
+            .flatten();
+        let reload_models = !scene.has_same_models(&self.scene);
+        if reload_models {
+            self.models = scene.models.iter().map(|_| None).collect();
+        } else {
+            for (entry, slot) in scene.models.iter().zip(self.models.iter()) {
+                let Some((model, base)) = slot else {
+                    continue;
+                };
+                if let Some(model) = model.upgrade() {
+                    entry.apply(&mut model.borrow_mut(), base);
+                }
+            }
+        }
+        self.scene = scene;
+
+        Some(SceneChanges {
+            reload_models,
+            environment,
+        })
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
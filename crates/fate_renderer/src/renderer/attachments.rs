@@ -3,23 +3,44 @@ use std::{ffi::CString, sync::Arc};
 use vulkan::{ash::vk, Context, Image, ImageParameters, Texture};
 
 pub const GBUFFER_NORMALS_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+pub const GBUFFER_PICK_ID_FORMAT: vk::Format = vk::Format::R32_UINT;
+pub const GBUFFER_MOTION_FORMAT: vk::Format = vk::Format::R16G16_SFLOAT;
 pub const AO_MAP_FORMAT: vk::Format = vk::Format::R8_UNORM;
 pub const SCENE_COLOR_FORMAT: vk::Format = vk::Format::R32G32B32A32_SFLOAT;
 pub const SHADOW_CASTER_COLOR_FORMAT: vk::Format = vk::Format::R32_SFLOAT;
 pub const BLOOM_FORMAT: vk::Format = vk::Format::B10G11R11_UFLOAT_PACK32;
 pub const BLOOM_MIP_LEVELS: u32 = 5;
+pub const MAX_SHADOW_CASCADES: u32 = 4;
+//点光源占六层(cube的六个面)，聚光灯占一层
+pub const MAX_PUNCTUAL_SHADOW_LAYERS: u32 = 12;
+//点光源和聚光灯的shadow map分辨率固定，与主光源的设置无关
+pub const PUNCTUAL_SHADOW_MAP_SIZE: u32 = 1024;
 
 pub struct Attachments {
     pub gbuffer_normals: Texture,
+    //鼠标拾取用的节点ID，0表示背景
+    pub gbuffer_pick_id: Texture,
+    //当前帧与上一帧的NDC坐标之差，供TAA和运动模糊使用
+    pub gbuffer_motion: Texture,
     pub gbuffer_depth: Texture,
+    //layer view要先于image销毁
+    pub shadow_caster_layers: ShadowCasterLayers,
     pub shadow_caster_color: Texture,
     pub shadow_caster_depth: Texture,
+    pub punctual_shadow_layers: ShadowCasterLayers,
+    pub punctual_shadow_color: Texture,
+    pub punctual_shadow_depth: Texture,
     pub ssao: Texture,
+    //双边模糊水平方向的结果
+    pub ssao_blur_temp: Texture,
     pub ssao_blur: Texture,
     pub scene_color: Texture,
     pub scene_depth: Texture,
-    pub fxaa: Texture,
+    //tone map后的结果，作为FXAA的输入
+    pub ldr_color: Texture,
     pub scene_resolve: Option<Texture>,
+    //MSAA时场景深度resolve后的单采样结果，供屏幕空间的pass采样
+    pub scene_depth_resolve: Option<Texture>,
     pub bloom: BloomAttachment,
 }
 
@@ -35,77 +56,303 @@ impl Drop for BloomAttachment {
     fn drop(&mut self) {
         unsafe {
             self.context.device().destroy_sampler(self.sampler, None);
-            self.mips_views
-                .iter()
-                .for_each(|v| self.context.device().destroy_image_view(*v, None));
         }
+        self.mips_views
+            .iter()
+            .for_each(|v| self.context.destroy_image_view(*v));
     }
 }
 
+//shadow map每个级联(或点光源、聚光灯的每个面)一层，渲染时用单层的view
+pub struct ShadowCasterLayers {
+    context: Arc<Context>,
+    pub color_views: Vec<vk::ImageView>,
+    pub depth_views: Vec<vk::ImageView>,
+}
+
+impl Drop for ShadowCasterLayers {
+    fn drop(&mut self) {
+        self.color_views
+            .iter()
+            .chain(self.depth_views.iter())
+            .for_each(|v| self.context.destroy_image_view(*v));
+    }
+}
+
+//光照pass采样的两张shadow map，关闭阴影时都换成占位贴图
+#[derive(Clone, Copy)]
+pub struct ShadowMaps<'a> {
+    pub cascades: &'a Texture,
+    pub punctual: &'a Texture,
+}
+
 impl Attachments {
     pub fn new(
         context: &Arc<Context>,
         extent: vk::Extent2D,
         depth_format: vk::Format,
         msaa_samples: vk::SampleCountFlags,
+        shadow_map_size: u32,
+        output_format: vk::Format,
     ) -> Self {
         let gbuffer_normals = create_gbuffer_normals(context, extent);
+        let gbuffer_pick_id = create_gbuffer_pick_id(context, extent);
+        let gbuffer_motion = create_gbuffer_motion(context, extent);
         let gbuffer_depth = create_gbuffer_depth(context, depth_format, extent);
         let ssao = create_ssao(context, extent);
-        let ssao_blur = create_ssao_blur(context, extent);
-        let shadow_caster_color = create_shadow_caster_depth(context, extent);
-        let shadow_caster_depth = create_scene_depth(context, depth_format, extent, msaa_samples);
+        let ssao_blur_temp = create_ssao_blur(context, extent, "SSAO Blur Temp Texture");
+        let ssao_blur = create_ssao_blur(context, extent, "SSAO Blur Texture");
+        let (shadow_caster_color, shadow_caster_depth, shadow_caster_layers) = create_shadow_caster(
+            context,
+            depth_format,
+            shadow_map_size,
+            MAX_SHADOW_CASCADES,
+            "ShadowCaster",
+        );
+        let (punctual_shadow_color, punctual_shadow_depth, punctual_shadow_layers) =
+            create_shadow_caster(
+                context,
+                depth_format,
+                PUNCTUAL_SHADOW_MAP_SIZE,
+                MAX_PUNCTUAL_SHADOW_LAYERS,
+                "PunctualShadow",
+            );
         let scene_color = create_scene_color(context, extent, msaa_samples);
         let scene_depth = create_scene_depth(context, depth_format, extent, msaa_samples);
         let scene_resolve = match msaa_samples {
             vk::SampleCountFlags::TYPE_1 => None,
             _ => Some(create_scene_resolve(context, extent)),
         };
-        let fxaa = create_fxaa_texture(context, extent);
+        let scene_depth_resolve = match msaa_samples {
+            vk::SampleCountFlags::TYPE_1 => None,
+            _ => Some(create_scene_depth_resolve(context, depth_format, extent)),
+        };
+        let ldr_color = create_ldr_color(context, extent, output_format);
         let bloom = create_bloom(context, extent);
 
         Self {
             gbuffer_normals,
+            gbuffer_pick_id,
+            gbuffer_motion,
             gbuffer_depth,
             shadow_caster_color,
             shadow_caster_depth,
+            shadow_caster_layers,
+            punctual_shadow_color,
+            punctual_shadow_depth,
+            punctual_shadow_layers,
             ssao,
+            ssao_blur_temp,
             ssao_blur,
             scene_color,
             scene_depth,
-            fxaa,
+            ldr_color,
             scene_resolve,
+            scene_depth_resolve,
             bloom,
         }
     }
 }
 
 impl Attachments {
+    //关闭模糊时光照pass直接采样原始AO
+    pub fn get_ao_map(&self, blurred: bool) -> &Texture {
+        if blurred {
+            &self.ssao_blur
+        } else {
+            &self.ssao
+        }
+    }
+
+    pub fn get_shadow_maps(&self) -> ShadowMaps {
+        ShadowMaps {
+            cascades: &self.shadow_caster_color,
+            punctual: &self.punctual_shadow_color,
+        }
+    }
+
     pub fn get_scene_resolved_color(&self) -> &Texture {
         self.scene_resolve.as_ref().unwrap_or(&self.scene_color)
     }
+
+    pub fn get_scene_resolved_depth(&self) -> &Texture {
+        self.scene_depth_resolve
+            .as_ref()
+            .unwrap_or(&self.scene_depth)
+    }
+
+    //窗口尺寸变化时只重建屏幕大小的attachment，shadow map与分辨率无关
+    pub fn resize(
+        &mut self,
+        context: &Arc<Context>,
+        extent: vk::Extent2D,
+        depth_format: vk::Format,
+        msaa_samples: vk::SampleCountFlags,
+        output_format: vk::Format,
+    ) {
+        self.gbuffer_normals = create_gbuffer_normals(context, extent);
+        self.gbuffer_pick_id = create_gbuffer_pick_id(context, extent);
+        self.gbuffer_motion = create_gbuffer_motion(context, extent);
+        self.gbuffer_depth = create_gbuffer_depth(context, depth_format, extent);
+        self.ssao = create_ssao(context, extent);
+        self.ssao_blur_temp = create_ssao_blur(context, extent, "SSAO Blur Temp Texture");
+        self.ssao_blur = create_ssao_blur(context, extent, "SSAO Blur Texture");
+        self.scene_color = create_scene_color(context, extent, msaa_samples);
+        self.scene_depth = create_scene_depth(context, depth_format, extent, msaa_samples);
+        self.scene_resolve = match msaa_samples {
+            vk::SampleCountFlags::TYPE_1 => None,
+            _ => Some(create_scene_resolve(context, extent)),
+        };
+        self.scene_depth_resolve = match msaa_samples {
+            vk::SampleCountFlags::TYPE_1 => None,
+            _ => Some(create_scene_depth_resolve(context, depth_format, extent)),
+        };
+        self.ldr_color = create_ldr_color(context, extent, output_format);
+        self.bloom = create_bloom(context, extent);
+    }
+
+    //只重建shadow map，其他attachment不受影响
+    pub fn set_shadow_map_size(
+        &mut self,
+        context: &Arc<Context>,
+        depth_format: vk::Format,
+        shadow_map_size: u32,
+    ) {
+        let (color, depth, layers) = create_shadow_caster(
+            context,
+            depth_format,
+            shadow_map_size,
+            MAX_SHADOW_CASCADES,
+            "ShadowCaster",
+        );
+        self.shadow_caster_layers = layers;
+        self.shadow_caster_color = color;
+        self.shadow_caster_depth = depth;
+    }
 }
 
-fn create_shadow_caster_depth(context: &Arc<Context>, extent: vk::Extent2D) -> Texture {
+fn create_shadow_caster(
+    context: &Arc<Context>,
+    depth_format: vk::Format,
+    size: u32,
+    layers: u32,
+    name: &str,
+) -> (Texture, Texture, ShadowCasterLayers) {
+    let extent = vk::Extent2D {
+        width: size,
+        height: size,
+    };
+    let color = create_shadow_caster_color(context, extent, layers, name);
+    let depth = create_shadow_caster_depth(context, depth_format, extent, layers, name);
+    let layers = ShadowCasterLayers {
+        context: Arc::clone(context),
+        color_views: color.image.create_layers_views(vk::ImageAspectFlags::COLOR),
+        depth_views: depth.image.create_layers_views(vk::ImageAspectFlags::DEPTH),
+    };
+    (color, depth, layers)
+}
+
+fn create_shadow_caster_color(
+    context: &Arc<Context>,
+    extent: vk::Extent2D,
+    layers: u32,
+    name: &str,
+) -> Texture {
     let image = Image::create(
         Arc::clone(context),
         ImageParameters {
             mem_properties: vk::MemoryPropertyFlags::DEVICE_LOCAL,
             extent,
+            layers,
             sample_count: vk::SampleCountFlags::TYPE_1,
             format: SHADOW_CASTER_COLOR_FORMAT,
             usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
             ..Default::default()
         },
-        CString::new("ShadowCaster Depth Texture").unwrap(),
-    );
+        CString::new(format!("{} Color Texture", name)).unwrap(),
+    )
+    .expect("创建attachment image失败！");
 
     image.transition_image_layout(
         vk::ImageLayout::UNDEFINED,
         vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
     );
 
-    let view = image.create_view(vk::ImageViewType::TYPE_2D, vk::ImageAspectFlags::COLOR);
+    let view = image.create_view(
+        vk::ImageViewType::TYPE_2D_ARRAY,
+        vk::ImageAspectFlags::COLOR,
+    );
+    let sampler = Some(create_sampler(
+        context,
+        vk::Filter::NEAREST,
+        vk::Filter::NEAREST,
+    ));
+
+    Texture::new(Arc::clone(context), image, view, sampler)
+}
+
+fn create_shadow_caster_depth(
+    context: &Arc<Context>,
+    format: vk::Format,
+    extent: vk::Extent2D,
+    layers: u32,
+    name: &str,
+) -> Texture {
+    let image = Image::create(
+        Arc::clone(context),
+        ImageParameters {
+            mem_properties: vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            extent,
+            layers,
+            sample_count: vk::SampleCountFlags::TYPE_1,
+            format,
+            usage: vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            ..Default::default()
+        },
+        CString::new(format!("{} Depth Texture", name)).unwrap(),
+    )
+    .expect("创建attachment image失败！");
+
+    image.transition_image_layout(
+        vk::ImageLayout::UNDEFINED,
+        vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+    );
+
+    let view = image.create_view(
+        vk::ImageViewType::TYPE_2D_ARRAY,
+        vk::ImageAspectFlags::DEPTH,
+    );
+
+    Texture::new(Arc::clone(context), image, view, None)
+}
+
+//关闭阴影时绑定的占位shadow map，shader里不会去采样
+pub fn create_dummy_shadow_map(context: &Arc<Context>) -> Texture {
+    let image = Image::create(
+        Arc::clone(context),
+        ImageParameters {
+            mem_properties: vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            extent: vk::Extent2D {
+                width: 1,
+                height: 1,
+            },
+            format: SHADOW_CASTER_COLOR_FORMAT,
+            usage: vk::ImageUsageFlags::SAMPLED,
+            ..Default::default()
+        },
+        CString::new("Dummy Shadow Map Texture").unwrap(),
+    )
+    .expect("创建attachment image失败！");
+
+    image.transition_image_layout(
+        vk::ImageLayout::UNDEFINED,
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+    );
+
+    let view = image.create_view(
+        vk::ImageViewType::TYPE_2D_ARRAY,
+        vk::ImageAspectFlags::COLOR,
+    );
     let sampler = Some(create_sampler(
         context,
         vk::Filter::NEAREST,
@@ -127,8 +374,73 @@ fn create_gbuffer_normals(context: &Arc<Context>, extent: vk::Extent2D) -> Textu
             ..Default::default()
         },
         CString::new("GBuffer Normals Texture").unwrap(),
+    )
+    .expect("创建attachment image失败！");
+
+    image.transition_image_layout(
+        vk::ImageLayout::UNDEFINED,
+        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
     );
 
+    let view = image.create_view(vk::ImageViewType::TYPE_2D, vk::ImageAspectFlags::COLOR);
+    let sampler = Some(create_sampler(
+        context,
+        vk::Filter::NEAREST,
+        vk::Filter::NEAREST,
+    ));
+
+    Texture::new(Arc::clone(context), image, view, sampler)
+}
+
+fn create_gbuffer_pick_id(context: &Arc<Context>, extent: vk::Extent2D) -> Texture {
+    let image = Image::create(
+        Arc::clone(context),
+        ImageParameters {
+            mem_properties: vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            extent,
+            sample_count: vk::SampleCountFlags::TYPE_1,
+            format: GBUFFER_PICK_ID_FORMAT,
+            usage: vk::ImageUsageFlags::COLOR_ATTACHMENT
+                | vk::ImageUsageFlags::SAMPLED
+                | vk::ImageUsageFlags::TRANSFER_SRC,
+            ..Default::default()
+        },
+        CString::new("GBuffer PickId Texture").unwrap(),
+    )
+    .expect("创建attachment image失败！");
+
+    image.transition_image_layout(
+        vk::ImageLayout::UNDEFINED,
+        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+    );
+
+    let view = image.create_view(vk::ImageViewType::TYPE_2D, vk::ImageAspectFlags::COLOR);
+
+    //描边时按整数ID读取，不能插值
+    let sampler = Some(create_sampler(
+        context,
+        vk::Filter::NEAREST,
+        vk::Filter::NEAREST,
+    ));
+
+    Texture::new(Arc::clone(context), image, view, sampler)
+}
+
+fn create_gbuffer_motion(context: &Arc<Context>, extent: vk::Extent2D) -> Texture {
+    let image = Image::create(
+        Arc::clone(context),
+        ImageParameters {
+            mem_properties: vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            extent,
+            sample_count: vk::SampleCountFlags::TYPE_1,
+            format: GBUFFER_MOTION_FORMAT,
+            usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+            ..Default::default()
+        },
+        CString::new("GBuffer Motion Texture").unwrap(),
+    )
+    .expect("创建attachment image失败！");
+
     image.transition_image_layout(
         vk::ImageLayout::UNDEFINED,
         vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
@@ -160,7 +472,8 @@ fn create_gbuffer_depth(
             ..Default::default()
         },
         CString::new("GBuffer Depth Texture").unwrap(),
-    );
+    )
+    .expect("创建attachment image失败！");
 
     image.transition_image_layout(
         vk::ImageLayout::UNDEFINED,
@@ -190,7 +503,8 @@ fn create_ssao(context: &Arc<Context>, extent: vk::Extent2D) -> Texture {
             ..Default::default()
         },
         CString::new("SSAO Texture").unwrap(),
-    );
+    )
+    .expect("创建attachment image失败！");
 
     image.transition_image_layout(
         vk::ImageLayout::UNDEFINED,
@@ -207,7 +521,7 @@ fn create_ssao(context: &Arc<Context>, extent: vk::Extent2D) -> Texture {
     Texture::new(Arc::clone(context), image, view, sampler)
 }
 
-fn create_ssao_blur(context: &Arc<Context>, extent: vk::Extent2D) -> Texture {
+fn create_ssao_blur(context: &Arc<Context>, extent: vk::Extent2D, name: &str) -> Texture {
     let image = Image::create(
         Arc::clone(context),
         ImageParameters {
@@ -218,8 +532,9 @@ fn create_ssao_blur(context: &Arc<Context>, extent: vk::Extent2D) -> Texture {
             usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
             ..Default::default()
         },
-        CString::new("SSAO Blur Texture").unwrap(),
-    );
+        CString::new(name).unwrap(),
+    )
+    .expect("创建attachment image失败！");
 
     image.transition_image_layout(
         vk::ImageLayout::UNDEFINED,
@@ -264,7 +579,8 @@ fn create_scene_color(
             ..Default::default()
         },
         CString::new("Scene Color Texture").unwrap(),
-    );
+    )
+    .expect("创建attachment image失败！");
 
     image.transition_image_layout(
         vk::ImageLayout::UNDEFINED,
@@ -273,11 +589,12 @@ fn create_scene_color(
 
     let view = image.create_view(vk::ImageViewType::TYPE_2D, vk::ImageAspectFlags::COLOR);
 
+    //缩放渲染时final pass直接把场景颜色放大到交换链，需要双线性过滤
     let sampler = match msaa_samples {
         vk::SampleCountFlags::TYPE_1 => Some(create_sampler(
             context,
-            vk::Filter::NEAREST,
-            vk::Filter::NEAREST,
+            vk::Filter::LINEAR,
+            vk::Filter::LINEAR,
         )),
         _ => None,
     };
@@ -292,7 +609,9 @@ fn create_scene_depth(
     msaa_samples: vk::SampleCountFlags,
 ) -> Texture {
     let image_usage = match msaa_samples {
-        vk::SampleCountFlags::TYPE_1 => vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+        vk::SampleCountFlags::TYPE_1 => {
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED
+        }
         _ => {
             vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT
                 | vk::ImageUsageFlags::TRANSIENT_ATTACHMENT
@@ -309,7 +628,8 @@ fn create_scene_depth(
             ..Default::default()
         },
         CString::new("Scene Depth Texture").unwrap(),
-    );
+    )
+    .expect("创建attachment image失败！");
 
     image.transition_image_layout(
         vk::ImageLayout::UNDEFINED,
@@ -330,6 +650,41 @@ fn create_scene_depth(
     Texture::new(Arc::clone(context), image, view, sampler)
 }
 
+fn create_scene_depth_resolve(
+    context: &Arc<Context>,
+    format: vk::Format,
+    extent: vk::Extent2D,
+) -> Texture {
+    let image = Image::create(
+        Arc::clone(context),
+        ImageParameters {
+            mem_properties: vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            extent,
+            sample_count: vk::SampleCountFlags::TYPE_1,
+            format,
+            usage: vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+            ..Default::default()
+        },
+        CString::new("Scene Depth Resolve Texture").unwrap(),
+    )
+    .expect("创建attachment image失败！");
+
+    image.transition_image_layout(
+        vk::ImageLayout::UNDEFINED,
+        vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+    );
+
+    let view = image.create_view(vk::ImageViewType::TYPE_2D, vk::ImageAspectFlags::DEPTH);
+
+    let sampler = Some(create_sampler(
+        context,
+        vk::Filter::NEAREST,
+        vk::Filter::NEAREST,
+    ));
+
+    Texture::new(Arc::clone(context), image, view, sampler)
+}
+
 fn create_scene_resolve(context: &Arc<Context>, extent: vk::Extent2D) -> Texture {
     let image = Image::create(
         Arc::clone(context),
@@ -341,7 +696,8 @@ fn create_scene_resolve(context: &Arc<Context>, extent: vk::Extent2D) -> Texture
             ..Default::default()
         },
         CString::new("Scene Resolve Texture").unwrap(),
-    );
+    )
+    .expect("创建attachment image失败！");
 
     image.transition_image_layout(
         vk::ImageLayout::UNDEFINED,
@@ -350,23 +706,25 @@ fn create_scene_resolve(context: &Arc<Context>, extent: vk::Extent2D) -> Texture
 
     let view = image.create_view(vk::ImageViewType::TYPE_2D, vk::ImageAspectFlags::COLOR);
 
-    let sampler = create_sampler(context, vk::Filter::NEAREST, vk::Filter::NEAREST);
+    let sampler = create_sampler(context, vk::Filter::LINEAR, vk::Filter::LINEAR);
 
     Texture::new(Arc::clone(context), image, view, Some(sampler))
 }
 
-fn create_fxaa_texture(context: &Arc<Context>, extent: vk::Extent2D) -> Texture {
+//和交换链格式一致，tone map的pipeline可以直接复用
+fn create_ldr_color(context: &Arc<Context>, extent: vk::Extent2D, format: vk::Format) -> Texture {
     let image = Image::create(
         Arc::clone(context),
         ImageParameters {
             mem_properties: vk::MemoryPropertyFlags::DEVICE_LOCAL,
             extent,
-            format: SCENE_COLOR_FORMAT,
+            format,
             usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
             ..Default::default()
         },
-        CString::new("FXAA Texture").unwrap(),
-    );
+        CString::new("LDR Color Texture").unwrap(),
+    )
+    .expect("创建attachment image失败！");
 
     image.transition_image_layout(
         vk::ImageLayout::UNDEFINED,
@@ -375,7 +733,7 @@ fn create_fxaa_texture(context: &Arc<Context>, extent: vk::Extent2D) -> Texture
 
     let view = image.create_view(vk::ImageViewType::TYPE_2D, vk::ImageAspectFlags::COLOR);
 
-    let sampler = create_sampler(context, vk::Filter::NEAREST, vk::Filter::NEAREST);
+    let sampler = create_sampler(context, vk::Filter::LINEAR, vk::Filter::LINEAR);
 
     Texture::new(Arc::clone(context), image, view, Some(sampler))
 }
@@ -397,7 +755,8 @@ fn create_bloom(context: &Arc<Context>, extent: vk::Extent2D) -> BloomAttachment
             ..Default::default()
         },
         CString::new("Bloom Texture").unwrap(),
-    );
+    )
+    .expect("创建attachment image失败！");
 
     let mips_views =
         image.create_mips_views(vk::ImageViewType::TYPE_2D, vk::ImageAspectFlags::COLOR);
@@ -0,0 +1,341 @@
+use crate::renderer::attachments::Attachments;
+use crate::renderer::fullscreen::*;
+use rendering::util::any_as_u8_slice;
+use serde::{Deserialize, Serialize};
+use std::{mem::size_of, sync::Arc};
+use vulkan::ash::{vk, Device};
+use vulkan::{debug_name, Context, Descriptors, Texture};
+
+//缩略图占屏幕的比例
+const OVERLAY_SCALE: f32 = 0.3;
+const OVERLAY_MARGIN: f32 = 10.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DebugAttachment {
+    None = 0,
+    ShadowMap,
+    GBufferNormals,
+    GBufferDepth,
+    SSAO,
+    SSAORaw,
+    SceneDepth,
+}
+
+impl DebugAttachment {
+    pub fn all() -> [DebugAttachment; 7] {
+        use DebugAttachment::*;
+        [
+            None,
+            ShadowMap,
+            GBufferNormals,
+            GBufferDepth,
+            SSAO,
+            SSAORaw,
+            SceneDepth,
+        ]
+    }
+
+    pub fn from_value(value: usize) -> Option<Self> {
+        use DebugAttachment::*;
+        match value {
+            0 => Some(None),
+            1 => Some(ShadowMap),
+            2 => Some(GBufferNormals),
+            3 => Some(GBufferDepth),
+            4 => Some(SSAO),
+            5 => Some(SSAORaw),
+            6 => Some(SceneDepth),
+            _ => Option::None,
+        }
+    }
+
+    //shadow map是数组纹理，只显示第一个级联
+    fn image_view(self, attachments: &Attachments) -> Option<(vk::ImageView, &Texture)> {
+        let texture = match self {
+            DebugAttachment::None => return Option::None,
+            DebugAttachment::ShadowMap => {
+                return Some((
+                    attachments.shadow_caster_layers.color_views[0],
+                    &attachments.shadow_caster_color,
+                ))
+            }
+            DebugAttachment::GBufferNormals => &attachments.gbuffer_normals,
+            DebugAttachment::GBufferDepth => &attachments.gbuffer_depth,
+            DebugAttachment::SSAO => &attachments.ssao_blur,
+            DebugAttachment::SSAORaw => &attachments.ssao,
+            DebugAttachment::SceneDepth => attachments.get_scene_resolved_depth(),
+        };
+        Some((texture.view, texture))
+    }
+
+    fn is_single_channel(self) -> bool {
+        !matches!(self, DebugAttachment::GBufferNormals)
+    }
+}
+
+//将中间结果绘制到屏幕右下角
+pub struct DebugOverlayPass {
+    context: Arc<Context>,
+    descriptors: Descriptors,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    attachment: DebugAttachment,
+}
+
+impl DebugOverlayPass {
+    pub fn create(
+        context: Arc<Context>,
+        output_format: vk::Format,
+        attachments: &Attachments,
+        attachment: DebugAttachment,
+    ) -> Self {
+        let descriptors = create_descriptors(&context);
+        let pipeline_layout = create_pipeline_layout(context.device(), descriptors.layout());
+        let pipeline = create_fullscreen_pipeline(
+            &context,
+            output_format,
+            pipeline_layout,
+            "debug_overlay",
+            None,
+            "DebugOverlay.Pipeline",
+        );
+
+        let mut pass = DebugOverlayPass {
+            context,
+            descriptors,
+            pipeline_layout,
+            pipeline,
+            attachment: DebugAttachment::None,
+        };
+        pass.set_attachment(attachments, attachment);
+        pass
+    }
+}
+
+impl DebugOverlayPass {
+    pub fn set_attachment(&mut self, attachments: &Attachments, attachment: DebugAttachment) {
+        self.attachment = attachment;
+        if let Some((view, texture)) = attachment.image_view(attachments) {
+            self.descriptors
+                .sets()
+                .iter()
+                .for_each(|s| update_descriptor_set(&self.context, *s, view, texture));
+        }
+    }
+
+    pub fn attachment(&self) -> DebugAttachment {
+        self.attachment
+    }
+
+    pub fn cmd_draw(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        quad_model: &QuadModel,
+        extent: vk::Extent2D,
+    ) {
+        if self.attachment == DebugAttachment::None {
+            return;
+        }
+
+        let device = self.context.device();
+
+        let width = extent.width as f32 * OVERLAY_SCALE;
+        let height = extent.height as f32 * OVERLAY_SCALE;
+        let x = (extent.width as f32 - width - OVERLAY_MARGIN).max(0.0);
+        let y = (extent.height as f32 - height - OVERLAY_MARGIN).max(0.0);
+
+        unsafe {
+            device.cmd_set_viewport(
+                command_buffer,
+                0,
+                &[vk::Viewport {
+                    x,
+                    y,
+                    width,
+                    height,
+                    min_depth: 0.0,
+                    max_depth: 1.0,
+                }],
+            );
+            device.cmd_set_scissor(
+                command_buffer,
+                0,
+                &[vk::Rect2D {
+                    offset: vk::Offset2D {
+                        x: x as _,
+                        y: y as _,
+                    },
+                    extent: vk::Extent2D {
+                        width: width as _,
+                        height: height as _,
+                    },
+                }],
+            );
+
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline,
+            );
+            device.cmd_bind_vertex_buffers(command_buffer, 0, &[quad_model.vertices.buffer], &[0]);
+            device.cmd_bind_index_buffer(
+                command_buffer,
+                quad_model.indices.buffer,
+                0,
+                vk::IndexType::UINT16,
+            );
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                self.descriptors.sets(),
+                &[],
+            );
+
+            let data = [self.attachment.is_single_channel() as u32];
+            let data = any_as_u8_slice(&data);
+            device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                0,
+                data,
+            );
+
+            device.cmd_draw_indexed(command_buffer, 6, 1, 0, 0, 1);
+
+            //恢复全屏viewport
+            device.cmd_set_viewport(
+                command_buffer,
+                0,
+                &[vk::Viewport {
+                    width: extent.width as _,
+                    height: extent.height as _,
+                    max_depth: 1.0,
+                    ..Default::default()
+                }],
+            );
+            device.cmd_set_scissor(
+                command_buffer,
+                0,
+                &[vk::Rect2D {
+                    extent,
+                    ..Default::default()
+                }],
+            );
+        }
+    }
+}
+
+impl Drop for DebugOverlayPass {
+    fn drop(&mut self) {
+        let device = self.context.device();
+        unsafe {
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+    }
+}
+
+fn create_descriptors(context: &Arc<Context>) -> Descriptors {
+    let layout = create_descriptor_set_layout(context.device());
+    let pool = create_descriptor_pool(context.device());
+    let sets = create_descriptor_sets(context, pool, layout);
+    Descriptors::new(Arc::clone(context), layout, pool, sets)
+}
+
+fn create_descriptor_set_layout(device: &Device) -> vk::DescriptorSetLayout {
+    let bindings = [vk::DescriptorSetLayoutBinding::builder()
+        .binding(0)
+        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+        .build()];
+
+    let layout_info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+
+    unsafe {
+        device
+            .create_descriptor_set_layout(&layout_info, None)
+            .unwrap()
+    }
+}
+
+fn create_descriptor_pool(device: &Device) -> vk::DescriptorPool {
+    let descriptor_count = 1;
+    let pool_sizes = [vk::DescriptorPoolSize {
+        ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+        descriptor_count,
+    }];
+
+    let create_info = vk::DescriptorPoolCreateInfo::builder()
+        .pool_sizes(&pool_sizes)
+        .max_sets(descriptor_count)
+        .flags(vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET);
+
+    unsafe { device.create_descriptor_pool(&create_info, None).unwrap() }
+}
+
+fn create_descriptor_sets(
+    context: &Arc<Context>,
+    pool: vk::DescriptorPool,
+    layout: vk::DescriptorSetLayout,
+) -> Vec<vk::DescriptorSet> {
+    let layouts = [layout];
+    let allocate_info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(pool)
+        .set_layouts(&layouts);
+    let sets = unsafe {
+        context
+            .device()
+            .allocate_descriptor_sets(&allocate_info)
+            .unwrap()
+    };
+    for (i, set) in sets.iter().enumerate() {
+        debug_name!(context, *set, "DebugOverlay.DescriptorSet[{}]", i);
+    }
+    sets
+}
+
+fn update_descriptor_set(
+    context: &Arc<Context>,
+    set: vk::DescriptorSet,
+    view: vk::ImageView,
+    input_image: &Texture,
+) {
+    let input_image_info = [vk::DescriptorImageInfo::builder()
+        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        .image_view(view)
+        .sampler(input_image.sampler.expect("调试视图输入image没有采样器！"))
+        .build()];
+
+    let descriptor_writes = [vk::WriteDescriptorSet::builder()
+        .dst_set(set)
+        .dst_binding(0)
+        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .image_info(&input_image_info)
+        .build()];
+
+    unsafe {
+        context
+            .device()
+            .update_descriptor_sets(&descriptor_writes, &[])
+    }
+}
+
+fn create_pipeline_layout(
+    device: &Device,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+) -> vk::PipelineLayout {
+    let layouts = [descriptor_set_layout];
+    let push_constant_ranges = [vk::PushConstantRange {
+        offset: 0,
+        size: size_of::<u32>() as _,
+        stage_flags: vk::ShaderStageFlags::FRAGMENT,
+    }];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(&layouts)
+        .push_constant_ranges(&push_constant_ranges);
+    unsafe { device.create_pipeline_layout(&layout_info, None).unwrap() }
+}
@@ -0,0 +1,102 @@
+use egui::TextureId;
+use std::sync::Arc;
+use vulkan::{ash::vk, Context};
+
+//在途帧越多，CPU可以越早开始录制下一帧，GPU也不容易空闲，吞吐更高；
+//代价是输入到画面显示之间每多一帧在途就多一帧延迟，通常2帧是较好的折中
+pub struct FrameSync {
+    context: Arc<Context>,
+    frames: Vec<FrameSyncObjects>,
+    //每个交换链image最后一次提交时使用的fence。在途帧数和image数不一致时，
+    //新获取的image可能仍被另一帧使用，必须等它完成才能重新录制对应的command buffer
+    images_in_flight: Vec<vk::Fence>,
+    current_frame: usize,
+    pub gui_textures_to_free: Vec<TextureId>,
+}
+
+#[derive(Clone, Copy)]
+pub struct FrameSyncObjects {
+    pub image_available_semaphore: vk::Semaphore,
+    pub render_finished_semaphore: vk::Semaphore,
+    pub fence: vk::Fence,
+}
+
+impl FrameSync {
+    pub fn new(context: Arc<Context>, frame_count: u32, image_count: usize) -> Self {
+        let device = context.device();
+        let frames = (0..frame_count)
+            .map(|_| {
+                let semaphore_info = vk::SemaphoreCreateInfo::builder();
+                let fence_info =
+                    vk::FenceCreateInfo::builder().flags(vk::FenceCreateFlags::SIGNALED);
+                unsafe {
+                    FrameSyncObjects {
+                        image_available_semaphore: device
+                            .create_semaphore(&semaphore_info, None)
+                            .expect("创建信号量失败！"),
+                        render_finished_semaphore: device
+                            .create_semaphore(&semaphore_info, None)
+                            .expect("创建信号量失败！"),
+                        fence: device
+                            .create_fence(&fence_info, None)
+                            .expect("创建fence失败！"),
+                    }
+                }
+            })
+            .collect();
+
+        Self {
+            context,
+            frames,
+            images_in_flight: vec![vk::Fence::null(); image_count],
+            current_frame: 0,
+            gui_textures_to_free: Vec::new(),
+        }
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn next_frame(&mut self) -> FrameSyncObjects {
+        let frame = self.frames[self.current_frame];
+        self.current_frame = (self.current_frame + 1) % self.frames.len();
+        frame
+    }
+
+    //等待image上一次的提交完成，并记录本帧将使用的fence
+    pub fn wait_for_image(
+        &mut self,
+        image_index: usize,
+        fence: vk::Fence,
+    ) -> Result<(), vk::Result> {
+        let image_fence = self.images_in_flight[image_index];
+        if image_fence != vk::Fence::null() && image_fence != fence {
+            unsafe {
+                self.context
+                    .device()
+                    .wait_for_fences(&[image_fence], true, std::u64::MAX)?
+            };
+        }
+        self.images_in_flight[image_index] = fence;
+        Ok(())
+    }
+
+    //交换链重建后调用，调用前需要等待GPU空闲
+    pub fn reset_images(&mut self, image_count: usize) {
+        self.images_in_flight = vec![vk::Fence::null(); image_count];
+    }
+}
+
+impl Drop for FrameSync {
+    fn drop(&mut self) {
+        let device = self.context.device();
+        for frame in self.frames.iter() {
+            unsafe {
+                device.destroy_semaphore(frame.image_available_semaphore, None);
+                device.destroy_semaphore(frame.render_finished_semaphore, None);
+                device.destroy_fence(frame.fence, None);
+            }
+        }
+    }
+}
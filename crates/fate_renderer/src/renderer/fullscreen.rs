@@ -1,6 +1,8 @@
 use std::mem::size_of;
 use std::sync::Arc;
-use vulkan::{ash::vk, create_device_local_buffer_with_data, Buffer, Context, Vertex};
+use vulkan::{
+    ash::vk, create_device_local_buffer_with_data, Buffer, Context, MultisampleParameters, Vertex,
+};
 
 use super::{create_renderer_pipeline, RendererPipelineParameters};
 
@@ -51,6 +53,7 @@ impl QuadModel {
             context,
             vk::BufferUsageFlags::INDEX_BUFFER,
             &indices,
+            "QuadModel.Indices",
         );
         let vertices: [f32; 16] = [
             -1.0, 1.0, 0.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, -1.0, 1.0, 0.0, -1.0, -1.0, 0.0, 0.0,
@@ -59,6 +62,7 @@ impl QuadModel {
             context,
             vk::BufferUsageFlags::VERTEX_BUFFER,
             &vertices,
+            "QuadModel.Vertices",
         );
 
         Self { vertices, indices }
@@ -71,6 +75,7 @@ pub fn create_fullscreen_pipeline(
     layout: vk::PipelineLayout,
     fragment_shader_name: &'static str,
     fragment_shader_specialization: Option<&vk::SpecializationInfo>,
+    name: &str,
 ) -> vk::Pipeline {
     let depth_stencil_info = vk::PipelineDepthStencilStateCreateInfo::builder()
         .depth_test_enable(false)
@@ -102,17 +107,21 @@ pub fn create_fullscreen_pipeline(
     create_renderer_pipeline::<QuadVertex>(
         context,
         RendererPipelineParameters {
+            name,
             vertex_shader_name: "fullscreen",
             fragment_shader_name,
             vertex_shader_specialization: None,
             fragment_shader_specialization,
-            msaa_samples: vk::SampleCountFlags::TYPE_1,
+            multisampling: MultisampleParameters::default(),
             color_attachment_formats: &[output_format],
             depth_attachment_format: None,
             layout,
             depth_stencil_info: &depth_stencil_info,
             color_blend_attachments: &color_blend_attachments,
             enable_face_culling: true,
+            front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+            polygon_mode: vk::PolygonMode::FILL,
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
             parent: None,
         },
     )
@@ -2,9 +2,33 @@ use crate::renderer::attachments::Attachments;
 use crate::renderer::{fullscreen::*, RendererSettings};
 use crate::FXAAMode;
 use rendering::util::any_as_u8_slice;
+use serde::{Deserialize, Serialize};
 use std::{mem::size_of, sync::Arc};
 use vulkan::ash::{vk, Device};
-use vulkan::{Context, Descriptors};
+use vulkan::{debug_name, Context, Descriptors};
+
+//tone map之后的抗锯齿方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AntiAliasingMode {
+    None = 0,
+    FXAA,
+}
+
+impl AntiAliasingMode {
+    pub fn all() -> [AntiAliasingMode; 2] {
+        use AntiAliasingMode::*;
+        [None, FXAA]
+    }
+
+    pub fn from_value(value: usize) -> Option<Self> {
+        use AntiAliasingMode::*;
+        match value {
+            0 => Some(None),
+            1 => Some(FXAA),
+            _ => Option::None,
+        }
+    }
+}
 
 pub struct FXAAPass {
     context: Arc<Context>,
@@ -16,6 +40,7 @@ pub struct FXAAPass {
     absolute_luminance_threshold: f32,
     relative_luminance_threshold: f32,
     subpixel_blending: f32,
+    sharpness: f32,
 }
 
 impl FXAAPass {
@@ -36,6 +61,7 @@ impl FXAAPass {
         let absolute_luminance_threshold = settings.absolute_luminance_threshold;
         let relative_luminance_threshold = settings.relative_luminance_threshold;
         let subpixel_blending = settings.subpixel_blending;
+        let sharpness = settings.sharpness;
 
         FXAAPass {
             context,
@@ -47,6 +73,7 @@ impl FXAAPass {
             absolute_luminance_threshold,
             relative_luminance_threshold,
             subpixel_blending,
+            sharpness,
         }
     }
 }
@@ -68,6 +95,10 @@ impl FXAAPass {
         self.subpixel_blending = subpixel_blending;
     }
 
+    pub fn set_sharpness(&mut self, sharpness: f32) {
+        self.sharpness = sharpness;
+    }
+
     pub fn set_attachments(&mut self, attachments: &Attachments) {
         self.descriptors
             .sets()
@@ -116,6 +147,7 @@ impl FXAAPass {
                 self.absolute_luminance_threshold,
                 self.relative_luminance_threshold,
                 self.subpixel_blending,
+                self.sharpness,
             ];
             let data = any_as_u8_slice(&data);
             device.cmd_push_constants(
@@ -195,6 +227,9 @@ fn create_descriptor_sets(
             .allocate_descriptor_sets(&allocate_info)
             .unwrap()
     };
+    for (i, set) in sets.iter().enumerate() {
+        debug_name!(context, *set, "FXAA.DescriptorSet[{}]", i);
+    }
 
     update_descriptor_set(context, sets[0], attachments);
 
@@ -208,10 +243,10 @@ fn update_descriptor_set(
 ) {
     let src_image_info = [vk::DescriptorImageInfo::builder()
         .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-        .image_view(attachments.get_scene_resolved_color().view)
+        .image_view(attachments.ldr_color.view)
         .sampler(
             attachments
-                .get_scene_resolved_color()
+                .ldr_color
                 .sampler
                 .expect("FXAA Src Image没采样器"),
         )
@@ -238,7 +273,7 @@ fn create_pipeline_layout(
     let layouts = [descriptor_set_layout];
     let push_constant_ranges = [vk::PushConstantRange {
         offset: 0,
-        size: size_of::<f32>() as _,
+        size: size_of::<[f32; 4]>() as _,
         stage_flags: vk::ShaderStageFlags::FRAGMENT,
     }];
     let layout_info = vk::PipelineLayoutCreateInfo::builder()
@@ -262,6 +297,7 @@ fn create_pipeline(
         layout,
         "fxaa",
         Some(&specialization_info),
+        &format!("FXAA.Pipeline.{:?}", fxaa_mode),
     )
 }
 fn create_model_frag_shader_specialization(
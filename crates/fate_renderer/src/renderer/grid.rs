@@ -0,0 +1,282 @@
+use super::{
+    attachments::SCENE_COLOR_FORMAT, create_renderer_pipeline, depth_compare_op,
+    RendererPipelineParameters,
+};
+use ash::{vk, Device};
+use rendering::util::any_as_u8_slice;
+use std::{mem::size_of, sync::Arc};
+use vulkan::*;
+
+const GRID_CELL_SIZE: f32 = 1.0;
+const GRID_HALF_LINE_COUNT: u32 = 50;
+const GRID_FADE_DISTANCE: f32 = 40.0;
+const AXES_VERTEX_COUNT: u32 = 6;
+
+//地面网格和原点坐标轴，在前向pass里画，不进gbuffer
+pub struct GridRenderer {
+    context: Arc<Context>,
+    descriptors: Descriptors,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl GridRenderer {
+    pub fn create(
+        context: Arc<Context>,
+        camera_buffers: &[Buffer],
+        msaa_samples: vk::SampleCountFlags,
+        depth_format: vk::Format,
+        reverse_z: bool,
+    ) -> Self {
+        let descriptors = create_descriptors(&context, camera_buffers);
+        let pipeline_layout = create_pipeline_layout(context.device(), descriptors.layout());
+        let pipeline = create_grid_pipeline(
+            &context,
+            msaa_samples,
+            depth_format,
+            reverse_z,
+            pipeline_layout,
+        );
+
+        Self {
+            context,
+            descriptors,
+            pipeline_layout,
+            pipeline,
+        }
+    }
+}
+
+impl GridRenderer {
+    pub fn cmd_draw(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        frame_index: usize,
+        grid_enabled: bool,
+        axes_enabled: bool,
+    ) {
+        if !grid_enabled && !axes_enabled {
+            return;
+        }
+
+        let device = self.context.device();
+        unsafe {
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline,
+            )
+        };
+
+        unsafe {
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &self.descriptors.sets()[frame_index..=frame_index],
+                &[],
+            )
+        };
+
+        let constants = GridConstants {
+            cell_size: GRID_CELL_SIZE,
+            fade_distance: GRID_FADE_DISTANCE,
+            half_line_count: GRID_HALF_LINE_COUNT,
+        };
+        unsafe {
+            device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                0,
+                any_as_u8_slice(&constants),
+            )
+        };
+
+        //顶点由shader根据gl_VertexIndex生成，先网格后坐标轴
+        let grid_vertex_count = (2 * GRID_HALF_LINE_COUNT + 1) * 4;
+        unsafe {
+            if grid_enabled {
+                device.cmd_draw(command_buffer, grid_vertex_count, 1, 0, 0);
+            }
+            if axes_enabled {
+                device.cmd_draw(command_buffer, AXES_VERTEX_COUNT, 1, grid_vertex_count, 0);
+            }
+        }
+    }
+}
+
+impl Drop for GridRenderer {
+    fn drop(&mut self) {
+        let device = self.context.device();
+        unsafe {
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+#[allow(dead_code)]
+struct GridConstants {
+    cell_size: f32,
+    fade_distance: f32,
+    half_line_count: u32,
+}
+
+fn create_descriptors(context: &Arc<Context>, uniform_buffers: &[Buffer]) -> Descriptors {
+    let layout = create_descriptor_set_layout(context.device());
+    let pool = create_descriptor_pool(context.device(), uniform_buffers.len() as _);
+    let sets = create_descriptor_sets(context, pool, layout, uniform_buffers);
+    Descriptors::new(Arc::clone(context), layout, pool, sets)
+}
+
+fn create_descriptor_set_layout(device: &Device) -> vk::DescriptorSetLayout {
+    let bindings = [vk::DescriptorSetLayoutBinding::builder()
+        .binding(0)
+        .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
+        .build()];
+
+    let layout_info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+
+    unsafe {
+        device
+            .create_descriptor_set_layout(&layout_info, None)
+            .unwrap()
+    }
+}
+
+fn create_descriptor_pool(device: &Device, descriptor_count: u32) -> vk::DescriptorPool {
+    let pool_sizes = [vk::DescriptorPoolSize {
+        ty: vk::DescriptorType::UNIFORM_BUFFER,
+        descriptor_count,
+    }];
+
+    let create_info = vk::DescriptorPoolCreateInfo::builder()
+        .pool_sizes(&pool_sizes)
+        .max_sets(descriptor_count);
+
+    unsafe { device.create_descriptor_pool(&create_info, None).unwrap() }
+}
+
+fn create_descriptor_sets(
+    context: &Arc<Context>,
+    pool: vk::DescriptorPool,
+    layout: vk::DescriptorSetLayout,
+    buffers: &[Buffer],
+) -> Vec<vk::DescriptorSet> {
+    let layouts = (0..buffers.len()).map(|_| layout).collect::<Vec<_>>();
+
+    let allocate_info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(pool)
+        .set_layouts(&layouts);
+    let sets = unsafe {
+        context
+            .device()
+            .allocate_descriptor_sets(&allocate_info)
+            .unwrap()
+    };
+    for (i, set) in sets.iter().enumerate() {
+        debug_name!(context, *set, "Grid.DescriptorSet[{}]", i);
+    }
+
+    sets.iter().zip(buffers.iter()).for_each(|(set, buffer)| {
+        let buffer_info = [vk::DescriptorBufferInfo::builder()
+            .buffer(buffer.buffer)
+            .offset(0)
+            .range(vk::WHOLE_SIZE)
+            .build()];
+
+        let descriptor_writes = [vk::WriteDescriptorSet::builder()
+            .dst_set(*set)
+            .dst_binding(0)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+            .buffer_info(&buffer_info)
+            .build()];
+
+        unsafe {
+            context
+                .device()
+                .update_descriptor_sets(&descriptor_writes, &[])
+        }
+    });
+
+    sets
+}
+
+fn create_pipeline_layout(
+    device: &Device,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+) -> vk::PipelineLayout {
+    let layouts = [descriptor_set_layout];
+    let push_constant_ranges = [vk::PushConstantRange {
+        stage_flags: vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+        offset: 0,
+        size: size_of::<GridConstants>() as _,
+    }];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(&layouts)
+        .push_constant_ranges(&push_constant_ranges);
+    unsafe { device.create_pipeline_layout(&layout_info, None).unwrap() }
+}
+
+fn create_grid_pipeline(
+    context: &Arc<Context>,
+    msaa_samples: vk::SampleCountFlags,
+    depth_format: vk::Format,
+    reverse_z: bool,
+    layout: vk::PipelineLayout,
+) -> vk::Pipeline {
+    //只做深度测试不写深度，被模型遮挡的部分不会画出来
+    let depth_stencil_info = vk::PipelineDepthStencilStateCreateInfo::builder()
+        .depth_test_enable(true)
+        .depth_write_enable(false)
+        .depth_compare_op(depth_compare_op(reverse_z))
+        .depth_bounds_test_enable(false)
+        .min_depth_bounds(0.0)
+        .max_depth_bounds(1.0)
+        .stencil_test_enable(false)
+        .front(Default::default())
+        .back(Default::default());
+
+    let color_blend_attachments = [vk::PipelineColorBlendAttachmentState::builder()
+        .color_write_mask(
+            vk::ColorComponentFlags::R
+                | vk::ColorComponentFlags::G
+                | vk::ColorComponentFlags::B
+                | vk::ColorComponentFlags::A,
+        )
+        .blend_enable(true)
+        .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+        .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+        .color_blend_op(vk::BlendOp::ADD)
+        .src_alpha_blend_factor(vk::BlendFactor::ZERO)
+        .dst_alpha_blend_factor(vk::BlendFactor::ONE)
+        .alpha_blend_op(vk::BlendOp::ADD)
+        .build()];
+
+    create_renderer_pipeline::<()>(
+        context,
+        RendererPipelineParameters {
+            name: "Grid.Pipeline",
+            vertex_shader_name: "grid",
+            fragment_shader_name: "grid",
+            vertex_shader_specialization: None,
+            fragment_shader_specialization: None,
+            multisampling: MultisampleParameters::new(msaa_samples),
+            color_attachment_formats: &[SCENE_COLOR_FORMAT],
+            depth_attachment_format: Some(depth_format),
+            layout,
+            depth_stencil_info: &depth_stencil_info,
+            color_blend_attachments: &color_blend_attachments,
+            enable_face_culling: false,
+            front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+            polygon_mode: vk::PolygonMode::FILL,
+            topology: vk::PrimitiveTopology::LINE_LIST,
+            parent: None,
+        },
+    )
+}
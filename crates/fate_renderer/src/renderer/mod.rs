@@ -1,65 +1,134 @@
 mod attachments;
+mod debugoverlay;
+mod framesync;
 mod fullscreen;
 mod fxaa;
+mod grid;
 mod model;
+mod occlusion;
+mod outline;
 mod postprocess;
+mod profiler;
+mod shadow;
+mod shadowcatcher;
 mod skybox;
 mod ssao;
+mod target;
 
-use self::attachments::Attachments;
+use self::attachments::{Attachments, ShadowCasterLayers};
+pub use self::attachments::{BLOOM_MIP_LEVELS, MAX_SHADOW_CASCADES};
+pub use self::debugoverlay::DebugAttachment;
+use self::debugoverlay::DebugOverlayPass;
+use self::framesync::FrameSync;
 use self::fullscreen::QuadModel;
+pub use self::fxaa::AntiAliasingMode;
 use self::fxaa::FXAAPass;
+use self::grid::GridRenderer;
 use self::model::gbufferpass::GBufferPass;
-pub use self::model::lightpass::{LightPass, OutputMode};
+use self::model::lightcullingpass::LightCullingPass;
+pub use self::model::lightcullingpass::{DEFAULT_LIGHT_CLUSTER_GRID, MAX_LIGHT_CLUSTER_GRID};
+pub use self::model::lightpass::{
+    LightPass, OutputMode, PolygonMode, DEFAULT_PARALLAX_STEPS, MAX_PARALLAX_STEPS,
+};
 use self::model::shadowcasterpass::ShadowCasterPass;
-use self::model::{ModelData, ModelRenderer};
+use self::model::skinningpass::SkinningPass;
+pub use self::model::CullingStats;
+use self::model::{decode_pick_id, model_pick_id, node_pick_id, ModelData, ModelRenderer};
+use self::occlusion::OcclusionCuller;
+use self::outline::OutlinePass;
+pub use self::outline::{DEFAULT_OUTLINE_COLOR, DEFAULT_OUTLINE_THICKNESS, MAX_OUTLINE_THICKNESS};
+pub use self::profiler::GpuPass;
+use self::profiler::GpuProfiler;
+use self::shadow::*;
+pub use self::shadow::{ShadowFilterMode, ShadowMapResolution, MAX_SHADOW_CASTING_LIGHTS};
+use self::shadowcatcher::{ShadowCatcherRenderer, ShadowCatcherUBO};
+pub use self::shadowcatcher::{DEFAULT_SHADOW_CATCHER_COLOR, MAX_SHADOW_CATCHER_SIZE};
 use self::ssao::*;
+use self::target::{pixels_to_rgba8, OffscreenTarget, RenderTarget};
 pub use self::{postprocess::*, skybox::*};
 
-use super::camera::{Camera, CameraUBO};
+use super::camera::{Camera, CameraUBO, Z_FAR, Z_NEAR};
 use super::config::Config;
 use super::gui::Gui;
-use ash::{vk, Device};
-use egui::{ClippedPrimitive, TextureId};
+use ash::vk;
+use egui::ClippedPrimitive;
 use egui_ash_renderer::{DynamicRendering, Options, Renderer as GuiRenderer};
 use gltf_loader::model::Model;
+use rendering::aabb::Aabb;
 use rendering::cgmath::{Deg, InnerSpace, Matrix4, Point3, SquareMatrix, Vector3};
 use rendering::environment::Environment;
+use scene::frustum::Frustum;
 use scene::scene_tree::SceneTree;
+use serde::{Deserialize, Serialize};
 use std::cell::RefCell;
 use std::f32::consts::LN_2;
 use std::ffi::CString;
 use std::mem::size_of;
+use std::path::PathBuf;
 use std::rc::Rc;
 use std::sync::Arc;
 use std::time::Instant;
-use vulkan::ash::vk::{RenderingAttachmentInfo, RenderingInfo};
 use vulkan::*;
 use winit::window::Window;
 
-pub const MAX_FRAMES_IN_FLIGHT: u32 = 2;
+pub const DEFAULT_FRAMES_IN_FLIGHT: u32 = 2;
+pub const MAX_FRAMES_IN_FLIGHT: u32 = 3;
 
 const DEFAULT_EMISSIVE_INTENSITY: f32 = 1.0;
 const DEFAULT_SSAO_KERNEL_SIZE: u32 = 32;
 const DEFAULT_SSAO_RADIUS: f32 = 0.15;
 const DEFAULT_SSAO_STRENGTH: f32 = 1.0;
 pub const DEFAULT_BLOOM_STRENGTH: f32 = 0.02;
+//内部渲染分辨率相对窗口的比例，大于1时为超采样
+pub const MIN_RENDER_SCALE: f32 = 0.25;
+pub const MAX_RENDER_SCALE: f32 = 2.0;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum RenderError {
     DirtySwapchain,
+    //驱动超时或GPU重置，需要重建整个设备
+    DeviceLost,
 }
 
-#[derive(Clone, Copy, Debug)]
+//一帧的渲染结果和耗时，供性能面板、嵌入方和无窗口模式使用
+//GPU耗时在等待该帧的fence之后读回，是同一帧槽位上一次提交的结果，关闭GPU计时时为空
+#[derive(Clone, Debug)]
+pub struct FrameInfo {
+    //从创建渲染器开始累计的帧序号
+    pub frame_index: u64,
+    pub result: Result<(), RenderError>,
+    //录制和提交命令的CPU耗时，单位毫秒
+    pub cpu_time: f32,
+    pub gpu_timings: Vec<(GpuPass, f32)>,
+}
+
+//场景资源统计，显存为所有buffer和image申请的内存之和
+#[derive(Clone, Copy, Default)]
+pub struct SceneStats {
+    pub texture_count: usize,
+    pub allocated_memory: vk::DeviceSize,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct RendererSettings {
     pub emissive_intensity: f32,
     pub ssao_enabled: bool,
     pub ssao_kernel_size: u32,
     pub ssao_radius: f32,
     pub ssao_strength: f32,
+    pub ssao_blur_enabled: bool,
+    pub ssao_blur_radius: u32,
+    pub ssao_range_check_enabled: bool,
     pub tone_map_mode: ToneMapMode,
+    pub anti_aliasing_mode: AntiAliasingMode,
     pub fxaa_mode: FXAAMode,
+    pub sharpness: f32,
     pub output_mode: OutputMode,
+    pub polygon_mode: PolygonMode,
     pub bloom_strength: f32,
+    pub bloom_threshold: f32,
+    pub bloom_mip_levels: u32,
     pub absolute_luminance_threshold: f32,
     pub relative_luminance_threshold: f32,
     pub subpixel_blending: f32,
@@ -67,6 +136,55 @@ pub struct RendererSettings {
     pub fog_end: f32,
     pub fog_start: f32,
     pub fog_color: [f32; 4],
+    //曝光补偿，单位EV，0为不调整
+    pub exposure: f32,
+    pub white_point: f32,
+    pub ambient_intensity: f32,
+    pub shadow_enabled: bool,
+    pub bloom_enabled: bool,
+    pub debug_attachment: DebugAttachment,
+    pub shadow_cascade_count: u32,
+    pub shadow_split_lambda: f32,
+    pub shadow_map_resolution: ShadowMapResolution,
+    pub shadow_filter_mode: ShadowFilterMode,
+    pub shadow_pcf_kernel_size: u32,
+    pub shadow_depth_bias: f32,
+    pub shadow_normal_bias: f32,
+    //投射阴影的点光源和聚光灯数量上限，0表示只有主光源有阴影
+    pub shadow_casting_lights: u32,
+    pub grid_enabled: bool,
+    pub axes_enabled: bool,
+    //场景最低点处的透明地面，只显示接收到的阴影和AO
+    pub shadow_catcher_enabled: bool,
+    //地面边长，0表示跟随相机的无限大地面
+    pub shadow_catcher_size: f32,
+    //rgb为阴影颜色，alpha为完全遮挡处的不透明度
+    pub shadow_catcher_color: [f32; 4],
+    pub gpu_skinning_enabled: bool,
+    //简单场景里查询本身的开销可能比省下的绘制还大，默认关闭
+    pub occlusion_culling_enabled: bool,
+    //环境绕Y轴的旋转角度，天空盒与IBL共用
+    pub environment_yaw: f32,
+    pub skybox_exposure: f32,
+    //0为清晰的原始天空盒，大于0时按粗糙度从预滤波贴图里取模糊的背景
+    pub skybox_blur: f32,
+    pub skybox_enabled: bool,
+    //天空盒关闭时的背景色，alpha为0时输出透明背景
+    pub clear_color: [f32; 4],
+    pub ibl_debug_roughness: f32,
+    //灯较多时按x、y、z划分视锥体做分簇剔除
+    pub light_cluster_grid: [u32; 3],
+    //材质带高度图时做视差遮蔽映射，步进次数越多越精确
+    pub parallax_enabled: bool,
+    pub parallax_steps: u32,
+    //先只画一遍不透明物体的深度，光照pass只对可见片元着色，多一次几何绘制
+    pub depth_prepass_enabled: bool,
+    //gbuffer和光照等attachment按窗口大小乘以该比例创建，最后双线性缩放到交换链
+    pub render_scale: f32,
+    //在选中节点的轮廓外描边，宽度单位为内部分辨率的像素
+    pub outline_enabled: bool,
+    pub outline_color: [f32; 3],
+    pub outline_thickness: u32,
 }
 
 impl Default for RendererSettings {
@@ -77,10 +195,18 @@ impl Default for RendererSettings {
             ssao_kernel_size: DEFAULT_SSAO_KERNEL_SIZE,
             ssao_radius: DEFAULT_SSAO_RADIUS,
             ssao_strength: DEFAULT_SSAO_STRENGTH,
+            ssao_blur_enabled: true,
+            ssao_blur_radius: DEFAULT_SSAO_BLUR_RADIUS,
+            ssao_range_check_enabled: true,
             tone_map_mode: ToneMapMode::Default,
+            anti_aliasing_mode: AntiAliasingMode::FXAA,
             fxaa_mode: FXAAMode::Quality,
+            sharpness: 0.0,
             output_mode: OutputMode::Final,
+            polygon_mode: PolygonMode::Fill,
             bloom_strength: DEFAULT_BLOOM_STRENGTH,
+            bloom_threshold: DEFAULT_BLOOM_THRESHOLD,
+            bloom_mip_levels: BLOOM_MIP_LEVELS,
             absolute_luminance_threshold: 0.1,
             relative_luminance_threshold: 0.1,
             subpixel_blending: 0.75,
@@ -88,6 +214,41 @@ impl Default for RendererSettings {
             fog_end: 100.0,
             fog_start: 0.1,
             fog_color: [1.0, 1.0, 1.0, 1.0],
+            exposure: 0.0,
+            white_point: DEFAULT_WHITE_POINT,
+            ambient_intensity: 1.0,
+            shadow_enabled: true,
+            bloom_enabled: true,
+            debug_attachment: DebugAttachment::None,
+            shadow_cascade_count: DEFAULT_SHADOW_CASCADE_COUNT,
+            shadow_split_lambda: DEFAULT_SHADOW_SPLIT_LAMBDA,
+            shadow_map_resolution: ShadowMapResolution::Medium,
+            shadow_filter_mode: ShadowFilterMode::PCF,
+            shadow_pcf_kernel_size: DEFAULT_SHADOW_PCF_KERNEL_SIZE,
+            shadow_depth_bias: DEFAULT_SHADOW_DEPTH_BIAS,
+            shadow_normal_bias: DEFAULT_SHADOW_NORMAL_BIAS,
+            shadow_casting_lights: DEFAULT_SHADOW_CASTING_LIGHTS,
+            grid_enabled: true,
+            axes_enabled: true,
+            shadow_catcher_enabled: false,
+            shadow_catcher_size: 0.0,
+            shadow_catcher_color: DEFAULT_SHADOW_CATCHER_COLOR,
+            gpu_skinning_enabled: true,
+            occlusion_culling_enabled: false,
+            environment_yaw: 0.0,
+            skybox_exposure: 1.0,
+            skybox_blur: 0.0,
+            skybox_enabled: true,
+            clear_color: [0.0, 0.0, 0.0, 1.0],
+            ibl_debug_roughness: 0.0,
+            light_cluster_grid: DEFAULT_LIGHT_CLUSTER_GRID,
+            parallax_enabled: true,
+            parallax_steps: DEFAULT_PARALLAX_STEPS,
+            depth_prepass_enabled: false,
+            render_scale: 1.0,
+            outline_enabled: true,
+            outline_color: DEFAULT_OUTLINE_COLOR,
+            outline_thickness: DEFAULT_OUTLINE_THICKNESS,
         }
     }
 }
@@ -96,25 +257,56 @@ pub struct Renderer {
     scene: SceneTree,
     settings: RendererSettings,
     depth_format: vk::Format,
+    reverse_z: bool,
     msaa_samples: vk::SampleCountFlags,
-    swapchain: Swapchain,
+    //MSAA时场景深度resolve使用的模式
+    depth_resolve_mode: vk::ResolveModeFlags,
+    //透明物体排序用
+    camera_position: Point3<f32>,
+    //当前相机的(近平面, 远平面)
+    camera_near_far: (f32, f32),
+    //正交投影时不绘制天空盒
+    orthographic: bool,
+    //上一帧相机的view-projection，计算运动矢量用
+    previous_view_proj: Option<Matrix4<f32>>,
+    culling_stats: CullingStats,
+    target: RenderTarget,
     command_buffers: Vec<vk::CommandBuffer>,
-    in_flight_frames: InFlightFrames,
+    frame_sync: FrameSync,
     environment: Environment,
     camera_uniform_buffers: Vec<Buffer>,
-    light_uniform_buffers: Vec<Buffer>,
+    shadow_uniform_buffers: Vec<Buffer>,
+    //本帧点光源和聚光灯阴影用到的shadow map层数
+    punctual_shadow_layer_count: u32,
     attachments: Attachments,
     skybox_renderer: SkyboxRenderer,
-    model_renderer: Option<ModelRenderer>,
+    grid_renderer: GridRenderer,
+    shadow_catcher_renderer: ShadowCatcherRenderer,
+    occlusion_culler: OcclusionCuller,
+    model_renderers: Vec<ModelRenderer>,
     ssao_pass: SSAOPass,
     ssao_blur_pass: BlurPass,
     quad_model: QuadModel,
     bloom_pass: BloomPass,
     fxaa_pass: FXAAPass,
     final_pass: FinalPass,
-    gui_renderer: GuiRenderer,
+    debug_overlay_pass: DebugOverlayPass,
+    outline_pass: OutlinePass,
+    //描边的(模型序号, 节点序号)
+    selected_node: Option<(usize, usize)>,
+    //ui渲染器只支持dynamic rendering，回退到render pass时不绘制ui
+    gui_renderer: Option<GuiRenderer>,
+    gpu_profiler: GpuProfiler,
+    //等待拾取的窗口像素坐标，画完gbuffer后把该像素的ID拷到pick_buffer
+    pick_request: Option<(u32, u32)>,
+    pick_buffer: Buffer,
+    picked_node: Option<Option<(usize, usize)>>,
+    //等待保存的截图路径，本帧最后把交换链image拷到screenshot_buffer
+    screenshot_request: Option<PathBuf>,
+    screenshot_buffer: Option<Buffer>,
     context: Arc<Context>,
     timer: Instant,
+    frame_count: u64,
 }
 
 impl Renderer {
@@ -123,47 +315,113 @@ impl Renderer {
         config: &Config,
         settings: RendererSettings,
         environment: Environment,
-    ) -> Self {
-        let timer: Instant = Instant::now();
+    ) -> Result<Self, VkError> {
         let swapchain_support_details = SwapchainSupportDetails::new(
             context.physical_device(),
             context.surface(),
             context.surface_khr(),
-        );
+        )?;
 
         let resolution = [config.resolution().width(), config.resolution().height()];
-        let swapchain_properties =
-            swapchain_support_details.get_ideal_swapchain_properties(resolution, config.vsync());
-        let depth_format = find_depth_format(&context);
-        let msaa_samples = context.get_max_usable_sample_count(config.msaa());
-        log::debug!(
-            "msaa: {:?} - preferred was {:?}",
-            msaa_samples,
-            config.msaa()
-        );
-
         let swapchain = Swapchain::create(
             Arc::clone(&context),
             swapchain_support_details,
             resolution,
             config.vsync(),
-        );
+        )?;
 
-        let command_buffers = allocate_command_buffers(&context, swapchain.image_count());
+        Ok(Self::create_with_target(
+            context,
+            config,
+            settings,
+            environment,
+            RenderTarget::Swapchain(swapchain),
+        ))
+    }
+
+    //不需要窗口，画面渲染到离屏图像上，通过render_offscreen读回
+    pub fn create_headless(
+        context: Arc<Context>,
+        config: &Config,
+        settings: RendererSettings,
+        environment: Environment,
+        resolution: [u32; 2],
+    ) -> Self {
+        let extent = vk::Extent2D {
+            width: resolution[0],
+            height: resolution[1],
+        };
+        let offscreen = OffscreenTarget::create(&context, extent);
+
+        Self::create_with_target(
+            context,
+            config,
+            settings,
+            environment,
+            RenderTarget::Offscreen(offscreen),
+        )
+    }
 
-        let in_flight_frames = create_sync_objects(&context);
+    fn create_with_target(
+        context: Arc<Context>,
+        config: &Config,
+        settings: RendererSettings,
+        environment: Environment,
+        target: RenderTarget,
+    ) -> Self {
+        let timer: Instant = Instant::now();
+        let depth_format = find_depth_format(&context);
+        let reverse_z = config.reverse_z();
+        if reverse_z && depth_format != vk::Format::D32_SFLOAT {
+            log::warn!(
+                "reverse-Z需要浮点深度缓冲，当前格式{:?}精度提升有限",
+                depth_format
+            );
+        }
+        let msaa_samples = context.get_max_usable_sample_count(config.msaa());
+        log::debug!(
+            "msaa: {:?} - preferred was {:?}",
+            msaa_samples,
+            config.msaa()
+        );
+        let depth_resolve_mode =
+            context.get_supported_depth_resolve_mode(depth_resolve_mode(reverse_z));
+
+        let output_format = target.format();
+
+        let command_buffers = allocate_command_buffers(&context, target.image_count());
+
+        let gpu_profiler = GpuProfiler::create(Arc::clone(&context), target.image_count());
+
+        //在途帧数不能超过交换链image数，否则多出的帧只能等待image
+        let frames_in_flight = config
+            .frames_in_flight()
+            .min(target.image_count() as u32)
+            .max(1);
+        if frames_in_flight != config.frames_in_flight() {
+            log::warn!(
+                "在途帧数{}超过交换链image数{}，调整为{}",
+                config.frames_in_flight(),
+                target.image_count(),
+                frames_in_flight
+            );
+        }
+        let frame_sync =
+            FrameSync::new(Arc::clone(&context), frames_in_flight, target.image_count());
 
         let camera_uniform_buffers =
-            create_camera_uniform_buffers(&context, swapchain.image_count() as u32);
+            create_camera_uniform_buffers(&context, target.image_count() as u32);
 
-        let light_uniform_buffers =
-            create_camera_uniform_buffers(&context, swapchain.image_count() as u32);
+        let shadow_uniform_buffers =
+            create_shadow_uniform_buffers(&context, target.image_count() as u32);
 
         let attachments = Attachments::new(
             &context,
-            swapchain_properties.extent,
+            scaled_extent(target.extent(), settings.render_scale),
             depth_format,
             msaa_samples,
+            settings.shadow_map_resolution.size(),
+            output_format,
         );
 
         let skybox_renderer = SkyboxRenderer::create(
@@ -174,6 +432,36 @@ impl Renderer {
             depth_format,
         );
 
+        let grid_renderer = GridRenderer::create(
+            Arc::clone(&context),
+            &camera_uniform_buffers,
+            msaa_samples,
+            depth_format,
+            reverse_z,
+        );
+
+        let shadow_catcher_renderer = ShadowCatcherRenderer::create(
+            Arc::clone(&context),
+            &camera_uniform_buffers,
+            settings
+                .ssao_enabled
+                .then(|| attachments.get_ao_map(settings.ssao_blur_enabled)),
+            settings
+                .shadow_enabled
+                .then(|| attachments.get_shadow_maps().cascades),
+            msaa_samples,
+            depth_format,
+            reverse_z,
+        );
+
+        let occlusion_culler = OcclusionCuller::create(
+            Arc::clone(&context),
+            &camera_uniform_buffers,
+            msaa_samples,
+            depth_format,
+            reverse_z,
+        );
+
         let ssao_pass = SSAOPass::create(
             Arc::clone(&context),
             &attachments.gbuffer_normals,
@@ -182,68 +470,162 @@ impl Renderer {
             settings,
         );
 
-        let ssao_blur_pass = BlurPass::create(Arc::clone(&context), &attachments.ssao);
+        let ssao_blur_pass = BlurPass::create(
+            Arc::clone(&context),
+            &attachments.ssao,
+            &attachments.ssao_blur_temp,
+            &attachments.gbuffer_depth,
+            settings.ssao_blur_radius,
+            reverse_z,
+        );
 
         let quad_model = QuadModel::new(&context);
 
-        let bloom_pass = BloomPass::create(Arc::clone(&context), &attachments);
-
-        let fxaa_pass = FXAAPass::create(
+        let bloom_pass = BloomPass::create(
             Arc::clone(&context),
-            swapchain_properties.format.format,
             &attachments,
-            settings,
+            settings.bloom_threshold,
+            settings.bloom_mip_levels,
         );
 
-        let final_pass = FinalPass::create(
+        let fxaa_pass =
+            FXAAPass::create(Arc::clone(&context), output_format, &attachments, settings);
+
+        let final_pass =
+            FinalPass::create(Arc::clone(&context), output_format, &attachments, settings);
+
+        let debug_overlay_pass = DebugOverlayPass::create(
             Arc::clone(&context),
-            swapchain_properties.format.format,
+            output_format,
             &attachments,
-            settings,
+            settings.debug_attachment,
         );
 
-        let gui_renderer = GuiRenderer::with_default_allocator(
-            context.instance(),
-            context.physical_device(),
-            context.device().clone(),
-            DynamicRendering {
-                color_attachment_format: swapchain_properties.format.format,
-                depth_attachment_format: None,
-            },
-            Options {
-                in_flight_frames: MAX_FRAMES_IN_FLIGHT as _,
-                ..Default::default()
-            },
-        )
-        .expect("创建ui渲染器失败！");
+        let outline_pass = OutlinePass::create(Arc::clone(&context), output_format, &attachments);
+
+        let gui_renderer = if context.supports(DeviceFeature::DynamicRendering) {
+            let gui_renderer = GuiRenderer::with_default_allocator(
+                context.instance(),
+                context.physical_device(),
+                context.device().clone(),
+                DynamicRendering {
+                    color_attachment_format: output_format,
+                    depth_attachment_format: None,
+                },
+                Options {
+                    in_flight_frames: frame_sync.frame_count(),
+                    ..Default::default()
+                },
+            )
+            .expect("创建ui渲染器失败！");
+            Some(gui_renderer)
+        } else {
+            log::warn!("未启用dynamic rendering，使用render pass渲染，ui不会显示");
+            None
+        };
+
+        let pick_buffer = Buffer::create(
+            Arc::clone(&context),
+            size_of::<u32>() as _,
+            vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::TRANSFER_SRC,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            "PickId.Buffer",
+        );
 
         Self {
             scene: SceneTree::default(),
             context,
             settings,
             depth_format,
+            reverse_z,
             msaa_samples,
-            swapchain,
+            depth_resolve_mode,
+            camera_position: Point3::new(0.0, 0.0, 0.0),
+            camera_near_far: (Z_NEAR, Z_FAR),
+            orthographic: false,
+            previous_view_proj: None,
+            culling_stats: CullingStats::default(),
+            target,
             command_buffers,
-            in_flight_frames,
+            frame_sync,
             environment,
             camera_uniform_buffers,
-            light_uniform_buffers,
+            shadow_uniform_buffers,
+            punctual_shadow_layer_count: 0,
             attachments,
             skybox_renderer,
-            model_renderer: None,
+            grid_renderer,
+            shadow_catcher_renderer,
+            occlusion_culler,
+            model_renderers: Vec::new(),
             ssao_pass,
             ssao_blur_pass,
             quad_model,
             bloom_pass,
             fxaa_pass,
             final_pass,
+            debug_overlay_pass,
+            outline_pass,
+            selected_node: None,
             gui_renderer,
+            gpu_profiler,
+            pick_request: None,
+            pick_buffer,
+            screenshot_request: None,
+            screenshot_buffer: None,
+            picked_node: None,
             timer,
+            frame_count: 0,
         }
     }
 }
 
+//主相机的深度约定，所有测试或读取场景深度的pass都要保持一致
+//标准深度近平面为0、远平面为1，使用LESS_OR_EQUAL比较并清除为1
+//reverse-Z反过来，近平面为1、远平面为0，使用GREATER_OR_EQUAL比较并清除为0，浮点深度缓冲在远处精度更高
+//阴影贴图是正交投影，深度本身是线性的，始终使用标准深度
+fn depth_compare_op(reverse_z: bool) -> vk::CompareOp {
+    if reverse_z {
+        vk::CompareOp::GREATER_OR_EQUAL
+    } else {
+        vk::CompareOp::LESS_OR_EQUAL
+    }
+}
+
+//glTF规定逆时针为正面，镜像节点的变换会翻转绕序，需要改用顺时针为正面
+fn front_face(mirrored: bool) -> vk::FrontFace {
+    if mirrored {
+        vk::FrontFace::CLOCKWISE
+    } else {
+        vk::FrontFace::COUNTER_CLOCKWISE
+    }
+}
+
+fn depth_clear_value(reverse_z: bool) -> f32 {
+    if reverse_z {
+        0.0
+    } else {
+        1.0
+    }
+}
+
+//resolve时保留离相机最近的样本，和深度测试的方向一致
+fn depth_resolve_mode(reverse_z: bool) -> vk::ResolveModeFlags {
+    if reverse_z {
+        vk::ResolveModeFlags::MAX
+    } else {
+        vk::ResolveModeFlags::MIN
+    }
+}
+
+//按比例缩放后的渲染分辨率，至少为1个像素
+fn scaled_extent(extent: vk::Extent2D, scale: f32) -> vk::Extent2D {
+    vk::Extent2D {
+        width: ((extent.width as f32 * scale).round() as u32).max(1),
+        height: ((extent.height as f32 * scale).round() as u32).max(1),
+    }
+}
+
 fn find_depth_format(context: &Context) -> vk::Format {
     let candidates = vec![
         vk::Format::D32_SFLOAT,
@@ -265,52 +647,43 @@ fn allocate_command_buffers(context: &Context, count: usize) -> Vec<vk::CommandB
         .level(vk::CommandBufferLevel::PRIMARY)
         .command_buffer_count(count as _);
 
-    unsafe {
+    let command_buffers = unsafe {
         context
             .device()
             .allocate_command_buffers(&allocate_info)
             .unwrap()
+    };
+    for (i, command_buffer) in command_buffers.iter().enumerate() {
+        debug_name!(context, *command_buffer, "Frame.CommandBuffer[{}]", i);
     }
-}
-
-fn create_sync_objects(context: &Arc<Context>) -> InFlightFrames {
-    let device = context.device();
-    let mut sync_objects_vec = Vec::new();
-    for _ in 0..MAX_FRAMES_IN_FLIGHT {
-        let image_available_semaphore = {
-            let semaphore_info = vk::SemaphoreCreateInfo::builder();
-            unsafe { device.create_semaphore(&semaphore_info, None).unwrap() }
-        };
-
-        let render_finished_semaphore = {
-            let semaphore_info = vk::SemaphoreCreateInfo::builder();
-            unsafe { device.create_semaphore(&semaphore_info, None).unwrap() }
-        };
-
-        let in_flight_fence = {
-            let fence_info = vk::FenceCreateInfo::builder().flags(vk::FenceCreateFlags::SIGNALED);
-            unsafe { device.create_fence(&fence_info, None).unwrap() }
-        };
-
-        let sync_objects = SyncObjects {
-            image_available_semaphore,
-            render_finished_semaphore,
-            fence: in_flight_fence,
-        };
-        sync_objects_vec.push(sync_objects)
-    }
-
-    InFlightFrames::new(Arc::clone(context), sync_objects_vec)
+    command_buffers
 }
 
 fn create_camera_uniform_buffers(context: &Arc<Context>, count: u32) -> Vec<Buffer> {
     (0..count)
-        .map(|_| {
+        .map(|i| {
             let mut buffer = Buffer::create(
                 Arc::clone(context),
                 size_of::<CameraUBO>() as _,
                 vk::BufferUsageFlags::UNIFORM_BUFFER,
                 vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                &format!("Camera.UBO[{}]", i),
+            );
+            buffer.map_memory();
+            buffer
+        })
+        .collect::<Vec<_>>()
+}
+
+fn create_shadow_uniform_buffers(context: &Arc<Context>, count: u32) -> Vec<Buffer> {
+    (0..count)
+        .map(|i| {
+            let mut buffer = Buffer::create(
+                Arc::clone(context),
+                size_of::<ShadowUBO>() as _,
+                vk::BufferUsageFlags::UNIFORM_BUFFER,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                &format!("Shadow.UBO[{}]", i),
             );
             buffer.map_memory();
             buffer
@@ -319,61 +692,115 @@ fn create_camera_uniform_buffers(context: &Arc<Context>, count: u32) -> Vec<Buff
 }
 
 impl Renderer {
+    //保留原来的接口，只关心结果时使用
+    #[allow(dead_code)]
     pub fn render(
         &mut self,
         window: &Window,
         camera: Camera,
         gui: &mut Gui,
+    ) -> Result<(), RenderError> {
+        self.render_frame(window, camera, gui).result
+    }
+
+    pub fn render_frame(&mut self, window: &Window, camera: Camera, gui: &mut Gui) -> FrameInfo {
+        let start = Instant::now();
+        let result = self.draw_frame(window, camera, gui);
+        self.frame_info(start, result)
+    }
+
+    fn frame_info(&mut self, start: Instant, result: Result<(), RenderError>) -> FrameInfo {
+        let frame_index = self.frame_count;
+        self.frame_count += 1;
+        FrameInfo {
+            frame_index,
+            result,
+            cpu_time: start.elapsed().as_secs_f32() * 1000.0,
+            gpu_timings: self.gpu_profiler.timings().to_vec(),
+        }
+    }
+
+    fn draw_frame(
+        &mut self,
+        window: &Window,
+        camera: Camera,
+        gui: &mut Gui,
     ) -> Result<(), RenderError> {
         log::trace!("渲染帧绘制");
-        let sync_objects = self.in_flight_frames.next().unwrap();
+        let sync_objects = self.frame_sync.next_frame();
         let image_available_semaphore = sync_objects.image_available_semaphore;
         let render_finished_semaphore = sync_objects.render_finished_semaphore;
         let in_flight_fence = sync_objects.fence;
         let wait_fences = [in_flight_fence];
 
-        unsafe {
+        let result = unsafe {
             self.context
                 .device()
                 .wait_for_fences(&wait_fences, true, std::u64::MAX)
-                .unwrap()
         };
+        match result {
+            Ok(_) => {}
+            Err(vk::Result::ERROR_DEVICE_LOST) => return Err(RenderError::DeviceLost),
+            Err(error) => panic!("等待fence失败，由于{}", error),
+        }
 
-        let result = self
-            .swapchain
-            .acquire_next_image(None, Some(image_available_semaphore), None);
+        let swapchain = self
+            .target
+            .swapchain()
+            .expect("无窗口模式请使用render_offscreen！");
+        let result = swapchain.acquire_next_image(None, Some(image_available_semaphore), None);
         let image_index = match result {
             Ok((image_index, _)) => image_index,
             Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
                 return Err(RenderError::DirtySwapchain);
             }
+            Err(vk::Result::ERROR_DEVICE_LOST) => return Err(RenderError::DeviceLost),
             Err(error) => panic!("获取下一个image失败，由于{}", error),
         };
 
+        match self
+            .frame_sync
+            .wait_for_image(image_index as usize, in_flight_fence)
+        {
+            Ok(_) => {}
+            Err(vk::Result::ERROR_DEVICE_LOST) => return Err(RenderError::DeviceLost),
+            Err(error) => panic!("等待fence失败，由于{}", error),
+        }
+
         unsafe { self.context.device().reset_fences(&wait_fences).unwrap() };
 
-        if !self.in_flight_frames.gui_textures_to_free.is_empty() {
-            self.gui_renderer
-                .free_textures(&self.in_flight_frames.gui_textures_to_free)
-                .unwrap();
+        if let Some(gui_renderer) = self.gui_renderer.as_mut() {
+            if !self.frame_sync.gui_textures_to_free.is_empty() {
+                gui_renderer
+                    .free_textures(&self.frame_sync.gui_textures_to_free)
+                    .unwrap();
+            }
         }
 
         let render_data = gui.render(window);
 
-        self.in_flight_frames.gui_textures_to_free = render_data.textures_delta.free;
+        self.frame_sync.gui_textures_to_free = render_data.textures_delta.free;
 
-        self.gui_renderer
-            .set_textures(
-                self.context.graphics_compute_queue(),
-                self.context.transient_command_pool(),
-                &render_data.textures_delta.set,
-            )
-            .unwrap();
+        //上传会直接提交到图形队列，需要和加载线程的提交互斥
+        let queue_guard = self.context.lock_queue();
+        if let Some(gui_renderer) = self.gui_renderer.as_mut() {
+            gui_renderer
+                .set_textures(
+                    self.context.graphics_compute_queue(),
+                    self.context.transient_command_pool(),
+                    &render_data.textures_delta.set,
+                )
+                .unwrap();
+        }
+        drop(queue_guard);
 
         {
             let command_buffer = self.command_buffers[image_index as usize];
             let frame_index = image_index as _;
 
+            self.gpu_profiler.read_results(frame_index);
+            self.update_culling(frame_index, &camera);
+
             unsafe {
                 self.context
                     .device()
@@ -392,6 +819,8 @@ impl Renderer {
                 };
             }
 
+            self.gpu_profiler.cmd_reset(command_buffer, frame_index);
+
             self.cmd_draw(
                 command_buffer,
                 frame_index,
@@ -426,19 +855,34 @@ impl Renderer {
                 .wait_semaphore_infos(std::slice::from_ref(&wait_semaphore_submit_info))
                 .signal_semaphore_infos(std::slice::from_ref(&signal_semaphore_submit_info));
 
-            unsafe {
-                self.context
-                    .synchronization2()
-                    .queue_submit2(
-                        self.context.graphics_compute_queue(),
-                        std::slice::from_ref(&submit_info),
-                        in_flight_fence,
-                    )
-                    .unwrap()
+            let result = unsafe {
+                let _queue_guard = self.context.lock_queue();
+                self.context.synchronization2().queue_submit2(
+                    self.context.graphics_compute_queue(),
+                    std::slice::from_ref(&submit_info),
+                    in_flight_fence,
+                )
             };
+            match result {
+                Ok(_) => {}
+                Err(vk::Result::ERROR_DEVICE_LOST) => return Err(RenderError::DeviceLost),
+                Err(error) => panic!("提交command buffer失败，由于{}", error),
+            }
+        }
+
+        //读回会等待本帧画完，只在点击的那一帧发生
+        if self.pick_request.take().is_some() {
+            let pick_id = self.pick_buffer.download::<u32>()[0];
+            self.picked_node = Some(decode_pick_id(pick_id));
         }
 
-        let swapchains = [self.swapchain.swapchain_khr()];
+        if let Some(buffer) = self.screenshot_buffer.take() {
+            let path = self.screenshot_request.take().unwrap();
+            self.save_screenshot(path, &buffer);
+        }
+
+        let swapchain = self.target.swapchain().unwrap();
+        let swapchains = [swapchain.swapchain_khr()];
         let images_indices = [image_index];
 
         {
@@ -449,10 +893,11 @@ impl Renderer {
                 .swapchains(&swapchains)
                 .image_indices(&images_indices);
 
-            match self.swapchain.present(&present_info) {
+            match swapchain.present(&present_info) {
                 Ok(true) | Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
                     return Err(RenderError::DirtySwapchain)
                 }
+                Err(vk::Result::ERROR_DEVICE_LOST) => return Err(RenderError::DeviceLost),
                 Err(error) => panic!("Failed to present queue. Cause: {}", error),
                 _ => {}
             }
@@ -461,6 +906,71 @@ impl Renderer {
         Ok(())
     }
 
+    //同步渲染一帧到离屏图像并读回RGBA8像素，不绘制UI
+    #[allow(dead_code)]
+    pub fn render_offscreen(&mut self, camera: Camera) -> Vec<u8> {
+        self.render_offscreen_frame(camera).1
+    }
+
+    //离屏渲染是同步的，GPU耗时就是这一帧的结果
+    pub fn render_offscreen_frame(&mut self, camera: Camera) -> (FrameInfo, Vec<u8>) {
+        log::trace!("离屏渲染帧绘制");
+        let start = Instant::now();
+        let command_buffer = self.command_buffers[0];
+        self.update_culling(0, &camera);
+
+        {
+            let command_buffer_begin_info = vk::CommandBufferBeginInfo::builder()
+                .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+            unsafe {
+                self.context
+                    .device()
+                    .begin_command_buffer(command_buffer, &command_buffer_begin_info)
+                    .unwrap()
+            };
+        }
+
+        self.gpu_profiler.cmd_reset(command_buffer, 0);
+        self.cmd_draw(command_buffer, 0, 1.0, &[]);
+
+        unsafe {
+            self.context
+                .device()
+                .end_command_buffer(command_buffer)
+                .unwrap()
+        };
+
+        self.update_ubos(0, camera);
+
+        {
+            let cmd_buffer_submit_info =
+                vk::CommandBufferSubmitInfo::builder().command_buffer(command_buffer);
+            let submit_info = vk::SubmitInfo2::builder()
+                .command_buffer_infos(std::slice::from_ref(&cmd_buffer_submit_info));
+
+            let _queue_guard = self.context.lock_queue();
+            unsafe {
+                self.context
+                    .synchronization2()
+                    .queue_submit2(
+                        self.context.graphics_compute_queue(),
+                        std::slice::from_ref(&submit_info),
+                        vk::Fence::null(),
+                    )
+                    .unwrap()
+            };
+        }
+        self.context.graphics_queue_wait_idle();
+        self.gpu_profiler.read_results(0);
+
+        let pixels = self
+            .target
+            .offscreen_mut()
+            .expect("有窗口时请使用render！")
+            .read_pixels();
+        (self.frame_info(start, Ok(())), pixels)
+    }
+
     fn cmd_draw(
         &mut self,
         command_buffer: vk::CommandBuffer,
@@ -468,7 +978,34 @@ impl Renderer {
         pixels_per_point: f32,
         gui_primitives: &[ClippedPrimitive],
     ) {
-        if self.settings.ssao_enabled {
+        //蒙皮只算一次，后面的gbuffer、阴影和光照pass共用结果
+        for renderer in self.model_renderers.iter() {
+            if let Some(skinning_pass) = renderer.skinning_pass.as_ref() {
+                skinning_pass.cmd_dispatch(command_buffer, frame_index, &renderer.data);
+            }
+        }
+
+        for renderer in self.model_renderers.iter() {
+            if let Some(light_culling_pass) = renderer.light_culling_pass.as_ref() {
+                light_culling_pass.cmd_dispatch(
+                    command_buffer,
+                    frame_index,
+                    &renderer.data,
+                    self.settings.light_cluster_grid,
+                );
+            }
+        }
+
+        if self.settings.occlusion_culling_enabled {
+            self.occlusion_culler
+                .cmd_reset(command_buffer, frame_index, &self.model_renderers);
+        }
+
+        //拾取和描边需要ID缓冲，关闭SSAO时也要画gbuffer
+        let pick_position = self.pick_request;
+        if self.settings.ssao_enabled || pick_position.is_some() || self.is_outline_rendered() {
+            self.gpu_profiler
+                .cmd_begin(command_buffer, frame_index, GpuPass::GBuffer);
             {
                 self.context.cmd_begin_debug_utils_label(
                     command_buffer,
@@ -483,6 +1020,18 @@ impl Renderer {
                             new_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
                             mips_range: MipsRange::All,
                         },
+                        LayoutTransition {
+                            image: &self.attachments.gbuffer_pick_id.image,
+                            old_layout: vk::ImageLayout::UNDEFINED,
+                            new_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                            mips_range: MipsRange::All,
+                        },
+                        LayoutTransition {
+                            image: &self.attachments.gbuffer_motion.image,
+                            old_layout: vk::ImageLayout::UNDEFINED,
+                            new_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                            mips_range: MipsRange::All,
+                        },
                         LayoutTransition {
                             image: &self.attachments.gbuffer_depth.image,
                             old_layout: vk::ImageLayout::UNDEFINED,
@@ -497,257 +1046,230 @@ impl Renderer {
                     height: self.attachments.gbuffer_normals.image.extent.height,
                 };
 
-                unsafe {
-                    self.context.device().cmd_set_viewport(
-                        command_buffer,
-                        0,
-                        &[vk::Viewport {
-                            width: extent.width as _,
-                            height: extent.height as _,
-                            max_depth: 1.0,
-                            ..Default::default()
-                        }],
-                    );
-                    self.context.device().cmd_set_scissor(
-                        command_buffer,
-                        0,
-                        &[vk::Rect2D {
-                            extent,
-                            ..Default::default()
-                        }],
+                cmd_set_viewport_and_scissor(self.context.device(), command_buffer, extent);
+
+                let color_attachments = [
+                    RenderingAttachment::new(
+                        &self.attachments.gbuffer_normals.image,
+                        self.attachments.gbuffer_normals.view,
+                        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
                     )
-                }
+                    .clear_color([0.0, 0.0, 0.0, 1.0]),
+                    //背景的拾取ID为0
+                    RenderingAttachment::new(
+                        &self.attachments.gbuffer_pick_id.image,
+                        self.attachments.gbuffer_pick_id.view,
+                        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                    ),
+                    //背景没有运动
+                    RenderingAttachment::new(
+                        &self.attachments.gbuffer_motion.image,
+                        self.attachments.gbuffer_motion.view,
+                        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                    ),
+                ];
+
+                let depth_attachment = RenderingAttachment::new(
+                    &self.attachments.gbuffer_depth.image,
+                    self.attachments.gbuffer_depth.view,
+                    vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                )
+                .clear_depth(depth_clear_value(self.reverse_z));
 
-                let color_attachment_info = RenderingAttachmentInfo::builder()
-                    .clear_value(vk::ClearValue {
-                        color: vk::ClearColorValue {
-                            float32: [0.0, 0.0, 0.0, 1.0],
-                        },
-                    })
-                    .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
-                    .image_view(self.attachments.gbuffer_normals.view)
-                    .load_op(vk::AttachmentLoadOp::CLEAR)
-                    .store_op(vk::AttachmentStoreOp::STORE);
-
-                let depth_attachment_info = RenderingAttachmentInfo::builder()
-                    .clear_value(vk::ClearValue {
-                        depth_stencil: vk::ClearDepthStencilValue {
-                            depth: 1.0,
-                            stencil: 0,
-                        },
-                    })
-                    .image_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
-                    .image_view(self.attachments.gbuffer_depth.view)
-                    .load_op(vk::AttachmentLoadOp::CLEAR)
-                    .store_op(vk::AttachmentStoreOp::STORE);
-
-                let rendering_info = RenderingInfo::builder()
-                    .color_attachments(std::slice::from_ref(&color_attachment_info))
-                    .depth_attachment(&depth_attachment_info)
-                    .layer_count(1)
-                    .render_area(vk::Rect2D {
-                        offset: vk::Offset2D { x: 0, y: 0 },
+                self.context.cmd_begin_rendering(
+                    command_buffer,
+                    &RenderingDesc {
                         extent,
-                    });
-
-                unsafe {
-                    self.context
-                        .dynamic_rendering()
-                        .cmd_begin_rendering(command_buffer, &rendering_info)
-                };
+                        color_attachments: &color_attachments,
+                        depth_attachment: Some(depth_attachment),
+                    },
+                );
 
-                if let Some(renderer) = self.model_renderer.as_ref() {
-                    renderer
-                        .gbuffer_pass
-                        .cmd_draw(command_buffer, frame_index, &renderer.data);
+                for (index, renderer) in self.model_renderers.iter().enumerate() {
+                    renderer.gbuffer_pass.cmd_draw(
+                        command_buffer,
+                        frame_index,
+                        &renderer.data,
+                        index,
+                    );
                 }
 
-                unsafe {
-                    self.context
-                        .dynamic_rendering()
-                        .cmd_end_rendering(command_buffer)
-                };
+                if self.is_shadow_catcher_rendered() {
+                    self.shadow_catcher_renderer
+                        .cmd_draw_gbuffer(command_buffer, frame_index);
+                }
 
-                self.context.cmd_end_debug_utils_label(command_buffer);
-            }
+                self.context.cmd_end_rendering(command_buffer);
 
-            self.context
-                .cmd_begin_debug_utils_label(command_buffer, CString::new("SSAO Pass").unwrap());
-            cmd_transition_images_layouts(
-                command_buffer,
-                &[
-                    LayoutTransition {
-                        image: &self.attachments.gbuffer_normals.image,
+                cmd_transition_images_layouts(
+                    command_buffer,
+                    &[LayoutTransition {
+                        image: &self.attachments.gbuffer_motion.image,
                         old_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
                         new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
                         mips_range: MipsRange::All,
-                    },
-                    LayoutTransition {
-                        image: &self.attachments.gbuffer_depth.image,
-                        old_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
-                        new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
-                        mips_range: MipsRange::All,
-                    },
-                    LayoutTransition {
-                        image: &self.attachments.ssao.image,
-                        old_layout: vk::ImageLayout::UNDEFINED,
-                        new_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-                        mips_range: MipsRange::All,
-                    },
-                ],
-            );
+                    }],
+                );
 
-            self.ssao_pass.cmd_draw(
-                command_buffer,
-                &self.attachments,
-                &self.quad_model,
-                frame_index,
-            );
+                if let Some((x, y)) = pick_position {
+                    self.cmd_copy_pick_id(command_buffer, x, y);
+                }
 
-            cmd_transition_images_layouts(
-                command_buffer,
-                &[
-                    LayoutTransition {
+                if self.is_outline_rendered() {
+                    let old_layout = if pick_position.is_some() {
+                        vk::ImageLayout::TRANSFER_SRC_OPTIMAL
+                    } else {
+                        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+                    };
+                    cmd_transition_images_layouts(
+                        command_buffer,
+                        &[LayoutTransition {
+                            image: &self.attachments.gbuffer_pick_id.image,
+                            old_layout,
+                            new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                            mips_range: MipsRange::All,
+                        }],
+                    );
+                }
+
+                self.context.cmd_end_debug_utils_label(command_buffer);
+            }
+
+            if self.settings.ssao_enabled {
+                self.context.cmd_begin_debug_utils_label(
+                    command_buffer,
+                    CString::new("SSAO Pass").unwrap(),
+                );
+                cmd_transition_images_layouts(
+                    command_buffer,
+                    &[
+                        LayoutTransition {
+                            image: &self.attachments.gbuffer_normals.image,
+                            old_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                            new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                            mips_range: MipsRange::All,
+                        },
+                        LayoutTransition {
+                            image: &self.attachments.gbuffer_depth.image,
+                            old_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                            new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                            mips_range: MipsRange::All,
+                        },
+                        LayoutTransition {
+                            image: &self.attachments.ssao.image,
+                            old_layout: vk::ImageLayout::UNDEFINED,
+                            new_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                            mips_range: MipsRange::All,
+                        },
+                    ],
+                );
+
+                self.ssao_pass.cmd_draw(
+                    command_buffer,
+                    &self.attachments,
+                    &self.quad_model,
+                    frame_index,
+                );
+
+                cmd_transition_images_layouts(
+                    command_buffer,
+                    &[LayoutTransition {
                         image: &self.attachments.ssao.image,
                         old_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
                         new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
                         mips_range: MipsRange::All,
-                    },
-                    LayoutTransition {
-                        image: &self.attachments.ssao_blur.image,
-                        old_layout: vk::ImageLayout::UNDEFINED,
-                        new_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-                        mips_range: MipsRange::All,
-                    },
-                ],
-            );
+                    }],
+                );
 
-            self.ssao_blur_pass
-                .cmd_draw(command_buffer, &self.attachments, &self.quad_model);
+                if self.settings.ssao_blur_enabled {
+                    cmd_transition_images_layouts(
+                        command_buffer,
+                        &[LayoutTransition {
+                            image: &self.attachments.ssao_blur_temp.image,
+                            old_layout: vk::ImageLayout::UNDEFINED,
+                            new_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                            mips_range: MipsRange::All,
+                        }],
+                    );
 
-            self.context.cmd_end_debug_utils_label(command_buffer);
+                    self.ssao_blur_pass.cmd_draw(
+                        command_buffer,
+                        BlurDirection::Horizontal,
+                        &self.attachments.ssao_blur_temp,
+                        &self.quad_model,
+                    );
+
+                    cmd_transition_images_layouts(
+                        command_buffer,
+                        &[
+                            LayoutTransition {
+                                image: &self.attachments.ssao_blur_temp.image,
+                                old_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                                new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                                mips_range: MipsRange::All,
+                            },
+                            LayoutTransition {
+                                image: &self.attachments.ssao_blur.image,
+                                old_layout: vk::ImageLayout::UNDEFINED,
+                                new_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                                mips_range: MipsRange::All,
+                            },
+                        ],
+                    );
+
+                    self.ssao_blur_pass.cmd_draw(
+                        command_buffer,
+                        BlurDirection::Vertical,
+                        &self.attachments.ssao_blur,
+                        &self.quad_model,
+                    );
+                }
+
+                self.context.cmd_end_debug_utils_label(command_buffer);
+            }
+            self.gpu_profiler
+                .cmd_end(command_buffer, frame_index, GpuPass::GBuffer);
         }
 
         //shadow caster pass
-        {
+        if self.settings.shadow_enabled {
+            self.gpu_profiler
+                .cmd_begin(command_buffer, frame_index, GpuPass::Shadow);
             self.context.cmd_begin_debug_utils_label(
                 command_buffer,
                 CString::new("ShadowCaster Pass").unwrap(),
             );
-            cmd_transition_images_layouts(
+            //每个级联渲染到shadow map数组的一层
+            let cascade_count = self
+                .settings
+                .shadow_cascade_count
+                .clamp(1, MAX_SHADOW_CASCADES);
+            self.cmd_draw_shadow_layers(
                 command_buffer,
-                &[
-                    LayoutTransition {
-                        image: &self.attachments.shadow_caster_color.image,
-                        old_layout: vk::ImageLayout::UNDEFINED,
-                        new_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-                        mips_range: MipsRange::All,
-                    },
-                    LayoutTransition {
-                        image: &self.attachments.shadow_caster_depth.image,
-                        old_layout: vk::ImageLayout::UNDEFINED,
-                        new_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
-                        mips_range: MipsRange::All,
-                    },
-                ],
+                frame_index,
+                &self.attachments.shadow_caster_color,
+                &self.attachments.shadow_caster_depth,
+                &self.attachments.shadow_caster_layers,
+                0,
+                cascade_count,
             );
-
-            let extent = vk::Extent2D {
-                width: self.attachments.shadow_caster_color.image.extent.width,
-                height: self.attachments.shadow_caster_color.image.extent.height,
-            };
-
-            unsafe {
-                self.context.device().cmd_set_viewport(
-                    command_buffer,
-                    0,
-                    &[vk::Viewport {
-                        width: extent.width as _,
-                        height: extent.height as _,
-                        max_depth: 1.0,
-                        ..Default::default()
-                    }],
-                );
-                self.context.device().cmd_set_scissor(
-                    command_buffer,
-                    0,
-                    &[vk::Rect2D {
-                        extent,
-                        ..Default::default()
-                    }],
-                )
-            }
-
-            let color_attachment_info = RenderingAttachmentInfo::builder()
-                .clear_value(vk::ClearValue {
-                    color: vk::ClearColorValue {
-                        float32: [0.0, 0.0, 0.0, 1.0],
-                    },
-                })
-                .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
-                .image_view(self.attachments.shadow_caster_color.view)
-                .load_op(vk::AttachmentLoadOp::CLEAR)
-                .store_op(vk::AttachmentStoreOp::STORE);
-
-            let depth_attachment_info = RenderingAttachmentInfo::builder()
-                .clear_value(vk::ClearValue {
-                    depth_stencil: vk::ClearDepthStencilValue {
-                        depth: 1.0,
-                        stencil: 0,
-                    },
-                })
-                .image_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
-                .image_view(self.attachments.shadow_caster_depth.view)
-                .load_op(vk::AttachmentLoadOp::CLEAR)
-                .store_op(vk::AttachmentStoreOp::STORE);
-
-            let rendering_info = RenderingInfo::builder()
-                .color_attachments(std::slice::from_ref(&color_attachment_info))
-                .depth_attachment(&depth_attachment_info)
-                .layer_count(1)
-                .render_area(vk::Rect2D {
-                    offset: vk::Offset2D { x: 0, y: 0 },
-                    extent,
-                });
-
-            unsafe {
-                self.context
-                    .dynamic_rendering()
-                    .cmd_begin_rendering(command_buffer, &rendering_info)
-            };
-
-            if let Some(renderer) = self.model_renderer.as_ref() {
-                renderer
-                    .shadow_caster_pass
-                    .cmd_draw(command_buffer, frame_index, &renderer.data);
-            }
-
-            unsafe {
-                self.context
-                    .dynamic_rendering()
-                    .cmd_end_rendering(command_buffer)
-            };
-
-            cmd_transition_images_layouts(
+            //点光源和聚光灯的矩阵排在级联矩阵之后
+            self.cmd_draw_shadow_layers(
                 command_buffer,
-                &[
-                    LayoutTransition {
-                        image: &self.attachments.shadow_caster_color.image,
-                        old_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-                        new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
-                        mips_range: MipsRange::All,
-                    },
-                    LayoutTransition {
-                        image: &self.attachments.shadow_caster_depth.image,
-                        old_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
-                        new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
-                        mips_range: MipsRange::All,
-                    },
-                ],
+                frame_index,
+                &self.attachments.punctual_shadow_color,
+                &self.attachments.punctual_shadow_depth,
+                &self.attachments.punctual_shadow_layers,
+                MAX_SHADOW_CASCADES,
+                self.punctual_shadow_layer_count,
             );
             self.context.cmd_end_debug_utils_label(command_buffer);
+            self.gpu_profiler
+                .cmd_end(command_buffer, frame_index, GpuPass::Shadow);
         }
 
+        self.gpu_profiler
+            .cmd_begin(command_buffer, frame_index, GpuPass::Light);
         let mut transitions = vec![
             LayoutTransition {
                 image: &self.attachments.get_scene_resolved_color().image,
@@ -762,7 +1284,15 @@ impl Renderer {
                 mips_range: MipsRange::All,
             },
         ];
-        if self.settings.ssao_enabled {
+        if let Some(depth_resolve) = self.attachments.scene_depth_resolve.as_ref() {
+            transitions.push(LayoutTransition {
+                image: &depth_resolve.image,
+                old_layout: vk::ImageLayout::UNDEFINED,
+                new_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                mips_range: MipsRange::All,
+            });
+        }
+        if self.settings.ssao_enabled && self.settings.ssao_blur_enabled {
             transitions.push(LayoutTransition {
                 image: &self.attachments.ssao_blur.image,
                 old_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
@@ -778,103 +1308,162 @@ impl Renderer {
                 height: self.attachments.scene_color.image.extent.height,
             };
 
-            unsafe {
-                self.context.device().cmd_set_viewport(
-                    command_buffer,
-                    0,
-                    &[vk::Viewport {
-                        width: extent.width as _,
-                        height: extent.height as _,
-                        max_depth: 1.0,
-                        ..Default::default()
-                    }],
-                );
-                self.context.device().cmd_set_scissor(
-                    command_buffer,
-                    0,
-                    &[vk::Rect2D {
-                        extent,
-                        ..Default::default()
-                    }],
-                )
-            }
+            cmd_set_viewport_and_scissor(self.context.device(), command_buffer, extent);
 
             {
                 self.context.cmd_begin_debug_utils_label(
                     command_buffer,
                     CString::new("Forward Pass").unwrap(),
                 );
-                let mut color_attachment_info = RenderingAttachmentInfo::builder()
-                    .clear_value(vk::ClearValue {
-                        color: vk::ClearColorValue {
-                            float32: [1.0, 0.0, 0.0, 1.0],
-                        },
-                    })
-                    .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
-                    .image_view(self.attachments.scene_color.view)
-                    .load_op(vk::AttachmentLoadOp::CLEAR)
-                    .store_op(vk::AttachmentStoreOp::STORE);
+                let mut color_attachment = RenderingAttachment::new(
+                    &self.attachments.scene_color.image,
+                    self.attachments.scene_color.view,
+                    vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                )
+                .clear_color(self.settings.clear_color);
 
                 if let Some(resolve_attachment) = self.attachments.scene_resolve.as_ref() {
-                    color_attachment_info = color_attachment_info
-                        .resolve_image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
-                        .resolve_mode(vk::ResolveModeFlags::AVERAGE_KHR)
-                        .resolve_image_view(resolve_attachment.view)
+                    color_attachment = color_attachment.resolve(
+                        resolve_attachment.view,
+                        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                        vk::ResolveModeFlags::AVERAGE_KHR,
+                    );
                 }
 
-                let depth_attachment_info = RenderingAttachmentInfo::builder()
-                    .clear_value(vk::ClearValue {
-                        depth_stencil: vk::ClearDepthStencilValue {
-                            depth: 1.0,
-                            stencil: 0,
-                        },
-                    })
-                    .image_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
-                    .image_view(self.attachments.scene_depth.view)
-                    .load_op(vk::AttachmentLoadOp::CLEAR)
-                    .store_op(vk::AttachmentStoreOp::STORE);
-
-                let rendering_info = RenderingInfo::builder()
-                    .color_attachments(std::slice::from_ref(&color_attachment_info))
-                    .depth_attachment(&depth_attachment_info)
-                    .layer_count(1)
-                    .render_area(vk::Rect2D {
-                        offset: vk::Offset2D { x: 0, y: 0 },
+                let mut depth_attachment = RenderingAttachment::new(
+                    &self.attachments.scene_depth.image,
+                    self.attachments.scene_depth.view,
+                    vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                )
+                .clear_depth(depth_clear_value(self.reverse_z));
+
+                //多重采样的深度不能直接采样，resolve出单采样深度给屏幕空间的pass使用
+                if let Some(depth_resolve) = self.attachments.scene_depth_resolve.as_ref() {
+                    depth_attachment = depth_attachment.resolve(
+                        depth_resolve.view,
+                        vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                        self.depth_resolve_mode,
+                    );
+                }
+
+                self.context.cmd_begin_rendering(
+                    command_buffer,
+                    &RenderingDesc {
                         extent,
-                    });
+                        color_attachments: std::slice::from_ref(&color_attachment),
+                        depth_attachment: Some(depth_attachment),
+                    },
+                );
+            }
 
-                unsafe {
-                    self.context
-                        .dynamic_rendering()
-                        .cmd_begin_rendering(command_buffer, &rendering_info)
-                };
+            //正交投影下所有视线平行，天空盒只剩单一颜色，干脆不画
+            if self.settings.skybox_enabled && !self.orthographic {
+                self.context.cmd_begin_debug_utils_label(
+                    command_buffer,
+                    CString::new("SkyBox Pass").unwrap(),
+                );
+                self.skybox_renderer.cmd_draw(
+                    command_buffer,
+                    frame_index,
+                    self.settings.skybox_exposure,
+                    self.settings.skybox_blur,
+                );
+                self.context.cmd_end_debug_utils_label(command_buffer);
             }
 
-            self.context
-                .cmd_begin_debug_utils_label(command_buffer, CString::new("SkyBox Pass").unwrap());
-            self.skybox_renderer.cmd_draw(command_buffer, frame_index);
-            self.context.cmd_end_debug_utils_label(command_buffer);
+            if !self.model_renderers.is_empty() {
+                let depth_prepass = self.settings.depth_prepass_enabled
+                    && self.settings.polygon_mode == PolygonMode::Fill;
+                if depth_prepass {
+                    self.context.cmd_begin_debug_utils_label(
+                        command_buffer,
+                        CString::new("Depth Prepass").unwrap(),
+                    );
+                    for renderer in self.model_renderers.iter() {
+                        renderer.light_pass.cmd_draw_depth_prepass(
+                            command_buffer,
+                            frame_index,
+                            &renderer.data,
+                        );
+                    }
+                    self.context.cmd_end_debug_utils_label(command_buffer);
+                }
 
-            if let Some(renderer) = self.model_renderer.as_ref() {
                 self.context.cmd_begin_debug_utils_label(
                     command_buffer,
                     CString::new("Model Light Pass").unwrap(),
                 );
-                renderer
-                    .light_pass
-                    .cmd_draw(command_buffer, frame_index, &renderer.data);
+                for renderer in self.model_renderers.iter() {
+                    renderer.light_pass.cmd_draw(
+                        command_buffer,
+                        frame_index,
+                        &renderer.data,
+                        self.camera_position,
+                        depth_prepass,
+                    );
+                }
+                self.context.cmd_end_debug_utils_label(command_buffer);
+
+                //用画完模型的深度测试包围盒，结果供之后的帧剔除
+                if self.settings.occlusion_culling_enabled {
+                    self.context.cmd_begin_debug_utils_label(
+                        command_buffer,
+                        CString::new("Occlusion Query Pass").unwrap(),
+                    );
+                    self.occlusion_culler.cmd_draw(
+                        command_buffer,
+                        frame_index,
+                        &self.model_renderers,
+                        self.camera_position,
+                        self.camera_near_far.0,
+                    );
+                    self.context.cmd_end_debug_utils_label(command_buffer);
+                }
+            }
+
+            if self.is_shadow_catcher_rendered() {
+                self.context.cmd_begin_debug_utils_label(
+                    command_buffer,
+                    CString::new("Shadow Catcher Pass").unwrap(),
+                );
+                self.shadow_catcher_renderer
+                    .cmd_draw(command_buffer, frame_index);
                 self.context.cmd_end_debug_utils_label(command_buffer);
             }
 
-            unsafe {
-                self.context
-                    .dynamic_rendering()
-                    .cmd_end_rendering(command_buffer)
-            };
+            if self.settings.grid_enabled || self.settings.axes_enabled {
+                self.context.cmd_begin_debug_utils_label(
+                    command_buffer,
+                    CString::new("Grid Pass").unwrap(),
+                );
+                self.grid_renderer.cmd_draw(
+                    command_buffer,
+                    frame_index,
+                    self.settings.grid_enabled,
+                    self.settings.axes_enabled,
+                );
+                self.context.cmd_end_debug_utils_label(command_buffer);
+            }
+
+            self.context.cmd_end_rendering(command_buffer);
             self.context.cmd_end_debug_utils_label(command_buffer);
+
+            cmd_transition_images_layouts(
+                command_buffer,
+                &[LayoutTransition {
+                    image: &self.attachments.get_scene_resolved_depth().image,
+                    old_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                    new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    mips_range: MipsRange::All,
+                }],
+            );
         }
+        self.gpu_profiler
+            .cmd_end(command_buffer, frame_index, GpuPass::Light);
 
-        {
+        self.gpu_profiler
+            .cmd_begin(command_buffer, frame_index, GpuPass::PostProcess);
+        if self.settings.bloom_enabled {
             self.context
                 .cmd_begin_debug_utils_label(command_buffer, CString::new("Bloom Pass").unwrap());
             self.bloom_pass
@@ -882,13 +1471,16 @@ impl Renderer {
             self.context.cmd_end_debug_utils_label(command_buffer);
         }
 
-        {
-            self.context
-                .cmd_begin_debug_utils_label(command_buffer, CString::new("FXAA Pass").unwrap());
+        //开启抗锯齿时先tone map到中间图像
+        if self.settings.anti_aliasing_mode != AntiAliasingMode::None {
+            self.context.cmd_begin_debug_utils_label(
+                command_buffer,
+                CString::new("Tone Map Pass").unwrap(),
+            );
             cmd_transition_images_layouts(
                 command_buffer,
                 &[LayoutTransition {
-                    image: &self.attachments.fxaa.image,
+                    image: &self.attachments.ldr_color.image,
                     old_layout: vk::ImageLayout::UNDEFINED,
                     new_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
                     mips_range: MipsRange::All,
@@ -896,66 +1488,34 @@ impl Renderer {
             );
 
             let extent = vk::Extent2D {
-                width: self.attachments.fxaa.image.extent.width,
-                height: self.attachments.fxaa.image.extent.height,
+                width: self.attachments.ldr_color.image.extent.width,
+                height: self.attachments.ldr_color.image.extent.height,
             };
 
-            unsafe {
-                self.context.device().cmd_set_viewport(
-                    command_buffer,
-                    0,
-                    &[vk::Viewport {
-                        width: extent.width as _,
-                        height: extent.height as _,
-                        max_depth: 1.0,
-                        ..Default::default()
-                    }],
-                );
-                self.context.device().cmd_set_scissor(
-                    command_buffer,
-                    0,
-                    &[vk::Rect2D {
-                        extent,
-                        ..Default::default()
-                    }],
-                )
-            }
+            cmd_set_viewport_and_scissor(self.context.device(), command_buffer, extent);
 
-            let color_attachment_info = RenderingAttachmentInfo::builder()
-                .clear_value(vk::ClearValue {
-                    color: vk::ClearColorValue {
-                        float32: [0.0, 0.0, 0.0, 1.0],
-                    },
-                })
-                .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
-                .image_view(self.attachments.fxaa.view)
-                .load_op(vk::AttachmentLoadOp::CLEAR)
-                .store_op(vk::AttachmentStoreOp::STORE);
-
-            let rendering_info = RenderingInfo::builder()
-                .color_attachments(std::slice::from_ref(&color_attachment_info))
-                .layer_count(1)
-                .render_area(vk::Rect2D {
-                    offset: vk::Offset2D { x: 0, y: 0 },
-                    extent,
-                });
+            let color_attachment = RenderingAttachment::new(
+                &self.attachments.ldr_color.image,
+                self.attachments.ldr_color.view,
+                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            )
+            .clear_color([0.0, 0.0, 0.0, 1.0]);
 
-            unsafe {
-                self.context
-                    .dynamic_rendering()
-                    .cmd_begin_rendering(command_buffer, &rendering_info)
-            };
+            self.context.cmd_begin_rendering(
+                command_buffer,
+                &RenderingDesc {
+                    extent,
+                    color_attachments: std::slice::from_ref(&color_attachment),
+                    depth_attachment: None,
+                },
+            );
 
-            self.fxaa_pass.cmd_draw(command_buffer, &self.quad_model);
+            self.final_pass.cmd_draw(command_buffer, &self.quad_model);
 
-            unsafe {
-                self.context
-                    .dynamic_rendering()
-                    .cmd_end_rendering(command_buffer)
-            };
+            self.context.cmd_end_rendering(command_buffer);
 
             self.attachments
-                .fxaa
+                .ldr_color
                 .image
                 .cmd_transition_image_mips_layout(
                     command_buffer,
@@ -968,7 +1528,7 @@ impl Renderer {
         }
 
         {
-            self.swapchain.images()[frame_index].cmd_transition_image_layout(
+            self.target.image(frame_index).cmd_transition_image_layout(
                 command_buffer,
                 vk::ImageLayout::UNDEFINED,
                 vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
@@ -978,199 +1538,396 @@ impl Renderer {
         {
             self.context
                 .cmd_begin_debug_utils_label(command_buffer, CString::new("Uber Pass").unwrap());
-            let extent = self.swapchain.properties().extent;
+            let extent = self.target.extent();
 
-            unsafe {
-                self.context.device().cmd_set_viewport(
-                    command_buffer,
-                    0,
-                    &[vk::Viewport {
-                        width: extent.width as _,
-                        height: extent.height as _,
-                        max_depth: 1.0,
-                        ..Default::default()
-                    }],
-                );
-                self.context.device().cmd_set_scissor(
+            cmd_set_viewport_and_scissor(self.context.device(), command_buffer, extent);
+
+            {
+                let color_attachment = RenderingAttachment::new(
+                    self.target.image(frame_index),
+                    self.target.image_view(frame_index),
+                    vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                )
+                .clear_color([0.0, 0.0, 0.0, 1.0]);
+
+                self.context.cmd_begin_rendering(
                     command_buffer,
-                    0,
-                    &[vk::Rect2D {
+                    &RenderingDesc {
                         extent,
-                        ..Default::default()
-                    }],
-                )
+                        color_attachments: std::slice::from_ref(&color_attachment),
+                        depth_attachment: None,
+                    },
+                );
             }
 
-            {
-                let color_attachment_info = RenderingAttachmentInfo::builder()
-                    .clear_value(vk::ClearValue {
-                        color: vk::ClearColorValue {
-                            float32: [0.0, 0.0, 0.0, 1.0],
-                        },
-                    })
-                    .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
-                    .image_view(self.swapchain.image_views()[frame_index])
-                    .load_op(vk::AttachmentLoadOp::CLEAR)
-                    .store_op(vk::AttachmentStoreOp::STORE);
-
-                let rendering_info = RenderingInfo::builder()
-                    .color_attachments(std::slice::from_ref(&color_attachment_info))
-                    .layer_count(1)
-                    .render_area(vk::Rect2D {
-                        offset: vk::Offset2D { x: 0, y: 0 },
-                        extent,
-                    });
+            match self.settings.anti_aliasing_mode {
+                AntiAliasingMode::None => {
+                    self.context.cmd_begin_debug_utils_label(
+                        command_buffer,
+                        CString::new("Final Pass").unwrap(),
+                    );
+                    self.final_pass.cmd_draw(command_buffer, &self.quad_model);
+                }
+                AntiAliasingMode::FXAA => {
+                    self.context.cmd_begin_debug_utils_label(
+                        command_buffer,
+                        CString::new("FXAA Pass").unwrap(),
+                    );
+                    self.fxaa_pass.cmd_draw(command_buffer, &self.quad_model);
+                }
+            }
+            self.context.cmd_end_debug_utils_label(command_buffer);
 
-                unsafe {
-                    self.context
-                        .dynamic_rendering()
-                        .cmd_begin_rendering(command_buffer, &rendering_info)
-                };
+            if self.is_debug_attachment_rendered() {
+                self.context.cmd_begin_debug_utils_label(
+                    command_buffer,
+                    CString::new("Debug Overlay Pass").unwrap(),
+                );
+                self.debug_overlay_pass
+                    .cmd_draw(command_buffer, &self.quad_model, extent);
+                self.context.cmd_end_debug_utils_label(command_buffer);
             }
 
-            self.context
-                .cmd_begin_debug_utils_label(command_buffer, CString::new("Final Pass").unwrap());
-            self.final_pass.cmd_draw(command_buffer, &self.quad_model);
-            self.context.cmd_end_debug_utils_label(command_buffer);
+            if let Some(selected_id) = self.selected_pick_id() {
+                self.context.cmd_begin_debug_utils_label(
+                    command_buffer,
+                    CString::new("Outline Pass").unwrap(),
+                );
+                self.outline_pass.cmd_draw(
+                    command_buffer,
+                    &self.quad_model,
+                    selected_id,
+                    self.settings.outline_color,
+                    self.settings.outline_thickness,
+                );
+                self.context.cmd_end_debug_utils_label(command_buffer);
+            }
+
+            self.gpu_profiler
+                .cmd_end(command_buffer, frame_index, GpuPass::PostProcess);
 
+            self.gpu_profiler
+                .cmd_begin(command_buffer, frame_index, GpuPass::UI);
             self.context
                 .cmd_begin_debug_utils_label(command_buffer, CString::new("UI Pass").unwrap());
-            self.gui_renderer
-                .cmd_draw(command_buffer, extent, pixels_per_point, gui_primitives)
-                .unwrap();
+            if let Some(gui_renderer) = self.gui_renderer.as_mut() {
+                gui_renderer
+                    .cmd_draw(command_buffer, extent, pixels_per_point, gui_primitives)
+                    .unwrap();
+            }
             self.context.cmd_end_debug_utils_label(command_buffer);
+            self.gpu_profiler
+                .cmd_end(command_buffer, frame_index, GpuPass::UI);
 
-            unsafe {
-                self.context
-                    .dynamic_rendering()
-                    .cmd_end_rendering(command_buffer)
-            };
+            self.context.cmd_end_rendering(command_buffer);
             self.context.cmd_end_debug_utils_label(command_buffer);
         }
 
-        {
-            self.swapchain.images()[frame_index].cmd_transition_image_layout(
-                command_buffer,
-                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-                vk::ImageLayout::PRESENT_SRC_KHR,
+        if self.screenshot_request.is_some() {
+            //每次截图按当前尺寸新建，交换链重建后尺寸可能已经变了
+            let extent = self.target.extent();
+            let buffer = Buffer::create(
+                Arc::clone(&self.context),
+                (extent.width * extent.height * 4) as _,
+                vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::TRANSFER_SRC,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                "Screenshot.Buffer",
             );
+            self.target
+                .cmd_finish_with_copy(command_buffer, frame_index, &buffer);
+            self.screenshot_buffer = Some(buffer);
+        } else {
+            self.target.cmd_finish(command_buffer, frame_index);
         }
     }
 
-    pub fn set_model(&mut self, model: &Rc<RefCell<Model>>) {
-        let model_data = ModelData::create(
+    pub fn add_model(&mut self, model: &Rc<RefCell<Model>>) {
+        let mut model_data = ModelData::create(
             Arc::clone(&self.context),
             Rc::downgrade(model),
-            self.swapchain.image_count() as u32,
+            self.target.image_count() as u32,
         );
+        model_data.set_gpu_skinning_enabled(self.settings.gpu_skinning_enabled);
 
         let ao_map = self
             .settings
             .ssao_enabled
-            .then(|| &self.attachments.ssao_blur);
-        let shadow_map = Some(&self.attachments.shadow_caster_color);
+            .then(|| self.attachments.get_ao_map(self.settings.ssao_blur_enabled));
+        let shadow_maps = self
+            .settings
+            .shadow_enabled
+            .then(|| self.attachments.get_shadow_maps());
 
-        if let Some(model_renderer) = self.model_renderer.as_mut() {
-            model_renderer
-                .gbuffer_pass
-                .set_model(&model_data, &self.camera_uniform_buffers);
+        let gbuffer_pass = GBufferPass::create(
+            Arc::clone(&self.context),
+            &model_data,
+            &self.camera_uniform_buffers,
+            self.depth_format,
+            self.reverse_z,
+        );
 
-            model_renderer
-                .shadow_caster_pass
-                .set_model(&model_data, &self.light_uniform_buffers);
+        let shadow_caster_pass = ShadowCasterPass::create(
+            Arc::clone(&self.context),
+            &model_data,
+            &self.shadow_uniform_buffers,
+            self.depth_format,
+        );
 
-            model_renderer.light_pass.set_model(
-                &model_data,
-                &self.camera_uniform_buffers,
-                &self.environment,
-                ao_map,
-                shadow_map,
-            );
+        let light_pass = LightPass::create(
+            Arc::clone(&self.context),
+            &model_data,
+            &self.camera_uniform_buffers,
+            &self.environment,
+            ao_map,
+            shadow_maps,
+            self.msaa_samples,
+            self.depth_format,
+            self.reverse_z,
+            self.settings,
+        );
 
-            model_renderer.data = model_data;
-        } else {
-            let gbuffer_pass = GBufferPass::create(
-                Arc::clone(&self.context),
-                &model_data,
-                &self.camera_uniform_buffers,
-                self.depth_format,
-            );
+        let skinning_pass = SkinningPass::create(Arc::clone(&self.context), &model_data);
+
+        let light_culling_pass = LightCullingPass::create(
+            Arc::clone(&self.context),
+            &model_data,
+            &self.camera_uniform_buffers,
+        );
+
+        self.model_renderers.push(ModelRenderer {
+            data: model_data,
+            gbuffer_pass,
+            shadow_caster_pass,
+            light_pass,
+            skinning_pass,
+            light_culling_pass,
+        });
+    }
 
-            let shadow_caster_pass = ShadowCasterPass::create(
-                Arc::clone(&self.context),
-                &model_data,
-                &self.light_uniform_buffers,
-                self.depth_format,
-            );
+    //替换天空盒和IBL贴图，需要重建引用了旧贴图的descriptor
+    pub fn set_environment(&mut self, environment: Environment) {
+        self.wait_idle_gpu();
+        self.environment = environment;
+        self.skybox_renderer
+            .set_environment(&self.camera_uniform_buffers, &self.environment);
 
-            let light_pass = LightPass::create(
-                Arc::clone(&self.context),
-                &model_data,
+        let ao_map = self
+            .settings
+            .ssao_enabled
+            .then(|| self.attachments.get_ao_map(self.settings.ssao_blur_enabled));
+        let shadow_maps = self
+            .settings
+            .shadow_enabled
+            .then(|| self.attachments.get_shadow_maps());
+        for renderer in self.model_renderers.iter_mut() {
+            renderer.light_pass.set_model(
+                &renderer.data,
                 &self.camera_uniform_buffers,
                 &self.environment,
                 ao_map,
-                shadow_map,
-                self.msaa_samples,
-                self.depth_format,
-                self.settings,
+                shadow_maps,
             );
+        }
+    }
 
-            self.model_renderer = Some(ModelRenderer {
-                data: model_data,
-                gbuffer_pass,
-                shadow_caster_pass,
-                light_pass,
-            });
+    //替换场景中的所有模型
+    pub fn set_model(&mut self, model: &Rc<RefCell<Model>>) {
+        self.clear_models();
+        self.add_model(model);
+    }
+
+    pub fn remove_model(&mut self, index: usize) {
+        if index < self.model_renderers.len() {
+            self.model_renderers.remove(index);
         }
     }
 
-    pub fn recreate_swapchain(&mut self, dimensions: [u32; 2], vsync: bool) {
+    pub fn clear_models(&mut self) {
+        self.model_renderers.clear();
+    }
+
+    pub fn model_count(&self) -> usize {
+        self.model_renderers.len()
+    }
+
+    //失败时旧交换链已经销毁，调用方需要重建设备
+    pub fn recreate_swapchain(&mut self, dimensions: [u32; 2], vsync: bool) -> Result<(), VkError> {
         log::debug!("重新创建交换链");
+        let start = Instant::now();
 
         self.wait_idle_gpu();
 
-        self.destroy_swapchain();
+        let image_count = self.target.image_count();
+        self.target.destroy();
 
         let swapchain_support_details = SwapchainSupportDetails::new(
             self.context.physical_device(),
             self.context.surface(),
             self.context.surface_khr(),
-        );
+        )?;
 
-        self.swapchain = Swapchain::create(
+        self.target = RenderTarget::Swapchain(Swapchain::create(
             Arc::clone(&self.context),
             swapchain_support_details,
             dimensions,
             vsync,
-        );
+        )?);
 
+        //pipeline使用动态viewport和scissor，只需要重建和分辨率相关的资源
         self.on_new_swapchain();
+        self.frame_sync.reset_images(self.target.image_count());
+
+        //图像数量不变时沿用每帧的command buffer和时间戳查询池
+        if self.target.image_count() != image_count {
+            self.free_command_buffers();
+            self.command_buffers =
+                allocate_command_buffers(&self.context, self.target.image_count());
+
+            let profiling_enabled = self.gpu_profiler.is_enabled();
+            self.gpu_profiler =
+                GpuProfiler::create(Arc::clone(&self.context), self.target.image_count());
+            self.gpu_profiler.set_enabled(profiling_enabled);
+        }
+
+        log::info!("交换链重建耗时{}ms", start.elapsed().as_millis());
+        Ok(())
+    }
+
+    pub fn settings(&self) -> RendererSettings {
+        self.settings
+    }
+
+    //关闭时不写时间戳
+    pub fn set_gpu_profiling_enabled(&mut self, enabled: bool) {
+        self.gpu_profiler.set_enabled(enabled);
+    }
+
+    //在下一帧拾取窗口像素坐标(x, y)处的节点，超出画面时忽略
+    pub fn request_pick(&mut self, x: u32, y: u32) {
+        //窗口坐标换算到缩放后的ID缓冲上
+        let target_extent = self.target.extent();
+        let extent = self.attachments.gbuffer_pick_id.image.extent;
+        let x = (x as u64 * extent.width as u64 / target_extent.width.max(1) as u64) as u32;
+        let y = (y as u64 * extent.height as u64 / target_extent.height.max(1) as u64) as u32;
+        if x < extent.width && y < extent.height {
+            self.pick_request = Some((x, y));
+        }
+    }
 
-        self.command_buffers =
-            allocate_command_buffers(&self.context, self.swapchain.image_count());
+    //取出上一次拾取的结果，外层None表示没有新结果，内层None表示点中了背景，
+    //否则为(模型序号, 节点序号)
+    pub fn take_picked_node(&mut self) -> Option<Option<(usize, usize)>> {
+        self.picked_node.take()
     }
 
+    //在下一帧把画面保存为PNG，表面不支持拷贝交换链image时忽略
+    pub fn request_screenshot(&mut self, path: PathBuf) {
+        if self.target.is_readable() {
+            self.screenshot_request = Some(path);
+        } else {
+            log::warn!("当前表面不支持读取交换链image，无法截图");
+        }
+    }
+
+    //和拾取一样等本帧画完再读回，编码和写文件放到后台线程
+    fn save_screenshot(&self, path: PathBuf, buffer: &Buffer) {
+        let extent = self.target.extent();
+        let mut pixels = buffer.download::<u8>();
+        pixels_to_rgba8(self.target.format(), &mut pixels);
+        std::thread::spawn(move || {
+            match image::save_buffer(
+                &path,
+                &pixels,
+                extent.width,
+                extent.height,
+                image::ColorType::Rgba8,
+            ) {
+                Ok(()) => log::info!("截图已保存到{}", path.display()),
+                Err(error) => log::error!("保存截图{}失败: {}", path.display(), error),
+            }
+        });
+    }
+
+    fn cmd_copy_pick_id(&self, command_buffer: vk::CommandBuffer, x: u32, y: u32) {
+        let image = &self.attachments.gbuffer_pick_id.image;
+        cmd_transition_images_layouts(
+            command_buffer,
+            &[LayoutTransition {
+                image,
+                old_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                new_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                mips_range: MipsRange::All,
+            }],
+        );
+        image.cmd_copy_texel_to_buffer(command_buffer, &self.pick_buffer, x, y);
+    }
+
+    //层级面板或视口中选中的节点，为None时不描边
+    pub fn set_selected_node(&mut self, selected_node: Option<(usize, usize)>) {
+        self.selected_node = selected_node;
+    }
+
+    fn selected_pick_id(&self) -> Option<u32> {
+        if !self.settings.outline_enabled {
+            return None;
+        }
+        self.selected_node
+            .filter(|(model_index, _)| *model_index < self.model_renderers.len())
+            .map(|(model_index, node_index)| model_pick_id(model_index) | node_pick_id(node_index))
+    }
+
+    fn is_outline_rendered(&self) -> bool {
+        self.selected_pick_id().is_some()
+    }
+
+    pub fn culling_stats(&self) -> CullingStats {
+        self.culling_stats
+    }
+
+    pub fn scene_stats(&self) -> SceneStats {
+        SceneStats {
+            texture_count: self
+                .model_renderers
+                .iter()
+                .map(|renderer| renderer.data.texture_count())
+                .sum(),
+            allocated_memory: self.context.allocated_memory(),
+        }
+    }
+
+    //设备丢失后已经没有需要等待的工作，直接返回
     pub fn wait_idle_gpu(&self) {
-        unsafe { self.context.device().device_wait_idle().unwrap() };
+        let _queue_guard = self.context.lock_queue();
+        match unsafe { self.context.device().device_wait_idle() } {
+            Ok(_) | Err(vk::Result::ERROR_DEVICE_LOST) => {}
+            Err(error) => panic!("等待设备空闲失败，由于{}", error),
+        }
     }
 
+    //交换链重建失败后Drop时会再次调用
     fn destroy_swapchain(&mut self) {
-        unsafe {
-            self.context
-                .device()
-                .free_command_buffers(self.context.general_command_pool(), &self.command_buffers);
+        self.free_command_buffers();
+        self.target.destroy();
+    }
+
+    fn free_command_buffers(&mut self) {
+        let command_buffers = std::mem::take(&mut self.command_buffers);
+        if !command_buffers.is_empty() {
+            unsafe {
+                self.context
+                    .device()
+                    .free_command_buffers(self.context.general_command_pool(), &command_buffers);
+            }
         }
-        self.swapchain.destroy();
     }
 
     fn on_new_swapchain(&mut self) {
-        let swapchain_properties = self.swapchain.properties();
-
-        self.attachments = Attachments::new(
+        self.attachments.resize(
             &self.context,
-            swapchain_properties.extent,
+            scaled_extent(self.target.extent(), self.settings.render_scale),
             self.depth_format,
             self.msaa_samples,
+            self.target.format(),
         );
 
         self.ssao_pass.set_inputs(
@@ -1178,23 +1935,24 @@ impl Renderer {
             &self.attachments.gbuffer_depth,
         );
 
-        self.ssao_blur_pass.set_input_image(&self.attachments.ssao);
+        self.ssao_blur_pass.set_input_images(
+            &self.attachments.ssao,
+            &self.attachments.ssao_blur_temp,
+            &self.attachments.gbuffer_depth,
+        );
 
-        if let Some(renderer) = self.model_renderer.as_mut() {
-            let ao_map = if self.settings.ssao_enabled {
-                Some(&self.attachments.ssao_blur)
-            } else {
-                None
-            };
-            let shadow_map = Some(&self.attachments.shadow_caster_color);
-            renderer.light_pass.set_map(ao_map, shadow_map);
-        }
+        self.update_light_pass_maps();
 
         self.bloom_pass.set_attachments(&self.attachments);
 
         self.fxaa_pass.set_attachments(&self.attachments);
 
         self.final_pass.set_attachments(&self.attachments);
+
+        self.debug_overlay_pass
+            .set_attachment(&self.attachments, self.settings.debug_attachment);
+
+        self.outline_pass.set_attachments(&self.attachments);
     }
 
     pub fn update_settings(&mut self, settings: RendererSettings) {
@@ -1209,9 +1967,27 @@ impl Renderer {
         if self.settings.fxaa_mode != settings.fxaa_mode {
             self.set_fxaa_mode(settings.fxaa_mode);
         }
+        if (self.settings.sharpness - settings.sharpness).abs() > f32::EPSILON {
+            self.set_sharpness(settings.sharpness);
+        }
+        self.settings.anti_aliasing_mode = settings.anti_aliasing_mode;
         if self.settings.output_mode != settings.output_mode {
             self.set_output_mode(settings.output_mode);
         }
+        if self.settings.ibl_debug_roughness != settings.ibl_debug_roughness {
+            self.set_ibl_debug_roughness(settings.ibl_debug_roughness);
+        }
+        if self.settings.polygon_mode != settings.polygon_mode {
+            self.set_polygon_mode(settings.polygon_mode);
+        }
+        if self.settings.light_cluster_grid != settings.light_cluster_grid {
+            self.set_light_cluster_grid(settings.light_cluster_grid);
+        }
+        if self.settings.parallax_enabled != settings.parallax_enabled
+            || self.settings.parallax_steps != settings.parallax_steps
+        {
+            self.set_parallax(settings.parallax_enabled, settings.parallax_steps);
+        }
         if self.settings.ssao_enabled != settings.ssao_enabled {
             self.enabled_ssao(settings.ssao_enabled);
         }
@@ -1224,9 +2000,24 @@ impl Renderer {
         if (self.settings.ssao_strength - settings.ssao_strength).abs() > f32::EPSILON {
             self.set_ssao_strength(settings.ssao_strength);
         }
+        if self.settings.ssao_blur_enabled != settings.ssao_blur_enabled {
+            self.enabled_ssao_blur(settings.ssao_blur_enabled);
+        }
+        if self.settings.ssao_blur_radius != settings.ssao_blur_radius {
+            self.set_ssao_blur_radius(settings.ssao_blur_radius);
+        }
+        if self.settings.ssao_range_check_enabled != settings.ssao_range_check_enabled {
+            self.enabled_ssao_range_check(settings.ssao_range_check_enabled);
+        }
         if (self.settings.bloom_strength - settings.bloom_strength).abs() > f32::EPSILON {
             self.set_bloom_strength(settings.bloom_strength);
         }
+        if (self.settings.bloom_threshold - settings.bloom_threshold).abs() > f32::EPSILON {
+            self.set_bloom_threshold(settings.bloom_threshold);
+        }
+        if self.settings.bloom_mip_levels != settings.bloom_mip_levels {
+            self.set_bloom_mip_levels(settings.bloom_mip_levels);
+        }
         if (self.settings.absolute_luminance_threshold - settings.absolute_luminance_threshold)
             .abs()
             > f32::EPSILON
@@ -1258,11 +2049,82 @@ impl Renderer {
         if (self.settings.fog_start - settings.fog_start).abs() > f32::EPSILON {
             self.set_fog_start(settings.fog_start);
         }
+        if self.settings.shadow_enabled != settings.shadow_enabled {
+            self.enabled_shadow(settings.shadow_enabled);
+        }
+        if self.settings.bloom_enabled != settings.bloom_enabled {
+            self.enabled_bloom(settings.bloom_enabled);
+        }
+        if self.settings.debug_attachment != settings.debug_attachment {
+            self.set_debug_attachment(settings.debug_attachment);
+        }
+        if (self.settings.exposure - settings.exposure).abs() > f32::EPSILON {
+            self.set_exposure(settings.exposure);
+        }
+        if (self.settings.white_point - settings.white_point).abs() > f32::EPSILON {
+            self.set_white_point(settings.white_point);
+        }
+        if (self.settings.ambient_intensity - settings.ambient_intensity).abs() > f32::EPSILON {
+            self.set_ambient_intensity(settings.ambient_intensity);
+        }
+        if self.settings.shadow_map_resolution != settings.shadow_map_resolution {
+            self.set_shadow_map_resolution(settings.shadow_map_resolution);
+        }
+        self.settings.shadow_filter_mode = settings.shadow_filter_mode;
+        self.settings.shadow_pcf_kernel_size = settings.shadow_pcf_kernel_size;
+        self.settings.shadow_depth_bias = settings.shadow_depth_bias;
+        self.settings.shadow_normal_bias = settings.shadow_normal_bias;
+        self.settings.shadow_cascade_count = settings.shadow_cascade_count;
+        self.settings.shadow_split_lambda = settings.shadow_split_lambda;
+        self.settings.shadow_casting_lights = settings.shadow_casting_lights;
+        self.settings.grid_enabled = settings.grid_enabled;
+        self.settings.axes_enabled = settings.axes_enabled;
+        self.settings.shadow_catcher_enabled = settings.shadow_catcher_enabled;
+        self.settings.shadow_catcher_size = settings.shadow_catcher_size;
+        self.settings.shadow_catcher_color = settings.shadow_catcher_color;
+        self.settings.depth_prepass_enabled = settings.depth_prepass_enabled;
+        if (self.settings.render_scale - settings.render_scale).abs() > f32::EPSILON {
+            self.set_render_scale(settings.render_scale);
+        }
+        if self.settings.gpu_skinning_enabled != settings.gpu_skinning_enabled {
+            self.enabled_gpu_skinning(settings.gpu_skinning_enabled);
+        }
+        if self.settings.occlusion_culling_enabled != settings.occlusion_culling_enabled {
+            self.enabled_occlusion_culling(settings.occlusion_culling_enabled);
+        }
+        self.settings.environment_yaw = settings.environment_yaw;
+        self.settings.skybox_exposure = settings.skybox_exposure;
+        self.settings.skybox_blur = settings.skybox_blur;
+        self.settings.skybox_enabled = settings.skybox_enabled;
+        self.settings.clear_color = settings.clear_color;
+        self.settings.outline_enabled = settings.outline_enabled;
+        self.settings.outline_color = settings.outline_color;
+        self.settings.outline_thickness = settings.outline_thickness;
+    }
+
+    //attachment和依赖它们的描述符都按新分辨率重建
+    fn set_render_scale(&mut self, render_scale: f32) {
+        self.settings.render_scale = render_scale;
+        self.on_new_swapchain();
+    }
+
+    fn enabled_occlusion_culling(&mut self, enable: bool) {
+        self.settings.occlusion_culling_enabled = enable;
+        if !enable {
+            self.occlusion_culler.clear(&mut self.model_renderers);
+        }
+    }
+
+    fn enabled_gpu_skinning(&mut self, enable: bool) {
+        self.settings.gpu_skinning_enabled = enable;
+        for renderer in self.model_renderers.iter_mut() {
+            renderer.data.set_gpu_skinning_enabled(enable);
+        }
     }
 
     fn set_emissive_intensity(&mut self, emissive_intensity: f32) {
         self.settings.emissive_intensity = emissive_intensity;
-        if let Some(renderer) = self.model_renderer.as_mut() {
+        for renderer in self.model_renderers.iter_mut() {
             renderer
                 .light_pass
                 .set_emissive_intensity(emissive_intensity);
@@ -1279,21 +2141,243 @@ impl Renderer {
         self.fxaa_pass.set_fxaa_mode(fxaa_mode);
     }
 
+    fn set_sharpness(&mut self, sharpness: f32) {
+        self.settings.sharpness = sharpness;
+        self.fxaa_pass.set_sharpness(sharpness);
+    }
+
     fn set_output_mode(&mut self, output_mode: OutputMode) {
         self.settings.output_mode = output_mode;
-        if let Some(renderer) = self.model_renderer.as_mut() {
+        for renderer in self.model_renderers.iter_mut() {
             renderer.light_pass.set_output_mode(output_mode);
         }
     }
 
+    fn set_ibl_debug_roughness(&mut self, roughness: f32) {
+        self.settings.ibl_debug_roughness = roughness;
+        for renderer in self.model_renderers.iter_mut() {
+            renderer.light_pass.set_ibl_debug_roughness(roughness);
+        }
+    }
+
+    fn set_polygon_mode(&mut self, polygon_mode: PolygonMode) {
+        self.settings.polygon_mode = polygon_mode;
+        for renderer in self.model_renderers.iter_mut() {
+            renderer.light_pass.set_polygon_mode(polygon_mode);
+        }
+    }
+
+    fn set_parallax(&mut self, enabled: bool, steps: u32) {
+        self.settings.parallax_enabled = enabled;
+        self.settings.parallax_steps = steps;
+        let steps = enabled.then_some(steps).unwrap_or(0);
+        for renderer in self.model_renderers.iter_mut() {
+            renderer.light_pass.set_parallax_steps(steps);
+        }
+    }
+
+    fn set_light_cluster_grid(&mut self, grid: [u32; 3]) {
+        self.settings.light_cluster_grid = grid;
+        for renderer in self.model_renderers.iter_mut() {
+            renderer.light_pass.set_light_cluster_grid(grid);
+        }
+    }
+
     fn enabled_ssao(&mut self, enable: bool) {
         if self.settings.ssao_enabled != enable {
             self.settings.ssao_enabled = enable;
-            if let Some(renderer) = self.model_renderer.as_mut() {
-                let ao_map = enable.then(|| &self.attachments.ssao_blur);
-                let shadow_map = Some(&self.attachments.shadow_caster_color);
-                renderer.light_pass.set_map(ao_map, shadow_map);
+            self.update_light_pass_maps();
+        }
+    }
+
+    fn enabled_ssao_blur(&mut self, enable: bool) {
+        self.settings.ssao_blur_enabled = enable;
+        self.update_light_pass_maps();
+    }
+
+    fn set_ssao_blur_radius(&mut self, radius: u32) {
+        self.settings.ssao_blur_radius = radius;
+        self.ssao_blur_pass.set_radius(radius);
+    }
+
+    fn enabled_ssao_range_check(&mut self, enable: bool) {
+        self.settings.ssao_range_check_enabled = enable;
+        self.ssao_pass.set_ssao_range_check(enable);
+    }
+
+    fn enabled_shadow(&mut self, enable: bool) {
+        if self.settings.shadow_enabled != enable {
+            self.settings.shadow_enabled = enable;
+            self.update_light_pass_maps();
+        }
+    }
+
+    fn set_shadow_map_resolution(&mut self, resolution: ShadowMapResolution) {
+        self.settings.shadow_map_resolution = resolution;
+        self.attachments
+            .set_shadow_map_size(&self.context, self.depth_format, resolution.size());
+        self.update_light_pass_maps();
+        self.debug_overlay_pass
+            .set_attachment(&self.attachments, self.settings.debug_attachment);
+    }
+
+    //把场景画到shadow map数组的前layer_count层，第i层用shadow UBO里第first_matrix + i个矩阵
+    fn cmd_draw_shadow_layers(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        frame_index: usize,
+        color: &Texture,
+        depth: &Texture,
+        layers: &ShadowCasterLayers,
+        first_matrix: u32,
+        layer_count: u32,
+    ) {
+        cmd_transition_images_layouts(
+            command_buffer,
+            &[
+                LayoutTransition {
+                    image: &color.image,
+                    old_layout: vk::ImageLayout::UNDEFINED,
+                    new_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                    mips_range: MipsRange::All,
+                },
+                LayoutTransition {
+                    image: &depth.image,
+                    old_layout: vk::ImageLayout::UNDEFINED,
+                    new_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                    mips_range: MipsRange::All,
+                },
+            ],
+        );
+
+        let extent = vk::Extent2D {
+            width: color.image.extent.width,
+            height: color.image.extent.height,
+        };
+
+        cmd_set_viewport_and_scissor(self.context.device(), command_buffer, extent);
+
+        for layer_index in 0..layer_count {
+            let color_attachment = RenderingAttachment::new(
+                &color.image,
+                layers.color_views[layer_index as usize],
+                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            )
+            .clear_color([1.0, 0.0, 0.0, 1.0]);
+
+            let depth_attachment = RenderingAttachment::new(
+                &depth.image,
+                layers.depth_views[layer_index as usize],
+                vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            )
+            .clear_depth(1.0)
+            .store_op(vk::AttachmentStoreOp::DONT_CARE);
+
+            self.context.cmd_begin_rendering(
+                command_buffer,
+                &RenderingDesc {
+                    extent,
+                    color_attachments: std::slice::from_ref(&color_attachment),
+                    depth_attachment: Some(depth_attachment),
+                },
+            );
+
+            for renderer in self.model_renderers.iter() {
+                renderer.shadow_caster_pass.cmd_draw(
+                    command_buffer,
+                    frame_index,
+                    &renderer.data,
+                    first_matrix + layer_index,
+                );
             }
+
+            self.context.cmd_end_rendering(command_buffer);
+        }
+
+        cmd_transition_images_layouts(
+            command_buffer,
+            &[LayoutTransition {
+                image: &color.image,
+                old_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                mips_range: MipsRange::All,
+            }],
+        );
+    }
+
+    //关闭的pass对应的贴图绑定为默认贴图
+    fn update_light_pass_maps(&mut self) {
+        let ao_map = self
+            .settings
+            .ssao_enabled
+            .then(|| self.attachments.get_ao_map(self.settings.ssao_blur_enabled));
+        let shadow_maps = self
+            .settings
+            .shadow_enabled
+            .then(|| self.attachments.get_shadow_maps());
+        for renderer in self.model_renderers.iter_mut() {
+            renderer.light_pass.set_map(ao_map, shadow_maps);
+        }
+        self.shadow_catcher_renderer
+            .set_maps(ao_map, shadow_maps.map(|maps| maps.cascades));
+    }
+
+    //地面的高度取自模型包围盒，没有模型时不画
+    fn is_shadow_catcher_rendered(&self) -> bool {
+        self.settings.shadow_catcher_enabled
+            && self
+                .model_renderers
+                .iter()
+                .any(|renderer| renderer.data.model().borrow().bounds().is_some())
+    }
+
+    //放在所有模型包围盒的最低点，xz为中心和半边长
+    fn shadow_catcher_plane(&mut self, camera: &Camera) -> [f32; 4] {
+        let bounds = self
+            .model_renderers
+            .iter_mut()
+            .filter_map(|renderer| renderer.data.model().borrow().bounds())
+            .collect::<Vec<_>>();
+        let bounds = Aabb::union(&bounds).expect("没有网格时不画地面");
+        let height = bounds.min().y;
+
+        //无限大的地面以相机为中心铺到远平面，边缘淡出在远处看不出来
+        if self.settings.shadow_catcher_size <= 0.0 {
+            let position = camera.position();
+            let (_, z_far) = camera.near_far();
+            return [position.x, height, position.z, z_far];
+        }
+
+        let center = (bounds.min() + bounds.max()) * 0.5;
+        [
+            center.x,
+            height,
+            center.z,
+            self.settings.shadow_catcher_size * 0.5,
+        ]
+    }
+
+    fn enabled_bloom(&mut self, enable: bool) {
+        self.settings.bloom_enabled = enable;
+        self.final_pass.set_bloom_enabled(&self.attachments, enable);
+    }
+
+    fn set_debug_attachment(&mut self, attachment: DebugAttachment) {
+        self.settings.debug_attachment = attachment;
+        self.debug_overlay_pass
+            .set_attachment(&self.attachments, attachment);
+    }
+
+    //调试视图对应的pass关闭时不绘制
+    fn is_debug_attachment_rendered(&self) -> bool {
+        match self.debug_overlay_pass.attachment() {
+            DebugAttachment::None => false,
+            DebugAttachment::ShadowMap => self.settings.shadow_enabled,
+            DebugAttachment::SceneDepth => true,
+            DebugAttachment::GBufferNormals
+            | DebugAttachment::GBufferDepth
+            | DebugAttachment::SSAORaw => self.settings.ssao_enabled,
+            DebugAttachment::SSAO => self.settings.ssao_enabled && self.settings.ssao_blur_enabled,
         }
     }
 
@@ -1317,6 +2401,16 @@ impl Renderer {
         self.final_pass.set_bloom_strength(strength);
     }
 
+    fn set_bloom_threshold(&mut self, threshold: f32) {
+        self.settings.bloom_threshold = threshold;
+        self.bloom_pass.set_threshold(threshold);
+    }
+
+    fn set_bloom_mip_levels(&mut self, mip_levels: u32) {
+        self.settings.bloom_mip_levels = mip_levels;
+        self.bloom_pass.set_mip_levels(mip_levels);
+    }
+
     fn set_absolute_luminance_threshold(&mut self, strength: f32) {
         self.settings.absolute_luminance_threshold = strength;
         self.fxaa_pass.set_absolute_luminance_threshold(strength);
@@ -1348,29 +2442,80 @@ impl Renderer {
         self.settings.fog_start = strength;
     }
 
+    fn set_exposure(&mut self, exposure: f32) {
+        self.settings.exposure = exposure;
+        self.final_pass.set_exposure(exposure);
+    }
+
+    fn set_white_point(&mut self, white_point: f32) {
+        self.settings.white_point = white_point;
+        self.final_pass.set_white_point(white_point);
+    }
+
+    fn set_ambient_intensity(&mut self, ambient_intensity: f32) {
+        self.settings.ambient_intensity = ambient_intensity;
+    }
+
+    fn camera_projection_matrix(&self, camera: &Camera) -> Matrix4<f32> {
+        let extent = self.target.extent();
+        let aspect = extent.width as f32 / extent.height as f32;
+        if self.reverse_z {
+            camera.reverse_z_projection_matrix(aspect)
+        } else {
+            camera.projection_matrix(aspect)
+        }
+    }
+
+    //录制command buffer之前决定这一帧要画哪些节点
+    fn update_culling(&mut self, frame_index: usize, camera: &Camera) {
+        self.camera_position = camera.position();
+        self.camera_near_far = camera.near_far();
+        self.orthographic = camera.projection().is_orthographic();
+        self.ssao_blur_pass.set_orthographic(self.orthographic);
+        let (z_near, z_far) = self.camera_near_far;
+        self.ssao_blur_pass.set_clip_planes(z_near, z_far);
+        if self.settings.occlusion_culling_enabled {
+            self.occlusion_culler
+                .read_results(frame_index, &mut self.model_renderers);
+        }
+
+        let view_projection = self.camera_projection_matrix(camera) * camera.view_matrix();
+        let frustum = Frustum::from_cols_array(view_projection.as_ref());
+        self.culling_stats = self
+            .model_renderers
+            .iter_mut()
+            .map(|renderer| renderer.data.update_culling(&frustum))
+            .fold(CullingStats::default(), CullingStats::merge);
+    }
+
     pub fn update_ubos(&mut self, frame_index: usize, camera: Camera) {
-        let extent = self.swapchain.properties().extent;
+        let extent = self.target.extent();
         let aspect = extent.width as f32 / extent.height as f32;
 
         //camera
-        let camera_view = Matrix4::look_at_rh(
-            camera.position(),
-            camera.target(),
-            Vector3::new(0.0, 1.0, 0.0),
-        );
-
-        const Z_NEAR: f32 = 0.01;
-        const Z_FAR: f32 = 100.0;
-        let camera_proj = rendering::math::perspective(Deg(45.0), aspect, Z_NEAR, Z_FAR);
+        let (z_near, z_far) = camera.near_far();
+        let camera_view = camera.view_matrix();
+        let camera_proj = self.camera_projection_matrix(&camera);
         let camera_inverted_proj = camera_proj.invert().unwrap();
+        //采样方向需要反向旋转，环境才会按设置的角度转动
+        let env_rotation = Matrix4::from_angle_y(Deg(-self.settings.environment_yaw));
+        //第一帧没有历史，运动矢量为0
+        let view_proj = camera_proj * camera_view;
+        let previous_view_proj = self
+            .previous_view_proj
+            .replace(view_proj)
+            .unwrap_or(view_proj);
 
         let camera_ubo = CameraUBO::new(
             camera_view,
             camera_proj,
             camera_inverted_proj,
             camera.position(),
-            Z_NEAR,
-            Z_FAR,
+            z_near,
+            z_far,
+            self.reverse_z,
+            env_rotation,
+            previous_view_proj,
         );
         let buffer = &mut self.camera_uniform_buffers[frame_index];
         unsafe {
@@ -1379,27 +2524,28 @@ impl Renderer {
         }
 
         //main light
-        if let Some(renderer) = self.model_renderer.as_mut() {
-            let model = renderer.data.model();
-            let model = model.borrow();
-            let lights = model
-                .nodes()
-                .nodes()
-                .iter()
-                .filter(|n| n.light_index().is_some())
-                .map(|n| (n.local_transform(), n.light_index().unwrap()))
-                .map(|(t, i)| (t, model.lights()[i]))
-                .collect::<Vec<_>>();
-            let directional_lights = lights
-                .iter()
-                .filter(|(_, l)| match l.light_type() {
-                    rendering::light::LightType::DirectionalLight => true,
-                    rendering::light::LightType::PointLight => false,
-                    rendering::light::LightType::SpotLight { .. } => false,
-                })
-                .collect::<Vec<_>>();
-            let main_light_pos = if directional_lights.len() > 0 {
-                directional_lights[0].0.clone().decomposed().0
+        if !self.model_renderers.is_empty() {
+            //取场景中第一个方向光作为主光源
+            let main_light = self.model_renderers.iter_mut().find_map(|renderer| {
+                let model = renderer.data.model();
+                let model = model.borrow();
+                let lights = model
+                    .nodes()
+                    .light_nodes()
+                    .map(|n| (n.local_transform(), n.light_index().unwrap()))
+                    .map(|(t, i)| (t, model.lights()[i]))
+                    .collect::<Vec<_>>();
+                lights
+                    .iter()
+                    .find(|(_, l)| match l.light_type() {
+                        rendering::light::LightType::DirectionalLight => true,
+                        rendering::light::LightType::PointLight => false,
+                        rendering::light::LightType::SpotLight { .. } => false,
+                    })
+                    .map(|(t, _)| t.clone().decomposed().0)
+            });
+            let main_light_pos = if let Some(main_light_pos) = main_light {
+                main_light_pos
             } else {
                 //println!("场景中没找到方向光，自己临时建一个");
                 //加上旋转每秒三十度
@@ -1413,30 +2559,57 @@ impl Renderer {
                 ]
             };
 
-            let light_view = Matrix4::look_at_rh(
-                Point3::new(main_light_pos[0], main_light_pos[1], main_light_pos[2]),
-                Point3::new(0.0, 0.0, 0.0),
-                Vector3::new(0.0, 1.0, 0.0),
-            );
+            //主光源朝向原点照射
+            let cascades = if self.settings.shadow_enabled {
+                let shadow_direction =
+                    -Vector3::new(main_light_pos[0], main_light_pos[1], main_light_pos[2]);
+                ShadowCascades::compute(
+                    camera_view,
+                    camera.projection(),
+                    aspect,
+                    (z_near, z_far),
+                    shadow_direction,
+                    self.settings.shadow_cascade_count,
+                    self.settings.shadow_split_lambda,
+                    self.settings.shadow_map_resolution.size(),
+                )
+            } else {
+                ShadowCascades::disabled()
+            };
+
+            //和光照pass里灯光缓冲的顺序一致，按light_nodes的顺序
+            let punctual_shadows = if self.settings.shadow_enabled {
+                PunctualShadows::compute(
+                    self.model_renderers.iter_mut().map(|renderer| {
+                        let model = renderer.data.model();
+                        let model = model.borrow();
+                        let lights = model
+                            .nodes()
+                            .light_nodes()
+                            .map(|n| (n.transform(), model.lights()[n.light_index().unwrap()]))
+                            .collect::<Vec<_>>();
+                        lights
+                    }),
+                    self.settings.shadow_casting_lights,
+                )
+            } else {
+                PunctualShadows::disabled(self.model_renderers.len())
+            };
+            self.punctual_shadow_layer_count = punctual_shadows.layer_count;
 
-            let light_proj = rendering::math::perspective(Deg(45.0), aspect, Z_NEAR, Z_FAR);
-            let light_inverted_proj = light_proj.invert().unwrap();
+            let shadow_filter = ShadowFilter {
+                mode: self.settings.shadow_filter_mode,
+                pcf_kernel_size: self.settings.shadow_pcf_kernel_size,
+                depth_bias: self.settings.shadow_depth_bias,
+                normal_bias: self.settings.shadow_normal_bias,
+            };
 
-            let light_ubo = CameraUBO::new(
-                light_view,
-                light_proj,
-                light_inverted_proj,
-                camera.position(),
-                Z_NEAR,
-                Z_FAR,
-            );
-            let buffer = &mut self.light_uniform_buffers[frame_index];
+            let shadow_ubo = ShadowUBO::new(&cascades, &punctual_shadows);
+            let buffer = &mut self.shadow_uniform_buffers[frame_index];
             unsafe {
                 let data_ptr = buffer.map_memory();
-                mem_copy(data_ptr, &[light_ubo]);
+                mem_copy(data_ptr, &[shadow_ubo]);
             }
-
-            let light_space_matrix = light_proj * light_view;
             let main_light_pos = [main_light_pos[0], main_light_pos[1], main_light_pos[2], 0.0];
 
             let light_dir = Vector3::new(
@@ -1447,21 +2620,41 @@ impl Renderer {
             .normalize();
             let light_dir = [light_dir.x, light_dir.y, light_dir.z, 1.0];
 
+            if self.is_shadow_catcher_rendered() {
+                let plane = self.shadow_catcher_plane(&camera);
+                self.shadow_catcher_renderer.update(
+                    frame_index,
+                    ShadowCatcherUBO::new(
+                        &cascades,
+                        &shadow_filter,
+                        light_dir,
+                        self.settings.shadow_catcher_color,
+                        plane,
+                    ),
+                );
+            }
+
             let fog_params_x = self.settings.fog_density / f32::sqrt(LN_2);
             let fog_params_y = self.settings.fog_density / LN_2;
             let e_sub_s = self.settings.fog_end - self.settings.fog_start;
             let fog_params_z = -1.0 / e_sub_s;
             let fog_params_w = self.settings.fog_end / e_sub_s;
-            renderer.data.update_buffers(
-                frame_index,
-                light_space_matrix,
-                main_light_pos,
-                light_dir,
-                [1.0, 0.956, 0.839, 1.0],
-                [fog_params_x, fog_params_y, fog_params_z, fog_params_w],
-                self.settings.fog_color,
-                1.0,
-            );
+            for (index, renderer) in self.model_renderers.iter_mut().enumerate() {
+                renderer.data.update_buffers(
+                    frame_index,
+                    &cascades,
+                    &punctual_shadows,
+                    punctual_shadows.model_lights(index),
+                    &shadow_filter,
+                    main_light_pos,
+                    light_dir,
+                    [1.0, 0.956, 0.839, 1.0],
+                    [fog_params_x, fog_params_y, fog_params_z, fog_params_w],
+                    self.settings.fog_color,
+                    1.0,
+                    self.settings.ambient_intensity,
+                );
+            }
         }
     }
 }
@@ -1474,17 +2667,21 @@ impl Drop for Renderer {
 
 #[derive(Copy, Clone)]
 struct RendererPipelineParameters<'a> {
+    name: &'a str,
     vertex_shader_name: &'static str,
     fragment_shader_name: &'static str,
     vertex_shader_specialization: Option<&'a vk::SpecializationInfo>,
     fragment_shader_specialization: Option<&'a vk::SpecializationInfo>,
-    msaa_samples: vk::SampleCountFlags,
+    multisampling: MultisampleParameters,
     color_attachment_formats: &'a [vk::Format],
     depth_attachment_format: Option<vk::Format>,
     layout: vk::PipelineLayout,
     depth_stencil_info: &'a vk::PipelineDepthStencilStateCreateInfo,
     color_blend_attachments: &'a [vk::PipelineColorBlendAttachmentState],
     enable_face_culling: bool,
+    front_face: vk::FrontFace,
+    polygon_mode: vk::PolygonMode,
+    topology: vk::PrimitiveTopology,
     parent: Option<vk::Pipeline>,
 }
 
@@ -1503,12 +2700,7 @@ fn create_renderer_pipeline<V: Vertex>(
             ShaderParameters::specialized(params.fragment_shader_name, s)
         });
 
-    let multisampling_info = vk::PipelineMultisampleStateCreateInfo::builder()
-        .sample_shading_enable(false)
-        .rasterization_samples(params.msaa_samples)
-        .min_sample_shading(1.0)
-        .alpha_to_coverage_enable(false)
-        .alpha_to_one_enable(false);
+    let multisampling_info = params.multisampling.create_info();
 
     let viewport_info = vk::PipelineViewportStateCreateInfo::builder()
         .viewport_count(1)
@@ -1523,27 +2715,28 @@ fn create_renderer_pipeline<V: Vertex>(
     let rasterizer_info = vk::PipelineRasterizationStateCreateInfo::builder()
         .depth_clamp_enable(false)
         .rasterizer_discard_enable(false)
-        .polygon_mode(vk::PolygonMode::FILL)
         .line_width(1.0)
         .cull_mode(cull_mode)
-        .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+        .front_face(params.front_face)
         .depth_bias_enable(false)
         .depth_bias_constant_factor(0.0)
         .depth_bias_clamp(0.0)
         .depth_bias_slope_factor(0.0);
 
-    let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
     let dynamic_state_info =
-        vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&dynamic_states);
+        vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&DYNAMIC_VIEWPORT_STATES);
 
     create_pipeline::<V>(
         context,
         PipelineParameters {
+            name: params.name,
             vertex_shader_params,
             fragment_shader_params,
             multisampling_info: &multisampling_info,
             viewport_info: &viewport_info,
             rasterizer_info: &rasterizer_info,
+            polygon_mode: params.polygon_mode,
+            topology: params.topology,
             dynamic_state_info: Some(&dynamic_state_info),
             depth_stencil_info: Some(params.depth_stencil_info),
             color_blend_attachments: params.color_blend_attachments,
@@ -1554,59 +2747,5 @@ fn create_renderer_pipeline<V: Vertex>(
             allow_derivatives: params.parent.is_none(),
         },
     )
-}
-
-struct InFlightFrames {
-    context: Arc<Context>,
-    sync_objects: Vec<SyncObjects>,
-    gui_textures_to_free: Vec<TextureId>,
-    current_frame: usize,
-}
-
-impl InFlightFrames {
-    fn new(context: Arc<Context>, sync_objects: Vec<SyncObjects>) -> Self {
-        Self {
-            context,
-            sync_objects,
-            gui_textures_to_free: Vec::new(),
-            current_frame: 0,
-        }
-    }
-}
-
-impl Drop for InFlightFrames {
-    fn drop(&mut self) {
-        self.sync_objects
-            .iter()
-            .for_each(|o| o.destroy(self.context.device()));
-    }
-}
-
-impl Iterator for InFlightFrames {
-    type Item = SyncObjects;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        let next = self.sync_objects[self.current_frame];
-
-        self.current_frame = (self.current_frame + 1) % self.sync_objects.len();
-
-        Some(next)
-    }
-}
-
-#[derive(Clone, Copy)]
-struct SyncObjects {
-    image_available_semaphore: vk::Semaphore,
-    render_finished_semaphore: vk::Semaphore,
-    fence: vk::Fence,
-}
-
-impl SyncObjects {
-    fn destroy(&self, device: &Device) {
-        unsafe {
-            device.destroy_semaphore(self.image_available_semaphore, None);
-            device.destroy_semaphore(self.render_finished_semaphore, None);
-            device.destroy_fence(self.fence, None);
-        }
-    }
+    .expect("创建渲染pipeline失败！")
 }
@@ -1,16 +1,21 @@
-use super::{JointsBuffer, ModelData};
-use crate::renderer::attachments::GBUFFER_NORMALS_FORMAT;
-use crate::renderer::{create_renderer_pipeline, RendererPipelineParameters};
-use gltf_loader::mesh::Primitive;
+use super::uniform::{texture_transform_uniform, TextureTransformUniform};
+use super::{
+    matches_winding, model_pick_id, node_pick_id, JointsBuffer, ModelData, WindingPipelines,
+};
+use crate::renderer::attachments::{
+    GBUFFER_MOTION_FORMAT, GBUFFER_NORMALS_FORMAT, GBUFFER_PICK_ID_FORMAT,
+};
+use crate::renderer::{create_renderer_pipeline, depth_compare_op, RendererPipelineParameters};
+use gltf_loader::mesh::{Mesh, Primitive};
 use gltf_loader::model::Model;
 use rendering::cgmath::Matrix4;
 use rendering::material::Material;
-use rendering::texture::Texture;
+use rendering::texture::{find_texture, Texture, TextureUsage};
 use rendering::util::any_as_u8_slice;
 use rendering::vertex::ModelVertex;
-use std::{mem::size_of, sync::Arc};
+use std::{mem::size_of, ops::Range, sync::Arc};
 use vulkan::ash::{vk, Device};
-use vulkan::{Buffer, Context, Texture as VulkanTexture};
+use vulkan::{debug_name, Buffer, Context, MultisampleParameters, Texture as VulkanTexture};
 
 const DYNAMIC_DATA_SET_INDEX: u32 = 0;
 const PER_PRIMITIVE_DATA_SET_INDEX: u32 = 1;
@@ -19,14 +24,22 @@ const CAMERA_UBO_BINDING: u32 = 0;
 const TRANSFORMS_UBO_BINDING: u32 = 1;
 const SKINS_UBO_BINDING: u32 = 2;
 const COLOR_SAMPLER_BINDING: u32 = 3;
+const INSTANCES_SSBO_BINDING: u32 = 4;
+const INSTANCE_PICK_IDS_SSBO_BINDING: u32 = 5;
+const PREVIOUS_TRANSFORMS_UBO_BINDING: u32 = 6;
+const PREVIOUS_SKINS_UBO_BINDING: u32 = 7;
+const PREVIOUS_INSTANCES_SSBO_BINDING: u32 = 8;
+
+const INSTANCED_PUSH_CONSTANT_OFFSET: u32 = size_of::<MaterialUniform>() as u32;
+const PICK_ID_PUSH_CONSTANT_OFFSET: u32 = INSTANCED_PUSH_CONSTANT_OFFSET + size_of::<u32>() as u32;
 
 pub struct GBufferPass {
     context: Arc<Context>,
     dummy_texture: VulkanTexture,
     descriptors: Descriptors,
     pipeline_layout: vk::PipelineLayout,
-    culled_pipeline: vk::Pipeline,
-    unculled_pipeline: vk::Pipeline,
+    culled_pipelines: WindingPipelines,
+    unculled_pipelines: WindingPipelines,
 }
 
 impl GBufferPass {
@@ -35,6 +48,7 @@ impl GBufferPass {
         model_data: &ModelData,
         camera_buffers: &[Buffer],
         depth_format: vk::Format,
+        reverse_z: bool,
     ) -> Self {
         let dummy_texture = VulkanTexture::from_rgba(
             &context,
@@ -43,7 +57,8 @@ impl GBufferPass {
             &[std::u8::MAX; 4],
             true,
             std::ffi::CString::new("Default Texture").unwrap(),
-        );
+        )
+        .expect("创建默认纹理失败！");
 
         let model_rc = model_data.model.upgrade().expect("模型已被释放！");
 
@@ -53,22 +68,46 @@ impl GBufferPass {
                 camera_buffers,
                 model_transform_buffers: &model_data.transform_ubos,
                 model_skin_buffers: &model_data.skin_ubos,
+                instance_buffers: &model_data.instance_buffers,
+                previous_transform_buffers: &model_data.previous_transform_ubos,
+                previous_skin_buffers: &model_data.previous_skin_ubos,
+                previous_instance_buffers: &model_data.previous_instance_buffers,
+                instance_pick_ids: &model_data.instance_pick_ids,
                 model: &model_rc.borrow(),
                 dummy_texture: &dummy_texture,
             },
         );
 
         let pipeline_layout = create_pipeline_layout(context.device(), &descriptors);
-        let culled_pipeline = create_pipeline(&context, depth_format, pipeline_layout, true);
-        let unculled_pipeline = create_pipeline(&context, depth_format, pipeline_layout, false);
+        let culled_pipelines = WindingPipelines::new(|front_face| {
+            create_pipeline(
+                &context,
+                depth_format,
+                reverse_z,
+                pipeline_layout,
+                true,
+                front_face,
+            )
+        });
+        //双面材质也需要区分绕序，gl_FrontFacing决定法线是否翻转
+        let unculled_pipelines = WindingPipelines::new(|front_face| {
+            create_pipeline(
+                &context,
+                depth_format,
+                reverse_z,
+                pipeline_layout,
+                false,
+                front_face,
+            )
+        });
 
         GBufferPass {
             context,
             dummy_texture,
             descriptors,
             pipeline_layout,
-            culled_pipeline,
-            unculled_pipeline,
+            culled_pipelines,
+            unculled_pipelines,
         }
     }
 }
@@ -83,57 +122,62 @@ impl GBufferPass {
                 camera_buffers,
                 model_transform_buffers: &model_data.transform_ubos,
                 model_skin_buffers: &model_data.skin_ubos,
+                instance_buffers: &model_data.instance_buffers,
+                previous_transform_buffers: &model_data.previous_transform_ubos,
+                previous_skin_buffers: &model_data.previous_skin_ubos,
+                previous_instance_buffers: &model_data.previous_instance_buffers,
+                instance_pick_ids: &model_data.instance_pick_ids,
                 model: &model_rc.borrow(),
                 dummy_texture: &self.dummy_texture,
             },
         );
     }
 
+    //model_index用于生成拾取ID
     pub fn cmd_draw(
         &self,
         command_buffer: vk::CommandBuffer,
         frame_index: usize,
         model_data: &ModelData,
+        model_index: usize,
     ) {
         let device = self.context.device();
-        let model = model_data.model.upgrade().expect("模型已被释放！");
-        let model = model.borrow();
 
-        unsafe {
-            device.cmd_bind_pipeline(
+        for mirrored in [false, true] {
+            unsafe {
+                device.cmd_bind_pipeline(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    self.culled_pipelines.get(mirrored),
+                )
+            };
+
+            self.register_model_draw_commands(
                 command_buffer,
-                vk::PipelineBindPoint::GRAPHICS,
-                self.culled_pipeline,
-            )
-        };
+                frame_index,
+                model_data,
+                model_index,
+                Some(mirrored),
+                |p| !p.material().is_transparent() && !p.material().is_double_sided(),
+            );
 
-        register_model_draw_commands(
-            &self.context,
-            self.pipeline_layout,
-            command_buffer,
-            &model,
-            &self.descriptors.dynamic_data_sets[frame_index..=frame_index],
-            &self.descriptors.per_primitive_sets,
-            |p| !p.material().is_transparent() && !p.material().is_double_sided(),
-        );
+            unsafe {
+                device.cmd_bind_pipeline(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    self.unculled_pipelines.get(mirrored),
+                )
+            };
 
-        unsafe {
-            device.cmd_bind_pipeline(
+            self.register_model_draw_commands(
                 command_buffer,
-                vk::PipelineBindPoint::GRAPHICS,
-                self.unculled_pipeline,
-            )
-        };
-
-        register_model_draw_commands(
-            &self.context,
-            self.pipeline_layout,
-            command_buffer,
-            &model,
-            &self.descriptors.dynamic_data_sets[frame_index..=frame_index],
-            &self.descriptors.per_primitive_sets,
-            |p| !p.material().is_transparent() && p.material().is_double_sided(),
-        );
+                frame_index,
+                model_data,
+                model_index,
+                Some(mirrored),
+                |p| !p.material().is_transparent() && p.material().is_double_sided(),
+            );
+        }
     }
 }
 
@@ -141,9 +185,9 @@ impl Drop for GBufferPass {
     fn drop(&mut self) {
         self.context.graphics_queue_wait_idle();
         let device = self.context.device();
+        self.unculled_pipelines.destroy(device);
+        self.culled_pipelines.destroy(device);
         unsafe {
-            device.destroy_pipeline(self.unculled_pipeline, None);
-            device.destroy_pipeline(self.culled_pipeline, None);
             device.destroy_pipeline_layout(self.pipeline_layout, None);
         }
     }
@@ -154,6 +198,11 @@ struct DescriptorsResources<'a> {
     camera_buffers: &'a [Buffer],
     model_transform_buffers: &'a [Buffer],
     model_skin_buffers: &'a [Buffer],
+    instance_buffers: &'a [Buffer],
+    previous_transform_buffers: &'a [Buffer],
+    previous_skin_buffers: &'a [Buffer],
+    previous_instance_buffers: &'a [Buffer],
+    instance_pick_ids: &'a Buffer,
     model: &'a Model,
     dummy_texture: &'a VulkanTexture,
 }
@@ -212,10 +261,15 @@ fn create_descriptor_pool(
             ty: vk::DescriptorType::UNIFORM_BUFFER,
             descriptor_count,
         },
-        // Transforms 和 skins
+        // 当前帧和上一帧的 transforms 和 skins
         vk::DescriptorPoolSize {
             ty: vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
-            descriptor_count: descriptor_count * 2,
+            descriptor_count: descriptor_count * 4,
+        },
+        // 当前帧和上一帧的 instances，以及 instance pick ids
+        vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::STORAGE_BUFFER,
+            descriptor_count: descriptor_count * 3,
         },
         // Color sampler
         vk::DescriptorPoolSize {
@@ -251,6 +305,36 @@ fn create_dynamic_data_descriptor_set_layout(device: &Device) -> vk::DescriptorS
             .descriptor_count(1)
             .stage_flags(vk::ShaderStageFlags::VERTEX)
             .build(),
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(INSTANCES_SSBO_BINDING)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::VERTEX)
+            .build(),
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(INSTANCE_PICK_IDS_SSBO_BINDING)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::VERTEX)
+            .build(),
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(PREVIOUS_TRANSFORMS_UBO_BINDING)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::VERTEX)
+            .build(),
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(PREVIOUS_SKINS_UBO_BINDING)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::VERTEX)
+            .build(),
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(PREVIOUS_INSTANCES_SSBO_BINDING)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::VERTEX)
+            .build(),
     ];
 
     let layout_info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
@@ -281,11 +365,18 @@ fn create_dynamic_data_descriptor_sets(
             .allocate_descriptor_sets(&allocate_info)
             .unwrap()
     };
+    for (i, set) in sets.iter().enumerate() {
+        debug_name!(context, *set, "GBuffer.DynamicData.DescriptorSet[{}]", i);
+    }
 
     sets.iter().enumerate().for_each(|(i, set)| {
         let camera_ubo = &resources.camera_buffers[i];
         let model_transform_ubo = &resources.model_transform_buffers[i];
         let model_skin_ubo = &resources.model_skin_buffers[i];
+        let instance_buffer = &resources.instance_buffers[i];
+        let previous_transform_ubo = &resources.previous_transform_buffers[i];
+        let previous_skin_ubo = &resources.previous_skin_buffers[i];
+        let previous_instance_buffer = &resources.previous_instance_buffers[i];
 
         let camera_buffer_info = [vk::DescriptorBufferInfo::builder()
             .buffer(camera_ubo.buffer)
@@ -305,6 +396,36 @@ fn create_dynamic_data_descriptor_sets(
             .range(size_of::<JointsBuffer>() as _)
             .build()];
 
+        let instance_buffer_info = [vk::DescriptorBufferInfo::builder()
+            .buffer(instance_buffer.buffer)
+            .offset(0)
+            .range(vk::WHOLE_SIZE)
+            .build()];
+
+        let instance_pick_ids_info = [vk::DescriptorBufferInfo::builder()
+            .buffer(resources.instance_pick_ids.buffer)
+            .offset(0)
+            .range(vk::WHOLE_SIZE)
+            .build()];
+
+        let previous_transform_buffer_info = [vk::DescriptorBufferInfo::builder()
+            .buffer(previous_transform_ubo.buffer)
+            .offset(0)
+            .range(size_of::<Matrix4<f32>>() as _)
+            .build()];
+
+        let previous_skin_buffer_info = [vk::DescriptorBufferInfo::builder()
+            .buffer(previous_skin_ubo.buffer)
+            .offset(0)
+            .range(size_of::<JointsBuffer>() as _)
+            .build()];
+
+        let previous_instance_buffer_info = [vk::DescriptorBufferInfo::builder()
+            .buffer(previous_instance_buffer.buffer)
+            .offset(0)
+            .range(vk::WHOLE_SIZE)
+            .build()];
+
         let descriptor_writes = [
             vk::WriteDescriptorSet::builder()
                 .dst_set(*set)
@@ -324,6 +445,36 @@ fn create_dynamic_data_descriptor_sets(
                 .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
                 .buffer_info(&model_skin_buffer_info)
                 .build(),
+            vk::WriteDescriptorSet::builder()
+                .dst_set(*set)
+                .dst_binding(INSTANCES_SSBO_BINDING)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .buffer_info(&instance_buffer_info)
+                .build(),
+            vk::WriteDescriptorSet::builder()
+                .dst_set(*set)
+                .dst_binding(INSTANCE_PICK_IDS_SSBO_BINDING)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .buffer_info(&instance_pick_ids_info)
+                .build(),
+            vk::WriteDescriptorSet::builder()
+                .dst_set(*set)
+                .dst_binding(PREVIOUS_TRANSFORMS_UBO_BINDING)
+                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
+                .buffer_info(&previous_transform_buffer_info)
+                .build(),
+            vk::WriteDescriptorSet::builder()
+                .dst_set(*set)
+                .dst_binding(PREVIOUS_SKINS_UBO_BINDING)
+                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
+                .buffer_info(&previous_skin_buffer_info)
+                .build(),
+            vk::WriteDescriptorSet::builder()
+                .dst_set(*set)
+                .dst_binding(PREVIOUS_INSTANCES_SSBO_BINDING)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .buffer_info(&previous_instance_buffer_info)
+                .build(),
         ];
 
         unsafe {
@@ -372,9 +523,12 @@ fn create_per_primitive_descriptor_sets(
             .allocate_descriptor_sets(&allocate_info)
             .unwrap()
     };
+    for (i, set) in sets.iter().enumerate() {
+        debug_name!(context, *set, "GBuffer.PerPrimitive.DescriptorSet[{}]", i);
+    }
 
     let model = resources.model;
-    let textures = resources.model.textures();
+    let textures = resources.model.textures(TextureUsage::Color);
     let mut primitive_index = 0;
     for mesh in model.meshes() {
         for primitive in mesh.primitives() {
@@ -408,11 +562,10 @@ fn create_per_primitive_descriptor_sets(
 
 fn create_descriptor_image_info(
     index: Option<usize>,
-    textures: &[Texture],
+    textures: &[Option<Texture>],
     dummy_texture: &VulkanTexture,
 ) -> [vk::DescriptorImageInfo; 1] {
-    let (view, sampler) = index
-        .map(|i| &textures[i])
+    let (view, sampler) = find_texture(textures, index)
         .map_or((dummy_texture.view, dummy_texture.sampler.unwrap()), |t| {
             (t.get_view(), t.get_sampler())
         });
@@ -429,11 +582,19 @@ fn create_pipeline_layout(device: &Device, descriptors: &Descriptors) -> vk::Pip
         descriptors.dynamic_data_layout,
         descriptors.per_primitive_layout,
     ];
-    let constant_ranges = [vk::PushConstantRange {
-        stage_flags: vk::ShaderStageFlags::FRAGMENT,
-        offset: 0,
-        size: size_of::<MaterialUniform>() as _,
-    }];
+    let constant_ranges = [
+        vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            offset: 0,
+            size: size_of::<MaterialUniform>() as _,
+        },
+        //是否为实例化绘制和拾取ID
+        vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::VERTEX,
+            offset: INSTANCED_PUSH_CONSTANT_OFFSET,
+            size: 2 * size_of::<u32>() as u32,
+        },
+    ];
     let layout_info = vk::PipelineLayoutCreateInfo::builder()
         .set_layouts(&layouts)
         .push_constant_ranges(&constant_ranges);
@@ -444,13 +605,15 @@ fn create_pipeline_layout(device: &Device, descriptors: &Descriptors) -> vk::Pip
 fn create_pipeline(
     context: &Arc<Context>,
     depth_format: vk::Format,
+    reverse_z: bool,
     layout: vk::PipelineLayout,
     enable_face_culling: bool,
+    front_face: vk::FrontFace,
 ) -> vk::Pipeline {
     let depth_stencil_info = vk::PipelineDepthStencilStateCreateInfo::builder()
         .depth_test_enable(true)
         .depth_write_enable(true)
-        .depth_compare_op(vk::CompareOp::LESS_OR_EQUAL)
+        .depth_compare_op(depth_compare_op(reverse_z))
         .depth_bounds_test_enable(false)
         .min_depth_bounds(0.0)
         .max_depth_bounds(1.0)
@@ -458,100 +621,246 @@ fn create_pipeline(
         .front(Default::default())
         .back(Default::default());
 
-    let color_blend_attachments = [vk::PipelineColorBlendAttachmentState::builder()
-        .color_write_mask(
-            vk::ColorComponentFlags::R
-                | vk::ColorComponentFlags::G
-                | vk::ColorComponentFlags::B
-                | vk::ColorComponentFlags::A,
-        )
-        .blend_enable(false)
-        .src_color_blend_factor(vk::BlendFactor::ONE)
-        .dst_color_blend_factor(vk::BlendFactor::ZERO)
-        .color_blend_op(vk::BlendOp::ADD)
-        .src_alpha_blend_factor(vk::BlendFactor::ONE)
-        .dst_alpha_blend_factor(vk::BlendFactor::ZERO)
-        .alpha_blend_op(vk::BlendOp::ADD)
-        .build()];
+    let color_blend_attachments = [
+        vk::PipelineColorBlendAttachmentState::builder()
+            .color_write_mask(
+                vk::ColorComponentFlags::R
+                    | vk::ColorComponentFlags::G
+                    | vk::ColorComponentFlags::B
+                    | vk::ColorComponentFlags::A,
+            )
+            .blend_enable(false)
+            .src_color_blend_factor(vk::BlendFactor::ONE)
+            .dst_color_blend_factor(vk::BlendFactor::ZERO)
+            .color_blend_op(vk::BlendOp::ADD)
+            .src_alpha_blend_factor(vk::BlendFactor::ONE)
+            .dst_alpha_blend_factor(vk::BlendFactor::ZERO)
+            .alpha_blend_op(vk::BlendOp::ADD)
+            .build(),
+        //整数格式不支持混合
+        vk::PipelineColorBlendAttachmentState::builder()
+            .color_write_mask(vk::ColorComponentFlags::R)
+            .blend_enable(false)
+            .build(),
+        vk::PipelineColorBlendAttachmentState::builder()
+            .color_write_mask(vk::ColorComponentFlags::R | vk::ColorComponentFlags::G)
+            .blend_enable(false)
+            .build(),
+    ];
 
     create_renderer_pipeline::<ModelVertex>(
         context,
         RendererPipelineParameters {
+            name: if enable_face_culling {
+                "GBuffer.Pipeline"
+            } else {
+                "GBuffer.Pipeline.DoubleSided"
+            },
             vertex_shader_name: "gbuffer",
             fragment_shader_name: "gbuffer",
             vertex_shader_specialization: None,
             fragment_shader_specialization: None,
-            msaa_samples: vk::SampleCountFlags::TYPE_1,
-            color_attachment_formats: &[GBUFFER_NORMALS_FORMAT],
+            multisampling: MultisampleParameters::default(),
+            color_attachment_formats: &[
+                GBUFFER_NORMALS_FORMAT,
+                GBUFFER_PICK_ID_FORMAT,
+                GBUFFER_MOTION_FORMAT,
+            ],
             depth_attachment_format: Some(depth_format),
             layout,
             depth_stencil_info: &depth_stencil_info,
             color_blend_attachments: &color_blend_attachments,
             enable_face_culling,
+            front_face,
+            polygon_mode: vk::PolygonMode::FILL,
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
             parent: None,
         },
     )
 }
 
-fn register_model_draw_commands<F>(
-    context: &Context,
-    pipeline_layout: vk::PipelineLayout,
-    command_buffer: vk::CommandBuffer,
-    model: &Model,
-    dynamic_descriptors: &[vk::DescriptorSet],
-    per_primitive_descriptors: &[vk::DescriptorSet],
-    primitive_filter: F,
-) where
-    F: FnMut(&&Primitive) -> bool + Copy,
-{
-    let device = context.device();
-    let model_transform_ubo_offset = context.get_ubo_alignment::<Matrix4<f32>>();
-    let model_skin_ubo_offset = context.get_ubo_alignment::<JointsBuffer>();
-
-    for (index, node) in model
-        .nodes()
-        .nodes()
-        .iter()
-        .filter(|n| n.mesh_index().is_some())
-        .enumerate()
+impl GBufferPass {
+    fn register_model_draw_commands<F>(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        frame_index: usize,
+        model_data: &ModelData,
+        model_index: usize,
+        mirrored: Option<bool>,
+        primitive_filter: F,
+    ) where
+        F: FnMut(&&Primitive) -> bool + Copy,
     {
-        let mesh = model.mesh(node.mesh_index().unwrap());
-        let skin_index = node.skin_index().unwrap_or(0);
+        let device = self.context.device();
+        let model_transform_ubo_offset = self.context.get_ubo_alignment::<Matrix4<f32>>();
+        let model_skin_ubo_offset = self.context.get_ubo_alignment::<JointsBuffer>();
+        let dynamic_descriptors = &self.descriptors.dynamic_data_sets[frame_index..=frame_index];
+
+        let model = model_data.model.upgrade().expect("模型已被释放！");
+        let model = model.borrow();
+
+        self.cmd_push_instanced_flag(command_buffer, false);
+
+        let mesh_nodes = model
+            .nodes()
+            .nodes()
+            .iter()
+            .enumerate()
+            .filter(|(_, n)| n.mesh_index().is_some());
+        for (index, (node_index, node)) in mesh_nodes.enumerate() {
+            //重复的网格在下面一次性实例化绘制，隐藏和视锥体外的节点直接跳过
+            if model_data.instancing.is_node_instanced(index)
+                || !node.is_visible()
+                || model_data.is_node_culled(index)
+                || !matches_winding(mirrored, node.is_mirrored())
+            {
+                continue;
+            }
+
+            self.cmd_push_pick_id(
+                command_buffer,
+                model_pick_id(model_index) | node_pick_id(node_index),
+            );
+
+            let mesh = model.mesh(node.mesh_index().unwrap());
+            let skin_index = node.skin_index().unwrap_or(0);
 
+            unsafe {
+                device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    self.pipeline_layout,
+                    DYNAMIC_DATA_SET_INDEX,
+                    dynamic_descriptors,
+                    //上一帧的缓冲与当前帧布局相同，偏移也相同
+                    &[
+                        model_transform_ubo_offset * index as u32,
+                        model_skin_ubo_offset * skin_index as u32,
+                        model_transform_ubo_offset * index as u32,
+                        model_skin_ubo_offset * skin_index as u32,
+                    ],
+                )
+            };
+
+            self.register_primitive_draw_commands(
+                command_buffer,
+                mesh,
+                model_data.skinned_vertices(frame_index, index),
+                primitive_filter,
+                0..1,
+            );
+        }
+
+        if model_data.instancing.batches.is_empty() {
+            return;
+        }
+
+        //实例化绘制的变换从存储缓冲中读取，动态偏移不会被用到
         unsafe {
             device.cmd_bind_descriptor_sets(
                 command_buffer,
                 vk::PipelineBindPoint::GRAPHICS,
-                pipeline_layout,
+                self.pipeline_layout,
                 DYNAMIC_DATA_SET_INDEX,
                 dynamic_descriptors,
-                &[
-                    model_transform_ubo_offset * index as u32,
-                    model_skin_ubo_offset * skin_index as u32,
-                ],
+                &[0, 0, 0, 0],
+            )
+        };
+
+        self.cmd_push_instanced_flag(command_buffer, true);
+        //实例的节点ID从存储缓冲中读取，这里只提供模型序号
+        self.cmd_push_pick_id(command_buffer, model_pick_id(model_index));
+
+        for batch in model_data
+            .instancing
+            .batches
+            .iter()
+            .filter(|b| matches_winding(mirrored, b.mirrored))
+        {
+            let first_instance = batch.first_instance;
+            let instance_count = batch.node_indices.len() as u32;
+
+            self.register_primitive_draw_commands(
+                command_buffer,
+                model.mesh(batch.mesh_index),
+                None,
+                primitive_filter,
+                first_instance..first_instance + instance_count,
+            );
+        }
+    }
+
+    fn cmd_push_instanced_flag(&self, command_buffer: vk::CommandBuffer, instanced: bool) {
+        let instanced = instanced as u32;
+        unsafe {
+            self.context.device().cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::VERTEX,
+                INSTANCED_PUSH_CONSTANT_OFFSET,
+                any_as_u8_slice(&instanced),
             )
         };
+    }
 
-        for primitive in mesh.primitives().iter().filter(primitive_filter) {
+    fn cmd_push_pick_id(&self, command_buffer: vk::CommandBuffer, pick_id: u32) {
+        unsafe {
+            self.context.device().cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::VERTEX,
+                PICK_ID_PUSH_CONSTANT_OFFSET,
+                any_as_u8_slice(&pick_id),
+            )
+        };
+    }
+
+    //skinned_vertices不为空时从计算着色器蒙皮的结果中读取顶点
+    fn register_primitive_draw_commands<F>(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        mesh: &Mesh,
+        skinned_vertices: Option<(vk::Buffer, &[vk::DeviceSize])>,
+        mut primitive_filter: F,
+        instances: Range<u32>,
+    ) where
+        F: FnMut(&&Primitive) -> bool + Copy,
+    {
+        let device = self.context.device();
+
+        for (position, primitive) in mesh
+            .primitives()
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| primitive_filter(p))
+        {
             let primitive_index = primitive.index();
 
             unsafe {
                 device.cmd_bind_descriptor_sets(
                     command_buffer,
                     vk::PipelineBindPoint::GRAPHICS,
-                    pipeline_layout,
+                    self.pipeline_layout,
                     PER_PRIMITIVE_DATA_SET_INDEX,
-                    &per_primitive_descriptors[primitive_index..=primitive_index],
+                    &self.descriptors.per_primitive_sets[primitive_index..=primitive_index],
                     &[],
                 )
             };
 
+            let (vertex_buffer, vertex_offset) = match skinned_vertices {
+                Some((buffer, offsets)) => (buffer, offsets[position]),
+                None => (
+                    primitive.vertices().buffer().buffer,
+                    primitive.vertices().offset(),
+                ),
+            };
+
             unsafe {
                 device.cmd_bind_vertex_buffers(
                     command_buffer,
                     0,
-                    &[primitive.vertices().buffer().buffer],
-                    &[primitive.vertices().offset()],
+                    &[vertex_buffer],
+                    &[vertex_offset],
                 );
             }
 
@@ -571,7 +880,7 @@ fn register_model_draw_commands<F>(
                 let material_contants = any_as_u8_slice(&material);
                 device.cmd_push_constants(
                     command_buffer,
-                    pipeline_layout,
+                    self.pipeline_layout,
                     vk::ShaderStageFlags::FRAGMENT,
                     0,
                     material_contants,
@@ -584,10 +893,10 @@ fn register_model_draw_commands<F>(
                         device.cmd_draw_indexed(
                             command_buffer,
                             index_buffer.element_count(),
-                            1,
-                            0,
+                            instances.len() as u32,
                             0,
                             0,
+                            instances.start,
                         )
                     };
                 }
@@ -596,9 +905,9 @@ fn register_model_draw_commands<F>(
                         device.cmd_draw(
                             command_buffer,
                             primitive.vertices().element_count(),
-                            1,
-                            0,
+                            instances.len() as u32,
                             0,
+                            instances.start,
                         )
                     };
                 }
@@ -614,6 +923,7 @@ pub struct MaterialUniform {
     color_texture_channel: u32,
     alpha_mode: u32,
     alpha_cutoff: f32,
+    color_texture_transform: TextureTransformUniform,
 }
 
 impl MaterialUniform {
@@ -628,12 +938,14 @@ impl From<Material> for MaterialUniform {
             .map_or(Self::NO_TEXTURE_ID, |info| info.get_channel());
         let alpha_mode = material.get_alpha_mode();
         let alpha_cutoff = material.get_alpha_cutoff();
+        let color_texture_transform = texture_transform_uniform(material.get_color_texture());
 
         MaterialUniform {
             alpha,
             color_texture_channel,
             alpha_mode,
             alpha_cutoff,
+            color_texture_transform,
         }
     }
 }
@@ -0,0 +1,315 @@
+use super::ModelData;
+use rendering::util::any_as_u8_slice;
+use std::{mem::size_of, sync::Arc};
+use vulkan::ash::{vk, Device};
+use vulkan::{create_compute_pipeline, debug_name, Buffer, Context, ShaderParameters};
+
+const CAMERA_UBO_BINDING: u32 = 0;
+const LIGHTS_SSBO_BINDING: u32 = 1;
+const CLUSTERS_SSBO_BINDING: u32 = 2;
+
+const WORKGROUP_SIZE: u32 = 64;
+
+pub const DEFAULT_LIGHT_CLUSTER_GRID: [u32; 3] = [16, 9, 24];
+pub const MAX_LIGHT_CLUSTER_GRID: u32 = 32;
+pub const MAX_LIGHTS_PER_CLUSTER: u32 = 32;
+//灯数不超过这个值时光照pass直接遍历所有灯，不做分簇剔除
+pub const MAX_SIMPLE_LIGHT_COUNT: usize = 8;
+
+//每个簇占MAX_LIGHTS_PER_CLUSTER + 1个uint，第一个是灯数，后面是灯的序号
+//按最大网格分配，调整网格大小时不需要重建缓冲和描述符
+pub fn create_light_cluster_buffers(context: &Arc<Context>, count: u32) -> Vec<Buffer> {
+    let cluster_count = MAX_LIGHT_CLUSTER_GRID.pow(3);
+    let buffer_size = (cluster_count * (MAX_LIGHTS_PER_CLUSTER + 1)) as usize * size_of::<u32>();
+
+    (0..count)
+        .map(|i| {
+            Buffer::create(
+                Arc::clone(context),
+                buffer_size as vk::DeviceSize,
+                vk::BufferUsageFlags::STORAGE_BUFFER,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                &format!("LightClusters.SSBO[{}]", i),
+            )
+        })
+        .collect()
+}
+
+//按x、y、z各8位打包，0表示不使用分簇
+pub fn pack_cluster_grid(grid: [u32; 3]) -> u32 {
+    grid[0] | (grid[1] << 8) | (grid[2] << 16)
+}
+
+//每帧把灯按视锥体内的簇(froxel)分桶，光照pass只计算片元所在簇里的灯
+//x、y方向在NDC中均匀划分，z方向在近远平面之间按指数划分
+pub struct LightCullingPass {
+    context: Arc<Context>,
+    descriptors: Descriptors,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl LightCullingPass {
+    //灯数较少、没有分簇缓冲时返回None
+    pub fn create(
+        context: Arc<Context>,
+        model_data: &ModelData,
+        camera_buffers: &[Buffer],
+    ) -> Option<Self> {
+        let cluster_buffers = model_data.light_clusters.as_ref()?;
+
+        let descriptors = create_descriptors(
+            &context,
+            camera_buffers,
+            &model_data.light_buffers,
+            cluster_buffers,
+        );
+        let pipeline_layout = create_pipeline_layout(context.device(), &descriptors);
+        let pipeline = create_compute_pipeline(
+            &context,
+            "LightCulling.Pipeline",
+            ShaderParameters::new("light_culling"),
+            pipeline_layout,
+        )
+        .expect("创建light culling pipeline失败！");
+
+        Some(Self {
+            context,
+            descriptors,
+            pipeline_layout,
+            pipeline,
+        })
+    }
+
+    pub fn cmd_dispatch(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        frame_index: usize,
+        model_data: &ModelData,
+        grid: [u32; 3],
+    ) {
+        let device = self.context.device();
+        let model = model_data.model.upgrade().expect("模型已被释放！");
+        let light_count = model.borrow().nodes().light_nodes().count() as u32;
+
+        let constants = LightCullingConstants {
+            grid_x: grid[0],
+            grid_y: grid[1],
+            grid_z: grid[2],
+            light_count,
+        };
+        let cluster_count = grid[0] * grid[1] * grid[2];
+
+        unsafe {
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline,
+            );
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline_layout,
+                0,
+                &self.descriptors.sets[frame_index..=frame_index],
+                &[],
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                any_as_u8_slice(&constants),
+            );
+            device.cmd_dispatch(
+                command_buffer,
+                (cluster_count + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE,
+                1,
+                1,
+            );
+        }
+
+        //分簇结果写完之后光照pass的片元着色器才能读取
+        let clusters = &model_data.light_clusters.as_ref().unwrap()[frame_index];
+        let barrier = vk::BufferMemoryBarrier2::builder()
+            .src_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+            .src_access_mask(vk::AccessFlags2::SHADER_STORAGE_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags2::FRAGMENT_SHADER)
+            .dst_access_mask(vk::AccessFlags2::SHADER_STORAGE_READ)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .buffer(clusters.buffer)
+            .offset(0)
+            .size(vk::WHOLE_SIZE);
+        let dependency_info =
+            vk::DependencyInfo::builder().buffer_memory_barriers(std::slice::from_ref(&barrier));
+
+        unsafe {
+            self.context
+                .synchronization2()
+                .cmd_pipeline_barrier2(command_buffer, &dependency_info)
+        };
+    }
+}
+
+impl Drop for LightCullingPass {
+    fn drop(&mut self) {
+        self.context.graphics_queue_wait_idle();
+        let device = self.context.device();
+        unsafe {
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+#[allow(dead_code)]
+#[repr(C)]
+struct LightCullingConstants {
+    grid_x: u32,
+    grid_y: u32,
+    grid_z: u32,
+    light_count: u32,
+}
+
+struct Descriptors {
+    context: Arc<Context>,
+    pool: vk::DescriptorPool,
+    layout: vk::DescriptorSetLayout,
+    sets: Vec<vk::DescriptorSet>,
+}
+
+impl Drop for Descriptors {
+    fn drop(&mut self) {
+        let device = self.context.device();
+        unsafe {
+            device.destroy_descriptor_pool(self.pool, None);
+            device.destroy_descriptor_set_layout(self.layout, None);
+        }
+    }
+}
+
+fn create_descriptors(
+    context: &Arc<Context>,
+    camera_buffers: &[Buffer],
+    light_buffers: &[Buffer],
+    cluster_buffers: &[Buffer],
+) -> Descriptors {
+    let device = context.device();
+    let descriptor_count = cluster_buffers.len() as u32;
+
+    let pool_sizes = [
+        vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::UNIFORM_BUFFER,
+            descriptor_count,
+        },
+        vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::STORAGE_BUFFER,
+            descriptor_count: descriptor_count * 2,
+        },
+    ];
+    let pool_info = vk::DescriptorPoolCreateInfo::builder()
+        .pool_sizes(&pool_sizes)
+        .max_sets(descriptor_count);
+    let pool = unsafe { device.create_descriptor_pool(&pool_info, None).unwrap() };
+
+    let bindings = [
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(CAMERA_UBO_BINDING)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .build(),
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(LIGHTS_SSBO_BINDING)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .build(),
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(CLUSTERS_SSBO_BINDING)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .build(),
+    ];
+    let layout_info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+    let layout = unsafe {
+        device
+            .create_descriptor_set_layout(&layout_info, None)
+            .unwrap()
+    };
+
+    let layouts = (0..descriptor_count).map(|_| layout).collect::<Vec<_>>();
+    let allocate_info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(pool)
+        .set_layouts(&layouts);
+    let sets = unsafe { device.allocate_descriptor_sets(&allocate_info).unwrap() };
+
+    sets.iter().enumerate().for_each(|(i, set)| {
+        debug_name!(context, *set, "LightCulling.DescriptorSet[{}]", i);
+
+        let camera_buffer_info = [vk::DescriptorBufferInfo::builder()
+            .buffer(camera_buffers[i].buffer)
+            .offset(0)
+            .range(vk::WHOLE_SIZE)
+            .build()];
+
+        let light_buffer_info = [vk::DescriptorBufferInfo::builder()
+            .buffer(light_buffers[i].buffer)
+            .offset(0)
+            .range(vk::WHOLE_SIZE)
+            .build()];
+
+        let cluster_buffer_info = [vk::DescriptorBufferInfo::builder()
+            .buffer(cluster_buffers[i].buffer)
+            .offset(0)
+            .range(vk::WHOLE_SIZE)
+            .build()];
+
+        let descriptor_writes = [
+            vk::WriteDescriptorSet::builder()
+                .dst_set(*set)
+                .dst_binding(CAMERA_UBO_BINDING)
+                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                .buffer_info(&camera_buffer_info)
+                .build(),
+            vk::WriteDescriptorSet::builder()
+                .dst_set(*set)
+                .dst_binding(LIGHTS_SSBO_BINDING)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .buffer_info(&light_buffer_info)
+                .build(),
+            vk::WriteDescriptorSet::builder()
+                .dst_set(*set)
+                .dst_binding(CLUSTERS_SSBO_BINDING)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .buffer_info(&cluster_buffer_info)
+                .build(),
+        ];
+
+        unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) }
+    });
+
+    Descriptors {
+        context: Arc::clone(context),
+        pool,
+        layout,
+        sets,
+    }
+}
+
+fn create_pipeline_layout(device: &Device, descriptors: &Descriptors) -> vk::PipelineLayout {
+    let layouts = [descriptors.layout];
+    let constant_ranges = [vk::PushConstantRange {
+        stage_flags: vk::ShaderStageFlags::COMPUTE,
+        offset: 0,
+        size: size_of::<LightCullingConstants>() as _,
+    }];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(&layouts)
+        .push_constant_ranges(&constant_ranges);
+
+    unsafe { device.create_pipeline_layout(&layout_info, None).unwrap() }
+}
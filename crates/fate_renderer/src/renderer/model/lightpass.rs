@@ -1,17 +1,21 @@
-use super::{uniform::*, JointsBuffer, ModelData};
-use crate::renderer::attachments::SCENE_COLOR_FORMAT;
-use crate::renderer::{create_renderer_pipeline, RendererPipelineParameters, RendererSettings};
-use rendering::cgmath::Matrix4;
+use super::lightcullingpass::{pack_cluster_grid, MAX_LIGHTS_PER_CLUSTER};
+use super::{matches_winding, uniform::*, JointsBuffer, ModelData, WindingPipelines};
+use crate::renderer::attachments::{create_dummy_shadow_map, ShadowMaps, SCENE_COLOR_FORMAT};
+use crate::renderer::{
+    create_renderer_pipeline, depth_compare_op, RendererPipelineParameters, RendererSettings,
+};
+use gltf_loader::mesh::{Mesh, Primitive};
+use gltf_loader::model::Model;
+use rendering::cgmath::{EuclideanSpace, InnerSpace, Matrix4, Point3};
 use rendering::environment::{Environment, PRE_FILTERED_MAP_SIZE};
 use rendering::material::PBRWorkflow;
-use gltf_loader::mesh::Primitive;
-use gltf_loader::model::Model;
-use rendering::texture::Texture;
+use rendering::texture::{find_texture, Texture, TextureUsage};
 use rendering::util::*;
 use rendering::vertex::ModelVertex;
-use std::{mem::size_of, sync::Arc};
+use serde::{Deserialize, Serialize};
+use std::{cmp::Ordering, mem::size_of, ops::Range, sync::Arc};
 use vulkan::ash::{vk, Device};
-use vulkan::{Buffer, Context, Texture as VulkanTexture};
+use vulkan::{debug_name, Buffer, Context, MultisampleParameters, Texture as VulkanTexture};
 
 const DYNAMIC_DATA_SET_INDEX: u32 = 0;
 const STATIC_DATA_SET_INDEX: u32 = 1;
@@ -19,7 +23,7 @@ const PER_PRIMITIVE_DATA_SET_INDEX: u32 = 2;
 const INPUT_SET_INDEX: u32 = 3;
 
 const CAMERA_UBO_BINDING: u32 = 0;
-const LIGHT_UBO_BINDING: u32 = 1;
+const LIGHTS_SSBO_BINDING: u32 = 1;
 const TRANSFORMS_UBO_BINDING: u32 = 2;
 const SKINS_UBO_BINDING: u32 = 3;
 const MAIN_LIGHT_UBO_BINDING: u32 = 4;
@@ -33,22 +37,49 @@ const OCCLUSION_SAMPLER_BINDING: u32 = 11;
 const EMISSIVE_SAMPLER_BINDING: u32 = 12;
 const SHADOW_MAP_SAMPLER_BINDING: u32 = 13;
 const AO_MAP_SAMPLER_BINDING: u32 = 14;
+const INSTANCES_SSBO_BINDING: u32 = 15;
+const TEXTURE_TRANSFORMS_UBO_BINDING: u32 = 16;
+const LIGHT_CLUSTERS_SSBO_BINDING: u32 = 17;
+const HEIGHT_SAMPLER_BINDING: u32 = 18;
+const PUNCTUAL_SHADOW_MAP_SAMPLER_BINDING: u32 = 19;
+
+pub const DEFAULT_PARALLAX_STEPS: u32 = 16;
+pub const MAX_PARALLAX_STEPS: u32 = 64;
 
-const MAX_LIGHT_COUNT: u32 = 8;
+//材质参数和配置参数之后
+const INSTANCED_PUSH_CONSTANT_OFFSET: u32 =
+    (size_of::<MaterialUniform>() + size_of::<ConfigUniform>()) as u32;
 
 pub struct LightPass {
     context: Arc<Context>,
     dummy_texture: VulkanTexture,
+    dummy_shadow_map: VulkanTexture,
     descriptors: Descriptors,
     pipeline_layout: vk::PipelineLayout,
-    opaque_pipeline: vk::Pipeline,
-    opaque_unculled_pipeline: vk::Pipeline,
-    transparent_pipeline: vk::Pipeline,
+    opaque_pipelines: WindingPipelines,
+    opaque_unculled_pipelines: WindingPipelines,
+    mask_pipelines: WindingPipelines,
+    mask_unculled_pipelines: WindingPipelines,
+    transparent_pipelines: WindingPipelines,
+    transparent_unculled_pipelines: WindingPipelines,
+    //深度预pass只写深度，之后不透明部分用EQUAL比较着色
+    depth_prepass_pipelines: WindingPipelines,
+    depth_prepass_unculled_pipelines: WindingPipelines,
+    opaque_depth_equal_pipelines: WindingPipelines,
+    opaque_depth_equal_unculled_pipelines: WindingPipelines,
+    line_pipeline: vk::Pipeline,
+    point_pipeline: vk::Pipeline,
     output_mode: OutputMode,
+    polygon_mode: PolygonMode,
     emissive_intensity: f32,
+    ibl_debug_roughness: f32,
+    //没有分簇缓冲时为None，片元着色器遍历所有灯
+    light_cluster_grid: Option<[u32; 3]>,
+    //视差遮蔽映射的最大步进次数，0表示关闭
+    parallax_steps: u32,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum OutputMode {
     Final = 0,
     Color,
@@ -62,14 +93,34 @@ pub enum OutputMode {
     TexCoord0,
     TexCoord1,
     Ssao,
+    VertexColor,
+    Unlit,
+    Irradiance,
+    PreFiltered,
+    BrdfLookup,
 }
 
 impl OutputMode {
-    pub fn all() -> [OutputMode; 12] {
+    pub fn all() -> [OutputMode; 17] {
         use OutputMode::*;
         [
-            Final, Color, Emissive, Metallic, Specular, Roughness, Occlusion, Normal, Alpha,
-            TexCoord0, TexCoord1, Ssao,
+            Final,
+            Color,
+            Emissive,
+            Metallic,
+            Specular,
+            Roughness,
+            Occlusion,
+            Normal,
+            Alpha,
+            TexCoord0,
+            TexCoord1,
+            Ssao,
+            VertexColor,
+            Unlit,
+            Irradiance,
+            PreFiltered,
+            BrdfLookup,
         ]
     }
 
@@ -88,9 +139,46 @@ impl OutputMode {
             9 => Some(TexCoord0),
             10 => Some(TexCoord1),
             11 => Some(Ssao),
+            12 => Some(VertexColor),
+            13 => Some(Unlit),
+            14 => Some(Irradiance),
+            15 => Some(PreFiltered),
+            16 => Some(BrdfLookup),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PolygonMode {
+    Fill = 0,
+    Line,
+    Point,
+}
+
+impl PolygonMode {
+    pub fn all() -> [PolygonMode; 3] {
+        use PolygonMode::*;
+        [Fill, Line, Point]
+    }
+
+    pub fn from_value(value: usize) -> Option<Self> {
+        use PolygonMode::*;
+        match value {
+            0 => Some(Fill),
+            1 => Some(Line),
+            2 => Some(Point),
             _ => None,
         }
     }
+
+    fn to_vk(self) -> vk::PolygonMode {
+        match self {
+            PolygonMode::Fill => vk::PolygonMode::FILL,
+            PolygonMode::Line => vk::PolygonMode::LINE,
+            PolygonMode::Point => vk::PolygonMode::POINT,
+        }
+    }
 }
 
 #[allow(dead_code)]
@@ -98,6 +186,11 @@ struct ConfigUniform {
     light_count: u32,
     output_mode: u32,
     emissive_intensity: f32,
+    //PreFiltered输出模式下显示的粗糙度对应的mip
+    ibl_debug_roughness: f32,
+    //打包的分簇网格大小，0表示不分簇
+    light_cluster_grid: u32,
+    parallax_steps: u32,
 }
 
 impl LightPass {
@@ -107,9 +200,10 @@ impl LightPass {
         camera_buffers: &[Buffer],
         environment: &Environment,
         ao_map: Option<&VulkanTexture>,
-        shadow_map: Option<&VulkanTexture>,
+        shadow_maps: Option<ShadowMaps>,
         msaa_samples: vk::SampleCountFlags,
         depth_format: vk::Format,
+        reverse_z: bool,
         settings: RendererSettings,
     ) -> Self {
         let dummy_texture = VulkanTexture::from_rgba(
@@ -118,8 +212,10 @@ impl LightPass {
             1,
             &[std::u8::MAX; 4],
             true,
-            std::ffi::CString::new("Default Texture").unwrap()
-        );
+            std::ffi::CString::new("Default Texture").unwrap(),
+        )
+        .expect("创建默认纹理失败！");
+        let dummy_shadow_map = create_dummy_shadow_map(&context);
 
         let model_rc = model_data.model.upgrade().expect("模型已被释放！");
 
@@ -129,51 +225,229 @@ impl LightPass {
                 camera_buffers,
                 model_transform_buffers: &model_data.transform_ubos,
                 model_skin_buffers: &model_data.skin_ubos,
+                instance_buffers: &model_data.instance_buffers,
                 light_buffers: &model_data.light_buffers,
+                light_cluster_buffers: model_data.light_clusters.as_deref(),
                 render_data_buffers: &model_data.render_data_buffers,
+                texture_transforms: &model_data.texture_transforms,
                 dummy_texture: &dummy_texture,
                 environment,
 
                 model: &model_rc.borrow(),
             },
             ao_map.unwrap_or(&dummy_texture),
-            shadow_map.unwrap_or(&dummy_texture),
+            shadow_maps.unwrap_or(ShadowMaps {
+                cascades: &dummy_shadow_map,
+                punctual: &dummy_shadow_map,
+            }),
         );
 
         let pipeline_layout = create_pipeline_layout(context.device(), &descriptors);
-        let opaque_pipeline =
-            create_opaque_pipeline(&context, msaa_samples, true, depth_format, pipeline_layout);
+        let opaque_pipelines = WindingPipelines::new(|front_face| {
+            create_opaque_pipeline(
+                &context,
+                msaa_samples,
+                true,
+                PolygonMode::Fill,
+                false,
+                front_face,
+                depth_format,
+                reverse_z,
+                false,
+                pipeline_layout,
+            )
+        });
+
+        let opaque_unculled_pipelines = WindingPipelines::new(|front_face| {
+            create_opaque_pipeline(
+                &context,
+                msaa_samples,
+                false,
+                PolygonMode::Fill,
+                false,
+                front_face,
+                depth_format,
+                reverse_z,
+                false,
+                pipeline_layout,
+            )
+        });
 
-        let opaque_unculled_pipeline =
-            create_opaque_pipeline(&context, msaa_samples, false, depth_format, pipeline_layout);
+        let mask_pipelines = WindingPipelines::new(|front_face| {
+            create_opaque_pipeline(
+                &context,
+                msaa_samples,
+                true,
+                PolygonMode::Fill,
+                true,
+                front_face,
+                depth_format,
+                reverse_z,
+                false,
+                pipeline_layout,
+            )
+        });
+
+        let mask_unculled_pipelines = WindingPipelines::new(|front_face| {
+            create_opaque_pipeline(
+                &context,
+                msaa_samples,
+                false,
+                PolygonMode::Fill,
+                true,
+                front_face,
+                depth_format,
+                reverse_z,
+                false,
+                pipeline_layout,
+            )
+        });
+
+        let transparent_pipelines = WindingPipelines::new(|front_face| {
+            create_transparent_pipeline(
+                &context,
+                msaa_samples,
+                true,
+                front_face,
+                depth_format,
+                reverse_z,
+                pipeline_layout,
+                opaque_pipelines.default,
+            )
+        });
+
+        let transparent_unculled_pipelines = WindingPipelines::new(|front_face| {
+            create_transparent_pipeline(
+                &context,
+                msaa_samples,
+                false,
+                front_face,
+                depth_format,
+                reverse_z,
+                pipeline_layout,
+                opaque_pipelines.default,
+            )
+        });
+
+        let depth_prepass_pipelines = WindingPipelines::new(|front_face| {
+            create_depth_prepass_pipeline(
+                &context,
+                msaa_samples,
+                true,
+                front_face,
+                depth_format,
+                reverse_z,
+                pipeline_layout,
+            )
+        });
+
+        let depth_prepass_unculled_pipelines = WindingPipelines::new(|front_face| {
+            create_depth_prepass_pipeline(
+                &context,
+                msaa_samples,
+                false,
+                front_face,
+                depth_format,
+                reverse_z,
+                pipeline_layout,
+            )
+        });
+
+        let opaque_depth_equal_pipelines = WindingPipelines::new(|front_face| {
+            create_opaque_pipeline(
+                &context,
+                msaa_samples,
+                true,
+                PolygonMode::Fill,
+                false,
+                front_face,
+                depth_format,
+                reverse_z,
+                true,
+                pipeline_layout,
+            )
+        });
+
+        let opaque_depth_equal_unculled_pipelines = WindingPipelines::new(|front_face| {
+            create_opaque_pipeline(
+                &context,
+                msaa_samples,
+                false,
+                PolygonMode::Fill,
+                false,
+                front_face,
+                depth_format,
+                reverse_z,
+                true,
+                pipeline_layout,
+            )
+        });
 
-        let transparent_pipeline = create_transparent_pipeline(
+        let line_pipeline = create_opaque_pipeline(
             &context,
             msaa_samples,
+            false,
+            PolygonMode::Line,
+            false,
+            vk::FrontFace::COUNTER_CLOCKWISE,
             depth_format,
+            reverse_z,
+            false,
+            pipeline_layout,
+        );
+
+        let point_pipeline = create_opaque_pipeline(
+            &context,
+            msaa_samples,
+            false,
+            PolygonMode::Point,
+            false,
+            vk::FrontFace::COUNTER_CLOCKWISE,
+            depth_format,
+            reverse_z,
+            false,
             pipeline_layout,
-            opaque_pipeline,
         );
 
         LightPass {
             context,
             dummy_texture,
+            dummy_shadow_map,
             descriptors,
             pipeline_layout,
-            opaque_pipeline,
-            opaque_unculled_pipeline,
-            transparent_pipeline,
+            opaque_pipelines,
+            opaque_unculled_pipelines,
+            mask_pipelines,
+            mask_unculled_pipelines,
+            transparent_pipelines,
+            transparent_unculled_pipelines,
+            depth_prepass_pipelines,
+            depth_prepass_unculled_pipelines,
+            opaque_depth_equal_pipelines,
+            opaque_depth_equal_unculled_pipelines,
+            line_pipeline,
+            point_pipeline,
             output_mode: settings.output_mode,
+            polygon_mode: settings.polygon_mode,
             emissive_intensity: settings.emissive_intensity,
+            ibl_debug_roughness: settings.ibl_debug_roughness,
+            light_cluster_grid: model_data
+                .light_clusters
+                .as_ref()
+                .map(|_| settings.light_cluster_grid),
+            parallax_steps: settings
+                .parallax_enabled
+                .then_some(settings.parallax_steps)
+                .unwrap_or(0),
         }
     }
 
-    pub fn set_map(&mut self, ao_map: Option<&VulkanTexture>, shadow_map: Option<&VulkanTexture>) {
+    pub fn set_map(&mut self, ao_map: Option<&VulkanTexture>, shadow_maps: Option<ShadowMaps>) {
         update_input_descriptor_set(
             &self.context,
             self.descriptors.input_set,
             ao_map.unwrap_or(&self.dummy_texture),
-            shadow_map.unwrap_or(&self.dummy_texture),
+            shadow_maps.unwrap_or(self.dummy_shadow_maps()),
         );
     }
 
@@ -184,16 +458,41 @@ impl LightPass {
     pub fn set_emissive_intensity(&mut self, emissive_intensity: f32) {
         self.emissive_intensity = emissive_intensity;
     }
+
+    pub fn set_ibl_debug_roughness(&mut self, roughness: f32) {
+        self.ibl_debug_roughness = roughness;
+    }
+
+    pub fn set_polygon_mode(&mut self, polygon_mode: PolygonMode) {
+        self.polygon_mode = polygon_mode;
+    }
+
+    pub fn set_parallax_steps(&mut self, steps: u32) {
+        self.parallax_steps = steps;
+    }
+
+    pub fn set_light_cluster_grid(&mut self, grid: [u32; 3]) {
+        if let Some(light_cluster_grid) = self.light_cluster_grid.as_mut() {
+            *light_cluster_grid = grid;
+        }
+    }
 }
 
 impl LightPass {
+    fn dummy_shadow_maps(&self) -> ShadowMaps {
+        ShadowMaps {
+            cascades: &self.dummy_shadow_map,
+            punctual: &self.dummy_shadow_map,
+        }
+    }
+
     pub fn set_model(
         &mut self,
         model_data: &ModelData,
         camera_buffers: &[Buffer],
         environment: &Environment,
         ao_map: Option<&VulkanTexture>,
-        shadow_map: Option<&VulkanTexture>,
+        shadow_maps: Option<ShadowMaps>,
     ) {
         let model_rc = model_data.model.upgrade().expect("模型已被释放！");
 
@@ -203,15 +502,18 @@ impl LightPass {
                 camera_buffers,
                 model_transform_buffers: &model_data.transform_ubos,
                 model_skin_buffers: &model_data.skin_ubos,
+                instance_buffers: &model_data.instance_buffers,
                 light_buffers: &model_data.light_buffers,
+                light_cluster_buffers: model_data.light_clusters.as_deref(),
                 render_data_buffers: &model_data.render_data_buffers,
+                texture_transforms: &model_data.texture_transforms,
                 dummy_texture: &self.dummy_texture,
                 environment,
 
                 model: &model_rc.borrow(),
             },
             ao_map.unwrap_or(&self.dummy_texture),
-            shadow_map.unwrap_or(&self.dummy_texture),
+            shadow_maps.unwrap_or(self.dummy_shadow_maps()),
         );
     }
 
@@ -220,19 +522,139 @@ impl LightPass {
         command_buffer: vk::CommandBuffer,
         frame_index: usize,
         model_data: &ModelData,
+        camera_position: Point3<f32>,
+        depth_prepass: bool,
     ) {
         let device = self.context.device();
-        let model = model_data.model.upgrade().expect("模型已被释放！");
-        let model = model.borrow();
+
+        //深度预pass已经写好了不透明部分的深度
+        let (opaque_pipelines, opaque_unculled_pipelines) = if depth_prepass {
+            (
+                self.opaque_depth_equal_pipelines,
+                self.opaque_depth_equal_unculled_pipelines,
+            )
+        } else {
+            (self.opaque_pipelines, self.opaque_unculled_pipelines)
+        };
+
+        let first_pipeline = match self.polygon_mode {
+            PolygonMode::Fill => opaque_pipelines.default,
+            PolygonMode::Line => self.line_pipeline,
+            PolygonMode::Point => self.point_pipeline,
+        };
 
         unsafe {
             device.cmd_bind_pipeline(
                 command_buffer,
                 vk::PipelineBindPoint::GRAPHICS,
-                self.opaque_pipeline,
+                first_pipeline,
             )
         };
 
+        self.cmd_bind_shared_descriptor_sets(command_buffer);
+
+        //线框和点模式下所有primitive共用一条管线
+        if self.polygon_mode != PolygonMode::Fill {
+            self.register_model_draw_commands(
+                command_buffer,
+                frame_index,
+                model_data,
+                None,
+                |_| true,
+            );
+            return;
+        }
+
+        //OPAQUE和MASK分开绘制，不透明部分的管线没有discard
+        let opaque_draws: [(WindingPipelines, fn(&&Primitive) -> bool); 4] = [
+            (opaque_pipelines, |p| {
+                is_opaque(p) && !p.material().is_double_sided()
+            }),
+            (opaque_unculled_pipelines, |p| {
+                is_opaque(p) && p.material().is_double_sided()
+            }),
+            (self.mask_pipelines, |p| {
+                p.material().is_masked() && !p.material().is_double_sided()
+            }),
+            (self.mask_unculled_pipelines, |p| {
+                p.material().is_masked() && p.material().is_double_sided()
+            }),
+        ];
+
+        //镜像节点的绕序相反，用顺时针为正面的管线再绘制一遍
+        for mirrored in [false, true] {
+            for (index, (pipelines, primitive_filter)) in opaque_draws.into_iter().enumerate() {
+                //第一条管线已经绑定过
+                if index > 0 || mirrored {
+                    unsafe {
+                        device.cmd_bind_pipeline(
+                            command_buffer,
+                            vk::PipelineBindPoint::GRAPHICS,
+                            pipelines.get(mirrored),
+                        )
+                    };
+                }
+                self.register_model_draw_commands(
+                    command_buffer,
+                    frame_index,
+                    model_data,
+                    Some(mirrored),
+                    primitive_filter,
+                );
+            }
+        }
+
+        self.register_transparent_draw_commands(
+            command_buffer,
+            frame_index,
+            model_data,
+            camera_position,
+        );
+    }
+
+    //只画OPAQUE的primitive并写入深度，MASK需要采样纹理做discard，仍在光照pass中写深度
+    //只用于填充模式，线框和点模式直接用光照pass的深度
+    pub fn cmd_draw_depth_prepass(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        frame_index: usize,
+        model_data: &ModelData,
+    ) {
+        let device = self.context.device();
+        self.cmd_bind_shared_descriptor_sets(command_buffer);
+
+        let prepass_draws: [(WindingPipelines, fn(&&Primitive) -> bool); 2] = [
+            (self.depth_prepass_pipelines, |p| {
+                is_opaque(p) && !p.material().is_double_sided()
+            }),
+            (self.depth_prepass_unculled_pipelines, |p| {
+                is_opaque(p) && p.material().is_double_sided()
+            }),
+        ];
+
+        for mirrored in [false, true] {
+            for (pipelines, primitive_filter) in prepass_draws.into_iter() {
+                unsafe {
+                    device.cmd_bind_pipeline(
+                        command_buffer,
+                        vk::PipelineBindPoint::GRAPHICS,
+                        pipelines.get(mirrored),
+                    )
+                };
+                self.register_model_draw_commands(
+                    command_buffer,
+                    frame_index,
+                    model_data,
+                    Some(mirrored),
+                    primitive_filter,
+                );
+            }
+        }
+    }
+
+    fn cmd_bind_shared_descriptor_sets(&self, command_buffer: vk::CommandBuffer) {
+        let device = self.context.device();
+
         unsafe {
             device.cmd_bind_descriptor_sets(
                 command_buffer,
@@ -254,41 +676,106 @@ impl LightPass {
                 &[],
             )
         };
+    }
 
-        self.register_model_draw_commands(command_buffer, frame_index, &model, |p: &&Primitive| {
-            !p.material().is_transparent() && !p.material().is_double_sided()
-        });
+    //BLEND的primitive按到相机的距离从远到近逐个绘制，实例化的节点也拆开参与排序
+    fn register_transparent_draw_commands(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        frame_index: usize,
+        model_data: &ModelData,
+        camera_position: Point3<f32>,
+    ) {
+        let device = self.context.device();
+        let model_transform_ubo_offset = self.context.get_ubo_alignment::<Matrix4<f32>>();
+        let model_skin_ubo_offset = self.context.get_ubo_alignment::<JointsBuffer>();
+        let dynamic_descriptors = &self.descriptors.dynamic_data_sets[frame_index..=frame_index];
 
-        unsafe {
-            device.cmd_bind_pipeline(
-                command_buffer,
-                vk::PipelineBindPoint::GRAPHICS,
-                self.opaque_unculled_pipeline,
-            )
-        };
+        let model = model_data.model.upgrade().expect("模型已被释放！");
+        let model = model.borrow();
+        let nodes = model.nodes();
 
-        self.register_model_draw_commands(command_buffer, frame_index, &model, |p| {
-            !p.material().is_transparent() && p.material().is_double_sided()
-        });
+        let light_count = nodes.light_nodes().count() as u32;
 
-        unsafe {
-            device.cmd_bind_pipeline(
-                command_buffer,
-                vk::PipelineBindPoint::GRAPHICS,
-                self.transparent_pipeline,
-            )
-        };
+        let mut draws = Vec::new();
+        for (index, node) in nodes.mesh_nodes().enumerate() {
+            if !node.is_visible() || model_data.is_node_culled(index) {
+                continue;
+            }
+            let mesh = model.mesh(node.mesh_index().unwrap());
+            for primitive in mesh
+                .primitives()
+                .iter()
+                .filter(|p| p.material().is_transparent())
+            {
+                let center = (primitive.aabb() * node.transform()).get_center();
+                let distance = (center - camera_position.to_vec()).magnitude2();
+                draws.push((
+                    distance,
+                    index,
+                    node,
+                    primitive.index(),
+                    primitive.material().is_double_sided(),
+                ));
+            }
+        }
+        draws.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(Ordering::Equal));
+
+        //排序后相邻的primitive可能剔除方式或绕序不同，只在变化时切换管线
+        let mut bound_pipeline = vk::Pipeline::null();
+        for (_, index, node, primitive_index, double_sided) in draws {
+            let pipelines = if double_sided {
+                self.transparent_unculled_pipelines
+            } else {
+                self.transparent_pipelines
+            };
+            let pipeline = pipelines.get(node.is_mirrored());
+            if pipeline != bound_pipeline {
+                unsafe {
+                    device.cmd_bind_pipeline(
+                        command_buffer,
+                        vk::PipelineBindPoint::GRAPHICS,
+                        pipeline,
+                    )
+                };
+                self.cmd_push_instanced_flag(command_buffer, false);
+                bound_pipeline = pipeline;
+            }
 
-        self.register_model_draw_commands(command_buffer, frame_index, &model, |p| {
-            p.material().is_transparent()
-        });
+            let mesh = model.mesh(node.mesh_index().unwrap());
+            let skin_index = node.skin_index().unwrap_or(0);
+
+            unsafe {
+                device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    self.pipeline_layout,
+                    DYNAMIC_DATA_SET_INDEX,
+                    dynamic_descriptors,
+                    &[
+                        model_transform_ubo_offset * index as u32,
+                        model_skin_ubo_offset * skin_index as u32,
+                    ],
+                )
+            };
+
+            self.register_primitive_draw_commands(
+                command_buffer,
+                mesh,
+                model_data.skinned_vertices(frame_index, index),
+                light_count,
+                move |p: &&Primitive| p.index() == primitive_index,
+                0..1,
+            );
+        }
     }
 
     fn register_model_draw_commands<F>(
         &self,
         command_buffer: vk::CommandBuffer,
         frame_index: usize,
-        model: &Model,
+        model_data: &ModelData,
+        mirrored: Option<bool>,
         primitive_filter: F,
     ) where
         F: FnMut(&&Primitive) -> bool + Copy,
@@ -296,14 +783,25 @@ impl LightPass {
         let device = self.context.device();
         let model_transform_ubo_offset = self.context.get_ubo_alignment::<Matrix4<f32>>();
         let model_skin_ubo_offset = self.context.get_ubo_alignment::<JointsBuffer>();
+        let dynamic_descriptors = &self.descriptors.dynamic_data_sets[frame_index..=frame_index];
+
+        let model = model_data.model.upgrade().expect("模型已被释放！");
+        let model = model.borrow();
+
+        let light_count = model.nodes().light_nodes().count() as u32;
+
+        self.cmd_push_instanced_flag(command_buffer, false);
+
+        for (index, node) in model.nodes().mesh_nodes().enumerate() {
+            //重复的网格在下面一次性实例化绘制，隐藏和视锥体外的节点直接跳过
+            if model_data.instancing.is_node_instanced(index)
+                || !node.is_visible()
+                || model_data.is_node_culled(index)
+                || !matches_winding(mirrored, node.is_mirrored())
+            {
+                continue;
+            }
 
-        for (index, node) in model
-            .nodes()
-            .nodes()
-            .iter()
-            .filter(|n| n.mesh_index().is_some())
-            .enumerate()
-        {
             let mesh = model.mesh(node.mesh_index().unwrap());
             let skin_index = node.skin_index().unwrap_or(0);
 
@@ -313,7 +811,7 @@ impl LightPass {
                     vk::PipelineBindPoint::GRAPHICS,
                     self.pipeline_layout,
                     DYNAMIC_DATA_SET_INDEX,
-                    &self.descriptors.dynamic_data_sets[frame_index..=frame_index],
+                    dynamic_descriptors,
                     &[
                         model_transform_ubo_offset * index as u32,
                         model_skin_ubo_offset * skin_index as u32,
@@ -321,91 +819,174 @@ impl LightPass {
                 )
             };
 
-            for primitive in mesh.primitives().iter().filter(primitive_filter) {
-                let primitive_index = primitive.index();
+            self.register_primitive_draw_commands(
+                command_buffer,
+                mesh,
+                model_data.skinned_vertices(frame_index, index),
+                light_count,
+                primitive_filter,
+                0..1,
+            );
+        }
 
-                unsafe {
-                    device.cmd_bind_descriptor_sets(
-                        command_buffer,
-                        vk::PipelineBindPoint::GRAPHICS,
-                        self.pipeline_layout,
-                        PER_PRIMITIVE_DATA_SET_INDEX,
-                        &self.descriptors.per_primitive_sets[primitive_index..=primitive_index],
-                        &[],
-                    )
-                };
+        if model_data.instancing.batches.is_empty() {
+            return;
+        }
+
+        //实例化绘制的变换从存储缓冲中读取，动态偏移不会被用到
+        unsafe {
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                DYNAMIC_DATA_SET_INDEX,
+                dynamic_descriptors,
+                &[0, 0],
+            )
+        };
+
+        self.cmd_push_instanced_flag(command_buffer, true);
+
+        for batch in model_data
+            .instancing
+            .batches
+            .iter()
+            .filter(|b| matches_winding(mirrored, b.mirrored))
+        {
+            let first_instance = batch.first_instance;
+            let instance_count = batch.node_indices.len() as u32;
+
+            self.register_primitive_draw_commands(
+                command_buffer,
+                model.mesh(batch.mesh_index),
+                None,
+                light_count,
+                primitive_filter,
+                first_instance..first_instance + instance_count,
+            );
+        }
+    }
 
+    fn cmd_push_instanced_flag(&self, command_buffer: vk::CommandBuffer, instanced: bool) {
+        let instanced = instanced as u32;
+        unsafe {
+            self.context.device().cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::VERTEX,
+                INSTANCED_PUSH_CONSTANT_OFFSET,
+                any_as_u8_slice(&instanced),
+            )
+        };
+    }
+
+    //skinned_vertices不为空时从计算着色器蒙皮的结果中读取顶点
+    fn register_primitive_draw_commands<F>(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        mesh: &Mesh,
+        skinned_vertices: Option<(vk::Buffer, &[vk::DeviceSize])>,
+        light_count: u32,
+        mut primitive_filter: F,
+        instances: Range<u32>,
+    ) where
+        F: FnMut(&&Primitive) -> bool + Copy,
+    {
+        let device = self.context.device();
+
+        for (position, primitive) in mesh
+            .primitives()
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| primitive_filter(p))
+        {
+            let primitive_index = primitive.index();
+
+            unsafe {
+                device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    self.pipeline_layout,
+                    PER_PRIMITIVE_DATA_SET_INDEX,
+                    &self.descriptors.per_primitive_sets[primitive_index..=primitive_index],
+                    &[],
+                )
+            };
+
+            let (vertex_buffer, vertex_offset) = match skinned_vertices {
+                Some((buffer, offsets)) => (buffer, offsets[position]),
+                None => (
+                    primitive.vertices().buffer().buffer,
+                    primitive.vertices().offset(),
+                ),
+            };
+
+            unsafe {
+                device.cmd_bind_vertex_buffers(
+                    command_buffer,
+                    0,
+                    &[vertex_buffer],
+                    &[vertex_offset],
+                );
+            }
+
+            if let Some(index_buffer) = primitive.indices() {
                 unsafe {
-                    device.cmd_bind_vertex_buffers(
+                    device.cmd_bind_index_buffer(
                         command_buffer,
-                        0,
-                        &[primitive.vertices().buffer().buffer],
-                        &[primitive.vertices().offset()],
+                        index_buffer.buffer().buffer,
+                        index_buffer.offset(),
+                        index_buffer.index_type(),
                     );
                 }
+            }
+
+            unsafe {
+                let material: MaterialUniform = primitive.material().into();
+                let mut data = any_as_u8_slice(&material).to_vec();
+
+                let config = ConfigUniform {
+                    light_count,
+                    output_mode: self.output_mode as _,
+                    emissive_intensity: self.emissive_intensity,
+                    ibl_debug_roughness: self.ibl_debug_roughness,
+                    light_cluster_grid: self.light_cluster_grid.map_or(0, pack_cluster_grid),
+                    parallax_steps: self.parallax_steps,
+                };
+                data.extend_from_slice(any_as_u8_slice(&config));
+
+                device.cmd_push_constants(
+                    command_buffer,
+                    self.pipeline_layout,
+                    vk::ShaderStageFlags::FRAGMENT,
+                    0,
+                    data.as_slice(),
+                );
+            };
 
-                if let Some(index_buffer) = primitive.indices() {
+            match primitive.indices() {
+                Some(index_buffer) => {
                     unsafe {
-                        device.cmd_bind_index_buffer(
+                        device.cmd_draw_indexed(
                             command_buffer,
-                            index_buffer.buffer().buffer,
-                            index_buffer.offset(),
-                            index_buffer.index_type(),
-                        );
-                    }
+                            index_buffer.element_count(),
+                            instances.len() as u32,
+                            0,
+                            0,
+                            instances.start,
+                        )
+                    };
                 }
-
-                unsafe {
-                    let material: MaterialUniform = primitive.material().into();
-                    let mut data = any_as_u8_slice(&material).to_vec();
-
-                    let light_count = model
-                        .nodes()
-                        .nodes()
-                        .iter()
-                        .filter(|n| n.light_index().is_some())
-                        .count() as u32;
-
-                    let config = ConfigUniform {
-                        light_count,
-                        output_mode: self.output_mode as _,
-                        emissive_intensity: self.emissive_intensity,
+                None => {
+                    unsafe {
+                        device.cmd_draw(
+                            command_buffer,
+                            primitive.vertices().element_count(),
+                            instances.len() as u32,
+                            0,
+                            instances.start,
+                        )
                     };
-                    data.extend_from_slice(any_as_u8_slice(&config));
-
-                    device.cmd_push_constants(
-                        command_buffer,
-                        self.pipeline_layout,
-                        vk::ShaderStageFlags::FRAGMENT,
-                        0,
-                        data.as_slice(),
-                    );
-                };
-
-                match primitive.indices() {
-                    Some(index_buffer) => {
-                        unsafe {
-                            device.cmd_draw_indexed(
-                                command_buffer,
-                                index_buffer.element_count(),
-                                1,
-                                0,
-                                0,
-                                0,
-                            )
-                        };
-                    }
-                    None => {
-                        unsafe {
-                            device.cmd_draw(
-                                command_buffer,
-                                primitive.vertices().element_count(),
-                                1,
-                                0,
-                                0,
-                            )
-                        };
-                    }
                 }
             }
         }
@@ -416,10 +997,19 @@ impl Drop for LightPass {
     fn drop(&mut self) {
         self.context.graphics_queue_wait_idle();
         let device = self.context.device();
+        self.opaque_pipelines.destroy(device);
+        self.opaque_unculled_pipelines.destroy(device);
+        self.mask_unculled_pipelines.destroy(device);
+        self.mask_pipelines.destroy(device);
+        self.transparent_unculled_pipelines.destroy(device);
+        self.transparent_pipelines.destroy(device);
+        self.depth_prepass_pipelines.destroy(device);
+        self.depth_prepass_unculled_pipelines.destroy(device);
+        self.opaque_depth_equal_pipelines.destroy(device);
+        self.opaque_depth_equal_unculled_pipelines.destroy(device);
         unsafe {
-            device.destroy_pipeline(self.opaque_pipeline, None);
-            device.destroy_pipeline(self.opaque_unculled_pipeline, None);
-            device.destroy_pipeline(self.transparent_pipeline, None);
+            device.destroy_pipeline(self.line_pipeline, None);
+            device.destroy_pipeline(self.point_pipeline, None);
             device.destroy_pipeline_layout(self.pipeline_layout, None);
         }
     }
@@ -430,8 +1020,11 @@ struct DescriptorsResources<'a> {
     camera_buffers: &'a [Buffer],
     model_transform_buffers: &'a [Buffer],
     model_skin_buffers: &'a [Buffer],
+    instance_buffers: &'a [Buffer],
     light_buffers: &'a [Buffer],
+    light_cluster_buffers: Option<&'a [Buffer]>,
     render_data_buffers: &'a [Buffer],
+    texture_transforms: &'a Buffer,
     dummy_texture: &'a VulkanTexture,
     environment: &'a Environment,
     model: &'a Model,
@@ -467,7 +1060,7 @@ fn create_descriptors(
     context: &Arc<Context>,
     resources: DescriptorsResources,
     ao_map: &VulkanTexture,
-    shadow_map: &VulkanTexture,
+    shadow_maps: ShadowMaps,
 ) -> Descriptors {
     let pool = create_descriptor_pool(context.device(), resources);
 
@@ -484,7 +1077,7 @@ fn create_descriptors(
         create_per_primitive_descriptor_sets(context, pool, per_primitive_layout, resources);
 
     let input_layout = create_input_descriptor_set_layout(context.device());
-    let input_set = create_input_descriptor_set(context, pool, input_layout, ao_map, shadow_map);
+    let input_set = create_input_descriptor_set(context, pool, input_layout, ao_map, shadow_maps);
 
     Descriptors {
         context: Arc::clone(context),
@@ -504,23 +1097,29 @@ fn create_descriptor_pool(
     device: &Device,
     descriptors_resources: DescriptorsResources,
 ) -> vk::DescriptorPool {
-    const GLOBAL_TEXTURES_COUNT: u32 = 4; // irradiance, prefiltered, brdf lut, ao
+    const GLOBAL_TEXTURES_COUNT: u32 = 6; // irradiance, prefiltered, brdf lut, ao, shadow maps
     const STATIC_SETS_COUNT: u32 = 1;
     const INPUT_SETS_COUNT: u32 = 1;
 
     let descriptor_count = descriptors_resources.camera_buffers.len() as u32;
     let primitive_count = descriptors_resources.model.primitive_count() as u32;
-    let textures_desc_count = primitive_count * 5;
+    let textures_desc_count = primitive_count * 6;
 
     let pool_sizes = [
+        //相机和主光源数据，以及每个primitive的纹理变换
         vk::DescriptorPoolSize {
             ty: vk::DescriptorType::UNIFORM_BUFFER,
-            descriptor_count: descriptor_count * 2,
+            descriptor_count: descriptor_count * 2 + primitive_count,
         },
         vk::DescriptorPoolSize {
             ty: vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
             descriptor_count: descriptor_count * 2,
         },
+        //实例、灯光和分簇结果
+        vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::STORAGE_BUFFER,
+            descriptor_count: descriptor_count * 3,
+        },
         vk::DescriptorPoolSize {
             ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
             descriptor_count: textures_desc_count + GLOBAL_TEXTURES_COUNT,
@@ -544,8 +1143,8 @@ fn create_dynamic_data_descriptor_set_layout(device: &Device) -> vk::DescriptorS
             .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
             .build(),
         vk::DescriptorSetLayoutBinding::builder()
-            .binding(LIGHT_UBO_BINDING)
-            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+            .binding(LIGHTS_SSBO_BINDING)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
             .descriptor_count(1)
             .stage_flags(vk::ShaderStageFlags::FRAGMENT)
             .build(),
@@ -567,6 +1166,18 @@ fn create_dynamic_data_descriptor_set_layout(device: &Device) -> vk::DescriptorS
             .descriptor_count(1)
             .stage_flags(vk::ShaderStageFlags::FRAGMENT)
             .build(),
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(INSTANCES_SSBO_BINDING)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::VERTEX)
+            .build(),
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(LIGHT_CLUSTERS_SSBO_BINDING)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .build(),
     ];
 
     let layout_info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
@@ -597,6 +1208,9 @@ fn create_dynamic_data_descriptor_sets(
             .allocate_descriptor_sets(&allocate_info)
             .unwrap()
     };
+    for (i, set) in sets.iter().enumerate() {
+        debug_name!(context, *set, "LightPass.DynamicData.DescriptorSet[{}]", i);
+    }
 
     sets.iter().enumerate().for_each(|(i, set)| {
         let camera_ubo = &resources.camera_buffers[i];
@@ -604,6 +1218,11 @@ fn create_dynamic_data_descriptor_sets(
         let model_transform_ubo = &resources.model_transform_buffers[i];
         let model_skin_ubo = &resources.model_skin_buffers[i];
         let render_data_buffer = &resources.render_data_buffers[i];
+        let instance_buffer = &resources.instance_buffers[i];
+        //不分簇时着色器不会读取，绑定灯光缓冲占位
+        let light_cluster_buffer = resources
+            .light_cluster_buffers
+            .map_or(light_buffer, |buffers| &buffers[i]);
 
         let camera_buffer_info = [vk::DescriptorBufferInfo::builder()
             .buffer(camera_ubo.buffer)
@@ -635,6 +1254,18 @@ fn create_dynamic_data_descriptor_sets(
             .range(vk::WHOLE_SIZE)
             .build()];
 
+        let instance_buffer_info = [vk::DescriptorBufferInfo::builder()
+            .buffer(instance_buffer.buffer)
+            .offset(0)
+            .range(vk::WHOLE_SIZE)
+            .build()];
+
+        let light_cluster_buffer_info = [vk::DescriptorBufferInfo::builder()
+            .buffer(light_cluster_buffer.buffer)
+            .offset(0)
+            .range(vk::WHOLE_SIZE)
+            .build()];
+
         let descriptor_writes = [
             vk::WriteDescriptorSet::builder()
                 .dst_set(*set)
@@ -644,8 +1275,8 @@ fn create_dynamic_data_descriptor_sets(
                 .build(),
             vk::WriteDescriptorSet::builder()
                 .dst_set(*set)
-                .dst_binding(LIGHT_UBO_BINDING)
-                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                .dst_binding(LIGHTS_SSBO_BINDING)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
                 .buffer_info(&light_buffer_info)
                 .build(),
             vk::WriteDescriptorSet::builder()
@@ -666,6 +1297,18 @@ fn create_dynamic_data_descriptor_sets(
                 .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
                 .buffer_info(&main_light_buffer_info)
                 .build(),
+            vk::WriteDescriptorSet::builder()
+                .dst_set(*set)
+                .dst_binding(INSTANCES_SSBO_BINDING)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .buffer_info(&instance_buffer_info)
+                .build(),
+            vk::WriteDescriptorSet::builder()
+                .dst_set(*set)
+                .dst_binding(LIGHT_CLUSTERS_SSBO_BINDING)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .buffer_info(&light_cluster_buffer_info)
+                .build(),
         ];
 
         unsafe {
@@ -725,6 +1368,7 @@ fn create_static_data_descriptor_set(
             .allocate_descriptor_sets(&allocate_info)
             .unwrap()[0]
     };
+    debug_name!(context, set, "LightPass.StaticData.DescriptorSet");
 
     let irradiance_info = [vk::DescriptorImageInfo::builder()
         .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
@@ -806,6 +1450,18 @@ fn create_per_primitive_descriptor_set_layout(device: &Device) -> vk::Descriptor
             .descriptor_count(1)
             .stage_flags(vk::ShaderStageFlags::FRAGMENT)
             .build(),
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(HEIGHT_SAMPLER_BINDING)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .build(),
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(TEXTURE_TRANSFORMS_UBO_BINDING)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .build(),
     ];
 
     let layout_info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
@@ -836,48 +1492,65 @@ fn create_per_primitive_descriptor_sets(
             .allocate_descriptor_sets(&allocate_info)
             .unwrap()
     };
+    for (i, set) in sets.iter().enumerate() {
+        debug_name!(context, *set, "LightPass.PerPrimitive.DescriptorSet[{}]", i);
+    }
 
     let model = resources.model;
-    let textures = resources.model.textures();
+    let color_textures = resources.model.textures(TextureUsage::Color);
+    let data_textures = resources.model.textures(TextureUsage::Data);
+    let texture_transforms_size = context.get_ubo_alignment::<TextureTransformsUniform>();
     let mut primitive_index = 0;
     for mesh in model.meshes() {
         for primitive in mesh.primitives() {
             let material = primitive.material();
             let albedo_info = create_descriptor_image_info(
                 material.get_color_texture_index(),
-                textures,
+                color_textures,
                 resources.dummy_texture,
             );
             let normals_info = create_descriptor_image_info(
                 material.get_normals_texture_index(),
-                textures,
+                data_textures,
                 resources.dummy_texture,
             );
 
-            let material_texture = match material.get_workflow() {
+            //高光光泽度纹理的RGB是sRGB颜色，金属粗糙度纹理是线性数据
+            let (material_texture, material_textures) = match material.get_workflow() {
                 PBRWorkflow::MetallicRoughness(workflow) => {
-                    workflow.get_metallic_roughness_texture()
+                    (workflow.get_metallic_roughness_texture(), data_textures)
                 }
                 PBRWorkflow::SpecularGlossiness(workflow) => {
-                    workflow.get_specular_glossiness_texture()
+                    (workflow.get_specular_glossiness_texture(), color_textures)
                 }
             };
             let material_info = create_descriptor_image_info(
                 material_texture.map(|t| t.get_index()),
-                textures,
+                material_textures,
                 resources.dummy_texture,
             );
             let occlusion_info = create_descriptor_image_info(
                 material.get_occlusion_texture_index(),
-                textures,
+                data_textures,
                 resources.dummy_texture,
             );
             let emissive_info = create_descriptor_image_info(
                 material.get_emissive_texture_index(),
-                textures,
+                color_textures,
+                resources.dummy_texture,
+            );
+            let height_info = create_descriptor_image_info(
+                material.get_height_texture_index(),
+                data_textures,
                 resources.dummy_texture,
             );
 
+            let texture_transforms_info = [vk::DescriptorBufferInfo::builder()
+                .buffer(resources.texture_transforms.buffer)
+                .offset(u64::from(texture_transforms_size) * primitive_index as u64)
+                .range(size_of::<TextureTransformsUniform>() as _)
+                .build()];
+
             let set = sets[primitive_index];
             primitive_index += 1;
 
@@ -912,6 +1585,18 @@ fn create_per_primitive_descriptor_sets(
                     .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
                     .image_info(&emissive_info)
                     .build(),
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(set)
+                    .dst_binding(HEIGHT_SAMPLER_BINDING)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(&height_info)
+                    .build(),
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(set)
+                    .dst_binding(TEXTURE_TRANSFORMS_UBO_BINDING)
+                    .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                    .buffer_info(&texture_transforms_info)
+                    .build(),
             ];
 
             unsafe {
@@ -939,6 +1624,12 @@ fn create_input_descriptor_set_layout(device: &Device) -> vk::DescriptorSetLayou
             .descriptor_count(1)
             .stage_flags(vk::ShaderStageFlags::FRAGMENT)
             .build(),
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(PUNCTUAL_SHADOW_MAP_SAMPLER_BINDING)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .build(),
     ];
 
     let layout_info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
@@ -955,7 +1646,7 @@ fn create_input_descriptor_set(
     pool: vk::DescriptorPool,
     layout: vk::DescriptorSetLayout,
     ao_map: &VulkanTexture,
-    shadow_map: &VulkanTexture,
+    shadow_maps: ShadowMaps,
 ) -> vk::DescriptorSet {
     let layouts = [layout];
     let allocate_info = vk::DescriptorSetAllocateInfo::builder()
@@ -967,8 +1658,9 @@ fn create_input_descriptor_set(
             .allocate_descriptor_sets(&allocate_info)
             .unwrap()[0]
     };
+    debug_name!(context, set, "LightPass.Input.DescriptorSet");
 
-    update_input_descriptor_set(context, set, ao_map, shadow_map);
+    update_input_descriptor_set(context, set, ao_map, shadow_maps);
 
     set
 }
@@ -977,7 +1669,7 @@ fn update_input_descriptor_set(
     context: &Arc<Context>,
     set: vk::DescriptorSet,
     ao_map: &VulkanTexture,
-    shadow_map: &VulkanTexture,
+    shadow_maps: ShadowMaps,
 ) {
     let ao_map_info = [vk::DescriptorImageInfo::builder()
         .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
@@ -987,8 +1679,14 @@ fn update_input_descriptor_set(
 
     let shadow_map_info = [vk::DescriptorImageInfo::builder()
         .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-        .image_view(shadow_map.view)
-        .sampler(shadow_map.sampler.expect("shadowmap没有sampler"))
+        .image_view(shadow_maps.cascades.view)
+        .sampler(shadow_maps.cascades.sampler.expect("shadowmap没有sampler"))
+        .build()];
+
+    let punctual_shadow_map_info = [vk::DescriptorImageInfo::builder()
+        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        .image_view(shadow_maps.punctual.view)
+        .sampler(shadow_maps.punctual.sampler.expect("shadowmap没有sampler"))
         .build()];
 
     let descriptor_writes = [
@@ -1004,6 +1702,12 @@ fn update_input_descriptor_set(
             .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
             .image_info(&shadow_map_info)
             .build(),
+        vk::WriteDescriptorSet::builder()
+            .dst_set(set)
+            .dst_binding(PUNCTUAL_SHADOW_MAP_SAMPLER_BINDING)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&punctual_shadow_map_info)
+            .build(),
     ];
 
     unsafe {
@@ -1015,11 +1719,10 @@ fn update_input_descriptor_set(
 
 fn create_descriptor_image_info(
     index: Option<usize>,
-    textures: &[Texture],
+    textures: &[Option<Texture>],
     dummy_texture: &VulkanTexture,
 ) -> [vk::DescriptorImageInfo; 1] {
-    let (view, sampler) = index
-        .map(|i| &textures[i])
+    let (view, sampler) = find_texture(textures, index)
         .map_or((dummy_texture.view, dummy_texture.sampler.unwrap()), |t| {
             (t.get_view(), t.get_sampler())
         });
@@ -1040,11 +1743,19 @@ fn create_pipeline_layout(device: &Device, descriptors: &Descriptors) -> vk::Pip
     ];
 
     let size = size_of::<MaterialUniform>() + size_of::<ConfigUniform>();
-    let push_constant_range = [vk::PushConstantRange {
-        stage_flags: vk::ShaderStageFlags::FRAGMENT,
-        offset: 0,
-        size: size as _,
-    }];
+    let push_constant_range = [
+        vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            offset: 0,
+            size: size as _,
+        },
+        //是否为实例化绘制
+        vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::VERTEX,
+            offset: INSTANCED_PUSH_CONSTANT_OFFSET,
+            size: size_of::<u32>() as _,
+        },
+    ];
     let layout_info = vk::PipelineLayoutCreateInfo::builder()
         .set_layouts(&layouts)
         .push_constant_ranges(&push_constant_range);
@@ -1052,19 +1763,37 @@ fn create_pipeline_layout(device: &Device, descriptors: &Descriptors) -> vk::Pip
     unsafe { device.create_pipeline_layout(&layout_info, None).unwrap() }
 }
 
+fn is_opaque(primitive: &&Primitive) -> bool {
+    !primitive.material().is_transparent() && !primitive.material().is_masked()
+}
+
+//alpha_test为true时是MASK材质使用的管线
+//depth_prepass为true时深度已由预pass写好，只对深度相等的片元着色
 fn create_opaque_pipeline(
     context: &Arc<Context>,
     msaa_samples: vk::SampleCountFlags,
     enable_face_culling: bool,
+    polygon_mode: PolygonMode,
+    alpha_test: bool,
+    front_face: vk::FrontFace,
     depth_format: vk::Format,
+    reverse_z: bool,
+    depth_prepass: bool,
     layout: vk::PipelineLayout,
 ) -> vk::Pipeline {
-    let (specialization_info, _map_entries, _data) = create_model_frag_shader_specialization();
+    let (specialization_info, _map_entries, _data) =
+        create_model_frag_shader_specialization(alpha_test);
+
+    let compare_op = if depth_prepass {
+        vk::CompareOp::EQUAL
+    } else {
+        depth_compare_op(reverse_z)
+    };
 
     let depth_stencil_info = vk::PipelineDepthStencilStateCreateInfo::builder()
         .depth_test_enable(true)
-        .depth_write_enable(true)
-        .depth_compare_op(vk::CompareOp::LESS_OR_EQUAL)
+        .depth_write_enable(!depth_prepass)
+        .depth_compare_op(compare_op)
         .depth_bounds_test_enable(false)
         .min_depth_bounds(0.0)
         .max_depth_bounds(1.0)
@@ -1091,17 +1820,28 @@ fn create_opaque_pipeline(
     create_renderer_pipeline::<ModelVertex>(
         context,
         RendererPipelineParameters {
+            name: match (alpha_test, depth_prepass, enable_face_culling) {
+                (false, false, true) => "LightPass.Pipeline",
+                (false, false, false) => "LightPass.Pipeline.DoubleSided",
+                (false, true, true) => "LightPass.Pipeline.DepthEqual",
+                (false, true, false) => "LightPass.Pipeline.DepthEqual.DoubleSided",
+                (true, _, true) => "LightPass.Pipeline.Masked",
+                (true, _, false) => "LightPass.Pipeline.Masked.DoubleSided",
+            },
             vertex_shader_name: "model",
             fragment_shader_name: "model",
             vertex_shader_specialization: None,
             fragment_shader_specialization: Some(&specialization_info),
-            msaa_samples,
+            multisampling: MultisampleParameters::new(msaa_samples),
             color_attachment_formats: &[SCENE_COLOR_FORMAT],
             depth_attachment_format: Some(depth_format),
             layout,
             depth_stencil_info: &depth_stencil_info,
             color_blend_attachments: &color_blend_attachments,
             enable_face_culling,
+            front_face,
+            polygon_mode: polygon_mode.to_vk(),
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
             parent: None,
         },
     )
@@ -1110,16 +1850,19 @@ fn create_opaque_pipeline(
 fn create_transparent_pipeline(
     context: &Arc<Context>,
     msaa_samples: vk::SampleCountFlags,
+    enable_face_culling: bool,
+    front_face: vk::FrontFace,
     depth_format: vk::Format,
+    reverse_z: bool,
     layout: vk::PipelineLayout,
     parent: vk::Pipeline,
 ) -> vk::Pipeline {
-    let (specialization_info, _map_entries, _data) = create_model_frag_shader_specialization();
+    let (specialization_info, _map_entries, _data) = create_model_frag_shader_specialization(false);
 
     let depth_stencil_info = vk::PipelineDepthStencilStateCreateInfo::builder()
         .depth_test_enable(true)
         .depth_write_enable(false)
-        .depth_compare_op(vk::CompareOp::LESS_OR_EQUAL)
+        .depth_compare_op(depth_compare_op(reverse_z))
         .depth_bounds_test_enable(false)
         .min_depth_bounds(0.0)
         .max_depth_bounds(1.0)
@@ -1138,31 +1881,95 @@ fn create_transparent_pipeline(
         .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
         .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
         .color_blend_op(vk::BlendOp::ADD)
+        //透明背景时alpha也要叠加，否则半透明物体会覆盖掉下面的不透明度
         .src_alpha_blend_factor(vk::BlendFactor::ONE)
-        .dst_alpha_blend_factor(vk::BlendFactor::ZERO)
+        .dst_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
         .alpha_blend_op(vk::BlendOp::ADD)
         .build()];
 
     create_renderer_pipeline::<ModelVertex>(
         context,
         RendererPipelineParameters {
+            name: if enable_face_culling {
+                "LightPass.Pipeline.Transparent"
+            } else {
+                "LightPass.Pipeline.Transparent.DoubleSided"
+            },
             vertex_shader_name: "model",
             fragment_shader_name: "model",
             vertex_shader_specialization: None,
             fragment_shader_specialization: Some(&specialization_info),
-            msaa_samples,
+            multisampling: MultisampleParameters::new(msaa_samples),
             color_attachment_formats: &[SCENE_COLOR_FORMAT],
             depth_attachment_format: Some(depth_format),
             layout,
             depth_stencil_info: &depth_stencil_info,
             color_blend_attachments: &color_blend_attachments,
-            enable_face_culling: false,
+            enable_face_culling,
+            front_face,
+            polygon_mode: vk::PolygonMode::FILL,
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
             parent: Some(parent),
         },
     )
 }
 
-fn create_model_frag_shader_specialization() -> (
+//顶点着色器与光照pass相同，保证深度完全一致；片元着色器为空，颜色写入被关闭
+fn create_depth_prepass_pipeline(
+    context: &Arc<Context>,
+    msaa_samples: vk::SampleCountFlags,
+    enable_face_culling: bool,
+    front_face: vk::FrontFace,
+    depth_format: vk::Format,
+    reverse_z: bool,
+    layout: vk::PipelineLayout,
+) -> vk::Pipeline {
+    let depth_stencil_info = vk::PipelineDepthStencilStateCreateInfo::builder()
+        .depth_test_enable(true)
+        .depth_write_enable(true)
+        .depth_compare_op(depth_compare_op(reverse_z))
+        .depth_bounds_test_enable(false)
+        .min_depth_bounds(0.0)
+        .max_depth_bounds(1.0)
+        .stencil_test_enable(false)
+        .front(Default::default())
+        .back(Default::default());
+
+    let color_blend_attachments = [vk::PipelineColorBlendAttachmentState::builder()
+        .color_write_mask(vk::ColorComponentFlags::empty())
+        .blend_enable(false)
+        .build()];
+
+    create_renderer_pipeline::<ModelVertex>(
+        context,
+        RendererPipelineParameters {
+            name: if enable_face_culling {
+                "LightPass.Pipeline.DepthPrepass"
+            } else {
+                "LightPass.Pipeline.DepthPrepass.DoubleSided"
+            },
+            vertex_shader_name: "model",
+            fragment_shader_name: "occlusion",
+            vertex_shader_specialization: None,
+            fragment_shader_specialization: None,
+            multisampling: MultisampleParameters::new(msaa_samples),
+            color_attachment_formats: &[SCENE_COLOR_FORMAT],
+            depth_attachment_format: Some(depth_format),
+            layout,
+            depth_stencil_info: &depth_stencil_info,
+            color_blend_attachments: &color_blend_attachments,
+            enable_face_culling,
+            front_face,
+            polygon_mode: vk::PolygonMode::FILL,
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            parent: None,
+        },
+    )
+}
+
+fn create_model_frag_shader_specialization(
+    alpha_test: bool,
+) -> (
     vk::SpecializationInfo,
     Vec<vk::SpecializationMapEntry>,
     Vec<u8>,
@@ -1178,11 +1985,20 @@ fn create_model_frag_shader_specialization() -> (
             offset: size_of::<u32>() as _,
             size: size_of::<u32>(),
         },
+        vk::SpecializationMapEntry {
+            constant_id: 2,
+            offset: (2 * size_of::<u32>()) as _,
+            size: size_of::<vk::Bool32>(),
+        },
     ];
 
     let max_reflection_lod = (PRE_FILTERED_MAP_SIZE as f32).log2().floor() as u32;
 
-    let data = [MAX_LIGHT_COUNT, max_reflection_lod];
+    let data = [
+        MAX_LIGHTS_PER_CLUSTER,
+        max_reflection_lod,
+        alpha_test as vk::Bool32,
+    ];
     let data = Vec::from(unsafe { any_as_u8_slice(&data) });
 
     let specialization_info = vk::SpecializationInfo::builder()
@@ -1,21 +1,35 @@
 pub mod gbufferpass;
+pub mod lightcullingpass;
 pub mod lightpass;
 pub mod shadowcasterpass;
+pub mod skinningpass;
 
 mod uniform;
 
 use gbufferpass::GBufferPass;
 use gltf_loader::model::Model;
+use lightcullingpass::{create_light_cluster_buffers, LightCullingPass, MAX_SIMPLE_LIGHT_COUNT};
 use lightpass::LightPass;
 use rendering::cgmath::Matrix4;
 use rendering::skin::MAX_JOINTS_PER_MESH;
+use rendering::texture::TextureUsage;
+use rendering::vertex::ModelVertex;
+use rendering::Aabb;
+use scene::frustum::Frustum;
 use std::cell::RefCell;
 use std::rc::Weak;
-use std::sync::Arc;
+use std::{mem::size_of, sync::Arc};
 use uniform::*;
-use vulkan::{mem_copy, mem_copy_aligned, Buffer, Context};
+use vulkan::ash::{vk, Device};
+use vulkan::{
+    create_device_local_buffer_with_data, mem_copy, mem_copy_aligned, mem_write_aligned, Buffer,
+    Context,
+};
 
 use self::shadowcasterpass::ShadowCasterPass;
+use self::skinningpass::SkinningPass;
+use super::front_face;
+use super::shadow::{PunctualShadows, ShadowCascades, ShadowFilter};
 
 type JointsBuffer = [Matrix4<f32>; MAX_JOINTS_PER_MESH];
 
@@ -25,8 +39,262 @@ pub struct ModelData {
     transform_ubos: Vec<Buffer>,
     skin_ubos: Vec<Buffer>,
     skin_matrices: Vec<Vec<JointsBuffer>>,
+    //上一帧的变换、蒙皮和实例矩阵，GBuffer用它们计算运动矢量
+    previous_transform_ubos: Vec<Buffer>,
+    previous_skin_ubos: Vec<Buffer>,
+    previous_instance_buffers: Vec<Buffer>,
+    previous_transforms: Option<Vec<Matrix4<f32>>>,
+    previous_skin_matrices: Option<Vec<JointsBuffer>>,
+    previous_instance_transforms: Option<Vec<Matrix4<f32>>>,
     light_buffers: Vec<Buffer>,
+    //灯较多时每帧的分簇结果，灯少时为None，光照pass直接遍历所有灯
+    light_clusters: Option<Vec<Buffer>>,
     render_data_buffers: Vec<Buffer>,
+    instance_buffers: Vec<Buffer>,
+    //每个实例的节点ID，拾取时与模型序号组合
+    instance_pick_ids: Buffer,
+    //每个primitive各纹理的KHR_texture_transform
+    texture_transforms: Buffer,
+    instancing: Instancing,
+    skinned_vertices: Option<SkinnedVertices>,
+    gpu_skinning_enabled: bool,
+    //每个网格节点是否在相机视锥体外
+    culled_nodes: Vec<bool>,
+    //每个网格节点在最近一次遮挡查询中是否被完全挡住
+    occluded_nodes: Vec<bool>,
+    //成功上传的纹理数，加载时统计
+    texture_count: usize,
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct CullingStats {
+    pub drawn: usize,
+    pub frustum_culled: usize,
+    pub occluded: usize,
+    //主pass每个primitive一次绘制，实例化的批次只算一次
+    pub draw_calls: usize,
+    pub total_draw_calls: usize,
+    pub vertices: usize,
+    pub triangles: usize,
+    pub total_triangles: usize,
+}
+
+impl CullingStats {
+    pub fn merge(self, other: CullingStats) -> Self {
+        Self {
+            drawn: self.drawn + other.drawn,
+            frustum_culled: self.frustum_culled + other.frustum_culled,
+            occluded: self.occluded + other.occluded,
+            draw_calls: self.draw_calls + other.draw_calls,
+            total_draw_calls: self.total_draw_calls + other.total_draw_calls,
+            vertices: self.vertices + other.vertices,
+            triangles: self.triangles + other.triangles,
+            total_triangles: self.total_triangles + other.total_triangles,
+        }
+    }
+}
+
+//计算着色器蒙皮的输出，所有pass共用同一份蒙皮后的顶点
+struct SkinnedVertices {
+    buffers: Vec<Buffer>,
+    //每个网格节点各primitive在输出缓冲中的字节偏移，无蒙皮节点为None
+    offsets: Vec<Option<Vec<vk::DeviceSize>>>,
+}
+
+impl SkinnedVertices {
+    fn create(context: &Arc<Context>, model: &Model, count: u32) -> Option<Self> {
+        let mut vertex_count = 0;
+        let offsets = model
+            .nodes()
+            .mesh_nodes()
+            .map(|node| {
+                node.skin_index()?;
+                let mesh = model.mesh(node.mesh_index().unwrap());
+                let offsets = mesh
+                    .primitives()
+                    .iter()
+                    .map(|p| {
+                        let offset = (vertex_count * size_of::<ModelVertex>()) as vk::DeviceSize;
+                        vertex_count += p.vertices().element_count() as usize;
+                        offset
+                    })
+                    .collect::<Vec<_>>();
+                Some(offsets)
+            })
+            .collect::<Vec<_>>();
+
+        if vertex_count == 0 {
+            return None;
+        }
+
+        let buffers = (0..count)
+            .map(|i| {
+                Buffer::create(
+                    Arc::clone(context),
+                    (vertex_count * size_of::<ModelVertex>()) as vk::DeviceSize,
+                    vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::STORAGE_BUFFER,
+                    vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                    &format!("SkinnedVertices[{}]", i),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        Some(Self { buffers, offsets })
+    }
+}
+
+//按正面绕序创建的一对管线，镜像节点使用顺时针为正面的版本
+#[derive(Copy, Clone)]
+struct WindingPipelines {
+    default: vk::Pipeline,
+    mirrored: vk::Pipeline,
+}
+
+impl WindingPipelines {
+    fn new<F>(mut create_pipeline: F) -> Self
+    where
+        F: FnMut(vk::FrontFace) -> vk::Pipeline,
+    {
+        Self {
+            default: create_pipeline(front_face(false)),
+            mirrored: create_pipeline(front_face(true)),
+        }
+    }
+
+    fn get(&self, mirrored: bool) -> vk::Pipeline {
+        if mirrored {
+            self.mirrored
+        } else {
+            self.default
+        }
+    }
+
+    fn destroy(&self, device: &Device) {
+        unsafe {
+            device.destroy_pipeline(self.default, None);
+            device.destroy_pipeline(self.mirrored, None);
+        }
+    }
+}
+
+//mirrored为None时不区分节点是否镜像
+fn matches_winding(mirrored: Option<bool>, node_mirrored: bool) -> bool {
+    mirrored.is_none() || mirrored == Some(node_mirrored)
+}
+
+//被多个无蒙皮节点共享的网格，合并为一次实例化绘制
+struct InstanceBatch {
+    mesh_index: usize,
+    //同一批次的节点绕序必须一致，按创建时的变换划分
+    mirrored: bool,
+    first_instance: u32,
+    //网格节点序号，与transform_ubos中的顺序一致
+    node_indices: Vec<usize>,
+}
+
+struct Instancing {
+    batches: Vec<InstanceBatch>,
+    instanced_nodes: Vec<bool>,
+    instance_count: usize,
+}
+
+impl Instancing {
+    fn new(model: &Model) -> Self {
+        let mesh_nodes = model.nodes().mesh_nodes().collect::<Vec<_>>();
+
+        let mut batches: Vec<InstanceBatch> = Vec::new();
+        for (index, node) in mesh_nodes.iter().enumerate() {
+            //蒙皮节点的骨骼矩阵各不相同，仍然逐个绘制
+            if node.skin_index().is_some() {
+                continue;
+            }
+            let mesh_index = node.mesh_index().unwrap();
+            let mirrored = node.is_mirrored();
+            match batches
+                .iter_mut()
+                .find(|b| b.mesh_index == mesh_index && b.mirrored == mirrored)
+            {
+                Some(batch) => batch.node_indices.push(index),
+                None => batches.push(InstanceBatch {
+                    mesh_index,
+                    mirrored,
+                    first_instance: 0,
+                    node_indices: vec![index],
+                }),
+            }
+        }
+        batches.retain(|b| b.node_indices.len() > 1);
+
+        let mut instanced_nodes = vec![false; mesh_nodes.len()];
+        let mut instance_count = 0;
+        for batch in batches.iter_mut() {
+            batch.first_instance = instance_count as u32;
+            instance_count += batch.node_indices.len();
+            batch
+                .node_indices
+                .iter()
+                .for_each(|i| instanced_nodes[*i] = true);
+        }
+
+        Self {
+            batches,
+            instanced_nodes,
+            instance_count,
+        }
+    }
+
+    fn is_node_instanced(&self, mesh_node_index: usize) -> bool {
+        self.instanced_nodes[mesh_node_index]
+    }
+
+    fn create_pick_ids_buffer(&self, context: &Arc<Context>, model: &Model) -> Buffer {
+        let node_indices = model
+            .nodes()
+            .nodes()
+            .iter()
+            .enumerate()
+            .filter(|(_, n)| n.mesh_index().is_some())
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>();
+
+        //没有实例化绘制时也需要一个有效的缓冲绑定到描述符上
+        let mut pick_ids = self
+            .batches
+            .iter()
+            .flat_map(|b| b.node_indices.iter())
+            .map(|i| node_pick_id(node_indices[*i]))
+            .collect::<Vec<_>>();
+        if pick_ids.is_empty() {
+            pick_ids.push(0);
+        }
+
+        create_device_local_buffer_with_data::<u8, _>(
+            context,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            &pick_ids,
+            "InstancePickIds.SSBO",
+        )
+    }
+}
+
+//拾取ID的高8位是模型序号，低24位是节点序号加一，0留给背景
+const PICK_ID_NODE_BITS: u32 = 24;
+
+pub fn model_pick_id(model_index: usize) -> u32 {
+    (model_index as u32) << PICK_ID_NODE_BITS
+}
+
+pub fn node_pick_id(node_index: usize) -> u32 {
+    node_index as u32 + 1
+}
+
+//解析拾取ID，背景返回None，否则返回(模型序号, 节点序号)
+pub fn decode_pick_id(id: u32) -> Option<(usize, usize)> {
+    let node = id & ((1 << PICK_ID_NODE_BITS) - 1);
+    if node == 0 {
+        return None;
+    }
+    Some(((id >> PICK_ID_NODE_BITS) as usize, node as usize - 1))
 }
 
 pub struct ModelRenderer {
@@ -34,17 +302,58 @@ pub struct ModelRenderer {
     pub gbuffer_pass: GBufferPass,
     pub shadow_caster_pass: ShadowCasterPass,
     pub light_pass: LightPass,
+    pub skinning_pass: Option<SkinningPass>,
+    pub light_culling_pass: Option<LightCullingPass>,
 }
 
 impl ModelData {
     pub fn create(context: Arc<Context>, model: Weak<RefCell<Model>>, image_count: u32) -> Self {
         let model_rc = model.upgrade().expect("模型已被释放！");
 
-        let transform_ubos = create_transform_ubos(&context, &model_rc.borrow(), image_count);
-        let (skin_ubos, skin_matrices) =
-            create_skin_ubos(&context, &model_rc.borrow(), image_count);
-        let light_buffers = create_lights_ubos(&context, &model_rc.borrow(), image_count);
+        let transform_ubos =
+            create_transform_ubos(&context, &model_rc.borrow(), image_count, "Transforms.UBO");
+        let previous_transform_ubos = create_transform_ubos(
+            &context,
+            &model_rc.borrow(),
+            image_count,
+            "PreviousTransforms.UBO",
+        );
+        let skin_ubos = create_skin_ubos(&context, &model_rc.borrow(), image_count, "Skins.UBO");
+        let previous_skin_ubos = create_skin_ubos(
+            &context,
+            &model_rc.borrow(),
+            image_count,
+            "PreviousSkins.UBO",
+        );
+        let skin_matrices = create_skin_matrices(&model_rc.borrow(), image_count);
+        let light_buffers = create_light_buffers(&context, &model_rc.borrow(), image_count);
+        let light_count = model_rc.borrow().nodes().light_nodes().count();
+        let light_clusters = (light_count > MAX_SIMPLE_LIGHT_COUNT)
+            .then(|| create_light_cluster_buffers(&context, image_count));
         let render_data_buffers = create_render_data_ubos(&context, image_count);
+        let instancing = Instancing::new(&model_rc.borrow());
+        let instance_buffers = create_instance_buffers(
+            &context,
+            instancing.instance_count,
+            image_count,
+            "Instances.SSBO",
+        );
+        let previous_instance_buffers = create_instance_buffers(
+            &context,
+            instancing.instance_count,
+            image_count,
+            "PreviousInstances.SSBO",
+        );
+        let instance_pick_ids = instancing.create_pick_ids_buffer(&context, &model_rc.borrow());
+        let texture_transforms = create_texture_transforms_ubo(&context, &model_rc.borrow());
+        let skinned_vertices = SkinnedVertices::create(&context, &model_rc.borrow(), image_count);
+        let mesh_node_count = model_rc.borrow().nodes().mesh_nodes().count();
+        let texture_count = model_rc
+            .borrow()
+            .textures(TextureUsage::Color)
+            .iter()
+            .flatten()
+            .count();
 
         Self {
             context,
@@ -52,9 +361,140 @@ impl ModelData {
             transform_ubos,
             skin_ubos,
             skin_matrices,
+            previous_transform_ubos,
+            previous_skin_ubos,
+            previous_instance_buffers,
+            previous_transforms: None,
+            previous_skin_matrices: None,
+            previous_instance_transforms: None,
             light_buffers,
+            light_clusters,
             render_data_buffers,
+            instance_buffers,
+            instance_pick_ids,
+            texture_transforms,
+            instancing,
+            skinned_vertices,
+            gpu_skinning_enabled: true,
+            culled_nodes: vec![false; mesh_node_count],
+            occluded_nodes: vec![false; mesh_node_count],
+            texture_count,
+        }
+    }
+
+    //用相机视锥体剔除网格节点，同时统计上次遮挡查询挡住的节点，隐藏的节点不计入
+    //蒙皮节点的包围盒来自绑定姿势，动画后不可靠；实例化的节点整批绘制且实例缓冲
+    //与阴影pass共用，这两类节点总是绘制
+    pub fn update_culling(&mut self, frustum: &Frustum) -> CullingStats {
+        let model = self.model.upgrade().expect("模型已被释放！");
+        let model = model.borrow();
+
+        let mut stats = CullingStats::default();
+        for (index, node) in model.nodes().mesh_nodes().enumerate() {
+            let outside = self.is_node_cullable(index, node.skin_index()) && {
+                let mesh = model.mesh(node.mesh_index().unwrap());
+                let aabb = mesh.aabb().transform(node.transform());
+                !frustum.is_aabb_visible(aabb.min().into(), aabb.max().into())
+            };
+            self.culled_nodes[index] = outside;
+
+            if !node.is_visible() {
+                continue;
+            }
+            let mesh = model.mesh(node.mesh_index().unwrap());
+            let triangles = mesh.triangle_count();
+            stats.vertices += mesh.vertex_count();
+            stats.total_triangles += triangles;
+            if !self.instancing.is_node_instanced(index) {
+                stats.total_draw_calls += mesh.primitive_count();
+            }
+            if outside {
+                stats.frustum_culled += 1;
+            } else if self.occluded_nodes[index] {
+                stats.occluded += 1;
+            } else {
+                stats.drawn += 1;
+                stats.triangles += triangles;
+                if !self.instancing.is_node_instanced(index) {
+                    stats.draw_calls += mesh.primitive_count();
+                }
+            }
         }
+        for batch in self.instancing.batches.iter() {
+            let primitive_count = model.mesh(batch.mesh_index).primitive_count();
+            stats.draw_calls += primitive_count;
+            stats.total_draw_calls += primitive_count;
+        }
+        stats
+    }
+
+    pub fn texture_count(&self) -> usize {
+        self.texture_count
+    }
+
+    pub fn mesh_node_count(&self) -> usize {
+        self.culled_nodes.len()
+    }
+
+    fn is_node_cullable(&self, mesh_node_index: usize, skin_index: Option<usize>) -> bool {
+        skin_index.is_none() && !self.instancing.is_node_instanced(mesh_node_index)
+    }
+
+    fn is_node_culled(&self, mesh_node_index: usize) -> bool {
+        self.culled_nodes[mesh_node_index] || self.occluded_nodes[mesh_node_index]
+    }
+
+    //需要做遮挡查询的节点：(网格节点序号, 世界变换, 局部包围盒)
+    pub fn occlusion_query_nodes(&self) -> Vec<(usize, Matrix4<f32>, Aabb<f32>)> {
+        let model = self.model.upgrade().expect("模型已被释放！");
+        let model = model.borrow();
+
+        model
+            .nodes()
+            .mesh_nodes()
+            .enumerate()
+            .filter(|(index, node)| {
+                node.is_visible()
+                    && !self.culled_nodes[*index]
+                    && self.is_node_cullable(*index, node.skin_index())
+            })
+            .map(|(index, node)| {
+                let mesh = model.mesh(node.mesh_index().unwrap());
+                (index, node.transform(), mesh.aabb())
+            })
+            .collect()
+    }
+
+    pub fn set_node_occluded(&mut self, mesh_node_index: usize, occluded: bool) {
+        if let Some(flag) = self.occluded_nodes.get_mut(mesh_node_index) {
+            *flag = occluded;
+        }
+    }
+
+    pub fn clear_occlusion(&mut self) {
+        self.occluded_nodes.fill(false);
+    }
+
+    pub fn set_gpu_skinning_enabled(&mut self, enabled: bool) {
+        self.gpu_skinning_enabled = enabled;
+    }
+
+    fn is_gpu_skinning_active(&self) -> bool {
+        self.gpu_skinning_enabled && self.skinned_vertices.is_some()
+    }
+
+    //计算着色器蒙皮开启时，返回网格节点在当前帧的蒙皮顶点缓冲和各primitive的偏移
+    fn skinned_vertices(
+        &self,
+        frame_index: usize,
+        mesh_node_index: usize,
+    ) -> Option<(vk::Buffer, &[vk::DeviceSize])> {
+        if !self.gpu_skinning_enabled {
+            return None;
+        }
+        let skinned_vertices = self.skinned_vertices.as_ref()?;
+        let offsets = skinned_vertices.offsets[mesh_node_index].as_ref()?;
+        Some((skinned_vertices.buffers[frame_index].buffer, offsets))
     }
 
     pub fn model(&mut self) -> std::rc::Rc<std::cell::RefCell<Model>> {
@@ -64,31 +504,88 @@ impl ModelData {
     pub fn update_buffers(
         &mut self,
         frame_index: usize,
-        light_space_matrix: Matrix4<f32>,
+        cascades: &ShadowCascades,
+        punctual_shadows: &PunctualShadows,
+        shadow_lights: &[(usize, u32)],
+        shadow_filter: &ShadowFilter,
         position: [f32; 4],
         direction: [f32; 4],
         color: [f32; 4],
         fog_params: [f32; 4],
         fog_color: [f32; 4],
         intensity: f32,
+        ambient_intensity: f32,
     ) {
         let model = &self.model.upgrade().expect("模型已被释放！");
         let model = model.borrow();
 
+        //各缓冲创建时就已持久映射，直接写入映射内存，不再每帧收集临时数组
         {
-            let mesh_nodes = model
-                .nodes()
+            let elem_size = self.context.get_ubo_alignment::<Matrix4<f32>>();
+            let transforms = model
                 .nodes()
-                .iter()
-                .filter(|n| n.mesh_index().is_some());
-
-            let transforms = mesh_nodes.map(|n| n.transform()).collect::<Vec<_>>();
+                .mesh_nodes()
+                .map(|n| n.transform())
+                .collect::<Vec<_>>();
+            //第一帧没有历史数据，上一帧等于当前帧，运动矢量为0
+            let previous_transforms = self
+                .previous_transforms
+                .replace(transforms.clone())
+                .unwrap_or_else(|| transforms.clone());
 
-            let elem_size = &self.context.get_ubo_alignment::<Matrix4<f32>>();
             let buffer = &mut self.transform_ubos[frame_index];
             unsafe {
                 let data_ptr = buffer.map_memory();
-                mem_copy_aligned(data_ptr, u64::from(*elem_size), &transforms);
+                mem_write_aligned(data_ptr, u64::from(elem_size), buffer.size, transforms);
+            }
+            let buffer = &mut self.previous_transform_ubos[frame_index];
+            unsafe {
+                let data_ptr = buffer.map_memory();
+                mem_write_aligned(
+                    data_ptr,
+                    u64::from(elem_size),
+                    buffer.size,
+                    previous_transforms,
+                );
+            }
+
+            if self.instancing.instance_count > 0 {
+                let mesh_nodes = model.nodes().mesh_nodes().collect::<Vec<_>>();
+                let instance_transforms = self
+                    .instancing
+                    .batches
+                    .iter()
+                    .flat_map(|b| b.node_indices.iter())
+                    .map(|i| {
+                        //隐藏的实例缩放为0，三角形退化后不会被光栅化
+                        if mesh_nodes[*i].is_visible() {
+                            mesh_nodes[*i].transform()
+                        } else {
+                            Matrix4::from_scale(0.0)
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                let previous_instance_transforms = self
+                    .previous_instance_transforms
+                    .replace(instance_transforms.clone())
+                    .unwrap_or_else(|| instance_transforms.clone());
+
+                let elem_size = size_of::<Matrix4<f32>>() as vk::DeviceSize;
+                let buffer = &mut self.instance_buffers[frame_index];
+                unsafe {
+                    let data_ptr = buffer.map_memory();
+                    mem_write_aligned(data_ptr, elem_size, buffer.size, instance_transforms);
+                }
+                let buffer = &mut self.previous_instance_buffers[frame_index];
+                unsafe {
+                    let data_ptr = buffer.map_memory();
+                    mem_write_aligned(
+                        data_ptr,
+                        elem_size,
+                        buffer.size,
+                        previous_instance_transforms,
+                    );
+                }
             }
         }
 
@@ -104,41 +601,60 @@ impl ModelData {
                 }
             }
 
-            let elem_size = &self.context.get_ubo_alignment::<JointsBuffer>();
+            let previous_skin_matrices = self
+                .previous_skin_matrices
+                .replace(skin_matrices.clone())
+                .unwrap_or_else(|| skin_matrices.clone());
+
+            let elem_size = self.context.get_ubo_alignment::<JointsBuffer>();
             let buffer = &mut self.skin_ubos[frame_index];
             unsafe {
                 let data_ptr = buffer.map_memory();
-                mem_copy_aligned(data_ptr, u64::from(*elem_size), skin_matrices);
+                mem_copy_aligned(data_ptr, u64::from(elem_size), skin_matrices);
+            }
+            let buffer = &mut self.previous_skin_ubos[frame_index];
+            unsafe {
+                let data_ptr = buffer.map_memory();
+                mem_copy_aligned(data_ptr, u64::from(elem_size), &previous_skin_matrices);
             }
         }
 
         {
             let uniforms = model
                 .nodes()
-                .nodes()
-                .iter()
-                .filter(|n| n.light_index().is_some())
+                .light_nodes()
                 .map(|n| (n.transform(), n.light_index().unwrap()))
-                .map(|(t, i)| (t, model.lights()[i]).into())
-                .collect::<Vec<LightUniform>>();
+                .map(|(t, i)| LightUniform::from((t, model.lights()[i])))
+                .enumerate()
+                .map(|(index, uniform)| {
+                    let shadow_layer = shadow_lights
+                        .iter()
+                        .find(|(light, _)| *light == index)
+                        .map(|(_, layer)| *layer);
+                    uniform.with_shadow_layer(shadow_layer)
+                });
 
-            if !uniforms.is_empty() {
-                let buffer = &mut self.light_buffers[frame_index];
+            let buffer = &mut self.light_buffers[frame_index];
+            let elem_size = size_of::<LightUniform>() as vk::DeviceSize;
+            unsafe {
                 let data_ptr = buffer.map_memory();
-                unsafe { mem_copy(data_ptr, &uniforms) };
+                mem_write_aligned(data_ptr, elem_size, buffer.size, uniforms);
             }
         }
 
         //mainlight ubo update
         {
             let uniforms = [RenderDataUniform::new(
-                light_space_matrix,
+                cascades,
+                punctual_shadows,
+                shadow_filter,
                 position,
                 direction,
                 color,
                 fog_params,
                 fog_color,
                 intensity,
+                ambient_intensity,
             )];
 
             let buffer = &mut self.render_data_buffers[frame_index];
@@ -147,3 +663,26 @@ impl ModelData {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vulkan::ash::vk::Handle;
+
+    //镜像节点的外表面在屏幕上是顺时针的，要取顺时针为正面的管线才不会被背面剔除
+    #[test]
+    fn mirrored_nodes_use_clockwise_front_face() {
+        let pipeline =
+            |front_face: vk::FrontFace| vk::Pipeline::from_raw(front_face.as_raw() as u64 + 1);
+        let pipelines = WindingPipelines::new(pipeline);
+        assert_eq!(
+            pipelines.get(false),
+            pipeline(vk::FrontFace::COUNTER_CLOCKWISE)
+        );
+        assert_eq!(pipelines.get(true), pipeline(vk::FrontFace::CLOCKWISE));
+
+        assert!(matches_winding(None, true));
+        assert!(matches_winding(Some(true), true));
+        assert!(!matches_winding(Some(false), true));
+    }
+}
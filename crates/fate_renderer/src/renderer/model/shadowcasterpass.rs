@@ -1,16 +1,17 @@
-use super::{JointsBuffer, ModelData};
-use crate::renderer::attachments::GBUFFER_NORMALS_FORMAT;
+use super::uniform::{texture_transform_uniform, TextureTransformUniform};
+use super::{matches_winding, JointsBuffer, ModelData, WindingPipelines};
+use crate::renderer::attachments::SHADOW_CASTER_COLOR_FORMAT;
 use crate::renderer::{create_renderer_pipeline, RendererPipelineParameters};
+use gltf_loader::mesh::{Mesh, Primitive};
+use gltf_loader::model::Model;
 use rendering::cgmath::Matrix4;
 use rendering::material::Material;
-use gltf_loader::mesh::Primitive;
-use gltf_loader::model::Model;
-use rendering::texture::Texture;
+use rendering::texture::{find_texture, Texture, TextureUsage};
 use rendering::util::any_as_u8_slice;
 use rendering::vertex::ModelVertex;
-use std::{mem::size_of, sync::Arc};
+use std::{mem::size_of, ops::Range, sync::Arc};
 use vulkan::ash::{vk, Device};
-use vulkan::{Buffer, Context, Texture as VulkanTexture};
+use vulkan::{debug_name, Buffer, Context, MultisampleParameters, Texture as VulkanTexture};
 
 const DYNAMIC_DATA_SET_INDEX: u32 = 0;
 const PER_PRIMITIVE_DATA_SET_INDEX: u32 = 1;
@@ -19,13 +20,18 @@ const CAMERA_UBO_BINDING: u32 = 0;
 const TRANSFORMS_UBO_BINDING: u32 = 1;
 const SKINS_UBO_BINDING: u32 = 2;
 const COLOR_SAMPLER_BINDING: u32 = 3;
+const INSTANCES_SSBO_BINDING: u32 = 4;
+
+//材质参数和级联序号之后
+const INSTANCED_PUSH_CONSTANT_OFFSET: u32 =
+    (size_of::<MaterialUniform>() + size_of::<u32>()) as u32;
 
 pub struct ShadowCasterPass {
     context: Arc<Context>,
     dummy_texture: VulkanTexture,
     descriptors: Descriptors,
     pipeline_layout: vk::PipelineLayout,
-    culled_pipeline: vk::Pipeline,
+    culled_pipelines: WindingPipelines,
     unculled_pipeline: vk::Pipeline,
 }
 
@@ -43,7 +49,8 @@ impl ShadowCasterPass {
             &[std::u8::MAX; 4],
             true,
             std::ffi::CString::new("Default Texture").unwrap(),
-        );
+        )
+        .expect("创建默认纹理失败！");
 
         let model_rc = model_data.model.upgrade().expect("模型已被释放！");
 
@@ -53,21 +60,31 @@ impl ShadowCasterPass {
                 light_buffers,
                 model_transform_buffers: &model_data.transform_ubos,
                 model_skin_buffers: &model_data.skin_ubos,
+                instance_buffers: &model_data.instance_buffers,
                 model: &model_rc.borrow(),
                 dummy_texture: &dummy_texture,
             },
         );
 
         let pipeline_layout = create_pipeline_layout(context.device(), &descriptors);
-        let culled_pipeline = create_pipeline(&context, depth_format, pipeline_layout, true);
-        let unculled_pipeline = create_pipeline(&context, depth_format, pipeline_layout, false);
+        let culled_pipelines = WindingPipelines::new(|front_face| {
+            create_pipeline(&context, depth_format, pipeline_layout, true, front_face)
+        });
+        //不剔除时绕序不影响阴影深度
+        let unculled_pipeline = create_pipeline(
+            &context,
+            depth_format,
+            pipeline_layout,
+            false,
+            vk::FrontFace::COUNTER_CLOCKWISE,
+        );
 
         ShadowCasterPass {
             context,
             dummy_texture,
             descriptors,
             pipeline_layout,
-            culled_pipeline,
+            culled_pipelines,
             unculled_pipeline,
         }
     }
@@ -83,6 +100,7 @@ impl ShadowCasterPass {
                 light_buffers,
                 model_transform_buffers: &model_data.transform_ubos,
                 model_skin_buffers: &model_data.skin_ubos,
+                instance_buffers: &model_data.instance_buffers,
                 model: &model_rc.borrow(),
                 dummy_texture: &self.dummy_texture,
             },
@@ -94,28 +112,37 @@ impl ShadowCasterPass {
         command_buffer: vk::CommandBuffer,
         frame_index: usize,
         model_data: &ModelData,
+        cascade_index: u32,
     ) {
         let device = self.context.device();
-        let model = model_data.model.upgrade().expect("模型已被释放！");
-        let model = model.borrow();
 
         unsafe {
-            device.cmd_bind_pipeline(
+            device.cmd_push_constants(
                 command_buffer,
-                vk::PipelineBindPoint::GRAPHICS,
-                self.culled_pipeline,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::VERTEX,
+                size_of::<MaterialUniform>() as _,
+                any_as_u8_slice(&cascade_index),
             )
         };
 
-        register_model_draw_commands(
-            &self.context,
-            self.pipeline_layout,
-            command_buffer,
-            &model,
-            &self.descriptors.dynamic_data_sets[frame_index..=frame_index],
-            &self.descriptors.per_primitive_sets,
-            |p| !p.material().is_transparent() && !p.material().is_double_sided(),
-        );
+        for mirrored in [false, true] {
+            unsafe {
+                device.cmd_bind_pipeline(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    self.culled_pipelines.get(mirrored),
+                )
+            };
+
+            self.register_model_draw_commands(
+                command_buffer,
+                frame_index,
+                model_data,
+                Some(mirrored),
+                |p| !p.material().is_transparent() && !p.material().is_double_sided(),
+            );
+        }
 
         unsafe {
             device.cmd_bind_pipeline(
@@ -125,15 +152,9 @@ impl ShadowCasterPass {
             )
         };
 
-        register_model_draw_commands(
-            &self.context,
-            self.pipeline_layout,
-            command_buffer,
-            &model,
-            &self.descriptors.dynamic_data_sets[frame_index..=frame_index],
-            &self.descriptors.per_primitive_sets,
-            |p| !p.material().is_transparent() && p.material().is_double_sided(),
-        );
+        self.register_model_draw_commands(command_buffer, frame_index, model_data, None, |p| {
+            !p.material().is_transparent() && p.material().is_double_sided()
+        });
     }
 }
 
@@ -141,9 +162,9 @@ impl Drop for ShadowCasterPass {
     fn drop(&mut self) {
         self.context.graphics_queue_wait_idle();
         let device = self.context.device();
+        self.culled_pipelines.destroy(device);
         unsafe {
             device.destroy_pipeline(self.unculled_pipeline, None);
-            device.destroy_pipeline(self.culled_pipeline, None);
             device.destroy_pipeline_layout(self.pipeline_layout, None);
         }
     }
@@ -154,6 +175,7 @@ struct DescriptorsResources<'a> {
     light_buffers: &'a [Buffer],
     model_transform_buffers: &'a [Buffer],
     model_skin_buffers: &'a [Buffer],
+    instance_buffers: &'a [Buffer],
     model: &'a Model,
     dummy_texture: &'a VulkanTexture,
 }
@@ -217,6 +239,11 @@ fn create_descriptor_pool(
             ty: vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
             descriptor_count: descriptor_count * 2,
         },
+        // Instances
+        vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::STORAGE_BUFFER,
+            descriptor_count,
+        },
         // Color sampler
         vk::DescriptorPoolSize {
             ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
@@ -251,6 +278,12 @@ fn create_dynamic_data_descriptor_set_layout(device: &Device) -> vk::DescriptorS
             .descriptor_count(1)
             .stage_flags(vk::ShaderStageFlags::VERTEX)
             .build(),
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(INSTANCES_SSBO_BINDING)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::VERTEX)
+            .build(),
     ];
 
     let layout_info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
@@ -281,11 +314,20 @@ fn create_dynamic_data_descriptor_sets(
             .allocate_descriptor_sets(&allocate_info)
             .unwrap()
     };
+    for (i, set) in sets.iter().enumerate() {
+        debug_name!(
+            context,
+            *set,
+            "ShadowCaster.DynamicData.DescriptorSet[{}]",
+            i
+        );
+    }
 
     sets.iter().enumerate().for_each(|(i, set)| {
         let camera_ubo = &resources.light_buffers[i];
         let model_transform_ubo = &resources.model_transform_buffers[i];
         let model_skin_ubo = &resources.model_skin_buffers[i];
+        let instance_buffer = &resources.instance_buffers[i];
 
         let camera_buffer_info = [vk::DescriptorBufferInfo::builder()
             .buffer(camera_ubo.buffer)
@@ -305,6 +347,12 @@ fn create_dynamic_data_descriptor_sets(
             .range(size_of::<JointsBuffer>() as _)
             .build()];
 
+        let instance_buffer_info = [vk::DescriptorBufferInfo::builder()
+            .buffer(instance_buffer.buffer)
+            .offset(0)
+            .range(vk::WHOLE_SIZE)
+            .build()];
+
         let descriptor_writes = [
             vk::WriteDescriptorSet::builder()
                 .dst_set(*set)
@@ -324,6 +372,12 @@ fn create_dynamic_data_descriptor_sets(
                 .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
                 .buffer_info(&model_skin_buffer_info)
                 .build(),
+            vk::WriteDescriptorSet::builder()
+                .dst_set(*set)
+                .dst_binding(INSTANCES_SSBO_BINDING)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .buffer_info(&instance_buffer_info)
+                .build(),
         ];
 
         unsafe {
@@ -372,9 +426,17 @@ fn create_per_primitive_descriptor_sets(
             .allocate_descriptor_sets(&allocate_info)
             .unwrap()
     };
+    for (i, set) in sets.iter().enumerate() {
+        debug_name!(
+            context,
+            *set,
+            "ShadowCaster.PerPrimitive.DescriptorSet[{}]",
+            i
+        );
+    }
 
     let model = resources.model;
-    let textures = resources.model.textures();
+    let textures = resources.model.textures(TextureUsage::Color);
     let mut primitive_index = 0;
     for mesh in model.meshes() {
         for primitive in mesh.primitives() {
@@ -408,11 +470,10 @@ fn create_per_primitive_descriptor_sets(
 
 fn create_descriptor_image_info(
     index: Option<usize>,
-    textures: &[Texture],
+    textures: &[Option<Texture>],
     dummy_texture: &VulkanTexture,
 ) -> [vk::DescriptorImageInfo; 1] {
-    let (view, sampler) = index
-        .map(|i| &textures[i])
+    let (view, sampler) = find_texture(textures, index)
         .map_or((dummy_texture.view, dummy_texture.sampler.unwrap()), |t| {
             (t.get_view(), t.get_sampler())
         });
@@ -429,11 +490,19 @@ fn create_pipeline_layout(device: &Device, descriptors: &Descriptors) -> vk::Pip
         descriptors.dynamic_data_layout,
         descriptors.per_primitive_layout,
     ];
-    let constant_ranges = [vk::PushConstantRange {
-        stage_flags: vk::ShaderStageFlags::FRAGMENT,
-        offset: 0,
-        size: size_of::<MaterialUniform>() as _,
-    }];
+    let constant_ranges = [
+        vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            offset: 0,
+            size: size_of::<MaterialUniform>() as _,
+        },
+        //当前绘制的级联和是否为实例化绘制
+        vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::VERTEX,
+            offset: size_of::<MaterialUniform>() as _,
+            size: (size_of::<u32>() * 2) as _,
+        },
+    ];
     let layout_info = vk::PipelineLayoutCreateInfo::builder()
         .set_layouts(&layouts)
         .push_constant_ranges(&constant_ranges);
@@ -446,6 +515,7 @@ fn create_pipeline(
     depth_format: vk::Format,
     layout: vk::PipelineLayout,
     enable_face_culling: bool,
+    front_face: vk::FrontFace,
 ) -> vk::Pipeline {
     let depth_stencil_info = vk::PipelineDepthStencilStateCreateInfo::builder()
         .depth_test_enable(true)
@@ -477,81 +547,182 @@ fn create_pipeline(
     create_renderer_pipeline::<ModelVertex>(
         context,
         RendererPipelineParameters {
+            name: if enable_face_culling {
+                "ShadowCaster.Pipeline"
+            } else {
+                "ShadowCaster.Pipeline.DoubleSided"
+            },
             vertex_shader_name: "shadowcaster",
             fragment_shader_name: "shadowcaster",
             vertex_shader_specialization: None,
             fragment_shader_specialization: None,
-            msaa_samples: vk::SampleCountFlags::TYPE_1,
-            color_attachment_formats: &[GBUFFER_NORMALS_FORMAT],
+            multisampling: MultisampleParameters::default(),
+            color_attachment_formats: &[SHADOW_CASTER_COLOR_FORMAT],
             depth_attachment_format: Some(depth_format),
             layout,
             depth_stencil_info: &depth_stencil_info,
             color_blend_attachments: &color_blend_attachments,
             enable_face_culling,
+            front_face,
+            polygon_mode: vk::PolygonMode::FILL,
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
             parent: None,
         },
     )
 }
 
-fn register_model_draw_commands<F>(
-    context: &Context,
-    pipeline_layout: vk::PipelineLayout,
-    command_buffer: vk::CommandBuffer,
-    model: &Model,
-    dynamic_descriptors: &[vk::DescriptorSet],
-    per_primitive_descriptors: &[vk::DescriptorSet],
-    primitive_filter: F,
-) where
-    F: FnMut(&&Primitive) -> bool + Copy,
-{
-    let device = context.device();
-    let model_transform_ubo_offset = context.get_ubo_alignment::<Matrix4<f32>>();
-    let model_skin_ubo_offset = context.get_ubo_alignment::<JointsBuffer>();
-
-    for (index, node) in model
-        .nodes()
-        .nodes()
-        .iter()
-        .filter(|n| n.mesh_index().is_some())
-        .enumerate()
+impl ShadowCasterPass {
+    fn register_model_draw_commands<F>(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        frame_index: usize,
+        model_data: &ModelData,
+        mirrored: Option<bool>,
+        primitive_filter: F,
+    ) where
+        F: FnMut(&&Primitive) -> bool + Copy,
     {
-        let mesh = model.mesh(node.mesh_index().unwrap());
-        let skin_index = node.skin_index().unwrap_or(0);
+        let device = self.context.device();
+        let model_transform_ubo_offset = self.context.get_ubo_alignment::<Matrix4<f32>>();
+        let model_skin_ubo_offset = self.context.get_ubo_alignment::<JointsBuffer>();
+        let dynamic_descriptors = &self.descriptors.dynamic_data_sets[frame_index..=frame_index];
+
+        let model = model_data.model.upgrade().expect("模型已被释放！");
+        let model = model.borrow();
+
+        self.cmd_push_instanced_flag(command_buffer, false);
+
+        for (index, node) in model.nodes().mesh_nodes().enumerate() {
+            //重复的网格在下面一次性实例化绘制，隐藏的节点直接跳过
+            if model_data.instancing.is_node_instanced(index)
+                || !node.is_visible()
+                || !matches_winding(mirrored, node.is_mirrored())
+            {
+                continue;
+            }
+
+            let mesh = model.mesh(node.mesh_index().unwrap());
+            let skin_index = node.skin_index().unwrap_or(0);
+
+            unsafe {
+                device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    self.pipeline_layout,
+                    DYNAMIC_DATA_SET_INDEX,
+                    dynamic_descriptors,
+                    &[
+                        model_transform_ubo_offset * index as u32,
+                        model_skin_ubo_offset * skin_index as u32,
+                    ],
+                )
+            };
+
+            self.register_primitive_draw_commands(
+                command_buffer,
+                mesh,
+                model_data.skinned_vertices(frame_index, index),
+                primitive_filter,
+                0..1,
+            );
+        }
+
+        if model_data.instancing.batches.is_empty() {
+            return;
+        }
 
+        //实例化绘制的变换从存储缓冲中读取，动态偏移不会被用到
         unsafe {
             device.cmd_bind_descriptor_sets(
                 command_buffer,
                 vk::PipelineBindPoint::GRAPHICS,
-                pipeline_layout,
+                self.pipeline_layout,
                 DYNAMIC_DATA_SET_INDEX,
                 dynamic_descriptors,
-                &[
-                    model_transform_ubo_offset * index as u32,
-                    model_skin_ubo_offset * skin_index as u32,
-                ],
+                &[0, 0],
             )
         };
 
-        for primitive in mesh.primitives().iter().filter(primitive_filter) {
+        self.cmd_push_instanced_flag(command_buffer, true);
+
+        for batch in model_data
+            .instancing
+            .batches
+            .iter()
+            .filter(|b| matches_winding(mirrored, b.mirrored))
+        {
+            let first_instance = batch.first_instance;
+            let instance_count = batch.node_indices.len() as u32;
+
+            self.register_primitive_draw_commands(
+                command_buffer,
+                model.mesh(batch.mesh_index),
+                None,
+                primitive_filter,
+                first_instance..first_instance + instance_count,
+            );
+        }
+    }
+
+    fn cmd_push_instanced_flag(&self, command_buffer: vk::CommandBuffer, instanced: bool) {
+        let instanced = instanced as u32;
+        unsafe {
+            self.context.device().cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::VERTEX,
+                INSTANCED_PUSH_CONSTANT_OFFSET,
+                any_as_u8_slice(&instanced),
+            )
+        };
+    }
+
+    //skinned_vertices不为空时从计算着色器蒙皮的结果中读取顶点
+    fn register_primitive_draw_commands<F>(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        mesh: &Mesh,
+        skinned_vertices: Option<(vk::Buffer, &[vk::DeviceSize])>,
+        mut primitive_filter: F,
+        instances: Range<u32>,
+    ) where
+        F: FnMut(&&Primitive) -> bool + Copy,
+    {
+        let device = self.context.device();
+
+        for (position, primitive) in mesh
+            .primitives()
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| primitive_filter(p))
+        {
             let primitive_index = primitive.index();
 
             unsafe {
                 device.cmd_bind_descriptor_sets(
                     command_buffer,
                     vk::PipelineBindPoint::GRAPHICS,
-                    pipeline_layout,
+                    self.pipeline_layout,
                     PER_PRIMITIVE_DATA_SET_INDEX,
-                    &per_primitive_descriptors[primitive_index..=primitive_index],
+                    &self.descriptors.per_primitive_sets[primitive_index..=primitive_index],
                     &[],
                 )
             };
 
+            let (vertex_buffer, vertex_offset) = match skinned_vertices {
+                Some((buffer, offsets)) => (buffer, offsets[position]),
+                None => (
+                    primitive.vertices().buffer().buffer,
+                    primitive.vertices().offset(),
+                ),
+            };
+
             unsafe {
                 device.cmd_bind_vertex_buffers(
                     command_buffer,
                     0,
-                    &[primitive.vertices().buffer().buffer],
-                    &[primitive.vertices().offset()],
+                    &[vertex_buffer],
+                    &[vertex_offset],
                 );
             }
 
@@ -571,7 +742,7 @@ fn register_model_draw_commands<F>(
                 let material_contants = any_as_u8_slice(&material);
                 device.cmd_push_constants(
                     command_buffer,
-                    pipeline_layout,
+                    self.pipeline_layout,
                     vk::ShaderStageFlags::FRAGMENT,
                     0,
                     material_contants,
@@ -584,10 +755,10 @@ fn register_model_draw_commands<F>(
                         device.cmd_draw_indexed(
                             command_buffer,
                             index_buffer.element_count(),
-                            1,
-                            0,
+                            instances.len() as u32,
                             0,
                             0,
+                            instances.start,
                         )
                     };
                 }
@@ -596,9 +767,9 @@ fn register_model_draw_commands<F>(
                         device.cmd_draw(
                             command_buffer,
                             primitive.vertices().element_count(),
-                            1,
-                            0,
+                            instances.len() as u32,
                             0,
+                            instances.start,
                         )
                     };
                 }
@@ -614,6 +785,7 @@ pub struct MaterialUniform {
     color_texture_channel: u32,
     alpha_mode: u32,
     alpha_cutoff: f32,
+    color_texture_transform: TextureTransformUniform,
 }
 
 impl MaterialUniform {
@@ -628,12 +800,14 @@ impl From<Material> for MaterialUniform {
             .map_or(Self::NO_TEXTURE_ID, |info| info.get_channel());
         let alpha_mode = material.get_alpha_mode();
         let alpha_cutoff = material.get_alpha_cutoff();
+        let color_texture_transform = texture_transform_uniform(material.get_color_texture());
 
         MaterialUniform {
             alpha,
             color_texture_channel,
             alpha_mode,
             alpha_cutoff,
+            color_texture_transform,
         }
     }
 }
@@ -0,0 +1,314 @@
+use super::{JointsBuffer, ModelData};
+use rendering::util::any_as_u8_slice;
+use rendering::vertex::ModelVertex;
+use std::{mem::size_of, sync::Arc};
+use vulkan::ash::{vk, Device};
+use vulkan::{create_compute_pipeline, debug_name, Buffer, Context, ShaderParameters};
+
+const INPUT_VERTICES_BINDING: u32 = 0;
+const OUTPUT_VERTICES_BINDING: u32 = 1;
+const SKINS_UBO_BINDING: u32 = 2;
+
+const WORKGROUP_SIZE: u32 = 64;
+
+//每帧用计算着色器把蒙皮网格的顶点变换一次，gbuffer、阴影和光照pass直接读取结果
+pub struct SkinningPass {
+    context: Arc<Context>,
+    descriptors: Descriptors,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl SkinningPass {
+    //模型中没有蒙皮网格时返回None
+    pub fn create(context: Arc<Context>, model_data: &ModelData) -> Option<Self> {
+        let skinned_vertices = model_data.skinned_vertices.as_ref()?;
+        let model_rc = model_data.model.upgrade().expect("模型已被释放！");
+        let model = model_rc.borrow();
+
+        //同一个模型的所有primitive共用一个顶点缓冲
+        let input_vertices = model
+            .meshes()
+            .iter()
+            .flat_map(|m| m.primitives())
+            .next()?
+            .vertices()
+            .buffer();
+
+        let descriptors = create_descriptors(
+            &context,
+            input_vertices,
+            &skinned_vertices.buffers,
+            &model_data.skin_ubos,
+        );
+        let pipeline_layout = create_pipeline_layout(context.device(), &descriptors);
+        let pipeline = create_compute_pipeline(
+            &context,
+            "Skinning.Pipeline",
+            ShaderParameters::new("skinning"),
+            pipeline_layout,
+        )
+        .expect("创建skinning pipeline失败！");
+
+        Some(Self {
+            context,
+            descriptors,
+            pipeline_layout,
+            pipeline,
+        })
+    }
+
+    pub fn cmd_dispatch(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        frame_index: usize,
+        model_data: &ModelData,
+    ) {
+        if !model_data.is_gpu_skinning_active() {
+            return;
+        }
+
+        let device = self.context.device();
+        let model_skin_ubo_offset = self.context.get_ubo_alignment::<JointsBuffer>();
+        let model = model_data.model.upgrade().expect("模型已被释放！");
+        let model = model.borrow();
+
+        unsafe {
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline,
+            )
+        };
+
+        for (index, node) in model.nodes().mesh_nodes().enumerate() {
+            if !node.is_visible() {
+                continue;
+            }
+            let offsets = match model_data.skinned_vertices(frame_index, index) {
+                Some((_, offsets)) => offsets,
+                None => continue,
+            };
+            let mesh = model.mesh(node.mesh_index().unwrap());
+            let skin_index = node.skin_index().unwrap_or(0);
+
+            unsafe {
+                device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::COMPUTE,
+                    self.pipeline_layout,
+                    0,
+                    &self.descriptors.sets[frame_index..=frame_index],
+                    &[model_skin_ubo_offset * skin_index as u32],
+                )
+            };
+
+            for (primitive, offset) in mesh.primitives().iter().zip(offsets.iter()) {
+                let vertex_size = size_of::<ModelVertex>() as vk::DeviceSize;
+                let constants = SkinningConstants {
+                    input_offset: (primitive.vertices().offset() / vertex_size) as u32,
+                    output_offset: (offset / vertex_size) as u32,
+                    vertex_count: primitive.vertices().element_count(),
+                };
+
+                unsafe {
+                    device.cmd_push_constants(
+                        command_buffer,
+                        self.pipeline_layout,
+                        vk::ShaderStageFlags::COMPUTE,
+                        0,
+                        any_as_u8_slice(&constants),
+                    );
+                    device.cmd_dispatch(
+                        command_buffer,
+                        (constants.vertex_count + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE,
+                        1,
+                        1,
+                    );
+                }
+            }
+        }
+
+        //蒙皮结果写完之后才能作为顶点输入
+        let output_vertices = &model_data.skinned_vertices.as_ref().unwrap().buffers[frame_index];
+        let barrier = vk::BufferMemoryBarrier2::builder()
+            .src_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+            .src_access_mask(vk::AccessFlags2::SHADER_STORAGE_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags2::VERTEX_ATTRIBUTE_INPUT)
+            .dst_access_mask(vk::AccessFlags2::VERTEX_ATTRIBUTE_READ)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .buffer(output_vertices.buffer)
+            .offset(0)
+            .size(vk::WHOLE_SIZE);
+        let dependency_info =
+            vk::DependencyInfo::builder().buffer_memory_barriers(std::slice::from_ref(&barrier));
+
+        unsafe {
+            self.context
+                .synchronization2()
+                .cmd_pipeline_barrier2(command_buffer, &dependency_info)
+        };
+    }
+}
+
+impl Drop for SkinningPass {
+    fn drop(&mut self) {
+        self.context.graphics_queue_wait_idle();
+        let device = self.context.device();
+        unsafe {
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+#[allow(dead_code)]
+#[repr(C)]
+struct SkinningConstants {
+    input_offset: u32,
+    output_offset: u32,
+    vertex_count: u32,
+}
+
+struct Descriptors {
+    context: Arc<Context>,
+    pool: vk::DescriptorPool,
+    layout: vk::DescriptorSetLayout,
+    sets: Vec<vk::DescriptorSet>,
+}
+
+impl Drop for Descriptors {
+    fn drop(&mut self) {
+        let device = self.context.device();
+        unsafe {
+            device.destroy_descriptor_pool(self.pool, None);
+            device.destroy_descriptor_set_layout(self.layout, None);
+        }
+    }
+}
+
+fn create_descriptors(
+    context: &Arc<Context>,
+    input_vertices: &Buffer,
+    output_vertices: &[Buffer],
+    skin_buffers: &[Buffer],
+) -> Descriptors {
+    let device = context.device();
+    let descriptor_count = output_vertices.len() as u32;
+
+    let pool_sizes = [
+        vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::STORAGE_BUFFER,
+            descriptor_count: descriptor_count * 2,
+        },
+        vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+            descriptor_count,
+        },
+    ];
+    let pool_info = vk::DescriptorPoolCreateInfo::builder()
+        .pool_sizes(&pool_sizes)
+        .max_sets(descriptor_count);
+    let pool = unsafe { device.create_descriptor_pool(&pool_info, None).unwrap() };
+
+    let bindings = [
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(INPUT_VERTICES_BINDING)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .build(),
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(OUTPUT_VERTICES_BINDING)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .build(),
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(SKINS_UBO_BINDING)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .build(),
+    ];
+    let layout_info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+    let layout = unsafe {
+        device
+            .create_descriptor_set_layout(&layout_info, None)
+            .unwrap()
+    };
+
+    let layouts = (0..descriptor_count).map(|_| layout).collect::<Vec<_>>();
+    let allocate_info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(pool)
+        .set_layouts(&layouts);
+    let sets = unsafe { device.allocate_descriptor_sets(&allocate_info).unwrap() };
+
+    sets.iter().enumerate().for_each(|(i, set)| {
+        debug_name!(context, *set, "Skinning.DescriptorSet[{}]", i);
+
+        let input_buffer_info = [vk::DescriptorBufferInfo::builder()
+            .buffer(input_vertices.buffer)
+            .offset(0)
+            .range(vk::WHOLE_SIZE)
+            .build()];
+
+        let output_buffer_info = [vk::DescriptorBufferInfo::builder()
+            .buffer(output_vertices[i].buffer)
+            .offset(0)
+            .range(vk::WHOLE_SIZE)
+            .build()];
+
+        let skin_buffer_info = [vk::DescriptorBufferInfo::builder()
+            .buffer(skin_buffers[i].buffer)
+            .offset(0)
+            .range(size_of::<JointsBuffer>() as _)
+            .build()];
+
+        let descriptor_writes = [
+            vk::WriteDescriptorSet::builder()
+                .dst_set(*set)
+                .dst_binding(INPUT_VERTICES_BINDING)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .buffer_info(&input_buffer_info)
+                .build(),
+            vk::WriteDescriptorSet::builder()
+                .dst_set(*set)
+                .dst_binding(OUTPUT_VERTICES_BINDING)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .buffer_info(&output_buffer_info)
+                .build(),
+            vk::WriteDescriptorSet::builder()
+                .dst_set(*set)
+                .dst_binding(SKINS_UBO_BINDING)
+                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
+                .buffer_info(&skin_buffer_info)
+                .build(),
+        ];
+
+        unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) }
+    });
+
+    Descriptors {
+        context: Arc::clone(context),
+        pool,
+        layout,
+        sets,
+    }
+}
+
+fn create_pipeline_layout(device: &Device, descriptors: &Descriptors) -> vk::PipelineLayout {
+    let layouts = [descriptors.layout];
+    let constant_ranges = [vk::PushConstantRange {
+        stage_flags: vk::ShaderStageFlags::COMPUTE,
+        offset: 0,
+        size: size_of::<SkinningConstants>() as _,
+    }];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(&layouts)
+        .push_constant_ranges(&constant_ranges);
+
+    unsafe { device.create_pipeline_layout(&layout_info, None).unwrap() }
+}
@@ -1,21 +1,27 @@
 use super::JointsBuffer;
+use crate::renderer::attachments::{MAX_PUNCTUAL_SHADOW_LAYERS, MAX_SHADOW_CASCADES};
+use crate::renderer::shadow::{PunctualShadows, ShadowCascades, ShadowFilter};
 use gltf_loader::model::Model;
 use rendering::cgmath::{InnerSpace, Matrix4, SquareMatrix, Vector4};
 use rendering::{
     light::{Light, LightType},
-    material::{Material, PBRWorkflow},
+    material::{Material, PBRWorkflow, TextureData},
     skin::MAX_JOINTS_PER_MESH,
+    util::any_as_u8_slice,
 };
 use std::{mem::size_of, sync::Arc};
-use vulkan::{ash::vk, Buffer, Context};
+use vulkan::{ash::vk, create_device_local_buffer_with_data, Buffer, Context};
 
 const DEFAULT_LIGHT_DIRECTION: [f32; 4] = [0.0, 0.0, -1.0, 0.0];
 const DIRECTIONAL_LIGHT_TYPE: u32 = 0;
 const POINT_LIGHT_TYPE: u32 = 1;
 const SPOT_LIGHT_TYPE: u32 = 2;
+const NO_SHADOW_LAYER: i32 = -1;
 const NO_TEXTURE_ID: u32 = std::u8::MAX as u32;
 const UNLIT_FLAG_LIT: u32 = 0;
 const UNLIT_FLAG_UNLIT: u32 = 1;
+const VERTEX_COLORS_FLAG: u32 = 2;
+const DERIVATIVE_TANGENTS_FLAG: u32 = 4;
 const METALLIC_ROUGHNESS_WORKFLOW: u32 = 0;
 const SPECULAR_GLOSSINESS_WORKFLOW: u32 = 1;
 
@@ -31,7 +37,16 @@ pub struct LightUniform {
     angle_scale: f32,
     angle_offset: f32,
     light_type: u32,
-    pad: [u32; 3],
+    //在点光源/聚光灯shadow map数组中的第一层，-1表示不投射阴影
+    shadow_layer: i32,
+    pad: [u32; 2],
+}
+
+impl LightUniform {
+    pub fn with_shadow_layer(mut self, layer: Option<u32>) -> Self {
+        self.shadow_layer = layer.map_or(NO_SHADOW_LAYER, |l| l as i32);
+        self
+    }
 }
 
 impl From<(Matrix4<f32>, Light)> for LightUniform {
@@ -81,7 +96,8 @@ impl From<(Matrix4<f32>, Light)> for LightUniform {
             angle_scale,
             angle_offset,
             light_type,
-            pad: [0, 0, 0],
+            shadow_layer: NO_SHADOW_LAYER,
+            pad: [0, 0],
         }
     }
 }
@@ -89,35 +105,56 @@ impl From<(Matrix4<f32>, Light)> for LightUniform {
 #[derive(Copy, Clone, Debug)]
 #[repr(C)]
 pub struct RenderDataUniform {
-    main_light_space_matrix: Matrix4<f32>,
+    cascade_matrices: [Matrix4<f32>; MAX_SHADOW_CASCADES as usize],
+    punctual_shadow_matrices: [Matrix4<f32>; MAX_PUNCTUAL_SHADOW_LAYERS as usize],
+    cascade_splits: [f32; MAX_SHADOW_CASCADES as usize],
+    cascade_texel_sizes: [f32; MAX_SHADOW_CASCADES as usize],
     main_light_position: [f32; 4],
     main_light_direction: [f32; 4],
     main_light_color: [f32; 4],
     fog_params: [f32; 4],
     fog_color: [f32; 4],
     main_light_intensity: f32,
-    pad: [f32; 3],
+    ambient_intensity: f32,
+    cascade_count: u32,
+    shadow_filter_mode: u32,
+    shadow_depth_bias: f32,
+    shadow_normal_bias: f32,
+    shadow_pcf_kernel_size: u32,
+    pad: f32,
 }
 
 impl RenderDataUniform {
     pub fn new(
-        main_light_space_matrix: Matrix4<f32>,
+        cascades: &ShadowCascades,
+        punctual_shadows: &PunctualShadows,
+        shadow_filter: &ShadowFilter,
         main_light_position: [f32; 4],
         main_light_direction: [f32; 4],
         main_light_color: [f32; 4],
         fog_params: [f32; 4],
         fog_color: [f32; 4],
         main_light_intensity: f32,
+        ambient_intensity: f32,
     ) -> Self {
         Self {
-            main_light_space_matrix,
+            cascade_matrices: cascades.matrices,
+            punctual_shadow_matrices: punctual_shadows.matrices,
+            cascade_splits: cascades.splits,
+            cascade_texel_sizes: cascades.texel_sizes,
             main_light_position,
             main_light_direction,
             main_light_color,
             fog_params,
             fog_color,
             main_light_intensity,
-            pad: [0.0, 0.0, 0.0],
+            ambient_intensity,
+            cascade_count: cascades.count,
+            shadow_filter_mode: shadow_filter.mode as u32,
+            shadow_depth_bias: shadow_filter.depth_bias,
+            shadow_normal_bias: shadow_filter.normal_bias,
+            shadow_pcf_kernel_size: shadow_filter.pcf_kernel_size,
+            pad: 0.0,
         }
     }
 }
@@ -139,10 +176,17 @@ pub struct MaterialUniform {
     color_material_emissive_normal_texture_channels: u32,
     // [0-7] Occlusion通道数
     // [8-15] Alpha mode
-    // [16-23] Unlit flag
+    // [16-23] Unlit flag | 顶点色flag | 导数切线flag
     // [24-31] Workflow
     occlusion_texture_channel_alpha_mode_unlit_flag_and_workflow: u32,
     alpha_cutoff: f32,
+    // [0-7] Occlusion读取的颜色通道
+    // [8-15] Roughness读取的颜色通道
+    // [16-23] Metallic读取的颜色通道
+    // [24-31] Height通道数，没有高度图或关闭视差时为NO_TEXTURE_ID
+    texture_swizzle: u32,
+    height_scale: f32,
+    normal_scale: f32,
 }
 
 impl From<Material> for MaterialUniform {
@@ -201,11 +245,17 @@ impl From<Material> for MaterialUniform {
             .get_occlusion_texture()
             .map_or(NO_TEXTURE_ID, |info| info.get_channel());
         let alpha_mode = material.get_alpha_mode();
-        let unlit_flag = if material.is_unlit() {
+        let mut unlit_flag = if material.is_unlit() {
             UNLIT_FLAG_UNLIT
         } else {
             UNLIT_FLAG_LIT
         };
+        if material.use_vertex_colors() {
+            unlit_flag |= VERTEX_COLORS_FLAG;
+        }
+        if material.use_derivative_tangents() {
+            unlit_flag |= DERIVATIVE_TANGENTS_FLAG;
+        }
         let workflow = if let PBRWorkflow::MetallicRoughness { .. } = workflow {
             METALLIC_ROUGHNESS_WORKFLOW
         } else {
@@ -216,6 +266,19 @@ impl From<Material> for MaterialUniform {
 
         let alpha_cutoff = material.get_alpha_cutoff();
 
+        let packing = material.get_texture_packing();
+        let height_texture_id = material
+            .get_height_texture()
+            .filter(|_| material.is_parallax_enabled())
+            .map_or(NO_TEXTURE_ID, |info| info.get_channel());
+        let texture_swizzle = (packing.occlusion_channel << 24)
+            | (packing.roughness_channel << 16)
+            | (packing.metallic_channel << 8)
+            | height_texture_id;
+
+        let height_scale = material.get_height_scale();
+        let normal_scale = material.get_normal_scale();
+
         MaterialUniform {
             color,
             emissive_and_roughness_glossiness,
@@ -223,26 +286,118 @@ impl From<Material> for MaterialUniform {
             color_material_emissive_normal_texture_channels,
             occlusion_texture_channel_alpha_mode_unlit_flag_and_workflow,
             alpha_cutoff,
+            texture_swizzle,
+            height_scale,
+            normal_scale,
+        }
+    }
+}
+
+//每个纹理两个vec4：[0]是2x2缩放旋转矩阵的两行，[1].xy是平移
+pub type TextureTransformUniform = [[f32; 4]; 2];
+
+pub fn texture_transform_uniform(texture: Option<TextureData>) -> TextureTransformUniform {
+    let [row0, row1, offset] = texture
+        .map(|t| t.get_transform())
+        .unwrap_or_default()
+        .matrix();
+    [
+        [row0[0], row0[1], row1[0], row1[1]],
+        [offset[0], offset[1], 0.0, 0.0],
+    ]
+}
+
+#[derive(Clone, Copy)]
+#[allow(dead_code)]
+pub struct TextureTransformsUniform {
+    color: TextureTransformUniform,
+    material: TextureTransformUniform,
+    emissive: TextureTransformUniform,
+    normals: TextureTransformUniform,
+    occlusion: TextureTransformUniform,
+    height: TextureTransformUniform,
+}
+
+impl From<Material> for TextureTransformsUniform {
+    fn from(material: Material) -> Self {
+        let material_texture = match material.get_workflow() {
+            PBRWorkflow::MetallicRoughness(workflow) => workflow.get_metallic_roughness_texture(),
+            PBRWorkflow::SpecularGlossiness(workflow) => workflow.get_specular_glossiness_texture(),
+        };
+        Self {
+            color: texture_transform_uniform(material.get_color_texture()),
+            material: texture_transform_uniform(material_texture),
+            emissive: texture_transform_uniform(material.get_emissive_texture()),
+            normals: texture_transform_uniform(material.get_normals_texture()),
+            occlusion: texture_transform_uniform(material.get_occlusion_texture()),
+            height: texture_transform_uniform(material.get_height_texture()),
         }
     }
 }
 
-pub fn create_transform_ubos(context: &Arc<Context>, model: &Model, count: u32) -> Vec<Buffer> {
-    let mesh_node_count = model
-        .nodes()
-        .nodes()
-        .iter()
-        .filter(|n| n.mesh_index().is_some())
-        .count() as u32;
+//按primitive顺序排列，每个元素按UBO偏移对齐，供逐primitive的描述符集引用
+pub fn create_texture_transforms_ubo(context: &Arc<Context>, model: &Model) -> Buffer {
+    let elem_size = context.get_ubo_alignment::<TextureTransformsUniform>() as usize;
+    let mut data = Vec::with_capacity(model.primitive_count().max(1) * elem_size);
+    for primitive in model.meshes().iter().flat_map(|m| m.primitives()) {
+        let uniform = TextureTransformsUniform::from(primitive.material());
+        let start = data.len();
+        data.extend_from_slice(unsafe { any_as_u8_slice(&uniform) });
+        data.resize(start + elem_size, 0);
+    }
+    if data.is_empty() {
+        data.resize(elem_size, 0);
+    }
+
+    create_device_local_buffer_with_data::<u8, _>(
+        context,
+        vk::BufferUsageFlags::UNIFORM_BUFFER,
+        &data,
+        "TextureTransforms.UBO",
+    )
+}
+
+pub fn create_transform_ubos(
+    context: &Arc<Context>,
+    model: &Model,
+    count: u32,
+    name: &str,
+) -> Vec<Buffer> {
+    let mesh_node_count = model.nodes().mesh_nodes().count() as u32;
     let elem_size = context.get_ubo_alignment::<Matrix4<f32>>();
 
     (0..count)
-        .map(|_| {
+        .map(|i| {
             let mut buffer = Buffer::create(
                 Arc::clone(context),
                 u64::from(elem_size * mesh_node_count),
                 vk::BufferUsageFlags::UNIFORM_BUFFER,
                 vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                &format!("{}[{}]", name, i),
+            );
+            buffer.map_memory();
+            buffer
+        })
+        .collect::<Vec<_>>()
+}
+
+pub fn create_instance_buffers(
+    context: &Arc<Context>,
+    instance_count: usize,
+    count: u32,
+    name: &str,
+) -> Vec<Buffer> {
+    //没有实例化绘制时也需要一个有效的缓冲绑定到描述符上
+    let buffer_size = instance_count.max(1) * size_of::<Matrix4<f32>>();
+
+    (0..count)
+        .map(|i| {
+            let mut buffer = Buffer::create(
+                Arc::clone(context),
+                buffer_size as vk::DeviceSize,
+                vk::BufferUsageFlags::STORAGE_BUFFER,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                &format!("{}[{}]", name, i),
             );
             buffer.map_memory();
             buffer
@@ -254,24 +409,30 @@ pub fn create_skin_ubos(
     context: &Arc<Context>,
     model: &Model,
     count: u32,
-) -> (Vec<Buffer>, Vec<Vec<JointsBuffer>>) {
+    name: &str,
+) -> Vec<Buffer> {
     let skin_node_count = model.skins().len().max(1);
     let elem_size = context.get_ubo_alignment::<JointsBuffer>();
 
-    let buffers = (0..count)
-        .map(|_| {
+    (0..count)
+        .map(|i| {
             let mut buffer = Buffer::create(
                 Arc::clone(context),
                 u64::from(elem_size * skin_node_count as u32),
                 vk::BufferUsageFlags::UNIFORM_BUFFER,
                 vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                &format!("{}[{}]", name, i),
             );
             buffer.map_memory();
             buffer
         })
-        .collect();
+        .collect()
+}
+
+pub fn create_skin_matrices(model: &Model, count: u32) -> Vec<Vec<JointsBuffer>> {
+    let skin_node_count = model.skins().len().max(1);
 
-    let matrices = (0..count)
+    (0..count)
         .map(|_| {
             let mut matrices = Vec::with_capacity(skin_node_count);
             for _ in 0..skin_node_count {
@@ -279,30 +440,26 @@ pub fn create_skin_ubos(
             }
             matrices
         })
-        .collect();
-
-    (buffers, matrices)
+        .collect()
 }
 
-pub fn create_lights_ubos(context: &Arc<Context>, model: &Model, count: u32) -> Vec<Buffer> {
-    let light_count = model
-        .nodes()
-        .nodes()
-        .iter()
-        .filter(|n| n.light_index().is_some())
-        .count();
+pub fn create_light_buffers(context: &Arc<Context>, model: &Model, count: u32) -> Vec<Buffer> {
+    let light_count = model.nodes().light_nodes().count();
 
     //灯的数量不能为0
     let buffer_size = std::cmp::max(1, light_count) * size_of::<LightUniform>();
 
     (0..count)
-        .map(|_| {
-            Buffer::create(
+        .map(|i| {
+            let mut buffer = Buffer::create(
                 Arc::clone(context),
                 buffer_size as vk::DeviceSize,
-                vk::BufferUsageFlags::UNIFORM_BUFFER,
+                vk::BufferUsageFlags::STORAGE_BUFFER,
                 vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
-            )
+                &format!("Lights.SSBO[{}]", i),
+            );
+            buffer.map_memory();
+            buffer
         })
         .collect::<Vec<_>>()
 }
@@ -311,13 +468,16 @@ pub fn create_render_data_ubos(context: &Arc<Context>, count: u32) -> Vec<Buffer
     let buffer_size = size_of::<RenderDataUniform>();
 
     (0..count)
-        .map(|_| {
-            Buffer::create(
+        .map(|i| {
+            let mut buffer = Buffer::create(
                 Arc::clone(context),
                 buffer_size as vk::DeviceSize,
                 vk::BufferUsageFlags::UNIFORM_BUFFER,
                 vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
-            )
+                &format!("RenderData.UBO[{}]", i),
+            );
+            buffer.map_memory();
+            buffer
         })
         .collect::<Vec<_>>()
 }
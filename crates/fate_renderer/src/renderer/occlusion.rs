@@ -0,0 +1,366 @@
+use super::{
+    attachments::SCENE_COLOR_FORMAT, create_renderer_pipeline, depth_compare_op,
+    model::ModelRenderer, RendererPipelineParameters,
+};
+use ash::{vk, Device};
+use rendering::cgmath::{Matrix4, Point3};
+use rendering::util::any_as_u8_slice;
+use std::{mem::size_of, sync::Arc};
+use vulkan::*;
+
+const BOX_VERTEX_COUNT: u32 = 36;
+//相机离包围盒太近时近平面会切掉盒子的正面，查询结果不可靠，余量为近平面距离的倍数
+const CAMERA_INSIDE_MARGIN_SCALE: f32 = 4.0;
+//包围盒按尺寸放大一点，盒子和网格表面重合时不会因为深度精度被判成遮挡
+const BOX_INFLATION: f32 = 0.01;
+
+//遮挡剔除：前向pass画完模型后，用每个节点的包围盒对深度缓冲做遮挡查询，
+//同一个command buffer下次录制前读回结果，没有样本通过的节点在这一帧跳过绘制。
+//被跳过的节点仍然参与查询，重新露出来时最多晚几帧出现
+pub struct OcclusionCuller {
+    context: Arc<Context>,
+    descriptors: Descriptors,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    //每个command buffer一个query pool，节点变多时重建
+    query_pools: Vec<Option<OcclusionQueryPool>>,
+    //每个command buffer上次录制的查询对应的(模型序号, 网格节点序号)
+    queries: Vec<Vec<(usize, usize)>>,
+}
+
+impl OcclusionCuller {
+    pub fn create(
+        context: Arc<Context>,
+        camera_buffers: &[Buffer],
+        msaa_samples: vk::SampleCountFlags,
+        depth_format: vk::Format,
+        reverse_z: bool,
+    ) -> Self {
+        let descriptors = create_descriptors(&context, camera_buffers);
+        let pipeline_layout = create_pipeline_layout(context.device(), descriptors.layout());
+        let pipeline = create_occlusion_pipeline(
+            &context,
+            msaa_samples,
+            depth_format,
+            reverse_z,
+            pipeline_layout,
+        );
+        let frame_count = camera_buffers.len();
+
+        Self {
+            context,
+            descriptors,
+            pipeline_layout,
+            pipeline,
+            query_pools: (0..frame_count).map(|_| None).collect(),
+            queries: vec![Vec::new(); frame_count],
+        }
+    }
+}
+
+impl OcclusionCuller {
+    //在重新录制这一帧的command buffer之前读取上次的结果，还没完成的查询视为可见
+    pub fn read_results(&mut self, frame_index: usize, model_renderers: &mut [ModelRenderer]) {
+        model_renderers
+            .iter_mut()
+            .for_each(|renderer| renderer.data.clear_occlusion());
+
+        let queries = std::mem::take(&mut self.queries[frame_index]);
+        let Some(query_pool) = self.query_pools[frame_index].as_ref() else {
+            return;
+        };
+        if queries.is_empty() {
+            return;
+        }
+
+        let results = query_pool.get_results(0, queries.len() as u32);
+        for ((model_index, node_index), samples) in queries.into_iter().zip(results) {
+            if let Some(renderer) = model_renderers.get_mut(model_index) {
+                renderer
+                    .data
+                    .set_node_occluded(node_index, samples == Some(0));
+            }
+        }
+    }
+
+    //关闭遮挡剔除时清掉上次的结果，避免重新打开时用到过期的数据
+    pub fn clear(&mut self, model_renderers: &mut [ModelRenderer]) {
+        self.queries.iter_mut().for_each(Vec::clear);
+        model_renderers
+            .iter_mut()
+            .for_each(|renderer| renderer.data.clear_occlusion());
+    }
+
+    //必须在render pass外调用
+    pub fn cmd_reset(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+        frame_index: usize,
+        model_renderers: &[ModelRenderer],
+    ) {
+        let query_count = model_renderers
+            .iter()
+            .map(|renderer| renderer.data.mesh_node_count())
+            .sum::<usize>()
+            .max(1) as u32;
+
+        let query_pool = &mut self.query_pools[frame_index];
+        if query_pool
+            .as_ref()
+            .is_none_or(|pool| pool.query_count() < query_count)
+        {
+            *query_pool = Some(OcclusionQueryPool::create(
+                Arc::clone(&self.context),
+                query_count,
+            ));
+        }
+
+        let query_pool = query_pool.as_ref().unwrap();
+        query_pool.cmd_reset(command_buffer, 0, query_pool.query_count());
+    }
+
+    //在前向pass里、模型画完之后调用
+    pub fn cmd_draw(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+        frame_index: usize,
+        model_renderers: &[ModelRenderer],
+        camera_position: Point3<f32>,
+        z_near: f32,
+    ) {
+        let Some(query_pool) = self.query_pools[frame_index].as_ref() else {
+            return;
+        };
+        let queries = &mut self.queries[frame_index];
+        queries.clear();
+
+        let device = self.context.device();
+        unsafe {
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline,
+            );
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &self.descriptors.sets()[frame_index..=frame_index],
+                &[],
+            );
+        }
+
+        let margin = z_near * CAMERA_INSIDE_MARGIN_SCALE;
+        for (model_index, renderer) in model_renderers.iter().enumerate() {
+            for (node_index, transform, aabb) in renderer.data.occlusion_query_nodes() {
+                if is_camera_inside(camera_position, aabb.transform(transform), margin) {
+                    continue;
+                }
+
+                let inflation = (aabb.max() - aabb.min()) * BOX_INFLATION;
+                let min = aabb.min() - inflation;
+                let max = aabb.max() + inflation;
+                let constants = OcclusionConstants {
+                    model: transform,
+                    aabb_min: [min.x, min.y, min.z, 1.0],
+                    aabb_max: [max.x, max.y, max.z, 1.0],
+                };
+
+                let query = queries.len() as u32;
+                query_pool.cmd_begin(command_buffer, query);
+                unsafe {
+                    device.cmd_push_constants(
+                        command_buffer,
+                        self.pipeline_layout,
+                        vk::ShaderStageFlags::VERTEX,
+                        0,
+                        any_as_u8_slice(&constants),
+                    );
+                    device.cmd_draw(command_buffer, BOX_VERTEX_COUNT, 1, 0, 0);
+                }
+                query_pool.cmd_end(command_buffer, query);
+                queries.push((model_index, node_index));
+            }
+        }
+    }
+}
+
+impl Drop for OcclusionCuller {
+    fn drop(&mut self) {
+        let device = self.context.device();
+        unsafe {
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+    }
+}
+
+fn is_camera_inside(
+    camera_position: Point3<f32>,
+    world_aabb: rendering::Aabb<f32>,
+    margin: f32,
+) -> bool {
+    let min = world_aabb.min();
+    let max = world_aabb.max();
+    camera_position.x > min.x - margin
+        && camera_position.x < max.x + margin
+        && camera_position.y > min.y - margin
+        && camera_position.y < max.y + margin
+        && camera_position.z > min.z - margin
+        && camera_position.z < max.z + margin
+}
+
+#[derive(Clone, Copy)]
+#[allow(dead_code)]
+struct OcclusionConstants {
+    model: Matrix4<f32>,
+    aabb_min: [f32; 4],
+    aabb_max: [f32; 4],
+}
+
+fn create_descriptors(context: &Arc<Context>, uniform_buffers: &[Buffer]) -> Descriptors {
+    let layout = create_descriptor_set_layout(context.device());
+    let pool = create_descriptor_pool(context.device(), uniform_buffers.len() as _);
+    let sets = create_descriptor_sets(context, pool, layout, uniform_buffers);
+    Descriptors::new(Arc::clone(context), layout, pool, sets)
+}
+
+fn create_descriptor_set_layout(device: &Device) -> vk::DescriptorSetLayout {
+    let bindings = [vk::DescriptorSetLayoutBinding::builder()
+        .binding(0)
+        .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::VERTEX)
+        .build()];
+
+    let layout_info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+
+    unsafe {
+        device
+            .create_descriptor_set_layout(&layout_info, None)
+            .unwrap()
+    }
+}
+
+fn create_descriptor_pool(device: &Device, descriptor_count: u32) -> vk::DescriptorPool {
+    let pool_sizes = [vk::DescriptorPoolSize {
+        ty: vk::DescriptorType::UNIFORM_BUFFER,
+        descriptor_count,
+    }];
+
+    let create_info = vk::DescriptorPoolCreateInfo::builder()
+        .pool_sizes(&pool_sizes)
+        .max_sets(descriptor_count);
+
+    unsafe { device.create_descriptor_pool(&create_info, None).unwrap() }
+}
+
+fn create_descriptor_sets(
+    context: &Arc<Context>,
+    pool: vk::DescriptorPool,
+    layout: vk::DescriptorSetLayout,
+    buffers: &[Buffer],
+) -> Vec<vk::DescriptorSet> {
+    let layouts = (0..buffers.len()).map(|_| layout).collect::<Vec<_>>();
+
+    let allocate_info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(pool)
+        .set_layouts(&layouts);
+    let sets = unsafe {
+        context
+            .device()
+            .allocate_descriptor_sets(&allocate_info)
+            .unwrap()
+    };
+    for (i, set) in sets.iter().enumerate() {
+        debug_name!(context, *set, "Occlusion.DescriptorSet[{}]", i);
+    }
+
+    sets.iter().zip(buffers.iter()).for_each(|(set, buffer)| {
+        let buffer_info = [vk::DescriptorBufferInfo::builder()
+            .buffer(buffer.buffer)
+            .offset(0)
+            .range(vk::WHOLE_SIZE)
+            .build()];
+
+        let descriptor_writes = [vk::WriteDescriptorSet::builder()
+            .dst_set(*set)
+            .dst_binding(0)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+            .buffer_info(&buffer_info)
+            .build()];
+
+        unsafe {
+            context
+                .device()
+                .update_descriptor_sets(&descriptor_writes, &[])
+        }
+    });
+
+    sets
+}
+
+fn create_pipeline_layout(
+    device: &Device,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+) -> vk::PipelineLayout {
+    let layouts = [descriptor_set_layout];
+    let push_constant_ranges = [vk::PushConstantRange {
+        stage_flags: vk::ShaderStageFlags::VERTEX,
+        offset: 0,
+        size: size_of::<OcclusionConstants>() as _,
+    }];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(&layouts)
+        .push_constant_ranges(&push_constant_ranges);
+    unsafe { device.create_pipeline_layout(&layout_info, None).unwrap() }
+}
+
+fn create_occlusion_pipeline(
+    context: &Arc<Context>,
+    msaa_samples: vk::SampleCountFlags,
+    depth_format: vk::Format,
+    reverse_z: bool,
+    layout: vk::PipelineLayout,
+) -> vk::Pipeline {
+    //只做深度测试，不写深度也不写颜色
+    let depth_stencil_info = vk::PipelineDepthStencilStateCreateInfo::builder()
+        .depth_test_enable(true)
+        .depth_write_enable(false)
+        .depth_compare_op(depth_compare_op(reverse_z))
+        .depth_bounds_test_enable(false)
+        .min_depth_bounds(0.0)
+        .max_depth_bounds(1.0)
+        .stencil_test_enable(false)
+        .front(Default::default())
+        .back(Default::default());
+
+    let color_blend_attachments = [vk::PipelineColorBlendAttachmentState::builder()
+        .color_write_mask(vk::ColorComponentFlags::empty())
+        .blend_enable(false)
+        .build()];
+
+    //不剔除背面，相机在盒子边上时背面也能算进可见样本
+    create_renderer_pipeline::<()>(
+        context,
+        RendererPipelineParameters {
+            name: "Occlusion.Pipeline",
+            vertex_shader_name: "occlusion",
+            fragment_shader_name: "occlusion",
+            vertex_shader_specialization: None,
+            fragment_shader_specialization: None,
+            multisampling: MultisampleParameters::new(msaa_samples),
+            color_attachment_formats: &[SCENE_COLOR_FORMAT],
+            depth_attachment_format: Some(depth_format),
+            layout,
+            depth_stencil_info: &depth_stencil_info,
+            color_blend_attachments: &color_blend_attachments,
+            enable_face_culling: false,
+            front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+            polygon_mode: vk::PolygonMode::FILL,
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            parent: None,
+        },
+    )
+}
@@ -0,0 +1,220 @@
+use crate::renderer::attachments::Attachments;
+use crate::renderer::fullscreen::*;
+use rendering::util::any_as_u8_slice;
+use std::{mem::size_of, sync::Arc};
+use vulkan::ash::{vk, Device};
+use vulkan::{debug_name, Context, Descriptors, Texture};
+
+pub const DEFAULT_OUTLINE_COLOR: [f32; 3] = [1.0, 0.5, 0.0];
+pub const DEFAULT_OUTLINE_THICKNESS: u32 = 2;
+pub const MAX_OUTLINE_THICKNESS: u32 = 8;
+
+#[derive(Clone, Copy)]
+#[allow(dead_code)]
+struct OutlineConstants {
+    color: [f32; 3],
+    selected_id: u32,
+    thickness: i32,
+}
+
+//在选中节点的轮廓外侧描边，直接画在最终画面上
+//轮廓取自gbuffer的拾取ID，和拾取一样跟随蒙皮、动画和节点变换
+pub struct OutlinePass {
+    context: Arc<Context>,
+    descriptors: Descriptors,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl OutlinePass {
+    pub fn create(
+        context: Arc<Context>,
+        output_format: vk::Format,
+        attachments: &Attachments,
+    ) -> Self {
+        let descriptors = create_descriptors(&context);
+        let pipeline_layout = create_pipeline_layout(context.device(), descriptors.layout());
+        let pipeline = create_fullscreen_pipeline(
+            &context,
+            output_format,
+            pipeline_layout,
+            "outline",
+            None,
+            "Outline.Pipeline",
+        );
+
+        let mut pass = OutlinePass {
+            context,
+            descriptors,
+            pipeline_layout,
+            pipeline,
+        };
+        pass.set_attachments(attachments);
+        pass
+    }
+}
+
+impl OutlinePass {
+    pub fn set_attachments(&mut self, attachments: &Attachments) {
+        self.descriptors
+            .sets()
+            .iter()
+            .for_each(|s| update_descriptor_set(&self.context, *s, &attachments.gbuffer_pick_id));
+    }
+
+    pub fn cmd_draw(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        quad_model: &QuadModel,
+        selected_id: u32,
+        color: [f32; 3],
+        thickness: u32,
+    ) {
+        let device = self.context.device();
+
+        unsafe {
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline,
+            );
+            device.cmd_bind_vertex_buffers(command_buffer, 0, &[quad_model.vertices.buffer], &[0]);
+            device.cmd_bind_index_buffer(
+                command_buffer,
+                quad_model.indices.buffer,
+                0,
+                vk::IndexType::UINT16,
+            );
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                self.descriptors.sets(),
+                &[],
+            );
+
+            let constants = OutlineConstants {
+                color,
+                selected_id,
+                thickness: thickness.min(MAX_OUTLINE_THICKNESS) as _,
+            };
+            device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                0,
+                any_as_u8_slice(&constants),
+            );
+
+            device.cmd_draw_indexed(command_buffer, 6, 1, 0, 0, 1);
+        }
+    }
+}
+
+impl Drop for OutlinePass {
+    fn drop(&mut self) {
+        let device = self.context.device();
+        unsafe {
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+    }
+}
+
+fn create_descriptors(context: &Arc<Context>) -> Descriptors {
+    let layout = create_descriptor_set_layout(context.device());
+    let pool = create_descriptor_pool(context.device());
+    let sets = create_descriptor_sets(context, pool, layout);
+    Descriptors::new(Arc::clone(context), layout, pool, sets)
+}
+
+fn create_descriptor_set_layout(device: &Device) -> vk::DescriptorSetLayout {
+    let bindings = [vk::DescriptorSetLayoutBinding::builder()
+        .binding(0)
+        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+        .build()];
+
+    let layout_info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+
+    unsafe {
+        device
+            .create_descriptor_set_layout(&layout_info, None)
+            .unwrap()
+    }
+}
+
+fn create_descriptor_pool(device: &Device) -> vk::DescriptorPool {
+    let descriptor_count = 1;
+    let pool_sizes = [vk::DescriptorPoolSize {
+        ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+        descriptor_count,
+    }];
+
+    let create_info = vk::DescriptorPoolCreateInfo::builder()
+        .pool_sizes(&pool_sizes)
+        .max_sets(descriptor_count)
+        .flags(vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET);
+
+    unsafe { device.create_descriptor_pool(&create_info, None).unwrap() }
+}
+
+fn create_descriptor_sets(
+    context: &Arc<Context>,
+    pool: vk::DescriptorPool,
+    layout: vk::DescriptorSetLayout,
+) -> Vec<vk::DescriptorSet> {
+    let layouts = [layout];
+    let allocate_info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(pool)
+        .set_layouts(&layouts);
+    let sets = unsafe {
+        context
+            .device()
+            .allocate_descriptor_sets(&allocate_info)
+            .unwrap()
+    };
+    for (i, set) in sets.iter().enumerate() {
+        debug_name!(context, *set, "Outline.DescriptorSet[{}]", i);
+    }
+    sets
+}
+
+fn update_descriptor_set(context: &Arc<Context>, set: vk::DescriptorSet, pick_ids: &Texture) {
+    let pick_ids_info = [vk::DescriptorImageInfo::builder()
+        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        .image_view(pick_ids.view)
+        .sampler(pick_ids.sampler.expect("拾取ID缓冲没有采样器！"))
+        .build()];
+
+    let descriptor_writes = [vk::WriteDescriptorSet::builder()
+        .dst_set(set)
+        .dst_binding(0)
+        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .image_info(&pick_ids_info)
+        .build()];
+
+    unsafe {
+        context
+            .device()
+            .update_descriptor_sets(&descriptor_writes, &[])
+    }
+}
+
+fn create_pipeline_layout(
+    device: &Device,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+) -> vk::PipelineLayout {
+    let layouts = [descriptor_set_layout];
+    let push_constant_ranges = [vk::PushConstantRange {
+        offset: 0,
+        size: size_of::<OutlineConstants>() as _,
+        stage_flags: vk::ShaderStageFlags::FRAGMENT,
+    }];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(&layouts)
+        .push_constant_ranges(&push_constant_ranges);
+    unsafe { device.create_pipeline_layout(&layout_info, None).unwrap() }
+}
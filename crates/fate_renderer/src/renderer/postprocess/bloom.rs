@@ -2,11 +2,9 @@ use std::{mem::size_of, sync::Arc};
 
 use rendering::util::any_as_u8_slice;
 use vulkan::{
-    ash::{
-        vk::{self, RenderingAttachmentInfo, RenderingInfo},
-        Device,
-    },
-    cmd_transition_images_layouts, Context, Descriptors, LayoutTransition, MipsRange,
+    ash::{vk, Device},
+    cmd_set_viewport_and_scissor, cmd_transition_images_layouts, debug_name, Context, Descriptors,
+    LayoutTransition, MipsRange, RenderingAttachment, RenderingDesc,
 };
 
 use crate::renderer::{
@@ -16,6 +14,16 @@ use crate::renderer::{
 
 const BLOOM_FILTER_RADIUS: f32 = 0.005;
 
+//阈值作用于曝光和tone map之前的HDR颜色，自发光强度越大越容易超过阈值
+pub const DEFAULT_BLOOM_THRESHOLD: f32 = 1.0;
+
+#[allow(dead_code)]
+struct DownsampleConstants {
+    src_resolution: [f32; 2],
+    threshold: f32,
+    prefilter: u32,
+}
+
 pub struct BloomPass {
     context: Arc<Context>,
     descriptors: Descriptors,
@@ -23,10 +31,17 @@ pub struct BloomPass {
     upsample_pipeline_layout: vk::PipelineLayout,
     downsample_pipeline: vk::Pipeline,
     upsample_pipeline: vk::Pipeline,
+    threshold: f32,
+    mip_levels: u32,
 }
 
 impl BloomPass {
-    pub fn create(context: Arc<Context>, attachments: &Attachments) -> Self {
+    pub fn create(
+        context: Arc<Context>,
+        attachments: &Attachments,
+        threshold: f32,
+        mip_levels: u32,
+    ) -> Self {
         let descriptors = create_descriptors(&context, attachments);
         let downsample_pipeline_layout =
             create_downsample_pipeline_layout(context.device(), descriptors.layout());
@@ -42,6 +57,8 @@ impl BloomPass {
             upsample_pipeline_layout,
             downsample_pipeline,
             upsample_pipeline,
+            threshold,
+            mip_levels: mip_levels.clamp(1, BLOOM_MIP_LEVELS),
         }
     }
 }
@@ -51,6 +68,15 @@ impl BloomPass {
         update_descriptor_sets(&self.context, self.descriptors.sets(), attachments);
     }
 
+    pub fn set_threshold(&mut self, threshold: f32) {
+        self.threshold = threshold;
+    }
+
+    //只渲染前mip_levels级，级数越多bloom扩散越远
+    pub fn set_mip_levels(&mut self, mip_levels: u32) {
+        self.mip_levels = mip_levels.clamp(1, BLOOM_MIP_LEVELS);
+    }
+
     pub fn cmd_draw(
         &self,
         command_buffer: vk::CommandBuffer,
@@ -85,7 +111,7 @@ impl BloomPass {
         let mut input_image = &attachments.get_scene_resolved_color().image;
         let mut input_mip = 0u32;
 
-        for output_mip in 0..attachments.bloom.image.get_mip_levels() as usize {
+        for output_mip in 0..self.mip_levels as usize {
             let output_extent = attachments.bloom.mips_resolution[output_mip];
 
             {
@@ -108,52 +134,24 @@ impl BloomPass {
                 )
             }
 
-            unsafe {
-                self.context.device().cmd_set_viewport(
-                    command_buffer,
-                    0,
-                    &[vk::Viewport {
-                        width: output_extent.width as _,
-                        height: output_extent.height as _,
-                        max_depth: 1.0,
-                        ..Default::default()
-                    }],
-                );
-                self.context.device().cmd_set_scissor(
-                    command_buffer,
-                    0,
-                    &[vk::Rect2D {
-                        extent: output_extent,
-                        ..Default::default()
-                    }],
-                )
-            }
+            cmd_set_viewport_and_scissor(self.context.device(), command_buffer, output_extent);
 
             {
-                let attachment_info = RenderingAttachmentInfo::builder()
-                    .clear_value(vk::ClearValue {
-                        color: vk::ClearColorValue {
-                            float32: [0.0, 0.0, 0.0, 1.0],
-                        },
-                    })
-                    .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
-                    .image_view(attachments.bloom.mips_views[output_mip])
-                    .load_op(vk::AttachmentLoadOp::CLEAR)
-                    .store_op(vk::AttachmentStoreOp::STORE);
-
-                let rendering_info = RenderingInfo::builder()
-                    .color_attachments(std::slice::from_ref(&attachment_info))
-                    .layer_count(1)
-                    .render_area(vk::Rect2D {
-                        extent: output_extent,
-                        ..Default::default()
-                    });
+                let attachment = RenderingAttachment::new(
+                    &attachments.bloom.image,
+                    attachments.bloom.mips_views[output_mip],
+                    vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                )
+                .clear_color([0.0, 0.0, 0.0, 1.0]);
 
-                unsafe {
-                    self.context
-                        .dynamic_rendering()
-                        .cmd_begin_rendering(command_buffer, &rendering_info)
-                };
+                self.context.cmd_begin_rendering(
+                    command_buffer,
+                    &RenderingDesc {
+                        extent: output_extent,
+                        color_attachments: std::slice::from_ref(&attachment),
+                        depth_attachment: None,
+                    },
+                );
             }
 
             unsafe {
@@ -192,8 +190,12 @@ impl BloomPass {
             };
 
             unsafe {
-                let data = [(input_extent.width as f32, input_extent.height as f32)];
-                let data = any_as_u8_slice(&data);
+                let constants = DownsampleConstants {
+                    src_resolution: [input_extent.width as f32, input_extent.height as f32],
+                    threshold: self.threshold,
+                    prefilter: (output_mip == 0) as u32,
+                };
+                let data = any_as_u8_slice(&constants);
                 device.cmd_push_constants(
                     command_buffer,
                     self.downsample_pipeline_layout,
@@ -205,11 +207,7 @@ impl BloomPass {
 
             unsafe { device.cmd_draw_indexed(command_buffer, 6, 1, 0, 0, 1) };
 
-            unsafe {
-                self.context
-                    .dynamic_rendering()
-                    .cmd_end_rendering(command_buffer)
-            };
+            self.context.cmd_end_rendering(command_buffer);
 
             input_extent = output_extent;
             input_image = &attachments.bloom.image;
@@ -225,7 +223,7 @@ impl BloomPass {
     ) {
         let device = self.context.device();
 
-        for output_mip in (0..(attachments.bloom.image.get_mip_levels() - 1) as usize).rev() {
+        for output_mip in (0..(self.mip_levels - 1) as usize).rev() {
             let output_extent = attachments.bloom.mips_resolution[output_mip];
             let input_mip = output_mip + 1;
 
@@ -249,52 +247,24 @@ impl BloomPass {
                 )
             }
 
-            unsafe {
-                self.context.device().cmd_set_viewport(
-                    command_buffer,
-                    0,
-                    &[vk::Viewport {
-                        width: output_extent.width as _,
-                        height: output_extent.height as _,
-                        max_depth: 1.0,
-                        ..Default::default()
-                    }],
-                );
-                self.context.device().cmd_set_scissor(
-                    command_buffer,
-                    0,
-                    &[vk::Rect2D {
-                        extent: output_extent,
-                        ..Default::default()
-                    }],
-                )
-            }
+            cmd_set_viewport_and_scissor(self.context.device(), command_buffer, output_extent);
 
             {
-                let attachment_info = RenderingAttachmentInfo::builder()
-                    .clear_value(vk::ClearValue {
-                        color: vk::ClearColorValue {
-                            float32: [0.0, 0.0, 0.0, 1.0],
-                        },
-                    })
-                    .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
-                    .image_view(attachments.bloom.mips_views[output_mip])
-                    .load_op(vk::AttachmentLoadOp::CLEAR)
-                    .store_op(vk::AttachmentStoreOp::STORE);
-
-                let rendering_info = RenderingInfo::builder()
-                    .color_attachments(std::slice::from_ref(&attachment_info))
-                    .layer_count(1)
-                    .render_area(vk::Rect2D {
-                        extent: output_extent,
-                        ..Default::default()
-                    });
+                let attachment = RenderingAttachment::new(
+                    &attachments.bloom.image,
+                    attachments.bloom.mips_views[output_mip],
+                    vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                )
+                .clear_color([0.0, 0.0, 0.0, 1.0]);
 
-                unsafe {
-                    self.context
-                        .dynamic_rendering()
-                        .cmd_begin_rendering(command_buffer, &rendering_info)
-                };
+                self.context.cmd_begin_rendering(
+                    command_buffer,
+                    &RenderingDesc {
+                        extent: output_extent,
+                        color_attachments: std::slice::from_ref(&attachment),
+                        depth_attachment: None,
+                    },
+                );
             }
 
             unsafe {
@@ -346,11 +316,7 @@ impl BloomPass {
 
             unsafe { device.cmd_draw_indexed(command_buffer, 6, 1, 0, 0, 1) };
 
-            unsafe {
-                self.context
-                    .dynamic_rendering()
-                    .cmd_end_rendering(command_buffer)
-            };
+            self.context.cmd_end_rendering(command_buffer);
         }
     }
 }
@@ -423,6 +389,9 @@ fn create_descriptor_sets(
             .allocate_descriptor_sets(&allocate_info)
             .unwrap()
     };
+    for (i, set) in sets.iter().enumerate() {
+        debug_name!(context, *set, "Bloom.DescriptorSet[{}]", i);
+    }
 
     update_descriptor_sets(context, &sets, attachments);
 
@@ -486,7 +455,7 @@ fn create_downsample_pipeline_layout(
     let layouts = [descriptor_set_layout];
     let push_constant_ranges = [vk::PushConstantRange {
         offset: 0,
-        size: (2 * size_of::<f32>()) as _,
+        size: size_of::<DownsampleConstants>() as _,
         stage_flags: vk::ShaderStageFlags::FRAGMENT,
     }];
     let layout_info = vk::PipelineLayoutCreateInfo::builder()
@@ -512,9 +481,23 @@ fn create_upsample_pipeline_layout(
 }
 
 fn create_downsample_pipeline(context: &Arc<Context>, layout: vk::PipelineLayout) -> vk::Pipeline {
-    create_fullscreen_pipeline(context, BLOOM_FORMAT, layout, "downsample", None)
+    create_fullscreen_pipeline(
+        context,
+        BLOOM_FORMAT,
+        layout,
+        "downsample",
+        None,
+        "Bloom.Downsample.Pipeline",
+    )
 }
 
 fn create_upsample_pipeline(context: &Arc<Context>, layout: vk::PipelineLayout) -> vk::Pipeline {
-    create_fullscreen_pipeline(context, BLOOM_FORMAT, layout, "upsample", None)
+    create_fullscreen_pipeline(
+        context,
+        BLOOM_FORMAT,
+        layout,
+        "upsample",
+        None,
+        "Bloom.Upsample.Pipeline",
+    )
 }
@@ -1,22 +1,58 @@
-use crate::renderer::attachments::Attachments;
+use crate::camera::{Z_FAR, Z_NEAR};
 use crate::renderer::fullscreen::*;
-use std::sync::Arc;
-use vulkan::ash::vk::{RenderingAttachmentInfo, RenderingInfo};
+use rendering::util::any_as_u8_slice;
+use std::{mem::size_of, sync::Arc};
 use vulkan::ash::{vk, Device};
-use vulkan::{Context, Descriptors, Texture};
+use vulkan::{
+    cmd_set_viewport_and_scissor, debug_name, Context, Descriptors, RenderingAttachment,
+    RenderingDesc, Texture,
+};
 
 const BLUR_OUTPUT_FORMAT: vk::Format = vk::Format::R8_UNORM;
 
+pub const DEFAULT_SSAO_BLUR_RADIUS: u32 = 4;
+pub const MAX_SSAO_BLUR_RADIUS: u32 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlurDirection {
+    Horizontal = 0,
+    Vertical,
+}
+
+#[allow(dead_code)]
+struct ConfigUniform {
+    direction: [f32; 2],
+    radius: i32,
+    z_near: f32,
+    z_far: f32,
+    reverse_z: u32,
+    orthographic: u32,
+}
+
+//可分离的双边模糊，水平和垂直方向各使用一个descriptor set
 pub struct BlurPass {
     context: Arc<Context>,
     descriptors: Descriptors,
     pipeline_layout: vk::PipelineLayout,
     pipeline: vk::Pipeline,
+    radius: u32,
+    reverse_z: bool,
+    orthographic: bool,
+    //深度线性化需要和当前相机的近远平面一致
+    z_near: f32,
+    z_far: f32,
 }
 
 impl BlurPass {
-    pub fn create(context: Arc<Context>, input_image: &Texture) -> Self {
-        let descriptors = create_descriptors(&context, input_image);
+    pub fn create(
+        context: Arc<Context>,
+        input_image: &Texture,
+        intermediate_image: &Texture,
+        depth: &Texture,
+        radius: u32,
+        reverse_z: bool,
+    ) -> Self {
+        let descriptors = create_descriptors(&context, input_image, intermediate_image, depth);
         let pipeline_layout = create_pipeline_layout(context.device(), descriptors.layout());
         let pipeline = create_pipeline(&context, pipeline_layout);
 
@@ -25,77 +61,73 @@ impl BlurPass {
             descriptors,
             pipeline_layout,
             pipeline,
+            radius,
+            reverse_z,
+            orthographic: false,
+            z_near: Z_NEAR,
+            z_far: Z_FAR,
         }
     }
 }
 
 impl BlurPass {
-    pub fn set_input_image(&mut self, input_image: &Texture) {
-        self.descriptors
-            .sets()
-            .iter()
-            .for_each(|s| update_descriptor_set(&self.context, *s, input_image));
+    pub fn set_input_images(
+        &mut self,
+        input_image: &Texture,
+        intermediate_image: &Texture,
+        depth: &Texture,
+    ) {
+        let sets = self.descriptors.sets();
+        update_descriptor_set(&self.context, sets[0], input_image, depth);
+        update_descriptor_set(&self.context, sets[1], intermediate_image, depth);
+    }
+
+    pub fn set_radius(&mut self, radius: u32) {
+        self.radius = radius;
+    }
+
+    pub fn set_orthographic(&mut self, orthographic: bool) {
+        self.orthographic = orthographic;
+    }
+
+    pub fn set_clip_planes(&mut self, z_near: f32, z_far: f32) {
+        self.z_near = z_near;
+        self.z_far = z_far;
     }
 
+    //水平方向读输入写中间图像，垂直方向读中间图像写输出
     pub fn cmd_draw(
         &self,
         command_buffer: vk::CommandBuffer,
-        attachments: &Attachments,
+        direction: BlurDirection,
+        output_image: &Texture,
         quad_model: &QuadModel,
     ) {
         let device = self.context.device();
 
         let extent = vk::Extent2D {
-            width: attachments.ssao_blur.image.extent.width,
-            height: attachments.ssao_blur.image.extent.height,
+            width: output_image.image.extent.width,
+            height: output_image.image.extent.height,
         };
 
-        unsafe {
-            self.context.device().cmd_set_viewport(
-                command_buffer,
-                0,
-                &[vk::Viewport {
-                    width: extent.width as _,
-                    height: extent.height as _,
-                    max_depth: 1.0,
-                    ..Default::default()
-                }],
-            );
-            self.context.device().cmd_set_scissor(
-                command_buffer,
-                0,
-                &[vk::Rect2D {
-                    extent,
-                    ..Default::default()
-                }],
-            )
-        }
+        cmd_set_viewport_and_scissor(self.context.device(), command_buffer, extent);
 
         {
-            let attachment_info = RenderingAttachmentInfo::builder()
-                .clear_value(vk::ClearValue {
-                    color: vk::ClearColorValue {
-                        float32: [0.0, 0.0, 0.0, 1.0],
-                    },
-                })
-                .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
-                .image_view(attachments.ssao_blur.view)
-                .load_op(vk::AttachmentLoadOp::CLEAR)
-                .store_op(vk::AttachmentStoreOp::STORE);
-
-            let rendering_info = RenderingInfo::builder()
-                .color_attachments(std::slice::from_ref(&attachment_info))
-                .layer_count(1)
-                .render_area(vk::Rect2D {
-                    extent,
-                    ..Default::default()
-                });
+            let attachment = RenderingAttachment::new(
+                &output_image.image,
+                output_image.view,
+                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            )
+            .clear_color([0.0, 0.0, 0.0, 1.0]);
 
-            unsafe {
-                self.context
-                    .dynamic_rendering()
-                    .cmd_begin_rendering(command_buffer, &rendering_info)
-            };
+            self.context.cmd_begin_rendering(
+                command_buffer,
+                &RenderingDesc {
+                    extent,
+                    color_attachments: std::slice::from_ref(&attachment),
+                    depth_attachment: None,
+                },
+            );
         }
 
         unsafe {
@@ -122,18 +154,35 @@ impl BlurPass {
                 vk::PipelineBindPoint::GRAPHICS,
                 self.pipeline_layout,
                 0,
-                self.descriptors.sets(),
+                &self.descriptors.sets()[direction as usize..=direction as usize],
                 &[],
             )
         };
 
-        unsafe { device.cmd_draw_indexed(command_buffer, 6, 1, 0, 0, 1) };
-
         unsafe {
-            self.context
-                .dynamic_rendering()
-                .cmd_end_rendering(command_buffer)
+            let config = ConfigUniform {
+                direction: match direction {
+                    BlurDirection::Horizontal => [1.0, 0.0],
+                    BlurDirection::Vertical => [0.0, 1.0],
+                },
+                radius: self.radius as _,
+                z_near: self.z_near,
+                z_far: self.z_far,
+                reverse_z: self.reverse_z as u32,
+                orthographic: self.orthographic as u32,
+            };
+            device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                0,
+                any_as_u8_slice(&config),
+            );
         };
+
+        unsafe { device.cmd_draw_indexed(command_buffer, 6, 1, 0, 0, 1) };
+
+        self.context.cmd_end_rendering(command_buffer);
     }
 }
 
@@ -147,20 +196,40 @@ impl Drop for BlurPass {
     }
 }
 
-fn create_descriptors(context: &Arc<Context>, input_image: &Texture) -> Descriptors {
+fn create_descriptors(
+    context: &Arc<Context>,
+    input_image: &Texture,
+    intermediate_image: &Texture,
+    depth: &Texture,
+) -> Descriptors {
     let layout = create_descriptor_set_layout(context.device());
     let pool = create_descriptor_pool(context.device());
-    let sets = create_descriptor_sets(context, pool, layout, input_image);
+    let sets = create_descriptor_sets(
+        context,
+        pool,
+        layout,
+        input_image,
+        intermediate_image,
+        depth,
+    );
     Descriptors::new(Arc::clone(context), layout, pool, sets)
 }
 
 fn create_descriptor_set_layout(device: &Device) -> vk::DescriptorSetLayout {
-    let bindings = [vk::DescriptorSetLayoutBinding::builder()
-        .binding(0)
-        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-        .descriptor_count(1)
-        .stage_flags(vk::ShaderStageFlags::FRAGMENT)
-        .build()];
+    let bindings = [
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .build(),
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(1)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .build(),
+    ];
 
     let layout_info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
 
@@ -171,15 +240,15 @@ fn create_descriptor_set_layout(device: &Device) -> vk::DescriptorSetLayout {
     }
 }
 fn create_descriptor_pool(device: &Device) -> vk::DescriptorPool {
-    let descriptor_count = 1;
+    let set_count = 2;
     let pool_sizes = [vk::DescriptorPoolSize {
         ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
-        descriptor_count,
+        descriptor_count: set_count * 2,
     }];
 
     let create_info = vk::DescriptorPoolCreateInfo::builder()
         .pool_sizes(&pool_sizes)
-        .max_sets(descriptor_count)
+        .max_sets(set_count)
         .flags(vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET);
 
     unsafe { device.create_descriptor_pool(&create_info, None).unwrap() }
@@ -190,8 +259,10 @@ fn create_descriptor_sets(
     pool: vk::DescriptorPool,
     layout: vk::DescriptorSetLayout,
     input_image: &Texture,
+    intermediate_image: &Texture,
+    depth: &Texture,
 ) -> Vec<vk::DescriptorSet> {
-    let layouts = [layout];
+    let layouts = [layout, layout];
     let allocate_info = vk::DescriptorSetAllocateInfo::builder()
         .descriptor_pool(pool)
         .set_layouts(&layouts);
@@ -201,30 +272,49 @@ fn create_descriptor_sets(
             .allocate_descriptor_sets(&allocate_info)
             .unwrap()
     };
+    for (i, set) in sets.iter().enumerate() {
+        debug_name!(context, *set, "SSAOBlur.DescriptorSet[{}]", i);
+    }
 
-    update_descriptor_set(context, sets[0], input_image);
+    update_descriptor_set(context, sets[0], input_image, depth);
+    update_descriptor_set(context, sets[1], intermediate_image, depth);
 
     sets
 }
 
-fn update_descriptor_set(context: &Arc<Context>, set: vk::DescriptorSet, input_image: &Texture) {
+fn update_descriptor_set(
+    context: &Arc<Context>,
+    set: vk::DescriptorSet,
+    input_image: &Texture,
+    depth: &Texture,
+) {
     let input_image_info = [vk::DescriptorImageInfo::builder()
         .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
         .image_view(input_image.view)
-        .sampler(
-            input_image
-                .sampler
-                .expect("后处理输入image没有采样器！"),
-        )
+        .sampler(input_image.sampler.expect("后处理输入image没有采样器！"))
         .build()];
 
-    let descriptor_writes = [vk::WriteDescriptorSet::builder()
-        .dst_set(set)
-        .dst_binding(0)
-        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-        .image_info(&input_image_info)
+    let depth_info = [vk::DescriptorImageInfo::builder()
+        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        .image_view(depth.view)
+        .sampler(depth.sampler.expect("深度贴图没有采样器！"))
         .build()];
 
+    let descriptor_writes = [
+        vk::WriteDescriptorSet::builder()
+            .dst_set(set)
+            .dst_binding(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&input_image_info)
+            .build(),
+        vk::WriteDescriptorSet::builder()
+            .dst_set(set)
+            .dst_binding(1)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&depth_info)
+            .build(),
+    ];
+
     unsafe {
         context
             .device()
@@ -237,10 +327,24 @@ fn create_pipeline_layout(
     descriptor_set_layout: vk::DescriptorSetLayout,
 ) -> vk::PipelineLayout {
     let layouts = [descriptor_set_layout];
-    let layout_info = vk::PipelineLayoutCreateInfo::builder().set_layouts(&layouts);
+    let push_constant_ranges = [vk::PushConstantRange {
+        stage_flags: vk::ShaderStageFlags::FRAGMENT,
+        offset: 0,
+        size: size_of::<ConfigUniform>() as _,
+    }];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(&layouts)
+        .push_constant_ranges(&push_constant_ranges);
     unsafe { device.create_pipeline_layout(&layout_info, None).unwrap() }
 }
 
 fn create_pipeline(context: &Arc<Context>, layout: vk::PipelineLayout) -> vk::Pipeline {
-    create_fullscreen_pipeline(context, BLUR_OUTPUT_FORMAT, layout, "blur", None)
+    create_fullscreen_pipeline(
+        context,
+        BLUR_OUTPUT_FORMAT,
+        layout,
+        "blur",
+        None,
+        "SSAOBlur.Pipeline",
+    )
 }
@@ -1,9 +1,18 @@
 use crate::renderer::attachments::Attachments;
 use crate::renderer::{fullscreen::*, RendererSettings};
 use rendering::util::any_as_u8_slice;
+use serde::{Deserialize, Serialize};
 use std::{mem::size_of, sync::Arc};
 use vulkan::ash::{vk, Device};
-use vulkan::{Context, Descriptors};
+use vulkan::{debug_name, Context, Descriptors};
+
+//曝光补偿的范围，单位EV
+pub const MIN_EXPOSURE: f32 = -10.0;
+pub const MAX_EXPOSURE: f32 = 10.0;
+//映射到纯白的线性亮度，只有Uncharted使用
+pub const DEFAULT_WHITE_POINT: f32 = 11.2;
+pub const MIN_WHITE_POINT: f32 = 1.0;
+pub const MAX_WHITE_POINT: f32 = 20.0;
 
 pub struct FinalPass {
     context: Arc<Context>,
@@ -16,9 +25,12 @@ pub struct FinalPass {
     none_pipeline: vk::Pipeline,
     tone_map_mode: ToneMapMode,
     bloom_strength: f32,
+    exposure: f32,
+    white_point: f32,
+    bloom_enabled: bool,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ToneMapMode {
     Default = 0,
     Uncharted,
@@ -44,9 +56,13 @@ impl ToneMapMode {
             _ => Option::None,
         }
     }
+
+    pub fn uses_white_point(&self) -> bool {
+        *self == ToneMapMode::Uncharted
+    }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum FXAAMode {
     Quality = 0,
     Console,
@@ -75,7 +91,8 @@ impl FinalPass {
         attachments: &Attachments,
         settings: RendererSettings,
     ) -> Self {
-        let descriptors = create_descriptors(&context, attachments);
+        let bloom_enabled = settings.bloom_enabled;
+        let descriptors = create_descriptors(&context, attachments, bloom_enabled);
         let pipeline_layout = create_pipeline_layout(context.device(), descriptors.layout());
         let default_pipeline = create_pipeline(
             &context,
@@ -102,6 +119,8 @@ impl FinalPass {
 
         let tone_map_mode = settings.tone_map_mode;
         let bloom_strength = settings.bloom_strength;
+        let exposure = settings.exposure;
+        let white_point = settings.white_point;
 
         FinalPass {
             context,
@@ -114,6 +133,9 @@ impl FinalPass {
             none_pipeline,
             tone_map_mode,
             bloom_strength,
+            exposure,
+            white_point,
+            bloom_enabled,
         }
     }
 }
@@ -127,11 +149,25 @@ impl FinalPass {
         self.bloom_strength = bloom_strength;
     }
 
+    pub fn set_exposure(&mut self, exposure: f32) {
+        self.exposure = exposure;
+    }
+
+    pub fn set_white_point(&mut self, white_point: f32) {
+        self.white_point = white_point;
+    }
+
     pub fn set_attachments(&mut self, attachments: &Attachments) {
         self.descriptors
             .sets()
             .iter()
-            .for_each(|s| update_descriptor_set(&self.context, *s, attachments))
+            .for_each(|s| update_descriptor_set(&self.context, *s, attachments, self.bloom_enabled))
+    }
+
+    //关闭bloom时bloom贴图绑定为输入图像，避免采样未渲染的图像
+    pub fn set_bloom_enabled(&mut self, attachments: &Attachments, bloom_enabled: bool) {
+        self.bloom_enabled = bloom_enabled;
+        self.set_attachments(attachments);
     }
 
     pub fn cmd_draw(&self, command_buffer: vk::CommandBuffer, quad_model: &QuadModel) {
@@ -174,7 +210,8 @@ impl FinalPass {
         };
 
         unsafe {
-            let data = [self.bloom_strength];
+            //曝光以EV保存，shader里直接乘缩放系数
+            let data = [self.bloom_strength, self.exposure.exp2(), self.white_point];
             let data = any_as_u8_slice(&data);
             device.cmd_push_constants(
                 command_buffer,
@@ -203,10 +240,14 @@ impl Drop for FinalPass {
     }
 }
 
-fn create_descriptors(context: &Arc<Context>, attachments: &Attachments) -> Descriptors {
+fn create_descriptors(
+    context: &Arc<Context>,
+    attachments: &Attachments,
+    bloom_enabled: bool,
+) -> Descriptors {
     let layout = create_descriptor_set_layout(context.device());
     let pool = create_descriptor_pool(context.device());
-    let sets = create_descriptor_sets(context, pool, layout, attachments);
+    let sets = create_descriptor_sets(context, pool, layout, attachments, bloom_enabled);
     Descriptors::new(Arc::clone(context), layout, pool, sets)
 }
 
@@ -253,6 +294,7 @@ fn create_descriptor_sets(
     pool: vk::DescriptorPool,
     layout: vk::DescriptorSetLayout,
     attachments: &Attachments,
+    bloom_enabled: bool,
 ) -> Vec<vk::DescriptorSet> {
     let layouts = [layout];
     let allocate_info = vk::DescriptorSetAllocateInfo::builder()
@@ -264,8 +306,11 @@ fn create_descriptor_sets(
             .allocate_descriptor_sets(&allocate_info)
             .unwrap()
     };
+    for (i, set) in sets.iter().enumerate() {
+        debug_name!(context, *set, "FinalPass.DescriptorSet[{}]", i);
+    }
 
-    update_descriptor_set(context, sets[0], attachments);
+    update_descriptor_set(context, sets[0], attachments, bloom_enabled);
 
     sets
 }
@@ -274,27 +319,28 @@ fn update_descriptor_set(
     context: &Arc<Context>,
     set: vk::DescriptorSet,
     attachments: &Attachments,
+    bloom_enabled: bool,
 ) {
     let input_image_info = [vk::DescriptorImageInfo::builder()
         .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-        .image_view(
-            attachments
-                .fxaa
-                .view,
-        )
+        .image_view(attachments.get_scene_resolved_color().view)
         .sampler(
             attachments
-                .fxaa
+                .get_scene_resolved_color()
                 .sampler
                 .expect("后处理输入image没有采样器！"),
         )
         .build()];
 
-    let bloom_info = [vk::DescriptorImageInfo::builder()
-        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-        .image_view(attachments.bloom.mips_views[0])
-        .sampler(attachments.bloom.sampler)
-        .build()];
+    let bloom_info = if bloom_enabled {
+        [vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(attachments.bloom.mips_views[0])
+            .sampler(attachments.bloom.sampler)
+            .build()]
+    } else {
+        input_image_info
+    };
 
     let descriptor_writes = [
         vk::WriteDescriptorSet::builder()
@@ -325,7 +371,7 @@ fn create_pipeline_layout(
     let layouts = [descriptor_set_layout];
     let push_constant_ranges = [vk::PushConstantRange {
         offset: 0,
-        size: size_of::<f32>() as _,
+        size: 3 * size_of::<f32>() as u32,
         stage_flags: vk::ShaderStageFlags::FRAGMENT,
     }];
     let layout_info = vk::PipelineLayoutCreateInfo::builder()
@@ -349,6 +395,7 @@ fn create_pipeline(
         layout,
         "final",
         Some(&specialization_info),
+        &format!("FinalPass.Pipeline.{:?}", tone_map_mode),
     )
 }
 
@@ -0,0 +1,121 @@
+use std::sync::Arc;
+use vulkan::{ash::vk, Context, TimestampQueryPool};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GpuPass {
+    GBuffer = 0,
+    Shadow,
+    Light,
+    PostProcess,
+    UI,
+}
+
+impl GpuPass {
+    pub const fn all() -> [GpuPass; 5] {
+        use GpuPass::*;
+        [GBuffer, Shadow, Light, PostProcess, UI]
+    }
+}
+
+//每个pass开始和结束各一个时间戳
+const QUERIES_PER_FRAME: u32 = GpuPass::all().len() as u32 * 2;
+
+//每个交换链image一段query，和command buffer一一对应
+pub struct GpuProfiler {
+    query_pool: Option<TimestampQueryPool>,
+    enabled: bool,
+    recorded: Vec<bool>,
+    timings: Vec<(GpuPass, f32)>,
+}
+
+impl GpuProfiler {
+    pub fn create(context: Arc<Context>, frame_count: usize) -> Self {
+        let query_pool =
+            TimestampQueryPool::create(context, frame_count as u32 * QUERIES_PER_FRAME);
+        Self {
+            query_pool,
+            enabled: false,
+            recorded: vec![false; frame_count],
+            timings: Vec::new(),
+        }
+    }
+}
+
+impl GpuProfiler {
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.timings.clear();
+        }
+    }
+
+    //最近一次读回的各pass耗时，单位毫秒，没执行的pass不在里面
+    pub fn timings(&self) -> &[(GpuPass, f32)] {
+        &self.timings
+    }
+
+    //在重新录制这一帧的command buffer之前读取上次的结果
+    pub fn read_results(&mut self, frame_index: usize) {
+        let query_pool = match self.query_pool.as_ref() {
+            Some(query_pool) if self.recorded[frame_index] => query_pool,
+            _ => return,
+        };
+
+        let results =
+            query_pool.get_results_ms(frame_index as u32 * QUERIES_PER_FRAME, QUERIES_PER_FRAME);
+        self.timings = GpuPass::all()
+            .iter()
+            .filter_map(|pass| {
+                let index = *pass as usize * 2;
+                match (results[index], results[index + 1]) {
+                    (Some(begin), Some(end)) => Some((*pass, (end - begin) as f32)),
+                    _ => None,
+                }
+            })
+            .collect();
+    }
+
+    pub fn cmd_reset(&mut self, command_buffer: vk::CommandBuffer, frame_index: usize) {
+        self.recorded[frame_index] = false;
+        if let Some(query_pool) = self.active_pool() {
+            query_pool.cmd_reset(
+                command_buffer,
+                frame_index as u32 * QUERIES_PER_FRAME,
+                QUERIES_PER_FRAME,
+            );
+            self.recorded[frame_index] = true;
+        }
+    }
+
+    pub fn cmd_begin(&self, command_buffer: vk::CommandBuffer, frame_index: usize, pass: GpuPass) {
+        if let Some(query_pool) = self.active_pool() {
+            query_pool.cmd_write_timestamp(
+                command_buffer,
+                vk::PipelineStageFlags2::TOP_OF_PIPE,
+                query_index(frame_index, pass),
+            );
+        }
+    }
+
+    pub fn cmd_end(&self, command_buffer: vk::CommandBuffer, frame_index: usize, pass: GpuPass) {
+        if let Some(query_pool) = self.active_pool() {
+            query_pool.cmd_write_timestamp(
+                command_buffer,
+                vk::PipelineStageFlags2::BOTTOM_OF_PIPE,
+                query_index(frame_index, pass) + 1,
+            );
+        }
+    }
+
+    fn active_pool(&self) -> Option<&TimestampQueryPool> {
+        self.query_pool.as_ref().filter(|_| self.enabled)
+    }
+}
+
+fn query_index(frame_index: usize, pass: GpuPass) -> u32 {
+    frame_index as u32 * QUERIES_PER_FRAME + pass as u32 * 2
+}
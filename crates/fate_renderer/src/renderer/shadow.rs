@@ -0,0 +1,359 @@
+use super::attachments::{MAX_PUNCTUAL_SHADOW_LAYERS, MAX_SHADOW_CASCADES};
+use crate::camera::{Projection, Z_FAR};
+use rendering::cgmath::{
+    Deg, EuclideanSpace, InnerSpace, Matrix4, Point3, Rad, SquareMatrix, Transform, Vector3,
+    Vector4,
+};
+use rendering::light::{Light, LightType};
+use serde::{Deserialize, Serialize};
+
+//光源相机沿光线方向往后退的距离，保证视锥外的遮挡物也能投下阴影
+const SHADOW_CASTER_DISTANCE: f32 = 50.0;
+
+pub const DEFAULT_SHADOW_CASCADE_COUNT: u32 = MAX_SHADOW_CASCADES;
+pub const DEFAULT_SHADOW_SPLIT_LAMBDA: f32 = 0.75;
+pub const DEFAULT_SHADOW_PCF_KERNEL_SIZE: u32 = 3;
+//深度偏移是光源裁剪空间下的深度，0.0005大约能消掉2k分辨率下的大部分acne
+pub const DEFAULT_SHADOW_DEPTH_BIAS: f32 = 0.0005;
+//法线偏移以texel为单位，沿法线把采样点推出表面，太大会出现漏光(peter-panning)
+pub const DEFAULT_SHADOW_NORMAL_BIAS: f32 = 1.0;
+
+//投射阴影的点光源和聚光灯数量，每盏点光源要多画六遍场景
+pub const DEFAULT_SHADOW_CASTING_LIGHTS: u32 = 1;
+pub const MAX_SHADOW_CASTING_LIGHTS: u32 = 4;
+const PUNCTUAL_SHADOW_NEAR: f32 = 0.05;
+//glTF里没有range的灯光照射距离无限，阴影只覆盖这个范围
+const PUNCTUAL_SHADOW_DEFAULT_RANGE: f32 = SHADOW_CASTER_DISTANCE;
+//聚光灯张角接近180度时透视投影退化
+const MAX_SPOT_SHADOW_FOV: f32 = 170.0;
+//cube六个面的朝向和上方向，顺序与shader里按主轴选面的顺序一致：+X -X +Y -Y +Z -Z
+const CUBE_FACES: [([f32; 3], [f32; 3]); 6] = [
+    ([1.0, 0.0, 0.0], [0.0, -1.0, 0.0]),
+    ([-1.0, 0.0, 0.0], [0.0, -1.0, 0.0]),
+    ([0.0, 1.0, 0.0], [0.0, 0.0, 1.0]),
+    ([0.0, -1.0, 0.0], [0.0, 0.0, -1.0]),
+    ([0.0, 0.0, 1.0], [0.0, -1.0, 0.0]),
+    ([0.0, 0.0, -1.0], [0.0, -1.0, 0.0]),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ShadowMapResolution {
+    Low = 0,
+    Medium,
+    High,
+}
+
+impl ShadowMapResolution {
+    pub fn all() -> [ShadowMapResolution; 3] {
+        use ShadowMapResolution::*;
+        [Low, Medium, High]
+    }
+
+    pub fn from_value(value: usize) -> Option<Self> {
+        use ShadowMapResolution::*;
+        match value {
+            0 => Some(Low),
+            1 => Some(Medium),
+            2 => Some(High),
+            _ => None,
+        }
+    }
+
+    pub fn size(self) -> u32 {
+        match self {
+            ShadowMapResolution::Low => 1024,
+            ShadowMapResolution::Medium => 2048,
+            ShadowMapResolution::High => 4096,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ShadowFilterMode {
+    Hard = 0,
+    PCF,
+    PCSS,
+}
+
+impl ShadowFilterMode {
+    pub fn all() -> [ShadowFilterMode; 3] {
+        use ShadowFilterMode::*;
+        [Hard, PCF, PCSS]
+    }
+
+    pub fn from_value(value: usize) -> Option<Self> {
+        use ShadowFilterMode::*;
+        match value {
+            0 => Some(Hard),
+            1 => Some(PCF),
+            2 => Some(PCSS),
+            _ => None,
+        }
+    }
+}
+
+//光照pass里采样shadow map用的参数
+#[derive(Clone, Copy, Debug)]
+pub struct ShadowFilter {
+    pub mode: ShadowFilterMode,
+    pub pcf_kernel_size: u32,
+    pub depth_bias: f32,
+    pub normal_bias: f32,
+}
+
+//vulkan的裁剪空间z在[0,1]
+#[rustfmt::skip]
+const OPENGL_TO_VULKAN_DEPTH: Matrix4<f32> = Matrix4::new(
+    1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, 0.5, 0.0,
+    0.0, 0.0, 0.5, 1.0,
+);
+
+#[derive(Clone, Copy, Debug)]
+pub struct ShadowCascades {
+    pub matrices: [Matrix4<f32>; MAX_SHADOW_CASCADES as usize],
+    //各级联在观察空间中的远平面距离
+    pub splits: [f32; MAX_SHADOW_CASCADES as usize],
+    //各级联一个texel对应的世界空间大小，用于法线偏移
+    pub texel_sizes: [f32; MAX_SHADOW_CASCADES as usize],
+    pub count: u32,
+}
+
+impl ShadowCascades {
+    //lambda为0时均匀划分，为1时对数划分
+    pub fn compute(
+        camera_view: Matrix4<f32>,
+        projection: Projection,
+        aspect: f32,
+        (z_near, z_far): (f32, f32),
+        light_direction: Vector3<f32>,
+        count: u32,
+        lambda: f32,
+        shadow_map_size: u32,
+    ) -> Self {
+        let count = count.clamp(1, MAX_SHADOW_CASCADES);
+        let inverted_view = camera_view.invert().unwrap();
+        let light_direction = light_direction.normalize();
+
+        let mut cascades = Self::disabled();
+        cascades.count = count;
+
+        let mut split_near = z_near;
+        for i in 0..count as usize {
+            let p = (i + 1) as f32 / count as f32;
+            let log_split = z_near * (z_far / z_near).powf(p);
+            let uniform_split = z_near + (z_far - z_near) * p;
+            let split_far = lambda * log_split + (1.0 - lambda) * uniform_split;
+
+            let (matrix, texel_size) = cascade_matrix(
+                inverted_view,
+                projection,
+                aspect,
+                split_near,
+                split_far,
+                light_direction,
+                shadow_map_size,
+            );
+            cascades.matrices[i] = matrix;
+            cascades.texel_sizes[i] = texel_size;
+            cascades.splits[i] = split_far;
+            split_near = split_far;
+        }
+
+        cascades
+    }
+
+    //级联数为0时shader不采样shadow map
+    pub fn disabled() -> Self {
+        Self {
+            matrices: [Matrix4::identity(); MAX_SHADOW_CASCADES as usize],
+            splits: [Z_FAR; MAX_SHADOW_CASCADES as usize],
+            texel_sizes: [0.0; MAX_SHADOW_CASCADES as usize],
+            count: 0,
+        }
+    }
+}
+
+//点光源和聚光灯的阴影，所有灯共用一张shadow map数组
+//聚光灯占一层透视投影，点光源按cube的六个面占六层，层数不够时后面的灯不投射阴影
+#[derive(Clone, Debug)]
+pub struct PunctualShadows {
+    pub matrices: [Matrix4<f32>; MAX_PUNCTUAL_SHADOW_LAYERS as usize],
+    pub layer_count: u32,
+    //每个模型里投射阴影的灯：(灯在light_nodes中的序号, 第一层的索引)
+    pub lights: Vec<Vec<(usize, u32)>>,
+}
+
+impl PunctualShadows {
+    //models按模型顺序给出每个模型的灯光节点变换和灯光
+    pub fn compute<I>(models: I, max_lights: u32) -> Self
+    where
+        I: IntoIterator<Item = Vec<(Matrix4<f32>, Light)>>,
+    {
+        let mut shadows = Self::disabled(0);
+        let mut light_count = 0;
+
+        for lights in models {
+            let mut model_lights = Vec::new();
+            for (index, (transform, light)) in lights.into_iter().enumerate() {
+                if light_count >= max_lights.min(MAX_SHADOW_CASTING_LIGHTS) {
+                    break;
+                }
+                let matrices = punctual_shadow_matrices(transform, light);
+                let first_layer = shadows.layer_count;
+                if matrices.is_empty()
+                    || first_layer as usize + matrices.len() > MAX_PUNCTUAL_SHADOW_LAYERS as usize
+                {
+                    continue;
+                }
+                for (offset, matrix) in matrices.into_iter().enumerate() {
+                    shadows.matrices[first_layer as usize + offset] = matrix;
+                    shadows.layer_count += 1;
+                }
+                model_lights.push((index, first_layer));
+                light_count += 1;
+            }
+            shadows.lights.push(model_lights);
+        }
+
+        shadows
+    }
+
+    pub fn disabled(model_count: usize) -> Self {
+        Self {
+            matrices: [Matrix4::identity(); MAX_PUNCTUAL_SHADOW_LAYERS as usize],
+            layer_count: 0,
+            lights: vec![Vec::new(); model_count],
+        }
+    }
+
+    //第model_index个模型中各灯光的第一层，不投射阴影的灯不在其中
+    pub fn model_lights(&self, model_index: usize) -> &[(usize, u32)] {
+        self.lights
+            .get(model_index)
+            .map(Vec::as_slice)
+            .unwrap_or(&[])
+    }
+}
+
+//和级联的正交投影一样不翻转Y，剔除的绕序与级联一致；方向光不在这里处理
+fn punctual_shadow_matrices(transform: Matrix4<f32>, light: Light) -> Vec<Matrix4<f32>> {
+    let position = Point3::new(transform.w.x, transform.w.y, transform.w.z);
+    let far = light
+        .range()
+        .unwrap_or(PUNCTUAL_SHADOW_DEFAULT_RANGE)
+        .max(PUNCTUAL_SHADOW_NEAR * 2.0);
+    let projection = |fov: Rad<f32>| {
+        let mut projection = rendering::math::perspective(fov, 1.0, PUNCTUAL_SHADOW_NEAR, far);
+        projection.y.y = -projection.y.y;
+        projection
+    };
+
+    match light.light_type() {
+        LightType::DirectionalLight => Vec::new(),
+        LightType::SpotLight {
+            outer_cone_angle, ..
+        } => {
+            let direction = (transform * Vector4::new(0.0, 0.0, -1.0, 0.0))
+                .truncate()
+                .normalize();
+            let up = if direction.y.abs() > 0.99 {
+                Vector3::unit_z()
+            } else {
+                Vector3::unit_y()
+            };
+            let fov = Rad((2.0 * outer_cone_angle).min(MAX_SPOT_SHADOW_FOV.to_radians()));
+            let view = Matrix4::look_to_rh(position, direction, up);
+            vec![projection(fov) * view]
+        }
+        LightType::PointLight => CUBE_FACES
+            .iter()
+            .map(|(direction, up)| {
+                let view =
+                    Matrix4::look_to_rh(position, Vector3::from(*direction), Vector3::from(*up));
+                projection(Deg(90.0).into()) * view
+            })
+            .collect(),
+    }
+}
+
+//级联矩阵之后是点光源和聚光灯各层的矩阵，shadow caster按层序号索引
+#[derive(Clone, Copy)]
+#[allow(dead_code)]
+pub struct ShadowUBO {
+    cascade_matrices: [Matrix4<f32>; MAX_SHADOW_CASCADES as usize],
+    punctual_matrices: [Matrix4<f32>; MAX_PUNCTUAL_SHADOW_LAYERS as usize],
+}
+
+impl ShadowUBO {
+    pub fn new(cascades: &ShadowCascades, punctual_shadows: &PunctualShadows) -> Self {
+        Self {
+            cascade_matrices: cascades.matrices,
+            punctual_matrices: punctual_shadows.matrices,
+        }
+    }
+}
+
+//用包围球拟合级联视锥，旋转相机时投影大小不变，再对齐到texel减少阴影边缘闪烁
+fn cascade_matrix(
+    inverted_view: Matrix4<f32>,
+    projection: Projection,
+    aspect: f32,
+    near: f32,
+    far: f32,
+    light_direction: Vector3<f32>,
+    shadow_map_size: u32,
+) -> (Matrix4<f32>, f32) {
+    let mut corners = Vec::with_capacity(8);
+    for distance in [near, far] {
+        let half_height = projection.half_height_at(distance);
+        let half_width = half_height * aspect;
+        for (x, y) in [(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)] {
+            let corner = Point3::new(x * half_width, y * half_height, -distance);
+            corners.push(inverted_view.transform_point(corner));
+        }
+    }
+
+    let center = corners
+        .iter()
+        .fold(Vector3::new(0.0, 0.0, 0.0), |sum, c| sum + c.to_vec())
+        / corners.len() as f32;
+    let center = Point3::from_vec(center);
+    let radius = corners
+        .iter()
+        .map(|c| (c - center).magnitude())
+        .fold(0.0_f32, f32::max);
+    let radius = (radius * 16.0).ceil() / 16.0;
+
+    let up = if light_direction.y.abs() > 0.99 {
+        Vector3::unit_z()
+    } else {
+        Vector3::unit_y()
+    };
+    let eye = center - light_direction * (radius + SHADOW_CASTER_DISTANCE);
+    let light_view = Matrix4::look_at_rh(eye, center, up);
+
+    let mut light_proj = OPENGL_TO_VULKAN_DEPTH
+        * rendering::math::ortho(
+            -radius,
+            radius,
+            -radius,
+            radius,
+            0.0,
+            2.0 * radius + SHADOW_CASTER_DISTANCE,
+        );
+
+    let shadow_matrix = light_proj * light_view;
+    let half_size = shadow_map_size as f32 * 0.5;
+    let origin = shadow_matrix * Vector4::new(0.0, 0.0, 0.0, 1.0) * half_size;
+    let offset_x = (origin.x.round() - origin.x) / half_size;
+    let offset_y = (origin.y.round() - origin.y) / half_size;
+    light_proj.w.x += offset_x;
+    light_proj.w.y += offset_y;
+
+    (
+        light_proj * light_view,
+        2.0 * radius / shadow_map_size as f32,
+    )
+}
@@ -0,0 +1,515 @@
+use super::{
+    attachments::{
+        create_dummy_shadow_map, GBUFFER_MOTION_FORMAT, GBUFFER_NORMALS_FORMAT,
+        GBUFFER_PICK_ID_FORMAT, MAX_SHADOW_CASCADES, SCENE_COLOR_FORMAT,
+    },
+    create_renderer_pipeline, depth_compare_op,
+    shadow::{ShadowCascades, ShadowFilter},
+    RendererPipelineParameters,
+};
+use ash::{vk, Device};
+use rendering::cgmath::Matrix4;
+use std::{mem::size_of, sync::Arc};
+use vulkan::*;
+
+pub const DEFAULT_SHADOW_CATCHER_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 0.6];
+pub const MAX_SHADOW_CATCHER_SIZE: f32 = 1000.0;
+const QUAD_VERTEX_COUNT: u32 = 6;
+
+const CAMERA_UBO_BINDING: u32 = 0;
+const SHADOW_CATCHER_UBO_BINDING: u32 = 1;
+const SHADOW_MAP_SAMPLER_BINDING: u32 = 2;
+const AO_MAP_SAMPLER_BINDING: u32 = 3;
+
+//只接收阴影和AO的地面，平面本身透明，不写入材质数据，也不会出现在IBL反射里
+pub struct ShadowCatcherRenderer {
+    context: Arc<Context>,
+    dummy_texture: Texture,
+    dummy_shadow_map: Texture,
+    uniform_buffers: Vec<Buffer>,
+    descriptors: Descriptors,
+    pipeline_layout: vk::PipelineLayout,
+    gbuffer_pipeline: vk::Pipeline,
+    pipeline: vk::Pipeline,
+}
+
+#[derive(Copy, Clone, Debug)]
+#[repr(C)]
+pub struct ShadowCatcherUBO {
+    cascade_matrices: [Matrix4<f32>; MAX_SHADOW_CASCADES as usize],
+    cascade_splits: [f32; MAX_SHADOW_CASCADES as usize],
+    cascade_texel_sizes: [f32; MAX_SHADOW_CASCADES as usize],
+    light_direction: [f32; 4],
+    color: [f32; 4],
+    //xz为中心，y为高度，w为半边长
+    plane: [f32; 4],
+    cascade_count: u32,
+    shadow_depth_bias: f32,
+    shadow_normal_bias: f32,
+    shadow_pcf_kernel_size: u32,
+}
+
+impl ShadowCatcherUBO {
+    pub fn new(
+        cascades: &ShadowCascades,
+        shadow_filter: &ShadowFilter,
+        light_direction: [f32; 4],
+        color: [f32; 4],
+        plane: [f32; 4],
+    ) -> Self {
+        Self {
+            cascade_matrices: cascades.matrices,
+            cascade_splits: cascades.splits,
+            cascade_texel_sizes: cascades.texel_sizes,
+            light_direction,
+            color,
+            plane,
+            cascade_count: cascades.count,
+            shadow_depth_bias: shadow_filter.depth_bias,
+            shadow_normal_bias: shadow_filter.normal_bias,
+            shadow_pcf_kernel_size: shadow_filter.pcf_kernel_size,
+        }
+    }
+}
+
+impl ShadowCatcherRenderer {
+    pub fn create(
+        context: Arc<Context>,
+        camera_buffers: &[Buffer],
+        ao_map: Option<&Texture>,
+        shadow_map: Option<&Texture>,
+        msaa_samples: vk::SampleCountFlags,
+        depth_format: vk::Format,
+        reverse_z: bool,
+    ) -> Self {
+        let dummy_texture = Texture::from_rgba(
+            &context,
+            1,
+            1,
+            &[std::u8::MAX; 4],
+            true,
+            std::ffi::CString::new("ShadowCatcher.DefaultTexture").unwrap(),
+        )
+        .expect("创建默认纹理失败！");
+        let dummy_shadow_map = create_dummy_shadow_map(&context);
+
+        let uniform_buffers = create_uniform_buffers(&context, camera_buffers.len() as _);
+        let descriptors = create_descriptors(
+            &context,
+            camera_buffers,
+            &uniform_buffers,
+            ao_map.unwrap_or(&dummy_texture),
+            shadow_map.unwrap_or(&dummy_shadow_map),
+        );
+        let pipeline_layout = create_pipeline_layout(context.device(), descriptors.layout());
+        let gbuffer_pipeline =
+            create_gbuffer_pipeline(&context, depth_format, reverse_z, pipeline_layout);
+        let pipeline = create_pipeline(
+            &context,
+            msaa_samples,
+            depth_format,
+            reverse_z,
+            pipeline_layout,
+        );
+
+        Self {
+            context,
+            dummy_texture,
+            dummy_shadow_map,
+            uniform_buffers,
+            descriptors,
+            pipeline_layout,
+            gbuffer_pipeline,
+            pipeline,
+        }
+    }
+
+    //关闭的pass对应的贴图绑定为默认贴图
+    pub fn set_maps(&mut self, ao_map: Option<&Texture>, shadow_map: Option<&Texture>) {
+        for set in self.descriptors.sets() {
+            update_map_descriptors(
+                self.context.device(),
+                *set,
+                ao_map.unwrap_or(&self.dummy_texture),
+                shadow_map.unwrap_or(&self.dummy_shadow_map),
+            );
+        }
+    }
+
+    pub fn update(&mut self, frame_index: usize, ubo: ShadowCatcherUBO) {
+        let buffer = &mut self.uniform_buffers[frame_index];
+        unsafe {
+            let data_ptr = buffer.map_memory();
+            mem_copy(data_ptr, &[ubo]);
+        }
+    }
+}
+
+impl ShadowCatcherRenderer {
+    //写入法线和深度，SSAO才能算出物体与地面接触处的遮蔽
+    pub fn cmd_draw_gbuffer(&self, command_buffer: vk::CommandBuffer, frame_index: usize) {
+        self.cmd_draw_quad(command_buffer, frame_index, self.gbuffer_pipeline);
+    }
+
+    pub fn cmd_draw(&self, command_buffer: vk::CommandBuffer, frame_index: usize) {
+        self.cmd_draw_quad(command_buffer, frame_index, self.pipeline);
+    }
+
+    fn cmd_draw_quad(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        frame_index: usize,
+        pipeline: vk::Pipeline,
+    ) {
+        let device = self.context.device();
+        unsafe {
+            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline)
+        };
+
+        unsafe {
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &self.descriptors.sets()[frame_index..=frame_index],
+                &[],
+            )
+        };
+
+        //顶点由shader根据gl_VertexIndex生成
+        unsafe { device.cmd_draw(command_buffer, QUAD_VERTEX_COUNT, 1, 0, 0) };
+    }
+}
+
+impl Drop for ShadowCatcherRenderer {
+    fn drop(&mut self) {
+        let device = self.context.device();
+        unsafe {
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline(self.gbuffer_pipeline, None);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+    }
+}
+
+fn create_uniform_buffers(context: &Arc<Context>, count: u32) -> Vec<Buffer> {
+    (0..count)
+        .map(|i| {
+            let mut buffer = Buffer::create(
+                Arc::clone(context),
+                size_of::<ShadowCatcherUBO>() as _,
+                vk::BufferUsageFlags::UNIFORM_BUFFER,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                &format!("ShadowCatcher.UBO[{}]", i),
+            );
+            buffer.map_memory();
+            buffer
+        })
+        .collect::<Vec<_>>()
+}
+
+fn create_descriptors(
+    context: &Arc<Context>,
+    camera_buffers: &[Buffer],
+    uniform_buffers: &[Buffer],
+    ao_map: &Texture,
+    shadow_map: &Texture,
+) -> Descriptors {
+    let layout = create_descriptor_set_layout(context.device());
+    let pool = create_descriptor_pool(context.device(), camera_buffers.len() as _);
+    let sets = create_descriptor_sets(context, pool, layout, camera_buffers, uniform_buffers);
+    for set in sets.iter() {
+        update_map_descriptors(context.device(), *set, ao_map, shadow_map);
+    }
+    Descriptors::new(Arc::clone(context), layout, pool, sets)
+}
+
+fn create_descriptor_set_layout(device: &Device) -> vk::DescriptorSetLayout {
+    let bindings = [
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(CAMERA_UBO_BINDING)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
+            .build(),
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(SHADOW_CATCHER_UBO_BINDING)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
+            .build(),
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(SHADOW_MAP_SAMPLER_BINDING)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .build(),
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(AO_MAP_SAMPLER_BINDING)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .build(),
+    ];
+
+    let layout_info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+
+    unsafe {
+        device
+            .create_descriptor_set_layout(&layout_info, None)
+            .unwrap()
+    }
+}
+
+fn create_descriptor_pool(device: &Device, set_count: u32) -> vk::DescriptorPool {
+    let pool_sizes = [
+        vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::UNIFORM_BUFFER,
+            descriptor_count: set_count * 2,
+        },
+        vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: set_count * 2,
+        },
+    ];
+
+    let create_info = vk::DescriptorPoolCreateInfo::builder()
+        .pool_sizes(&pool_sizes)
+        .max_sets(set_count);
+
+    unsafe { device.create_descriptor_pool(&create_info, None).unwrap() }
+}
+
+fn create_descriptor_sets(
+    context: &Arc<Context>,
+    pool: vk::DescriptorPool,
+    layout: vk::DescriptorSetLayout,
+    camera_buffers: &[Buffer],
+    uniform_buffers: &[Buffer],
+) -> Vec<vk::DescriptorSet> {
+    let layouts = (0..camera_buffers.len())
+        .map(|_| layout)
+        .collect::<Vec<_>>();
+
+    let allocate_info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(pool)
+        .set_layouts(&layouts);
+    let sets = unsafe {
+        context
+            .device()
+            .allocate_descriptor_sets(&allocate_info)
+            .unwrap()
+    };
+    for (i, set) in sets.iter().enumerate() {
+        debug_name!(context, *set, "ShadowCatcher.DescriptorSet[{}]", i);
+    }
+
+    sets.iter()
+        .zip(camera_buffers.iter().zip(uniform_buffers.iter()))
+        .for_each(|(set, (camera_buffer, uniform_buffer))| {
+            let camera_buffer_info = [vk::DescriptorBufferInfo::builder()
+                .buffer(camera_buffer.buffer)
+                .offset(0)
+                .range(vk::WHOLE_SIZE)
+                .build()];
+
+            let uniform_buffer_info = [vk::DescriptorBufferInfo::builder()
+                .buffer(uniform_buffer.buffer)
+                .offset(0)
+                .range(vk::WHOLE_SIZE)
+                .build()];
+
+            let descriptor_writes = [
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(*set)
+                    .dst_binding(CAMERA_UBO_BINDING)
+                    .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                    .buffer_info(&camera_buffer_info)
+                    .build(),
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(*set)
+                    .dst_binding(SHADOW_CATCHER_UBO_BINDING)
+                    .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                    .buffer_info(&uniform_buffer_info)
+                    .build(),
+            ];
+
+            unsafe {
+                context
+                    .device()
+                    .update_descriptor_sets(&descriptor_writes, &[])
+            }
+        });
+
+    sets
+}
+
+fn update_map_descriptors(
+    device: &Device,
+    set: vk::DescriptorSet,
+    ao_map: &Texture,
+    shadow_map: &Texture,
+) {
+    let shadow_map_info = [vk::DescriptorImageInfo::builder()
+        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        .image_view(shadow_map.view)
+        .sampler(shadow_map.sampler.expect("shadowmap没有sampler"))
+        .build()];
+
+    let ao_map_info = [vk::DescriptorImageInfo::builder()
+        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        .image_view(ao_map.view)
+        .sampler(ao_map.sampler.unwrap())
+        .build()];
+
+    let descriptor_writes = [
+        vk::WriteDescriptorSet::builder()
+            .dst_set(set)
+            .dst_binding(SHADOW_MAP_SAMPLER_BINDING)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&shadow_map_info)
+            .build(),
+        vk::WriteDescriptorSet::builder()
+            .dst_set(set)
+            .dst_binding(AO_MAP_SAMPLER_BINDING)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&ao_map_info)
+            .build(),
+    ];
+
+    unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) }
+}
+
+fn create_pipeline_layout(
+    device: &Device,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+) -> vk::PipelineLayout {
+    let layouts = [descriptor_set_layout];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder().set_layouts(&layouts);
+    unsafe { device.create_pipeline_layout(&layout_info, None).unwrap() }
+}
+
+fn create_gbuffer_pipeline(
+    context: &Arc<Context>,
+    depth_format: vk::Format,
+    reverse_z: bool,
+    layout: vk::PipelineLayout,
+) -> vk::Pipeline {
+    let depth_stencil_info = vk::PipelineDepthStencilStateCreateInfo::builder()
+        .depth_test_enable(true)
+        .depth_write_enable(true)
+        .depth_compare_op(depth_compare_op(reverse_z))
+        .depth_bounds_test_enable(false)
+        .min_depth_bounds(0.0)
+        .max_depth_bounds(1.0)
+        .stencil_test_enable(false)
+        .front(Default::default())
+        .back(Default::default());
+
+    let color_blend_attachments = [
+        vk::PipelineColorBlendAttachmentState::builder()
+            .color_write_mask(
+                vk::ColorComponentFlags::R
+                    | vk::ColorComponentFlags::G
+                    | vk::ColorComponentFlags::B
+                    | vk::ColorComponentFlags::A,
+            )
+            .blend_enable(false)
+            .build(),
+        //整数格式不支持混合
+        vk::PipelineColorBlendAttachmentState::builder()
+            .color_write_mask(vk::ColorComponentFlags::R)
+            .blend_enable(false)
+            .build(),
+        vk::PipelineColorBlendAttachmentState::builder()
+            .color_write_mask(vk::ColorComponentFlags::R | vk::ColorComponentFlags::G)
+            .blend_enable(false)
+            .build(),
+    ];
+
+    create_renderer_pipeline::<()>(
+        context,
+        RendererPipelineParameters {
+            name: "ShadowCatcher.GBuffer.Pipeline",
+            vertex_shader_name: "shadow_catcher",
+            fragment_shader_name: "shadow_catcher_gbuffer",
+            vertex_shader_specialization: None,
+            fragment_shader_specialization: None,
+            multisampling: MultisampleParameters::default(),
+            color_attachment_formats: &[
+                GBUFFER_NORMALS_FORMAT,
+                GBUFFER_PICK_ID_FORMAT,
+                GBUFFER_MOTION_FORMAT,
+            ],
+            depth_attachment_format: Some(depth_format),
+            layout,
+            depth_stencil_info: &depth_stencil_info,
+            color_blend_attachments: &color_blend_attachments,
+            enable_face_culling: false,
+            front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+            polygon_mode: vk::PolygonMode::FILL,
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            parent: None,
+        },
+    )
+}
+
+fn create_pipeline(
+    context: &Arc<Context>,
+    msaa_samples: vk::SampleCountFlags,
+    depth_format: vk::Format,
+    reverse_z: bool,
+    layout: vk::PipelineLayout,
+) -> vk::Pipeline {
+    //只做深度测试不写深度，之后画的网格不会被地面挡住
+    let depth_stencil_info = vk::PipelineDepthStencilStateCreateInfo::builder()
+        .depth_test_enable(true)
+        .depth_write_enable(false)
+        .depth_compare_op(depth_compare_op(reverse_z))
+        .depth_bounds_test_enable(false)
+        .min_depth_bounds(0.0)
+        .max_depth_bounds(1.0)
+        .stencil_test_enable(false)
+        .front(Default::default())
+        .back(Default::default());
+
+    //按alpha压暗背景，背景的alpha保持不变
+    let color_blend_attachments = [vk::PipelineColorBlendAttachmentState::builder()
+        .color_write_mask(
+            vk::ColorComponentFlags::R
+                | vk::ColorComponentFlags::G
+                | vk::ColorComponentFlags::B
+                | vk::ColorComponentFlags::A,
+        )
+        .blend_enable(true)
+        .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+        .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+        .color_blend_op(vk::BlendOp::ADD)
+        .src_alpha_blend_factor(vk::BlendFactor::ZERO)
+        .dst_alpha_blend_factor(vk::BlendFactor::ONE)
+        .alpha_blend_op(vk::BlendOp::ADD)
+        .build()];
+
+    create_renderer_pipeline::<()>(
+        context,
+        RendererPipelineParameters {
+            name: "ShadowCatcher.Pipeline",
+            vertex_shader_name: "shadow_catcher",
+            fragment_shader_name: "shadow_catcher",
+            vertex_shader_specialization: None,
+            fragment_shader_specialization: None,
+            multisampling: MultisampleParameters::new(msaa_samples),
+            color_attachment_formats: &[SCENE_COLOR_FORMAT],
+            depth_attachment_format: Some(depth_format),
+            layout,
+            depth_stencil_info: &depth_stencil_info,
+            color_blend_attachments: &color_blend_attachments,
+            enable_face_culling: false,
+            front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+            polygon_mode: vk::PolygonMode::FILL,
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            parent: None,
+        },
+    )
+}
@@ -2,10 +2,19 @@ use super::{
     attachments::SCENE_COLOR_FORMAT, create_renderer_pipeline, RendererPipelineParameters,
 };
 use ash::{vk, Device};
-use rendering::environment::{Environment, SkyboxModel, SkyboxVertex};
-use std::sync::Arc;
+use rendering::environment::{Environment, SkyboxModel, SkyboxVertex, PRE_FILTERED_MAP_SIZE};
+use rendering::util::any_as_u8_slice;
+use std::{mem::size_of, sync::Arc};
 use vulkan::*;
 
+#[derive(Clone, Copy)]
+#[allow(dead_code)]
+struct SkyboxConstants {
+    exposure: f32,
+    //预滤波贴图的mip层级，0表示直接采样原始天空盒
+    lod: f32,
+}
+
 pub struct SkyboxRenderer {
     context: Arc<Context>,
     model: SkyboxModel,
@@ -39,7 +48,17 @@ impl SkyboxRenderer {
 }
 
 impl SkyboxRenderer {
-    pub fn cmd_draw(&self, command_buffer: vk::CommandBuffer, frame_index: usize) {
+    pub fn set_environment(&mut self, camera_buffers: &[Buffer], environment: &Environment) {
+        self.descriptors = create_descriptors(&self.context, camera_buffers, environment);
+    }
+
+    pub fn cmd_draw(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        frame_index: usize,
+        exposure: f32,
+        blur: f32,
+    ) {
         let device = self.context.device();
         // Bind skybox pipeline
         unsafe {
@@ -62,6 +81,21 @@ impl SkyboxRenderer {
             )
         };
 
+        let max_lod = (PRE_FILTERED_MAP_SIZE as f32).log2().floor();
+        let constants = SkyboxConstants {
+            exposure,
+            lod: blur.clamp(0.0, 1.0) * max_lod,
+        };
+        unsafe {
+            device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                0,
+                any_as_u8_slice(&constants),
+            );
+        }
+
         unsafe {
             device.cmd_bind_vertex_buffers(
                 command_buffer,
@@ -102,7 +136,7 @@ fn create_descriptors(
 ) -> Descriptors {
     let layout = create_descriptor_set_layout(context.device());
     let pool = create_descriptor_pool(context.device(), uniform_buffers.len() as _);
-    let sets = create_descriptor_sets(context, pool, layout, uniform_buffers, environment.skybox());
+    let sets = create_descriptor_sets(context, pool, layout, uniform_buffers, environment);
     Descriptors::new(Arc::clone(context), layout, pool, sets)
 }
 
@@ -120,6 +154,12 @@ fn create_descriptor_set_layout(device: &Device) -> vk::DescriptorSetLayout {
             .descriptor_count(1)
             .stage_flags(vk::ShaderStageFlags::FRAGMENT)
             .build(),
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(2)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .build(),
     ];
 
     let layout_info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
@@ -138,7 +178,7 @@ fn create_descriptor_pool(device: &Device, descriptor_count: u32) -> vk::Descrip
         },
         vk::DescriptorPoolSize {
             ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
-            descriptor_count,
+            descriptor_count: descriptor_count * 2,
         },
     ];
 
@@ -154,8 +194,10 @@ fn create_descriptor_sets(
     pool: vk::DescriptorPool,
     layout: vk::DescriptorSetLayout,
     buffers: &[Buffer],
-    cubemap: &Texture,
+    environment: &Environment,
 ) -> Vec<vk::DescriptorSet> {
+    let cubemap = environment.skybox();
+    let pre_filtered = environment.pre_filtered();
     let layouts = (0..buffers.len()).map(|_| layout).collect::<Vec<_>>();
 
     let allocate_info = vk::DescriptorSetAllocateInfo::builder()
@@ -167,6 +209,9 @@ fn create_descriptor_sets(
             .allocate_descriptor_sets(&allocate_info)
             .unwrap()
     };
+    for (i, set) in sets.iter().enumerate() {
+        debug_name!(context, *set, "Skybox.DescriptorSet[{}]", i);
+    }
 
     sets.iter().zip(buffers.iter()).for_each(|(set, buffer)| {
         let buffer_info = [vk::DescriptorBufferInfo::builder()
@@ -181,6 +226,12 @@ fn create_descriptor_sets(
             .sampler(cubemap.sampler.unwrap())
             .build()];
 
+        let pre_filtered_info = [vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(pre_filtered.view)
+            .sampler(pre_filtered.sampler.unwrap())
+            .build()];
+
         let descriptor_writes = [
             vk::WriteDescriptorSet::builder()
                 .dst_set(*set)
@@ -194,6 +245,12 @@ fn create_descriptor_sets(
                 .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
                 .image_info(&cubemap_info)
                 .build(),
+            vk::WriteDescriptorSet::builder()
+                .dst_set(*set)
+                .dst_binding(2)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(&pre_filtered_info)
+                .build(),
         ];
 
         unsafe {
@@ -211,7 +268,14 @@ fn create_pipeline_layout(
     descriptor_set_layout: vk::DescriptorSetLayout,
 ) -> vk::PipelineLayout {
     let layouts = [descriptor_set_layout];
-    let layout_info = vk::PipelineLayoutCreateInfo::builder().set_layouts(&layouts);
+    let push_constant_ranges = [vk::PushConstantRange {
+        stage_flags: vk::ShaderStageFlags::FRAGMENT,
+        offset: 0,
+        size: size_of::<SkyboxConstants>() as _,
+    }];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(&layouts)
+        .push_constant_ranges(&push_constant_ranges);
     unsafe { device.create_pipeline_layout(&layout_info, None).unwrap() }
 }
 
@@ -251,17 +315,21 @@ fn create_skybox_pipeline(
     create_renderer_pipeline::<SkyboxVertex>(
         context,
         RendererPipelineParameters {
+            name: "Skybox.Pipeline",
             vertex_shader_name: "skybox",
             fragment_shader_name: "skybox",
             vertex_shader_specialization: None,
             fragment_shader_specialization: None,
-            msaa_samples,
+            multisampling: MultisampleParameters::new(msaa_samples),
             color_attachment_formats: &[SCENE_COLOR_FORMAT],
             depth_attachment_format: Some(depth_format),
             layout,
             depth_stencil_info: &depth_stencil_info,
             color_blend_attachments: &color_blend_attachments,
             enable_face_culling: true,
+            front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+            polygon_mode: vk::PolygonMode::FILL,
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
             parent: None,
         },
     )
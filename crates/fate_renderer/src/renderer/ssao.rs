@@ -9,9 +9,11 @@ use rendering::{
 };
 use std::mem::size_of;
 use std::sync::Arc;
-use vulkan::ash::vk::{RenderingAttachmentInfo, RenderingInfo};
 use vulkan::ash::{vk, Device};
-use vulkan::{create_device_local_buffer_with_data, Buffer, Context, SamplerParameters, Texture};
+use vulkan::{
+    cmd_set_viewport_and_scissor, create_device_local_buffer_with_data, debug_name, Buffer,
+    Context, MultisampleParameters, RenderingAttachment, RenderingDesc, SamplerParameters, Texture,
+};
 
 const AO_MAP_FORMAT: vk::Format = vk::Format::R8_UNORM;
 
@@ -36,12 +38,14 @@ pub struct SSAOPass {
     kernel_size: u32,
     ssao_radius: f32,
     ssao_strength: f32,
+    ssao_range_check: bool,
 }
 
 #[allow(dead_code)]
 struct ConfigUniform {
     ssao_radius: f32,
     ssao_strength: f32,
+    ssao_range_check: u32,
 }
 
 impl SSAOPass {
@@ -81,6 +85,7 @@ impl SSAOPass {
                 }),
                 std::ffi::CString::new("Noise Texture").unwrap(),
             )
+            .expect("创建SSAO噪声纹理失败！")
         };
 
         let descriptors = create_descriptors(
@@ -104,6 +109,7 @@ impl SSAOPass {
             kernel_size: settings.ssao_kernel_size,
             ssao_radius: settings.ssao_radius,
             ssao_strength: settings.ssao_strength,
+            ssao_range_check: settings.ssao_range_check_enabled,
         }
     }
 }
@@ -127,6 +133,7 @@ fn create_kernel_buffer(context: &Arc<Context>, kernel_size: u32) -> Buffer {
         context,
         vk::BufferUsageFlags::UNIFORM_BUFFER,
         &kernel,
+        "SSAO.Kernel",
     )
 }
 
@@ -160,6 +167,10 @@ impl SSAOPass {
         self.ssao_strength = strength;
     }
 
+    pub fn set_ssao_range_check(&mut self, enabled: bool) {
+        self.ssao_range_check = enabled;
+    }
+
     pub fn rebuild_pipelines(&mut self) {
         let device = self.context.device();
 
@@ -184,52 +195,24 @@ impl SSAOPass {
             height: attachments.ssao.image.extent.height,
         };
 
-        unsafe {
-            self.context.device().cmd_set_viewport(
-                command_buffer,
-                0,
-                &[vk::Viewport {
-                    width: extent.width as _,
-                    height: extent.height as _,
-                    max_depth: 1.0,
-                    ..Default::default()
-                }],
-            );
-            self.context.device().cmd_set_scissor(
-                command_buffer,
-                0,
-                &[vk::Rect2D {
-                    extent,
-                    ..Default::default()
-                }],
-            )
-        }
+        cmd_set_viewport_and_scissor(self.context.device(), command_buffer, extent);
 
         {
-            let attachment_info = RenderingAttachmentInfo::builder()
-                .clear_value(vk::ClearValue {
-                    color: vk::ClearColorValue {
-                        float32: [0.0, 0.0, 0.0, 1.0],
-                    },
-                })
-                .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
-                .image_view(attachments.ssao.view)
-                .load_op(vk::AttachmentLoadOp::CLEAR)
-                .store_op(vk::AttachmentStoreOp::STORE);
-
-            let rendering_info = RenderingInfo::builder()
-                .color_attachments(std::slice::from_ref(&attachment_info))
-                .layer_count(1)
-                .render_area(vk::Rect2D {
-                    extent,
-                    ..Default::default()
-                });
+            let attachment = RenderingAttachment::new(
+                &attachments.ssao.image,
+                attachments.ssao.view,
+                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            )
+            .clear_color([0.0, 0.0, 0.0, 1.0]);
 
-            unsafe {
-                self.context
-                    .dynamic_rendering()
-                    .cmd_begin_rendering(command_buffer, &rendering_info)
-            };
+            self.context.cmd_begin_rendering(
+                command_buffer,
+                &RenderingDesc {
+                    extent,
+                    color_attachments: std::slice::from_ref(&attachment),
+                    depth_attachment: None,
+                },
+            );
         }
 
         // Bind pipeline
@@ -293,6 +276,7 @@ impl SSAOPass {
             let config = ConfigUniform {
                 ssao_radius: self.ssao_radius,
                 ssao_strength: self.ssao_strength,
+                ssao_range_check: self.ssao_range_check as u32,
             };
             let data = any_as_u8_slice(&config);
 
@@ -308,11 +292,7 @@ impl SSAOPass {
         // Draw
         unsafe { device.cmd_draw_indexed(command_buffer, 6, 1, 0, 0, 1) };
 
-        unsafe {
-            self.context
-                .dynamic_rendering()
-                .cmd_end_rendering(command_buffer)
-        };
+        self.context.cmd_end_rendering(command_buffer);
     }
 }
 
@@ -459,6 +439,7 @@ fn create_static_set(
             .allocate_descriptor_sets(&allocate_info)
             .unwrap()[0]
     };
+    debug_name!(context, set, "SSAO.Static.DescriptorSet");
 
     update_static_set(context, set, normals, depth, noise_texture);
 
@@ -552,6 +533,7 @@ fn create_dynamic_set(
             .allocate_descriptor_sets(&allocate_info)
             .unwrap()[0]
     };
+    debug_name!(context, set, "SSAO.Dynamic.DescriptorSet");
 
     update_dynamic_set(context, set, kernel_buffer);
 
@@ -614,6 +596,9 @@ fn create_per_frame_sets(
             .allocate_descriptor_sets(&allocate_info)
             .unwrap()
     };
+    for (i, set) in sets.iter().enumerate() {
+        debug_name!(context, *set, "SSAO.PerFrame.DescriptorSet[{}]", i);
+    }
 
     sets.iter()
         .zip(camera_buffers.iter())
@@ -698,17 +683,21 @@ fn create_pipeline(
     create_renderer_pipeline::<QuadVertex>(
         context,
         RendererPipelineParameters {
+            name: "SSAO.Pipeline",
             vertex_shader_name: "ssao",
             fragment_shader_name: "ssao",
             vertex_shader_specialization: None,
             fragment_shader_specialization: Some(&specialization_info),
-            msaa_samples: vk::SampleCountFlags::TYPE_1,
+            multisampling: MultisampleParameters::default(),
             color_attachment_formats: &[AO_MAP_FORMAT],
             depth_attachment_format: None,
             layout,
             depth_stencil_info: &depth_stencil_info,
             color_blend_attachments: &color_blend_attachments,
             enable_face_culling: true,
+            front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+            polygon_mode: vk::PolygonMode::FILL,
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
             parent: None,
         },
     )
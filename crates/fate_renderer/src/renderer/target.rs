@@ -0,0 +1,209 @@
+use ash::vk;
+use std::{ffi::CString, sync::Arc};
+use vulkan::*;
+
+//和交换链一样是UNORM格式，gamma校正在shader里做
+pub const OFFSCREEN_COLOR_FORMAT: vk::Format = vk::Format::R8G8B8A8_UNORM;
+
+//最终画面的输出位置，有窗口时为交换链，无窗口时为离屏图像
+pub enum RenderTarget {
+    Swapchain(Swapchain),
+    Offscreen(OffscreenTarget),
+}
+
+impl RenderTarget {
+    pub fn image_count(&self) -> usize {
+        match self {
+            RenderTarget::Swapchain(swapchain) => swapchain.image_count(),
+            RenderTarget::Offscreen(_) => 1,
+        }
+    }
+
+    pub fn extent(&self) -> vk::Extent2D {
+        match self {
+            RenderTarget::Swapchain(swapchain) => swapchain.properties().extent,
+            RenderTarget::Offscreen(offscreen) => offscreen.extent(),
+        }
+    }
+
+    pub fn format(&self) -> vk::Format {
+        match self {
+            RenderTarget::Swapchain(swapchain) => swapchain.properties().format.format,
+            RenderTarget::Offscreen(_) => OFFSCREEN_COLOR_FORMAT,
+        }
+    }
+
+    pub fn image(&self, index: usize) -> &Image {
+        match self {
+            RenderTarget::Swapchain(swapchain) => &swapchain.images()[index],
+            RenderTarget::Offscreen(offscreen) => &offscreen.color.image,
+        }
+    }
+
+    pub fn image_view(&self, index: usize) -> vk::ImageView {
+        match self {
+            RenderTarget::Swapchain(swapchain) => swapchain.image_views()[index],
+            RenderTarget::Offscreen(offscreen) => offscreen.color.view,
+        }
+    }
+
+    pub fn swapchain(&self) -> Option<&Swapchain> {
+        match self {
+            RenderTarget::Swapchain(swapchain) => Some(swapchain),
+            RenderTarget::Offscreen(_) => None,
+        }
+    }
+
+    pub fn offscreen_mut(&mut self) -> Option<&mut OffscreenTarget> {
+        match self {
+            RenderTarget::Swapchain(_) => None,
+            RenderTarget::Offscreen(offscreen) => Some(offscreen),
+        }
+    }
+
+    //交换链image转到呈现布局，离屏图像拷贝到回读buffer
+    pub fn cmd_finish(&self, command_buffer: vk::CommandBuffer, index: usize) {
+        match self {
+            RenderTarget::Swapchain(swapchain) => swapchain.images()[index]
+                .cmd_transition_image_layout(
+                    command_buffer,
+                    vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                    vk::ImageLayout::PRESENT_SRC_KHR,
+                ),
+            RenderTarget::Offscreen(offscreen) => {
+                offscreen.cmd_copy_to_readback_buffer(command_buffer)
+            }
+        }
+    }
+
+    //截图的那一帧代替cmd_finish，交换链image先拷到buffer再转到呈现布局
+    pub fn cmd_finish_with_copy(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        index: usize,
+        buffer: &Buffer,
+    ) {
+        let image = self.image(index);
+        image.cmd_transition_image_layout(
+            command_buffer,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        );
+        image.cmd_copy_to_buffer(command_buffer, buffer);
+        image.cmd_transition_image_layout(
+            command_buffer,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            vk::ImageLayout::PRESENT_SRC_KHR,
+        );
+    }
+
+    //交换链image且表面允许拷贝时才能截图
+    pub fn is_readable(&self) -> bool {
+        self.swapchain().is_some_and(Swapchain::is_readable)
+    }
+
+    pub fn destroy(&mut self) {
+        if let RenderTarget::Swapchain(swapchain) = self {
+            swapchain.destroy();
+        }
+    }
+}
+
+pub struct OffscreenTarget {
+    color: Texture,
+    readback_buffer: Buffer,
+}
+
+impl OffscreenTarget {
+    pub fn create(context: &Arc<Context>, extent: vk::Extent2D) -> Self {
+        let image = Image::create(
+            Arc::clone(context),
+            ImageParameters {
+                mem_properties: vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                extent,
+                format: OFFSCREEN_COLOR_FORMAT,
+                usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+                ..Default::default()
+            },
+            CString::new("Offscreen Color Texture").unwrap(),
+        )
+        .expect("创建离屏渲染image失败！");
+        let view = image.create_view(vk::ImageViewType::TYPE_2D, vk::ImageAspectFlags::COLOR);
+        let color = Texture::new(Arc::clone(context), image, view, None);
+
+        let readback_buffer = Buffer::create(
+            Arc::clone(context),
+            (extent.width * extent.height * 4) as _,
+            vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            "Offscreen.Readback",
+        );
+
+        Self {
+            color,
+            readback_buffer,
+        }
+    }
+}
+
+impl OffscreenTarget {
+    pub fn extent(&self) -> vk::Extent2D {
+        vk::Extent2D {
+            width: self.color.image.extent.width,
+            height: self.color.image.extent.height,
+        }
+    }
+
+    fn cmd_copy_to_readback_buffer(&self, command_buffer: vk::CommandBuffer) {
+        self.color.image.cmd_transition_image_layout(
+            command_buffer,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        );
+        self.color
+            .image
+            .cmd_copy_to_buffer(command_buffer, &self.readback_buffer);
+    }
+
+    //GPU执行完拷贝后调用，返回逐行排列的RGBA8像素
+    pub fn read_pixels(&mut self) -> Vec<u8> {
+        let size = self.readback_buffer.size as usize;
+        let data_ptr = self.readback_buffer.map_memory();
+        let pixels = unsafe { std::slice::from_raw_parts(data_ptr as *const u8, size) }.to_vec();
+        self.readback_buffer.unmap_memory();
+        pixels
+    }
+}
+
+//把读回的像素转成RGBA8，BGRA格式交换R和B，alpha固定为不透明
+pub fn pixels_to_rgba8(format: vk::Format, pixels: &mut [u8]) {
+    let swap_red_blue = matches!(
+        format,
+        vk::Format::B8G8R8A8_UNORM | vk::Format::B8G8R8A8_SRGB
+    );
+    for pixel in pixels.chunks_exact_mut(4) {
+        if swap_red_blue {
+            pixel.swap(0, 2);
+        }
+        pixel[3] = 255;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bgra_pixels_are_swizzled_to_rgba() {
+        let mut pixels = vec![10, 20, 30, 0, 40, 50, 60, 128];
+        pixels_to_rgba8(vk::Format::B8G8R8A8_UNORM, &mut pixels);
+        assert_eq!(pixels, [30, 20, 10, 255, 60, 50, 40, 255]);
+    }
+
+    #[test]
+    fn rgba_pixels_only_get_opaque_alpha() {
+        let mut pixels = vec![10, 20, 30, 0];
+        pixels_to_rgba8(OFFSCREEN_COLOR_FORMAT, &mut pixels);
+        assert_eq!(pixels, [10, 20, 30, 255]);
+    }
+}
@@ -5,6 +5,9 @@ use crate::{
     ray::Ray,
 };
 
+//包围盒每个轴的最小厚度
+const PAD_DELTA: f64 = 0.0001;
+
 #[derive(Default, Clone)]
 pub struct Aabb {
     pub x: Interval,
@@ -24,10 +27,10 @@ impl Aabb {
     pub fn append(&mut self, p: &Point3<f64>) {
         self.x.min = self.x.min.min(p.x);
         self.x.max = self.x.max.max(p.x);
-        self.y.min = self.y.min.min(p.x);
-        self.y.max = self.y.max.max(p.x);
-        self.z.min = self.z.min.min(p.x);
-        self.z.max = self.z.max.max(p.x);
+        self.y.min = self.y.min.min(p.y);
+        self.y.max = self.y.max.max(p.y);
+        self.z.min = self.z.min.min(p.z);
+        self.z.max = self.z.max.max(p.z);
         self.pad_to_minimums();
     }
 
@@ -60,27 +63,9 @@ impl Aabb {
     }
 
     pub fn pad(&self) -> Self {
-        let delta = 0.0001;
-        let new_x = if self.x.size() < delta {
-            self.x.expand(delta)
-        } else {
-            self.x.clone()
-        };
-        let new_y = if self.y.size() < delta {
-            self.y.expand(delta)
-        } else {
-            self.y.clone()
-        };
-        let new_z = if self.z.size() < delta {
-            self.z.expand(delta)
-        } else {
-            self.z.clone()
-        };
-        Self {
-            x: new_x,
-            y: new_y,
-            z: new_z,
-        }
+        let mut padded = self.clone();
+        padded.pad_to_minimums();
+        padded
     }
 
     pub fn axis(&self, n: usize) -> &Interval {
@@ -91,25 +76,35 @@ impl Aabb {
         }
     }
 
+    //平板法求交，ray_t收缩为光线在包围盒内的区间
+    //方向分量为0时1/d为无穷大，原点正好在平板边界上会得到0*inf=NaN，
+    //这种情况单独按原点是否在平板内判断
     pub fn hit(&self, r: &Ray, ray_t: &mut Interval) -> bool {
         for a in 0..3 {
-            let inv0 = 1.0 / r.direction()[a];
+            let ax = self.axis(a);
+            let dir = r.direction()[a];
             let orig = r.origin()[a];
 
-            let mut t0 = (self.axis(a).min - orig) * inv0;
-            let mut t1 = (self.axis(a).max - orig) * inv0;
-
-            if inv0 < 0.0 {
-                std::mem::swap(&mut t0, &mut t1);
+            //平行于平板的光线，原点不在平板内则永远不会相交
+            if dir == 0.0 {
+                if !ax.contains(orig) {
+                    return false;
+                }
+                continue;
             }
 
-            if t0 > ray_t.min {
-                ray_t.min = t0;
-            }
-            if t1 < ray_t.max {
-                ray_t.max = t1;
+            let inv0 = 1.0 / dir;
+            let t0 = (ax.min - orig) * inv0;
+            let t1 = (ax.max - orig) * inv0;
+
+            //包围盒本身非法（NaN或空区间）时不相交
+            if t0.is_nan() || t1.is_nan() {
+                return false;
             }
 
+            ray_t.min = ray_t.min.max(t0.min(t1));
+            ray_t.max = ray_t.max.min(t0.max(t1));
+
             if ray_t.max <= ray_t.min {
                 return false;
             }
@@ -131,16 +126,12 @@ impl Aabb {
         }
     }
 
+    //平面四边形等厚度为0的包围盒在该轴上补一点厚度，否则斜射的光线无法命中
     fn pad_to_minimums(&mut self) {
-        let delta = 0.0001;
-        if self.x.size() < delta {
-            self.x.expand(delta);
-        }
-        if self.y.size() < delta {
-            self.y.expand(delta);
-        }
-        if self.z.size() < delta {
-            self.z.expand(delta);
+        for ax in [&mut self.x, &mut self.y, &mut self.z] {
+            if ax.size() < PAD_DELTA {
+                *ax = ax.expand(PAD_DELTA);
+            }
         }
     }
 }
@@ -179,3 +170,58 @@ impl std::ops::Add<&Aabb> for Vector3<f64> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit_box() -> Aabb {
+        Aabb::new_with_point(&Point3::new(0.0, 0.0, 0.0), &Point3::new(1.0, 1.0, 1.0))
+    }
+
+    #[test]
+    fn axis_parallel_ray_on_slab_boundary_hits() {
+        //原点在x=0的边界上且x方向为0，旧实现得到0*inf=NaN
+        let ray = Ray::new(Point3::new(0.0, 0.5, -1.0), Vector3::new(0.0, 0.0, 1.0));
+        let mut ray_t = interval::UNIVERSE;
+        assert!(unit_box().hit(&ray, &mut ray_t));
+        assert!((ray_t.min - 1.0).abs() < 1e-12);
+        assert!((ray_t.max - 2.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn axis_parallel_ray_outside_slab_misses() {
+        let ray = Ray::new(Point3::new(2.0, 0.5, -1.0), Vector3::new(0.0, 0.0, 1.0));
+        let mut ray_t = interval::UNIVERSE;
+        assert!(!unit_box().hit(&ray, &mut ray_t));
+    }
+
+    #[test]
+    fn nan_box_never_hits() {
+        let nan = Interval::new(f64::NAN, f64::NAN);
+        let bbox = Aabb::new(&nan, &nan, &nan);
+        let ray = Ray::new(Point3::new(0.5, 0.5, -1.0), Vector3::new(0.0, 0.0, 1.0));
+        let mut ray_t = interval::UNIVERSE;
+        assert!(!bbox.hit(&ray, &mut ray_t));
+    }
+
+    #[test]
+    fn flat_box_is_padded_and_hit_obliquely() {
+        let bbox = Aabb::new_with_point(&Point3::new(0.0, 0.0, 0.0), &Point3::new(1.0, 1.0, 0.0));
+        assert!(bbox.z.size() >= PAD_DELTA);
+        assert!(bbox.x.size() == 1.0);
+
+        let ray = Ray::new(Point3::new(-1.0, -1.0, -1.0), Vector3::new(1.5, 1.5, 1.0));
+        let mut ray_t = interval::UNIVERSE;
+        assert!(bbox.hit(&ray, &mut ray_t));
+    }
+
+    #[test]
+    fn append_grows_each_axis_independently() {
+        let mut bbox = unit_box();
+        bbox.append(&Point3::new(0.5, 3.0, -2.0));
+        assert_eq!((bbox.x.min, bbox.x.max), (0.0, 1.0));
+        assert_eq!((bbox.y.min, bbox.y.max), (0.0, 3.0));
+        assert_eq!((bbox.z.min, bbox.z.max), (-2.0, 1.0));
+    }
+}
@@ -4,20 +4,60 @@ use cgmath::{InnerSpace, Point3, Vector3};
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
 
 use crate::{
+    environment::EnvironmentLight,
     hit::{Hit, HitRecord},
     interval::Interval,
     material::{Metal, ScatterRecord},
-    pdf::{HittablePdf, MixturePdf, Pdf},
+    pdf::{EnvironmentPdf, HittablePdf, MixturePdf, Pdf},
     ray::Ray,
-    utils::{degrees_to_radians, random_double, random_in_unit_disk},
+    utils::{
+        degrees_to_radians, derive_seed, random_double, random_in_unit_disk, seed_thread_rng, Pcg32,
+    },
 };
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ToneMap {
+    //仅做截断
+    None,
+    Reinhard,
+    Aces,
+}
+
+impl ToneMap {
+    fn apply(&self, c: f64) -> f64 {
+        match self {
+            ToneMap::None => c,
+            ToneMap::Reinhard => c / (1.0 + c),
+            ToneMap::Aces => {
+                let (a, b, c2, d, e) = (2.51, 0.03, 2.43, 0.59, 0.14);
+                (c * (a * c + b)) / (c * (c2 * c + d) + e)
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SamplePattern {
+    //纯随机采样，仅用于对比
+    Random,
+    //NxN网格内抖动
+    Stratified,
+    //预生成的蓝噪声偏移表，每个像素随机平移
+    BlueNoise,
+}
+
 pub struct Camera {
     pub aspect_ratio: f64,
     pub image_width: usize,
     pub samples_per_pixel: usize,
     pub max_depth: usize,
     pub background: Vector3<f64>,
+    //设置后未命中的光线取环境贴图的颜色，并对其做重要性采样
+    pub environment: Option<Arc<EnvironmentLight>>,
+    pub tone_map: ToneMap,
+    pub sample_pattern: SamplePattern,
+    //同样的种子得到逐位相同的图像
+    pub seed: u64,
     pub vfov: f64,
     pub lookfrom: Point3<f64>,
     pub lookat: Point3<f64>,
@@ -36,6 +76,7 @@ pub struct Camera {
     w: Vector3<f64>,
     defocus_disk_u: Vector3<f64>,
     defocus_disk_v: Vector3<f64>,
+    blue_noise_offsets: Vec<(f64, f64)>,
 }
 
 impl Default for Camera {
@@ -46,6 +87,10 @@ impl Default for Camera {
             samples_per_pixel: 10,
             max_depth: 10,
             background: Vector3::new(0.0, 0.0, 0.0),
+            environment: None,
+            tone_map: ToneMap::None,
+            sample_pattern: SamplePattern::Stratified,
+            seed: 0,
             vfov: 90.0,
             lookfrom: Point3::new(0.0, 0.0, -1.0),
             lookat: Point3::new(0.0, 0.0, 0.0),
@@ -64,12 +109,27 @@ impl Default for Camera {
             w: Vector3::new(0.0, 0.0, 0.0),
             defocus_disk_u: Vector3::new(0.0, 0.0, 0.0),
             defocus_disk_v: Vector3::new(0.0, 0.0, 0.0),
+            blue_noise_offsets: Vec::new(),
         }
     }
 }
 
 impl Camera {
     pub fn render(&mut self, world: &dyn Hit, lights: &dyn Hit, path: &Path) {
+        let bytes = self.render_to_buffer(world, lights);
+
+        let _ = image::save_buffer(
+            path,
+            &bytes,
+            self.image_width as u32,
+            self.image_height as u32,
+            image::ColorType::Rgb8,
+        );
+        eprintln!("渲染完毕");
+    }
+
+    //逐行输出的RGB8像素，尺寸为image_width x image_height()
+    pub fn render_to_buffer(&mut self, world: &dyn Hit, lights: &dyn Hit) -> Vec<u8> {
         self.initialize();
 
         let mut bytes: Vec<u8> = Vec::with_capacity(self.image_width * self.image_height * 3);
@@ -83,20 +143,14 @@ impl Camera {
             let scanline: Vec<Vector3<f64>> = (0..self.image_width)
                 .into_par_iter()
                 .map(|i| {
-                    let mut pixel_color = Vector3::new(0.0, 0.0, 0.0);
-                    for s_j in 0..self.sqrt_spp {
-                        for s_i in 0..self.sqrt_spp {
-                            let r = self.get_ray(i as i32, j as i32, s_i as i32, s_j as i32);
-                            pixel_color += self.ray_color(&r, self.max_depth, world, lights);
-                        }
-                    }
-
-                    pixel_color
+                    let pixel_index = (j * self.image_width + i) as u64;
+                    seed_thread_rng(derive_seed(self.seed, pixel_index));
+                    self.sample_pixel(i as i32, j as i32, world, lights)
                 })
                 .collect();
 
             for pixel_color in scanline {
-                let final_color = format_color(pixel_color, self.samples_per_pixel);
+                let final_color = format_color(pixel_color, self.sample_count(), self.tone_map);
 
                 bytes.push(final_color.x as u8);
                 bytes.push(final_color.y as u8);
@@ -104,25 +158,23 @@ impl Camera {
             }
         }
 
-        let _ = image::save_buffer(
-            path,
-            &bytes,
-            self.image_width as u32,
-            self.image_height as u32,
-            image::ColorType::Rgb8,
-        );
-        eprintln!("渲染完毕");
+        bytes
+    }
+
+    pub fn image_height(&self) -> usize {
+        ((self.image_width as f64 / self.aspect_ratio) as usize).max(1)
     }
 
     fn initialize(&mut self) {
-        self.image_height = (self.image_width as f64 / self.aspect_ratio) as usize;
-        self.image_height = if self.image_height < 1 {
-            1
-        } else {
-            self.image_height
-        };
+        self.image_height = self.image_height();
         self.sqrt_spp = (self.samples_per_pixel as f64).sqrt() as usize;
         self.recip_sqrt_spp = 1.0 / (self.sqrt_spp as f64);
+        self.blue_noise_offsets = if self.sample_pattern == SamplePattern::BlueNoise {
+            //用局部RNG生成，不改动调用线程的随机数状态
+            blue_noise_offsets(self.samples_per_pixel, &mut Pcg32::new(self.seed, 0))
+        } else {
+            Vec::new()
+        };
 
         self.center = self.lookfrom;
 
@@ -150,10 +202,50 @@ impl Camera {
         self.defocus_disk_v = self.v * defocus_radius;
     }
 
-    fn get_ray(&self, i: i32, j: i32, s_i: i32, s_j: i32) -> Ray {
+    fn sample_count(&self) -> usize {
+        match self.sample_pattern {
+            SamplePattern::Stratified => self.sqrt_spp * self.sqrt_spp,
+            _ => self.samples_per_pixel,
+        }
+    }
+
+    fn sample_pixel(&self, i: i32, j: i32, world: &dyn Hit, lights: &dyn Hit) -> Vector3<f64> {
+        let mut pixel_color = Vector3::new(0.0, 0.0, 0.0);
+        match self.sample_pattern {
+            SamplePattern::Random => {
+                for _ in 0..self.samples_per_pixel {
+                    let offset = (random_double() - 0.5, random_double() - 0.5);
+                    let r = self.get_ray(i, j, offset);
+                    pixel_color += self.ray_color(&r, self.max_depth, world, lights);
+                }
+            }
+            SamplePattern::Stratified => {
+                for s_j in 0..self.sqrt_spp {
+                    for s_i in 0..self.sqrt_spp {
+                        let offset = self.pixel_sample_square(s_i as i32, s_j as i32);
+                        let r = self.get_ray(i, j, offset);
+                        pixel_color += self.ray_color(&r, self.max_depth, world, lights);
+                    }
+                }
+            }
+            SamplePattern::BlueNoise => {
+                //Cranley-Patterson平移，避免所有像素使用相同的采样点
+                let (shift_x, shift_y) = (random_double(), random_double());
+                for &(x, y) in &self.blue_noise_offsets {
+                    let offset = ((x + shift_x).fract() - 0.5, (y + shift_y).fract() - 0.5);
+                    let r = self.get_ray(i, j, offset);
+                    pixel_color += self.ray_color(&r, self.max_depth, world, lights);
+                }
+            }
+        }
+        pixel_color
+    }
+
+    fn get_ray(&self, i: i32, j: i32, offset: (f64, f64)) -> Ray {
         let pixel_center =
             self.pixel00_loc + i as f64 * self.pixel_delta_u + j as f64 * self.pixel_delta_v;
-        let pixel_sample = pixel_center + self.pixel_sample_square(s_i, s_j);
+        let pixel_sample =
+            pixel_center + offset.0 * self.pixel_delta_u + offset.1 * self.pixel_delta_v;
 
         let ray_origin = if self.defocus_angle <= 0.0 {
             self.center
@@ -165,10 +257,10 @@ impl Camera {
         Ray::new(ray_origin, ray_direction)
     }
 
-    fn pixel_sample_square(&self, s_i: i32, s_j: i32) -> Vector3<f64> {
+    fn pixel_sample_square(&self, s_i: i32, s_j: i32) -> (f64, f64) {
         let px = -0.5 + self.recip_sqrt_spp * (s_i as f64 + random_double());
         let py = -0.5 + self.recip_sqrt_spp * (s_j as f64 + random_double());
-        px * self.pixel_delta_u + py * self.pixel_delta_v
+        (px, py)
     }
 
     fn defocus_disk_sample(&self) -> Point3<f64> {
@@ -176,7 +268,13 @@ impl Camera {
         self.center + p.x * self.defocus_disk_u + p.y * self.defocus_disk_v
     }
 
-    fn ray_color(&self, r: &Ray, depth: usize, world: &dyn Hit, lights: &dyn Hit) -> Vector3<f64> {
+    pub(crate) fn ray_color(
+        &self,
+        r: &Ray,
+        depth: usize,
+        world: &dyn Hit,
+        lights: &dyn Hit,
+    ) -> Vector3<f64> {
         let mut rec = HitRecord {
             p: Point3::new(0.0, 0.0, 0.0),
             normal: Vector3::new(0.0, 0.0, 0.0),
@@ -184,6 +282,8 @@ impl Camera {
             t: 0.0,
             u: 0.0,
             v: 0.0,
+            u_1: 0.0,
+            v_1: 0.0,
             front_face: true,
         };
 
@@ -192,7 +292,10 @@ impl Camera {
         }
 
         if !world.hit(r, &Interval::new(0.001, f64::INFINITY), &mut rec) {
-            return self.background;
+            return match &self.environment {
+                Some(environment) => environment.radiance(r.direction()),
+                None => self.background,
+            };
         }
 
         let mut srec = ScatterRecord::default();
@@ -210,8 +313,24 @@ impl Camera {
             );
         }
 
-        let light_pdf = HittablePdf::new(lights, rec.p);
-        let mixed_pdf = MixturePdf::new(&light_pdf, &*srec.pdf);
+        let hittable_pdf = HittablePdf::new(lights, rec.p);
+        let environment_pdf = self.environment.as_deref().map(EnvironmentPdf::new);
+        //有环境贴图时光源与环境各占光源采样的一半
+        let light_and_environment_pdf = environment_pdf
+            .as_ref()
+            .map(|environment_pdf| MixturePdf::new(&hittable_pdf, environment_pdf));
+        let light_pdf: Option<&dyn Pdf> = match (&light_and_environment_pdf, &environment_pdf) {
+            //没有光源时只对环境采样，两者都没有时只按材质采样
+            (_, Some(environment_pdf)) if lights.is_empty() => Some(environment_pdf),
+            _ if lights.is_empty() => None,
+            (Some(pdf), _) => Some(pdf),
+            (None, _) => Some(&hittable_pdf),
+        };
+        let light_pdf = match light_pdf {
+            Some(light_pdf) => light_pdf,
+            None => &*srec.pdf,
+        };
+        let mixed_pdf = MixturePdf::new(light_pdf, &*srec.pdf);
 
         let scattered = Ray::new(rec.p, mixed_pdf.generate());
         let pdf = mixed_pdf.value(scattered.direction());
@@ -228,6 +347,38 @@ impl Camera {
     }
 }
 
+//Mitchell最佳候选算法生成[0,1)^2内的蓝噪声点集，距离按环面计算以便平移后仍然均匀
+fn blue_noise_offsets(count: usize, rng: &mut Pcg32) -> Vec<(f64, f64)> {
+    const CANDIDATES: usize = 16;
+
+    let toroidal_distance = |a: (f64, f64), b: (f64, f64)| {
+        let dx = (a.0 - b.0).abs();
+        let dy = (a.1 - b.1).abs();
+        let dx = dx.min(1.0 - dx);
+        let dy = dy.min(1.0 - dy);
+        dx * dx + dy * dy
+    };
+
+    let mut points: Vec<(f64, f64)> = Vec::with_capacity(count);
+    for _ in 0..count {
+        let mut best = (rng.next_f64(), rng.next_f64());
+        let mut best_distance = 0.0;
+        for _ in 0..CANDIDATES {
+            let candidate = (rng.next_f64(), rng.next_f64());
+            let distance = points
+                .iter()
+                .map(|&p| toroidal_distance(p, candidate))
+                .fold(f64::INFINITY, f64::min);
+            if distance > best_distance {
+                best = candidate;
+                best_distance = distance;
+            }
+        }
+        points.push(best);
+    }
+    points
+}
+
 pub fn linear_to_gamma(linear_component: f64) -> f64 {
     if linear_component > 0.0 {
         linear_component.sqrt()
@@ -240,7 +391,11 @@ const INTENSITY: Interval = Interval {
     max: 0.999,
 };
 
-pub fn format_color(color: Vector3<f64>, samples_per_pixel: usize) -> Vector3<u64> {
+pub fn format_color(
+    color: Vector3<f64>,
+    samples_per_pixel: usize,
+    tone_map: ToneMap,
+) -> Vector3<u64> {
     let r = color.x;
     let g = color.y;
     let b = color.z;
@@ -254,6 +409,10 @@ pub fn format_color(color: Vector3<f64>, samples_per_pixel: usize) -> Vector3<u6
     let g = scale * g;
     let b = scale * b;
 
+    let r = tone_map.apply(r);
+    let g = tone_map.apply(g);
+    let b = tone_map.apply(b);
+
     let r = linear_to_gamma(r);
     let g = linear_to_gamma(g);
     let b = linear_to_gamma(b);
@@ -264,3 +423,92 @@ pub fn format_color(color: Vector3<f64>, samples_per_pixel: usize) -> Vector3<u6
         z: (256.0 * INTENSITY.clamp(b)) as u64,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        hittable_list::HittableList,
+        material::{Dielectric, DiffuseLight, Lambertian},
+        quad::Quad,
+        sphere::Sphere,
+    };
+
+    fn scene() -> (HittableList, HittableList) {
+        let mut world = HittableList::default();
+        let mut lights = HittableList::default();
+        let ground = Arc::new(Lambertian::new(Vector3::new(0.5, 0.5, 0.5)));
+        let glass = Arc::new(Dielectric::new(1.5));
+        world.add(Arc::new(
+            Sphere::new(Point3::new(0.0, -100.5, -1.0), 100.0, ground).unwrap(),
+        ));
+        world.add(Arc::new(
+            Sphere::new(Point3::new(0.0, 0.0, -1.0), 0.5, glass).unwrap(),
+        ));
+
+        let light = Quad::new(
+            Point3::new(-0.5, 1.5, -1.5),
+            Vector3::new(1.0, 0.0, 0.0),
+            Vector3::new(0.0, 0.0, 1.0),
+            Arc::new(DiffuseLight::new_with_color(Vector3::new(4.0, 4.0, 4.0))),
+        );
+        let light = Arc::new(light);
+        world.add(light.clone());
+        lights.add(light);
+        (world, lights)
+    }
+
+    fn render(seed: u64, threads: usize, pattern: SamplePattern) -> Vec<u8> {
+        let (world, lights) = scene();
+        let mut camera = Camera {
+            image_width: 16,
+            samples_per_pixel: 4,
+            max_depth: 4,
+            background: Vector3::new(0.2, 0.3, 0.5),
+            sample_pattern: pattern,
+            seed,
+            lookfrom: Point3::new(0.0, 0.5, 1.0),
+            lookat: Point3::new(0.0, 0.0, -1.0),
+            ..Default::default()
+        };
+
+        //线程数不同时rayon的调度也不同，像素结果不能受影响
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap()
+            .install(|| camera.render_to_buffer(&world, &lights))
+    }
+
+    #[test]
+    fn same_seed_renders_bit_identical_frames() {
+        for pattern in [SamplePattern::Stratified, SamplePattern::BlueNoise] {
+            let first = render(7, 1, pattern);
+            let second = render(7, 4, pattern);
+            assert_eq!(first.len(), 16 * 16 * 3);
+            assert!(first == second);
+        }
+    }
+
+    #[test]
+    fn blue_noise_setup_leaves_thread_rng_untouched() {
+        seed_thread_rng(42);
+        let expected = random_double();
+
+        seed_thread_rng(42);
+        let mut camera = Camera {
+            seed: 7,
+            samples_per_pixel: 16,
+            sample_pattern: SamplePattern::BlueNoise,
+            ..Default::default()
+        };
+        camera.initialize();
+        assert_eq!(camera.blue_noise_offsets.len(), 16);
+        assert_eq!(random_double(), expected);
+    }
+
+    #[test]
+    fn different_seeds_render_different_frames() {
+        assert!(render(7, 2, SamplePattern::Stratified) != render(8, 2, SamplePattern::Stratified));
+    }
+}
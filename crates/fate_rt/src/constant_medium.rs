@@ -15,6 +15,8 @@ use crate::{
 pub struct ConstantMedium {
     boundary: Arc<dyn Hit>,
     neg_inv_density: f64,
+    //为空时密度处处相同；否则取纹理x分量(0~1)乘以最大密度
+    density_texture: Option<Arc<dyn Texture>>,
     phase_function: Arc<dyn Scatter>,
 }
 
@@ -23,6 +25,20 @@ impl ConstantMedium {
         Self {
             boundary: b,
             neg_inv_density: -1.0 / d,
+            density_texture: None,
+            phase_function: Arc::new(Isotropic::new(a)),
+        }
+    }
+    pub fn new_with_density_texture(
+        b: Arc<dyn Hit>,
+        max_density: f64,
+        density: Arc<dyn Texture>,
+        a: Arc<dyn Texture>,
+    ) -> Self {
+        Self {
+            boundary: b,
+            neg_inv_density: -1.0 / max_density,
+            density_texture: Some(density),
             phase_function: Arc::new(Isotropic::new(a)),
         }
     }
@@ -30,6 +46,7 @@ impl ConstantMedium {
         Self {
             boundary: b,
             neg_inv_density: -1.0 / d,
+            density_texture: None,
             phase_function: Arc::new(Isotropic::new_with_color(c)),
         }
     }
@@ -44,6 +61,8 @@ impl Hit for ConstantMedium {
             t: 0.0,
             u: 0.0,
             v: 0.0,
+            u_1: 0.0,
+            v_1: 0.0,
             front_face: true,
         };
         let mut rec2 = HitRecord {
@@ -53,6 +72,8 @@ impl Hit for ConstantMedium {
             t: 0.0,
             u: 0.0,
             v: 0.0,
+            u_1: 0.0,
+            v_1: 0.0,
             front_face: true,
         };
 
@@ -84,14 +105,27 @@ impl Hit for ConstantMedium {
 
         let ray_length = r.direction().magnitude();
         let distance_inside_boundary = (rec2.t - rec1.t) * ray_length;
-        let hit_distance = self.neg_inv_density * random_double().ln();
 
-        if hit_distance > distance_inside_boundary {
-            return false;
+        //非均匀密度使用delta tracking：按最大密度步进，再以局部密度占比决定是否真正散射
+        let mut hit_distance = 0.0;
+        loop {
+            hit_distance += self.neg_inv_density * random_double().ln();
+            if hit_distance > distance_inside_boundary {
+                return false;
+            }
+
+            rec.t = rec1.t + hit_distance / ray_length;
+            rec.p = r.at(rec.t);
+
+            let scattered = match &self.density_texture {
+                Some(density) => random_double() < density.value(0.0, 0.0, rec.p).x.clamp(0.0, 1.0),
+                None => true,
+            };
+            if scattered {
+                break;
+            }
         }
 
-        rec.t = rec1.t + hit_distance / ray_length;
-        rec.p = r.at(rec.t);
         rec.normal = Vector3::new(1.0, 0.0, 0.0);
         rec.front_face = true;
         rec.mat = Arc::clone(&self.phase_function);
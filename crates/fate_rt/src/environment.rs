@@ -0,0 +1,156 @@
+use std::f64::consts::PI;
+use std::path::Path;
+
+use cgmath::{InnerSpace, Vector3};
+use image::{DynamicImage, ImageError};
+
+use crate::texture::ColorSpace;
+use crate::utils::random_double;
+
+//经纬度(equirect)环境贴图，按亮度构建二维CDF做重要性采样
+//u对应方位角phi，v对应天顶角theta，+Y朝上
+pub struct EnvironmentLight {
+    width: usize,
+    height: usize,
+    pixels: Vec<Vector3<f64>>,
+    //每行的条件CDF，长度为height * (width + 1)
+    conditional_cdf: Vec<f64>,
+    //按行的边缘CDF，长度为height + 1
+    marginal_cdf: Vec<f64>,
+    //所有像素权重之和，为0时整张图都是黑的
+    total_weight: f64,
+    intensity: f64,
+}
+
+impl EnvironmentLight {
+    pub fn from_path(path: &Path, intensity: f64) -> Result<Self, ImageError> {
+        Ok(Self::new(image::open(path)?, intensity))
+    }
+
+    //HDR/EXR按线性值读取，其他格式视为sRGB编码
+    pub fn new(image: DynamicImage, intensity: f64) -> Self {
+        let color_space = match image {
+            DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => ColorSpace::Linear,
+            _ => ColorSpace::Srgb,
+        };
+        let rgb = image.to_rgb32f();
+        let (width, height) = (rgb.width() as usize, rgb.height() as usize);
+        let pixels = rgb
+            .pixels()
+            .map(|p| {
+                Vector3::new(
+                    color_space.decode(p[0] as f64),
+                    color_space.decode(p[1] as f64),
+                    color_space.decode(p[2] as f64),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        //越靠近两极的像素覆盖的立体角越小，权重乘上sin(theta)
+        let mut conditional_cdf = vec![0.0; height * (width + 1)];
+        let mut marginal_cdf = vec![0.0; height + 1];
+        for y in 0..height {
+            let sin_theta = (PI * (y as f64 + 0.5) / height as f64).sin();
+            let row = &mut conditional_cdf[y * (width + 1)..(y + 1) * (width + 1)];
+            for x in 0..width {
+                let weight = luminance(pixels[y * width + x]) * sin_theta;
+                row[x + 1] = row[x] + weight.max(0.0);
+            }
+            marginal_cdf[y + 1] = marginal_cdf[y] + row[width];
+        }
+        let total_weight = marginal_cdf[height];
+
+        Self {
+            width,
+            height,
+            pixels,
+            conditional_cdf,
+            marginal_cdf,
+            total_weight,
+            intensity,
+        }
+    }
+
+    pub fn radiance(&self, direction: Vector3<f64>) -> Vector3<f64> {
+        let (x, y) = self.pixel(direction);
+        self.pixels[y * self.width + x] * self.intensity
+    }
+
+    //方向对应的立体角pdf
+    pub fn pdf(&self, direction: Vector3<f64>) -> f64 {
+        if self.total_weight <= 0.0 {
+            return uniform_sphere_pdf();
+        }
+        let direction = direction.normalize();
+        let sin_theta = (1.0 - direction.y * direction.y).max(0.0).sqrt();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        let (x, y) = self.pixel(direction);
+        let row = y * (self.width + 1);
+        let weight = self.conditional_cdf[row + x + 1] - self.conditional_cdf[row + x];
+        //离散像素概率换算到[0,1]^2上的密度，再换算到立体角
+        let pdf_uv = weight / self.total_weight * (self.width * self.height) as f64;
+        pdf_uv / (2.0 * PI * PI * sin_theta)
+    }
+
+    //按亮度采样一个方向
+    pub fn sample(&self) -> Vector3<f64> {
+        if self.total_weight <= 0.0 {
+            return uniform_sphere_direction();
+        }
+        let y = sample_cdf(&self.marginal_cdf, random_double() * self.total_weight);
+        let row = &self.conditional_cdf[y * (self.width + 1)..(y + 1) * (self.width + 1)];
+        let x = sample_cdf(row, random_double() * row[self.width]);
+
+        //在选中的像素内均匀抖动
+        let u = (x as f64 + random_double()) / self.width as f64;
+        let v = (y as f64 + random_double()) / self.height as f64;
+        direction_from_uv(u, v)
+    }
+
+    fn pixel(&self, direction: Vector3<f64>) -> (usize, usize) {
+        let (u, v) = uv_from_direction(direction.normalize());
+        let x = ((u * self.width as f64) as usize).min(self.width - 1);
+        let y = ((v * self.height as f64) as usize).min(self.height - 1);
+        (x, y)
+    }
+}
+
+fn luminance(color: Vector3<f64>) -> f64 {
+    0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z
+}
+
+//返回第一个累积值超过value的区间序号，跳过权重为0的区间
+fn sample_cdf(cdf: &[f64], value: f64) -> usize {
+    let count = cdf.len() - 1;
+    cdf[1..].partition_point(|c| *c <= value).min(count - 1)
+}
+
+fn uv_from_direction(direction: Vector3<f64>) -> (f64, f64) {
+    let theta = direction.y.clamp(-1.0, 1.0).acos();
+    let phi = direction.z.atan2(direction.x);
+    let phi = if phi < 0.0 { phi + 2.0 * PI } else { phi };
+    (phi / (2.0 * PI), theta / PI)
+}
+
+fn direction_from_uv(u: f64, v: f64) -> Vector3<f64> {
+    let phi = u * 2.0 * PI;
+    let theta = v * PI;
+    Vector3::new(
+        theta.sin() * phi.cos(),
+        theta.cos(),
+        theta.sin() * phi.sin(),
+    )
+}
+
+fn uniform_sphere_pdf() -> f64 {
+    1.0 / (4.0 * PI)
+}
+
+fn uniform_sphere_direction() -> Vector3<f64> {
+    let z = 1.0 - 2.0 * random_double();
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * random_double();
+    Vector3::new(r * phi.cos(), r * phi.sin(), z)
+}
@@ -12,10 +12,22 @@ pub struct HitRecord {
     pub t: f64,
     pub u: f64,
     pub v: f64,
+    //第二套uv，图元没有时与u、v相同
+    pub u_1: f64,
+    pub v_1: f64,
     pub front_face: bool,
 }
 
 impl HitRecord {
+    //按glTF纹理的texCoord选择uv，只支持两套uv
+    pub fn tex_coord(&self, set: u32) -> (f64, f64) {
+        if set == 1 {
+            (self.u_1, self.v_1)
+        } else {
+            (self.u, self.v)
+        }
+    }
+
     pub fn set_face_normal(&mut self, r: &Ray, outward_normal: Vector3<f64>) -> () {
         self.front_face = r.direction().dot(outward_normal) < 0.0;
         self.normal = if self.front_face {
@@ -36,6 +48,10 @@ pub trait Hit: Send + Sync {
     fn random(&self, _origin: Point3<f64>) -> Vector3<f64> {
         Vector3::new(1.0, 0.0, 0.0)
     }
+    //空的光源列表无法采样，相机据此跳过光源重要性采样
+    fn is_empty(&self) -> bool {
+        false
+    }
 }
 
 pub struct Translate {
@@ -7,7 +7,8 @@ use crate::{
     hit::{Hit, HitRecord},
     interval::Interval,
     material::Metal,
-    ray::Ray, utils::random_int,
+    ray::Ray,
+    utils::random_int,
 };
 
 #[derive(Default)]
@@ -47,6 +48,8 @@ impl Hit for HittableList {
             t: 0.0,
             u: 0.0,
             v: 0.0,
+            u_1: 0.0,
+            v_1: 0.0,
             front_face: true,
         };
         let mut hit_anything = false;
@@ -78,4 +81,8 @@ impl Hit for HittableList {
         let int_size = self.objects.len() as i32;
         self.objects[random_int(0, int_size - 1) as usize].random(origin)
     }
+
+    fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
 }
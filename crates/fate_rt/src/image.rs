@@ -1,4 +1,5 @@
-use image::{GenericImageView, Pixel};
+use image::{GenericImageView, ImageError, Pixel};
+use std::path::Path;
 
 pub const BYTES_PER_PIXEL: usize = 3;
 
@@ -12,16 +13,19 @@ pub struct Image {
 
 impl Image {
     pub fn new(image_filename: &str) -> Self {
-        let filename = image_filename;
-        let mut _self = Self::default();
-        let img = image::open(&format!("res/texture/{}", filename));
-        let dyn_img: image::DynamicImage = img.expect("Image loading failed.");
-        let (width, height) = (dyn_img.width(), dyn_img.height());
-        _self.image = dyn_img;
-        _self.image_width = width as usize;
-        _self.image_height = height as usize;
-        //_self.bytes_per_scanline = BYTES_PER_PIXEL;
-        return _self;
+        let path = Path::new("res/texture").join(image_filename);
+        Self::from_path(&path).expect("Image loading failed.")
+    }
+
+    //任意尺寸和颜色类型都可以，采样时统一转换成RGB
+    pub fn from_path(path: &Path) -> Result<Self, ImageError> {
+        let dyn_img = image::open(path)?;
+        if dyn_img.width() == 0 || dyn_img.height() == 0 {
+            return Err(ImageError::Limits(image::error::LimitError::from_kind(
+                image::error::LimitErrorKind::DimensionError,
+            )));
+        }
+        Ok(Self::new_with_dyn_img(dyn_img))
     }
 
     pub fn new_with_dyn_img(dyn_img: image::DynamicImage) -> Self {
@@ -40,7 +44,7 @@ impl Image {
         self.image.height() as usize
     }
 
-    pub fn pixel_data(&self, x: usize, y: usize) -> [u8;3] {
+    pub fn pixel_data(&self, x: usize, y: usize) -> [u8; 3] {
         let x = Self::clamp(x, 0, self.image_width);
         let y = Self::clamp(y, 0, self.image_height);
 
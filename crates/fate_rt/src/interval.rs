@@ -58,8 +58,8 @@ pub const EMPTY: Interval = Interval {
     max: f64::NEG_INFINITY,
 };
 pub const UNIVERSE: Interval = Interval {
-    min: f64::INFINITY,
-    max: f64::NEG_INFINITY,
+    min: f64::NEG_INFINITY,
+    max: f64::INFINITY,
 };
 
 impl std::ops::Add<f64> for &Interval {
@@ -2,10 +2,12 @@ pub mod aabb;
 pub mod bvh;
 pub mod camera;
 pub mod constant_medium;
+pub mod environment;
 pub mod hit;
 pub mod hittable_list;
 pub mod image;
 pub mod interval;
+pub mod lightmap;
 pub mod material;
 pub mod model;
 pub mod onb;
@@ -0,0 +1,361 @@
+use std::{path::Path, sync::Arc};
+
+use anyhow::Result;
+use cgmath::{EuclideanSpace, InnerSpace, Point3, Vector2, Vector3};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+use crate::{
+    camera::{format_color, Camera, ToneMap},
+    environment::EnvironmentLight,
+    hit::{Hit, HitRecord},
+    hittable_list::HittableList,
+    interval::Interval,
+    material::Metal,
+    model::Model,
+    onb::Onb,
+    ray::Ray,
+    triangle::Vertex,
+    utils::{derive_seed, random_cosine_direction, seed_thread_rng},
+};
+
+//光线起点沿法线的偏移，避免打到自己所在的三角形
+const RAY_OFFSET: f64 = 0.0001;
+//重心坐标允许的误差，让恰好落在共享边上的texel不会漏掉
+const BARYCENTRIC_EPS: f64 = 1.0e-6;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BakeMode {
+    //环境光遮蔽，未被遮挡的比例，1为完全不遮挡
+    AmbientOcclusion,
+    //多次反弹的间接光照
+    Irradiance,
+}
+
+#[derive(Clone)]
+pub struct BakeSettings {
+    pub mode: BakeMode,
+    pub samples_per_texel: usize,
+    //只对Irradiance生效
+    pub max_depth: usize,
+    //AO光线的最远距离，模型空间单位，超过此距离的遮挡不计
+    pub ao_distance: f64,
+    //未命中时的颜色，设置了环境贴图时以环境贴图为准
+    pub background: Vector3<f64>,
+    pub environment: Option<Arc<EnvironmentLight>>,
+    //向未覆盖的texel扩展的圈数，防止双线性过滤在uv岛边缘采到黑色
+    pub dilation: usize,
+    pub seed: u64,
+}
+
+impl Default for BakeSettings {
+    fn default() -> Self {
+        Self {
+            mode: BakeMode::AmbientOcclusion,
+            samples_per_texel: 64,
+            max_depth: 4,
+            ao_distance: 1.0,
+            background: Vector3::new(1.0, 1.0, 1.0),
+            environment: None,
+            dilation: 4,
+            seed: 0,
+        }
+    }
+}
+
+//按uv烘焙的光照贴图，texel为线性值，第0行对应v=0
+//Irradiance模式存的是辐照度/π，光栅化时直接乘albedo
+pub struct Lightmap {
+    width: usize,
+    height: usize,
+    texels: Vec<Vector3<f64>>,
+    //被三角形覆盖或被扩展填充的texel
+    coverage: Vec<bool>,
+}
+
+impl Lightmap {
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn texel(&self, x: usize, y: usize) -> Vector3<f64> {
+        self.texels[y * self.width + x]
+    }
+
+    pub fn is_covered(&self, x: usize, y: usize) -> bool {
+        self.coverage[y * self.width + x]
+    }
+
+    //逐行的线性RGB32F，可直接作为HDR纹理上传
+    pub fn to_rgb32f(&self) -> Vec<f32> {
+        self.texels
+            .iter()
+            .flat_map(|t| [t.x as f32, t.y as f32, t.z as f32])
+            .collect()
+    }
+
+    //逐行的RGB8，做gamma校正
+    pub fn to_rgb8(&self, tone_map: ToneMap) -> Vec<u8> {
+        self.texels
+            .iter()
+            .flat_map(|t| {
+                let c = format_color(*t, 1, tone_map);
+                [c.x as u8, c.y as u8, c.z as u8]
+            })
+            .collect()
+    }
+
+    //扩展名为exr时保存线性值，否则保存gamma校正后的8位图像
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let is_exr = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("exr"));
+        if is_exr {
+            let buffer = image::Rgb32FImage::from_raw(
+                self.width as u32,
+                self.height as u32,
+                self.to_rgb32f(),
+            )
+            .ok_or_else(|| anyhow::anyhow!("光照贴图尺寸与数据长度不一致"))?;
+            image::DynamicImage::ImageRgb32F(buffer).save(path)?;
+        } else {
+            image::save_buffer(
+                path,
+                &self.to_rgb8(ToneMap::None),
+                self.width as u32,
+                self.height as u32,
+                image::ColorType::Rgb8,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+//texel中心对应的模型空间表面点
+#[derive(Copy, Clone)]
+struct SurfaceSample {
+    pos: Point3<f64>,
+    normal: Vector3<f64>,
+}
+
+pub fn bake_lightmap(model: &Model, uv_set: usize, resolution: usize) -> Result<Lightmap> {
+    bake_lightmap_with_settings(model, uv_set, resolution, &BakeSettings::default())
+}
+
+//在模型空间中烘焙，只考虑模型自身的遮挡，不受model.transform影响
+pub fn bake_lightmap_with_settings(
+    model: &Model,
+    uv_set: usize,
+    resolution: usize,
+    settings: &BakeSettings,
+) -> Result<Lightmap> {
+    if uv_set > 1 {
+        anyhow::bail!("只支持两套uv，uv_set为{}", uv_set);
+    }
+    if resolution == 0 {
+        anyhow::bail!("光照贴图分辨率不能为0");
+    }
+    if settings.samples_per_texel == 0 {
+        anyhow::bail!("每个texel至少需要一个采样");
+    }
+
+    let samples = rasterize_uv(model, uv_set, resolution);
+
+    //Irradiance借用相机的路径追踪，没有显式光源时只按材质和环境贴图采样
+    let mut camera = Camera::default();
+    camera.max_depth = settings.max_depth;
+    camera.background = settings.background;
+    camera.environment = settings.environment.clone();
+    let lights = HittableList::default();
+
+    let texels: Vec<Vector3<f64>> = (0..samples.len())
+        .into_par_iter()
+        .map(|index| match samples[index] {
+            Some(sample) => {
+                seed_thread_rng(derive_seed(settings.seed, index as u64));
+                bake_texel(&sample, model, &camera, &lights, settings)
+            }
+            None => Vector3::new(0.0, 0.0, 0.0),
+        })
+        .collect();
+    let coverage = samples.iter().map(|s| s.is_some()).collect();
+
+    let mut lightmap = Lightmap {
+        width: resolution,
+        height: resolution,
+        texels,
+        coverage,
+    };
+    dilate(&mut lightmap, settings.dilation);
+    Ok(lightmap)
+}
+
+fn bake_texel(
+    sample: &SurfaceSample,
+    model: &Model,
+    camera: &Camera,
+    lights: &HittableList,
+    settings: &BakeSettings,
+) -> Vector3<f64> {
+    let uvw = Onb::new_from_w(sample.normal);
+    let origin = sample.pos + sample.normal * RAY_OFFSET;
+
+    let mut sum = Vector3::new(0.0, 0.0, 0.0);
+    for _ in 0..settings.samples_per_texel {
+        //余弦加权采样，cos/pdf恰好为π，平均值即为辐照度/π
+        let r = Ray::new(origin, uvw.local_v(random_cosine_direction()));
+        sum += match settings.mode {
+            BakeMode::AmbientOcclusion => {
+                let mut rec = empty_hit_record();
+                let ray_t = Interval::new(0.0, settings.ao_distance);
+                if model.triangles.hit(&r, &ray_t, &mut rec) {
+                    Vector3::new(0.0, 0.0, 0.0)
+                } else {
+                    Vector3::new(1.0, 1.0, 1.0)
+                }
+            }
+            BakeMode::Irradiance => {
+                let color = camera.ray_color(&r, settings.max_depth, &model.triangles, lights);
+                //个别路径的NaN会污染整个texel
+                if color.x.is_finite() && color.y.is_finite() && color.z.is_finite() {
+                    color
+                } else {
+                    Vector3::new(0.0, 0.0, 0.0)
+                }
+            }
+        };
+    }
+    sum / settings.samples_per_texel as f64
+}
+
+//在uv空间光栅化所有三角形，求出每个texel中心对应的表面点，多个三角形重叠时后者覆盖前者
+fn rasterize_uv(model: &Model, uv_set: usize, resolution: usize) -> Vec<Option<SurfaceSample>> {
+    let mut samples = vec![None; resolution * resolution];
+    let size = resolution as f64;
+    let uv_of = |v: &Vertex| -> Vector2<f64> {
+        let uv = if uv_set == 0 {
+            v.tex_coord
+        } else {
+            v.tex_coord_1
+        };
+        //加载时对uv取了余数，负数要再绕回[0, 1)
+        Vector2::new(uv.x.rem_euclid(1.0), uv.y.rem_euclid(1.0)) * size
+    };
+
+    for triangle in model.indices.chunks_exact(3) {
+        let [a, b, c] = [0, 1, 2].map(|i| &model.vertices[triangle[i] as usize]);
+        let (ta, tb, tc) = (uv_of(a), uv_of(b), uv_of(c));
+
+        let area = edge(ta, tb, tc);
+        if area.abs() < f64::EPSILON {
+            continue;
+        }
+        let geometric_normal = (b.pos - a.pos).cross(c.pos - a.pos);
+        if geometric_normal.magnitude2() == 0.0 {
+            continue;
+        }
+
+        let min_x = ta.x.min(tb.x).min(tc.x).floor().max(0.0) as usize;
+        let min_y = ta.y.min(tb.y).min(tc.y).floor().max(0.0) as usize;
+        let max_x = (ta.x.max(tb.x).max(tc.x).ceil() as usize).min(resolution);
+        let max_y = (ta.y.max(tb.y).max(tc.y).ceil() as usize).min(resolution);
+
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                let p = Vector2::new(x as f64 + 0.5, y as f64 + 0.5);
+                let alpha = edge(tb, tc, p) / area;
+                let beta = edge(tc, ta, p) / area;
+                let gamma = 1.0 - alpha - beta;
+                if alpha < -BARYCENTRIC_EPS || beta < -BARYCENTRIC_EPS || gamma < -BARYCENTRIC_EPS {
+                    continue;
+                }
+
+                let pos = Point3::from_vec(
+                    a.pos.to_vec() * alpha + b.pos.to_vec() * beta + c.pos.to_vec() * gamma,
+                );
+                let normal = a.normal * alpha + b.normal * beta + c.normal * gamma;
+                //顶点法线插值退化时退回几何法线
+                let normal = if normal.magnitude2() > 0.0 {
+                    normal.normalize()
+                } else {
+                    geometric_normal.normalize()
+                };
+                samples[y * resolution + x] = Some(SurfaceSample { pos, normal });
+            }
+        }
+    }
+    samples
+}
+
+//二维叉积，p在ab左侧时为正
+fn edge(a: Vector2<f64>, b: Vector2<f64>, p: Vector2<f64>) -> f64 {
+    (b.x - a.x) * (p.y - a.y) - (b.y - a.y) * (p.x - a.x)
+}
+
+//每一圈把未覆盖的texel设为相邻已覆盖texel的平均值，uv岛边缘的缝隙因此被填上
+fn dilate(lightmap: &mut Lightmap, passes: usize) {
+    let (width, height) = (lightmap.width as i64, lightmap.height as i64);
+    for _ in 0..passes {
+        let mut filled = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                if lightmap.coverage[(y * width + x) as usize] {
+                    continue;
+                }
+                let mut sum = Vector3::new(0.0, 0.0, 0.0);
+                let mut count = 0;
+                for (dx, dy) in NEIGHBORS {
+                    let (nx, ny) = (x + dx, y + dy);
+                    if nx < 0 || ny < 0 || nx >= width || ny >= height {
+                        continue;
+                    }
+                    let neighbor = (ny * width + nx) as usize;
+                    if lightmap.coverage[neighbor] {
+                        sum += lightmap.texels[neighbor];
+                        count += 1;
+                    }
+                }
+                if count > 0 {
+                    filled.push(((y * width + x) as usize, sum / count as f64));
+                }
+            }
+        }
+        if filled.is_empty() {
+            break;
+        }
+        //本圈填充的texel不参与本圈的平均，扩展才是各向均匀的
+        for (index, texel) in filled {
+            lightmap.texels[index] = texel;
+            lightmap.coverage[index] = true;
+        }
+    }
+}
+
+const NEIGHBORS: [(i64, i64); 8] = [
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+    (-1, 0),
+    (1, 0),
+    (-1, 1),
+    (0, 1),
+    (1, 1),
+];
+
+fn empty_hit_record() -> HitRecord {
+    HitRecord {
+        p: Point3::new(0.0, 0.0, 0.0),
+        normal: Vector3::new(0.0, 0.0, 0.0),
+        mat: Arc::new(Metal::new(Vector3::new(0.0, 0.0, 0.0), 0.0)),
+        t: 0.0,
+        u: 0.0,
+        v: 0.0,
+        u_1: 0.0,
+        v_1: 0.0,
+        front_face: true,
+    }
+}
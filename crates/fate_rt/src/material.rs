@@ -1,14 +1,13 @@
 use std::{f64::consts::PI, sync::Arc};
 
 use cgmath::{InnerSpace, Point3, Vector3};
-use rand::Rng;
 
 use crate::{
     hit::HitRecord,
     pdf::{CosinePdf, NonePdf, Pdf, SpherePdf},
     ray::Ray,
     texture::{SolidColor, Texture},
-    utils::{random_in_unit_sphere, reflect, refract},
+    utils::{random_double, random_in_unit_sphere, reflect, refract},
 };
 
 pub struct ScatterRecord {
@@ -29,6 +28,9 @@ impl Default for ScatterRecord {
     }
 }
 
+//scatter返回的srec.pdf用于采样出射方向，渲染器会将其与光源pdf混合做重要性采样；
+//scattering_pdf给出BSDF对应方向的pdf，两者需要描述同一个分布。
+//镜面类材质无法给出pdf，设置skip_pdf并直接给出skip_pdf_ray
 pub trait Scatter: Send + Sync {
     fn scatter(&self, r_in: &Ray, rec: &HitRecord, srec: &mut ScatterRecord) -> bool;
 
@@ -87,17 +89,35 @@ pub struct PBR {
     //pub emissive: Arc<dyn Texture>,
     //pub normal: Arc<dyn Texture>,
     pub metal_roughness: Arc<dyn Texture>,
+    //各纹理采样的uv套数，对应glTF纹理的texCoord
+    pub albedo_tex_coord: u32,
+    pub metal_roughness_tex_coord: u32,
 }
 
 impl PBR {
     pub fn new(albedo: Arc<dyn Texture>, metal_roughness: Arc<dyn Texture>) -> Self {
-        Self { albedo, metal_roughness }
+        Self::new_with_tex_coords(albedo, metal_roughness, 0, 0)
+    }
+
+    pub fn new_with_tex_coords(
+        albedo: Arc<dyn Texture>,
+        metal_roughness: Arc<dyn Texture>,
+        albedo_tex_coord: u32,
+        metal_roughness_tex_coord: u32,
+    ) -> Self {
+        Self {
+            albedo,
+            metal_roughness,
+            albedo_tex_coord,
+            metal_roughness_tex_coord,
+        }
     }
 }
 
 impl Scatter for PBR {
     fn scatter(&self, _r_in: &Ray, rec: &HitRecord, srec: &mut ScatterRecord) -> bool {
-        srec.attenuation = self.albedo.value(rec.u, rec.v, rec.p);
+        let (u, v) = rec.tex_coord(self.albedo_tex_coord);
+        srec.attenuation = self.albedo.value(u, v, rec.p);
         srec.pdf = Box::new(CosinePdf::new(rec.normal));
         srec.skip_pdf = false;
         true
@@ -166,9 +186,8 @@ impl Scatter for Dielectric {
         let cos_theta = ((-1.0) * unit_direction).dot(rec.normal).min(1.0);
         let sin_theta = (1.0 - cos_theta.powi(2)).sqrt();
 
-        let mut rng = rand::thread_rng();
         let cannot_refract = refraction_ratio * sin_theta > 1.0;
-        let will_reflect = rng.gen::<f64>() < Self::reflectance(cos_theta, refraction_ratio);
+        let will_reflect = random_double() < Self::reflectance(cos_theta, refraction_ratio);
 
         let direction = if cannot_refract || will_reflect {
             reflect(&unit_direction, &rec.normal)
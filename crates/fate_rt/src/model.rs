@@ -1,10 +1,13 @@
+use std::f32::consts::FRAC_PI_2;
 use std::fs::{self, File};
 use std::io::{self, BufReader};
 use std::path::Path;
 use std::sync::Arc;
 
 use anyhow::Result;
-use cgmath::{Point3, Vector2, Vector3};
+use cgmath::{
+    InnerSpace, Matrix, Matrix4, Point3, Rad, SquareMatrix, Transform as _, Vector2, Vector3,
+};
 use gltf::image::Source;
 use image::ImageFormat::{Jpeg, Png};
 use std::collections::HashMap;
@@ -17,19 +20,64 @@ use crate::image::Image;
 use crate::interval::Interval;
 use crate::material::{Scatter, PBR};
 use crate::ray::Ray;
-use crate::texture::ImageTexture;
+use crate::texture::{ColorSpace, ImageTexture};
 use crate::transform::Transform;
-use crate::triangle::{Triangle, Vertex};
+use crate::triangle::{Shading, Triangle, Vertex};
+
+//资源作者使用的朝上轴，导入时统一转换到Y轴朝上的世界坐标系
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum UpAxis {
+    //glTF规范的约定，与光栅化渲染器一致
+    #[default]
+    Y,
+    //Blender、3ds Max等工具常用的约定
+    Z,
+}
+
+impl UpAxis {
+    //只旋转不镜像，三角形绕序保持不变
+    fn to_y_up(self) -> Matrix4<f32> {
+        match self {
+            UpAxis::Y => Matrix4::identity(),
+            UpAxis::Z => Matrix4::from_angle_x(Rad(-FRAC_PI_2)),
+        }
+    }
+}
+
+//资源坐标到模型空间的转换矩阵：先转成Y轴朝上，再乘以单位缩放
+//例如Z轴朝上、单位为厘米的资源传入`(UpAxis::Z, 0.01)`，(1, 2, 3)落在(0.01, 0.03, -0.02)
+pub fn import_matrix(up_axis: UpAxis, unit_scale: f32) -> Matrix4<f32> {
+    Matrix4::from_scale(unit_scale) * up_axis.to_y_up()
+}
 
 pub struct Model {
     pub bbox: Aabb,
     pub triangles: HittableList,
+    //模型空间的顶点和索引，烘焙光照贴图时按uv光栅化三角形用
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
     pub material: Arc<dyn Scatter>,
     pub transform: Transform,
 }
 
 impl Model {
-    pub fn new(path: &str, scale: f32, transform: Transform) -> Result<Self> {
+    pub fn new(path: &str, up_axis: UpAxis, unit_scale: f32, transform: Transform) -> Result<Self> {
+        Self::new_with_shading(path, up_axis, unit_scale, transform, Shading::default())
+    }
+
+    pub fn new_with_shading(
+        path: &str,
+        up_axis: UpAxis,
+        unit_scale: f32,
+        transform: Transform,
+        shading: Shading,
+    ) -> Result<Self> {
+        //负数会镜像模型，0会让所有三角形退化
+        if !unit_scale.is_finite() || unit_scale <= 0.0 {
+            anyhow::bail!("非法单位缩放{}", unit_scale);
+        }
+        let import_transform = import_matrix(up_axis, unit_scale);
+
         let mut unique_vertices = HashMap::new();
         let mut indices: Vec<u32> = Vec::new();
         let mut vertices: Vec<Vertex> = Vec::new();
@@ -39,6 +87,8 @@ impl Model {
 
         let mut model_images: Vec<Image> = Vec::new();
         let mut material_image_index: Vec<i32> = vec![-1; 5];
+        //纹理采样的uv套数，与material_image_index一一对应
+        let mut material_tex_coords = [0u32; 5];
         if path.ends_with(".obj") {
             let mut reader = BufReader::new(File::open(path)?);
 
@@ -58,22 +108,28 @@ impl Model {
                     let normal_index = model.mesh.normal_indices[idx] as usize;
                     let pos_offset = (3 * index) as usize;
 
+                    let pos = import_transform.transform_point(Point3::new(
+                        model.mesh.positions[pos_offset],
+                        model.mesh.positions[pos_offset + 1],
+                        model.mesh.positions[pos_offset + 2],
+                    ));
+                    //转换矩阵是旋转加均匀缩放，法线直接变换后归一化即可
+                    let normal = import_transform
+                        .transform_vector(Vector3::new(
+                            model.mesh.normals[normal_index * 3],
+                            model.mesh.normals[normal_index * 3 + 1],
+                            model.mesh.normals[normal_index * 3 + 2],
+                        ))
+                        .normalize();
                     let vertex = Vertex {
-                        pos: Point3::new(
-                            (model.mesh.positions[pos_offset] * scale) as f64,
-                            (model.mesh.positions[pos_offset + 1] * scale) as f64,
-                            (model.mesh.positions[pos_offset + 2] * scale) as f64,
-                        ),
+                        pos: Point3::new(pos.x as f64, pos.y as f64, pos.z as f64),
                         color: Vector3::new(1.0, 1.0, 1.0),
-                        normal: Vector3::new(
-                            model.mesh.normals[normal_index * 3] as f64,
-                            model.mesh.normals[normal_index * 3 + 1] as f64,
-                            model.mesh.normals[normal_index * 3 + 2] as f64,
-                        ),
+                        normal: Vector3::new(normal.x as f64, normal.y as f64, normal.z as f64),
                         tex_coord: Vector2::new(
                             0.0, //model.mesh.texcoords[tex_coord_offset] as f64,
                             1.0, // - model.mesh.texcoords[tex_coord_offset + 1] as f64,
                         ),
+                        tex_coord_1: Vector2::new(0.0, 1.0),
                     };
 
                     if let Some(index) = unique_vertices.get(&vertex) {
@@ -89,70 +145,33 @@ impl Model {
             }
         } else if path.ends_with(".gltf") || path.ends_with(".glb") {
             let (gltf, buffers, _images) = gltf::import(path)?;
-            for mesh in gltf.meshes() {
-                for primitive in mesh.primitives() {
-                    let r = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
-                    if let Some(iter) = r.read_indices() {
-                        for v in iter.into_u32() {
-                            indices.push(v);
-                        }
+            //按节点层级累乘变换，把每个primitive放到静止姿态下的位置，根节点从导入转换开始
+            match gltf.default_scene().or_else(|| gltf.scenes().next()) {
+                Some(scene) => {
+                    for node in scene.nodes() {
+                        append_gltf_node(
+                            &node,
+                            import_transform,
+                            &buffers,
+                            &mut indices,
+                            &mut vertices,
+                            &mut bbox,
+                        );
                     }
-                    let mut positions = Vec::new();
-                    if let Some(iter) = r.read_positions() {
-                        for v in iter {
-                            positions.push(v);
-                        }
-                    }
-
-                    let mut uvs = Vec::new();
-                    let mut tex_coord_set = 0;
-                    while let Some(tex_coords) = r.read_tex_coords(tex_coord_set) {
-                        if tex_coord_set > 1 {
-                            println!("忽略槽位{},只支持两套uv", tex_coord_set);
-                            tex_coord_set += 1;
-                            continue;
-                        }
-                        for tex_coord in tex_coords.into_f32() {
-                            match tex_coord_set {
-                                0 => {
-                                    uvs.push(Vector2::new(tex_coord[0] as f64, tex_coord[1] as f64))
-                                }
-                                //1 => vertices[i].tex_coord_1 = Vector2::from(tex_coord),
-                                _ => unreachable!(),
-                            }
-                        }
-                        tex_coord_set += 1;
-                    }
-
-                    let mut normals = Vec::new();
-                    if let Some(iter) = r.read_normals() {
-                        for v in iter {
-                            normals.push(v);
+                }
+                None => {
+                    for mesh in gltf.meshes() {
+                        for primitive in mesh.primitives() {
+                            append_gltf_primitive(
+                                &primitive,
+                                import_transform,
+                                &buffers,
+                                &mut indices,
+                                &mut vertices,
+                                &mut bbox,
+                            );
                         }
                     }
-
-                    let size = positions.len();
-                    for idx in 0..size {
-                        let pos = positions[idx];
-                        let normal = normals[idx];
-                        let uv = uvs[idx];
-                        let vertex = Vertex {
-                            pos: Point3::new(
-                                (pos[0] * scale) as f64,
-                                (pos[2] * scale) as f64,
-                                (pos[1] * scale) as f64,
-                            ),
-                            color: Vector3::new(1.0, 1.0, 1.0),
-                            normal: Vector3::new(
-                                normal[0] as f64,
-                                normal[2] as f64,
-                                normal[1] as f64,
-                            ),
-                            tex_coord: Vector2::new(uv[0] % 1.0, uv[1] % 1.0),
-                        };
-                        vertices.push(vertex);
-                        bbox.append(&vertex.pos);
-                    }
                 }
             }
             for image in gltf.images() {
@@ -242,7 +261,10 @@ impl Model {
                 //albedo
                 let color_texture_idx = match material.pbr_metallic_roughness().base_color_texture()
                 {
-                    Some(color_texture) => color_texture.texture().index() as i32,
+                    Some(color_texture) => {
+                        material_tex_coords[0] = color_texture.tex_coord();
+                        color_texture.texture().index() as i32
+                    }
                     None => -1,
                 };
                 material_image_index[0] = color_texture_idx;
@@ -260,6 +282,7 @@ impl Model {
                     .metallic_roughness_texture()
                 {
                     Some(metallic_roughness_texture) => {
+                        material_tex_coords[2] = metallic_roughness_texture.tex_coord();
                         metallic_roughness_texture.texture().index() as i32
                     }
                     None => -1,
@@ -281,41 +304,169 @@ impl Model {
                 material_image_index[4] = emissive_texture_idx;
             }
         }
-        let material: Arc<dyn Scatter> = Arc::new(PBR::new(
+        let material: Arc<dyn Scatter> = Arc::new(PBR::new_with_tex_coords(
             Arc::new(ImageTexture::new_with_image(
                 model_images[material_image_index[0] as usize].clone(),
             )),
-            Arc::new(ImageTexture::new_with_image(
+            Arc::new(ImageTexture::new_with_color_space(
                 model_images[material_image_index[2] as usize].clone(),
+                ColorSpace::Linear,
             )),
+            material_tex_coords[0],
+            material_tex_coords[2],
         ));
         let normal_image = Arc::new(model_images[material_image_index[1] as usize].clone());
 
         let num = indices.len() / 3;
         for idx in 0..num {
-            triangles.add(Arc::new(Triangle::new(
+            triangles.add(Arc::new(Triangle::new_with_shading(
                 vertices[indices[idx * 3] as usize].clone(),
                 vertices[indices[idx * 3 + 1] as usize].clone(),
                 vertices[indices[idx * 3 + 2] as usize].clone(),
                 Arc::clone(&material),
                 Arc::clone(&normal_image),
+                shading,
             )));
         }
         let triangles = HittableList::new(Arc::new(BvhNode::new(&mut triangles)));
 
         //let metallic_roughness_image = model_images[material_image_index[2] as usize].clone();
 
-        indices.clear();
-        vertices.clear();
         Ok(Self {
             bbox,
             triangles,
+            vertices,
+            indices,
             material,
             transform,
         })
     }
 }
 
+fn append_gltf_node(
+    node: &gltf::Node,
+    parent_transform: Matrix4<f32>,
+    buffers: &[gltf::buffer::Data],
+    indices: &mut Vec<u32>,
+    vertices: &mut Vec<Vertex>,
+    bbox: &mut Aabb,
+) {
+    let world_transform = parent_transform * Matrix4::from(node.transform().matrix());
+    if let Some(mesh) = node.mesh() {
+        for primitive in mesh.primitives() {
+            append_gltf_primitive(
+                &primitive,
+                world_transform,
+                buffers,
+                indices,
+                vertices,
+                bbox,
+            );
+        }
+    }
+    for child in node.children() {
+        append_gltf_node(&child, world_transform, buffers, indices, vertices, bbox);
+    }
+}
+
+fn append_gltf_primitive(
+    primitive: &gltf::Primitive,
+    world_transform: Matrix4<f32>,
+    buffers: &[gltf::buffer::Data],
+    indices: &mut Vec<u32>,
+    vertices: &mut Vec<Vertex>,
+    bbox: &mut Aabb,
+) {
+    let r = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+
+    //sparse替换由reader的迭代器完成；既没有bufferView也没有sparse的accessor按规范全为0
+    //sparse且没有bufferView时迭代器不会结束，size_hint也会溢出，所有属性逐个读取到顶点数为止
+    let count = primitive
+        .get(&gltf::mesh::Semantic::Positions)
+        .map_or(0, |accessor| accessor.count());
+    let mut positions = Vec::with_capacity(count);
+    match r.read_positions() {
+        Some(iter) => {
+            for v in iter.take(count) {
+                positions.push(v);
+            }
+        }
+        None => positions.resize(count, [0.0; 3]),
+    }
+
+    //索引是primitive内部的，要加上之前已经写入的顶点数
+    let base_vertex = vertices.len() as u32;
+    let mut primitive_indices: Vec<u32> = match (r.read_indices(), primitive.indices()) {
+        (Some(iter), Some(accessor)) => {
+            let mut primitive_indices = Vec::with_capacity(accessor.count());
+            for v in iter.into_u32().take(accessor.count()) {
+                primitive_indices.push(v + base_vertex);
+            }
+            primitive_indices
+        }
+        _ => (base_vertex..base_vertex + positions.len() as u32).collect(),
+    };
+    //镜像变换会翻转三角形的绕序
+    if world_transform.determinant() < 0.0 {
+        for triangle in primitive_indices.chunks_exact_mut(3) {
+            triangle.swap(1, 2);
+        }
+    }
+    indices.extend(primitive_indices);
+
+    let mut uvs = Vec::new();
+    let mut uvs_1 = Vec::new();
+    let mut tex_coord_set = 0;
+    while let Some(tex_coords) = r.read_tex_coords(tex_coord_set) {
+        if tex_coord_set > 1 {
+            println!("忽略槽位{},只支持两套uv", tex_coord_set);
+            tex_coord_set += 1;
+            continue;
+        }
+        for tex_coord in tex_coords.into_f32().take(count) {
+            match tex_coord_set {
+                0 => uvs.push(Vector2::new(tex_coord[0] as f64, tex_coord[1] as f64)),
+                1 => uvs_1.push(Vector2::new(tex_coord[0] as f64, tex_coord[1] as f64)),
+                _ => unreachable!(),
+            }
+        }
+        tex_coord_set += 1;
+    }
+
+    let mut normals = Vec::new();
+    if let Some(iter) = r.read_normals() {
+        for v in iter.take(count) {
+            normals.push(v);
+        }
+    }
+
+    //法线用逆转置矩阵变换，非均匀缩放下才能保持垂直
+    let normal_transform = world_transform
+        .invert()
+        .unwrap_or_else(Matrix4::identity)
+        .transpose();
+
+    let size = positions.len();
+    for idx in 0..size {
+        let pos = world_transform.transform_point(Point3::from(positions[idx]));
+        let normal = normal_transform
+            .transform_vector(Vector3::from(normals[idx]))
+            .normalize();
+        let uv = uvs[idx];
+        //没有第二套uv时沿用第一套
+        let uv_1 = *uvs_1.get(idx).unwrap_or(&uv);
+        let vertex = Vertex {
+            pos: Point3::new(pos.x as f64, pos.y as f64, pos.z as f64),
+            color: Vector3::new(1.0, 1.0, 1.0),
+            normal: Vector3::new(normal.x as f64, normal.y as f64, normal.z as f64),
+            tex_coord: Vector2::new(uv[0] % 1.0, uv[1] % 1.0),
+            tex_coord_1: Vector2::new(uv_1[0] % 1.0, uv_1[1] % 1.0),
+        };
+        vertices.push(vertex);
+        bbox.append(&vertex.pos);
+    }
+}
+
 impl Hit for Model {
     fn hit(&self, r: &Ray, ray_t: &Interval, rec: &mut HitRecord) -> bool {
         let mut ray_t = ray_t.clone();
@@ -341,3 +492,93 @@ impl Hit for Model {
         self.triangles.random(origin)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //加载路径要求模型带有底色、法线和金属度贴图
+    const HELMET: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/../../assets/models/DamagedHelmet/glTF-Binary/DamagedHelmet.glb"
+    );
+
+    fn identity() -> Transform {
+        Transform::new(
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(1.0, 1.0, 1.0),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn z_up_centimeters_match_documented_example() {
+        let p = import_matrix(UpAxis::Z, 0.01).transform_point(Point3::new(1.0, 2.0, 3.0));
+        assert!((p - Point3::new(0.01, 0.03, -0.02)).magnitude() < 1e-6);
+    }
+
+    #[test]
+    fn import_matrix_never_mirrors() {
+        for up_axis in [UpAxis::Y, UpAxis::Z] {
+            assert!(import_matrix(up_axis, 2.0).determinant() > 0.0);
+        }
+        assert_eq!(import_matrix(UpAxis::Y, 1.0), Matrix4::identity());
+    }
+
+    #[test]
+    fn rejects_non_positive_unit_scale() {
+        for scale in [0.0, -1.0, f32::NAN] {
+            assert!(Model::new(HELMET, UpAxis::Y, scale, identity()).is_err());
+        }
+    }
+
+    #[test]
+    fn z_up_gltf_is_rotated_and_scaled() {
+        let y_up = Model::new(HELMET, UpAxis::Y, 1.0, identity()).unwrap();
+        let z_up = Model::new(HELMET, UpAxis::Z, 2.0, identity()).unwrap();
+
+        //Z朝上时原来的y范围变到-z上，并放大两倍
+        assert!((z_up.bbox.x.size() - 2.0 * y_up.bbox.x.size()).abs() < 1e-3);
+        assert!((z_up.bbox.z.min + 2.0 * y_up.bbox.y.max).abs() < 1e-3);
+        assert!((z_up.bbox.z.max + 2.0 * y_up.bbox.y.min).abs() < 1e-3);
+    }
+
+    //sparse替换后的位置，没有bufferView的TEXCOORD_0也只读4个顶点
+    #[test]
+    fn sparse_accessors_apply_overrides() {
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../../assets/models/SparseAccessor/glTF-Embedded/SparseAccessor.gltf"
+        );
+        let (document, buffers, _) = gltf::import(path).unwrap();
+        let primitive = document
+            .meshes()
+            .next()
+            .unwrap()
+            .primitives()
+            .next()
+            .unwrap();
+        let (mut indices, mut vertices, mut bbox) = (Vec::new(), Vec::new(), EMPTY);
+        append_gltf_primitive(
+            &primitive,
+            Matrix4::identity(),
+            &buffers,
+            &mut indices,
+            &mut vertices,
+            &mut bbox,
+        );
+
+        let positions = vertices.iter().map(|v| v.pos).collect::<Vec<_>>();
+        assert_eq!(
+            positions,
+            vec![
+                Point3::new(-0.5, -0.5, 0.0),
+                Point3::new(0.5, -0.5, 0.0),
+                Point3::new(0.5, 1.5, 0.0),
+                Point3::new(-0.5, 1.5, 0.0),
+            ]
+        );
+        assert_eq!(indices.len(), 6);
+    }
+}
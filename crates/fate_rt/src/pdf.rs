@@ -3,9 +3,10 @@ use std::f64::consts::PI;
 use cgmath::{InnerSpace, Point3, Vector3};
 
 use crate::{
+    environment::EnvironmentLight,
     hit::Hit,
     onb::Onb,
-    utils::{random_cosine_direction, random_double_range, random_in_unit_sphere},
+    utils::{random_cosine_direction, random_double, random_double_range, random_in_unit_sphere},
 };
 
 pub trait Pdf {
@@ -48,6 +49,64 @@ impl Pdf for CosinePdf {
     }
 }
 
+//按GGX法线分布采样半程向量，再由观察方向反射得到出射方向
+//view为指向观察者的方向，返回的pdf已换算到出射方向的立体角上
+pub struct GgxPdf {
+    uvw: Onb,
+    alpha: f64,
+    view: Vector3<f64>,
+}
+
+impl GgxPdf {
+    pub fn new(roughness: f64, normal: Vector3<f64>, view: Vector3<f64>) -> Self {
+        //粗糙度过小时分布退化为冲激，pdf数值不稳定
+        let roughness = roughness.clamp(0.03, 1.0);
+        Self {
+            uvw: Onb::new_from_w(normal),
+            alpha: roughness * roughness,
+            view: view.normalize(),
+        }
+    }
+
+    fn distribution(&self, n_dot_h: f64) -> f64 {
+        let alpha2 = self.alpha * self.alpha;
+        let d = n_dot_h * n_dot_h * (alpha2 - 1.0) + 1.0;
+        alpha2 / (PI * d * d)
+    }
+}
+
+impl Pdf for GgxPdf {
+    fn value(&self, direction: Vector3<f64>) -> f64 {
+        let direction = direction.normalize();
+        let h = self.view + direction;
+        if h.magnitude2() <= 0.0 {
+            return 0.0;
+        }
+        let h = h.normalize();
+        let n_dot_h = Vector3::dot(h, self.uvw.w());
+        let v_dot_h = Vector3::dot(self.view, h).abs();
+        if n_dot_h <= 0.0 || v_dot_h <= 0.0 {
+            return 0.0;
+        }
+        self.distribution(n_dot_h) * n_dot_h / (4.0 * v_dot_h)
+    }
+
+    fn generate(&self) -> Vector3<f64> {
+        let r1 = random_double();
+        let r2 = random_double();
+        let alpha2 = self.alpha * self.alpha;
+
+        let phi = 2.0 * PI * r1;
+        let cos_theta = ((1.0 - r2) / (1.0 + (alpha2 - 1.0) * r2)).sqrt();
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+
+        let h = self
+            .uvw
+            .local(phi.cos() * sin_theta, phi.sin() * sin_theta, cos_theta);
+        2.0 * Vector3::dot(self.view, h) * h - self.view
+    }
+}
+
 pub struct HittablePdf<'a> {
     pub objects: &'a dyn Hit,
     pub origin: Point3<f64>,
@@ -69,23 +128,53 @@ impl Pdf for HittablePdf<'_> {
     }
 }
 
+//按环境贴图亮度采样方向，与BSDF采样混合后即为balance heuristic的MIS
+pub struct EnvironmentPdf<'a> {
+    environment: &'a EnvironmentLight,
+}
+
+impl<'a> EnvironmentPdf<'a> {
+    pub fn new(environment: &'a EnvironmentLight) -> Self {
+        Self { environment }
+    }
+}
+
+impl Pdf for EnvironmentPdf<'_> {
+    fn value(&self, direction: Vector3<f64>) -> f64 {
+        self.environment.pdf(direction)
+    }
+
+    fn generate(&self) -> Vector3<f64> {
+        self.environment.sample()
+    }
+}
+
+//weight为选择p[0]的概率
 pub struct MixturePdf<'a> {
     pub p: [&'a dyn Pdf; 2],
+    pub weight: f64,
 }
 
 impl<'a> MixturePdf<'a> {
     pub fn new(p0: &'a dyn Pdf, p1: &'a dyn Pdf) -> Self {
-        Self { p: [p0, p1] }
+        Self::new_with_weight(p0, p1, 0.5)
+    }
+
+    pub fn new_with_weight(p0: &'a dyn Pdf, p1: &'a dyn Pdf, weight: f64) -> Self {
+        Self {
+            p: [p0, p1],
+            weight: weight.clamp(0.0, 1.0),
+        }
     }
 }
 
 impl Pdf for MixturePdf<'_> {
     fn value(&self, direction: Vector3<f64>) -> f64 {
-        0.5 * self.p[0].value(direction) + 0.5 * self.p[1].value(direction)
+        self.weight * self.p[0].value(direction) + (1.0 - self.weight) * self.p[1].value(direction)
     }
 
     fn generate(&self) -> Vector3<f64> {
-        if random_double_range(0.0, 1.0) < 0.5 {
+        if random_double_range(0.0, 1.0) < self.weight {
             self.p[0].generate()
         } else {
             self.p[1].generate()
@@ -103,3 +192,66 @@ impl Pdf for NonePdf {
         Vector3::new(1.0, 0.0, 0.0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //按(cosθ, φ)网格在整个球面上积分
+    fn integrate(pdf: &dyn Pdf) -> f64 {
+        let n = 800;
+        let d_cos = 2.0 / n as f64;
+        let d_phi = 2.0 * PI / n as f64;
+        let mut sum = 0.0;
+        for i in 0..n {
+            let cos_theta = -1.0 + (i as f64 + 0.5) * d_cos;
+            let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+            for j in 0..n {
+                let phi = (j as f64 + 0.5) * d_phi;
+                let direction =
+                    Vector3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+                sum += pdf.value(direction) * d_cos * d_phi;
+            }
+        }
+        sum
+    }
+
+    #[test]
+    fn ggx_pdf_integrates_to_one_at_normal_incidence() {
+        let normal = Vector3::new(0.0, 0.0, 1.0);
+        for roughness in [0.5, 0.8, 1.0] {
+            let pdf = GgxPdf::new(roughness, normal, normal);
+            assert!(
+                (integrate(&pdf) - 1.0).abs() < 0.01,
+                "roughness {}",
+                roughness
+            );
+        }
+    }
+
+    #[test]
+    fn ggx_samples_have_positive_pdf() {
+        let normal = Vector3::new(0.0, 1.0, 0.0);
+        let view = Vector3::new(0.3, 1.0, 0.0).normalize();
+        let pdf = GgxPdf::new(0.4, normal, view);
+        for _ in 0..256 {
+            assert!(pdf.value(pdf.generate()) > 0.0);
+        }
+    }
+
+    #[test]
+    fn mixture_pdf_weights_its_components() {
+        let cosine = CosinePdf::new(Vector3::new(0.0, 0.0, 1.0));
+        let mixture = MixturePdf::new_with_weight(&SpherePdf, &cosine, 0.25);
+        let direction = Vector3::new(0.0, 0.0, 1.0);
+        let expected = 0.25 / (4.0 * PI) + 0.75 / PI;
+        assert!((mixture.value(direction) - expected).abs() < 1e-12);
+        assert!((integrate(&mixture) - 1.0).abs() < 0.01);
+
+        //超出[0, 1]的权重被截断
+        assert_eq!(
+            MixturePdf::new_with_weight(&SpherePdf, &cosine, 2.0).weight,
+            1.0
+        );
+    }
+}
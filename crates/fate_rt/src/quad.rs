@@ -1,4 +1,4 @@
-use std::sync::Arc;
+use std::{f64::consts::PI, sync::Arc};
 
 use cgmath::{EuclideanSpace, InnerSpace, Point3, Vector3};
 
@@ -10,6 +10,9 @@ use crate::material::*;
 use crate::ray::Ray;
 use crate::utils::random_double;
 
+//立体角小于该值时光源近似为一个点，球面矩形采样的数值误差反而更大，退回按面积采样
+const MIN_SOLID_ANGLE: f64 = 1e-4;
+
 pub struct Quad {
     q: Point3<f64>,
     u: Vector3<f64>,
@@ -46,6 +49,8 @@ impl Quad {
 
         rec.u = a;
         rec.v = b;
+        rec.u_1 = a;
+        rec.v_1 = b;
 
         true
     }
@@ -93,6 +98,8 @@ impl Hit for Quad {
             t: 0.0,
             u: 0.0,
             v: 0.0,
+            u_1: 0.0,
+            v_1: 0.0,
             front_face: true,
         };
         if !self.hit(
@@ -103,6 +110,10 @@ impl Hit for Quad {
             return 0.0;
         }
 
+        if let Some(rectangle) = SphericalRectangle::new(self, origin) {
+            return 1.0 / rectangle.solid_angle;
+        }
+
         let distance_squared = rec.t * rec.t * direction.magnitude2();
         let cosine = (Vector3::dot(direction, rec.normal) / direction.magnitude()).abs();
 
@@ -110,11 +121,122 @@ impl Hit for Quad {
     }
 
     fn random(&self, origin: Point3<f64>) -> Vector3<f64> {
+        if let Some(rectangle) = SphericalRectangle::new(self, origin) {
+            return rectangle.sample(random_double(), random_double()) - origin;
+        }
+
         let p = self.q + (random_double() * self.u) + (random_double() * self.v);
         return p - origin;
     }
 }
 
+//Ureña等人的球面矩形采样：在矩形对origin张成的立体角内均匀采样，近处或大面积光源噪声明显更低。
+//只适用于u、v正交的矩形
+struct SphericalRectangle {
+    origin: Point3<f64>,
+    x: Vector3<f64>,
+    y: Vector3<f64>,
+    z: Vector3<f64>,
+    z0: f64,
+    x0: f64,
+    x1: f64,
+    y0: f64,
+    y1: f64,
+    b0: f64,
+    b1: f64,
+    k: f64,
+    solid_angle: f64,
+}
+
+impl SphericalRectangle {
+    fn new(quad: &Quad, origin: Point3<f64>) -> Option<Self> {
+        let ex_len = quad.u.magnitude();
+        let ey_len = quad.v.magnitude();
+        if Vector3::dot(quad.u, quad.v).abs() > 1e-6 * ex_len * ey_len {
+            return None;
+        }
+
+        let x = quad.u / ex_len;
+        let y = quad.v / ey_len;
+        let mut z = Vector3::cross(x, y);
+
+        let d = quad.q - origin;
+        let mut z0 = Vector3::dot(d, z);
+        if z0 > 0.0 {
+            z = -z;
+            z0 = -z0;
+        }
+        if z0.abs() < 1e-8 {
+            return None;
+        }
+
+        let x0 = Vector3::dot(d, x);
+        let y0 = Vector3::dot(d, y);
+        let x1 = x0 + ex_len;
+        let y1 = y0 + ey_len;
+
+        let v00 = Vector3::new(x0, y0, z0);
+        let v01 = Vector3::new(x0, y1, z0);
+        let v10 = Vector3::new(x1, y0, z0);
+        let v11 = Vector3::new(x1, y1, z0);
+
+        let n0 = Vector3::cross(v00, v10).normalize();
+        let n1 = Vector3::cross(v10, v11).normalize();
+        let n2 = Vector3::cross(v11, v01).normalize();
+        let n3 = Vector3::cross(v01, v00).normalize();
+
+        let g0 = (-Vector3::dot(n0, n1)).clamp(-1.0, 1.0).acos();
+        let g1 = (-Vector3::dot(n1, n2)).clamp(-1.0, 1.0).acos();
+        let g2 = (-Vector3::dot(n2, n3)).clamp(-1.0, 1.0).acos();
+        let g3 = (-Vector3::dot(n3, n0)).clamp(-1.0, 1.0).acos();
+
+        let k = 2.0 * PI - g2 - g3;
+        let solid_angle = g0 + g1 - k;
+        if solid_angle.is_nan() || solid_angle <= MIN_SOLID_ANGLE {
+            return None;
+        }
+
+        Some(Self {
+            origin,
+            x,
+            y,
+            z,
+            z0,
+            x0,
+            x1,
+            y0,
+            y1,
+            b0: n0.z,
+            b1: n2.z,
+            k,
+            solid_angle,
+        })
+    }
+
+    fn sample(&self, u: f64, v: f64) -> Point3<f64> {
+        let au = u * self.solid_angle + self.k;
+        let fu = (au.cos() * self.b0 - self.b1) / au.sin();
+        let cu = (1.0 / (fu * fu + self.b0 * self.b0).sqrt()).copysign(fu);
+        let cu = cu.clamp(-1.0, 1.0);
+
+        let xu = -(cu * self.z0) / (1.0 - cu * cu).max(1e-12).sqrt();
+        let xu = xu.clamp(self.x0, self.x1);
+
+        let d = (xu * xu + self.z0 * self.z0).sqrt();
+        let h0 = self.y0 / (d * d + self.y0 * self.y0).sqrt();
+        let h1 = self.y1 / (d * d + self.y1 * self.y1).sqrt();
+        let hv = h0 + v * (h1 - h0);
+        let hv2 = hv * hv;
+        let yv = if hv2 < 1.0 - 1e-6 {
+            (hv * d) / (1.0 - hv2).sqrt()
+        } else {
+            self.y1
+        };
+
+        self.origin + xu * self.x + yv * self.y + self.z0 * self.z
+    }
+}
+
 pub fn make_box(a: Point3<f64>, b: Point3<f64>, mat: Arc<dyn Scatter>) -> Arc<HittableList> {
     let mut sides = HittableList::default();
 
@@ -164,3 +286,65 @@ pub fn make_box(a: Point3<f64>, b: Point3<f64>, mat: Arc<dyn Scatter>) -> Arc<Hi
 
     Arc::new(sides)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square(half: f64) -> Quad {
+        Quad::new(
+            Point3::new(-half, -half, 0.0),
+            Vector3::new(2.0 * half, 0.0, 0.0),
+            Vector3::new(0.0, 2.0 * half, 0.0),
+            Arc::new(Metal::new(Vector3::new(0.0, 0.0, 0.0), 0.0)),
+        )
+    }
+
+    #[test]
+    fn solid_angle_matches_closed_form() {
+        let (half, distance) = (1.0, 2.0);
+        let quad = square(half);
+        let origin = Point3::new(0.0, 0.0, distance);
+        let rectangle = SphericalRectangle::new(&quad, origin).unwrap();
+
+        //正上方看边长2a的正方形：4·asin(a²/(a²+d²))
+        let expected = 4.0 * (half * half / (half * half + distance * distance)).asin();
+        assert!((rectangle.solid_angle - expected).abs() < 1e-9);
+    }
+
+    #[test]
+    fn samples_hit_quad_with_uniform_pdf() {
+        let quad = square(1.0);
+        let origin = Point3::new(0.3, -0.2, 1.5);
+        let rectangle = SphericalRectangle::new(&quad, origin).unwrap();
+
+        for i in 0..16 {
+            for j in 0..16 {
+                let (u, v) = ((i as f64 + 0.5) / 16.0, (j as f64 + 0.5) / 16.0);
+                let direction = rectangle.sample(u, v) - origin;
+                let pdf = quad.pdf_value(origin, direction);
+                assert!((pdf * rectangle.solid_angle - 1.0).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn falls_back_to_area_sampling_in_plane_and_for_parallelograms() {
+        let quad = square(1.0);
+        assert!(SphericalRectangle::new(&quad, Point3::new(3.0, 0.0, 0.0)).is_none());
+
+        let skewed = Quad::new(
+            Point3::new(0.0, 0.0, 0.0),
+            Vector3::new(1.0, 0.0, 0.0),
+            Vector3::new(0.5, 1.0, 0.0),
+            Arc::new(Metal::new(Vector3::new(0.0, 0.0, 0.0), 0.0)),
+        );
+        assert!(SphericalRectangle::new(&skewed, Point3::new(0.5, 0.5, 1.0)).is_none());
+    }
+
+    #[test]
+    fn tiny_solid_angle_falls_back_to_area_sampling() {
+        let quad = square(0.001);
+        assert!(SphericalRectangle::new(&quad, Point3::new(0.0, 0.0, 10.0)).is_none());
+    }
+}
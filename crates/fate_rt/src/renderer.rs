@@ -4,31 +4,162 @@ use anyhow::Result;
 use cgmath::{Point3, Vector3};
 
 use crate::{
-    camera::Camera,
+    camera::{Camera, SamplePattern, ToneMap},
+    environment::EnvironmentLight,
     hit::{RotateY, Translate},
     hittable_list::HittableList,
     material::{DiffuseLight, Lambertian, Metal, Scatter},
-    model::Model,
+    model::{Model, UpAxis},
     quad::{make_box, Quad},
     texture::ImageTexture,
     transform::Transform,
 };
 
 #[derive(Copy, Clone, Debug)]
-pub struct Renderer {}
+pub struct RenderSettings {
+    pub samples_per_pixel: usize,
+    pub max_depth: usize,
+    pub background: Vector3<f64>,
+    //0表示使用rayon默认的线程数
+    pub thread_count: usize,
+    pub tonemap: ToneMap,
+    pub sample_pattern: SamplePattern,
+    pub seed: u64,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            samples_per_pixel: 100,
+            max_depth: 30,
+            background: Vector3::new(0.0, 0.0, 0.0),
+            thread_count: 0,
+            tonemap: ToneMap::None,
+            sample_pattern: SamplePattern::Stratified,
+            seed: 0,
+        }
+    }
+}
+
+//与光栅化渲染器对比时使用的相机，坐标为glTF空间(Y轴朝上)
+#[derive(Copy, Clone, Debug)]
+pub struct View {
+    pub lookfrom: Point3<f64>,
+    pub lookat: Point3<f64>,
+    pub vup: Vector3<f64>,
+    //垂直视角，单位为度
+    pub vfov: f64,
+    pub width: usize,
+    pub height: usize,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Renderer {
+    settings: RenderSettings,
+}
 
 impl Renderer {
     pub fn new() -> Result<Self> {
-        Ok(Self {})
+        Self::new_with_settings(RenderSettings::default())
+    }
+
+    pub fn new_with_settings(settings: RenderSettings) -> Result<Self> {
+        Ok(Self { settings })
+    }
+
+    pub fn settings(&self) -> &RenderSettings {
+        &self.settings
     }
 
     pub fn render(&self, _width: usize, _height: usize, path: &Path) -> anyhow::Result<()> {
-        cornell_box(path);
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(self.settings.thread_count)
+            .build()?;
+        pool.install(|| cornell_box(&self.settings, path));
         Ok(())
     }
+
+    //只有环境光照明的glTF模型，返回逐行的RGB8像素，尺寸与view一致
+    pub fn render_gltf_to_buffer(
+        &self,
+        model_path: &Path,
+        environment_path: &Path,
+        environment_intensity: f64,
+        view: &View,
+    ) -> Result<Vec<u8>> {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(self.settings.thread_count)
+            .build()?;
+        pool.install(|| {
+            gltf_with_environment(
+                &self.settings,
+                model_path,
+                environment_path,
+                environment_intensity,
+                view,
+            )
+        })
+    }
+}
+
+fn gltf_with_environment(
+    settings: &RenderSettings,
+    model_path: &Path,
+    environment_path: &Path,
+    environment_intensity: f64,
+    view: &View,
+) -> Result<Vec<u8>> {
+    //glTF是Y轴朝上，按原样导入即与光栅化渲染器处在同一坐标系
+    let mut transform = Transform::new(
+        Vector3::new(0.0, 0.0, 0.0),
+        Vector3::new(0.0, 0.0, 0.0),
+        Vector3::new(1.0, 1.0, 1.0),
+    )?;
+    transform.update_matrix();
+    let model_path = model_path
+        .to_str()
+        .ok_or_else(|| anyhow::anyhow!("模型路径不是合法的UTF-8: {}", model_path.display()))?;
+    let model = Model::new(model_path, UpAxis::Y, 1.0, transform)?;
+
+    let mut world = HittableList::default();
+    world.add(Arc::new(model));
+    let lights = HittableList::default();
+
+    let environment = EnvironmentLight::from_path(environment_path, environment_intensity)?;
+
+    let mut cam = Camera::default();
+
+    cam.aspect_ratio = view.width as f64 / view.height as f64;
+    cam.image_width = view.width;
+    cam.samples_per_pixel = settings.samples_per_pixel;
+    cam.max_depth = settings.max_depth;
+    cam.background = settings.background;
+    cam.environment = Some(Arc::new(environment));
+    cam.tone_map = settings.tonemap;
+    cam.sample_pattern = settings.sample_pattern;
+    cam.seed = settings.seed;
+
+    cam.vfov = view.vfov;
+    cam.lookfrom = view.lookfrom;
+    cam.lookat = view.lookat;
+    cam.vup = view.vup;
+
+    cam.defocus_angle = 0.0;
+
+    //宽高比换算可能差一行，以view给出的尺寸为准
+    if cam.image_height() != view.height {
+        anyhow::bail!(
+            "无法得到{}x{}的输出，实际高度为{}",
+            view.width,
+            view.height,
+            cam.image_height()
+        );
+    }
+
+    Ok(cam.render_to_buffer(&world, &lights))
 }
 
-fn cornell_box(path: &Path) {
+fn cornell_box(settings: &RenderSettings, path: &Path) {
     let mut world = HittableList::default();
 
     let red: Arc<dyn Scatter> = Arc::new(Lambertian::new(Vector3::new(0.65, 0.05, 0.05)));
@@ -87,6 +218,7 @@ fn cornell_box(path: &Path) {
     let bunny = Arc::new(
         Model::new(
             "res/model/Duck/glTF/Duck.gltf",
+            UpAxis::Y,
             1.0,
             b_transform,
         )
@@ -104,6 +236,7 @@ fn cornell_box(path: &Path) {
     let dragon = Arc::new(
         Model::new(
             "res/model/FlightHelmet/glTF/FlightHelmet.gltf",
+            UpAxis::Y,
             100.0,
             d_transform,
         )
@@ -134,9 +267,12 @@ fn cornell_box(path: &Path) {
 
     cam.aspect_ratio = 1.0;
     cam.image_width = 400;
-    cam.samples_per_pixel = 100;
-    cam.max_depth = 30;
-    cam.background = Vector3::new(0.0, 0.0, 0.0);
+    cam.samples_per_pixel = settings.samples_per_pixel;
+    cam.max_depth = settings.max_depth;
+    cam.background = settings.background;
+    cam.tone_map = settings.tonemap;
+    cam.sample_pattern = settings.sample_pattern;
+    cam.seed = settings.seed;
 
     cam.vfov = 40.0;
     cam.lookfrom = Point3::new(278.0, 278.0, -800.0);
@@ -80,6 +80,7 @@ impl Hit for Sphere {
         let outward_normal = (hit_record.p - self.center) / self.radius;
         hit_record.set_face_normal(&ray, outward_normal);
         (hit_record.u, hit_record.v) = Self::get_sphere_uv(outward_normal);
+        (hit_record.u_1, hit_record.v_1) = (hit_record.u, hit_record.v);
 
         true
     }
@@ -96,6 +97,8 @@ impl Hit for Sphere {
             t: 0.0,
             u: 0.0,
             v: 0.0,
+            u_1: 0.0,
+            v_1: 0.0,
             front_face: true,
         };
         if !self.hit(
@@ -1,6 +1,7 @@
-use std::sync::Arc;
+use std::{path::Path, sync::Arc};
 
 use cgmath::{Point3, Vector3};
+use image::ImageError;
 
 use crate::image::Image;
 
@@ -70,19 +71,53 @@ impl Texture for CheckerTexture {
     }
 }
 
+//颜色贴图按sRGB存储，采样后要转到线性空间再参与光照；
+//法线、金属度/粗糙度等数据贴图存的就是线性值，不能再做转换
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorSpace {
+    Srgb,
+    Linear,
+}
+
+impl ColorSpace {
+    pub(crate) fn decode(self, value: f64) -> f64 {
+        match self {
+            ColorSpace::Linear => value,
+            ColorSpace::Srgb => {
+                if value <= 0.04045 {
+                    value / 12.92
+                } else {
+                    ((value + 0.055) / 1.055).powf(2.4)
+                }
+            }
+        }
+    }
+}
+
 pub struct ImageTexture {
     image: Image,
+    color_space: ColorSpace,
 }
 
 impl ImageTexture {
     pub fn new(filename: &str) -> Self {
-        Self {
-            image: Image::new(filename),
-        }
+        Self::new_with_image(Image::new(filename))
+    }
+
+    pub fn from_path(path: &Path) -> Result<Self, ImageError> {
+        Ok(Self::new_with_image(Image::from_path(path)?))
     }
 
     pub fn new_with_image(image: Image) -> Self {
-        Self { image }
+        Self::new_with_color_space(image, ColorSpace::Srgb)
+    }
+
+    pub fn new_with_color_space(image: Image, color_space: ColorSpace) -> Self {
+        Self { image, color_space }
+    }
+
+    pub fn color_space(&self) -> ColorSpace {
+        self.color_space
     }
 }
 
@@ -106,9 +141,36 @@ impl Texture for ImageTexture {
 
         let color_scale = 1.0 / 255.0;
         Vector3::new(
-            color_scale * pixel[0] as f64,
-            color_scale * pixel[1] as f64,
-            color_scale * pixel[2] as f64,
+            self.color_space.decode(color_scale * pixel[0] as f64),
+            self.color_space.decode(color_scale * pixel[1] as f64),
+            self.color_space.decode(color_scale * pixel[2] as f64),
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mid_gray() -> Image {
+        let pixels = image::RgbImage::from_pixel(2, 2, image::Rgb([128, 128, 128]));
+        Image::new_with_dyn_img(image::DynamicImage::ImageRgb8(pixels))
+    }
+
+    #[test]
+    fn linear_texture_returns_stored_value() {
+        let texture = ImageTexture::new_with_color_space(mid_gray(), ColorSpace::Linear);
+        let value = texture.value(0.5, 0.5, Point3::new(0.0, 0.0, 0.0));
+        assert!((value.x - 128.0 / 255.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn srgb_texture_is_linearized() {
+        let texture = ImageTexture::new_with_image(mid_gray());
+        assert_eq!(texture.color_space(), ColorSpace::Srgb);
+        let value = texture.value(0.5, 0.5, Point3::new(0.0, 0.0, 0.0));
+        assert!((value.x - 0.2158605).abs() < 1e-6);
+        assert_eq!(value.x, value.y);
+        assert_eq!(value.y, value.z);
+    }
+}
@@ -18,6 +18,7 @@ pub struct Vertex {
     pub(crate) color: Vector3<f64>,
     pub(crate) normal: Vector3<f64>,
     pub(crate) tex_coord: Vector2<f64>,
+    pub(crate) tex_coord_1: Vector2<f64>,
 }
 
 impl Vertex {
@@ -26,12 +27,14 @@ impl Vertex {
         color: Vector3<f64>,
         normal: Vector3<f64>,
         tex_coord: Vector2<f64>,
+        tex_coord_1: Vector2<f64>,
     ) -> Self {
         Self {
             pos,
             color,
             normal,
             tex_coord,
+            tex_coord_1,
         }
     }
 }
@@ -42,6 +45,7 @@ impl PartialEq for Vertex {
             && self.color == other.color
             && self.normal == other.normal
             && self.tex_coord == other.tex_coord
+            && self.tex_coord_1 == other.tex_coord_1
     }
 }
 
@@ -57,9 +61,20 @@ impl Hash for Vertex {
         self.color[2].to_bits().hash(state);
         self.tex_coord[0].to_bits().hash(state);
         self.tex_coord[1].to_bits().hash(state);
+        self.tex_coord_1[0].to_bits().hash(state);
+        self.tex_coord_1[1].to_bits().hash(state);
     }
 }
 
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Shading {
+    //插值顶点法线
+    #[default]
+    Smooth,
+    //使用三角形的几何法线，呈现面片效果
+    Flat,
+}
+
 pub struct Triangle {
     a: Vertex,
     b: Vertex,
@@ -68,6 +83,7 @@ pub struct Triangle {
     _normal_texture: Arc<Image>,
     bbox: Aabb,
     area: f64,
+    shading: Shading,
 }
 
 impl Triangle {
@@ -77,6 +93,17 @@ impl Triangle {
         c: Vertex,
         mat: Arc<dyn Scatter>,
         _normal_texture: Arc<Image>,
+    ) -> Self {
+        Self::new_with_shading(a, b, c, mat, _normal_texture, Shading::default())
+    }
+
+    pub fn new_with_shading(
+        a: Vertex,
+        b: Vertex,
+        c: Vertex,
+        mat: Arc<dyn Scatter>,
+        _normal_texture: Arc<Image>,
+        shading: Shading,
     ) -> Self {
         Self {
             a,
@@ -86,8 +113,13 @@ impl Triangle {
             _normal_texture,
             bbox: Aabb::new_with_points(&a.pos, &b.pos, &c.pos),
             area: (a.pos - c.pos).cross(a.pos - b.pos).magnitude() * 0.5,
+            shading,
         }
     }
+
+    pub fn shading(&self) -> Shading {
+        self.shading
+    }
 }
 
 impl Hit for Triangle {
@@ -123,7 +155,13 @@ impl Hit for Triangle {
 
             let alpha = 1.0 - beta - gamma;
 
-            let normal = self.a.normal * alpha + self.b.normal * beta + self.c.normal * gamma;
+            //set_face_normal会按光线方向翻转，几何法线的朝向不影响结果
+            let normal = match self.shading {
+                Shading::Smooth => {
+                    self.a.normal * alpha + self.b.normal * beta + self.c.normal * gamma
+                }
+                Shading::Flat => e1.cross(e2).normalize(),
+            };
 
             let u = self.a.tex_coord[0] * alpha
                 + self.b.tex_coord[0] * beta
@@ -131,11 +169,15 @@ impl Hit for Triangle {
             let v = self.a.tex_coord[1] * alpha
                 + self.b.tex_coord[1] * beta
                 + self.c.tex_coord[1] * gamma;
+            let tex_coord_1 =
+                self.a.tex_coord_1 * alpha + self.b.tex_coord_1 * beta + self.c.tex_coord_1 * gamma;
 
             rec.t = t;
             rec.p = intersection_point;
             rec.u = u;
             rec.v = v;
+            rec.u_1 = tex_coord_1.x;
+            rec.v_1 = tex_coord_1.y;
             rec.normal = normal;
             rec.mat = Arc::clone(&self.mat);
             rec.set_face_normal(r, rec.normal);
@@ -156,6 +198,8 @@ impl Hit for Triangle {
             t: 0.0,
             u: 0.0,
             v: 0.0,
+            u_1: 0.0,
+            v_1: 0.0,
             front_face: true,
         };
         if !self.hit(
@@ -185,3 +229,80 @@ impl Hit for Triangle {
         return p - origin;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::texture::Texture;
+
+    //把uv当作颜色返回，便于检查材质采样的是哪一套uv
+    struct UvTexture;
+
+    impl Texture for UvTexture {
+        fn value(&self, u: f64, v: f64, _p: Point3<f64>) -> Vector3<f64> {
+            Vector3::new(u, v, 0.0)
+        }
+    }
+
+    fn vertex(x: f64, y: f64, tex_coord: [f64; 2], tex_coord_1: [f64; 2]) -> Vertex {
+        Vertex::new(
+            Point3::new(x, y, 0.0),
+            Vector3::new(1.0, 1.0, 1.0),
+            Vector3::new(0.0, 0.0, 1.0),
+            Vector2::from(tex_coord),
+            Vector2::from(tex_coord_1),
+        )
+    }
+
+    fn hit(mat: Arc<dyn Scatter>) -> HitRecord {
+        let triangle = Triangle::new(
+            vertex(0.0, 0.0, [0.0, 0.0], [0.5, 0.5]),
+            vertex(1.0, 0.0, [1.0, 0.0], [0.5, 1.0]),
+            vertex(0.0, 1.0, [0.0, 1.0], [1.0, 0.5]),
+            mat,
+            Arc::new(Image::default()),
+        );
+        let mut rec = HitRecord {
+            p: Point3::new(0.0, 0.0, 0.0),
+            normal: Vector3::new(0.0, 0.0, 0.0),
+            mat: Arc::new(Metal::new(Vector3::new(0.0, 0.0, 0.0), 0.0)),
+            t: 0.0,
+            u: 0.0,
+            v: 0.0,
+            u_1: 0.0,
+            v_1: 0.0,
+            front_face: true,
+        };
+        let ray = Ray::new(Point3::new(0.25, 0.5, 1.0), Vector3::new(0.0, 0.0, -1.0));
+        assert!(triangle.hit(&ray, &Interval::new(0.0, f64::INFINITY), &mut rec));
+        rec
+    }
+
+    #[test]
+    fn hit_interpolates_both_uv_sets() {
+        let rec = hit(Arc::new(Lambertian::new(Vector3::new(1.0, 1.0, 1.0))));
+        let (u, v) = rec.tex_coord(0);
+        assert!((u - 0.25).abs() < 1e-9 && (v - 0.5).abs() < 1e-9);
+        let (u, v) = rec.tex_coord(1);
+        assert!((u - 0.75).abs() < 1e-9 && (v - 0.625).abs() < 1e-9);
+    }
+
+    #[test]
+    fn pbr_samples_the_selected_uv_set() {
+        for (set, expected) in [
+            (0, Vector3::new(0.25, 0.5, 0.0)),
+            (1, Vector3::new(0.75, 0.625, 0.0)),
+        ] {
+            let mat = Arc::new(PBR::new_with_tex_coords(
+                Arc::new(UvTexture),
+                Arc::new(UvTexture),
+                set,
+                0,
+            ));
+            let rec = hit(mat.clone());
+            let mut srec = ScatterRecord::default();
+            assert!(mat.scatter(&Ray::new(rec.p, -rec.normal), &rec, &mut srec));
+            assert!((srec.attenuation - expected).magnitude() < 1e-9);
+        }
+    }
+}
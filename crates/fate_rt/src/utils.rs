@@ -1,19 +1,73 @@
-use std::{f64::consts::PI, ops::Range};
+use std::{cell::RefCell, f64::consts::PI, ops::Range};
 
 use cgmath::{InnerSpace, Vector3};
-use rand::Rng;
+
+//PCG32，状态小、速度快，同样的种子在任何平台上生成同样的序列
+#[derive(Clone, Copy, Debug)]
+pub struct Pcg32 {
+    state: u64,
+    inc: u64,
+}
+
+impl Pcg32 {
+    const MULTIPLIER: u64 = 6364136223846793005;
+
+    pub fn new(seed: u64, stream: u64) -> Self {
+        let mut rng = Self {
+            state: 0,
+            inc: (stream << 1) | 1,
+        };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+        rng
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let old_state = self.state;
+        self.state = old_state
+            .wrapping_mul(Self::MULTIPLIER)
+            .wrapping_add(self.inc);
+        let xor_shifted = (((old_state >> 18) ^ old_state) >> 27) as u32;
+        let rot = (old_state >> 59) as u32;
+        xor_shifted.rotate_right(rot)
+    }
+
+    //[0, 1)，取高53位保证均匀
+    pub fn next_f64(&mut self) -> f64 {
+        let bits = ((self.next_u32() as u64) << 32) | self.next_u32() as u64;
+        (bits >> 11) as f64 * (1.0 / (1u64 << 53) as f64)
+    }
+}
+
+thread_local! {
+    //没有显式设置种子时用随机熵初始化，行为和之前的thread_rng一致
+    static THREAD_RNG: RefCell<Pcg32> = RefCell::new(Pcg32::new(rand::random(), 0));
+}
+
+//重置当前线程的随机数序列。渲染时每个像素都用基础种子和像素序号派生的子种子重置，
+//结果与线程数和调度顺序无关
+pub fn seed_thread_rng(seed: u64) {
+    THREAD_RNG.with(|rng| *rng.borrow_mut() = Pcg32::new(seed, 0));
+}
+
+//splitmix64，把基础种子和序号混合成互不相关的子种子
+pub fn derive_seed(seed: u64, index: u64) -> u64 {
+    let mut z = seed.wrapping_add(index.wrapping_add(1).wrapping_mul(0x9E3779B97F4A7C15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
 
 pub fn degrees_to_radians(degrees: f64) -> f64 {
     degrees * PI / 180.0
 }
 
 pub fn random(r: Range<f64>) -> Vector3<f64> {
-    let mut rng = rand::thread_rng();
-
     Vector3 {
-        x: rng.gen_range(r.clone()),
-        y: rng.gen_range(r.clone()),
-        z: rng.gen_range(r.clone()),
+        x: random_double_range(r.start, r.end),
+        y: random_double_range(r.start, r.end),
+        z: random_double_range(r.start, r.end),
     }
 }
 
@@ -64,10 +118,12 @@ pub fn refract(vec: &Vector3<f64>, n: &Vector3<f64>, etai_over_etat: f64) -> Vec
 }
 
 pub fn random_in_unit_disk() -> Vector3<f64> {
-    let mut rng = rand::thread_rng();
-
     loop {
-        let p = Vector3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), 0.0);
+        let p = Vector3::new(
+            random_double_range(-1.0, 1.0),
+            random_double_range(-1.0, 1.0),
+            0.0,
+        );
         if p.magnitude() < 1.0 {
             return p;
         }
@@ -75,7 +131,7 @@ pub fn random_in_unit_disk() -> Vector3<f64> {
 }
 
 pub fn random_double() -> f64 {
-    rand::random::<f64>()
+    THREAD_RNG.with(|rng| rng.borrow_mut().next_f64())
 }
 
 pub fn random_double_range(min: f64, max: f64) -> f64 {
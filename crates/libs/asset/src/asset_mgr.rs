@@ -99,7 +99,7 @@ impl AssetMgr {
     }
 
     fn internal_load(&self, path: &Path) -> Option<Arc<dyn Asset>> {
-        let extension = path.extension().unwrap().to_str().unwrap();
+        let extension = path.extension()?.to_str()?;
         let loader = self.internal_get_asset_loader_with_extension(extension)?;
         loader.load(path.to_str().unwrap())
     }
@@ -0,0 +1,160 @@
+use crate::mesh::read_meshes_from_gltf;
+use gltf::json::Root;
+use rendering::{
+    error::ModelLoadingError,
+    fscene::{generate_mips, write_fscene, FSceneData, FSceneImage},
+    texture::{build_rgba_buffer, classify_images},
+    util::parallel_map,
+};
+use std::{error::Error, path::Path, time::Instant};
+
+//把glTF/glb预处理成.fscene
+//顶点读取、法线和切线生成、网格优化、图片解码和mip生成都在这里完成，
+//加载.fscene时只解析JSON并把各段数据拷贝到GPU
+pub fn convert_gltf_to_fscene<P: AsRef<Path>, Q: AsRef<Path>>(
+    input: P,
+    output: Q,
+) -> Result<(), Box<dyn Error>> {
+    let (input, output) = (input.as_ref(), output.as_ref());
+    let start = Instant::now();
+    let (document, buffers, images) = gltf::import(input)
+        .map_err(|err| ModelLoadingError::new(format!("{}: {}", input.display(), err)))?;
+
+    let meshes = read_meshes_from_gltf(&document, &buffers);
+
+    //颜色图片在线性空间中生成mip，和运行时sRGB格式blit的结果一致
+    let textures = document.textures().collect::<Vec<_>>();
+    let (color_image_indices, _) = classify_images(&textures, document.materials());
+    let indexed_images = images.iter().enumerate().collect::<Vec<_>>();
+    let images = parallel_map(&indexed_images, |(index, image)| {
+        let pixels = build_rgba_buffer(image);
+        let srgb = color_image_indices.contains(index);
+        FSceneImage {
+            width: image.width,
+            height: image.height,
+            levels: generate_mips(image.width, image.height, &pixels, srgb),
+        }
+    });
+
+    let mut root = document.into_json();
+    strip_data_uris(&mut root);
+
+    //原始缓冲只在加载时给动画和蒙皮读取，glb的二进制块也在其中
+    let data = FSceneData {
+        document: gltf::json::serialize::to_vec(&root)?,
+        buffers: buffers.into_iter().map(|buffer| buffer.0).collect(),
+        vertices: meshes.vertices,
+        indices: meshes.indices,
+        primitives: meshes.primitives,
+        images,
+    };
+    write_fscene(output, &data)?;
+
+    log::info!(
+        "{}转换为{}，耗时{}ms",
+        input.display(),
+        output.display(),
+        start.elapsed().as_millis()
+    );
+    Ok(())
+}
+
+//内嵌的base64数据已经解码写入各段，留在JSON里只会拖慢解析
+fn strip_data_uris(root: &mut Root) {
+    for buffer in root.buffers.iter_mut() {
+        if buffer
+            .uri
+            .as_ref()
+            .is_some_and(|uri| uri.starts_with("data:"))
+        {
+            buffer.uri = None;
+        }
+    }
+    //图片必须有uri或bufferView，保留一个空的data URI
+    for image in root.images.iter_mut() {
+        if image
+            .uri
+            .as_ref()
+            .is_some_and(|uri| uri.starts_with("data:"))
+        {
+            image.uri = Some(String::from("data:,"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rendering::fscene::FScene;
+
+    #[test]
+    fn converted_scene_matches_gltf_meshes() {
+        let input = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../../../assets/models/DamagedHelmet/glTF-Binary/DamagedHelmet.glb"
+        );
+        let output =
+            std::env::temp_dir().join(format!("fate_{}_helmet.fscene", std::process::id()));
+        convert_gltf_to_fscene(input, &output).unwrap();
+        let scene = FScene::read(&output).unwrap();
+        std::fs::remove_file(output).unwrap();
+
+        let (document, buffers, images) = gltf::import(input).unwrap();
+        let meshes = read_meshes_from_gltf(&document, &buffers);
+        assert_eq!(scene.primitives().len(), meshes.primitives.len());
+        assert_eq!(
+            scene.vertex_bytes().len(),
+            std::mem::size_of_val(&meshes.vertices[..])
+        );
+        assert_eq!(scene.index_bytes().len(), meshes.indices.len() * 4);
+
+        assert_eq!(scene.image_count(), images.len());
+        for (index, image) in images.iter().enumerate() {
+            assert_eq!(scene.image_size(index), (image.width, image.height));
+            let levels = scene.image_levels(index);
+            assert_eq!(levels[0], &build_rgba_buffer(image)[..]);
+        }
+
+        //内嵌数据不再留在JSON里，文档仍然能解析
+        let json = gltf::Gltf::from_slice_without_validation(scene.document()).unwrap();
+        assert_eq!(json.document.meshes().count(), document.meshes().count());
+    }
+
+    //转换后的文档仍然带着场景、节点和材质的extras
+    #[test]
+    fn converted_scene_keeps_extras() {
+        let input = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../../../assets/models/NodeExtras/glTF-Embedded/NodeExtras.gltf"
+        );
+        let output =
+            std::env::temp_dir().join(format!("fate_{}_extras.fscene", std::process::id()));
+        convert_gltf_to_fscene(input, &output).unwrap();
+        let scene = FScene::read(&output).unwrap();
+        std::fs::remove_file(output).unwrap();
+
+        let extras = |document: &gltf::Document| {
+            let scenes = document
+                .scenes()
+                .map(|s| s.extras().as_ref().map(|e| e.get().to_owned()));
+            let nodes = document
+                .nodes()
+                .map(|n| n.extras().as_ref().map(|e| e.get().to_owned()));
+            let materials = document
+                .materials()
+                .map(|m| m.extras().as_ref().map(|e| e.get().to_owned()));
+            scenes.chain(nodes).chain(materials).collect::<Vec<_>>()
+        };
+        let (document, _, _) = gltf::import(input).unwrap();
+        let converted = gltf::Gltf::from_slice_without_validation(scene.document()).unwrap();
+        assert_eq!(extras(&document).iter().flatten().count(), 4);
+        assert_eq!(extras(&converted.document), extras(&document));
+    }
+
+    #[test]
+    fn missing_input_is_an_error() {
+        let output = std::env::temp_dir().join("fate_missing_input.fscene");
+        assert!(convert_gltf_to_fscene("missing.gltf", &output).is_err());
+        assert!(!output.exists());
+    }
+}
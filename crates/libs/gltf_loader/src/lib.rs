@@ -1,3 +1,4 @@
+pub mod fscene;
 pub mod mesh;
 pub mod mikktspace;
-pub mod model;
\ No newline at end of file
+pub mod model;
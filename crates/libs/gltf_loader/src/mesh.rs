@@ -1,15 +1,21 @@
 use ash::vk;
-use cgmath::Vector3;
+use cgmath::{InnerSpace, Vector3, Zero};
 use gltf::{
     buffer::{Buffer as GltfBuffer, Data},
     mesh::{Bounds, Reader, Semantic},
     Document,
 };
-use rendering::{material::Material, vertex::{IndexBuffer, ModelVertex, VertexBuffer}, Aabb};
+use rendering::fscene::FScenePrimitive;
+use rendering::{
+    material::{Material, MaterialOverride},
+    util::parallel_map,
+    vertex::{IndexBuffer, ModelVertex, VertexBuffer},
+    Aabb,
+};
 use std::{mem::size_of, sync::Arc};
 use vulkan::*;
 
-use crate::mikktspace::generate_tangents;
+use crate::mikktspace::{generate_tangents, orthonormalize_tangents};
 
 pub struct Mesh {
     primitives: Vec<Primitive>,
@@ -19,7 +25,9 @@ pub struct Mesh {
 impl Mesh {
     fn new(primitives: Vec<Primitive>) -> Self {
         let aabbs = primitives.iter().map(|p| p.aabb()).collect::<Vec<_>>();
-        let aabb = Aabb::union(&aabbs).unwrap();
+        //primitive全部读取失败时退化为原点处的点
+        let aabb =
+            Aabb::union(&aabbs).unwrap_or_else(|| Aabb::new(Vector3::zero(), Vector3::zero()));
         Mesh { primitives, aabb }
     }
 }
@@ -33,9 +41,21 @@ impl Mesh {
         self.primitives.len()
     }
 
+    pub fn vertex_count(&self) -> usize {
+        self.primitives.iter().map(Primitive::vertex_count).sum()
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.primitives.iter().map(Primitive::triangle_count).sum()
+    }
+
     pub fn aabb(&self) -> Aabb<f32> {
         self.aabb
     }
+
+    pub(crate) fn primitive_mut(&mut self, index: usize) -> &mut Primitive {
+        &mut self.primitives[index]
+    }
 }
 
 pub struct Primitive {
@@ -43,10 +63,13 @@ pub struct Primitive {
     vertices: VertexBuffer,
     indices: Option<IndexBuffer>,
     material: Material,
+    //第一次覆盖材质参数前的值，恢复时使用
+    asset_override: Option<MaterialOverride>,
     aabb: Aabb<f32>,
 }
 
 impl Primitive {
+    //在整个模型中的序号，从0开始连续编号，各个pass按它查找primitive的描述符和变换
     pub fn index(&self) -> usize {
         self.index
     }
@@ -59,10 +82,44 @@ impl Primitive {
         &self.indices
     }
 
+    pub fn vertex_count(&self) -> usize {
+        self.vertices.element_count() as usize
+    }
+
+    //按三角形列表统计，没有索引时每三个顶点一个三角形
+    pub fn triangle_count(&self) -> usize {
+        let count = self
+            .indices
+            .as_ref()
+            .map_or(self.vertices.element_count(), IndexBuffer::element_count);
+        count as usize / 3
+    }
+
     pub fn material(&self) -> Material {
         self.material
     }
 
+    pub(crate) fn material_mut(&mut self) -> &mut Material {
+        &mut self.material
+    }
+
+    pub fn is_material_overridden(&self) -> bool {
+        self.asset_override.is_some()
+    }
+
+    pub(crate) fn set_material_override(&mut self, material_override: MaterialOverride) {
+        if self.asset_override.is_none() {
+            self.asset_override = Some(self.material.get_override());
+        }
+        self.material.apply_override(material_override);
+    }
+
+    pub(crate) fn reset_material_override(&mut self) {
+        if let Some(asset_override) = self.asset_override.take() {
+            self.material.apply_override(asset_override);
+        }
+    }
+
     pub fn aabb(&self) -> Aabb<f32> {
         self.aabb
     }
@@ -80,6 +137,13 @@ struct PrimitiveData {
     aabb: Aabb<f32>,
 }
 
+//所有primitive预处理后的顶点和索引，转换成.fscene时原样写入
+pub struct MeshesData {
+    pub vertices: Vec<ModelVertex>,
+    pub indices: Vec<u32>,
+    pub primitives: Vec<FScenePrimitive>,
+}
+
 pub struct Meshes {
     pub meshes: Vec<Mesh>,
     pub vertices: Buffer,
@@ -92,164 +156,369 @@ pub fn create_meshes_from_gltf(
     document: &Document,
     buffers: &[Data],
 ) -> Option<Meshes> {
-    let mut meshes_data = Vec::<Vec<PrimitiveData>>::new();
+    let data = read_meshes_from_gltf(document, buffers);
+    let vertices = unsafe {
+        std::slice::from_raw_parts(
+            data.vertices.as_ptr() as *const u8,
+            data.vertices.len() * size_of::<ModelVertex>(),
+        )
+    };
+    let indices = unsafe {
+        std::slice::from_raw_parts(
+            data.indices.as_ptr() as *const u8,
+            data.indices.len() * size_of::<u32>(),
+        )
+    };
+    create_meshes(
+        context,
+        command_buffer,
+        document,
+        vertices,
+        indices,
+        &data.primitives,
+    )
+}
+
+pub fn read_meshes_from_gltf(document: &Document, buffers: &[Data]) -> MeshesData {
+    //读取顶点属性和生成切线只依赖CPU，按primitive分发到多个线程并行处理
+    let gltf_primitives = document
+        .meshes()
+        .flat_map(|mesh| {
+            let mesh_index = mesh.index();
+            mesh.primitives().map(move |p| (mesh_index, p))
+        })
+        .collect::<Vec<_>>();
+    let primitives_vertices = parallel_map(&gltf_primitives, |(_, primitive)| {
+        read_primitive_vertices(primitive, buffers)
+    });
+
     let mut all_vertices = Vec::<ModelVertex>::new();
     let mut all_indices = Vec::<u32>::new();
+    let mut primitives = Vec::new();
 
-    let mut primitive_count = 0;
+    for ((mesh_index, primitive), data) in gltf_primitives.iter().zip(primitives_vertices) {
+        let Some((vertices, indices, tangents_valid)) = data else {
+            continue;
+        };
 
-    for mesh in document.meshes() {
-        let mut primitives_buffers = Vec::<PrimitiveData>::new();
-
-        for primitive in mesh.primitives() {
-            let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
-
-            if let Some(accessor) = primitive.get(&Semantic::Positions) {
-                let aabb = get_aabb(&primitive.bounding_box());
-                let positions = read_positions(&reader);
-                let normals = read_normals(&reader);
-                let tex_coords_0 = read_tex_coords(&reader, 0);
-                let tex_coords_1 = read_tex_coords(&reader, 1);
-                let tangents = read_tangents(&reader);
-                let weights = read_weights(&reader);
-                let joints = read_joints(&reader);
-                let colors = read_colors(&reader);
-
-                let mut vertices = positions
-                    .iter()
-                    .enumerate()
-                    .map(|(index, position)| {
-                        let position = *position;
-                        let normal = *normals.get(index).unwrap_or(&[1.0, 1.0, 1.0]);
-                        let tex_coords_0 = *tex_coords_0.get(index).unwrap_or(&[0.0, 0.0]);
-                        let tex_coords_1 = *tex_coords_1.get(index).unwrap_or(&[0.0, 0.0]);
-                        let tangent = *tangents.get(index).unwrap_or(&[1.0, 1.0, 1.0, 1.0]);
-                        let weights = *weights.get(index).unwrap_or(&[0.0, 0.0, 0.0, 0.0]);
-                        let joints = *joints.get(index).unwrap_or(&[0, 0, 0, 0]);
-                        let colors = *colors.get(index).unwrap_or(&[1.0, 1.0, 1.0, 1.0]);
-
-                        ModelVertex {
-                            position,
-                            normal,
-                            tex_coords_0,
-                            tex_coords_1,
-                            tangent,
-                            weights,
-                            joints,
-                            colors,
-                        }
-                    })
-                    .collect::<Vec<_>>();
-
-                let indices = read_indices(&reader);
-
-                if !positions.is_empty()
-                    && !normals.is_empty()
-                    && !tex_coords_0.is_empty()
-                    && tangents.is_empty()
-                {
-                    generate_tangents(indices.as_deref(), &mut vertices);
-                }
-
-                let indices = indices.map(|indices| {
-                    let offset = all_indices.len() * size_of::<u32>();
-                    all_indices.extend_from_slice(&indices);
-                    (offset, indices.len())
-                });
-
-                let offset = all_vertices.len() * size_of::<ModelVertex>();
-                all_vertices.extend_from_slice(&vertices);
-
-                let material = primitive.material().into();
-
-                let index = primitive_count;
-                primitive_count += 1;
-
-                primitives_buffers.push(PrimitiveData {
-                    index,
-                    indices,
-                    vertices: (offset, accessor.count()),
-                    material,
-                    aabb,
-                });
-            }
-        }
+        //sparse替换后的位置不一定在accessor记录的min/max之内，直接用读到的顶点计算
+        let aabb = is_sparse_positions(primitive)
+            .then(|| get_vertices_aabb(&vertices))
+            .flatten()
+            .unwrap_or_else(|| get_aabb(&primitive.bounding_box()));
 
-        meshes_data.push(primitives_buffers);
+        let indices = indices.map(|indices| {
+            let offset = all_indices.len();
+            all_indices.extend_from_slice(&indices);
+            (offset, indices.len())
+        });
+
+        let offset = all_vertices.len();
+        all_vertices.extend_from_slice(&vertices);
+
+        primitives.push(FScenePrimitive {
+            mesh_index: *mesh_index,
+            primitive_index: primitive.index(),
+            vertices: (offset, vertices.len()),
+            indices,
+            use_vertex_colors: primitive.get(&Semantic::Colors(0)).is_some(),
+            tangents_valid,
+            aabb,
+        });
     }
 
-    if !meshes_data.is_empty() {
-        let indices = if all_indices.is_empty() {
-            None
-        } else {
-            let (indices, staged_indices) = cmd_create_device_local_buffer_with_data::<u8, _>(
-                context,
-                command_buffer,
-                vk::BufferUsageFlags::INDEX_BUFFER,
-                &all_indices,
-            );
-            Some((Arc::new(indices), staged_indices))
-        };
+    MeshesData {
+        vertices: all_vertices,
+        indices: all_indices,
+        primitives,
+    }
+}
+
+//把预处理好的顶点和索引直接上传到GPU，材质按primitive记录的位置从glTF文档中取回
+//vertices和indices是ModelVertex和u32紧密排列的字节，primitive的范围按元素计
+pub fn create_meshes(
+    context: &Arc<Context>,
+    command_buffer: vk::CommandBuffer,
+    document: &Document,
+    vertices: &[u8],
+    indices: &[u8],
+    primitives: &[FScenePrimitive],
+) -> Option<Meshes> {
+    let meshes_data = group_primitives(document, primitives);
+
+    if meshes_data.is_empty() {
+        return None;
+    }
 
-        let (vertices, staged_vertices) = cmd_create_device_local_buffer_with_data::<u8, _>(
+    let indices = if indices.is_empty() {
+        None
+    } else {
+        let (indices, staged_indices) = cmd_create_device_local_buffer_with_data::<u8, _>(
             context,
             command_buffer,
-            vk::BufferUsageFlags::VERTEX_BUFFER,
-            &all_vertices,
+            vk::BufferUsageFlags::INDEX_BUFFER,
+            indices,
+            "Model.Indices",
         );
-        let vertices = Arc::new(vertices);
+        Some((Arc::new(indices), staged_indices))
+    };
+
+    let (vertices, staged_vertices) = cmd_create_device_local_buffer_with_data::<u8, _>(
+        context,
+        command_buffer,
+        //计算着色器蒙皮时作为存储缓冲读取
+        vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::STORAGE_BUFFER,
+        vertices,
+        "Model.Vertices",
+    );
+    let vertices = Arc::new(vertices);
+
+    let meshes = meshes_data
+        .iter()
+        .map(|primitives_buffers| {
+            let primitives = primitives_buffers
+                .iter()
+                .map(|buffers| {
+                    let mesh_vertices = buffers.vertices;
+                    let vertex_buffer = VertexBuffer::new(
+                        Arc::clone(&vertices),
+                        mesh_vertices.0 as _,
+                        mesh_vertices.1 as _,
+                    );
+
+                    let index_buffer = buffers.indices.map(|mesh_indices| {
+                        IndexBuffer::new(
+                            Arc::clone(indices.as_ref().map(|(indices, _)| indices).unwrap()),
+                            mesh_indices.0 as _,
+                            mesh_indices.1 as _,
+                        )
+                    });
+
+                    Primitive {
+                        index: buffers.index,
+                        vertices: vertex_buffer,
+                        indices: index_buffer,
+                        material: buffers.material,
+                        asset_override: None,
+                        aabb: buffers.aabb,
+                    }
+                })
+                .collect::<Vec<_>>();
+            Mesh::new(primitives)
+        })
+        .collect();
+
+    Some(Meshes {
+        meshes,
+        vertices: staged_vertices,
+        indices: indices.map(|(_, staged_indices)| staged_indices),
+    })
+}
 
-        let meshes = meshes_data
-            .iter()
-            .map(|primitives_buffers| {
-                let primitives = primitives_buffers
-                    .iter()
-                    .map(|buffers| {
-                        let mesh_vertices = buffers.vertices;
-                        let vertex_buffer = VertexBuffer::new(
-                            Arc::clone(&vertices),
-                            mesh_vertices.0 as _,
-                            mesh_vertices.1 as _,
-                        );
-
-                        let index_buffer = buffers.indices.map(|mesh_indices| {
-                            IndexBuffer::new(
-                                Arc::clone(indices.as_ref().map(|(indices, _)| indices).unwrap()),
-                                mesh_indices.0 as _,
-                                mesh_indices.1 as _,
-                            )
-                        });
-
-                        Primitive {
-                            index: buffers.index,
-                            vertices: vertex_buffer,
-                            indices: index_buffer,
-                            material: buffers.material,
-                            aabb: buffers.aabb,
-                        }
-                    })
-                    .collect::<Vec<_>>();
-                Mesh::new(primitives)
-            })
-            .collect();
+//按网格分组primitive并取回材质，序号按实际创建的primitive连续分配
+fn group_primitives(
+    document: &Document,
+    primitives: &[FScenePrimitive],
+) -> Vec<Vec<PrimitiveData>> {
+    let gltf_meshes = document.meshes().collect::<Vec<_>>();
+    let mut meshes_data = gltf_meshes
+        .iter()
+        .map(|_| Vec::<PrimitiveData>::new())
+        .collect::<Vec<_>>();
+
+    let mut primitive_count = 0;
+    for primitive in primitives.iter() {
+        let gltf_primitive = gltf_meshes
+            .get(primitive.mesh_index)
+            .and_then(|mesh| mesh.primitives().nth(primitive.primitive_index));
+        let Some(gltf_primitive) = gltf_primitive else {
+            log::warn!(
+                "网格{}中没有primitive {}",
+                primitive.mesh_index,
+                primitive.primitive_index
+            );
+            continue;
+        };
 
-        return Some(Meshes {
-            meshes,
-            vertices: staged_vertices,
-            indices: indices.map(|(_, staged_indices)| staged_indices),
+        let mut material: Material = gltf_primitive.material().into();
+        material.set_use_vertex_colors(primitive.use_vertex_colors);
+        material.set_use_derivative_tangents(!primitive.tangents_valid);
+
+        //跳过的primitive不占序号
+        let index = primitive_count;
+        primitive_count += 1;
+
+        let (vertex_offset, vertex_count) = primitive.vertices;
+        meshes_data[primitive.mesh_index].push(PrimitiveData {
+            index,
+            indices: primitive
+                .indices
+                .map(|(offset, count)| (offset * size_of::<u32>(), count)),
+            vertices: (vertex_offset * size_of::<ModelVertex>(), vertex_count),
+            material,
+            aabb: primitive.aabb,
         });
     }
 
-    None
+    meshes_data
 }
 
-fn read_indices<'a, 's, F>(reader: &Reader<'a, 's, F>) -> Option<Vec<u32>>
+fn read_primitive_vertices(
+    primitive: &gltf::Primitive,
+    buffers: &[Data],
+) -> Option<(Vec<ModelVertex>, Option<Vec<u32>>, bool)> {
+    let count = primitive.get(&Semantic::Positions)?.count();
+
+    let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+    let positions = read_positions(primitive, &reader);
+    let normals = read_normals(&reader, count);
+    let tex_coords_0 = read_tex_coords(&reader, 0, count);
+    let tex_coords_1 = read_tex_coords(&reader, 1, count);
+    let tangents = read_tangents(&reader, count);
+    let weights = read_weights(&reader, count);
+    let joints = read_joints(&reader, count);
+    let colors = read_colors(&reader, count);
+
+    let mut vertices = positions
+        .iter()
+        .enumerate()
+        .map(|(index, position)| {
+            let position = *position;
+            let normal = *normals.get(index).unwrap_or(&[1.0, 1.0, 1.0]);
+            let tex_coords_0 = *tex_coords_0.get(index).unwrap_or(&[0.0, 0.0]);
+            let tex_coords_1 = *tex_coords_1.get(index).unwrap_or(&[0.0, 0.0]);
+            let tangent = *tangents.get(index).unwrap_or(&[1.0, 1.0, 1.0, 1.0]);
+            let weights = *weights.get(index).unwrap_or(&[0.0, 0.0, 0.0, 0.0]);
+            let joints = *joints.get(index).unwrap_or(&[0, 0, 0, 0]);
+            let colors = *colors.get(index).unwrap_or(&[1.0, 1.0, 1.0, 1.0]);
+
+            ModelVertex {
+                position,
+                normal,
+                tex_coords_0,
+                tex_coords_1,
+                tangent,
+                weights,
+                joints,
+                colors,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let mut indices = read_indices(primitive, &reader);
+
+    if !positions.is_empty() && normals.is_empty() {
+        //glTF规范要求缺少法线时使用平面法线
+        log::warn!("图元缺少法线，生成平面法线");
+        (vertices, indices) = generate_flat_normals(vertices, indices);
+    }
+
+    //切线生成失败时由着色器用屏幕空间导数重建切线空间
+    let mut tangents_valid = true;
+    if !positions.is_empty() && tangents.is_empty() {
+        if tex_coords_0.is_empty() {
+            //没有UV无法计算切线空间，至少保证切线与法线正交
+            orthonormalize_tangents(&mut vertices);
+            tangents_valid = false;
+        } else {
+            tangents_valid = generate_tangents(indices.as_mut(), &mut vertices);
+        }
+    }
+
+    #[cfg(feature = "mesh_optimization")]
+    let (vertices, indices) = optimize_primitive(vertices, indices);
+
+    Some((vertices, indices, tangents_valid))
+}
+
+//平面法线要求每个三角形有独立的顶点，因此先展开索引，再按面法线赋值
+fn generate_flat_normals(
+    vertices: Vec<ModelVertex>,
+    indices: Option<Vec<u32>>,
+) -> (Vec<ModelVertex>, Option<Vec<u32>>) {
+    let triangle_indices = match indices {
+        Some(indices) => indices,
+        None => (0..vertices.len() as u32).collect(),
+    };
+
+    let mut flat_vertices = Vec::with_capacity(triangle_indices.len());
+    for face in triangle_indices.chunks_exact(3) {
+        let corners = [face[0], face[1], face[2]].map(|i| vertices.get(i as usize).copied());
+        let [Some(a), Some(b), Some(c)] = corners else {
+            continue;
+        };
+
+        let p0 = Vector3::from(a.position);
+        let normal = (Vector3::from(b.position) - p0).cross(Vector3::from(c.position) - p0);
+        let normal = if normal.magnitude2() > 0.0 {
+            normal.normalize()
+        } else {
+            Vector3::unit_z()
+        };
+
+        for mut vertex in [a, b, c] {
+            vertex.normal = normal.into();
+            flat_vertices.push(vertex);
+        }
+    }
+
+    let flat_indices = (0..flat_vertices.len() as u32).collect();
+    (flat_vertices, Some(flat_indices))
+}
+
+//重排索引提高顶点缓存命中率并减少overdraw，再按索引首次出现的顺序重排顶点，
+//三角形集合不变，只改变绘制顺序和顶点在缓冲中的位置
+#[cfg(feature = "mesh_optimization")]
+fn optimize_primitive(
+    vertices: Vec<ModelVertex>,
+    indices: Option<Vec<u32>>,
+) -> (Vec<ModelVertex>, Option<Vec<u32>>) {
+    let Some(indices) = indices else {
+        return (vertices, None);
+    };
+    if indices.len() % 3 != 0 || indices.iter().any(|i| *i as usize >= vertices.len()) {
+        log::warn!("索引数据无效，跳过网格优化");
+        return (vertices, Some(indices));
+    }
+
+    let mut indices = meshopt::optimize_vertex_cache(&indices, vertices.len());
+
+    let vertex_data = unsafe {
+        std::slice::from_raw_parts(
+            vertices.as_ptr() as *const u8,
+            vertices.len() * size_of::<ModelVertex>(),
+        )
+    };
+    //position是ModelVertex的第一个字段
+    if let Ok(adapter) = meshopt::VertexDataAdapter::new(vertex_data, size_of::<ModelVertex>(), 0) {
+        meshopt::optimize_overdraw_in_place(&mut indices, &adapter, 1.05);
+    }
+
+    let mut remap = vec![u32::MAX; vertices.len()];
+    let mut optimized_vertices = Vec::with_capacity(vertices.len());
+    for index in indices.iter_mut() {
+        let new_index = &mut remap[*index as usize];
+        if *new_index == u32::MAX {
+            *new_index = optimized_vertices.len() as u32;
+            optimized_vertices.push(vertices[*index as usize]);
+        }
+        *index = *new_index;
+    }
+
+    (optimized_vertices, Some(indices))
+}
+
+fn read_indices<'a, 's, F>(
+    primitive: &gltf::Primitive,
+    reader: &Reader<'a, 's, F>,
+) -> Option<Vec<u32>>
 where
     F: Clone + Fn(GltfBuffer<'a>) -> Option<&'s [u8]>,
 {
+    let count = primitive.indices()?.count();
     reader
         .read_indices()
-        .map(|indices| indices.into_u32().collect::<Vec<_>>())
+        .map(|indices| collect_items(indices.into_u32(), count))
 }
 
 fn get_aabb(bounds: &Bounds<[f32; 3]>) -> Aabb<f32> {
@@ -262,69 +531,429 @@ fn get_aabb(bounds: &Bounds<[f32; 3]>) -> Aabb<f32> {
     Aabb::new(min, max)
 }
 
-fn read_positions<'a, 's, F>(reader: &Reader<'a, 's, F>) -> Vec<[f32; 3]>
+fn is_sparse_positions(primitive: &gltf::Primitive) -> bool {
+    primitive
+        .get(&Semantic::Positions)
+        .is_some_and(|accessor| accessor.sparse().is_some())
+}
+
+fn get_vertices_aabb(vertices: &[ModelVertex]) -> Option<Aabb<f32>> {
+    let first = Vector3::from(vertices.first()?.position);
+    let (min, max) = vertices.iter().fold((first, first), |(min, max), vertex| {
+        let [x, y, z] = vertex.position;
+        (
+            Vector3::new(min.x.min(x), min.y.min(y), min.z.min(z)),
+            Vector3::new(max.x.max(x), max.y.max(y), max.z.max(z)),
+        )
+    });
+    Some(Aabb::new(min, max))
+}
+
+//gltf 1.4的sparse迭代器在没有bufferView时不会结束，size_hint在读过sparse值的数量之后还会溢出，
+//所以不能直接collect，逐个读取到accessor的数量为止
+fn collect_items<T>(items: impl Iterator<Item = T>, count: usize) -> Vec<T> {
+    let mut collected = Vec::with_capacity(count);
+    for item in items.take(count) {
+        collected.push(item);
+    }
+    collected
+}
+
+//reader的迭代器已经处理了sparse替换(包括没有bufferView、基础值为0的情况)，
+//但既没有bufferView也没有sparse的accessor会返回None，按规范这时全部为0
+fn read_positions<'a, 's, F>(
+    primitive: &gltf::Primitive,
+    reader: &Reader<'a, 's, F>,
+) -> Vec<[f32; 3]>
 where
     F: Clone + Fn(GltfBuffer<'a>) -> Option<&'s [u8]>,
 {
-    reader
-        .read_positions()
-        .expect("Position primitives should be present")
-        .collect()
+    reader.read_positions().map_or_else(
+        || {
+            let accessor = primitive
+                .get(&Semantic::Positions)
+                .expect("Position primitives should be present");
+            log::warn!("顶点位置accessor {}没有数据，按0初始化", accessor.index());
+            vec![[0.0; 3]; accessor.count()]
+        },
+        |positions| {
+            let count = primitive.get(&Semantic::Positions).map_or(0, |a| a.count());
+            collect_items(positions, count)
+        },
+    )
 }
 
-fn read_normals<'a, 's, F>(reader: &Reader<'a, 's, F>) -> Vec<[f32; 3]>
+fn read_normals<'a, 's, F>(reader: &Reader<'a, 's, F>, count: usize) -> Vec<[f32; 3]>
 where
     F: Clone + Fn(GltfBuffer<'a>) -> Option<&'s [u8]>,
 {
     reader
         .read_normals()
-        .map_or(vec![], |normals| normals.collect())
+        .map_or(vec![], |normals| collect_items(normals, count))
 }
 
-fn read_tex_coords<'a, 's, F>(reader: &Reader<'a, 's, F>, channel: u32) -> Vec<[f32; 2]>
+fn read_tex_coords<'a, 's, F>(
+    reader: &Reader<'a, 's, F>,
+    channel: u32,
+    count: usize,
+) -> Vec<[f32; 2]>
 where
     F: Clone + Fn(GltfBuffer<'a>) -> Option<&'s [u8]>,
 {
     reader
         .read_tex_coords(channel)
-        .map_or(vec![], |coords| coords.into_f32().collect())
+        .map_or(vec![], |coords| collect_items(coords.into_f32(), count))
 }
 
-fn read_tangents<'a, 's, F>(reader: &Reader<'a, 's, F>) -> Vec<[f32; 4]>
+fn read_tangents<'a, 's, F>(reader: &Reader<'a, 's, F>, count: usize) -> Vec<[f32; 4]>
 where
     F: Clone + Fn(GltfBuffer<'a>) -> Option<&'s [u8]>,
 {
     reader
         .read_tangents()
-        .map_or(vec![], |tangents| tangents.collect())
+        .map_or(vec![], |tangents| collect_items(tangents, count))
 }
 
-fn read_weights<'a, 's, F>(reader: &Reader<'a, 's, F>) -> Vec<[f32; 4]>
+fn read_weights<'a, 's, F>(reader: &Reader<'a, 's, F>, count: usize) -> Vec<[f32; 4]>
 where
     F: Clone + Fn(GltfBuffer<'a>) -> Option<&'s [u8]>,
 {
     reader
         .read_weights(0)
-        .map_or(vec![], |weights| weights.into_f32().collect())
+        .map_or(vec![], |weights| collect_items(weights.into_f32(), count))
 }
 
-fn read_joints<'a, 's, F>(reader: &Reader<'a, 's, F>) -> Vec<[u32; 4]>
+fn read_joints<'a, 's, F>(reader: &Reader<'a, 's, F>, count: usize) -> Vec<[u32; 4]>
 where
     F: Clone + Fn(GltfBuffer<'a>) -> Option<&'s [u8]>,
 {
     reader.read_joints(0).map_or(vec![], |joints| {
-        joints
+        let joints = joints
             .into_u16()
-            .map(|[x, y, z, w]| [u32::from(x), u32::from(y), u32::from(z), u32::from(w)])
-            .collect()
+            .map(|[x, y, z, w]| [u32::from(x), u32::from(y), u32::from(z), u32::from(w)]);
+        collect_items(joints, count)
     })
 }
 
-fn read_colors<'a, 's, F>(reader: &Reader<'a, 's, F>) -> Vec<[f32; 4]>
+fn read_colors<'a, 's, F>(reader: &Reader<'a, 's, F>, count: usize) -> Vec<[f32; 4]>
 where
     F: Clone + Fn(GltfBuffer<'a>) -> Option<&'s [u8]>,
 {
-    reader
-        .read_colors(0)
-        .map_or(vec![], |colors| colors.into_rgba_f32().collect())
+    reader.read_colors(0).map_or(vec![], |colors| {
+        collect_items(colors.into_rgba_f32(), count)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::Vector2;
+    use gltf::Gltf;
+
+    //XY平面上的四边形，只有位置和索引，没有法线
+    const QUAD_DOCUMENT: &str = r#"{
+        "asset": { "version": "2.0" },
+        "buffers": [{ "byteLength": 72 }],
+        "bufferViews": [
+            { "buffer": 0, "byteOffset": 0, "byteLength": 48 },
+            { "buffer": 0, "byteOffset": 48, "byteLength": 24 }
+        ],
+        "accessors": [
+            { "bufferView": 0, "componentType": 5126, "count": 4, "type": "VEC3",
+              "min": [0.0, 0.0, 0.0], "max": [1.0, 1.0, 0.0] },
+            { "bufferView": 1, "componentType": 5125, "count": 6, "type": "SCALAR" }
+        ],
+        "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0 }, "indices": 1 }] }]
+    }"#;
+
+    fn quad_buffer() -> Data {
+        let positions = [
+            [0.0f32, 0.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [1.0, 1.0, 0.0],
+            [0.0, 1.0, 0.0],
+        ];
+        let indices = [0u32, 1, 2, 0, 2, 3];
+        let mut bytes = Vec::new();
+        for p in positions.iter().flatten() {
+            bytes.extend_from_slice(&p.to_le_bytes());
+        }
+        for i in indices {
+            bytes.extend_from_slice(&i.to_le_bytes());
+        }
+        Data(bytes)
+    }
+
+    fn document(json: &str) -> Document {
+        Gltf::from_slice_without_validation(json.as_bytes())
+            .unwrap()
+            .document
+    }
+
+    fn vertex(position: [f32; 3]) -> ModelVertex {
+        ModelVertex {
+            position,
+            normal: [0.0, 0.0, 0.0],
+            tex_coords_0: [0.0, 0.0],
+            tex_coords_1: [0.0, 0.0],
+            tangent: [1.0, 0.0, 0.0, 1.0],
+            weights: [0.0, 0.0, 0.0, 0.0],
+            joints: [0, 0, 0, 0],
+            colors: [1.0, 1.0, 1.0, 1.0],
+        }
+    }
+
+    //n×n的网格，三角形按打乱的顺序排列
+    #[cfg(feature = "mesh_optimization")]
+    fn grid(n: u32) -> (Vec<ModelVertex>, Vec<u32>) {
+        let mut vertices = Vec::new();
+        for y in 0..=n {
+            for x in 0..=n {
+                vertices.push(vertex([x as f32, y as f32, 0.0]));
+            }
+        }
+        let mut triangles = Vec::new();
+        for y in 0..n {
+            for x in 0..n {
+                let i = y * (n + 1) + x;
+                triangles.push([i, i + 1, i + n + 1]);
+                triangles.push([i + 1, i + n + 2, i + n + 1]);
+            }
+        }
+        let len = triangles.len();
+        for i in 0..len {
+            triangles.swap(i, (i * 7919) % len);
+        }
+        (vertices, triangles.concat())
+    }
+
+    //三角形按位置比较，旋转到最小的顶点开头，保留环绕方向
+    #[cfg(feature = "mesh_optimization")]
+    fn triangle_set(vertices: &[ModelVertex], indices: &[u32]) -> Vec<[[u32; 3]; 3]> {
+        let mut triangles = indices
+            .chunks_exact(3)
+            .map(|face| {
+                let mut corners = [face[0], face[1], face[2]]
+                    .map(|i| vertices[i as usize].position.map(f32::to_bits));
+                let first = (0..3).min_by_key(|i| corners[*i]).unwrap();
+                corners.rotate_left(first);
+                corners
+            })
+            .collect::<Vec<_>>();
+        triangles.sort();
+        triangles
+    }
+
+    #[cfg(feature = "mesh_optimization")]
+    #[test]
+    fn optimize_primitive_keeps_triangle_set() {
+        let (vertices, indices) = grid(8);
+        let expected = triangle_set(&vertices, &indices);
+
+        let (optimized_vertices, optimized_indices) =
+            optimize_primitive(vertices.clone(), Some(indices.clone()));
+        let optimized_indices = optimized_indices.unwrap();
+
+        assert_eq!(optimized_vertices.len(), vertices.len());
+        assert_eq!(optimized_indices.len(), indices.len());
+        assert_eq!(
+            triangle_set(&optimized_vertices, &optimized_indices),
+            expected
+        );
+
+        //顶点按首次被引用的顺序排列
+        let mut next = 0;
+        for index in optimized_indices {
+            assert!(index <= next);
+            if index == next {
+                next += 1;
+            }
+        }
+    }
+
+    #[cfg(feature = "mesh_optimization")]
+    #[test]
+    fn optimize_primitive_skips_invalid_indices() {
+        let vertices = vec![vertex([0.0; 3]); 3];
+        let (_, indices) = optimize_primitive(vertices.clone(), Some(vec![0, 1, 5]));
+        assert_eq!(indices, Some(vec![0, 1, 5]));
+        let (_, indices) = optimize_primitive(vertices, None);
+        assert!(indices.is_none());
+    }
+
+    #[test]
+    fn missing_normals_are_generated_per_face() {
+        let document = document(QUAD_DOCUMENT);
+        let data = read_meshes_from_gltf(&document, &[quad_buffer()]);
+
+        assert_eq!(data.primitives.len(), 1);
+        //每个三角形有独立的顶点
+        assert_eq!(data.vertices.len(), 6);
+        assert_eq!(data.indices.len(), 6);
+        for vertex in &data.vertices {
+            let normal = Vector3::from(vertex.normal);
+            assert!((normal.magnitude() - 1.0).abs() < 1e-5);
+            assert!((normal - Vector3::unit_z()).magnitude() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn flat_normals_follow_winding() {
+        let vertices = vec![
+            vertex([0.0, 0.0, 0.0]),
+            vertex([0.0, 1.0, 0.0]),
+            vertex([1.0, 0.0, 0.0]),
+        ];
+        //没有索引时按顶点顺序组成三角形，顺时针得到-Z
+        let (flat_vertices, indices) = generate_flat_normals(vertices, None);
+        assert_eq!(indices, Some(vec![0, 1, 2]));
+        for vertex in flat_vertices {
+            assert_eq!(vertex.normal, [0.0, 0.0, -1.0]);
+        }
+    }
+
+    #[test]
+    fn degenerate_faces_get_fallback_normal() {
+        let vertices = vec![vertex([1.0, 2.0, 3.0]); 3];
+        let (flat_vertices, _) = generate_flat_normals(vertices, Some(vec![0, 1, 2, 0, 1, 9]));
+        //越界的三角形被丢弃
+        assert_eq!(flat_vertices.len(), 3);
+        for vertex in flat_vertices {
+            assert_eq!(vertex.normal, [0.0, 0.0, 1.0]);
+        }
+    }
+
+    fn fscene_primitive(mesh_index: usize, primitive_index: usize) -> FScenePrimitive {
+        FScenePrimitive {
+            mesh_index,
+            primitive_index,
+            vertices: (3, 4),
+            indices: Some((6, 6)),
+            use_vertex_colors: false,
+            tangents_valid: true,
+            aabb: Aabb::new(Vector3::zero(), Vector3::new(1.0, 1.0, 1.0)),
+        }
+    }
+
+    #[test]
+    fn skipped_primitives_take_no_index() {
+        let document = document(
+            r#"{
+                "asset": { "version": "2.0" },
+                "meshes": [
+                    { "primitives": [{ "attributes": {} }, { "attributes": {} }] },
+                    { "primitives": [{ "attributes": {} }] }
+                ]
+            }"#,
+        );
+        //第二个和最后一个在文档中不存在
+        let primitives = [
+            fscene_primitive(0, 0),
+            fscene_primitive(0, 5),
+            fscene_primitive(1, 0),
+            fscene_primitive(0, 1),
+            fscene_primitive(7, 0),
+        ];
+        let meshes = group_primitives(&document, &primitives);
+
+        let indices = meshes
+            .iter()
+            .map(|primitives| primitives.iter().map(|p| p.index).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(indices, vec![vec![0, 2], vec![1]]);
+
+        //范围从按元素换算成按字节
+        let primitive = &meshes[0][0];
+        assert_eq!(primitive.vertices, (3 * size_of::<ModelVertex>(), 4));
+        assert_eq!(primitive.indices, Some((6 * size_of::<u32>(), 6)));
+    }
+
+    #[test]
+    fn glb_and_gltf_load_the_same_meshes() {
+        let models = concat!(env!("CARGO_MANIFEST_DIR"), "/../../../assets/models");
+        let load = |path: &str| {
+            let (document, buffers, _) = gltf::import(format!("{models}/{path}")).unwrap();
+            read_meshes_from_gltf(&document, &buffers)
+        };
+        let glb = load("DamagedHelmet/glTF-Binary/DamagedHelmet.glb");
+        let gltf = load("DamagedHelmet/glTF/DamagedHelmet.gltf");
+
+        assert!(!glb.primitives.is_empty());
+        assert_eq!(glb.primitives.len(), gltf.primitives.len());
+        assert_eq!(glb.vertices.len(), gltf.vertices.len());
+        assert_eq!(glb.indices, gltf.indices);
+        for (a, b) in glb.vertices.iter().zip(&gltf.vertices) {
+            assert_eq!(a.position, b.position);
+            assert_eq!(a.tex_coords_0, b.tex_coords_0);
+        }
+    }
+
+    //镜像一半的UV行列式为负，重建的副切线仍要沿V增大的方向
+    #[test]
+    fn mirrored_uv_tangents_reconstruct_bitangent() {
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../../../assets/models/MirroredUV/glTF-Embedded/MirroredUV.gltf"
+        );
+        let (document, buffers, _) = gltf::import(path).unwrap();
+        let data = read_meshes_from_gltf(&document, &buffers);
+        assert!(data.primitives[0].tangents_valid);
+
+        //接缝上的两个顶点被拆开
+        assert_eq!(data.vertices.len(), 8);
+
+        let mut signs = Vec::new();
+        for face in data.indices.chunks_exact(3) {
+            let [a, b, c] = [face[0], face[1], face[2]].map(|i| data.vertices[i as usize]);
+            let d1 = Vector3::from(b.position) - Vector3::from(a.position);
+            let d2 = Vector3::from(c.position) - Vector3::from(a.position);
+            let (t1, t2) = (
+                Vector2::from(b.tex_coords_0) - Vector2::from(a.tex_coords_0),
+                Vector2::from(c.tex_coords_0) - Vector2::from(a.tex_coords_0),
+            );
+            let det = t1.x * t2.y - t1.y * t2.x;
+            let dp_du = (d1 * t2.y - d2 * t1.y) / det;
+            let dp_dv = (d2 * t1.x - d1 * t2.x) / det;
+
+            for vertex in [a, b, c] {
+                let [x, y, z, w] = vertex.tangent;
+                let tangent = Vector3::new(x, y, z);
+                let bitangent = Vector3::from(vertex.normal).cross(tangent) * w;
+                assert!(tangent.dot(dp_du) > 0.0);
+                assert!(bitangent.dot(dp_dv) > 0.0);
+                signs.push(w);
+            }
+        }
+        assert!(signs.contains(&1.0) && signs.contains(&-1.0));
+    }
+
+    //README里列出的替换结果，顶点顺序可能被优化打乱，按位置比较
+    #[test]
+    fn sparse_accessors_apply_overrides() {
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../../../assets/models/SparseAccessor/glTF-Embedded/SparseAccessor.gltf"
+        );
+        let (document, buffers, _) = gltf::import(path).unwrap();
+        let data = read_meshes_from_gltf(&document, &buffers);
+
+        let mut vertices = data
+            .vertices
+            .iter()
+            .map(|v| (v.position, v.tex_coords_0))
+            .collect::<Vec<_>>();
+        vertices.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let mut expected = vec![
+            ([-0.5, -0.5, 0.0], [0.0, 1.0]),
+            ([0.5, -0.5, 0.0], [1.0, 1.0]),
+            ([0.5, 1.5, 0.0], [1.0, 0.0]),
+            ([-0.5, 1.5, 0.0], [0.0, 0.0]),
+        ];
+        expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(vertices, expected);
+
+        let aabb = data.primitives[0].aabb;
+        assert_eq!(aabb.min(), Vector3::new(-0.5, -0.5, 0.0));
+        assert_eq!(aabb.max(), Vector3::new(0.5, 1.5, 0.0));
+    }
 }
@@ -1,12 +1,22 @@
+use cgmath::{InnerSpace, Vector2, Vector3};
 use rendering::vertex::ModelVertex;
+use std::collections::HashMap;
 
 const VERTEX_PER_FACE: usize = 3;
 
+//面积或UV行列式小于该值的三角形视为退化
+const DEGENERATE_EPSILON: f32 = 1e-12;
+
+//同一顶点在不同三角形中得到的切线夹角余弦低于该值时拆成两个顶点
+const SPLIT_COS_THRESHOLD: f32 = 0.999;
+
 type Face = [u32; 3];
 
 struct Mesh<'a> {
     faces: Vec<Face>,
-    vertices: &'a mut [ModelVertex],
+    vertices: &'a [ModelVertex],
+    //按三角形的角记录切线，共用顶点的角可能得到不同的切线
+    tangents: Vec<[f32; 4]>,
 }
 
 impl<'a> Mesh<'a> {
@@ -14,11 +24,6 @@ impl<'a> Mesh<'a> {
         let face = self.faces[face];
         self.vertices[face[vert] as usize]
     }
-
-    fn get_vertex_mut(&mut self, face: usize, vert: usize) -> &mut ModelVertex {
-        let face = self.faces[face];
-        &mut self.vertices[face[vert] as usize]
-    }
 }
 
 impl<'a> mikktspace::Geometry for Mesh<'a> {
@@ -52,26 +57,28 @@ impl<'a> mikktspace::Geometry for Mesh<'a> {
         face: usize,
         vert: usize,
     ) {
+        //与glTF约定一致：bitangent = cross(normal, tangent.xyz) * tangent.w
         let sign = if bi_tangent_preserves_orientation {
-            -1.0
-        } else {
             1.0
+        } else {
+            -1.0
         };
-        let vertex = self.get_vertex_mut(face, vert);
-        vertex.tangent = [tangent[0], tangent[1], tangent[2], sign];
+        self.tangents[face * VERTEX_PER_FACE + vert] = [tangent[0], tangent[1], tangent[2], sign];
     }
 }
 
-pub fn generate_tangents(indices: Option<&[u32]>, vertices: &mut [ModelVertex]) {
+//返回生成的切线是否全部可用，有顶点退回任意正交切线时返回false
+pub fn generate_tangents(indices: Option<&mut Vec<u32>>, vertices: &mut Vec<ModelVertex>) -> bool {
     log::info!("生成切线");
 
-    let index_count = indices.map_or(0, |indices| indices.len());
+    let index_count = indices.as_ref().map_or(0, |indices| indices.len());
     if !can_generate_inputs(index_count, vertices.len()) {
-        log::warn!("无法生成切线");
-        return;
+        log::warn!("无法生成切线，使用与法线正交的任意切线");
+        orthonormalize_tangents(vertices);
+        return false;
     }
 
-    let faces = if let Some(indices) = indices {
+    let faces = if let Some(indices) = indices.as_deref() {
         (0..index_count)
             .step_by(VERTEX_PER_FACE)
             .map(|i| [indices[i], indices[i + 1], indices[i + 2]])
@@ -84,9 +91,160 @@ pub fn generate_tangents(indices: Option<&[u32]>, vertices: &mut [ModelVertex])
             .collect::<Vec<_>>()
     };
 
-    let mut mesh = Mesh { faces, vertices };
+    let degenerate_count = count_degenerate_faces(&faces, vertices);
+    if degenerate_count > 0 {
+        log::warn!(
+            "{}/{}个三角形面积为零或UV退化，对应顶点的切线将被重新构造",
+            degenerate_count,
+            faces.len()
+        );
+    }
+
+    let mut mesh = Mesh {
+        tangents: vec![[0.0; 4]; faces.len() * VERTEX_PER_FACE],
+        faces,
+        vertices,
+    };
+
+    let generated = mikktspace::generate_tangents(&mut mesh);
+    if !generated {
+        log::warn!("mikktspace生成切线失败，使用与法线正交的任意切线");
+    }
+
+    let Mesh {
+        faces, tangents, ..
+    } = mesh;
+    let split_count = apply_tangents(&faces, &tangents, indices, vertices);
+    if split_count > 0 {
+        log::info!("{}个顶点在镜像UV接缝两侧切线不同，已拆分", split_count);
+    }
+
+    let fixed_count = orthonormalize_tangents(vertices);
+    generated && degenerate_count == 0 && fixed_count == 0
+}
+
+//把每个角的切线写回顶点，顶点已被切线不兼容的角占用时复制一份并改写索引，
+//没有索引时每个角都是独立的顶点，不会发生冲突
+fn apply_tangents(
+    faces: &[Face],
+    tangents: &[[f32; 4]],
+    mut indices: Option<&mut Vec<u32>>,
+    vertices: &mut Vec<ModelVertex>,
+) -> usize {
+    let mut assigned = vec![false; vertices.len()];
+    let mut copies = HashMap::<u32, Vec<u32>>::new();
+    let mut split_count = 0;
+
+    for (corner, tangent) in tangents.iter().enumerate() {
+        let index = faces[corner / VERTEX_PER_FACE][corner % VERTEX_PER_FACE];
+        if !assigned[index as usize] {
+            assigned[index as usize] = true;
+            vertices[index as usize].tangent = *tangent;
+            continue;
+        }
+        if is_compatible(&vertices[index as usize].tangent, tangent) {
+            continue;
+        }
+        let Some(indices) = indices.as_deref_mut() else {
+            continue;
+        };
+
+        let copies = copies.entry(index).or_default();
+        let existing = copies
+            .iter()
+            .copied()
+            .find(|copy| is_compatible(&vertices[*copy as usize].tangent, tangent));
+        let new_index = existing.unwrap_or_else(|| {
+            let mut vertex = vertices[index as usize];
+            vertex.tangent = *tangent;
+            vertices.push(vertex);
+            let new_index = (vertices.len() - 1) as u32;
+            copies.push(new_index);
+            split_count += 1;
+            new_index
+        });
+        indices[corner] = new_index;
+    }
+
+    split_count
+}
+
+fn is_compatible(a: &[f32; 4], b: &[f32; 4]) -> bool {
+    let a_xyz = Vector3::new(a[0], a[1], a[2]);
+    let b_xyz = Vector3::new(b[0], b[1], b[2]);
+    //退化三角形得到的零切线彼此相等，不需要拆分
+    a == b || ((a[3] < 0.0) == (b[3] < 0.0) && a_xyz.dot(b_xyz) >= SPLIT_COS_THRESHOLD)
+}
+
+//把切线对法线做Gram-Schmidt正交化，切线无效（NaN、为零或与法线平行）时构造任意正交切线，
+//返回被替换的顶点数
+pub fn orthonormalize_tangents(vertices: &mut [ModelVertex]) -> usize {
+    let mut fixed_count = 0;
+
+    for vertex in vertices.iter_mut() {
+        let normal = Vector3::from(vertex.normal);
+        let normal = if is_usable(normal) {
+            normal.normalize()
+        } else {
+            Vector3::unit_z()
+        };
+
+        let [x, y, z, w] = vertex.tangent;
+        let tangent = Vector3::new(x, y, z);
+        let tangent = tangent - normal * normal.dot(tangent);
+
+        let (tangent, w) = if is_usable(tangent) {
+            (tangent.normalize(), if w < 0.0 { -1.0 } else { 1.0 })
+        } else {
+            fixed_count += 1;
+            (arbitrary_tangent(normal), 1.0)
+        };
+
+        vertex.tangent = [tangent.x, tangent.y, tangent.z, w];
+    }
+
+    if fixed_count > 0 {
+        log::warn!("{}个顶点的切线无效，已替换为任意正交切线", fixed_count);
+    }
+    debug_assert!(vertices
+        .iter()
+        .all(|v| v.tangent.iter().all(|c| c.is_finite())));
+    fixed_count
+}
+
+fn is_usable(v: Vector3<f32>) -> bool {
+    v.x.is_finite() && v.y.is_finite() && v.z.is_finite() && v.magnitude2() > DEGENERATE_EPSILON
+}
+
+//选与法线最不平行的坐标轴做叉乘，保证结果不退化
+fn arbitrary_tangent(normal: Vector3<f32>) -> Vector3<f32> {
+    let axis = if normal.x.abs() < 0.9 {
+        Vector3::unit_x()
+    } else {
+        Vector3::unit_y()
+    };
+    normal.cross(axis).cross(normal).normalize()
+}
+
+fn count_degenerate_faces(faces: &[Face], vertices: &[ModelVertex]) -> usize {
+    faces
+        .iter()
+        .filter(|face| {
+            let [a, b, c] = face.map(|i| &vertices[i as usize]);
+
+            let p0 = Vector3::from(a.position);
+            let area = (Vector3::from(b.position) - p0)
+                .cross(Vector3::from(c.position) - p0)
+                .magnitude2();
 
-    mikktspace::generate_tangents(&mut mesh);
+            let uv0 = Vector2::from(a.tex_coords_0);
+            let duv1 = Vector2::from(b.tex_coords_0) - uv0;
+            let duv2 = Vector2::from(c.tex_coords_0) - uv0;
+            let uv_det = duv1.x * duv2.y - duv1.y * duv2.x;
+
+            !(area > DEGENERATE_EPSILON && uv_det.abs() > DEGENERATE_EPSILON)
+        })
+        .count()
 }
 
 fn can_generate_inputs(index_count: usize, vertex_count: usize) -> bool {
@@ -107,3 +265,57 @@ fn can_generate_inputs(index_count: usize, vertex_count: usize) -> bool {
 
     true
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vertex() -> ModelVertex {
+        ModelVertex {
+            position: [0.0, 0.0, 0.0],
+            normal: [0.0, 0.0, 1.0],
+            tex_coords_0: [0.0, 0.0],
+            tex_coords_1: [0.0, 0.0],
+            tangent: [1.0, 1.0, 1.0, 1.0],
+            weights: [0.0, 0.0, 0.0, 0.0],
+            joints: [0, 0, 0, 0],
+            colors: [1.0, 1.0, 1.0, 1.0],
+        }
+    }
+
+    #[test]
+    fn mirrored_corners_split_shared_vertices() {
+        //两个三角形共用顶点1和2，第二个三角形的UV是镜像的
+        let faces = [[0, 1, 2], [1, 3, 2]];
+        let mut indices = vec![0, 1, 2, 1, 3, 2];
+        let mut vertices = vec![vertex(); 4];
+        let right = [1.0, 0.0, 0.0, -1.0];
+        let left = [-1.0, 0.0, 0.0, 1.0];
+        let tangents = [right, right, right, left, left, left];
+
+        let split_count = apply_tangents(&faces, &tangents, Some(&mut indices), &mut vertices);
+
+        assert_eq!(split_count, 2);
+        assert_eq!(vertices.len(), 6);
+        assert_eq!(indices, vec![0, 1, 2, 4, 3, 5]);
+        for (corner, index) in indices.iter().enumerate() {
+            assert_eq!(vertices[*index as usize].tangent, tangents[corner]);
+        }
+    }
+
+    #[test]
+    fn compatible_corners_keep_shared_vertices() {
+        let faces = [[0, 1, 2], [2, 1, 3]];
+        let mut indices = vec![0, 1, 2, 2, 1, 3];
+        let mut vertices = vec![vertex(); 4];
+        let tangent = [1.0, 0.0, 0.0, 1.0];
+        let zero = [0.0; 4];
+        let tangents = [tangent, zero, tangent, tangent, zero, tangent];
+
+        let split_count = apply_tangents(&faces, &tangents, Some(&mut indices), &mut vertices);
+
+        assert_eq!(split_count, 0);
+        assert_eq!(vertices.len(), 4);
+        assert_eq!(indices, vec![0, 1, 2, 2, 1, 3]);
+    }
+}
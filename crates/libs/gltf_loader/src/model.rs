@@ -1,25 +1,29 @@
-use crate::mesh::{create_meshes_from_gltf, Mesh, Meshes};
-use cgmath::{Vector3, Zero};
-use gltf::image::Source;
-use gltf::{iter::Nodes as GltfNodes, Scene};
+use crate::mesh::{create_meshes, create_meshes_from_gltf, Mesh, Meshes};
+use cgmath::{Matrix4, Quaternion, Vector3, Zero};
+use gltf::buffer::Data;
+use gltf::{iter::Nodes as GltfNodes, Document, Scene};
 use rendering::{
     animation::{load_animations, Animations, PlaybackMode, PlaybackState},
     error::ModelLoadingError,
+    fscene::{FScene, FSCENE_EXTENSION},
     light::{create_lights_from_gltf, Light},
+    material::MaterialOverride,
     metadata::Metadata,
-    skin::{create_skins_from_gltf, Skin},
-    texture::{self, Texture, Textures},
+    node::{compute_node_uids, NodeUid},
+    skin::{create_skins_from_gltf, Skin, MAX_JOINTS_PER_MESH},
+    texture::{self, Texture, TextureUsage, Textures},
     Aabb,
 };
 use scene::scene_tree::Node;
 use scene::transform::Transform;
-use std::{error::Error, path::Path, rc::Rc, result::Result, sync::Arc};
+use std::{error::Error, path::Path, rc::Rc, result::Result, sync::Arc, time::Instant};
 use vulkan::{ash::vk, Buffer, Context, PreLoadedResource};
 
+const DEFAULT_MODEL_SIZE: f32 = 10.0;
+
 pub struct ModelStagingResources {
     _staged_vertices: Buffer,
     _staged_indices: Option<Buffer>,
-    _staged_textures: Vec<Buffer>,
 }
 
 pub struct Model {
@@ -34,57 +38,150 @@ pub struct Model {
 }
 
 impl Model {
+    //扩展名为fscene时按预处理过的二进制场景加载
     pub fn create_from_file<P: AsRef<Path>>(
         context: Arc<Context>,
         command_buffer: vk::CommandBuffer,
         path: P,
     ) -> Result<PreLoadedResource<Model, ModelStagingResources>, Box<dyn Error>> {
-        let (document, buffers, images) = gltf::import(&path)?;
-
-        let mut image_paths: Vec<&str> = Vec::new();
-        for image in document.images() {
-            match image.source() {
-                Source::View {
-                    view: _,
-                    mime_type: _,
-                } => {}
-                Source::Uri { uri, mime_type: _ } => {
-                    image_paths.push(uri);
-                    println!("Loading {} {}", image.index(), uri);
-                }
-            };
+        if is_fscene_file(&path) {
+            return Self::create_from_fscene(context, command_buffer, path);
         }
 
-        let metadata = Metadata::new(path, &document);
+        //外部图片按glTF文件所在目录解析，data URI由gltf解码base64，失败时带上模型路径
+        let (document, buffers, images) = gltf::import(&path).map_err(|err| {
+            ModelLoadingError::new(format!("{}: {}", path.as_ref().display(), err))
+        })?;
 
         if document.scenes().len() == 0 {
             return Err(Box::new(ModelLoadingError::new("没有场景")));
         }
 
+        let start = Instant::now();
         let meshes = create_meshes_from_gltf(&context, command_buffer, &document, &buffers);
-        if meshes.is_none() {
+        log::info!("网格数据处理耗时{}ms", start.elapsed().as_millis());
+        let Some(meshes) = meshes else {
             return Err(Box::new(ModelLoadingError::new("没有可渲染的mesh")));
+        };
+
+        let start = Instant::now();
+        let textures = texture::create_textures_from_gltf(
+            &context,
+            document.textures(),
+            document.materials(),
+            &images,
+            texture::image_paths(&document),
+        )?;
+        log::info!(
+            "{}张纹理上传耗时{}ms",
+            images.len(),
+            start.elapsed().as_millis()
+        );
+
+        Self::create(
+            context,
+            command_buffer,
+            path,
+            &document,
+            &buffers,
+            meshes,
+            textures,
+        )
+    }
+
+    //加载convert_gltf_to_fscene生成的文件，顶点、索引和带mip的图片直接上传，
+    //材质、节点、动画、蒙皮和灯光仍按glTF JSON和原始缓冲创建
+    pub fn create_from_fscene<P: AsRef<Path>>(
+        context: Arc<Context>,
+        command_buffer: vk::CommandBuffer,
+        path: P,
+    ) -> Result<PreLoadedResource<Model, ModelStagingResources>, Box<dyn Error>> {
+        let start = Instant::now();
+        let fscene = FScene::read(&path)?;
+        let document = gltf::Gltf::from_slice(fscene.document())
+            .map_err(|err| ModelLoadingError::new(format!("{}: {}", path.as_ref().display(), err)))?
+            .document;
+        let buffers = fscene
+            .buffers()
+            .map(|buffer| Data(buffer.to_vec()))
+            .collect::<Vec<_>>();
+        if buffers.len() != document.buffers().len() {
+            return Err(Box::new(ModelLoadingError::new(
+                "fscene缓冲数量与文档不一致",
+            )));
         }
 
+        if document.scenes().len() == 0 {
+            return Err(Box::new(ModelLoadingError::new("没有场景")));
+        }
+
+        let meshes = create_meshes(
+            &context,
+            command_buffer,
+            &document,
+            fscene.vertex_bytes(),
+            fscene.index_bytes(),
+            fscene.primitives(),
+        );
+        let Some(meshes) = meshes else {
+            return Err(Box::new(ModelLoadingError::new("没有可渲染的mesh")));
+        };
+
+        let textures = texture::create_textures_from_fscene(
+            &context,
+            document.textures(),
+            document.materials(),
+            &fscene,
+            texture::image_paths(&document),
+        )?;
+
+        let model = Self::create(
+            context,
+            command_buffer,
+            path,
+            &document,
+            &buffers,
+            meshes,
+            textures,
+        );
+        log::info!("fscene加载耗时{}ms", start.elapsed().as_millis());
+        model
+    }
+
+    fn create<P: AsRef<Path>>(
+        context: Arc<Context>,
+        command_buffer: vk::CommandBuffer,
+        path: P,
+        document: &Document,
+        buffers: &[Data],
+        meshes: Meshes,
+        textures: Textures,
+    ) -> Result<PreLoadedResource<Model, ModelStagingResources>, Box<dyn Error>> {
+        //Metadata和渲染节点共用同一组ID
+        let node_uids = compute_node_uids(document);
+        let metadata = Metadata::new(path, document, &node_uids);
+
         let Meshes {
             meshes,
             vertices: staged_vertices,
             indices: staged_indices,
-        } = meshes.unwrap();
+        } = meshes;
 
         let scene = document
             .default_scene()
             .unwrap_or_else(|| document.scenes().next().unwrap());
 
-        let animations = load_animations(document.animations(), &buffers);
+        let animations = load_animations(document.animations(), buffers);
 
-        let mut skins = create_skins_from_gltf(document.skins(), &buffers);
+        let mut skins = create_skins_from_gltf(document.skins(), buffers);
 
-        let mut node = from_gltf_nodes(document.nodes(), &scene);
+        let mut node = from_gltf_nodes(document.nodes(), &scene, &node_uids);
 
         let transform = {
-            let aabb = compute_aabb(&node, &meshes);
-            let mut transform = compute_unit_cube_at_origin_transform(aabb);
+            //没有网格的场景不缩放
+            let mut transform = compute_aabb(&node, &meshes)
+                .map(|aabb| compute_unit_cube_at_origin_transform(aabb, DEFAULT_MODEL_SIZE))
+                .unwrap_or_default();
             node.transform(Some(transform.local_to_world_matrix()));
             node.get_skins_transform()
                 .iter()
@@ -95,16 +192,7 @@ impl Model {
             transform
         };
 
-        let (textures, staged_textures) = texture::create_textures_from_gltf(
-            &context,
-            command_buffer,
-            document.textures(),
-            document.materials(),
-            &images,
-            image_paths,
-        );
-
-        let lights = create_lights_from_gltf(&document);
+        let lights = create_lights_from_gltf(document);
 
         let model = Model {
             metadata,
@@ -120,7 +208,6 @@ impl Model {
         let model_staging_res = ModelStagingResources {
             _staged_vertices: staged_vertices,
             _staged_indices: staged_indices,
-            _staged_textures: staged_textures,
         };
 
         Ok(PreLoadedResource::new(
@@ -141,19 +228,23 @@ impl Model {
         };
 
         if updated {
-            self.nodes
-                .transform(Some(self.transform.local_to_world_matrix()));
-            self.nodes
-                .get_skins_transform()
-                .iter()
-                .for_each(|(index, transform)| {
-                    let skin = &mut self.skins[*index];
-                    skin.compute_joints_matrices(*transform, self.nodes.nodes());
-                });
+            self.update_animated_nodes();
         }
 
         updated
     }
+
+    fn update_animated_nodes(&mut self) {
+        self.nodes
+            .transform(Some(self.transform.local_to_world_matrix()));
+        self.nodes
+            .get_skins_transform()
+            .iter()
+            .for_each(|(index, transform)| {
+                let skin = &mut self.skins[*index];
+                skin.compute_joints_matrices(*transform, self.nodes.nodes());
+            });
+    }
 }
 
 impl Model {
@@ -170,6 +261,13 @@ impl Model {
         }
     }
 
+    //在fade_time秒内从当前动画过渡到新动画
+    pub fn blend_to_animation(&mut self, animation_index: usize, fade_time: f32) {
+        if let Some(animations) = self.animations.as_mut() {
+            animations.blend_to(animation_index, fade_time);
+        }
+    }
+
     pub fn set_animation_playback_mode(&mut self, playback_mode: PlaybackMode) {
         if let Some(animations) = self.animations.as_mut() {
             animations.set_playback_mode(playback_mode);
@@ -194,10 +292,102 @@ impl Model {
         }
     }
 
+    //拖动时间轴时调用，会暂停播放并立即应用该时刻的姿势
+    pub fn set_animation_time(&mut self, time: f32) {
+        let updated = self
+            .animations
+            .as_mut()
+            .is_some_and(|animations| animations.set_time(&mut self.nodes, time));
+        if updated {
+            self.update_animated_nodes();
+        }
+    }
+
     pub fn update_transform(&mut self) {
         self.node
             .transform(Some(self.transform.local_to_world_matrix()));
     }
+
+    //修改节点的局部变换并更新全局变换
+    pub fn set_node_local_transform(
+        &mut self,
+        index: usize,
+        translation: Vector3<f32>,
+        rotation: Quaternion<f32>,
+        scale: Vector3<f32>,
+    ) {
+        {
+            let node = &mut self.nodes.nodes_mut()[index];
+            node.set_translation(translation);
+            node.set_rotation(rotation);
+            node.set_scale(scale);
+        }
+        self.nodes
+            .transform(Some(self.transform.local_to_world_matrix()));
+        self.nodes
+            .get_skins_transform()
+            .iter()
+            .for_each(|(index, transform)| {
+                let skin = &mut self.skins[*index];
+                skin.compute_joints_matrices(*transform, self.nodes.nodes());
+            });
+    }
+
+    pub fn set_node_visible(&mut self, index: usize, visible: bool) {
+        self.nodes.set_visible(index, visible);
+    }
+
+    //返回值为(节点索引, 节点)，索引用于修改节点的接口
+    pub fn node_by_uid(&self, uid: NodeUid) -> Option<(usize, &rendering::node::Node)> {
+        self.nodes.node_by_uid(uid)
+    }
+
+    //primitive_index是primitive在网格中的位置
+    pub fn set_parallax_enabled(
+        &mut self,
+        mesh_index: usize,
+        primitive_index: usize,
+        enabled: bool,
+    ) {
+        self.meshes[mesh_index]
+            .primitive_mut(primitive_index)
+            .material_mut()
+            .set_parallax_enabled(enabled);
+    }
+
+    //覆盖的参数在模型卸载前一直有效
+    pub fn set_material_override(
+        &mut self,
+        mesh_index: usize,
+        primitive_index: usize,
+        material_override: MaterialOverride,
+    ) {
+        self.meshes[mesh_index]
+            .primitive_mut(primitive_index)
+            .set_material_override(material_override);
+    }
+
+    pub fn reset_material_override(&mut self, mesh_index: usize, primitive_index: usize) {
+        self.meshes[mesh_index]
+            .primitive_mut(primitive_index)
+            .reset_material_override();
+    }
+
+    //移到原点并缩放到单位大小
+    pub fn normalize(&mut self) {
+        self.node.transform(None);
+        self.transform = compute_aabb(self.node.clone(), &self.meshes)
+            .map(|aabb| compute_unit_cube_at_origin_transform(aabb, 1.0))
+            .unwrap_or_default();
+        self.update_transform();
+        self.node
+            .get_skins_transform()
+            .iter()
+            .for_each(|(index, transform)| {
+                let skin = &mut self.skins[*index];
+                skin.compute_joints_matrices(*transform, self.node.nodes());
+            });
+    }
 }
 
 /// Getters
@@ -226,14 +416,69 @@ impl Model {
         self.node.clone()
     }
 
-    pub fn textures(&self) -> &[Texture] {
-        &self.textures.textures
+    pub fn textures(&self, usage: TextureUsage) -> &[Option<Texture>] {
+        self.textures.get(usage)
     }
 
     pub fn lights(&self) -> &[Light] {
         &self.lights
     }
 
+    pub fn lights_mut(&mut self) -> &mut [Light] {
+        &mut self.lights
+    }
+
+    //模型整体的变换，加载时已缩放并平移到原点附近
+    pub fn transform(&self) -> &Transform {
+        &self.transform
+    }
+
+    //没有网格时返回None
+    pub fn bounds(&self) -> Option<Aabb<f32>> {
+        let aabbs = self
+            .node
+            .mesh_nodes()
+            .map(|n| self.mesh_bounds(n.mesh_index().unwrap(), n.skin_index(), n.transform()))
+            .collect::<Vec<_>>();
+        Aabb::union(&aabbs)
+    }
+
+    //蒙皮网格用当前的骨骼矩阵重新计算包围盒，动画播放后剔除依然保守
+    pub fn mesh_bounds(
+        &self,
+        mesh_index: usize,
+        skin_index: Option<usize>,
+        transform: Matrix4<f32>,
+    ) -> Aabb<f32> {
+        let aabb = self.meshes[mesh_index].aabb();
+        let joint_aabbs = skin_index
+            .map(|i| &self.skins[i])
+            .map(|skin| {
+                skin.joints()
+                    .iter()
+                    .take(MAX_JOINTS_PER_MESH)
+                    .map(|j| aabb * j.matrix())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        Aabb::union(&joint_aabbs).unwrap_or(aabb) * transform
+    }
+
+    //节点及其子节点的包围盒，子树中没有网格时返回None
+    pub fn node_bounds(&self, index: usize) -> Option<Aabb<f32>> {
+        let nodes = self.nodes.nodes();
+        let mut aabbs = Vec::new();
+        let mut stack = vec![index];
+        while let Some(index) = stack.pop() {
+            let node = nodes.get(index)?;
+            if let Some(mesh_index) = node.mesh_index() {
+                aabbs.push(self.mesh_bounds(mesh_index, node.skin_index(), node.transform()));
+            }
+            stack.extend_from_slice(node.children_indices());
+        }
+        Aabb::union(&aabbs)
+    }
+
     pub fn translate(&mut self, position: Vector3<f32>) {
         self.transform.translate(position);
     }
@@ -255,22 +500,30 @@ impl Model {
     }
 }
 
-fn compute_aabb(nodes: Rc<Node>, meshes: &[Mesh]) -> Aabb<f32> {
+pub fn is_fscene_file<P: AsRef<Path>>(path: P) -> bool {
+    path.as_ref()
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case(FSCENE_EXTENSION))
+}
+
+fn compute_aabb(nodes: Rc<Node>, meshes: &[Mesh]) -> Option<Aabb<f32>> {
     let aabbs = nodes
-        .nodes()
-        .iter()
-        .filter(|n| n.mesh_index().is_some())
+        .mesh_nodes()
         .map(|n| {
             let mesh = &meshes[n.mesh_index().unwrap()];
             mesh.aabb() * n.transform()
         })
         .collect::<Vec<_>>();
-    Aabb::union(&aabbs).unwrap()
+    Aabb::union(&aabbs)
 }
 
-fn compute_unit_cube_at_origin_transform(aabb: Aabb<f32>) -> Transform {
+fn compute_unit_cube_at_origin_transform(aabb: Aabb<f32>, size: f32) -> Transform {
     let larger_side = aabb.get_larger_side_size();
-    let scale_factor = (1.0_f32 / larger_side) * 10.0;
+    if larger_side <= 0.0 {
+        return Transform::default();
+    }
+    let scale_factor = (1.0_f32 / larger_side) * size;
 
     let aabb = aabb * scale_factor;
     let center = aabb.get_center();
@@ -286,7 +539,7 @@ fn compute_unit_cube_at_origin_transform(aabb: Aabb<f32>) -> Transform {
     transform
 }
 
-pub fn from_gltf_nodes(gltf_nodes: GltfNodes, scene: &Scene) -> Rc<Node> {
+pub fn from_gltf_nodes(gltf_nodes: GltfNodes, scene: &Scene, uids: &[NodeUid]) -> Rc<Node> {
     let roots_indices = scene.nodes().map(|n| n.index()).collect::<Vec<_>>();
     let node_count = gltf_nodes.len();
     let mut nodes = Vec::with_capacity(node_count);
@@ -299,6 +552,7 @@ pub fn from_gltf_nodes(gltf_nodes: GltfNodes, scene: &Scene) -> Rc<Node> {
         let light_index = node.light().map(|l| l.index());
         let children_indices = node.children().map(|c| c.index()).collect::<Vec<_>>();
         let node = Node {
+            uid: uids[node_index],
             local_transform,
             global_transform_matrix,
             mesh_index,
@@ -15,6 +15,16 @@ impl<S> Aabb<S> {
     }
 }
 
+impl<S: Copy> Aabb<S> {
+    pub fn min(&self) -> Vector3<S> {
+        self.min
+    }
+
+    pub fn max(&self) -> Vector3<S> {
+        self.max
+    }
+}
+
 impl<S: BaseFloat> Aabb<S> {
     pub fn union(aabbs: &[Aabb<S>]) -> Option<Self> {
         if aabbs.is_empty() {
@@ -55,6 +65,25 @@ impl<S: BaseFloat> Aabb<S> {
         let two = S::one() + S::one();
         self.min + (self.max - self.min) / two
     }
+
+    //变换八个顶点后重新求包围盒，旋转后仍然能完整包住原来的盒子
+    pub fn transform(&self, matrix: Matrix4<S>) -> Self {
+        let mut corners = (0..8).map(|i| {
+            let x = if i & 1 == 0 { self.min.x } else { self.max.x };
+            let y = if i & 2 == 0 { self.min.y } else { self.max.y };
+            let z = if i & 4 == 0 { self.min.z } else { self.max.z };
+            (matrix * Vector4::new(x, y, z, S::one())).truncate()
+        });
+
+        let first = corners.next().unwrap();
+        let (min, max) = corners.fold((first, first), |(min, max), p| {
+            (
+                Vector3::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z)),
+                Vector3::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z)),
+            )
+        });
+        Aabb::new(min, max)
+    }
 }
 
 impl<S: BaseFloat> Mul<S> for Aabb<S> {
@@ -77,4 +106,4 @@ impl<S: BaseFloat> Mul<Matrix4<S>> for Aabb<S> {
 
         Aabb::new(min.truncate(), max.truncate())
     }
-}
\ No newline at end of file
+}
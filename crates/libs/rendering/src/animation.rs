@@ -14,6 +14,8 @@ use gltf::{
 };
 use math::slerp;
 use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::hash::Hash;
 
 trait Interpolate: Copy {
     fn linear(self, other: Self, amount: f32) -> Self;
@@ -27,6 +29,31 @@ trait Interpolate: Copy {
     ) -> Self;
 }
 
+impl Interpolate for f32 {
+    fn linear(self, other: Self, amount: f32) -> Self {
+        self + (other - self) * amount
+    }
+
+    fn cubic_spline(
+        source: [Self; 3],
+        source_time: f32,
+        target: [Self; 3],
+        target_time: f32,
+        amount: f32,
+    ) -> Self {
+        let t = amount;
+        let p0 = source[1];
+        let m0 = (target_time - source_time) * source[2];
+        let p1 = target[1];
+        let m1 = (target_time - source_time) * target[0];
+
+        (2.0 * t * t * t - 3.0 * t * t + 1.0) * p0
+            + (t * t * t - 2.0 * t * t + t) * m0
+            + (-2.0 * t * t * t + 3.0 * t * t) * p1
+            + (t * t * t - t * t) * m1
+    }
+}
+
 impl Interpolate for Vector3<f32> {
     fn linear(self, other: Self, amount: f32) -> Self {
         self.lerp(other, amount)
@@ -86,6 +113,16 @@ enum Interpolation {
     CubicSpline,
 }
 
+impl Interpolation {
+    //CubicSpline每个关键帧有入切线、值、出切线三个输出
+    fn outputs_per_key_frame(self) -> usize {
+        match self {
+            Interpolation::CubicSpline => 3,
+            _ => 1,
+        }
+    }
+}
+
 #[derive(Debug)]
 struct Sampler<T> {
     interpolation: Interpolation,
@@ -97,10 +134,29 @@ impl<T> Sampler<T> {
     fn get_max_time(&self) -> f32 {
         self.times.last().copied().unwrap_or(0.0)
     }
+
+    //输出数量与关键帧数量不匹配时采样会越界
+    fn is_valid(&self) -> bool {
+        !self.times.is_empty()
+            && self.values.len() == self.times.len() * self.interpolation.outputs_per_key_frame()
+    }
 }
 
 impl<T: Interpolate> Sampler<T> {
+    //关键帧范围之外按glTF规范取第一帧或最后一帧的值
     fn sample(&self, t: f32) -> Option<T> {
+        let outputs = self.interpolation.outputs_per_key_frame();
+        let key_frame_value = |i: usize| match self.interpolation {
+            Interpolation::CubicSpline => self.values.get(i * outputs + 1).copied(),
+            _ => self.values.get(i).copied(),
+        };
+        if t <= self.times[0] {
+            return key_frame_value(0);
+        }
+        if t >= self.get_max_time() {
+            return key_frame_value(self.times.len() - 1);
+        }
+
         let index = {
             let mut index = None;
             for i in 0..(self.times.len() - 1) {
@@ -171,16 +227,76 @@ impl<T: Interpolate> Channel<T> {
     }
 }
 
+//morph target权重按target拆成单独的标量通道
+#[derive(Debug)]
+struct WeightChannel {
+    channel: Channel<f32>,
+    target_index: usize,
+}
+
+impl WeightChannel {
+    fn get_max_time(&self) -> f32 {
+        self.channel.get_max_time()
+    }
+
+    fn sample(&self, t: f32) -> Option<((usize, usize), f32)> {
+        self.channel
+            .sample(t)
+            .map(|(node_index, weight)| ((node_index, self.target_index), weight))
+    }
+}
+
 struct NodesKeyFrame(
     Vec<(usize, Vector3<f32>)>,
     Vec<(usize, Quaternion<f32>)>,
     Vec<(usize, Vector3<f32>)>,
+    Vec<((usize, usize), f32)>,
 );
 
+impl NodesKeyFrame {
+    //weight为0时完全是previous，为1时完全是self
+    fn blend_from(self, previous: NodesKeyFrame, weight: f32) -> NodesKeyFrame {
+        NodesKeyFrame(
+            blend_channels(previous.0, self.0, weight),
+            blend_channels(previous.1, self.1, weight),
+            blend_channels(previous.2, self.2, weight),
+            blend_channels(previous.3, self.3, weight),
+        )
+    }
+}
+
+//只在一个片段中有动画的节点直接使用该片段的值
+fn blend_channels<K: Eq + Hash, T: Interpolate>(
+    previous: Vec<(K, T)>,
+    current: Vec<(K, T)>,
+    weight: f32,
+) -> Vec<(K, T)> {
+    let mut previous = previous.into_iter().collect::<HashMap<_, _>>();
+    let mut blended = current
+        .into_iter()
+        .map(|(node_index, value)| match previous.remove(&node_index) {
+            Some(previous_value) => (node_index, previous_value.linear(value, weight)),
+            None => (node_index, value),
+        })
+        .collect::<Vec<_>>();
+    blended.extend(previous);
+    blended
+}
+
 #[derive(Debug)]
 pub struct Animations {
     animations: Vec<Animation>,
     playback_state: PlaybackState,
+    crossfade: Option<Crossfade>,
+}
+
+//切换片段时旧片段继续播放，在duration内权重逐渐过渡到新片段
+#[derive(Debug, Copy, Clone)]
+struct Crossfade {
+    previous: usize,
+    previous_time: f32,
+    elapsed: f32,
+    duration: f32,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -221,13 +337,37 @@ impl Animations {
             return false;
         }
 
-        match self.animations.get_mut(self.playback_state.current) {
-            Some(animation) => {
-                self.playback_state.advance(delta_time);
-                animation.animate(nodes, self.playback_state.time)
-            }
-            _ => false,
+        if self.animations.get(self.playback_state.current).is_none() {
+            return false;
         }
+        self.playback_state.advance(delta_time);
+
+        let current = &self.animations[self.playback_state.current];
+        let key_frame = current.sample(self.playback_state.time);
+
+        let key_frame = match self.crossfade.as_mut() {
+            Some(crossfade) => {
+                let previous = &self.animations[crossfade.previous];
+                crossfade.elapsed += delta_time;
+                crossfade.previous_time = match self.playback_state.playback_mode {
+                    PlaybackMode::Loop if previous.total_time > 0.0 => {
+                        (crossfade.previous_time + delta_time) % previous.total_time
+                    }
+                    _ => f32::min(crossfade.previous_time + delta_time, previous.total_time),
+                };
+
+                let weight = crossfade.elapsed / crossfade.duration;
+                if weight >= 1.0 {
+                    self.crossfade = None;
+                    key_frame
+                } else {
+                    key_frame.blend_from(previous.sample(crossfade.previous_time), weight)
+                }
+            }
+            None => key_frame,
+        };
+
+        apply_key_frame(nodes, key_frame)
     }
 
     pub fn get_playback_state(&self) -> &PlaybackState {
@@ -242,6 +382,26 @@ impl Animations {
         }
     }
 
+    //fade_time为0时与set_current一样立即切换
+    pub fn blend_to(&mut self, index: usize, fade_time: f32) {
+        let current = self.playback_state.current;
+        if index == current || index >= self.animations.len() {
+            return;
+        }
+
+        self.crossfade = if fade_time > 0.0 && current < self.animations.len() {
+            Some(Crossfade {
+                previous: current,
+                previous_time: self.playback_state.time,
+                elapsed: 0.0,
+                duration: fade_time,
+            })
+        } else {
+            None
+        };
+        self.set_current(index);
+    }
+
     pub fn set_playback_mode(&mut self, playback_mode: PlaybackMode) {
         self.playback_state.playback_mode = playback_mode;
     }
@@ -252,6 +412,7 @@ impl Animations {
 
     pub fn stop(&mut self) {
         self.playback_state.paused = true;
+        self.crossfade = None;
         self.reset();
     }
 
@@ -259,6 +420,17 @@ impl Animations {
         self.playback_state.time = 0.0;
     }
 
+    pub fn set_time(&mut self, nodes: &mut Nodes, time: f32) -> bool {
+        self.playback_state.paused = true;
+        self.crossfade = None;
+        self.playback_state.time = time.clamp(0.0, self.playback_state.total_time);
+
+        match self.animations.get_mut(self.playback_state.current) {
+            Some(animation) => animation.animate(nodes, self.playback_state.time),
+            _ => false,
+        }
+    }
+
     pub fn animations(&self) -> &[Animation] {
         &self.animations
     }
@@ -270,6 +442,7 @@ pub struct Animation {
     translation_channels: Vec<Channel<Vector3<f32>>>,
     rotation_channels: Vec<Channel<Quaternion<f32>>>,
     scale_channels: Vec<Channel<Vector3<f32>>>,
+    weight_channels: Vec<WeightChannel>,
 }
 
 impl Animation {
@@ -277,18 +450,7 @@ impl Animation {
     ///
     /// Returns true if any nodes was updated.
     pub fn animate(&mut self, nodes: &mut Nodes, time: f32) -> bool {
-        let NodesKeyFrame(translations, rotations, scale) = self.sample(time);
-        translations.iter().for_each(|(node_index, translation)| {
-            nodes.nodes_mut()[*node_index].set_translation(*translation);
-        });
-        rotations.iter().for_each(|(node_index, rotation)| {
-            nodes.nodes_mut()[*node_index].set_rotation(*rotation);
-        });
-        scale.iter().for_each(|(node_index, scale)| {
-            nodes.nodes_mut()[*node_index].set_scale(*scale);
-        });
-
-        !translations.is_empty() || !rotations.is_empty() || !scale.is_empty()
+        apply_key_frame(nodes, self.sample(time))
     }
 
     fn sample(&self, t: f32) -> NodesKeyFrame {
@@ -305,10 +467,34 @@ impl Animation {
                 .iter()
                 .filter_map(|tc| tc.sample(t))
                 .collect::<Vec<_>>(),
+            self.weight_channels
+                .iter()
+                .filter_map(|wc| wc.sample(t))
+                .collect::<Vec<_>>(),
         )
     }
 }
 
+fn apply_key_frame(nodes: &mut Nodes, key_frame: NodesKeyFrame) -> bool {
+    let NodesKeyFrame(translations, rotations, scale, weights) = key_frame;
+    translations.iter().for_each(|(node_index, translation)| {
+        nodes.nodes_mut()[*node_index].set_translation(*translation);
+    });
+    rotations.iter().for_each(|(node_index, rotation)| {
+        nodes.nodes_mut()[*node_index].set_rotation(*rotation);
+    });
+    scale.iter().for_each(|(node_index, scale)| {
+        nodes.nodes_mut()[*node_index].set_scale(*scale);
+    });
+    weights
+        .iter()
+        .for_each(|((node_index, target_index), weight)| {
+            nodes.nodes_mut()[*node_index].set_weight(*target_index, *weight);
+        });
+
+    !translations.is_empty() || !rotations.is_empty() || !scale.is_empty() || !weights.is_empty()
+}
+
 pub fn load_animations(gltf_animations: GltfAnimations, data: &[Data]) -> Option<Animations> {
     if gltf_animations.len() == 0 {
         return None;
@@ -328,13 +514,16 @@ pub fn load_animations(gltf_animations: GltfAnimations, data: &[Data]) -> Option
             paused: false,
             playback_mode: PlaybackMode::Loop,
         },
+        crossfade: None,
     })
 }
 
 fn map_animation(gltf_animation: &GltfAnimation, data: &[Data]) -> Animation {
+    warn_camera_channels(gltf_animation);
     let translation_channels = map_translation_channels(gltf_animation.channels(), data);
     let rotation_channels = map_rotation_channels(gltf_animation.channels(), data);
     let scale_channels = map_scale_channels(gltf_animation.channels(), data);
+    let weight_channels = map_weight_channels(gltf_animation.channels(), data);
 
     let max_translation_time = translation_channels
         .iter()
@@ -351,24 +540,64 @@ fn map_animation(gltf_animation: &GltfAnimation, data: &[Data]) -> Animation {
         .map(Channel::get_max_time)
         .max_by(|c0, c1| c0.partial_cmp(c1).unwrap_or(Ordering::Equal))
         .unwrap_or(0.0);
-
-    let total_time = *[max_translation_time, max_rotation_time, max_scale_time]
+    let max_weight_time = weight_channels
         .iter()
+        .map(WeightChannel::get_max_time)
         .max_by(|c0, c1| c0.partial_cmp(c1).unwrap_or(Ordering::Equal))
-        .unwrap_or(&0.0);
+        .unwrap_or(0.0);
+
+    let total_time = *[
+        max_translation_time,
+        max_rotation_time,
+        max_scale_time,
+        max_weight_time,
+    ]
+    .iter()
+    .max_by(|c0, c1| c0.partial_cmp(c1).unwrap_or(Ordering::Equal))
+    .unwrap_or(&0.0);
 
     Animation {
         total_time,
         translation_channels,
         rotation_channels,
         scale_channels,
+        weight_channels,
+    }
+}
+
+//glTF没有相机属性的动画目标，相机节点的TRS通道只移动节点，渲染器不使用glTF相机
+fn warn_camera_channels(gltf_animation: &GltfAnimation) {
+    let animates_camera = gltf_animation
+        .channels()
+        .any(|c| c.target().node().camera().is_some());
+    if animates_camera {
+        log::warn!(
+            "动画{}作用于glTF相机节点，相机不会跟随动画",
+            gltf_animation.index()
+        );
+    }
+}
+
+//输出与关键帧对不上的通道直接丢弃，避免把错误的数据应用到节点上
+fn validate_channel<T>(gltf_channel: &GltfChannel, channel: Channel<T>) -> Option<Channel<T>> {
+    if channel.sampler.is_valid() {
+        Some(channel)
+    } else {
+        log::warn!(
+            "动画{}中节点{}的通道关键帧与输出数量不匹配，忽略",
+            gltf_channel.animation().index(),
+            gltf_channel.target().node().index()
+        );
+        None
     }
 }
 
 fn map_translation_channels(gltf_channels: Channels, data: &[Data]) -> Vec<Channel<Vector3<f32>>> {
     gltf_channels
         .filter(|c| c.target().property() == Property::Translation)
-        .filter_map(|c| map_translation_channel(&c, data))
+        .filter_map(|c| {
+            map_translation_channel(&c, data).and_then(|channel| validate_channel(&c, channel))
+        })
         .collect::<Vec<_>>()
 }
 
@@ -399,7 +628,9 @@ fn map_translation_channel(
 fn map_rotation_channels(gltf_channels: Channels, data: &[Data]) -> Vec<Channel<Quaternion<f32>>> {
     gltf_channels
         .filter(|c| c.target().property() == Property::Rotation)
-        .filter_map(|c| map_rotation_channel(&c, data))
+        .filter_map(|c| {
+            map_rotation_channel(&c, data).and_then(|channel| validate_channel(&c, channel))
+        })
         .collect::<Vec<_>>()
 }
 
@@ -430,7 +661,9 @@ fn map_rotation_channel(
 fn map_scale_channels(gltf_channels: Channels, data: &[Data]) -> Vec<Channel<Vector3<f32>>> {
     gltf_channels
         .filter(|c| c.target().property() == Property::Scale)
-        .filter_map(|c| map_scale_channel(&c, data))
+        .filter_map(|c| {
+            map_scale_channel(&c, data).and_then(|channel| validate_channel(&c, channel))
+        })
         .collect::<Vec<_>>()
 }
 
@@ -455,6 +688,67 @@ fn map_scale_channel(gltf_channel: &GltfChannel, data: &[Data]) -> Option<Channe
     }
 }
 
+fn map_weight_channels(gltf_channels: Channels, data: &[Data]) -> Vec<WeightChannel> {
+    gltf_channels
+        .filter(|c| c.target().property() == Property::MorphTargetWeights)
+        .filter_map(|c| map_weight_channel(&c, data))
+        .flatten()
+        .collect::<Vec<_>>()
+}
+
+//输出按关键帧排列，每个关键帧依次是所有target的权重
+fn map_weight_channel(gltf_channel: &GltfChannel, data: &[Data]) -> Option<Vec<WeightChannel>> {
+    let node = gltf_channel.target().node();
+    let target_count = node.mesh().map_or(0, |mesh| {
+        mesh.primitives()
+            .map(|p| p.morph_targets().len())
+            .max()
+            .unwrap_or(0)
+    });
+    if target_count == 0 {
+        log::warn!(
+            "动画{}的权重通道作用于没有morph target的节点{}，忽略",
+            gltf_channel.animation().index(),
+            node.index()
+        );
+        return None;
+    }
+
+    let interpolation = map_interpolation(gltf_channel.sampler().interpolation())?;
+    let reader = gltf_channel.reader(|buffer| Some(&data[buffer.index()]));
+    let times = read_times(&reader);
+    let weights = read_weights(&reader);
+    let stride = interpolation.outputs_per_key_frame();
+    if times.is_empty() || weights.len() != times.len() * stride * target_count {
+        log::warn!(
+            "动画{}中节点{}的权重数量与morph target数量不匹配，忽略",
+            gltf_channel.animation().index(),
+            node.index()
+        );
+        return None;
+    }
+
+    let channels = (0..target_count)
+        .map(|target_index| WeightChannel {
+            channel: Channel {
+                sampler: Sampler {
+                    interpolation,
+                    times: times.clone(),
+                    values: weights
+                        .iter()
+                        .skip(target_index)
+                        .step_by(target_count)
+                        .copied()
+                        .collect(),
+                },
+                node_index: node.index(),
+            },
+            target_index,
+        })
+        .collect();
+    Some(channels)
+}
+
 fn map_interpolation(gltf_interpolation: GltfInterpolation) -> Option<Interpolation> {
     match gltf_interpolation {
         GltfInterpolation::Linear => Some(Interpolation::Linear),
@@ -508,3 +802,55 @@ where
             _ => vec![],
         })
 }
+
+fn read_weights<'a, 's, F>(reader: &Reader<'a, 's, F>) -> Vec<f32>
+where
+    F: Clone + Fn(Buffer<'a>) -> Option<&'s [u8]>,
+{
+    reader
+        .read_outputs()
+        .map_or(vec![], |outputs| match outputs {
+            ReadOutputs::MorphTargetWeights(weights) => weights.into_f32().collect(),
+            _ => vec![],
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::compute_node_uids;
+
+    //按README里的关键帧表在各个时间点采样morph权重
+    #[test]
+    fn morph_weights_are_sampled() {
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../../../assets/models/MorphWeightsAnimation/glTF-Embedded/MorphWeightsAnimation.gltf"
+        );
+        let (document, buffers, _) = gltf::import(path).unwrap();
+        let scene = document.default_scene().unwrap();
+        let uids = compute_node_uids(&document);
+        let mut nodes = Nodes::from_gltf_nodes(document.nodes(), &scene, &uids);
+        let mut animations = load_animations(document.animations(), &buffers).unwrap();
+        let mut weights_at = |animation: usize, time: f32| {
+            animations.set_current(animation);
+            animations.set_time(&mut nodes, time);
+            nodes.nodes()[0].weights().to_vec()
+        };
+
+        assert_eq!(weights_at(0, 0.5), [0.5, 0.0]);
+        assert_eq!(weights_at(0, 1.0), [1.0, 0.0]);
+        assert_eq!(weights_at(0, 1.5), [0.5, 0.5]);
+        assert_eq!(weights_at(0, 2.0), [0.0, 1.0]);
+
+        //切线全为0时三次样条在中点取两端的平均值
+        assert_eq!(weights_at(1, 0.5), [0.5, 0.5]);
+        assert_eq!(weights_at(1, 1.0), [1.0, 1.0]);
+        assert_eq!(weights_at(1, 2.0), [0.0, 0.0]);
+
+        //输出数量不匹配的通道被忽略，权重保持上一次的值
+        weights_at(0, 1.0);
+        assert_eq!(weights_at(2, 0.5), [1.0, 0.0]);
+        assert!(animations.animations()[2].weight_channels.is_empty());
+    }
+}
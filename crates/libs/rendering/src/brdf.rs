@@ -1,8 +1,11 @@
 use std::mem::size_of;
 use std::sync::Arc;
 use std::time::Instant;
-use vulkan::ash::vk::{self, RenderingAttachmentInfo, RenderingInfo};
-use vulkan::{create_device_local_buffer_with_data, Buffer, Context, Texture, Vertex};
+use vulkan::ash::vk;
+use vulkan::{
+    create_device_local_buffer_with_data, Buffer, Context, RenderingAttachment, RenderingDesc,
+    Texture, Vertex,
+};
 
 use crate::environment::{create_env_pipeline, EnvPipelineParameters};
 
@@ -53,6 +56,7 @@ impl QuadModel {
             context,
             vk::BufferUsageFlags::INDEX_BUFFER,
             &indices,
+            "BrdfQuad.Indices",
         );
         let vertices: [f32; 16] = [
             -1.0, -1.0, 0.0, 1.0, 1.0, -1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 0.0, -1.0, 1.0, 0.0, 0.0,
@@ -61,6 +65,7 @@ impl QuadModel {
             context,
             vk::BufferUsageFlags::VERTEX_BUFFER,
             &vertices,
+            "BrdfQuad.Vertices",
         );
 
         Self { vertices, indices }
@@ -132,38 +137,35 @@ pub(crate) fn create_brdf_lookup(context: &Arc<Context>, size: u32) -> Texture {
         (layout, pipeline)
     };
 
-    let lookup = Texture::create_renderable_texture(context, size, size, vk::Format::R16G16_SFLOAT
-        ,std::ffi::CString::new("BRDF LUT Texture").unwrap());
+    let lookup = Texture::create_renderable_texture(
+        context,
+        size,
+        size,
+        vk::Format::R16G16_SFLOAT,
+        std::ffi::CString::new("BRDF LUT Texture").unwrap(),
+    )
+    .expect("创建BRDF LUT失败！");
 
     // Render
     context.execute_one_time_commands(|buffer| {
-        let attachment_info = RenderingAttachmentInfo::builder()
-            .clear_value(vk::ClearValue {
-                color: vk::ClearColorValue {
-                    float32: [1.0, 0.0, 0.0, 1.0],
-                },
-            })
-            .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
-            .image_view(lookup.view)
-            .load_op(vk::AttachmentLoadOp::CLEAR)
-            .store_op(vk::AttachmentStoreOp::STORE);
-
-        let rendering_info = RenderingInfo::builder()
-            .color_attachments(std::slice::from_ref(&attachment_info))
-            .layer_count(1)
-            .render_area(vk::Rect2D {
-                offset: vk::Offset2D { x: 0, y: 0 },
+        let attachment = RenderingAttachment::new(
+            &lookup.image,
+            lookup.view,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        )
+        .clear_color([1.0, 0.0, 0.0, 1.0]);
+
+        context.cmd_begin_rendering(
+            buffer,
+            &RenderingDesc {
                 extent: vk::Extent2D {
                     width: size,
                     height: size,
                 },
-            });
-
-        unsafe {
-            context
-                .dynamic_rendering()
-                .cmd_begin_rendering(buffer, &rendering_info)
-        };
+                color_attachments: std::slice::from_ref(&attachment),
+                depth_attachment: None,
+            },
+        );
 
         unsafe { device.cmd_bind_pipeline(buffer, vk::PipelineBindPoint::GRAPHICS, pipeline) };
 
@@ -182,7 +184,7 @@ pub(crate) fn create_brdf_lookup(context: &Arc<Context>, size: u32) -> Texture {
 
         unsafe { device.cmd_draw_indexed(buffer, 6, 1, 0, 0, 0) };
 
-        unsafe { context.dynamic_rendering().cmd_end_rendering(buffer) }
+        context.cmd_end_rendering(buffer);
     });
 
     lookup.image.transition_image_layout(
@@ -1,3 +1,4 @@
+use crate::error::EnvironmentLoadingError;
 use crate::hdr_loader::{HDRTextureSource, HdrTextureLoader};
 use crate::math::perspective;
 use crate::util::*;
@@ -7,8 +8,10 @@ use std::mem::size_of;
 use std::path::Path;
 use std::sync::Arc;
 use std::time::Instant;
-use vulkan::ash::vk::{self, RenderingAttachmentInfo, RenderingInfo};
-use vulkan::{Context, SamplerParameters, Texture};
+use vulkan::ash::vk;
+use vulkan::{
+    Context, RenderingAttachment, RenderingDesc, SamplerParameters, Texture, DEFAULT_MAX_ANISOTROPY,
+};
 
 use crate::environment::{
     create_descriptors, create_env_pipeline, get_view_matrices, EnvPipelineParameters, SkyboxModel,
@@ -19,22 +22,27 @@ pub(crate) fn create_skybox_cubemap<P: AsRef<Path>>(
     context: &Arc<Context>,
     path: P,
     size: u32,
-) -> Texture {
+) -> Result<Texture, EnvironmentLoadingError> {
     log::info!("生成cubemap");
     let start = Instant::now();
     let device = context.device();
 
     AssetMgr::register_loader(HdrTextureLoader::default());
-    let resource = AssetMgr::load(path.as_ref());
-    let binding = resource.unwrap();
-    let hdr_resource = binding.as_any().downcast_ref::<HDRTextureSource>().unwrap();
+    let path = path.as_ref();
+    let resource = AssetMgr::load(path)
+        .ok_or_else(|| EnvironmentLoadingError::new(format!("无法读取{}", path.display())))?;
+    let hdr_resource = resource
+        .as_any()
+        .downcast_ref::<HDRTextureSource>()
+        .ok_or_else(|| {
+            EnvironmentLoadingError::new(format!("{}不是hdr或exr文件", path.display()))
+        })?;
     let mip_levels = (size as f32).log2().floor() as u32 + 1;
 
     let cubemap_format = vk::Format::R16G16B16A16_SFLOAT;
 
     let sampler_parameters = SamplerParameters {
-        anisotropy_enabled: true,
-        max_anisotropy: 16.0,
+        max_anisotropy: Some(DEFAULT_MAX_ANISOTROPY),
         ..Default::default()
     };
     let texture = Texture::from_rgba_32(
@@ -45,14 +53,14 @@ pub(crate) fn create_skybox_cubemap<P: AsRef<Path>>(
         &hdr_resource.data,
         Some(sampler_parameters),
         std::ffi::CString::new("Skybox Cubemap Texture").unwrap(),
-    );
+    )?;
     let cubemap = Texture::create_renderable_cubemap(
         context,
         size,
         mip_levels,
         cubemap_format,
         std::ffi::CString::new("Skybox Cubemap Texture").unwrap(),
-    );
+    )?;
 
     let skybox_model = SkyboxModel::new(context);
 
@@ -149,33 +157,24 @@ pub(crate) fn create_skybox_cubemap<P: AsRef<Path>>(
 
     context.execute_one_time_commands(|buffer| {
         for face in 0..6 {
-            let attachment_info = RenderingAttachmentInfo::builder()
-                .clear_value(vk::ClearValue {
-                    color: vk::ClearColorValue {
-                        float32: [0.0, 0.0, 0.0, 1.0],
-                    },
-                })
-                .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
-                .image_view(views[face])
-                .load_op(vk::AttachmentLoadOp::CLEAR)
-                .store_op(vk::AttachmentStoreOp::STORE);
-
-            let rendering_info = RenderingInfo::builder()
-                .color_attachments(std::slice::from_ref(&attachment_info))
-                .layer_count(1)
-                .render_area(vk::Rect2D {
-                    offset: vk::Offset2D { x: 0, y: 0 },
+            let attachment = RenderingAttachment::new(
+                &cubemap.image,
+                views[face],
+                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            )
+            .clear_color([0.0, 0.0, 0.0, 1.0]);
+
+            context.cmd_begin_rendering(
+                buffer,
+                &RenderingDesc {
                     extent: vk::Extent2D {
                         width: size,
                         height: size,
                     },
-                });
-
-            unsafe {
-                context
-                    .dynamic_rendering()
-                    .cmd_begin_rendering(buffer, &rendering_info)
-            };
+                    color_attachments: std::slice::from_ref(&attachment),
+                    depth_attachment: None,
+                },
+            );
 
             unsafe { device.cmd_bind_pipeline(buffer, vk::PipelineBindPoint::GRAPHICS, pipeline) };
 
@@ -218,7 +217,7 @@ pub(crate) fn create_skybox_cubemap<P: AsRef<Path>>(
 
             unsafe { device.cmd_draw_indexed(buffer, 36, 1, 0, 0, 0) };
 
-            unsafe { context.dynamic_rendering().cmd_end_rendering(buffer) };
+            context.cmd_end_rendering(buffer);
         }
     });
 
@@ -232,10 +231,9 @@ pub(crate) fn create_skybox_cubemap<P: AsRef<Path>>(
         height: size,
     });
 
+    views.iter().for_each(|v| context.destroy_image_view(*v));
+
     unsafe {
-        views
-            .iter()
-            .for_each(|v| device.destroy_image_view(*v, None));
         device.destroy_pipeline(pipeline, None);
         device.destroy_pipeline_layout(pipeline_layout, None);
     }
@@ -243,5 +241,5 @@ pub(crate) fn create_skybox_cubemap<P: AsRef<Path>>(
     let time = start.elapsed().as_millis();
     log::info!("cubemap生成结束，耗时{}ms", time);
 
-    cubemap
+    Ok(cubemap)
 }
@@ -1,5 +1,7 @@
 use crate::brdf::create_brdf_lookup;
 use crate::cubemap::create_skybox_cubemap;
+use crate::environment_cache::EnvironmentCacheKey;
+use crate::error::EnvironmentLoadingError;
 use crate::irradiance::create_irradiance_map;
 use crate::pre_filtered::create_pre_filtered_map;
 use cgmath::{Matrix4, Point3, Vector3};
@@ -8,11 +10,16 @@ use std::path::Path;
 use std::sync::Arc;
 use vulkan::ash::vk;
 use vulkan::{
-    create_device_local_buffer_with_data, create_pipeline, Buffer, Context, Descriptors,
-    PipelineParameters, ShaderParameters, Texture, Vertex,
+    create_device_local_buffer_with_data, create_pipeline, debug_name, Buffer, Context,
+    Descriptors, MultisampleParameters, PipelineParameters, ShaderParameters, Texture, Vertex,
 };
 
 pub const PRE_FILTERED_MAP_SIZE: u32 = 512;
+const IRRADIANCE_MAP_SIZE: u32 = 32;
+
+const SKYBOX_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+const IRRADIANCE_FORMAT: vk::Format = vk::Format::R32G32B32A32_SFLOAT;
+const PRE_FILTERED_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
 
 pub struct Environment {
     skybox: Texture,
@@ -22,21 +29,74 @@ pub struct Environment {
 }
 
 impl Environment {
-    pub fn new<P: AsRef<Path>>(context: &Arc<Context>, path: P, resolution: u32) -> Self {
-        let skybox = create_skybox_cubemap(context, path, resolution);
-        let irradiance = create_irradiance_map(context, &skybox, 32);
-        let pre_filtered = create_pre_filtered_map(context, &skybox, 512);
+    //读不到环境贴图时返回错误，IBL预计算中的设备错误仍然panic
+    pub fn new<P: AsRef<Path>>(
+        context: &Arc<Context>,
+        path: P,
+        resolution: u32,
+    ) -> Result<Self, EnvironmentLoadingError> {
+        let cache_key = EnvironmentCacheKey::new(
+            path.as_ref(),
+            &[resolution, IRRADIANCE_MAP_SIZE, PRE_FILTERED_MAP_SIZE],
+        );
+        let cached = cache_key
+            .as_ref()
+            .and_then(|key| load_cached_environment(context, key));
+
+        let (skybox, irradiance, pre_filtered) = match cached {
+            Some(textures) => textures,
+            None => {
+                let skybox = create_skybox_cubemap(context, path, resolution)?;
+                let irradiance = create_irradiance_map(context, &skybox, IRRADIANCE_MAP_SIZE);
+                let pre_filtered = create_pre_filtered_map(context, &skybox, PRE_FILTERED_MAP_SIZE);
+
+                if let Some(key) = cache_key {
+                    key.save_cubemap(context, "skybox", &skybox);
+                    key.save_cubemap(context, "irradiance", &irradiance);
+                    key.save_cubemap(context, "pre_filtered", &pre_filtered);
+                    key.remove_stale();
+                }
+
+                (skybox, irradiance, pre_filtered)
+            }
+        };
         let brdf_lookup = create_brdf_lookup(context, PRE_FILTERED_MAP_SIZE);
 
-        Self {
+        Ok(Self {
             skybox,
             irradiance,
             pre_filtered,
             brdf_lookup,
-        }
+        })
     }
 }
 
+//三张图都命中缓存时才跳过烘焙
+fn load_cached_environment(
+    context: &Arc<Context>,
+    key: &EnvironmentCacheKey,
+) -> Option<(Texture, Texture, Texture)> {
+    let skybox = key.load_cubemap(
+        context,
+        "skybox",
+        SKYBOX_FORMAT,
+        std::ffi::CString::new("Skybox Cubemap Texture").unwrap(),
+    )?;
+    let irradiance = key.load_cubemap(
+        context,
+        "irradiance",
+        IRRADIANCE_FORMAT,
+        std::ffi::CString::new("Irradiance Texture").unwrap(),
+    )?;
+    let pre_filtered = key.load_cubemap(
+        context,
+        "pre_filtered",
+        PRE_FILTERED_FORMAT,
+        std::ffi::CString::new("Pre-Filtered Texture").unwrap(),
+    )?;
+    Some((skybox, irradiance, pre_filtered))
+}
+
 impl Environment {
     pub fn skybox(&self) -> &Texture {
         &self.skybox
@@ -96,6 +156,7 @@ impl SkyboxModel {
             context,
             vk::BufferUsageFlags::INDEX_BUFFER,
             &indices,
+            "SkyboxModel.Indices",
         );
         let vertices: [f32; 24] = [
             -0.5, -0.5, -0.5, 0.5, -0.5, -0.5, 0.5, 0.5, -0.5, -0.5, 0.5, -0.5, -0.5, -0.5, 0.5,
@@ -105,6 +166,7 @@ impl SkyboxModel {
             context,
             vk::BufferUsageFlags::VERTEX_BUFFER,
             &vertices,
+            "SkyboxModel.Vertices",
         );
 
         SkyboxModel { vertices, indices }
@@ -202,6 +264,7 @@ pub fn create_descriptors(context: &Arc<Context>, texture: &Texture) -> Descript
                 .allocate_descriptor_sets(&allocate_info)
                 .unwrap()
         };
+        debug_name!(context, sets[0], "Environment.DescriptorSet");
 
         let cubemap_info = [vk::DescriptorImageInfo::builder()
             .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
@@ -243,12 +306,7 @@ pub fn create_env_pipeline<V: Vertex>(
     context: &Arc<Context>,
     params: EnvPipelineParameters,
 ) -> vk::Pipeline {
-    let multisampling_info = vk::PipelineMultisampleStateCreateInfo::builder()
-        .sample_shading_enable(false)
-        .rasterization_samples(vk::SampleCountFlags::TYPE_1)
-        .min_sample_shading(1.0)
-        .alpha_to_coverage_enable(false)
-        .alpha_to_one_enable(false);
+    let multisampling_info = MultisampleParameters::default().create_info();
 
     let color_blend_attachments = [vk::PipelineColorBlendAttachmentState::builder()
         .color_write_mask(
@@ -266,14 +324,18 @@ pub fn create_env_pipeline<V: Vertex>(
         .alpha_blend_op(vk::BlendOp::ADD)
         .build()];
 
+    let name = format!("{}.Pipeline", params.fragment_shader_name);
     create_pipeline::<V>(
         context,
         PipelineParameters {
+            name: &name,
             vertex_shader_params: ShaderParameters::new(params.vertex_shader_name),
             fragment_shader_params: ShaderParameters::new(params.fragment_shader_name),
             multisampling_info: &multisampling_info,
             viewport_info: params.viewport_info,
             rasterizer_info: params.rasterizer_info,
+            polygon_mode: params.rasterizer_info.polygon_mode,
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
             dynamic_state_info: params.dynamic_state_info,
             depth_stencil_info: None,
             color_blend_attachments: &color_blend_attachments,
@@ -284,4 +346,5 @@ pub fn create_env_pipeline<V: Vertex>(
             allow_derivatives: false,
         },
     )
+    .expect("创建环境贴图pipeline失败！")
 }
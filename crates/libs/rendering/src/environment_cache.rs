@@ -0,0 +1,391 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Instant, UNIX_EPOCH};
+use vulkan::ash::vk;
+use vulkan::{Buffer, Context, Texture, VkError};
+
+pub const ENVIRONMENT_CACHE_DIR: &str = "cache/environment";
+
+//烘焙算法或贴图参数变化时递增，旧缓存自动失效
+const CACHE_VERSION: u32 = 1;
+
+pub(crate) const KTX2_IDENTIFIER: [u8; 12] = [
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+pub(crate) const KTX2_HEADER_SIZE: usize = 80;
+pub(crate) const KTX2_LEVEL_INDEX_SIZE: usize = 24;
+
+//按HDRI路径、修改时间、分辨率和烘焙参数生成的缓存键，任何一项变化都会得到不同的缓存文件
+pub struct EnvironmentCacheKey {
+    directory: PathBuf,
+    stem: String,
+    name: String,
+}
+
+impl EnvironmentCacheKey {
+    pub fn new<P: AsRef<Path>>(path: P, parameters: &[u32]) -> Option<Self> {
+        let path = path.as_ref();
+        let metadata = fs::metadata(path).ok()?;
+        let modified = metadata
+            .modified()
+            .ok()?
+            .duration_since(UNIX_EPOCH)
+            .ok()?
+            .as_nanos();
+
+        let mut hasher = DefaultHasher::new();
+        CACHE_VERSION.hash(&mut hasher);
+        fs::canonicalize(path)
+            .unwrap_or_else(|_| path.to_path_buf())
+            .hash(&mut hasher);
+        modified.hash(&mut hasher);
+        metadata.len().hash(&mut hasher);
+        parameters.hash(&mut hasher);
+
+        let stem = path.file_stem()?.to_string_lossy().into_owned();
+        let name = format!("{}_{:016x}", stem, hasher.finish());
+        Some(Self {
+            directory: PathBuf::from(ENVIRONMENT_CACHE_DIR),
+            stem,
+            name,
+        })
+    }
+
+    fn file_path(&self, texture_name: &str) -> PathBuf {
+        self.directory
+            .join(format!("{}_{}.ktx2", self.name, texture_name))
+    }
+
+    pub fn load_cubemap(
+        &self,
+        context: &Arc<Context>,
+        texture_name: &str,
+        format: vk::Format,
+        image_name: std::ffi::CString,
+    ) -> Option<Texture> {
+        let path = self.file_path(texture_name);
+        let bytes = fs::read(&path).ok()?;
+        let start = Instant::now();
+
+        let cubemap = match read_ktx2_cubemap(&bytes, format) {
+            Some(cubemap) => cubemap,
+            None => {
+                log::warn!("环境缓存{}无效，重新烘焙", path.display());
+                return None;
+            }
+        };
+        let texture = match upload_cubemap(context, &cubemap, &bytes, image_name) {
+            Ok(texture) => texture,
+            Err(error) => {
+                log::warn!("环境缓存{}上传失败，重新烘焙: {}", path.display(), error);
+                return None;
+            }
+        };
+
+        let time = start.elapsed().as_millis();
+        log::info!("从缓存{}加载，耗时{}ms", path.display(), time);
+        Some(texture)
+    }
+
+    pub fn save_cubemap(&self, context: &Arc<Context>, texture_name: &str, texture: &Texture) {
+        let path = self.file_path(texture_name);
+        let Some(texel_size) = texel_size(texture.image.format) else {
+            log::warn!("不支持缓存格式{:?}", texture.image.format);
+            return;
+        };
+
+        let levels = read_back_cubemap(context, texture, texel_size);
+        let bytes = write_ktx2_cubemap(
+            texture.image.format,
+            texture.image.extent.width,
+            texel_size,
+            &levels,
+        );
+
+        let result = fs::create_dir_all(&self.directory).and_then(|_| fs::write(&path, bytes));
+        if let Err(error) = result {
+            log::warn!("写入环境缓存{}失败：{}", path.display(), error);
+        }
+    }
+
+    //删除同一HDRI其它参数或旧修改时间下的缓存
+    pub fn remove_stale(&self) {
+        let Ok(entries) = fs::read_dir(&self.directory) else {
+            return;
+        };
+        let prefix = format!("{}_", self.stem);
+        for entry in entries.flatten() {
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            if file_name.starts_with(&prefix) && !file_name.starts_with(&self.name) {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+    }
+}
+
+fn texel_size(format: vk::Format) -> Option<u32> {
+    match format {
+        vk::Format::R16G16B16A16_SFLOAT => Some(8),
+        vk::Format::R32G32B32A32_SFLOAT => Some(16),
+        _ => None,
+    }
+}
+
+fn level_size(size: u32, level: u32, texel_size: u32) -> usize {
+    let level_size = (size >> level).max(1) as usize;
+    level_size * level_size * texel_size as usize * 6
+}
+
+fn read_back_cubemap(context: &Arc<Context>, texture: &Texture, texel_size: u32) -> Vec<Vec<u8>> {
+    let image = &texture.image;
+    let size = image.extent.width;
+    let level_sizes = (0..image.mip_levels)
+        .map(|level| level_size(size, level, texel_size))
+        .collect::<Vec<_>>();
+
+    let mut buffer = Buffer::create(
+        Arc::clone(context),
+        level_sizes.iter().sum::<usize>() as _,
+        vk::BufferUsageFlags::TRANSFER_DST,
+        vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        "EnvironmentCache.Readback",
+    );
+
+    context.execute_one_time_commands(|command_buffer| {
+        image.cmd_transition_image_layout(
+            command_buffer,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        );
+        let mut offset = 0;
+        for (level, level_size) in level_sizes.iter().enumerate() {
+            image.cmd_copy_mip_to_buffer(command_buffer, &buffer, level as _, offset as _);
+            offset += level_size;
+        }
+        image.cmd_transition_image_layout(
+            command_buffer,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        );
+    });
+
+    let ptr = buffer.map_memory() as *const u8;
+    let data = unsafe { std::slice::from_raw_parts(ptr, buffer.size as _) };
+    let mut offset = 0;
+    level_sizes
+        .iter()
+        .map(|level_size| {
+            let level = data[offset..offset + level_size].to_vec();
+            offset += level_size;
+            level
+        })
+        .collect()
+}
+
+fn upload_cubemap(
+    context: &Arc<Context>,
+    cubemap: &Ktx2Cubemap,
+    bytes: &[u8],
+    image_name: std::ffi::CString,
+) -> Result<Texture, VkError> {
+    let texture = Texture::create_renderable_cubemap(
+        context,
+        cubemap.size,
+        cubemap.levels.len() as _,
+        cubemap.format,
+        image_name,
+    )?;
+
+    let total_size = cubemap.levels.iter().map(|l| l.len()).sum::<usize>();
+    let mut buffer = Buffer::create(
+        Arc::clone(context),
+        total_size as _,
+        vk::BufferUsageFlags::TRANSFER_SRC,
+        vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        "EnvironmentCache.Staging",
+    );
+
+    let ptr = buffer.map_memory() as *mut u8;
+    let mut offset = 0;
+    for level in cubemap.levels.iter() {
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                bytes[level.clone()].as_ptr(),
+                ptr.add(offset),
+                level.len(),
+            )
+        };
+        offset += level.len();
+    }
+
+    context.execute_one_time_commands(|command_buffer| {
+        let image = &texture.image;
+        image.cmd_transition_image_layout(
+            command_buffer,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        );
+        let mut offset = 0;
+        for (level, range) in cubemap.levels.iter().enumerate() {
+            image.cmd_copy_buffer_to_mip(command_buffer, &buffer, level as _, offset as _);
+            offset += range.len();
+        }
+        image.cmd_transition_image_layout(
+            command_buffer,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        );
+    });
+
+    Ok(texture)
+}
+
+struct Ktx2Cubemap {
+    format: vk::Format,
+    size: u32,
+    //每层mip在文件中的字节范围
+    levels: Vec<std::ops::Range<usize>>,
+}
+
+//只支持未压缩的浮点cubemap，数据格式描述(DFD)按RGBA线性浮点填写
+fn write_ktx2_cubemap(
+    format: vk::Format,
+    size: u32,
+    texel_size: u32,
+    levels: &[Vec<u8>],
+) -> Vec<u8> {
+    let channel_bits = texel_size * 2;
+    let dfd = {
+        let mut block = Vec::new();
+        push_u32(&mut block, 0); //vendorId | descriptorType
+        push_u16(&mut block, 2); //versionNumber
+        push_u16(&mut block, 24 + 16 * 4); //descriptorBlockSize
+        block.extend_from_slice(&[1, 1, 1, 0]); //RGBSDA | BT709 | linear | alpha straight
+        block.extend_from_slice(&[0; 4]); //texelBlockDimension
+        block.extend_from_slice(&[texel_size as u8, 0, 0, 0, 0, 0, 0, 0]);
+        for (i, channel) in [0u8, 1, 2, 15].iter().enumerate() {
+            push_u16(&mut block, (i as u32 * channel_bits) as u16);
+            block.push((channel_bits - 1) as u8);
+            block.push(channel | 0x80 | 0x40); //float | signed
+            block.extend_from_slice(&[0; 4]);
+            push_u32(&mut block, (-1.0f32).to_bits());
+            push_u32(&mut block, 1.0f32.to_bits());
+        }
+        let mut dfd = Vec::new();
+        push_u32(&mut dfd, 4 + block.len() as u32);
+        dfd.extend(block);
+        dfd
+    };
+
+    let dfd_offset = KTX2_HEADER_SIZE + KTX2_LEVEL_INDEX_SIZE * levels.len();
+    let mut data_offset = dfd_offset + dfd.len();
+
+    //KTX2要求从最小的mip开始存放，每层按texel大小对齐
+    let mut level_offsets = vec![0; levels.len()];
+    for (level, bytes) in levels.iter().enumerate().rev() {
+        data_offset = align(data_offset, texel_size as usize);
+        level_offsets[level] = data_offset;
+        data_offset += bytes.len();
+    }
+
+    let mut file = Vec::with_capacity(data_offset);
+    file.extend_from_slice(&KTX2_IDENTIFIER);
+    push_u32(&mut file, format.as_raw() as u32);
+    push_u32(&mut file, texel_size / 4);
+    push_u32(&mut file, size);
+    push_u32(&mut file, size);
+    push_u32(&mut file, 0); //pixelDepth
+    push_u32(&mut file, 0); //layerCount
+    push_u32(&mut file, 6); //faceCount
+    push_u32(&mut file, levels.len() as u32);
+    push_u32(&mut file, 0); //supercompressionScheme
+    push_u32(&mut file, dfd_offset as u32);
+    push_u32(&mut file, dfd.len() as u32);
+    push_u32(&mut file, 0); //kvdByteOffset
+    push_u32(&mut file, 0); //kvdByteLength
+    push_u64(&mut file, 0); //sgdByteOffset
+    push_u64(&mut file, 0); //sgdByteLength
+
+    for (level, bytes) in levels.iter().enumerate() {
+        push_u64(&mut file, level_offsets[level] as u64);
+        push_u64(&mut file, bytes.len() as u64);
+        push_u64(&mut file, bytes.len() as u64);
+    }
+    file.extend(dfd);
+
+    for (level, bytes) in levels.iter().enumerate().rev() {
+        file.resize(level_offsets[level], 0);
+        file.extend_from_slice(bytes);
+    }
+
+    file
+}
+
+fn read_ktx2_cubemap(bytes: &[u8], format: vk::Format) -> Option<Ktx2Cubemap> {
+    if bytes.len() < KTX2_HEADER_SIZE || bytes[0..12] != KTX2_IDENTIFIER {
+        return None;
+    }
+
+    let texel_size = texel_size(format)?;
+    let vk_format = read_u32(bytes, 12)?;
+    let size = read_u32(bytes, 20)?;
+    let face_count = read_u32(bytes, 36)?;
+    let level_count = read_u32(bytes, 40)?;
+    let supercompression = read_u32(bytes, 44)?;
+    if vk_format != format.as_raw() as u32
+        || read_u32(bytes, 24)? != size
+        || face_count != 6
+        || supercompression != 0
+        || level_count == 0
+    {
+        return None;
+    }
+
+    let levels = (0..level_count)
+        .map(|level| {
+            let index = KTX2_HEADER_SIZE + KTX2_LEVEL_INDEX_SIZE * level as usize;
+            let offset = read_u64(bytes, index)? as usize;
+            let length = read_u64(bytes, index + 8)? as usize;
+            let end = offset.checked_add(length)?;
+            if length != level_size(size, level, texel_size) || end > bytes.len() {
+                return None;
+            }
+            Some(offset..end)
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    Some(Ktx2Cubemap {
+        format,
+        size,
+        levels,
+    })
+}
+
+pub(crate) fn align(offset: usize, alignment: usize) -> usize {
+    offset.div_ceil(alignment) * alignment
+}
+
+pub(crate) fn push_u16(bytes: &mut Vec<u8>, value: u16) {
+    bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+pub(crate) fn push_u32(bytes: &mut Vec<u8>, value: u32) {
+    bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+pub(crate) fn push_u64(bytes: &mut Vec<u8>, value: u64) {
+    bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+pub(crate) fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let value = bytes.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes(value.try_into().ok()?))
+}
+
+pub(crate) fn read_u64(bytes: &[u8], offset: usize) -> Option<u64> {
+    let value = bytes.get(offset..offset + 8)?;
+    Some(u64::from_le_bytes(value.try_into().ok()?))
+}
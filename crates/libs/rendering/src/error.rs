@@ -1,4 +1,5 @@
 use std::{error::Error, fmt};
+use vulkan::VkError;
 
 #[derive(Debug)]
 pub struct ModelLoadingError {
@@ -20,3 +21,31 @@ impl fmt::Display for ModelLoadingError {
 }
 
 impl Error for ModelLoadingError {}
+
+//读取环境贴图或创建skybox cubemap失败时返回
+#[derive(Debug)]
+pub struct EnvironmentLoadingError {
+    message: String,
+}
+
+impl EnvironmentLoadingError {
+    pub fn new<S: Into<String>>(message: S) -> Self {
+        Self {
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for EnvironmentLoadingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "环境贴图加载失败: {}", self.message)
+    }
+}
+
+impl Error for EnvironmentLoadingError {}
+
+impl From<VkError> for EnvironmentLoadingError {
+    fn from(error: VkError) -> Self {
+        Self::new(error.to_string())
+    }
+}
@@ -0,0 +1,701 @@
+use crate::environment_cache::{
+    align, push_u16, push_u32, push_u64, read_u32, read_u64, KTX2_HEADER_SIZE, KTX2_IDENTIFIER,
+    KTX2_LEVEL_INDEX_SIZE,
+};
+use crate::error::ModelLoadingError;
+use crate::vertex::ModelVertex;
+use crate::Aabb;
+use cgmath::Vector3;
+use std::fs;
+use std::mem::size_of;
+use std::ops::Range;
+use std::path::Path;
+use vulkan::ash::vk;
+
+pub const FSCENE_EXTENSION: &str = "fscene";
+
+const FSCENE_MAGIC: &[u8; 4] = b"FSCN";
+//顶点布局、切线生成或文件结构变化时递增，旧文件需要重新转换
+const FSCENE_VERSION: u32 = 1;
+const FSCENE_HEADER_SIZE: usize = 16;
+const SECTION_ALIGNMENT: usize = 16;
+const PRIMITIVE_RECORD_SIZE: usize = 13 * 4;
+
+const PRIMITIVE_HAS_INDICES: u32 = 1;
+const PRIMITIVE_VERTEX_COLORS: u32 = 1 << 1;
+const PRIMITIVE_TANGENTS_VALID: u32 = 1 << 2;
+
+//预处理过的primitive，顶点和索引的范围按元素计，指向整个场景共用的顶点/索引数组
+#[derive(Clone, Copy, Debug)]
+pub struct FScenePrimitive {
+    pub mesh_index: usize,
+    //primitive在glTF网格中的位置，用来取回材质
+    pub primitive_index: usize,
+    pub vertices: (usize, usize),
+    pub indices: Option<(usize, usize)>,
+    pub use_vertex_colors: bool,
+    pub tangents_valid: bool,
+    pub aabb: Aabb<f32>,
+}
+
+//RGBA8图片和完整的mip链，levels[0]为原图
+pub struct FSceneImage {
+    pub width: u32,
+    pub height: u32,
+    pub levels: Vec<Vec<u8>>,
+}
+
+//写入.fscene的内容
+//材质、节点、灯光和采样参数仍由glTF JSON描述，动画和蒙皮从原始缓冲读取，
+//读取顶点、生成法线和切线、网格优化、解码图片和生成mip都在转换时完成
+pub struct FSceneData {
+    pub document: Vec<u8>,
+    pub buffers: Vec<Vec<u8>>,
+    pub vertices: Vec<ModelVertex>,
+    pub indices: Vec<u32>,
+    pub primitives: Vec<FScenePrimitive>,
+    pub images: Vec<FSceneImage>,
+}
+
+//读入内存的.fscene，各段只记录字节范围，顶点、索引和mip数据直接拷贝到staging缓冲
+pub struct FScene {
+    bytes: Vec<u8>,
+    document: Range<usize>,
+    buffers: Vec<Range<usize>>,
+    vertices: Range<usize>,
+    indices: Range<usize>,
+    primitives: Vec<FScenePrimitive>,
+    images: Vec<Ktx2Image>,
+}
+
+struct Ktx2Image {
+    width: u32,
+    height: u32,
+    levels: Vec<Range<usize>>,
+}
+
+impl FScene {
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self, ModelLoadingError> {
+        let path = path.as_ref();
+        let bytes = fs::read(path)
+            .map_err(|err| ModelLoadingError::new(format!("{}: {}", path.display(), err)))?;
+        Self::from_bytes(bytes)
+            .map_err(|message| ModelLoadingError::new(format!("{}: {}", path.display(), message)))
+    }
+
+    fn from_bytes(bytes: Vec<u8>) -> Result<Self, &'static str> {
+        if bytes.len() < FSCENE_HEADER_SIZE || &bytes[0..4] != FSCENE_MAGIC {
+            return Err("不是fscene文件");
+        }
+        if read_u32(&bytes, 4) != Some(FSCENE_VERSION)
+            || read_u32(&bytes, 8) != Some(size_of::<ModelVertex>() as u32)
+        {
+            return Err("fscene版本不匹配，需要重新转换");
+        }
+
+        let mut sections = Sections {
+            bytes: &bytes,
+            offset: FSCENE_HEADER_SIZE,
+        };
+        let invalid = "fscene数据损坏";
+
+        let document = sections.next().ok_or(invalid)?;
+        let buffers = (0..sections.count().ok_or(invalid)?)
+            .map(|_| sections.next())
+            .collect::<Option<Vec<_>>>()
+            .ok_or(invalid)?;
+        let vertices = sections.next().ok_or(invalid)?;
+        let indices = sections.next().ok_or(invalid)?;
+        let primitives = sections.next().ok_or(invalid)?;
+        let images = (0..sections.count().ok_or(invalid)?)
+            .map(|_| sections.next())
+            .collect::<Option<Vec<_>>>()
+            .ok_or(invalid)?;
+
+        if vertices.len() % size_of::<ModelVertex>() != 0
+            || indices.len() % size_of::<u32>() != 0
+            || primitives.len() % PRIMITIVE_RECORD_SIZE != 0
+        {
+            return Err(invalid);
+        }
+        let vertex_count = vertices.len() / size_of::<ModelVertex>();
+        let index_count = indices.len() / size_of::<u32>();
+
+        //范围越界的primitive会让GPU读到缓冲之外，加载时就拒绝
+        let primitives = bytes[primitives]
+            .chunks_exact(PRIMITIVE_RECORD_SIZE)
+            .map(read_primitive)
+            .map(|primitive| {
+                let primitive = primitive?;
+                let (offset, count) = primitive.vertices;
+                let vertices_valid = offset.checked_add(count)? <= vertex_count;
+                let indices_valid = primitive.indices.map_or(Some(true), |(offset, count)| {
+                    Some(offset.checked_add(count)? <= index_count)
+                })?;
+                (vertices_valid && indices_valid).then_some(primitive)
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or(invalid)?;
+
+        let images = images
+            .into_iter()
+            .map(|range| read_ktx2_image(&bytes, range))
+            .collect::<Option<Vec<_>>>()
+            .ok_or("fscene中的KTX2图片无效")?;
+
+        Ok(Self {
+            bytes,
+            document,
+            buffers,
+            vertices,
+            indices,
+            primitives,
+            images,
+        })
+    }
+}
+
+//Getters
+impl FScene {
+    pub fn document(&self) -> &[u8] {
+        &self.bytes[self.document.clone()]
+    }
+
+    pub fn buffers(&self) -> impl Iterator<Item = &[u8]> {
+        self.buffers.iter().map(|range| &self.bytes[range.clone()])
+    }
+
+    pub fn vertex_bytes(&self) -> &[u8] {
+        &self.bytes[self.vertices.clone()]
+    }
+
+    pub fn index_bytes(&self) -> &[u8] {
+        &self.bytes[self.indices.clone()]
+    }
+
+    pub fn primitives(&self) -> &[FScenePrimitive] {
+        &self.primitives
+    }
+
+    pub fn image_count(&self) -> usize {
+        self.images.len()
+    }
+
+    pub fn image_size(&self, index: usize) -> (u32, u32) {
+        let image = &self.images[index];
+        (image.width, image.height)
+    }
+
+    pub fn image_levels(&self, index: usize) -> Vec<&[u8]> {
+        self.images[index]
+            .levels
+            .iter()
+            .map(|range| &self.bytes[range.clone()])
+            .collect()
+    }
+}
+
+pub fn write_fscene<P: AsRef<Path>>(path: P, data: &FSceneData) -> std::io::Result<()> {
+    let mut file = Vec::new();
+    file.extend_from_slice(FSCENE_MAGIC);
+    push_u32(&mut file, FSCENE_VERSION);
+    push_u32(&mut file, size_of::<ModelVertex>() as u32);
+    push_u32(&mut file, 0);
+
+    push_section(&mut file, &data.document);
+    push_u32(&mut file, data.buffers.len() as u32);
+    for buffer in data.buffers.iter() {
+        push_section(&mut file, buffer);
+    }
+
+    //ModelVertex是repr(C)且只有4字节字段，没有填充，按内存布局直接写入
+    let vertices = unsafe {
+        std::slice::from_raw_parts(
+            data.vertices.as_ptr() as *const u8,
+            data.vertices.len() * size_of::<ModelVertex>(),
+        )
+    };
+    push_section(&mut file, vertices);
+    let indices = data
+        .indices
+        .iter()
+        .flat_map(|index| index.to_le_bytes())
+        .collect::<Vec<_>>();
+    push_section(&mut file, &indices);
+
+    let mut primitives = Vec::with_capacity(data.primitives.len() * PRIMITIVE_RECORD_SIZE);
+    data.primitives
+        .iter()
+        .for_each(|primitive| push_primitive(&mut primitives, primitive));
+    push_section(&mut file, &primitives);
+
+    push_u32(&mut file, data.images.len() as u32);
+    for image in data.images.iter() {
+        push_section(&mut file, &write_ktx2_image(image));
+    }
+
+    fs::write(path, file)
+}
+
+//按2x2盒式滤波生成mip链，层数与运行时blit生成的一致
+//颜色图片在线性空间中平均，alpha始终按线性数据处理
+pub fn generate_mips(width: u32, height: u32, rgba: &[u8], srgb: bool) -> Vec<Vec<u8>> {
+    let level_count = ((width.min(height) as f32).log2().floor() + 1.0) as u32;
+    let to_linear = (0..=255)
+        .map(|value| srgb_to_linear(value as f32 / 255.0))
+        .collect::<Vec<_>>();
+
+    let mut levels = vec![rgba.to_vec()];
+    let (mut src_width, mut src_height) = (width as usize, height as usize);
+    for _ in 1..level_count {
+        let src = levels.last().unwrap();
+        let dst_width = (src_width / 2).max(1);
+        let dst_height = (src_height / 2).max(1);
+        let mut dst = Vec::with_capacity(dst_width * dst_height * 4);
+        for y in 0..dst_height {
+            let ys = [(y * 2).min(src_height - 1), (y * 2 + 1).min(src_height - 1)];
+            for x in 0..dst_width {
+                let xs = [(x * 2).min(src_width - 1), (x * 2 + 1).min(src_width - 1)];
+                for channel in 0..4 {
+                    let decode = srgb && channel < 3;
+                    let sum = ys
+                        .iter()
+                        .flat_map(|y| xs.iter().map(move |x| (y * src_width + x) * 4 + channel))
+                        .map(|index| {
+                            if decode {
+                                to_linear[src[index] as usize]
+                            } else {
+                                src[index] as f32 / 255.0
+                            }
+                        })
+                        .sum::<f32>();
+                    let value = if decode {
+                        linear_to_srgb(sum / 4.0)
+                    } else {
+                        sum / 4.0
+                    };
+                    dst.push((value * 255.0).round().clamp(0.0, 255.0) as u8);
+                }
+            }
+        }
+        levels.push(dst);
+        (src_width, src_height) = (dst_width, dst_height);
+    }
+    levels
+}
+
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+//每段前面是8字节长度，数据按SECTION_ALIGNMENT对齐
+fn push_section(file: &mut Vec<u8>, bytes: &[u8]) {
+    file.resize(align(file.len(), 8), 0);
+    push_u64(file, bytes.len() as u64);
+    file.resize(align(file.len(), SECTION_ALIGNMENT), 0);
+    file.extend_from_slice(bytes);
+}
+
+struct Sections<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl Sections<'_> {
+    fn count(&mut self) -> Option<usize> {
+        let count = read_u32(self.bytes, self.offset)?;
+        self.offset += 4;
+        Some(count as usize)
+    }
+
+    fn next(&mut self) -> Option<Range<usize>> {
+        let offset = align(self.offset, 8);
+        let length = read_u64(self.bytes, offset)? as usize;
+        let start = align(offset + 8, SECTION_ALIGNMENT);
+        let end = start.checked_add(length)?;
+        if end > self.bytes.len() {
+            return None;
+        }
+        self.offset = end;
+        Some(start..end)
+    }
+}
+
+fn push_primitive(bytes: &mut Vec<u8>, primitive: &FScenePrimitive) {
+    let (index_offset, index_count) = primitive.indices.unwrap_or((0, 0));
+    let mut flags = 0;
+    if primitive.indices.is_some() {
+        flags |= PRIMITIVE_HAS_INDICES;
+    }
+    if primitive.use_vertex_colors {
+        flags |= PRIMITIVE_VERTEX_COLORS;
+    }
+    if primitive.tangents_valid {
+        flags |= PRIMITIVE_TANGENTS_VALID;
+    }
+
+    push_u32(bytes, primitive.mesh_index as u32);
+    push_u32(bytes, primitive.primitive_index as u32);
+    push_u32(bytes, primitive.vertices.0 as u32);
+    push_u32(bytes, primitive.vertices.1 as u32);
+    push_u32(bytes, index_offset as u32);
+    push_u32(bytes, index_count as u32);
+    push_u32(bytes, flags);
+    let (min, max) = (primitive.aabb.min(), primitive.aabb.max());
+    for value in [min.x, min.y, min.z, max.x, max.y, max.z] {
+        push_u32(bytes, value.to_bits());
+    }
+}
+
+fn read_primitive(bytes: &[u8]) -> Option<FScenePrimitive> {
+    let values = (0..13)
+        .map(|i| read_u32(bytes, i * 4))
+        .collect::<Option<Vec<_>>>()?;
+    let float = |i: usize| f32::from_bits(values[i]);
+    let flags = values[6];
+
+    Some(FScenePrimitive {
+        mesh_index: values[0] as usize,
+        primitive_index: values[1] as usize,
+        vertices: (values[2] as usize, values[3] as usize),
+        indices: (flags & PRIMITIVE_HAS_INDICES != 0)
+            .then_some((values[4] as usize, values[5] as usize)),
+        use_vertex_colors: flags & PRIMITIVE_VERTEX_COLORS != 0,
+        tangents_valid: flags & PRIMITIVE_TANGENTS_VALID != 0,
+        aabb: Aabb::new(
+            Vector3::new(float(7), float(8), float(9)),
+            Vector3::new(float(10), float(11), float(12)),
+        ),
+    })
+}
+
+fn rgba8_level_size(width: u32, height: u32, level: u32) -> usize {
+    (width >> level).max(1) as usize * (height >> level).max(1) as usize * 4
+}
+
+//只写未压缩的RGBA8二维纹理，是否按sRGB采样由材质用途决定，DFD按线性填写
+fn write_ktx2_image(image: &FSceneImage) -> Vec<u8> {
+    let dfd = {
+        let mut block = Vec::new();
+        push_u32(&mut block, 0); //vendorId | descriptorType
+        push_u16(&mut block, 2); //versionNumber
+        push_u16(&mut block, 24 + 16 * 4); //descriptorBlockSize
+        block.extend_from_slice(&[1, 1, 1, 0]); //RGBSDA | BT709 | linear | alpha straight
+        block.extend_from_slice(&[0; 4]); //texelBlockDimension
+        block.extend_from_slice(&[4, 0, 0, 0, 0, 0, 0, 0]);
+        for (i, channel) in [0u8, 1, 2, 15].iter().enumerate() {
+            push_u16(&mut block, i as u16 * 8);
+            block.push(7);
+            block.push(*channel);
+            block.extend_from_slice(&[0; 4]);
+            push_u32(&mut block, 0);
+            push_u32(&mut block, 255);
+        }
+        let mut dfd = Vec::new();
+        push_u32(&mut dfd, 4 + block.len() as u32);
+        dfd.extend(block);
+        dfd
+    };
+
+    let levels = &image.levels;
+    let dfd_offset = KTX2_HEADER_SIZE + KTX2_LEVEL_INDEX_SIZE * levels.len();
+    let mut data_offset = dfd_offset + dfd.len();
+
+    //KTX2要求从最小的mip开始存放
+    let mut level_offsets = vec![0; levels.len()];
+    for (level, bytes) in levels.iter().enumerate().rev() {
+        data_offset = align(data_offset, 4);
+        level_offsets[level] = data_offset;
+        data_offset += bytes.len();
+    }
+
+    let mut file = Vec::with_capacity(data_offset);
+    file.extend_from_slice(&KTX2_IDENTIFIER);
+    push_u32(&mut file, vk::Format::R8G8B8A8_UNORM.as_raw() as u32);
+    push_u32(&mut file, 1); //typeSize
+    push_u32(&mut file, image.width);
+    push_u32(&mut file, image.height);
+    push_u32(&mut file, 0); //pixelDepth
+    push_u32(&mut file, 0); //layerCount
+    push_u32(&mut file, 1); //faceCount
+    push_u32(&mut file, levels.len() as u32);
+    push_u32(&mut file, 0); //supercompressionScheme
+    push_u32(&mut file, dfd_offset as u32);
+    push_u32(&mut file, dfd.len() as u32);
+    push_u32(&mut file, 0); //kvdByteOffset
+    push_u32(&mut file, 0); //kvdByteLength
+    push_u64(&mut file, 0); //sgdByteOffset
+    push_u64(&mut file, 0); //sgdByteLength
+
+    for (level, bytes) in levels.iter().enumerate() {
+        push_u64(&mut file, level_offsets[level] as u64);
+        push_u64(&mut file, bytes.len() as u64);
+        push_u64(&mut file, bytes.len() as u64);
+    }
+    file.extend(dfd);
+
+    for (level, bytes) in levels.iter().enumerate().rev() {
+        file.resize(level_offsets[level], 0);
+        file.extend_from_slice(bytes);
+    }
+
+    file
+}
+
+//返回的mip范围是相对整个fscene文件的
+fn read_ktx2_image(bytes: &[u8], range: Range<usize>) -> Option<Ktx2Image> {
+    let ktx2 = &bytes[range.clone()];
+    if ktx2.len() < KTX2_HEADER_SIZE || ktx2[0..12] != KTX2_IDENTIFIER {
+        return None;
+    }
+
+    let width = read_u32(ktx2, 20)?;
+    let height = read_u32(ktx2, 24)?;
+    let level_count = read_u32(ktx2, 40)?;
+    if read_u32(ktx2, 12)? != vk::Format::R8G8B8A8_UNORM.as_raw() as u32
+        || read_u32(ktx2, 36)? != 1
+        || read_u32(ktx2, 44)? != 0
+        || width == 0
+        || height == 0
+        || level_count == 0
+        || level_count > u32::BITS - width.max(height).leading_zeros()
+    {
+        return None;
+    }
+
+    let levels = (0..level_count)
+        .map(|level| {
+            let index = KTX2_HEADER_SIZE + KTX2_LEVEL_INDEX_SIZE * level as usize;
+            let offset = read_u64(ktx2, index)? as usize;
+            let length = read_u64(ktx2, index + 8)? as usize;
+            let end = offset.checked_add(length)?;
+            if length != rgba8_level_size(width, height, level) || end > ktx2.len() {
+                return None;
+            }
+            Some(range.start + offset..range.start + end)
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    Some(Ktx2Image {
+        width,
+        height,
+        levels,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vertex(index: u32) -> ModelVertex {
+        let value = index as f32;
+        ModelVertex {
+            position: [value, value + 0.5, -value],
+            normal: [0.0, 0.0, 1.0],
+            tex_coords_0: [value / 4.0, 0.25],
+            tex_coords_1: [0.0, 0.0],
+            tangent: [1.0, 0.0, 0.0, -1.0],
+            weights: [1.0, 0.0, 0.0, 0.0],
+            joints: [index, 0, 0, 0],
+            colors: [1.0, 1.0, 1.0, 1.0],
+        }
+    }
+
+    fn scene_data() -> FSceneData {
+        let pixels = (0..4 * 2 * 4).map(|i| (i * 7) as u8).collect::<Vec<_>>();
+        FSceneData {
+            document: br#"{"asset":{"version":"2.0"}}"#.to_vec(),
+            buffers: vec![vec![1, 2, 3], vec![]],
+            vertices: (0..5).map(vertex).collect(),
+            indices: vec![0, 1, 2, 2, 3, 4],
+            primitives: vec![
+                FScenePrimitive {
+                    mesh_index: 0,
+                    primitive_index: 0,
+                    vertices: (0, 5),
+                    indices: Some((0, 6)),
+                    use_vertex_colors: true,
+                    tangents_valid: false,
+                    aabb: Aabb::new(Vector3::new(-4.0, 0.5, -4.0), Vector3::new(4.0, 4.5, 0.0)),
+                },
+                FScenePrimitive {
+                    mesh_index: 1,
+                    primitive_index: 2,
+                    vertices: (2, 3),
+                    indices: None,
+                    use_vertex_colors: false,
+                    tangents_valid: true,
+                    aabb: Aabb::new(Vector3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 1.0, 1.0)),
+                },
+            ],
+            images: vec![FSceneImage {
+                width: 4,
+                height: 2,
+                levels: generate_mips(4, 2, &pixels, true),
+            }],
+        }
+    }
+
+    //测试文件写到临时目录，文件名带进程号避免并行测试冲突
+    fn write_bytes(name: &str, data: &FSceneData) -> Vec<u8> {
+        let path =
+            std::env::temp_dir().join(format!("fate_{}_{}.fscene", std::process::id(), name));
+        write_fscene(&path, data).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        fs::remove_file(path).unwrap();
+        bytes
+    }
+
+    fn vertex_bytes(vertices: &[ModelVertex]) -> &[u8] {
+        unsafe {
+            std::slice::from_raw_parts(
+                vertices.as_ptr() as *const u8,
+                std::mem::size_of_val(vertices),
+            )
+        }
+    }
+
+    #[test]
+    fn fscene_round_trip() {
+        let data = scene_data();
+        let path =
+            std::env::temp_dir().join(format!("fate_{}_round_trip.fscene", std::process::id()));
+        write_fscene(&path, &data).unwrap();
+        let scene = FScene::read(&path).unwrap();
+        fs::remove_file(path).unwrap();
+
+        assert_eq!(scene.document(), &data.document[..]);
+        assert_eq!(
+            scene.buffers().collect::<Vec<_>>(),
+            vec![&[1u8, 2, 3][..], &[]]
+        );
+        assert_eq!(scene.vertex_bytes(), vertex_bytes(&data.vertices));
+        let indices = scene
+            .index_bytes()
+            .chunks_exact(4)
+            .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(indices, data.indices);
+
+        assert_eq!(scene.primitives().len(), 2);
+        for (read, written) in scene.primitives().iter().zip(&data.primitives) {
+            assert_eq!(read.mesh_index, written.mesh_index);
+            assert_eq!(read.primitive_index, written.primitive_index);
+            assert_eq!(read.vertices, written.vertices);
+            assert_eq!(read.indices, written.indices);
+            assert_eq!(read.use_vertex_colors, written.use_vertex_colors);
+            assert_eq!(read.tangents_valid, written.tangents_valid);
+            assert_eq!(read.aabb.min(), written.aabb.min());
+            assert_eq!(read.aabb.max(), written.aabb.max());
+        }
+
+        assert_eq!(scene.image_count(), 1);
+        assert_eq!(scene.image_size(0), (4, 2));
+        let levels = &data.images[0].levels;
+        assert_eq!(
+            scene.image_levels(0),
+            levels.iter().map(Vec::as_slice).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn sections_are_aligned() {
+        let bytes = write_bytes("aligned", &scene_data());
+        let scene = FScene::from_bytes(bytes).unwrap();
+        assert_eq!(scene.vertices.start % SECTION_ALIGNMENT, 0);
+        assert_eq!(scene.indices.start % SECTION_ALIGNMENT, 0);
+    }
+
+    #[test]
+    fn missing_file_is_an_error() {
+        let path = std::env::temp_dir().join("fate_missing_file.fscene");
+        assert!(FScene::read(path).is_err());
+    }
+
+    #[test]
+    fn wrong_header_is_rejected() {
+        let bytes = write_bytes("header", &scene_data());
+
+        let mut magic = bytes.clone();
+        magic[0] = b'X';
+        assert_eq!(FScene::from_bytes(magic).err(), Some("不是fscene文件"));
+
+        let mut version = bytes.clone();
+        version[4..8].copy_from_slice(&(FSCENE_VERSION + 1).to_le_bytes());
+        assert_eq!(
+            FScene::from_bytes(version).err(),
+            Some("fscene版本不匹配，需要重新转换")
+        );
+
+        let mut stride = bytes;
+        stride[8..12].copy_from_slice(&0u32.to_le_bytes());
+        assert!(FScene::from_bytes(stride).is_err());
+
+        assert!(FScene::from_bytes(Vec::new()).is_err());
+    }
+
+    #[test]
+    fn truncated_file_is_rejected() {
+        let bytes = write_bytes("truncated", &scene_data());
+        //任何位置截断都只能返回错误，不能越界
+        for len in 0..bytes.len() {
+            assert!(
+                FScene::from_bytes(bytes[..len].to_vec()).is_err(),
+                "len {}",
+                len
+            );
+        }
+    }
+
+    #[test]
+    fn out_of_range_primitive_is_rejected() {
+        let mut data = scene_data();
+        data.primitives[1].vertices = (4, 2);
+        let bytes = write_bytes("vertex_range", &data);
+        assert_eq!(FScene::from_bytes(bytes).err(), Some("fscene数据损坏"));
+
+        let mut data = scene_data();
+        data.primitives[0].indices = Some((u32::MAX as usize, 6));
+        let bytes = write_bytes("index_range", &data);
+        assert_eq!(FScene::from_bytes(bytes).err(), Some("fscene数据损坏"));
+    }
+
+    #[test]
+    fn corrupt_image_is_rejected() {
+        let mut data = scene_data();
+        //缺一层mip的数据长度与宽高不符
+        data.images[0].levels[0].pop();
+        let bytes = write_bytes("image", &data);
+        assert_eq!(
+            FScene::from_bytes(bytes).err(),
+            Some("fscene中的KTX2图片无效")
+        );
+    }
+
+    #[test]
+    fn mips_average_in_linear_space() {
+        //黑白相间的sRGB像素平均后是线性50%灰，不是sRGB的128
+        let pixels = [0u8, 0, 0, 255, 255, 255, 255, 255].repeat(2);
+        let levels = generate_mips(2, 2, &pixels, true);
+        assert_eq!(levels.len(), 2);
+        assert_eq!(levels[1], vec![188, 188, 188, 255]);
+
+        let levels = generate_mips(2, 2, &pixels, false);
+        assert_eq!(levels[1], vec![128, 128, 128, 255]);
+
+        //层数按短边计算
+        assert_eq!(generate_mips(8, 2, &[0; 8 * 2 * 4], false).len(), 2);
+    }
+}
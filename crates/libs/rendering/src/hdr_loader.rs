@@ -1,10 +1,12 @@
 use std::any::Any;
+use std::error::Error;
 use std::sync::Arc;
 use std::{fs::File, io::BufReader, path::Path};
 
 use asset::asset::Asset;
 use asset::asset_loader::AssetLoader;
 
+use exr::prelude::read_first_rgba_layer_from_file;
 use image::{codecs::hdr::HdrDecoder, Rgb};
 
 #[derive(Debug, Clone)]
@@ -24,8 +26,11 @@ impl Asset for HDRTextureSource {
 pub struct HdrTextureLoader;
 
 impl AssetLoader for HdrTextureLoader {
+    //读取失败时记录原因并返回None，由调用方决定如何处理
     fn load(&self, path: &str) -> Option<Arc<dyn Asset>> {
-        let (width, height, data) = load_hdr_image(path);
+        let (width, height, data) = load_image(path)
+            .map_err(|error| log::error!("读取{}失败: {}", path, error))
+            .ok()?;
         Some(Arc::new(HDRTextureSource {
             width,
             height,
@@ -34,19 +39,90 @@ impl AssetLoader for HdrTextureLoader {
     }
 
     fn extensions(&self) -> &[&str] {
-        &["hdr"]
+        &["hdr", "exr"]
     }
 }
 
-fn load_hdr_image<P: AsRef<Path>>(path: P) -> (u32, u32, Vec<f32>) {
-    let decoder = HdrDecoder::new(BufReader::new(File::open(path).unwrap())).unwrap();
+fn load_image<P: AsRef<Path>>(path: P) -> Result<(u32, u32, Vec<f32>), Box<dyn Error>> {
+    let is_exr = path
+        .as_ref()
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("exr"));
+    if is_exr {
+        load_exr_image(path)
+    } else {
+        load_hdr_image(path)
+    }
+}
+
+fn load_hdr_image<P: AsRef<Path>>(path: P) -> Result<(u32, u32, Vec<f32>), Box<dyn Error>> {
+    let decoder = HdrDecoder::new(BufReader::new(File::open(path)?))?;
     let w = decoder.metadata().width;
     let h = decoder.metadata().height;
-    let rgb = decoder.read_image_hdr().unwrap();
+    let rgb = decoder.read_image_hdr()?;
     let mut data = Vec::with_capacity(rgb.len() * 4);
     for Rgb(p) in rgb.iter() {
         data.extend_from_slice(p);
         data.push(0.0);
     }
-    (w, h, data)
+    Ok((w, h, data))
+}
+
+//只取RGB通道，alpha与hdr保持一致补0
+fn load_exr_image<P: AsRef<Path>>(path: P) -> Result<(u32, u32, Vec<f32>), Box<dyn Error>> {
+    let image = read_first_rgba_layer_from_file(
+        path,
+        |resolution, _| {
+            let w = resolution.width();
+            let h = resolution.height();
+            (w, h, vec![0.0f32; w * h * 4])
+        },
+        |(w, _, data), position, (r, g, b, _a): (f32, f32, f32, f32)| {
+            let offset = (position.y() * *w + position.x()) * 4;
+            data[offset..offset + 4].copy_from_slice(&[r, g, b, 0.0]);
+        },
+    )?;
+    let (w, h, data) = image.layer_data.channel_data.pixels;
+    Ok((w as u32, h as u32, data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::codecs::hdr::HdrEncoder;
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("fate_hdr_{}_{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn hdr_pixels_get_zero_alpha() {
+        let path = temp_path("pixels.hdr");
+        let pixels = [Rgb([1.0f32, 0.5, 0.25]), Rgb([2.0, 4.0, 8.0])];
+        HdrEncoder::new(File::create(&path).unwrap())
+            .encode(&pixels, 2, 1)
+            .unwrap();
+
+        let (w, h, data) = load_image(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!((w, h), (2, 1));
+        assert_eq!(data, [1.0, 0.5, 0.25, 0.0, 2.0, 4.0, 8.0, 0.0]);
+    }
+
+    //读取失败时返回错误，不再panic
+    #[test]
+    fn missing_and_corrupt_files_are_errors() {
+        assert!(load_image(temp_path("missing.hdr")).is_err());
+        assert!(load_image(temp_path("missing.exr")).is_err());
+
+        let path = temp_path("corrupt.hdr");
+        std::fs::write(&path, b"not an hdr file").unwrap();
+        let result = load_image(&path);
+        let loaded = HdrTextureLoader.load(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+        assert!(result.is_err());
+        assert!(loaded.is_none());
+    }
 }
@@ -4,8 +4,8 @@ use cgmath::{Deg, Matrix4};
 use std::mem::size_of;
 use std::sync::Arc;
 use std::time::Instant;
-use vulkan::ash::vk::{self, RenderingAttachmentInfo, RenderingInfo};
-use vulkan::{Context, Texture};
+use vulkan::ash::vk;
+use vulkan::{Context, RenderingAttachment, RenderingDesc, Texture};
 
 use crate::environment::{
     create_descriptors, create_env_pipeline, get_view_matrices, EnvPipelineParameters, SkyboxModel,
@@ -94,8 +94,14 @@ pub(crate) fn create_irradiance_map(
     };
 
     // create cubemap
-    let irradiance_map =
-        Texture::create_renderable_cubemap(context, size, 1, vk::Format::R32G32B32A32_SFLOAT,std::ffi::CString::new("Irradiance Texture").unwrap());
+    let irradiance_map = Texture::create_renderable_cubemap(
+        context,
+        size,
+        1,
+        vk::Format::R32G32B32A32_SFLOAT,
+        std::ffi::CString::new("Irradiance Texture").unwrap(),
+    )
+    .expect("创建irradiance cubemap失败！");
 
     let views = (0..6)
         .map(|i| {
@@ -122,33 +128,24 @@ pub(crate) fn create_irradiance_map(
     // Render
     context.execute_one_time_commands(|buffer| {
         for face in 0..6 {
-            let attachment_info = RenderingAttachmentInfo::builder()
-                .clear_value(vk::ClearValue {
-                    color: vk::ClearColorValue {
-                        float32: [0.0, 0.0, 0.0, 1.0],
-                    },
-                })
-                .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
-                .image_view(views[face])
-                .load_op(vk::AttachmentLoadOp::CLEAR)
-                .store_op(vk::AttachmentStoreOp::STORE);
-
-            let rendering_info = RenderingInfo::builder()
-                .color_attachments(std::slice::from_ref(&attachment_info))
-                .layer_count(1)
-                .render_area(vk::Rect2D {
-                    offset: vk::Offset2D { x: 0, y: 0 },
+            let attachment = RenderingAttachment::new(
+                &irradiance_map.image,
+                views[face],
+                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            )
+            .clear_color([0.0, 0.0, 0.0, 1.0]);
+
+            context.cmd_begin_rendering(
+                buffer,
+                &RenderingDesc {
                     extent: vk::Extent2D {
                         width: size,
                         height: size,
                     },
-                });
-
-            unsafe {
-                context
-                    .dynamic_rendering()
-                    .cmd_begin_rendering(buffer, &rendering_info)
-            };
+                    color_attachments: std::slice::from_ref(&attachment),
+                    depth_attachment: None,
+                },
+            );
 
             unsafe { device.cmd_bind_pipeline(buffer, vk::PipelineBindPoint::GRAPHICS, pipeline) };
 
@@ -191,7 +188,7 @@ pub(crate) fn create_irradiance_map(
 
             unsafe { device.cmd_draw_indexed(buffer, 36, 1, 0, 0, 0) };
 
-            unsafe { context.dynamic_rendering().cmd_end_rendering(buffer) };
+            context.cmd_end_rendering(buffer);
         }
     });
 
@@ -200,10 +197,9 @@ pub(crate) fn create_irradiance_map(
         vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
     );
 
+    views.iter().for_each(|v| context.destroy_image_view(*v));
+
     unsafe {
-        views
-            .iter()
-            .for_each(|v| device.destroy_image_view(*v, None));
         device.destroy_pipeline(pipeline, None);
         device.destroy_pipeline_layout(pipeline_layout, None);
     }
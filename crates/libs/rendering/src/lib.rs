@@ -3,7 +3,9 @@ pub mod animation;
 pub mod brdf;
 pub mod cubemap;
 pub mod environment;
+pub mod environment_cache;
 pub mod error;
+pub mod fscene;
 pub mod hdr_loader;
 pub mod irradiance;
 pub mod light;
@@ -2,6 +2,9 @@ use gltf::iter::Lights;
 use gltf::khr_lights_punctual::{Kind, Light as GltfLight};
 use gltf::Document;
 
+pub const MIN_COLOR_TEMPERATURE: f32 = 1000.0;
+pub const MAX_COLOR_TEMPERATURE: f32 = 12000.0;
+
 #[derive(Copy, Clone, PartialEq, Debug)]
 pub enum LightType {
     DirectionalLight,
@@ -18,6 +21,8 @@ pub struct Light {
     intensity: f32,
     range: Option<f32>,
     light_type: LightType,
+    //颜色由色温换算而来时记录开尔文值，直接设置RGB后清空
+    temperature: Option<f32>,
 }
 
 impl Light {
@@ -27,6 +32,7 @@ impl Light {
             intensity: 1.0,
             range: Some(1.0),
             light_type: LightType::DirectionalLight,
+            temperature: None,
         }
     }
 
@@ -45,6 +51,76 @@ impl Light {
     pub fn range(&self) -> Option<f32> {
         self.range
     }
+
+    pub fn temperature(&self) -> Option<f32> {
+        self.temperature
+    }
+
+    pub fn set_color(&mut self, color: [f32; 3]) {
+        self.color = color;
+        self.temperature = None;
+    }
+
+    pub fn set_temperature(&mut self, kelvin: f32) {
+        let kelvin = kelvin.clamp(MIN_COLOR_TEMPERATURE, MAX_COLOR_TEMPERATURE);
+        self.color = color_temperature_to_rgb(kelvin);
+        self.temperature = Some(kelvin);
+    }
+
+    pub fn set_intensity(&mut self, intensity: f32) {
+        self.intensity = intensity.max(0.0);
+    }
+
+    pub fn set_range(&mut self, range: Option<f32>) {
+        self.range = range;
+    }
+
+    //内锥角需小于外锥角
+    pub fn set_cone_angles(&mut self, inner_cone_angle: f32, outer_cone_angle: f32) {
+        if let LightType::SpotLight { .. } = self.light_type {
+            let outer_cone_angle = outer_cone_angle.clamp(0.0, std::f32::consts::FRAC_PI_2);
+            self.light_type = LightType::SpotLight {
+                inner_cone_angle: inner_cone_angle.clamp(0.0, outer_cone_angle),
+                outer_cone_angle,
+            };
+        }
+    }
+}
+
+//Tanner Helland对黑体辐射颜色的拟合，得到的是sRGB，转到线性空间后归一化到最大分量为1，
+//亮度完全由intensity控制
+pub fn color_temperature_to_rgb(kelvin: f32) -> [f32; 3] {
+    let t = kelvin.clamp(MIN_COLOR_TEMPERATURE, MAX_COLOR_TEMPERATURE) / 100.0;
+
+    let r = if t <= 66.0 {
+        255.0
+    } else {
+        329.698_73 * (t - 60.0).powf(-0.133_204_76)
+    };
+    let g = if t <= 66.0 {
+        99.470_8 * t.ln() - 161.119_57
+    } else {
+        288.122_16 * (t - 60.0).powf(-0.075_514_85)
+    };
+    let b = if t >= 66.0 {
+        255.0
+    } else if t <= 19.0 {
+        0.0
+    } else {
+        138.517_73 * (t - 10.0).ln() - 305.044_8
+    };
+
+    let linear = [r, g, b].map(|c| srgb_to_linear((c / 255.0).clamp(0.0, 1.0)));
+    let max = linear[0].max(linear[1]).max(linear[2]);
+    linear.map(|c| c / max)
+}
+
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
 }
 
 fn map_gltf_lights(lights: Lights) -> Vec<Light> {
@@ -70,6 +146,7 @@ fn map_gltf_lights(lights: Lights) -> Vec<Light> {
                 intensity,
                 range,
                 light_type,
+                temperature: None,
             }
         })
         .collect()
@@ -78,3 +155,77 @@ fn map_gltf_lights(lights: Lights) -> Vec<Light> {
 pub fn create_lights_from_gltf(document: &Document) -> Vec<Light> {
     document.lights().map_or(vec![], map_gltf_lights)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn warm_is_red_and_cool_is_blue() {
+        let [r, _, b] = color_temperature_to_rgb(2700.0);
+        assert_eq!(r, 1.0);
+        assert!(b < 0.3);
+
+        let [r, _, b] = color_temperature_to_rgb(10000.0);
+        assert_eq!(b, 1.0);
+        assert!(r < 0.8);
+    }
+
+    #[test]
+    fn daylight_is_close_to_white() {
+        let color = color_temperature_to_rgb(6600.0);
+        assert!(color.iter().all(|c| *c > 0.9 && *c <= 1.0));
+    }
+
+    #[test]
+    fn temperature_is_clamped_and_cleared_by_set_color() {
+        let mut light = Light::new();
+        light.set_temperature(100_000.0);
+        assert_eq!(light.temperature(), Some(MAX_COLOR_TEMPERATURE));
+        assert_eq!(
+            light.color(),
+            color_temperature_to_rgb(MAX_COLOR_TEMPERATURE)
+        );
+
+        light.set_temperature(0.0);
+        assert_eq!(light.temperature(), Some(MIN_COLOR_TEMPERATURE));
+
+        light.set_color([1.0, 0.0, 0.0]);
+        assert_eq!(light.temperature(), None);
+    }
+
+    #[test]
+    fn every_temperature_is_normalized() {
+        for kelvin in (1000..=12000).step_by(100) {
+            let color = color_temperature_to_rgb(kelvin as f32);
+            let max = color[0].max(color[1]).max(color[2]);
+            assert!((max - 1.0).abs() < 1e-6, "{}K", kelvin);
+            assert!(color.iter().all(|c| c.is_finite() && *c >= 0.0));
+        }
+    }
+
+    //Tanner Helland公布的黑体颜色表(sRGB，0-255)
+    #[test]
+    fn matches_blackbody_reference_table() {
+        let to_srgb = |c: f32| {
+            let c = if c <= 0.003_130_8 {
+                c * 12.92
+            } else {
+                1.055 * c.powf(1.0 / 2.4) - 0.055
+            };
+            c * 255.0
+        };
+        let references = [
+            (1000.0, [255.0, 68.0, 0.0]),
+            (2000.0, [255.0, 137.0, 14.0]),
+            (4000.0, [255.0, 206.0, 166.0]),
+            (10000.0, [202.0, 218.0, 255.0]),
+        ];
+        for (kelvin, reference) in references {
+            let color = color_temperature_to_rgb(kelvin);
+            for (c, r) in color.iter().zip(reference) {
+                assert!((to_srgb(*c) - r).abs() < 1.5, "{}K {:?}", kelvin, color);
+            }
+        }
+    }
+}
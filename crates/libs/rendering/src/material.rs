@@ -1,4 +1,5 @@
 use gltf::{
+    json::Value,
     material::{AlphaMode, Material as GltfMaterial, NormalTexture, OcclusionTexture},
     texture::Info,
 };
@@ -6,11 +7,105 @@ use gltf::{
 const ALPHA_MODE_OPAQUE: u32 = 0;
 const ALPHA_MODE_MASK: u32 = 1;
 const ALPHA_MODE_BLEND: u32 = 2;
+const CHANNEL_R: u32 = 0;
+const CHANNEL_G: u32 = 1;
+const CHANNEL_B: u32 = 2;
+const TEXTURE_TRANSFORM_EXTENSION: &str = "KHR_texture_transform";
+//glTF没有标准的高度图，用材质上的自定义扩展描述：{"heightTexture": textureInfo, "heightScale": f32}
+const PARALLAX_EXTENSION: &str = "FATE_materials_parallax";
+pub const DEFAULT_HEIGHT_SCALE: f32 = 0.05;
 
 #[derive(Clone, Copy, Debug)]
 pub struct TextureData {
     index: usize,
     channel: u32,
+    transform: TextureTransform,
+}
+
+//KHR_texture_transform定义的UV变换，先缩放再旋转最后平移
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TextureTransform {
+    offset: [f32; 2],
+    rotation: f32,
+    scale: [f32; 2],
+}
+
+impl Default for TextureTransform {
+    fn default() -> Self {
+        Self {
+            offset: [0.0, 0.0],
+            rotation: 0.0,
+            scale: [1.0, 1.0],
+        }
+    }
+}
+
+impl TextureTransform {
+    pub fn get_offset(&self) -> [f32; 2] {
+        self.offset
+    }
+
+    pub fn get_rotation(&self) -> f32 {
+        self.rotation
+    }
+
+    pub fn get_scale(&self) -> [f32; 2] {
+        self.scale
+    }
+
+    //2x3矩阵，前两行是缩放和旋转，第三行是平移，uv' = (dot(m[0], uv), dot(m[1], uv)) + m[2]
+    pub fn matrix(&self) -> [[f32; 2]; 3] {
+        let (sin, cos) = self.rotation.sin_cos();
+        let [sx, sy] = self.scale;
+        [
+            [cos * sx, sin * sy],
+            [-sin * sx, cos * sy],
+            [self.offset[0], self.offset[1]],
+        ]
+    }
+
+    //法线和遮蔽贴图的扩展没有强类型接口，直接解析JSON，缺省字段取默认值
+    fn from_json(value: &Value) -> (Self, Option<u32>) {
+        let pair = |name: &str, default: [f32; 2]| {
+            value
+                .get(name)
+                .and_then(Value::as_array)
+                .filter(|v| v.len() == 2)
+                .and_then(|v| Some([v[0].as_f64()? as f32, v[1].as_f64()? as f32]))
+                .unwrap_or(default)
+        };
+        let transform = Self {
+            offset: pair("offset", [0.0, 0.0]),
+            rotation: value.get("rotation").and_then(Value::as_f64).unwrap_or(0.0) as f32,
+            scale: pair("scale", [1.0, 1.0]),
+        };
+        let tex_coord = value
+            .get("texCoord")
+            .and_then(Value::as_u64)
+            .map(|t| t as u32);
+        (transform, tex_coord)
+    }
+}
+
+//遮蔽、粗糙度和金属度分别从贴图的哪个通道读取，0~3对应rgba
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TexturePacking {
+    pub occlusion_channel: u32,
+    pub roughness_channel: u32,
+    pub metallic_channel: u32,
+    //遮蔽和金属度/粗糙度共用同一张贴图（ORM）
+    pub orm: bool,
+}
+
+impl Default for TexturePacking {
+    fn default() -> Self {
+        Self {
+            occlusion_channel: CHANNEL_R,
+            roughness_channel: CHANNEL_G,
+            metallic_channel: CHANNEL_B,
+            orm: false,
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -69,6 +164,16 @@ impl SpecularGlossinessWorkflow {
     }
 }
 
+//检视面板中临时修改的材质参数，只改渲染用的材质，不写回资源
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MaterialOverride {
+    pub color: [f32; 4],
+    pub emissive: [f32; 3],
+    //高光/光泽度工作流忽略金属度和粗糙度
+    pub metallic: f32,
+    pub roughness: f32,
+}
+
 #[derive(Clone, Copy, Debug)]
 pub struct Material {
     color: [f32; 4],
@@ -77,12 +182,22 @@ pub struct Material {
     color_texture: Option<TextureData>,
     emissive_texture: Option<TextureData>,
     normals_texture: Option<TextureData>,
+    //切线空间法线xy分量的缩放
+    normal_scale: f32,
     occlusion_texture: Option<TextureData>,
+    //视差遮蔽映射用的高度图，白色为最高处
+    height_texture: Option<TextureData>,
+    height_scale: f32,
+    parallax_enabled: bool,
     workflow: PBRWorkflow,
     alpha_mode: u32,
     alpha_cutoff: f32,
     double_sided: bool,
     is_unlit: bool,
+    use_vertex_colors: bool,
+    //顶点切线不可用，法线贴图改用屏幕空间导数构造切线空间
+    use_derivative_tangents: bool,
+    texture_packing: TexturePacking,
 }
 
 impl Material {
@@ -95,6 +210,10 @@ impl Material {
     }
 
     pub fn get_occlusion(&self) -> f32 {
+        //ORM且没有单独的遮蔽贴图时，glTF里没有强度，按1处理
+        if self.occlusion_texture.is_none() && self.get_orm_texture().is_some() {
+            return 1.0;
+        }
         self.occlusion
     }
 
@@ -122,14 +241,48 @@ impl Material {
         self.normals_texture
     }
 
+    pub fn get_normal_scale(&self) -> f32 {
+        self.normal_scale
+    }
+
     pub fn get_occlusion_texture(&self) -> Option<TextureData> {
-        self.occlusion_texture
+        self.occlusion_texture.or_else(|| self.get_orm_texture())
+    }
+
+    pub fn get_height_texture(&self) -> Option<TextureData> {
+        self.height_texture
+    }
+
+    pub fn get_height_scale(&self) -> f32 {
+        self.height_scale
+    }
+
+    //关闭时即使有高度图也只做普通的法线贴图
+    pub fn is_parallax_enabled(&self) -> bool {
+        self.parallax_enabled
+    }
+
+    pub fn set_parallax_enabled(&mut self, parallax_enabled: bool) {
+        self.parallax_enabled = parallax_enabled;
+    }
+
+    fn get_orm_texture(&self) -> Option<TextureData> {
+        match self.workflow {
+            PBRWorkflow::MetallicRoughness(workflow) if self.texture_packing.orm => {
+                workflow.metallic_roughness_texture
+            }
+            _ => None,
+        }
     }
 
     pub fn is_transparent(&self) -> bool {
         self.alpha_mode == ALPHA_MODE_BLEND
     }
 
+    pub fn is_masked(&self) -> bool {
+        self.alpha_mode == ALPHA_MODE_MASK
+    }
+
     pub fn get_color_texture_index(&self) -> Option<usize> {
         self.color_texture.map(|info| info.index)
     }
@@ -143,7 +296,11 @@ impl Material {
     }
 
     pub fn get_occlusion_texture_index(&self) -> Option<usize> {
-        self.occlusion_texture.map(|info| info.index)
+        self.get_occlusion_texture().map(|info| info.index)
+    }
+
+    pub fn get_height_texture_index(&self) -> Option<usize> {
+        self.get_height_texture().map(|info| info.index)
     }
 
     pub fn is_unlit(&self) -> bool {
@@ -153,6 +310,57 @@ impl Material {
     pub fn get_workflow(&self) -> PBRWorkflow {
         self.workflow
     }
+
+    pub fn use_vertex_colors(&self) -> bool {
+        self.use_vertex_colors
+    }
+
+    pub fn set_use_vertex_colors(&mut self, use_vertex_colors: bool) {
+        self.use_vertex_colors = use_vertex_colors;
+    }
+
+    pub fn use_derivative_tangents(&self) -> bool {
+        self.use_derivative_tangents
+    }
+
+    pub fn set_use_derivative_tangents(&mut self, use_derivative_tangents: bool) {
+        self.use_derivative_tangents = use_derivative_tangents;
+    }
+
+    pub fn get_override(&self) -> MaterialOverride {
+        let (metallic, roughness) = match self.workflow {
+            PBRWorkflow::MetallicRoughness(workflow) => (workflow.metallic, workflow.roughness),
+            PBRWorkflow::SpecularGlossiness(_) => (0.0, 1.0),
+        };
+        MaterialOverride {
+            color: self.color,
+            emissive: self.emissive,
+            metallic,
+            roughness,
+        }
+    }
+
+    pub fn apply_override(&mut self, material_override: MaterialOverride) {
+        self.color = material_override.color.map(|c| c.clamp(0.0, 1.0));
+        self.emissive = material_override.emissive.map(|e| e.max(0.0));
+        if let PBRWorkflow::MetallicRoughness(workflow) = &mut self.workflow {
+            workflow.metallic = material_override.metallic.clamp(0.0, 1.0);
+            workflow.roughness = material_override.roughness.clamp(0.0, 1.0);
+        }
+    }
+
+    pub fn get_texture_packing(&self) -> TexturePacking {
+        self.texture_packing
+    }
+
+    pub fn set_texture_packing(&mut self, texture_packing: TexturePacking) {
+        self.texture_packing = TexturePacking {
+            occlusion_channel: texture_packing.occlusion_channel.min(3),
+            roughness_channel: texture_packing.roughness_channel.min(3),
+            metallic_channel: texture_packing.metallic_channel.min(3),
+            orm: texture_packing.orm,
+        };
+    }
 }
 
 impl TextureData {
@@ -163,6 +371,10 @@ impl TextureData {
     pub fn get_channel(&self) -> u32 {
         self.channel
     }
+
+    pub fn get_transform(&self) -> TextureTransform {
+        self.transform
+    }
 }
 
 impl<'a> From<GltfMaterial<'a>> for Material {
@@ -186,8 +398,10 @@ impl<'a> From<GltfMaterial<'a>> for Material {
         };
         let color_texture = get_texture(color_texture);
         let emissive_texture = get_texture(material.emissive_texture());
-        let normals_texture = get_normals_texture(material.normal_texture());
+        let (normal_scale, normals_texture) = get_normals_texture(material.normal_texture());
         let (occlusion, occlusion_texture) = get_occlusion(material.occlusion_texture());
+        let (height_scale, height_texture) =
+            get_height(material.extension_value(PARALLAX_EXTENSION));
 
         let workflow = match material.pbr_specular_glossiness() {
             Some(pbr) => PBRWorkflow::SpecularGlossiness(SpecularGlossinessWorkflow {
@@ -212,6 +426,18 @@ impl<'a> From<GltfMaterial<'a>> for Material {
 
         let is_unlit = material.unlit();
 
+        //遮蔽贴图和金属度/粗糙度贴图是同一张时视为ORM
+        let orm = match (occlusion_texture, &workflow) {
+            (Some(occlusion_texture), PBRWorkflow::MetallicRoughness(workflow)) => workflow
+                .metallic_roughness_texture
+                .is_some_and(|t| t.index == occlusion_texture.index),
+            _ => false,
+        };
+        let texture_packing = TexturePacking {
+            orm,
+            ..Default::default()
+        };
+
         Material {
             color,
             emissive,
@@ -219,28 +445,67 @@ impl<'a> From<GltfMaterial<'a>> for Material {
             color_texture,
             emissive_texture,
             normals_texture,
+            normal_scale,
             occlusion_texture,
+            height_texture,
+            height_scale,
+            parallax_enabled: true,
             workflow,
             alpha_mode,
             alpha_cutoff,
             double_sided,
             is_unlit,
+            use_vertex_colors: false,
+            use_derivative_tangents: false,
+            texture_packing,
         }
     }
 }
 
+//扩展中的texCoord会覆盖纹理本身的texCoord
 fn get_texture(texture_info: Option<Info>) -> Option<TextureData> {
-    texture_info.map(|tex_info| TextureData {
-        index: tex_info.texture().index(),
-        channel: tex_info.tex_coord(),
+    texture_info.map(|tex_info| {
+        let transform = tex_info.texture_transform();
+        let tex_coord = transform.as_ref().and_then(|t| t.tex_coord());
+        let transform = transform.map_or_else(TextureTransform::default, |t| TextureTransform {
+            offset: t.offset(),
+            rotation: t.rotation(),
+            scale: t.scale(),
+        });
+        TextureData {
+            index: tex_info.texture().index(),
+            channel: tex_coord.unwrap_or_else(|| tex_info.tex_coord()),
+            transform,
+        }
     })
 }
 
-fn get_normals_texture(texture_info: Option<NormalTexture>) -> Option<TextureData> {
-    texture_info.map(|tex_info| TextureData {
-        index: tex_info.texture().index(),
-        channel: tex_info.tex_coord(),
-    })
+fn get_json_texture(index: usize, tex_coord: u32, extension: Option<&Value>) -> TextureData {
+    let (transform, transform_tex_coord) = extension.map_or(
+        (TextureTransform::default(), None),
+        TextureTransform::from_json,
+    );
+    TextureData {
+        index,
+        channel: transform_tex_coord.unwrap_or(tex_coord),
+        transform,
+    }
+}
+
+fn get_normals_texture(texture_info: Option<NormalTexture>) -> (f32, Option<TextureData>) {
+    let scale = texture_info
+        .as_ref()
+        .map_or(1.0, |tex_info| tex_info.scale());
+
+    let texture = texture_info.map(|tex_info| {
+        get_json_texture(
+            tex_info.texture().index(),
+            tex_info.tex_coord(),
+            tex_info.extension_value(TEXTURE_TRANSFORM_EXTENSION),
+        )
+    });
+
+    (scale, texture)
 }
 
 fn get_occlusion(texture_info: Option<OcclusionTexture>) -> (f32, Option<TextureData>) {
@@ -248,14 +513,38 @@ fn get_occlusion(texture_info: Option<OcclusionTexture>) -> (f32, Option<Texture
         .as_ref()
         .map_or(0.0, |tex_info| tex_info.strength());
 
-    let texture = texture_info.map(|tex_info| TextureData {
-        index: tex_info.texture().index(),
-        channel: tex_info.tex_coord(),
+    let texture = texture_info.map(|tex_info| {
+        get_json_texture(
+            tex_info.texture().index(),
+            tex_info.tex_coord(),
+            tex_info.extension_value(TEXTURE_TRANSFORM_EXTENSION),
+        )
     });
 
     (strength, texture)
 }
 
+//按glTF的textureInfo解析，同样支持KHR_texture_transform
+fn get_height(extension: Option<&Value>) -> (f32, Option<TextureData>) {
+    let height_scale = extension
+        .and_then(|e| e.get("heightScale"))
+        .and_then(Value::as_f64)
+        .map_or(DEFAULT_HEIGHT_SCALE, |s| s as f32);
+
+    let texture = extension
+        .and_then(|e| e.get("heightTexture"))
+        .and_then(|info| {
+            let index = info.get("index")?.as_u64()? as usize;
+            let tex_coord = info.get("texCoord").and_then(Value::as_u64).unwrap_or(0);
+            let transform = info
+                .get("extensions")
+                .and_then(|e| e.get(TEXTURE_TRANSFORM_EXTENSION));
+            Some(get_json_texture(index, tex_coord as u32, transform))
+        });
+
+    (height_scale, texture)
+}
+
 fn get_alpha_mode_index(alpha_mode: AlphaMode) -> u32 {
     match alpha_mode {
         AlphaMode::Opaque => ALPHA_MODE_OPAQUE,
@@ -263,3 +552,63 @@ fn get_alpha_mode_index(alpha_mode: AlphaMode) -> u32 {
         AlphaMode::Blend => ALPHA_MODE_BLEND,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load_materials(model: &str) -> Vec<Material> {
+        let path = format!(
+            "{}/../../../assets/models/{model}/glTF-Embedded/{model}.gltf",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        let (document, _, _) = gltf::import(path).unwrap();
+        document.materials().map(Material::from).collect()
+    }
+
+    //按着色器里的写法变换uv
+    fn transform_uv(transform: TextureTransform, [u, v]: [f32; 2]) -> [f32; 2] {
+        let [m0, m1, m2] = transform.matrix();
+        [m0[0] * u + m0[1] * v + m2[0], m1[0] * u + m1[1] * v + m2[1]]
+    }
+
+    //scale为4时uv放大4倍，棋盘格在每个方向上重复4次
+    #[test]
+    fn texture_transform_tiles_four_times() {
+        let materials = load_materials("TextureTransform");
+        let texture = materials[0].get_color_texture().unwrap();
+        let transform = texture.get_transform();
+        assert_eq!(transform.get_scale(), [4.0, 4.0]);
+        assert_eq!(transform_uv(transform, [1.0, 1.0]), [4.0, 4.0]);
+        assert_eq!(transform_uv(transform, [0.25, 0.5]), [1.0, 2.0]);
+        assert_eq!(transform_uv(transform, [0.0, 0.0]), [0.0, 0.0]);
+    }
+
+    //三个材质的normal scale和occlusion strength与README的表格一致，包括0
+    #[test]
+    fn normal_scale_and_occlusion_strength_are_read() {
+        let materials = load_materials("OcclusionNormalScale");
+        let values = materials
+            .iter()
+            .map(|m| (m.get_normal_scale(), m.get_occlusion()))
+            .collect::<Vec<_>>();
+        assert_eq!(values, vec![(1.0, 1.0), (2.0, 0.5), (0.0, 0.0)]);
+        assert!(materials
+            .iter()
+            .all(|m| m.get_occlusion_texture().is_some()));
+    }
+
+    //左边的单面材质从背面看应被剔除，右边的双面材质两面都绘制
+    #[test]
+    fn double_sided_flag_is_read() {
+        let materials = load_materials("DoubleSided");
+        let double_sided = materials
+            .iter()
+            .map(Material::is_double_sided)
+            .collect::<Vec<_>>();
+        assert_eq!(double_sided, vec![false, true]);
+        assert!(materials
+            .iter()
+            .all(|m| !m.is_transparent() && !m.is_masked()));
+    }
+}
@@ -44,6 +44,39 @@ where
     )
 }
 
+//近平面深度为1，远平面为0，配合GREATER深度比较和浮点深度缓冲使用
+pub fn perspective_reverse_z<S, F>(fovy: F, aspect: S, near: S, far: S) -> Matrix4<S>
+where
+    S: BaseFloat,
+    F: Into<Rad<S>>,
+{
+    let mut proj = perspective(fovy, aspect, near, far);
+    proj.z.z = near / (far - near);
+    proj.w.z = (far * near) / (far - near);
+    proj
+}
+
+//Vulkan裁剪空间的正交投影：Y轴向下，深度范围[0, 1]，height为视口对应的高度
+#[rustfmt::skip]
+pub fn orthographic<S: BaseFloat>(height: S, aspect: S, near: S, far: S) -> Matrix4<S> {
+    let two = S::one() + S::one();
+    let width = height * aspect;
+
+    Matrix4::new(
+        two / width, S::zero(), S::zero(), S::zero(),
+        S::zero(), -two / height, S::zero(), S::zero(),
+        S::zero(), S::zero(), -S::one() / (far - near), S::zero(),
+        S::zero(), S::zero(), -near / (far - near), S::one(),
+    )
+}
+
+pub fn orthographic_reverse_z<S: BaseFloat>(height: S, aspect: S, near: S, far: S) -> Matrix4<S> {
+    let mut proj = orthographic(height, aspect, near, far);
+    proj.z.z = S::one() / (far - near);
+    proj.w.z = far / (far - near);
+    proj
+}
+
 #[rustfmt::skip]
 pub fn ortho<S: BaseFloat>(left: S, right: S, bottom: S, top: S, near: S, far: S) -> Matrix4<S>
 {
@@ -1,10 +1,14 @@
+use crate::node::{compute_scene_uid, NodeUid};
 use gltf::{
+    animation::Property as GltfProperty,
+    json::Extras,
     khr_lights_punctual::{Kind as GltfLightKind, Light as GltfLight},
     material::AlphaMode as GltfAlphaMode,
     mesh::Mode as GltfPrimitiveMode,
     Animation as GltfAnimation, Document, Material as GltfMaterial, Mesh as GltfMesh,
     Node as GltfNode, Primitive as GltfPrimitive, Scene,
 };
+use serde_json::Value;
 use std::{fmt, path::Path};
 
 #[derive(Clone, Debug)]
@@ -24,7 +28,8 @@ pub struct Metadata {
 }
 
 impl Metadata {
-    pub fn new<P: AsRef<Path>>(path: P, document: &Document) -> Self {
+    //node_uids由compute_node_uids计算，与渲染用的节点共用
+    pub fn new<P: AsRef<Path>>(path: P, document: &Document, node_uids: &[NodeUid]) -> Self {
         Metadata {
             name: String::from(path.as_ref().file_name().unwrap().to_str().unwrap()),
             path: String::from(path.as_ref().to_str().unwrap()),
@@ -36,46 +41,45 @@ impl Metadata {
             material_count: document.materials().len(),
             texture_count: document.textures().len(),
             light_count: document.lights().map_or(0, |lights| lights.len()),
-            nodes: build_tree(document),
+            nodes: build_tree(document, node_uids),
             animations: document.animations().map(Animation::from).collect(),
         }
     }
 }
 
-fn build_tree(document: &Document) -> Vec<Node> {
-    let mut uid = 0;
+fn build_tree(document: &Document, node_uids: &[NodeUid]) -> Vec<Node> {
     document
         .scenes()
-        .map(|s| map_scene_node(&s, &mut uid))
+        .map(|s| map_scene_node(&s, node_uids))
         .collect()
 }
 
-fn map_scene_node(scene: &Scene, current_uid: &mut usize) -> Node {
+fn map_scene_node(scene: &Scene, node_uids: &[NodeUid]) -> Node {
     let children = scene
         .nodes()
-        .map(|n| map_node(&n, true, current_uid))
+        .map(|n| map_node(&n, true, node_uids))
         .collect();
-    *current_uid += 1;
     Node {
-        uid: *current_uid,
+        uid: compute_scene_uid(scene),
         index: scene.index(),
         name: scene.name().map(String::from),
         kind: NodeKind::Scene,
+        extras: parse_extras(scene.extras()),
         children,
     }
 }
 
-fn map_node(node: &GltfNode, root: bool, current_uid: &mut usize) -> Node {
+fn map_node(node: &GltfNode, root: bool, node_uids: &[NodeUid]) -> Node {
     let children = node
         .children()
-        .map(|n| map_node(&n, false, current_uid))
+        .map(|n| map_node(&n, false, node_uids))
         .collect();
-    *current_uid += 1;
     Node {
-        uid: *current_uid,
+        uid: node_uids[node.index()],
         index: node.index(),
         name: node.name().map(String::from),
         kind: map_node_kind(node, root),
+        extras: parse_extras(node.extras()),
         children,
     }
 }
@@ -97,6 +101,25 @@ fn map_light_data(node: &GltfNode) -> Option<Light> {
     node.light().map(Light::from)
 }
 
+//extras是任意JSON，解析失败时只记录警告，不影响模型加载
+fn parse_extras(extras: &Extras) -> Option<Value> {
+    let raw = extras.as_ref()?;
+    match serde_json::from_str(raw.get()) {
+        Ok(value) => Some(value),
+        Err(error) => {
+            log::warn!("无法解析extras: {}", error);
+            None
+        }
+    }
+}
+
+fn find_node(nodes: &[Node], index: usize) -> Option<&Node> {
+    nodes.iter().find_map(|node| match node.kind {
+        NodeKind::Node(_) if node.index == index => Some(node),
+        _ => find_node(&node.children, index),
+    })
+}
+
 impl Metadata {
     pub fn name(&self) -> &str {
         &self.name
@@ -145,19 +168,29 @@ impl Metadata {
     pub fn animations(&self) -> &[Animation] {
         &self.animations
     }
+
+    //按glTF中的节点序号查找
+    pub fn node(&self, index: usize) -> Option<&Node> {
+        find_node(&self.nodes, index)
+    }
+
+    pub fn node_extras(&self, index: usize) -> Option<&Value> {
+        self.node(index)?.extras()
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct Node {
-    uid: usize,
+    uid: NodeUid,
     index: usize,
     name: Option<String>,
     kind: NodeKind,
+    extras: Option<Value>,
     children: Vec<Node>,
 }
 
 impl Node {
-    pub fn uid(&self) -> usize {
+    pub fn uid(&self) -> NodeUid {
         self.uid
     }
 
@@ -173,6 +206,10 @@ impl Node {
         &self.kind
     }
 
+    pub fn extras(&self) -> Option<&Value> {
+        self.extras.as_ref()
+    }
+
     pub fn children(&self) -> &[Node] {
         &self.children
     }
@@ -290,6 +327,7 @@ pub struct Material {
     pub roughness_factor: f32,
     pub emissive_color: [f32; 3],
     pub unlit: bool,
+    pub extras: Option<Value>,
 }
 
 impl From<GltfMaterial<'_>> for Material {
@@ -305,6 +343,7 @@ impl From<GltfMaterial<'_>> for Material {
             roughness_factor: material.pbr_metallic_roughness().roughness_factor(),
             emissive_color: material.emissive_factor(),
             unlit: material.unlit(),
+            extras: parse_extras(material.extras()),
         }
     }
 }
@@ -394,13 +433,110 @@ impl fmt::Display for LightKind {
 pub struct Animation {
     pub index: usize,
     pub name: Option<String>,
+    //动画中出现的通道类型，按ChannelKind的顺序去重
+    pub channel_kinds: Vec<ChannelKind>,
+}
+
+impl Animation {
+    pub fn has_morph_weights(&self) -> bool {
+        self.channel_kinds
+            .contains(&ChannelKind::MorphTargetWeights)
+    }
 }
 
 impl From<GltfAnimation<'_>> for Animation {
     fn from(animation: GltfAnimation) -> Self {
+        let mut channel_kinds = animation
+            .channels()
+            .map(|c| ChannelKind::from(c.target().property()))
+            .collect::<Vec<_>>();
+        channel_kinds.sort();
+        channel_kinds.dedup();
+
         Self {
             index: animation.index(),
             name: animation.name().map(String::from),
+            channel_kinds,
         }
     }
 }
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum ChannelKind {
+    Translation,
+    Rotation,
+    Scale,
+    MorphTargetWeights,
+}
+
+impl fmt::Display for ChannelKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ChannelKind::Translation => "Translation",
+            ChannelKind::Rotation => "Rotation",
+            ChannelKind::Scale => "Scale",
+            ChannelKind::MorphTargetWeights => "Weights",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl From<GltfProperty> for ChannelKind {
+    fn from(property: GltfProperty) -> ChannelKind {
+        match property {
+            GltfProperty::Translation => ChannelKind::Translation,
+            GltfProperty::Rotation => ChannelKind::Rotation,
+            GltfProperty::Scale => ChannelKind::Scale,
+            GltfProperty::MorphTargetWeights => ChannelKind::MorphTargetWeights,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::compute_node_uids;
+    use serde_json::json;
+
+    const NODE_EXTRAS: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/../../../assets/models/NodeExtras/glTF-Embedded/NodeExtras.gltf"
+    );
+
+    //README里列出的场景、节点和材质extras都原样保留
+    #[test]
+    fn extras_survive_import() {
+        let (document, _, _) = gltf::import(NODE_EXTRAS).unwrap();
+        let metadata = Metadata::new(NODE_EXTRAS, &document, &compute_node_uids(&document));
+
+        assert_eq!(
+            metadata.nodes()[0].extras(),
+            Some(&json!({ "author": "fate" }))
+        );
+        assert_eq!(
+            metadata.node_extras(0),
+            Some(&json!({ "tags": ["level", "static"], "id": "root-0001" }))
+        );
+        assert_eq!(
+            metadata.node_extras(1),
+            Some(&json!({
+                "id": "pickup-0042",
+                "kind": "health",
+                "amount": 25,
+                "respawn": { "enabled": true, "seconds": 30.5 },
+                "tags": ["gameplay", "pickup"],
+                "note": null
+            }))
+        );
+        assert_eq!(metadata.node_extras(2), None);
+
+        let NodeKind::Node(data) = metadata.node(1).unwrap().kind() else {
+            panic!("节点1不是普通节点");
+        };
+        let material = &data.mesh.as_ref().unwrap().primitives[0].material;
+        assert_eq!(
+            material.extras,
+            Some(json!({ "surface": "metal_grate", "footstep": "metal" }))
+        );
+    }
+}
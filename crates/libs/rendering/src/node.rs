@@ -1,14 +1,119 @@
-use crate::cgmath::{Matrix4, Quaternion, Vector3};
-use gltf::{iter::Nodes as GltfNodes, scene::Transform, Scene};
+use crate::cgmath::{Matrix4, Quaternion, SquareMatrix, Vector3};
+use gltf::{iter::Nodes as GltfNodes, scene::Transform, Document, Node as GltfNode, Scene};
+use std::{collections::HashMap, fmt};
+
+//加载时分配的节点ID，由节点在层级中的路径计算，同一资源重新加载后保持不变
+//界面和序列化通过它引用节点，不依赖节点在数组中的位置
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeUid(u64);
+
+impl NodeUid {
+    pub fn value(&self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Display for NodeUid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+//FNV-1a，结果不随Rust版本和运行环境变化，可以写入文件
+fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(FNV_PRIME)
+    })
+}
+
+//路径的每一段取节点名，没有名字时取glTF中的序号；同名兄弟节点再按出现顺序区分
+fn path_uid(parent: u64, segment: &str, ordinal: u32) -> u64 {
+    let hash = fnv1a(parent, segment.as_bytes());
+    fnv1a(fnv1a(hash, &[0]), &ordinal.to_le_bytes())
+}
+
+//计算所有节点的ID，按glTF中的节点序号索引
+//先遍历各场景，被多个场景引用的节点沿用第一次得到的ID，不属于任何场景的节点最后处理
+pub fn compute_node_uids(document: &Document) -> Vec<NodeUid> {
+    let mut uids = vec![None; document.nodes().len()];
+    for scene in document.scenes() {
+        assign_node_uids(scene.nodes(), FNV_OFFSET_BASIS, &mut uids);
+    }
+
+    let mut child_flags = vec![false; uids.len()];
+    document
+        .nodes()
+        .flat_map(|n| n.children())
+        .for_each(|c| child_flags[c.index()] = true);
+    let orphan_roots = document
+        .nodes()
+        .filter(|n| uids[n.index()].is_none() && !child_flags[n.index()])
+        .collect::<Vec<_>>();
+    assign_node_uids(
+        orphan_roots.into_iter(),
+        fnv1a(FNV_OFFSET_BASIS, b"orphan"),
+        &mut uids,
+    );
+
+    //只有成环的非法层级会走到这里
+    uids.iter()
+        .enumerate()
+        .map(|(index, uid)| {
+            uid.unwrap_or_else(|| NodeUid(path_uid(FNV_OFFSET_BASIS, "#cycle", index as u32)))
+        })
+        .collect()
+}
+
+fn assign_node_uids<'a>(
+    nodes: impl Iterator<Item = GltfNode<'a>>,
+    parent: u64,
+    uids: &mut [Option<NodeUid>],
+) {
+    let mut ordinals = HashMap::new();
+    for node in nodes {
+        if uids[node.index()].is_some() {
+            continue;
+        }
+        let segment = node
+            .name()
+            .map_or_else(|| format!("#{}", node.index()), String::from);
+        let ordinal = ordinals.entry(segment.clone()).or_insert(0);
+        let uid = path_uid(parent, &segment, *ordinal);
+        *ordinal += 1;
+
+        uids[node.index()] = Some(NodeUid(uid));
+        assign_node_uids(node.children(), uid, uids);
+    }
+}
+
+//场景在层级面板中也作为节点出现，ID与节点的ID分开计算
+pub fn compute_scene_uid(scene: &Scene) -> NodeUid {
+    let segment = scene
+        .name()
+        .map_or_else(|| format!("#{}", scene.index()), String::from);
+    NodeUid(path_uid(
+        fnv1a(FNV_OFFSET_BASIS, b"scene"),
+        &segment,
+        scene.index() as u32,
+    ))
+}
 
 #[derive(Clone, Debug)]
 pub struct Node {
+    uid: NodeUid,
+    name: Option<String>,
     local_transform: Transform,
     global_transform_matrix: Matrix4<f32>,
     mesh_index: Option<usize>,
     skin_index: Option<usize>,
     light_index: Option<usize>,
     children_indices: Vec<usize>,
+    visible: bool,
+    //morph target权重，节点没有指定时使用网格的默认权重
+    weights: Vec<f32>,
 }
 
 impl Node {
@@ -19,10 +124,23 @@ impl Node {
 }
 
 impl Node {
+    pub fn uid(&self) -> NodeUid {
+        self.uid
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
     pub fn transform(&self) -> Matrix4<f32> {
         self.global_transform_matrix
     }
 
+    //变换的行列式为负时(奇数个轴的缩放为负)三角形绕序会被翻转
+    pub fn is_mirrored(&self) -> bool {
+        self.global_transform_matrix.determinant() < 0.0
+    }
+
     pub fn local_transform(&self) -> &Transform {
         &self.local_transform
     }
@@ -39,44 +157,50 @@ impl Node {
         self.light_index
     }
 
+    pub fn children_indices(&self) -> &[usize] {
+        &self.children_indices
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    pub fn weights(&self) -> &[f32] {
+        &self.weights
+    }
+
+    //超出morph target数量的序号直接忽略
+    pub fn set_weight(&mut self, target_index: usize, weight: f32) {
+        if let Some(w) = self.weights.get_mut(target_index) {
+            *w = weight;
+        }
+    }
+
+    //矩阵形式的变换先分解后再修改
     pub fn set_translation(&mut self, translation: Vector3<f32>) {
-        if let Transform::Decomposed {
-            rotation, scale, ..
-        } = self.local_transform
-        {
-            self.local_transform = Transform::Decomposed {
-                translation: [translation.x, translation.y, translation.z],
-                rotation,
-                scale,
-            }
+        let (_, rotation, scale) = self.local_transform.clone().decomposed();
+        self.local_transform = Transform::Decomposed {
+            translation: [translation.x, translation.y, translation.z],
+            rotation,
+            scale,
         }
     }
 
     pub fn set_rotation(&mut self, rotation: Quaternion<f32>) {
-        if let Transform::Decomposed {
-            translation, scale, ..
-        } = self.local_transform
-        {
-            self.local_transform = Transform::Decomposed {
-                translation,
-                rotation: [rotation.v.x, rotation.v.y, rotation.v.z, rotation.s],
-                scale,
-            }
+        let (translation, _, scale) = self.local_transform.clone().decomposed();
+        self.local_transform = Transform::Decomposed {
+            translation,
+            rotation: [rotation.v.x, rotation.v.y, rotation.v.z, rotation.s],
+            scale,
         }
     }
 
     pub fn set_scale(&mut self, scale: Vector3<f32>) {
-        if let Transform::Decomposed {
+        let (translation, rotation, _) = self.local_transform.clone().decomposed();
+        self.local_transform = Transform::Decomposed {
             translation,
             rotation,
-            ..
-        } = self.local_transform
-        {
-            self.local_transform = Transform::Decomposed {
-                translation,
-                rotation,
-                scale: [scale.x, scale.y, scale.z],
-            }
+            scale: [scale.x, scale.y, scale.z],
         }
     }
 }
@@ -88,7 +212,8 @@ pub struct Nodes {
 }
 
 impl Nodes {
-    pub fn from_gltf_nodes(gltf_nodes: GltfNodes, scene: &Scene) -> Nodes {
+    //uids由compute_node_uids计算，与Metadata共用
+    pub fn from_gltf_nodes(gltf_nodes: GltfNodes, scene: &Scene, uids: &[NodeUid]) -> Nodes {
         let roots_indices = scene.nodes().map(|n| n.index()).collect::<Vec<_>>();
         let node_count = gltf_nodes.len();
         let mut nodes = Vec::with_capacity(node_count);
@@ -100,13 +225,21 @@ impl Nodes {
             let skin_index = node.skin().map(|s| s.index());
             let light_index = node.light().map(|l| l.index());
             let children_indices = node.children().map(|c| c.index()).collect::<Vec<_>>();
+            let weights = node
+                .weights()
+                .or_else(|| node.mesh().and_then(|m| m.weights()))
+                .map_or(vec![], |w| w.to_vec());
             let node = Node {
+                uid: uids[node_index],
+                name: node.name().map(String::from),
                 local_transform,
                 global_transform_matrix,
                 mesh_index,
                 skin_index,
                 light_index,
                 children_indices,
+                visible: true,
+                weights,
             };
             nodes.insert(node_index, node);
         }
@@ -142,6 +275,15 @@ impl Nodes {
         }
     }
 
+    //子节点跟随父节点一起显示或隐藏
+    pub fn set_visible(&mut self, index: usize, visible: bool) {
+        let node = &mut self.nodes[index];
+        node.visible = visible;
+        for child_index in node.children_indices.clone() {
+            self.set_visible(child_index, visible);
+        }
+    }
+
     pub fn get_skins_transform(&self) -> Vec<(usize, Matrix4<f32>)> {
         self.nodes
             .iter()
@@ -179,6 +321,32 @@ impl Nodes {
     pub fn nodes_mut(&mut self) -> &mut [Node] {
         &mut self.nodes
     }
+
+    pub fn node(&self, index: usize) -> Option<&Node> {
+        self.nodes.get(index)
+    }
+
+    //带网格的节点，顺序与变换UBO中的顺序一致
+    pub fn mesh_nodes(&self) -> impl Iterator<Item = &Node> {
+        self.nodes.iter().filter(|n| n.mesh_index.is_some())
+    }
+
+    pub fn light_nodes(&self) -> impl Iterator<Item = &Node> {
+        self.nodes.iter().filter(|n| n.light_index.is_some())
+    }
+
+    //返回值为(节点索引, 节点)
+    pub fn node_by_uid(&self, uid: NodeUid) -> Option<(usize, &Node)> {
+        self.nodes.iter().enumerate().find(|(_, n)| n.uid == uid)
+    }
+
+    //同名节点返回第一个，返回值为(节点索引, 节点)
+    pub fn find_by_name(&self, name: &str) -> Option<(usize, &Node)> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .find(|(_, n)| n.name.as_deref() == Some(name))
+    }
 }
 
 fn compute_transform_matrix(transform: &Transform) -> Matrix4<f32> {
@@ -196,3 +364,137 @@ fn compute_transform_matrix(transform: &Transform) -> Matrix4<f32> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gltf::Gltf;
+
+    //网格不需要真实数据，只要节点引用到即可
+    const DOCUMENT: &str = r#"{
+        "asset": { "version": "2.0" },
+        "extensionsUsed": ["KHR_lights_punctual"],
+        "extensions": {
+            "KHR_lights_punctual": { "lights": [{ "type": "point" }] }
+        },
+        "scene": 0,
+        "scenes": [{ "nodes": [0, 3] }],
+        "nodes": [
+            { "name": "root", "children": [1, 2], "translation": [1.0, 0.0, 0.0] },
+            { "name": "part", "mesh": 0 },
+            { "name": "part", "mesh": 0, "translation": [0.0, 2.0, 0.0] },
+            { "extensions": { "KHR_lights_punctual": { "light": 0 } } },
+            { "name": "orphan" }
+        ],
+        "meshes": [{ "primitives": [{ "attributes": {} }] }]
+    }"#;
+
+    fn document(json: &str) -> Document {
+        Gltf::from_slice_without_validation(json.as_bytes())
+            .unwrap()
+            .document
+    }
+
+    fn nodes(document: &Document) -> Nodes {
+        let scene = document.default_scene().unwrap();
+        let uids = compute_node_uids(document);
+        Nodes::from_gltf_nodes(document.nodes(), &scene, &uids)
+    }
+
+    #[test]
+    fn mesh_and_light_nodes_are_filtered() {
+        let document = document(DOCUMENT);
+        let nodes = nodes(&document);
+        assert_eq!(nodes.mesh_nodes().count(), 2);
+        assert!(nodes.mesh_nodes().all(|n| n.mesh_index() == Some(0)));
+        let lights = nodes.light_nodes().collect::<Vec<_>>();
+        assert_eq!(lights.len(), 1);
+        assert_eq!(lights[0].light_index(), Some(0));
+    }
+
+    #[test]
+    fn find_by_name_returns_first_match() {
+        let document = document(DOCUMENT);
+        let nodes = nodes(&document);
+        let (index, node) = nodes.find_by_name("part").unwrap();
+        assert_eq!(index, 1);
+        assert_eq!(node.transform().w.x, 1.0);
+        assert!(nodes.find_by_name("missing").is_none());
+    }
+
+    #[test]
+    fn node_uids_are_unique_and_stable() {
+        let uids = compute_node_uids(&document(DOCUMENT));
+        let mut sorted = uids.clone();
+        sorted.sort();
+        sorted.dedup();
+        assert_eq!(sorted.len(), uids.len());
+        assert_eq!(uids, compute_node_uids(&document(DOCUMENT)));
+
+        //ID只取决于路径，节点在数组中的位置变化后不变
+        let reordered = DOCUMENT
+            .replace(r#""nodes": [0, 3]"#, r#""nodes": [3, 0]"#)
+            .replace(
+                r#"{ "name": "orphan" }"#,
+                r#"{ "name": "orphan" }, { "name": "extra" }"#,
+            );
+        assert_eq!(uids[..], compute_node_uids(&document(&reordered))[..5]);
+    }
+
+    #[test]
+    fn node_by_uid_finds_the_node() {
+        let document = document(DOCUMENT);
+        let nodes = nodes(&document);
+        let uid = compute_node_uids(&document)[2];
+        let (index, node) = nodes.node_by_uid(uid).unwrap();
+        assert_eq!(index, 2);
+        assert_eq!(node.uid(), uid);
+    }
+
+    //镜像节点变换后的三角形绕序翻转，按逆时针为正面会剔除掉立方体的外表面
+    #[test]
+    fn mirrored_nodes_flip_triangle_winding() {
+        use crate::cgmath::{EuclideanSpace, InnerSpace, Point3, Transform as _};
+
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../../../assets/models/NegativeScale/glTF-Embedded/NegativeScale.gltf"
+        );
+        let (document, buffers, _) = gltf::import(path).unwrap();
+        let nodes = nodes(&document);
+        let primitive = document
+            .meshes()
+            .next()
+            .unwrap()
+            .primitives()
+            .next()
+            .unwrap();
+        let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+        let positions = reader.read_positions().unwrap().collect::<Vec<_>>();
+        let indices = reader
+            .read_indices()
+            .unwrap()
+            .into_u32()
+            .collect::<Vec<_>>();
+
+        let mirrored = nodes
+            .nodes()
+            .iter()
+            .map(Node::is_mirrored)
+            .collect::<Vec<_>>();
+        assert_eq!(mirrored, vec![false, true]);
+
+        for node in nodes.nodes() {
+            let transform = node.transform();
+            let center = transform.transform_point(Point3::origin());
+            for face in indices.chunks_exact(3) {
+                let [a, b, c] = [face[0], face[1], face[2]]
+                    .map(|i| transform.transform_point(Point3::from(positions[i as usize])));
+                let winding_normal = (b - a).cross(c - a);
+                let outward = Point3::centroid(&[a, b, c]) - center;
+                let counter_clockwise_outside = winding_normal.dot(outward) > 0.0;
+                assert_eq!(counter_clockwise_outside, !node.is_mirrored());
+            }
+        }
+    }
+}
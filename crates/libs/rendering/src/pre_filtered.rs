@@ -8,8 +8,8 @@ use cgmath::{Deg, Matrix4};
 use std::mem::size_of;
 use std::sync::Arc;
 use std::time::Instant;
-use vulkan::ash::vk::{self, RenderingAttachmentInfo, RenderingInfo};
-use vulkan::{Context, Texture};
+use vulkan::ash::vk;
+use vulkan::{Context, RenderingAttachment, RenderingDesc, Texture, DYNAMIC_VIEWPORT_STATES};
 
 pub(crate) fn create_pre_filtered_map(
     context: &Arc<Context>,
@@ -68,9 +68,8 @@ pub(crate) fn create_pre_filtered_map(
                 .depth_bias_clamp(0.0)
                 .depth_bias_slope_factor(0.0);
 
-            let dynamic_state = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
-            let dynamic_state_info =
-                vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&dynamic_state);
+            let dynamic_state_info = vk::PipelineDynamicStateCreateInfo::builder()
+                .dynamic_states(&DYNAMIC_VIEWPORT_STATES);
 
             create_env_pipeline::<SkyboxVertex>(
                 context,
@@ -89,8 +88,14 @@ pub(crate) fn create_pre_filtered_map(
         (layout, pipeline)
     };
 
-    let pre_filtered =
-        Texture::create_renderable_cubemap(context, size, max_mip_levels, cubemap_format,std::ffi::CString::new("Pre-Filtered Texture").unwrap());
+    let pre_filtered = Texture::create_renderable_cubemap(
+        context,
+        size,
+        max_mip_levels,
+        cubemap_format,
+        std::ffi::CString::new("Pre-Filtered Texture").unwrap(),
+    )
+    .expect("创建pre-filtered cubemap失败！");
 
     let mut views = Vec::new();
     for lod in 0..max_mip_levels {
@@ -147,33 +152,24 @@ pub(crate) fn create_pre_filtered_map(
             for (face, view) in view_matrices.iter().enumerate() {
                 let image_view = views[lod as usize][face];
 
-                let attachment_info = RenderingAttachmentInfo::builder()
-                    .clear_value(vk::ClearValue {
-                        color: vk::ClearColorValue {
-                            float32: [0.0, 0.0, 0.0, 1.0],
-                        },
-                    })
-                    .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
-                    .image_view(image_view)
-                    .load_op(vk::AttachmentLoadOp::CLEAR)
-                    .store_op(vk::AttachmentStoreOp::STORE);
-
-                let rendering_info = RenderingInfo::builder()
-                    .color_attachments(std::slice::from_ref(&attachment_info))
-                    .layer_count(1)
-                    .render_area(vk::Rect2D {
-                        offset: vk::Offset2D { x: 0, y: 0 },
+                let attachment = RenderingAttachment::new(
+                    &pre_filtered.image,
+                    image_view,
+                    vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                )
+                .clear_color([0.0, 0.0, 0.0, 1.0]);
+
+                context.cmd_begin_rendering(
+                    buffer,
+                    &RenderingDesc {
                         extent: vk::Extent2D {
                             width: viewport_size,
                             height: viewport_size,
                         },
-                    });
-
-                unsafe {
-                    context
-                        .dynamic_rendering()
-                        .cmd_begin_rendering(buffer, &rendering_info)
-                };
+                        color_attachments: std::slice::from_ref(&attachment),
+                        depth_attachment: None,
+                    },
+                );
 
                 unsafe {
                     device.cmd_bind_pipeline(buffer, vk::PipelineBindPoint::GRAPHICS, pipeline)
@@ -232,7 +228,7 @@ pub(crate) fn create_pre_filtered_map(
 
                 unsafe { device.cmd_draw_indexed(buffer, 36, 1, 0, 0, 0) };
 
-                unsafe { context.dynamic_rendering().cmd_end_rendering(buffer) };
+                context.cmd_end_rendering(buffer);
             }
         }
     });
@@ -242,11 +238,12 @@ pub(crate) fn create_pre_filtered_map(
         vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
     );
 
+    views
+        .iter()
+        .flatten()
+        .for_each(|v| context.destroy_image_view(*v));
+
     unsafe {
-        views
-            .iter()
-            .flatten()
-            .for_each(|v| device.destroy_image_view(*v, None));
         device.destroy_pipeline(pipeline, None);
         device.destroy_pipeline_layout(pipeline_layout, None);
     }
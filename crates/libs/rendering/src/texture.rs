@@ -1,22 +1,57 @@
-use gltf::image::{Data, Format};
+use gltf::image::{Data, Format, Source};
 use gltf::iter::{Materials, Textures as GltfTextures};
 use gltf::json::texture::{MagFilter, MinFilter, WrappingMode};
-use gltf::texture::Sampler;
-use std::collections::HashSet;
+use gltf::texture::{Sampler, Texture as GltfTexture};
+use gltf::Document;
+use std::collections::{HashMap, HashSet};
 use std::ffi::CString;
 use std::sync::Arc;
 use vulkan::ash::vk;
-use vulkan::{Buffer, Context, Image, Texture as VulkanTexture};
+use vulkan::{Buffer, Context, Image, Texture as VulkanTexture, VkError, DEFAULT_MAX_ANISOTROPY};
+
+use crate::fscene::FScene;
+use crate::material::Material;
+use crate::util::parallel_map;
+
+//纹理在材质中的用途，决定采样时是否需要sRGB解码
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextureUsage {
+    //基础色、漫反射、高光光泽度、自发光，存储的是sRGB编码的颜色
+    Color,
+    //金属粗糙度、法线、遮蔽、高度，存储的是线性数据
+    Data,
+}
 
 pub struct Textures {
     _images: Vec<VulkanTexture>,
-    pub textures: Vec<Texture>,
+    color_textures: Vec<Option<Texture>>,
+    data_textures: Vec<Option<Texture>>,
+}
+
+impl Textures {
+    //按glTF纹理序号索引，同一纹理同时用于颜色和数据时两个列表指向不同格式的图像，
+    //引用的图片不存在时对应位置为None
+    pub fn get(&self, usage: TextureUsage) -> &[Option<Texture>] {
+        match usage {
+            TextureUsage::Color => &self.color_textures,
+            TextureUsage::Data => &self.data_textures,
+        }
+    }
+}
+
+//材质引用的纹理不存在时返回None，调用方改用默认纹理，避免越界
+pub fn find_texture<T>(textures: &[Option<T>], index: Option<usize>) -> Option<&T> {
+    let index = index?;
+    let texture = textures.get(index).and_then(Option::as_ref);
+    if texture.is_none() {
+        log::warn!("材质引用的纹理{}不存在，使用默认纹理", index);
+    }
+    texture
 }
 
 pub struct Texture {
-    context: Arc<Context>,
     view: vk::ImageView,
-    sampler: vk::Sampler,
+    sampler: Arc<SharedSampler>,
 }
 
 impl Texture {
@@ -25,11 +60,17 @@ impl Texture {
     }
 
     pub fn get_sampler(&self) -> vk::Sampler {
-        self.sampler
+        self.sampler.sampler
     }
 }
 
-impl Drop for Texture {
+//采样参数和mip层数相同的纹理共用一个sampler
+struct SharedSampler {
+    context: Arc<Context>,
+    sampler: vk::Sampler,
+}
+
+impl Drop for SharedSampler {
     fn drop(&mut self) {
         unsafe {
             self.context.device().destroy_sampler(self.sampler, None);
@@ -39,82 +80,243 @@ impl Drop for Texture {
 
 pub fn create_textures_from_gltf(
     context: &Arc<Context>,
-    command_buffer: vk::CommandBuffer,
     textures: GltfTextures,
     materials: Materials,
     images: &[Data],
-    image_paths: Vec<&str>
-) -> (Textures, Vec<Buffer>) {
-    let srgb_image_indices = {
-        let mut indices = HashSet::new();
-
-        for m in materials {
-            if let Some(t) = m.pbr_metallic_roughness().base_color_texture() {
-                indices.insert(t.texture().source().index());
-            }
-
-            if let Some(pbr_specular_glossiness) = m.pbr_specular_glossiness() {
-                if let Some(t) = pbr_specular_glossiness.diffuse_texture() {
-                    indices.insert(t.texture().source().index());
-                }
-
-                if let Some(t) = pbr_specular_glossiness.specular_glossiness_texture() {
-                    indices.insert(t.texture().source().index());
-                }
-            }
-
-            if let Some(t) = m.emissive_texture() {
-                indices.insert(t.texture().source().index());
-            }
-        }
-
-        indices
-    };
-
-    let (images, buffers) = images
-        .iter()
-        .enumerate()
-        .map(|(index, image)| {
-            let pixels = build_rgba_buffer(image);
-            let is_srgb = srgb_image_indices.contains(&index);
+    image_paths: Vec<Option<&str>>,
+) -> Result<Textures, VkError> {
+    create_textures(
+        context,
+        textures,
+        materials,
+        images.len(),
+        image_paths,
+        |index| (&images[index], build_rgba_buffer(&images[index])),
+        |command_buffer, (image, pixels), linear| {
             VulkanTexture::cmd_from_rgba(
                 context,
                 command_buffer,
                 image.width,
                 image.height,
-                &pixels,
-                !is_srgb,
-                CString::new("Unknown").unwrap(),//下面sampler给名字，这里拿不到
+                pixels,
+                linear,
+                CString::new("Unknown").unwrap(), //下面sampler给名字，这里拿不到
             )
-        })
-        .unzip::<_, _, Vec<_>, _>();
-
-    let textures = textures
-        .map(|t| {
-            let context = Arc::clone(context);
-            let image = &images[t.source().index()];
-            let path = image_paths[t.source().index()];
-            image.image.set_debug_utils_object_name(&context, CString::new(path).unwrap());
-            let view = image.view;
-            let sampler = map_sampler(&context, &image.image, &t.sampler());
-            Texture {
+        },
+    )
+}
+
+//图片已经在转换时解码并生成好mip，直接按层上传
+pub fn create_textures_from_fscene(
+    context: &Arc<Context>,
+    textures: GltfTextures,
+    materials: Materials,
+    fscene: &FScene,
+    image_paths: Vec<Option<&str>>,
+) -> Result<Textures, VkError> {
+    create_textures(
+        context,
+        textures,
+        materials,
+        fscene.image_count(),
+        image_paths,
+        |index| (fscene.image_size(index), fscene.image_levels(index)),
+        |command_buffer, ((width, height), levels), linear| {
+            VulkanTexture::cmd_from_rgba_mips(
                 context,
-                view,
-                sampler,
+                command_buffer,
+                *width,
+                *height,
+                levels,
+                linear,
+                CString::new("Unknown").unwrap(),
+            )
+        },
+    )
+}
+
+//按图片序号对齐，内嵌在缓冲或data URI中的图片没有路径
+pub fn image_paths(document: &Document) -> Vec<Option<&str>> {
+    document
+        .images()
+        .map(|image| match image.source() {
+            Source::Uri { uri, mime_type: _ } if !uri.starts_with("data:") => {
+                log::info!("加载图片{} {}", image.index(), uri);
+                Some(uri)
             }
+            _ => None,
         })
-        .collect();
+        .collect()
+}
 
-    (
-        Textures {
-            _images: images,
-            textures,
-        },
-        buffers,
-    )
+//按材质引用的情况把图片分为颜色(sRGB)和数据(线性)两类，同一张图片可以同时属于两类
+pub fn classify_images(
+    textures: &[GltfTexture],
+    materials: Materials,
+) -> (HashSet<usize>, HashSet<usize>) {
+    let mut color_image_indices = HashSet::new();
+    let mut data_image_indices = HashSet::new();
+    for m in materials {
+        let pbr = m.pbr_metallic_roughness();
+        let color_textures = [
+            pbr.base_color_texture().map(|t| t.texture()),
+            m.emissive_texture().map(|t| t.texture()),
+            m.pbr_specular_glossiness()
+                .and_then(|p| p.diffuse_texture())
+                .map(|t| t.texture()),
+            m.pbr_specular_glossiness()
+                .and_then(|p| p.specular_glossiness_texture())
+                .map(|t| t.texture()),
+        ];
+        let data_textures = [
+            pbr.metallic_roughness_texture().map(|t| t.texture()),
+            m.normal_texture().map(|t| t.texture()),
+            m.occlusion_texture().map(|t| t.texture()),
+            Material::from(m.clone())
+                .get_height_texture_index()
+                .and_then(|index| textures.get(index).cloned()),
+        ];
+
+        color_image_indices.extend(color_textures.iter().flatten().map(|t| t.source().index()));
+        data_image_indices.extend(data_textures.iter().flatten().map(|t| t.source().index()));
+    }
+    (color_image_indices, data_image_indices)
+}
+
+//load取出一张图片的像素，upload按sRGB或线性格式上传，同一张图片两种格式都需要时只load一次
+fn create_textures<P, L, U>(
+    context: &Arc<Context>,
+    textures: GltfTextures,
+    materials: Materials,
+    image_count: usize,
+    image_paths: Vec<Option<&str>>,
+    load: L,
+    upload: U,
+) -> Result<Textures, VkError>
+where
+    P: Send,
+    L: Fn(usize) -> P + Sync,
+    U: Fn(vk::CommandBuffer, &P, bool) -> Result<(VulkanTexture, Buffer), VkError>,
+{
+    //记录每张图片被哪些用途引用，同时被颜色和数据引用的图片上传两份
+    let textures = textures.collect::<Vec<_>>();
+    let (color_image_indices, data_image_indices) = classify_images(&textures, materials);
+
+    //解码按图片并行，上传全部录制到同一个command buffer里只提交一次
+    let image_indices = (0..image_count).collect::<Vec<_>>();
+    let pixels = parallel_map(&image_indices, |index| load(*index));
+
+    let uploads = image_uploads(image_count, &color_image_indices, &data_image_indices);
+    let mut images = Vec::with_capacity(image_count);
+    let mut staging_buffers = Vec::new();
+    let result = context.execute_one_time_commands(|command_buffer| -> Result<(), VkError> {
+        for (pixels, &(is_color, is_data)) in pixels.iter().zip(&uploads) {
+            let mut upload = |linear: bool| -> Result<_, VkError> {
+                let (texture, buffer) = upload(command_buffer, pixels, linear)?;
+                staging_buffers.push(buffer);
+                Ok(texture)
+            };
+            let srgb = is_color.then(|| upload(false)).transpose()?;
+            let linear = is_data.then(|| upload(true)).transpose()?;
+            images.push((srgb, linear));
+        }
+        Ok(())
+    });
+    //命令执行完才释放staging buffer，出错时已录制的图像也要等提交结束再销毁
+    drop(staging_buffers);
+    result?;
+
+    //每张图片只命名一次，内嵌的图片没有路径
+    for (index, (srgb, linear)) in images.iter().enumerate() {
+        let path = image_paths
+            .get(index)
+            .copied()
+            .flatten()
+            .unwrap_or("Embedded");
+        for image in srgb.iter().chain(linear) {
+            image
+                .image
+                .set_debug_utils_object_name(context, CString::new(path).unwrap());
+        }
+    }
+
+    //多个glTF纹理引用同一张图片时共用图像和视图，只按采样参数区分sampler
+    let mut samplers = HashMap::<(Option<usize>, u32), Arc<SharedSampler>>::new();
+    let mut create_textures = |usage: TextureUsage| {
+        textures
+            .iter()
+            .map(|t| {
+                //资源损坏时纹理可能引用不存在的图片，保留空位让序号与glTF纹理对齐
+                let Some(image) = select_image(&images, t.source().index(), usage) else {
+                    log::warn!("纹理{}引用的图片{}不存在", t.index(), t.source().index());
+                    return None;
+                };
+                let gltf_sampler = t.sampler();
+                let sampler = samplers
+                    .entry((gltf_sampler.index(), image.image.get_mip_levels()))
+                    .or_insert_with(|| {
+                        Arc::new(SharedSampler {
+                            context: Arc::clone(context),
+                            sampler: map_sampler(context, &image.image, &gltf_sampler),
+                        })
+                    });
+                Some(Texture {
+                    view: image.view,
+                    sampler: Arc::clone(sampler),
+                })
+            })
+            .collect::<Vec<_>>()
+    };
+    let color_textures = create_textures(TextureUsage::Color);
+    let data_textures = create_textures(TextureUsage::Data);
+
+    let images = images
+        .into_iter()
+        .flat_map(|(srgb, linear)| srgb.into_iter().chain(linear))
+        .collect::<Vec<_>>();
+    log::info!(
+        "{}个纹理共用{}张图像和{}个sampler",
+        textures.len(),
+        images.len(),
+        samplers.len()
+    );
+
+    Ok(Textures {
+        _images: images,
+        color_textures,
+        data_textures,
+    })
+}
+
+//每张图片需要上传的(sRGB, 线性)两份，没有被材质引用的图片按线性数据上传
+fn image_uploads(
+    image_count: usize,
+    color_image_indices: &HashSet<usize>,
+    data_image_indices: &HashSet<usize>,
+) -> Vec<(bool, bool)> {
+    (0..image_count)
+        .map(|index| {
+            let is_color = color_image_indices.contains(&index);
+            let is_data = data_image_indices.contains(&index) || !is_color;
+            (is_color, is_data)
+        })
+        .collect()
+}
+
+//images按图片序号保存(sRGB, 线性)两份，缺少用途对应的一份时用另一份
+fn select_image<T>(
+    images: &[(Option<T>, Option<T>)],
+    index: usize,
+    usage: TextureUsage,
+) -> Option<&T> {
+    let (srgb, linear) = images.get(index)?;
+    match usage {
+        TextureUsage::Color => srgb.as_ref().or(linear.as_ref()),
+        TextureUsage::Data => linear.as_ref().or(srgb.as_ref()),
+    }
 }
 
-fn build_rgba_buffer(image: &Data) -> Vec<u8> {
+pub fn build_rgba_buffer(image: &Data) -> Vec<u8> {
     let mut buffer = Vec::new();
     let size = image.width * image.height;
     for index in 0..size {
@@ -162,14 +364,18 @@ fn map_sampler(context: &Arc<Context>, image: &Image, sampler: &Sampler) -> vk::
         0.25
     };
 
+    //没有mipmap时各向异性过滤没有意义
+    let anisotropy =
+        context.get_supported_anisotropy(has_mipmaps.then_some(DEFAULT_MAX_ANISOTROPY));
+
     let sampler_info = vk::SamplerCreateInfo::builder()
         .mag_filter(map_mag_filter(mag_filter))
         .min_filter(map_min_filter(min_filter))
         .address_mode_u(map_wrap_mode(sampler.wrap_s()))
         .address_mode_v(map_wrap_mode(sampler.wrap_t()))
         .address_mode_w(vk::SamplerAddressMode::REPEAT)
-        .anisotropy_enable(has_mipmaps)
-        .max_anisotropy(16.0)
+        .anisotropy_enable(anisotropy.is_some())
+        .max_anisotropy(anisotropy.unwrap_or(0.0))
         .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
         .unnormalized_coordinates(false)
         .compare_enable(false)
@@ -227,3 +433,140 @@ fn map_mipmap_filter(min_filter: MinFilter) -> vk::SamplerMipmapMode {
         MinFilter::LinearMipmapLinear => vk::SamplerMipmapMode::LINEAR,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gltf::Gltf;
+
+    //纹理0和2共用图片0，图片0同时被颜色和法线引用
+    const DOCUMENT: &str = r#"{
+        "asset": { "version": "2.0" },
+        "images": [{ "uri": "a.png" }, { "uri": "b.png" }, { "uri": "c.png" }],
+        "textures": [{ "source": 0 }, { "source": 1 }, { "source": 0 }, { "source": 2 }],
+        "materials": [
+            {
+                "pbrMetallicRoughness": {
+                    "baseColorTexture": { "index": 0 },
+                    "metallicRoughnessTexture": { "index": 1 }
+                },
+                "normalTexture": { "index": 2 }
+            },
+            { "emissiveTexture": { "index": 3 } }
+        ]
+    }"#;
+
+    fn sorted(indices: HashSet<usize>) -> Vec<usize> {
+        let mut indices = indices.into_iter().collect::<Vec<_>>();
+        indices.sort();
+        indices
+    }
+
+    #[test]
+    fn images_are_classified_by_material_slot() {
+        let document = Gltf::from_slice_without_validation(DOCUMENT.as_bytes())
+            .unwrap()
+            .document;
+        let textures = document.textures().collect::<Vec<_>>();
+        let (color, data) = classify_images(&textures, document.materials());
+        assert_eq!(sorted(color), vec![0, 2]);
+        assert_eq!(sorted(data), vec![0, 1]);
+    }
+
+    //4个纹理只引用2张图片，每张图片只用于一种用途，上传的图像数等于不重复的图片数
+    #[test]
+    fn shared_images_are_uploaded_once() {
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../../../assets/models/SharedTextures/glTF-Embedded/SharedTextures.gltf"
+        );
+        let (document, _, images) = gltf::import(path).unwrap();
+        let textures = document.textures().collect::<Vec<_>>();
+        let (color, data) = classify_images(&textures, document.materials());
+        let uploads = image_uploads(images.len(), &color, &data);
+
+        let unique_images = textures
+            .iter()
+            .map(|t| t.source().index())
+            .collect::<HashSet<_>>();
+        let uploaded = uploads
+            .iter()
+            .map(|&(srgb, linear)| srgb as usize + linear as usize)
+            .sum::<usize>();
+        assert_eq!(textures.len(), 4);
+        assert_eq!(unique_images.len(), 2);
+        assert_eq!(uploaded, unique_images.len());
+    }
+
+    //外部文件和base64两种来源解码出相同的图片，只有外部文件有路径
+    #[test]
+    fn uri_and_embedded_images_load_the_same() {
+        let models = concat!(env!("CARGO_MANIFEST_DIR"), "/../../../assets/models");
+        let load = |path: &str| {
+            let (document, _, images) = gltf::import(format!("{models}/{path}")).unwrap();
+            let paths = image_paths(&document)
+                .into_iter()
+                .map(|p| p.map(String::from))
+                .collect::<Vec<_>>();
+            (images, paths)
+        };
+        let (uri_images, uri_paths) = load("TexturedQuad/glTF/TexturedQuad.gltf");
+        let (embedded_images, embedded_paths) =
+            load("TexturedQuad/glTF-Embedded/TexturedQuad.gltf");
+
+        assert_eq!(uri_paths, vec![Some(String::from("checker.png"))]);
+        assert_eq!(embedded_paths, vec![None]);
+        assert_eq!(uri_images.len(), 1);
+        assert_eq!(embedded_images.len(), 1);
+        let (a, b) = (&uri_images[0], &embedded_images[0]);
+        assert_eq!((a.width, a.height), (b.width, b.height));
+        assert_eq!(build_rgba_buffer(a), build_rgba_buffer(b));
+    }
+
+    #[test]
+    fn missing_textures_and_images_fall_back() {
+        let textures = [Some(1), None, Some(3)];
+        assert_eq!(find_texture(&textures, Some(0)), Some(&1));
+        assert_eq!(find_texture(&textures, Some(1)), None);
+        assert_eq!(find_texture(&textures, None), None);
+
+        let images = [(Some("srgb"), None), (None, Some("linear")), (None, None)];
+        assert_eq!(select_image(&images, 0, TextureUsage::Data), Some(&"srgb"));
+        assert_eq!(
+            select_image(&images, 1, TextureUsage::Color),
+            Some(&"linear")
+        );
+        assert_eq!(select_image(&images, 2, TextureUsage::Color), None);
+    }
+
+    //随机的纹理和图片序号，包括越界和极大的值，都不能panic
+    #[test]
+    fn random_indices_never_panic() {
+        use rand::{Rng, SeedableRng};
+        let mut rng = rand::rngs::StdRng::seed_from_u64(2391);
+        let textures = (0..8)
+            .map(|i| rng.gen_bool(0.5).then_some(i))
+            .collect::<Vec<_>>();
+        let images = (0..8)
+            .map(|i| {
+                (
+                    rng.gen_bool(0.5).then_some(i),
+                    rng.gen_bool(0.5).then_some(i),
+                )
+            })
+            .collect::<Vec<_>>();
+        for _ in 0..10_000 {
+            let index = match rng.gen_range(0..3) {
+                0 => rng.gen_range(0..16),
+                1 => usize::MAX - rng.gen_range(0..4),
+                _ => rng.gen(),
+            };
+            let found = find_texture(&textures, Some(index));
+            assert_eq!(found, textures.get(index).and_then(Option::as_ref));
+            for usage in [TextureUsage::Color, TextureUsage::Data] {
+                let image = select_image(&images, index, usage);
+                assert!(image.is_none() || image == Some(&index));
+            }
+        }
+    }
+}
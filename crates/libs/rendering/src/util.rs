@@ -1,4 +1,51 @@
 pub unsafe fn any_as_u8_slice<T: Sized>(any: &T) -> &[u8] {
     let ptr = (any as *const T) as *const u8;
     std::slice::from_raw_parts(ptr, std::mem::size_of::<T>())
-}
\ No newline at end of file
+}
+//把items按线程数切成连续的块并行处理，结果顺序与items一致
+//每个工作线程先调用一次init创建线程私有的状态，再对块内每个元素调用f
+pub fn parallel_map_with<T, S, R, I, F>(items: &[T], init: I, f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    I: Fn() -> S + Sync,
+    F: Fn(&mut S, &T) -> R + Sync,
+{
+    if items.is_empty() {
+        return Vec::new();
+    }
+
+    let thread_count = std::thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .min(items.len());
+    let chunk_size = items.len().div_ceil(thread_count);
+
+    std::thread::scope(|scope| {
+        let handles = items
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(|| {
+                    let mut state = init();
+                    chunk
+                        .iter()
+                        .map(|item| f(&mut state, item))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("工作线程崩溃！"))
+            .collect()
+    })
+}
+
+pub fn parallel_map<T, R, F>(items: &[T], f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    parallel_map_with(items, || (), |_, item| f(item))
+}
@@ -21,14 +21,25 @@ pub struct Frustum {
 }
 impl Frustum {
     pub fn compute(perspective_matrix: Mat4, view_matrix: Mat4) -> Self {
-        let mat = (perspective_matrix * view_matrix).transpose();
+        Self::from_view_projection(perspective_matrix * view_matrix)
+    }
+
+    //列主序的view projection矩阵，方便不使用glam的调用方
+    pub fn from_cols_array(view_projection: &[f32; 16]) -> Self {
+        Self::from_view_projection(Mat4::from_cols_array(view_projection))
+    }
+
+    pub fn from_view_projection(view_projection: Mat4) -> Self {
+        let mat = view_projection.transpose();
 
         let mut planes = [Vec4::default(); PLANE_COUNT];
         planes[FrustumPlane::Left as usize] = mat.w_axis + mat.x_axis;
         planes[FrustumPlane::Right as usize] = mat.w_axis - mat.x_axis;
         planes[FrustumPlane::Bottom as usize] = mat.w_axis + mat.y_axis;
         planes[FrustumPlane::Top as usize] = mat.w_axis - mat.y_axis;
-        planes[FrustumPlane::Near as usize] = mat.w_axis + mat.z_axis;
+        //Vulkan的裁剪空间深度范围是[0, w]，近平面是z >= 0而不是z >= -w
+        //反向Z时这两个平面互换，不影响剔除结果
+        planes[FrustumPlane::Near as usize] = mat.z_axis;
         planes[FrustumPlane::Far as usize] = mat.w_axis - mat.z_axis;
 
         let crosses = [
@@ -148,6 +159,10 @@ impl Frustum {
 
         true
     }
+
+    pub fn is_aabb_visible(&self, min: [f32; 3], max: [f32; 3]) -> bool {
+        self.is_bounding_box_visible(Vec3::from(min), Vec3::from(max))
+    }
 }
 
 const fn ij2k<const I: usize, const J: usize>() -> usize {
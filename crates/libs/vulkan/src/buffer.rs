@@ -16,6 +16,8 @@ pub struct Buffer {
     pub buffer: vk::Buffer,
     pub memory: vk::DeviceMemory,
     pub size: vk::DeviceSize,
+    //实际申请的内存大小，可能因对齐大于size
+    allocation_size: vk::DeviceSize,
     mapped_pointer: Option<MemoryMapPointer>,
 }
 
@@ -25,12 +27,15 @@ impl Buffer {
         buffer: vk::Buffer,
         memory: vk::DeviceMemory,
         size: vk::DeviceSize,
+        allocation_size: vk::DeviceSize,
     ) -> Self {
+        context.track_allocation(allocation_size);
         Self {
             context,
             buffer,
             memory,
             size,
+            allocation_size,
             mapped_pointer: None,
         }
     }
@@ -40,6 +45,7 @@ impl Buffer {
         size: vk::DeviceSize,
         usage: vk::BufferUsageFlags,
         mem_properties: vk::MemoryPropertyFlags,
+        name: &str,
     ) -> Self {
         let device = context.device();
         let buffer = {
@@ -53,6 +59,7 @@ impl Buffer {
                     .expect("Failed to create buffer")
             }
         };
+        context.set_debug_name(buffer, name);
 
         let mem_requirements = unsafe { device.get_buffer_memory_requirements(buffer) };
         let memory = {
@@ -78,7 +85,7 @@ impl Buffer {
                 .expect("绑定buffer内存失败！")
         };
 
-        Buffer::new(context, buffer, memory, size)
+        Buffer::new(context, buffer, memory, size, mem_requirements.size)
     }
 }
 
@@ -114,6 +121,68 @@ impl Buffer {
         }
     }
 
+    //把缓冲内容读回内存，设备本地缓冲先拷贝到临时的主机可见缓冲，需要TRANSFER_SRC用途
+    //提交后等待fence完成，会阻塞调用线程，只适合截图、拾取这类偶发的读回。
+    pub fn download<T: Copy>(&self) -> Vec<T> {
+        let count = self.size as usize / size_of::<T>();
+        let size = (count * size_of::<T>()) as vk::DeviceSize;
+        let mut staging_buffer = Buffer::create(
+            Arc::clone(&self.context),
+            size.max(1),
+            vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            "Download.Staging",
+        );
+
+        self.context.execute_one_time_commands(|command_buffer| {
+            //等待之前提交的任意写入完成后再拷贝，拷贝完成后对主机可见
+            let barriers = [
+                vk::BufferMemoryBarrier2::builder()
+                    .src_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+                    .src_access_mask(vk::AccessFlags2::MEMORY_WRITE)
+                    .dst_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+                    .dst_access_mask(vk::AccessFlags2::TRANSFER_READ)
+                    .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .buffer(self.buffer)
+                    .offset(0)
+                    .size(vk::WHOLE_SIZE)
+                    .build(),
+                vk::BufferMemoryBarrier2::builder()
+                    .src_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+                    .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+                    .dst_stage_mask(vk::PipelineStageFlags2::HOST)
+                    .dst_access_mask(vk::AccessFlags2::HOST_READ)
+                    .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .buffer(staging_buffer.buffer)
+                    .offset(0)
+                    .size(vk::WHOLE_SIZE)
+                    .build(),
+            ];
+
+            let synchronization2 = self.context.synchronization2();
+            unsafe {
+                synchronization2.cmd_pipeline_barrier2(
+                    command_buffer,
+                    &vk::DependencyInfo::builder().buffer_memory_barriers(&barriers[..1]),
+                )
+            };
+            if size > 0 {
+                staging_buffer.cmd_copy(command_buffer, self, size);
+            }
+            unsafe {
+                synchronization2.cmd_pipeline_barrier2(
+                    command_buffer,
+                    &vk::DependencyInfo::builder().buffer_memory_barriers(&barriers[1..]),
+                )
+            };
+        });
+
+        let ptr = staging_buffer.map_memory();
+        unsafe { std::slice::from_raw_parts(ptr as *const T, count).to_vec() }
+    }
+
     pub fn unmap_memory(&mut self) {
         if self.mapped_pointer.take().is_some() {
             unsafe {
@@ -130,6 +199,7 @@ impl Drop for Buffer {
             self.context.device().destroy_buffer(self.buffer, None);
             self.context.device().free_memory(self.memory, None);
         }
+        self.context.track_free(self.allocation_size);
     }
 }
 
@@ -137,9 +207,10 @@ pub fn create_device_local_buffer_with_data<A, T: Copy>(
     context: &Arc<Context>,
     usage: vk::BufferUsageFlags,
     data: &[T],
+    name: &str,
 ) -> Buffer {
     let (buffer, _) = context.execute_one_time_commands(|command_buffer| {
-        cmd_create_device_local_buffer_with_data::<A, _>(context, command_buffer, usage, data)
+        cmd_create_device_local_buffer_with_data::<A, _>(context, command_buffer, usage, data, name)
     });
     buffer
 }
@@ -149,15 +220,21 @@ pub fn cmd_create_device_local_buffer_with_data<A, T: Copy>(
     command_buffer: vk::CommandBuffer,
     usage: vk::BufferUsageFlags,
     data: &[T],
+    name: &str,
 ) -> (Buffer, Buffer) {
     let size = (data.len() * size_of::<T>()) as vk::DeviceSize;
-    let staging_buffer =
-        create_host_visible_buffer(context, vk::BufferUsageFlags::TRANSFER_SRC, data);
+    let staging_buffer = create_host_visible_buffer(
+        context,
+        vk::BufferUsageFlags::TRANSFER_SRC,
+        data,
+        &format!("{}.Staging", name),
+    );
     let buffer = Buffer::create(
         Arc::clone(context),
         size,
         vk::BufferUsageFlags::TRANSFER_DST | usage,
         vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        name,
     );
 
     buffer.cmd_copy(command_buffer, &staging_buffer, staging_buffer.size);
@@ -169,6 +246,7 @@ pub fn create_host_visible_buffer<T: Copy>(
     context: &Arc<Context>,
     usage: vk::BufferUsageFlags,
     data: &[T],
+    name: &str,
 ) -> Buffer {
     let size = (data.len() * size_of::<T>()) as vk::DeviceSize;
     let mut buffer = Buffer::create(
@@ -176,6 +254,7 @@ pub fn create_host_visible_buffer<T: Copy>(
         size,
         usage,
         vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        name,
     );
 
     unsafe {
@@ -185,3 +264,34 @@ pub fn create_host_visible_buffer<T: Copy>(
 
     buffer
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::{assert_no_validation_errors, test_context};
+
+    #[test]
+    fn download_reads_back_device_local_data() {
+        let Some(context) = test_context() else {
+            return;
+        };
+        let pattern = (0..1000u32)
+            .map(|i| i.wrapping_mul(2_654_435_761))
+            .collect::<Vec<_>>();
+        let buffer = create_device_local_buffer_with_data::<u32, _>(
+            &context,
+            vk::BufferUsageFlags::TRANSFER_SRC | vk::BufferUsageFlags::STORAGE_BUFFER,
+            &pattern,
+            "Test.Download",
+        );
+        assert_eq!(buffer.download::<u32>(), pattern);
+
+        //按更大的元素读取时丢弃末尾不足一个元素的字节
+        let pairs = buffer.download::<[u32; 3]>();
+        assert_eq!(pairs.len(), 333);
+        assert_eq!(pairs[1], [pattern[3], pattern[4], pattern[5]]);
+
+        drop(buffer);
+        assert_no_validation_errors();
+    }
+}
@@ -1,14 +1,61 @@
 mod shared;
 
 use self::shared::*;
-use crate::MsaaSamples;
+use crate::{error::VkError, MsaaSamples, RenderingDesc};
 use ash::{
-    extensions::khr::{DynamicRendering, Surface, Synchronization2},
+    extensions::khr::{Surface, Synchronization2},
     vk, Device, Instance,
 };
-use std::{ffi::CString, sync::Arc};
+use std::{
+    ffi::CString,
+    sync::{Arc, MutexGuard},
+};
 use winit::window::Window;
 
+//多显卡时指定使用的物理设备，都为None时自动选择
+#[derive(Clone, Debug, Default)]
+pub struct DevicePreference {
+    pub index: Option<usize>,
+    //不区分大小写，设备名包含该字符串即匹配
+    pub name: Option<String>,
+    //即使设备支持也不使用dynamic rendering，用于测试render pass路径
+    pub disable_dynamic_rendering: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct PhysicalDeviceInfo {
+    pub index: usize,
+    pub name: String,
+    pub device_type: vk::PhysicalDeviceType,
+}
+
+//创建设备时按物理设备能力开启的可选特性
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DeviceFeature {
+    SamplerAnisotropy,
+    FillModeNonSolid,
+    LargePoints,
+    WideLines,
+    SampleRateShading,
+    DepthClamp,
+    //不支持时开始和结束渲染改用render pass
+    DynamicRendering,
+}
+
+impl DeviceFeature {
+    pub fn all() -> [DeviceFeature; 7] {
+        [
+            DeviceFeature::SamplerAnisotropy,
+            DeviceFeature::FillModeNonSolid,
+            DeviceFeature::LargePoints,
+            DeviceFeature::WideLines,
+            DeviceFeature::SampleRateShading,
+            DeviceFeature::DepthClamp,
+            DeviceFeature::DynamicRendering,
+        ]
+    }
+}
+
 pub struct Context {
     shared_context: Arc<SharedContext>,
     general_command_pool: vk::CommandPool,
@@ -16,44 +63,72 @@ pub struct Context {
 }
 
 impl Context {
-    pub fn new(window: &Window, enable_debug: bool) -> Self {
-        let shared_context = Arc::new(SharedContext::new(window, enable_debug));
-        let general_command_pool = create_command_pool(
-            shared_context.device(),
-            shared_context.queue_families_indices,
+    pub fn new(
+        window: &Window,
+        enable_debug: bool,
+        preference: &DevicePreference,
+    ) -> Result<Self, VkError> {
+        let shared_context = SharedContext::new(Some(window), enable_debug, preference)?;
+        Self::with_shared_context(
+            Arc::new(shared_context),
             vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER,
-        );
-        let transient_command_pool = create_command_pool(
-            shared_context.device(),
-            shared_context.queue_families_indices,
-            vk::CommandPoolCreateFlags::TRANSIENT,
-        );
+        )
+    }
 
-        Self {
-            shared_context,
-            general_command_pool,
-            transient_command_pool,
-        }
+    //不创建surface和交换链，用于离屏渲染
+    pub fn new_headless(
+        enable_debug: bool,
+        preference: &DevicePreference,
+    ) -> Result<Self, VkError> {
+        let shared_context = SharedContext::new(None, enable_debug, preference)?;
+        Self::with_shared_context(
+            Arc::new(shared_context),
+            vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER,
+        )
+    }
+
+    //列出所有物理设备，index即DevicePreference中使用的序号
+    pub fn enumerate_devices() -> Result<Vec<PhysicalDeviceInfo>, VkError> {
+        enumerate_devices()
     }
 
-    pub fn new_thread(&self) -> Self {
-        let shared_context = Arc::clone(&self.shared_context);
+    fn with_shared_context(
+        shared_context: Arc<SharedContext>,
+        general_pool_flags: vk::CommandPoolCreateFlags,
+    ) -> Result<Self, VkError> {
         let general_command_pool = create_command_pool(
             shared_context.device(),
             shared_context.queue_families_indices,
-            vk::CommandPoolCreateFlags::empty(),
-        );
-        let transient_command_pool = create_command_pool(
+            general_pool_flags,
+        )?;
+        let transient_command_pool = match create_command_pool(
             shared_context.device(),
             shared_context.queue_families_indices,
             vk::CommandPoolCreateFlags::TRANSIENT,
-        );
+        ) {
+            Ok(pool) => pool,
+            Err(error) => {
+                unsafe {
+                    shared_context
+                        .device()
+                        .destroy_command_pool(general_command_pool, None)
+                };
+                return Err(error);
+            }
+        };
 
-        Self {
+        Ok(Self {
             shared_context,
             general_command_pool,
             transient_command_pool,
-        }
+        })
+    }
+
+    pub fn new_thread(&self) -> Result<Self, VkError> {
+        Self::with_shared_context(
+            Arc::clone(&self.shared_context),
+            vk::CommandPoolCreateFlags::empty(),
+        )
     }
 
     pub fn set_debug_utils_object_name(
@@ -66,6 +141,20 @@ impl Context {
             .set_debug_utils_object_name(object_handle, object_name, object_type);
     }
 
+    pub fn debug_utils_enabled(&self) -> bool {
+        self.shared_context.debug_utils_enabled()
+    }
+
+    //对象类型由handle类型决定，名字用于RenderDoc/Nsight等工具中显示
+    pub fn set_debug_name<H: vk::Handle>(&self, handle: H, name: &str) {
+        if !self.debug_utils_enabled() {
+            return;
+        }
+        if let Ok(name) = CString::new(name) {
+            self.set_debug_utils_object_name(handle.as_raw(), name, H::TYPE);
+        }
+    }
+
     pub fn cmd_begin_debug_utils_label(
         &self,
         command_buffer: vk::CommandBuffer,
@@ -85,16 +174,12 @@ fn create_command_pool(
     device: &Device,
     queue_families_indices: QueueFamiliesIndices,
     create_flags: vk::CommandPoolCreateFlags,
-) -> vk::CommandPool {
+) -> Result<vk::CommandPool, VkError> {
     let command_pool_info = vk::CommandPoolCreateInfo::builder()
         .queue_family_index(queue_families_indices.graphics_index)
         .flags(create_flags);
 
-    unsafe {
-        device
-            .create_command_pool(&command_pool_info, None)
-            .expect("创建command pool失败！")
-    }
+    Ok(unsafe { device.create_command_pool(&command_pool_info, None)? })
 }
 
 impl Context {
@@ -110,6 +195,10 @@ impl Context {
         self.shared_context.surface_khr()
     }
 
+    pub fn is_headless(&self) -> bool {
+        self.shared_context.is_headless()
+    }
+
     pub fn physical_device(&self) -> vk::PhysicalDevice {
         self.shared_context.physical_device()
     }
@@ -118,6 +207,10 @@ impl Context {
         self.shared_context.device()
     }
 
+    pub fn enabled_features(&self) -> vk::PhysicalDeviceFeatures {
+        self.shared_context.enabled_features()
+    }
+
     pub fn queue_families_indices(&self) -> QueueFamiliesIndices {
         self.shared_context.queue_families_indices()
     }
@@ -130,8 +223,44 @@ impl Context {
         self.shared_context.present_queue()
     }
 
-    pub fn dynamic_rendering(&self) -> &DynamicRendering {
-        self.shared_context.dynamic_rendering()
+    pub fn lock_queue(&self) -> MutexGuard<'_, ()> {
+        self.shared_context.lock_queue()
+    }
+
+    //开始渲染到desc中的attachment，设备不支持dynamic rendering时使用缓存的render pass
+    pub fn cmd_begin_rendering(&self, command_buffer: vk::CommandBuffer, desc: &RenderingDesc) {
+        self.shared_context
+            .rendering()
+            .cmd_begin_rendering(self.device(), command_buffer, desc);
+    }
+
+    pub fn cmd_end_rendering(&self, command_buffer: vk::CommandBuffer) {
+        self.shared_context
+            .rendering()
+            .cmd_end_rendering(self.device(), command_buffer);
+    }
+
+    //创建pipeline时使用，使用dynamic rendering时为None
+    pub fn pipeline_render_pass(
+        &self,
+        color_formats: &[vk::Format],
+        depth_format: Option<vk::Format>,
+        samples: vk::SampleCountFlags,
+    ) -> Option<vk::RenderPass> {
+        self.shared_context.rendering().pipeline_render_pass(
+            self.device(),
+            color_formats,
+            depth_format,
+            samples,
+        )
+    }
+
+    //可能作为attachment的view都通过这里销毁，同时释放引用它的framebuffer
+    pub fn destroy_image_view(&self, view: vk::ImageView) {
+        self.shared_context
+            .rendering()
+            .release_image_view(self.device(), view);
+        unsafe { self.device().destroy_image_view(view, None) };
     }
 
     pub fn synchronization2(&self) -> &Synchronization2 {
@@ -152,6 +281,19 @@ impl Context {
         self.shared_context.get_mem_properties()
     }
 
+    pub fn track_allocation(&self, size: vk::DeviceSize) {
+        self.shared_context.track_allocation(size)
+    }
+
+    pub fn track_free(&self, size: vk::DeviceSize) {
+        self.shared_context.track_free(size)
+    }
+
+    //当前所有buffer和image占用的设备内存字节数
+    pub fn allocated_memory(&self) -> vk::DeviceSize {
+        self.shared_context.allocated_memory()
+    }
+
     pub fn find_supported_format(
         &self,
         candidates: &[vk::Format],
@@ -166,6 +308,30 @@ impl Context {
         self.shared_context.get_max_usable_sample_count(preferred)
     }
 
+    pub fn is_sample_rate_shading_supported(&self) -> bool {
+        self.shared_context.is_sample_rate_shading_supported()
+    }
+
+    pub fn supports(&self, feature: DeviceFeature) -> bool {
+        self.shared_context.supports(feature)
+    }
+
+    pub fn get_supported_polygon_mode(&self, polygon_mode: vk::PolygonMode) -> vk::PolygonMode {
+        self.shared_context.get_supported_polygon_mode(polygon_mode)
+    }
+
+    pub fn get_supported_anisotropy(&self, max_anisotropy: Option<f32>) -> Option<f32> {
+        self.shared_context.get_supported_anisotropy(max_anisotropy)
+    }
+
+    pub fn get_supported_depth_resolve_mode(
+        &self,
+        preferred: vk::ResolveModeFlags,
+    ) -> vk::ResolveModeFlags {
+        self.shared_context
+            .get_supported_depth_resolve_mode(preferred)
+    }
+
     pub fn get_ubo_alignment<T>(&self) -> u32 {
         self.shared_context.get_ubo_alignment::<T>()
     }
@@ -208,3 +374,26 @@ pub fn find_memory_type(
     }
     panic!("找不到适合的memory type!")
 }
+
+//测试用的离屏设备，开启调试扩展以捕获验证层消息；没有Vulkan驱动的环境返回None，测试直接跳过
+#[cfg(test)]
+pub(crate) fn test_context() -> Option<Arc<Context>> {
+    crate::set_validation_capture(true);
+    match Context::new_headless(true, &DevicePreference::default()) {
+        Ok(context) => Some(Arc::new(context)),
+        Err(err) => {
+            eprintln!("没有可用的Vulkan设备，跳过: {}", err);
+            None
+        }
+    }
+}
+
+//测试结束时检查验证层没有报错
+#[cfg(test)]
+pub(crate) fn assert_no_validation_errors() {
+    let errors = crate::drain_validation_messages()
+        .into_iter()
+        .filter(|m| m.severity == crate::ValidationSeverity::Error)
+        .collect::<Vec<_>>();
+    assert!(errors.is_empty(), "{:#?}", errors);
+}
@@ -1,87 +1,143 @@
-use crate::{debug::*, swapchain::*, MsaaSamples};
+use super::{DeviceFeature, DevicePreference, PhysicalDeviceInfo};
+use crate::{
+    debug::*,
+    error::VkError,
+    rendering::{DynamicRenderingBackend, RenderPassBackend, RenderingBackend},
+    swapchain::*,
+    MsaaSamples,
+};
 use ash::{
     extensions::{
         ext::DebugUtils,
-        khr::{DynamicRendering, Surface, Swapchain as SwapchainLoader, Synchronization2},
+        khr::{
+            DynamicRendering, GetPhysicalDeviceProperties2, Surface, Swapchain as SwapchainLoader,
+            Synchronization2,
+        },
     },
     vk::{self, ObjectType},
     Device, Entry, Instance,
 };
-use raw_window_handle::{HasRawDisplayHandle, HasRawWindowHandle};
+use raw_window_handle::{HasRawDisplayHandle, HasRawWindowHandle, RawDisplayHandle};
 use std::{
     ffi::{CStr, CString},
     mem::size_of,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, MutexGuard,
+    },
 };
 use winit::window::Window;
 
 pub struct SharedContext {
-    _entry: Entry,
+    entry: Entry,
     instance: Instance,
     debug_report_callback: Option<(DebugUtils, vk::DebugUtilsMessengerEXT)>,
-    surface: Surface,
-    surface_khr: vk::SurfaceKHR,
+    //无窗口模式下没有surface
+    surface: Option<(Surface, vk::SurfaceKHR)>,
     physical_device: vk::PhysicalDevice,
+    //属性在设备生命周期内不变，缓存起来避免每帧查询驱动
+    physical_device_properties: vk::PhysicalDeviceProperties,
     device: Device,
+    enabled_features: vk::PhysicalDeviceFeatures,
     pub queue_families_indices: QueueFamiliesIndices,
     graphics_compute_queue: vk::Queue,
     present_queue: vk::Queue,
-    dynamic_rendering: DynamicRendering,
+    //多个线程共用同一个队列，提交、呈现和等待空闲都需要先持有这把锁
+    queue_lock: Mutex<()>,
+    //设备支持dynamic rendering时直接使用，否则退回render pass
+    rendering: Box<dyn RenderingBackend>,
+    dynamic_rendering: bool,
     synchronization2: Synchronization2,
-    debug_utils: DebugUtils,
+    //未开启调试时扩展没有加载，对象命名和标签都不生效
+    debug_utils: Option<DebugUtils>,
+    //buffer和image申请的设备内存总量，用于估算显存占用
+    allocated_memory: AtomicU64,
 }
 
 impl SharedContext {
-    pub fn new(window: &Window, enable_debug: bool) -> Self {
-        let entry = unsafe { Entry::load().unwrap() };
-        let instance = create_instance(&entry, window, enable_debug);
-        let debug_utils: DebugUtils = DebugUtils::new(&entry, &instance);
-
-        let surface = Surface::new(&entry, &instance);
-        let surface_khr = unsafe {
-            ash_window::create_surface(
-                &entry,
-                &instance,
-                window.raw_display_handle(),
-                window.raw_window_handle(),
-                None,
-            )
-            .expect("surface创建失败！")
+    pub fn new(
+        window: Option<&Window>,
+        enable_debug: bool,
+        preference: &DevicePreference,
+    ) -> Result<Self, VkError> {
+        let entry = unsafe { Entry::load()? };
+        let instance = create_instance(
+            &entry,
+            window.map(|window| window.raw_display_handle()),
+            enable_debug,
+        )?;
+        let debug_utils = enable_debug.then(|| DebugUtils::new(&entry, &instance));
+
+        let surface = match window {
+            Some(window) => {
+                let surface_khr = unsafe {
+                    ash_window::create_surface(
+                        &entry,
+                        &instance,
+                        window.raw_display_handle(),
+                        window.raw_window_handle(),
+                        None,
+                    )?
+                };
+                Some((Surface::new(&entry, &instance), surface_khr))
+            }
+            None => None,
         };
 
         let debug_report_callback = if enable_debug {
-            Some(setup_debug_messenger(&entry, &instance))
+            Some(setup_debug_messenger(&entry, &instance)?)
         } else {
             None
         };
 
+        let surface_ref = surface.as_ref().map(|(surface, khr)| (surface, *khr));
         let (physical_device, queue_families_indices) =
-            pick_physical_device(&instance, &surface, surface_khr);
+            pick_physical_device(&entry, &instance, surface_ref, preference)?;
 
-        let (device, graphics_compute_queue, present_queue) =
+        let dynamic_rendering = !preference.disable_dynamic_rendering
+            && supports_dynamic_rendering(&entry, &instance, physical_device);
+        let (device, enabled_features, graphics_compute_queue, present_queue) =
             create_logical_device_with_graphics_queue(
                 &instance,
                 physical_device,
                 queue_families_indices,
-            );
-
-        let dynamic_rendering = DynamicRendering::new(&instance, &device);
+                surface.is_none(),
+                dynamic_rendering,
+            )?;
+
+        let physical_device_properties =
+            unsafe { instance.get_physical_device_properties(physical_device) };
+        let rendering: Box<dyn RenderingBackend> = if dynamic_rendering {
+            Box::new(DynamicRenderingBackend::new(&instance, &device))
+        } else {
+            log::warn!("不使用dynamic rendering，改用render pass渲染");
+            Box::new(RenderPassBackend::new(&instance, &device))
+        };
         let synchronization2 = Synchronization2::new(&instance, &device);
 
-        Self {
-            _entry: entry,
+        Ok(Self {
+            entry,
             instance,
             debug_report_callback,
             surface,
-            surface_khr,
             physical_device,
+            physical_device_properties,
             device,
+            enabled_features,
             queue_families_indices,
             graphics_compute_queue,
             present_queue,
+            queue_lock: Mutex::new(()),
+            rendering,
             dynamic_rendering,
             synchronization2,
             debug_utils,
-        }
+            allocated_memory: AtomicU64::new(0),
+        })
+    }
+
+    pub fn debug_utils_enabled(&self) -> bool {
+        self.debug_utils.is_some()
     }
 
     pub fn set_debug_utils_object_name(
@@ -90,16 +146,16 @@ impl SharedContext {
         object_name: CString,
         object_type: ObjectType,
     ) {
+        let Some(debug_utils) = self.debug_utils.as_ref() else {
+            return;
+        };
         unsafe {
-            let name = CString::new(object_name).expect("Unknown");
             let name_info = vk::DebugUtilsObjectNameInfoEXT::builder()
                 .object_handle(object_handle)
-                .object_name(&name)
+                .object_name(&object_name)
                 .object_type(object_type)
                 .build();
-            let _ = self
-                .debug_utils
-                .set_debug_utils_object_name(self.device.handle(), &name_info);
+            let _ = debug_utils.set_debug_utils_object_name(self.device.handle(), &name_info);
         };
     }
 
@@ -108,22 +164,29 @@ impl SharedContext {
         command_buffer: vk::CommandBuffer,
         label_name: CString,
     ) {
+        let Some(debug_utils) = self.debug_utils.as_ref() else {
+            return;
+        };
         unsafe {
-            let name = CString::new(label_name).expect("Unknown");
-            let name_info = vk::DebugUtilsLabelEXT::builder().label_name(&name).build();
-            self.debug_utils
-                .cmd_begin_debug_utils_label(command_buffer, &name_info);
+            let name_info = vk::DebugUtilsLabelEXT::builder()
+                .label_name(&label_name)
+                .build();
+            debug_utils.cmd_begin_debug_utils_label(command_buffer, &name_info);
         };
     }
 
     pub fn cmd_end_debug_utils_label(&self, command_buffer: vk::CommandBuffer) {
-        unsafe {
-            self.debug_utils.cmd_end_debug_utils_label(command_buffer);
-        };
+        if let Some(debug_utils) = self.debug_utils.as_ref() {
+            unsafe { debug_utils.cmd_end_debug_utils_label(command_buffer) };
+        }
     }
 }
 
-fn create_instance(entry: &Entry, window: &Window, enable_debug: bool) -> Instance {
+fn create_instance(
+    entry: &Entry,
+    display_handle: Option<RawDisplayHandle>,
+    enable_debug: bool,
+) -> Result<Instance, VkError> {
     let app_name = CString::new("Fate Launcher").unwrap();
     let engine_name = CString::new("Fate Engine").unwrap();
     let app_info = vk::ApplicationInfo::builder()
@@ -133,10 +196,10 @@ fn create_instance(entry: &Entry, window: &Window, enable_debug: bool) -> Instan
         .engine_version(vk::make_api_version(0, 0, 1, 0))
         .api_version(vk::make_api_version(0, 1, 0, 0));
 
-    let mut extension_names =
-        ash_window::enumerate_required_extensions(window.raw_display_handle())
-            .expect("枚举extensions失败！")
-            .to_vec();
+    let mut extension_names = match display_handle {
+        Some(display_handle) => ash_window::enumerate_required_extensions(display_handle)?.to_vec(),
+        None => Vec::new(),
+    };
     extension_names.push(vk::KhrGetPhysicalDeviceProperties2Fn::name().as_ptr());
     if enable_debug {
         extension_names.push(DebugUtils::name().as_ptr());
@@ -146,111 +209,270 @@ fn create_instance(entry: &Entry, window: &Window, enable_debug: bool) -> Instan
         .application_info(&app_info)
         .enabled_extension_names(&extension_names);
 
-    unsafe {
-        entry
-            .create_instance(&instance_create_info, None)
-            .expect("instance创建失败！")
+    Ok(unsafe { entry.create_instance(&instance_create_info, None)? })
+}
+
+pub fn enumerate_devices() -> Result<Vec<PhysicalDeviceInfo>, VkError> {
+    let entry = unsafe { Entry::load()? };
+    let instance = create_instance(&entry, None, false)?;
+    let devices = get_device_infos(&instance);
+    unsafe { instance.destroy_instance(None) };
+    Ok(devices?.into_iter().map(|(_, info)| info).collect())
+}
+
+fn get_device_infos(
+    instance: &Instance,
+) -> Result<Vec<(vk::PhysicalDevice, PhysicalDeviceInfo)>, VkError> {
+    let devices = unsafe { instance.enumerate_physical_devices()? };
+    let devices = devices
+        .into_iter()
+        .enumerate()
+        .map(|(index, device)| {
+            let props = unsafe { instance.get_physical_device_properties(device) };
+            let name = unsafe { CStr::from_ptr(props.device_name.as_ptr()) }
+                .to_string_lossy()
+                .into_owned();
+            let info = PhysicalDeviceInfo {
+                index,
+                name,
+                device_type: props.device_type,
+            };
+            (device, info)
+        })
+        .collect();
+    Ok(devices)
+}
+
+fn find_preferred_device<'a>(
+    devices: &'a [(vk::PhysicalDevice, PhysicalDeviceInfo)],
+    preference: &DevicePreference,
+) -> Option<&'a (vk::PhysicalDevice, PhysicalDeviceInfo)> {
+    if let Some(index) = preference.index {
+        let device = devices.get(index);
+        if device.is_none() {
+            log::warn!("物理设备序号{}超出范围，共{}个设备", index, devices.len());
+        }
+        device
+    } else if let Some(name) = preference.name.as_ref() {
+        let name = name.to_lowercase();
+        let device = devices
+            .iter()
+            .find(|(_, info)| info.name.to_lowercase().contains(&name));
+        if device.is_none() {
+            log::warn!("找不到名称包含{}的物理设备", name);
+        }
+        device
+    } else {
+        None
     }
 }
 
+//优先使用指定的设备，不存在或不满足要求时按独显、集显的顺序自动选择
 fn pick_physical_device(
+    entry: &Entry,
     instance: &Instance,
-    surface: &Surface,
-    surface_khr: vk::SurfaceKHR,
-) -> (vk::PhysicalDevice, QueueFamiliesIndices) {
-    let devices = unsafe {
-        let mut devices = instance
-            .enumerate_physical_devices()
-            .expect("枚举physical devices失败！");
-        devices.sort_by_key(|d| {
-            let props = instance.get_physical_device_properties(*d);
-            match props.device_type {
+    surface: Option<(&Surface, vk::SurfaceKHR)>,
+    preference: &DevicePreference,
+) -> Result<(vk::PhysicalDevice, QueueFamiliesIndices), VkError> {
+    let mut devices = get_device_infos(instance)?;
+
+    let preferred = match find_preferred_device(&devices, preference) {
+        Some((device, info)) => {
+            let problems = check_device(entry, instance, surface, *device);
+            if problems.is_empty() {
+                Some(*device)
+            } else {
+                log::warn!(
+                    "物理设备{}不满足要求({})，改为自动选择",
+                    info.name,
+                    problems.join("; ")
+                );
+                None
+            }
+        }
+        None => None,
+    };
+
+    let device = match preferred {
+        Some(device) => device,
+        None => {
+            devices.sort_by_key(|(_, info)| match info.device_type {
                 vk::PhysicalDeviceType::DISCRETE_GPU => 0,
                 vk::PhysicalDeviceType::INTEGRATED_GPU => 1,
                 _ => 10,
+            });
+            //记录每个设备不满足的原因，全部不可用时放进错误里
+            let mut unsuitable = Vec::new();
+            let mut suitable = None;
+            for (device, info) in devices.iter() {
+                let problems = check_device(entry, instance, surface, *device);
+                if problems.is_empty() {
+                    suitable = Some(*device);
+                    break;
+                }
+                log::info!("跳过物理设备{}: {}", info.name, problems.join("; "));
+                unsuitable.push(format!(
+                    "[{}] {}: {}",
+                    info.index,
+                    info.name,
+                    problems.join("; ")
+                ));
             }
-        });
-
-        devices
+            suitable.ok_or(VkError::NoSuitableDevice(unsuitable))?
+        }
     };
-    let device = devices
-        .into_iter()
-        .find(|device| is_device_suitable(instance, surface, surface_khr, *device))
-        .expect("没有适合的物理设备!");
 
-    let props = unsafe { instance.get_physical_device_properties(device) };
-    log::debug!("选择物理设备为:{:?}", unsafe {
-        CStr::from_ptr(props.device_name.as_ptr())
-    });
+    if let Some((_, info)) = devices.iter().find(|(d, _)| *d == device) {
+        log::info!(
+            "选择物理设备为:[{}] {} ({:?})",
+            info.index,
+            info.name,
+            info.device_type
+        );
+    }
 
-    let (graphics_compute, present) = find_queue_families(instance, surface, surface_khr, device);
+    let (graphics_compute, present) = find_queue_families(instance, surface, device);
     let queue_families_indices = QueueFamiliesIndices {
         graphics_index: graphics_compute.unwrap(),
         present_index: present.unwrap(),
     };
 
-    (device, queue_families_indices)
+    Ok((device, queue_families_indices))
 }
 
-fn is_device_suitable(
+//返回设备不满足要求的原因，为空时设备可用
+fn check_device(
+    entry: &Entry,
     instance: &Instance,
-    surface: &Surface,
-    surface_khr: vk::SurfaceKHR,
+    surface: Option<(&Surface, vk::SurfaceKHR)>,
     device: vk::PhysicalDevice,
-) -> bool {
-    let (graphics_compute, present) = find_queue_families(instance, surface, surface_khr, device);
-    let extention_support = check_device_extension_support(instance, device);
-    let is_swapchain_adequate = {
-        let details = SwapchainSupportDetails::new(device, surface, surface_khr);
-        !details.formats.is_empty() && !details.present_modes.is_empty()
-    };
-    let features = unsafe { instance.get_physical_device_features(device) };
-    graphics_compute.is_some()
-        && present.is_some()
-        && extention_support
-        && is_swapchain_adequate
-        && features.sampler_anisotropy == vk::TRUE
+) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    let (graphics_compute, present) = find_queue_families(instance, surface, device);
+    if graphics_compute.is_none() {
+        problems.push(String::from("没有同时支持图形和计算的队列"));
+    }
+    if present.is_none() {
+        problems.push(String::from("没有支持呈现到窗口的队列"));
+    }
+
+    match get_missing_device_extensions(instance, device, surface.is_none()) {
+        Ok(missing) if missing.is_empty() => {
+            //扩展都支持时才能查询对应的特性
+            let missing_features = get_missing_device_features(entry, instance, device);
+            if !missing_features.is_empty() {
+                problems.push(format!("缺少特性{}", missing_features.join(", ")));
+            }
+        }
+        Ok(missing) => {
+            let names = missing
+                .iter()
+                .map(|name| name.to_string_lossy())
+                .collect::<Vec<_>>();
+            problems.push(format!("缺少扩展{}", names.join(", ")));
+        }
+        Err(error) => problems.push(format!("查询设备扩展失败: {}", error)),
+    }
+
+    let is_swapchain_adequate = surface.is_none_or(|(surface, surface_khr)| {
+        SwapchainSupportDetails::new(device, surface, surface_khr)
+            .is_ok_and(|details| !details.formats.is_empty() && !details.present_modes.is_empty())
+    });
+    if !is_swapchain_adequate {
+        problems.push(String::from("surface没有可用的格式或呈现模式"));
+    }
+
+    problems
 }
 
-fn check_device_extension_support(instance: &Instance, device: vk::PhysicalDevice) -> bool {
-    let required_extentions = get_required_device_extensions();
+fn get_missing_device_extensions(
+    instance: &Instance,
+    device: vk::PhysicalDevice,
+    headless: bool,
+) -> Result<Vec<&'static CStr>, vk::Result> {
+    let extension_props = unsafe { instance.enumerate_device_extension_properties(device)? };
+
+    let missing = get_required_device_extensions(headless)
+        .into_iter()
+        .filter(|required| {
+            !extension_props.iter().any(|ext| {
+                let name = unsafe { CStr::from_ptr(ext.extension_name.as_ptr()) };
+                required == &name
+            })
+        })
+        .collect();
+    Ok(missing)
+}
 
-    let extension_props = unsafe {
-        instance
-            .enumerate_device_extension_properties(device)
-            .expect("枚举device extention properties失败！")
+//扩展存在但驱动没有实现对应特性时创建设备会失败
+fn get_missing_device_features(
+    entry: &Entry,
+    instance: &Instance,
+    device: vk::PhysicalDevice,
+) -> Vec<&'static str> {
+    let mut synchronization2_feature = vk::PhysicalDeviceSynchronization2Features::default();
+    let mut features =
+        vk::PhysicalDeviceFeatures2::builder().push_next(&mut synchronization2_feature);
+    unsafe {
+        GetPhysicalDeviceProperties2::new(entry, instance)
+            .get_physical_device_features2(device, &mut features)
     };
 
-    for required in required_extentions.iter() {
-        let found = extension_props.iter().any(|ext| {
-            let name = unsafe { CStr::from_ptr(ext.extension_name.as_ptr()) };
-            required == &name
-        });
+    let mut missing = Vec::new();
+    if synchronization2_feature.synchronization2 != vk::TRUE {
+        missing.push("synchronization2");
+    }
+    missing
+}
 
-        if !found {
-            return false;
-        }
+//dynamic rendering是可选的，不支持时使用render pass
+fn supports_dynamic_rendering(
+    entry: &Entry,
+    instance: &Instance,
+    device: vk::PhysicalDevice,
+) -> bool {
+    let Ok(extension_props) = (unsafe { instance.enumerate_device_extension_properties(device) })
+    else {
+        return false;
+    };
+    let has_extension = extension_props.iter().any(|ext| {
+        let name = unsafe { CStr::from_ptr(ext.extension_name.as_ptr()) };
+        name == DynamicRendering::name()
+    });
+    if !has_extension {
+        return false;
     }
 
-    true
+    let mut dynamic_rendering_feature = vk::PhysicalDeviceDynamicRenderingFeatures::default();
+    let mut features =
+        vk::PhysicalDeviceFeatures2::builder().push_next(&mut dynamic_rendering_feature);
+    unsafe {
+        GetPhysicalDeviceProperties2::new(entry, instance)
+            .get_physical_device_features2(device, &mut features)
+    };
+    dynamic_rendering_feature.dynamic_rendering == vk::TRUE
 }
 
-fn get_required_device_extensions() -> [&'static CStr; 7] {
-    [
-        SwapchainLoader::name(),
-        DynamicRendering::name(),
+//无窗口模式不需要交换链扩展
+fn get_required_device_extensions(headless: bool) -> Vec<&'static CStr> {
+    let mut extensions = vec![
         vk::KhrDepthStencilResolveFn::name(),
         vk::KhrCreateRenderpass2Fn::name(),
         vk::KhrMultiviewFn::name(),
         vk::KhrMaintenance2Fn::name(),
         vk::KhrSynchronization2Fn::name(),
-    ]
+    ];
+    if !headless {
+        extensions.push(SwapchainLoader::name());
+    }
+    extensions
 }
 
+//无窗口模式下present队列就是图形队列
 fn find_queue_families(
     instance: &Instance,
-    surface: &Surface,
-    surface_khr: vk::SurfaceKHR,
+    surface: Option<(&Surface, vk::SurfaceKHR)>,
     device: vk::PhysicalDevice,
 ) -> (Option<u32>, Option<u32>) {
     let mut graphics_compute = None;
@@ -267,10 +489,13 @@ fn find_queue_families(
             graphics_compute = Some(index);
         }
 
-        let present_support = unsafe {
-            surface
-                .get_physical_device_surface_support(device, index, surface_khr)
-                .expect("获取surface support失败！")
+        let present_support = match surface {
+            Some((surface, surface_khr)) => unsafe {
+                surface
+                    .get_physical_device_surface_support(device, index, surface_khr)
+                    .unwrap_or(false)
+            },
+            None => graphics_compute == Some(index),
         };
         if present_support && present.is_none() {
             present = Some(index);
@@ -288,7 +513,9 @@ fn create_logical_device_with_graphics_queue(
     instance: &Instance,
     device: vk::PhysicalDevice,
     queue_families_indices: QueueFamiliesIndices,
-) -> (Device, vk::Queue, vk::Queue) {
+    headless: bool,
+    dynamic_rendering: bool,
+) -> Result<(Device, vk::PhysicalDeviceFeatures, vk::Queue, vk::Queue), VkError> {
     let graphics_family_index = queue_families_indices.graphics_index;
     let present_family_index = queue_families_indices.present_index;
     let queue_priorities = [1.0f32];
@@ -308,36 +535,73 @@ fn create_logical_device_with_graphics_queue(
             .collect::<Vec<_>>()
     };
 
-    let device_extensions = get_required_device_extensions();
+    let mut device_extensions = get_required_device_extensions(headless);
+    if dynamic_rendering {
+        device_extensions.push(DynamicRendering::name());
+    }
     let device_extensions_ptrs = device_extensions
         .iter()
         .map(|ext| ext.as_ptr())
         .collect::<Vec<_>>();
 
-    let device_features = vk::PhysicalDeviceFeatures::builder().sampler_anisotropy(true);
+    //各向异性过滤、线框、点模式、线宽、sample shading和深度截断为可选特性
+    let supported_features = unsafe { instance.get_physical_device_features(device) };
+    let device_features = vk::PhysicalDeviceFeatures::builder()
+        .sampler_anisotropy(supported_features.sampler_anisotropy == vk::TRUE)
+        .fill_mode_non_solid(supported_features.fill_mode_non_solid == vk::TRUE)
+        .large_points(supported_features.large_points == vk::TRUE)
+        .wide_lines(supported_features.wide_lines == vk::TRUE)
+        .sample_rate_shading(supported_features.sample_rate_shading == vk::TRUE)
+        .depth_clamp(supported_features.depth_clamp == vk::TRUE)
+        .build();
+    for feature in DeviceFeature::all() {
+        if !is_feature_enabled(&device_features, dynamic_rendering, feature) {
+            log::warn!("设备不支持可选特性{:?}", feature);
+        }
+    }
     let mut dynamic_rendering_feature =
         vk::PhysicalDeviceDynamicRenderingFeatures::builder().dynamic_rendering(true);
     let mut synchronization2_feature =
         vk::PhysicalDeviceSynchronization2Features::builder().synchronization2(true);
     let mut device_features_2 = vk::PhysicalDeviceFeatures2::builder()
-        .features(device_features.build())
-        .push_next(&mut dynamic_rendering_feature)
+        .features(device_features)
         .push_next(&mut synchronization2_feature);
+    if dynamic_rendering {
+        device_features_2 = device_features_2.push_next(&mut dynamic_rendering_feature);
+    }
 
     let device_create_info = vk::DeviceCreateInfo::builder()
         .queue_create_infos(&queue_create_infos)
         .enabled_extension_names(&device_extensions_ptrs)
         .push_next(&mut device_features_2);
 
-    let device = unsafe {
-        instance
-            .create_device(device, &device_create_info, None)
-            .expect("创建逻辑设备失败！")
-    };
+    let device = unsafe { instance.create_device(device, &device_create_info, None)? };
     let graphics_compute_queue = unsafe { device.get_device_queue(graphics_family_index, 0) };
     let present_queue = unsafe { device.get_device_queue(present_family_index, 0) };
 
-    (device, graphics_compute_queue, present_queue)
+    Ok((
+        device,
+        device_features,
+        graphics_compute_queue,
+        present_queue,
+    ))
+}
+
+fn is_feature_enabled(
+    features: &vk::PhysicalDeviceFeatures,
+    dynamic_rendering: bool,
+    feature: DeviceFeature,
+) -> bool {
+    let enabled = match feature {
+        DeviceFeature::DynamicRendering => return dynamic_rendering,
+        DeviceFeature::SamplerAnisotropy => features.sampler_anisotropy,
+        DeviceFeature::FillModeNonSolid => features.fill_mode_non_solid,
+        DeviceFeature::LargePoints => features.large_points,
+        DeviceFeature::WideLines => features.wide_lines,
+        DeviceFeature::SampleRateShading => features.sample_rate_shading,
+        DeviceFeature::DepthClamp => features.depth_clamp,
+    };
+    enabled == vk::TRUE
 }
 
 impl SharedContext {
@@ -346,11 +610,15 @@ impl SharedContext {
     }
 
     pub fn surface(&self) -> &Surface {
-        &self.surface
+        &self.surface.as_ref().expect("无窗口模式没有surface！").0
     }
 
     pub fn surface_khr(&self) -> vk::SurfaceKHR {
-        self.surface_khr
+        self.surface.as_ref().expect("无窗口模式没有surface！").1
+    }
+
+    pub fn is_headless(&self) -> bool {
+        self.surface.is_none()
     }
 
     pub fn physical_device(&self) -> vk::PhysicalDevice {
@@ -361,6 +629,10 @@ impl SharedContext {
         &self.device
     }
 
+    pub fn enabled_features(&self) -> vk::PhysicalDeviceFeatures {
+        self.enabled_features
+    }
+
     pub fn queue_families_indices(&self) -> QueueFamiliesIndices {
         self.queue_families_indices
     }
@@ -373,8 +645,12 @@ impl SharedContext {
         self.present_queue
     }
 
-    pub fn dynamic_rendering(&self) -> &DynamicRendering {
-        &self.dynamic_rendering
+    pub fn lock_queue(&self) -> MutexGuard<'_, ()> {
+        self.queue_lock.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub(crate) fn rendering(&self) -> &dyn RenderingBackend {
+        self.rendering.as_ref()
     }
 
     pub fn synchronization2(&self) -> &Synchronization2 {
@@ -383,6 +659,18 @@ impl SharedContext {
 }
 
 impl SharedContext {
+    pub fn track_allocation(&self, size: vk::DeviceSize) {
+        self.allocated_memory.fetch_add(size, Ordering::Relaxed);
+    }
+
+    pub fn track_free(&self, size: vk::DeviceSize) {
+        self.allocated_memory.fetch_sub(size, Ordering::Relaxed);
+    }
+
+    pub fn allocated_memory(&self) -> vk::DeviceSize {
+        self.allocated_memory.load(Ordering::Relaxed)
+    }
+
     pub fn get_mem_properties(&self) -> vk::PhysicalDeviceMemoryProperties {
         unsafe {
             self.instance
@@ -408,12 +696,9 @@ impl SharedContext {
     }
 
     pub fn get_max_usable_sample_count(&self, preferred: MsaaSamples) -> vk::SampleCountFlags {
-        let props = unsafe {
-            self.instance
-                .get_physical_device_properties(self.physical_device)
-        };
-        let color_sample_counts = props.limits.framebuffer_color_sample_counts;
-        let depth_sample_counts = props.limits.framebuffer_depth_sample_counts;
+        let limits = &self.physical_device_properties.limits;
+        let color_sample_counts = limits.framebuffer_color_sample_counts;
+        let depth_sample_counts = limits.framebuffer_depth_sample_counts;
         let max_sample_count = color_sample_counts.min(depth_sample_counts);
 
         use MsaaSamples::*;
@@ -434,12 +719,65 @@ impl SharedContext {
         }
     }
 
-    fn get_min_uniform_buffer_offset_alignment(&self) -> u32 {
-        let props = unsafe {
-            self.instance
-                .get_physical_device_properties(self.physical_device)
+    pub fn is_sample_rate_shading_supported(&self) -> bool {
+        self.supports(DeviceFeature::SampleRateShading)
+    }
+
+    pub fn supports(&self, feature: DeviceFeature) -> bool {
+        is_feature_enabled(&self.enabled_features, self.dynamic_rendering, feature)
+    }
+
+    pub fn get_supported_polygon_mode(&self, polygon_mode: vk::PolygonMode) -> vk::PolygonMode {
+        let supported = match polygon_mode {
+            vk::PolygonMode::LINE => self.supports(DeviceFeature::FillModeNonSolid),
+            vk::PolygonMode::POINT => {
+                self.supports(DeviceFeature::FillModeNonSolid)
+                    && self.supports(DeviceFeature::LargePoints)
+            }
+            _ => true,
         };
-        props.limits.min_uniform_buffer_offset_alignment as _
+        if supported {
+            polygon_mode
+        } else {
+            log::warn!("设备不支持{:?}模式，回退到FILL", polygon_mode);
+            vk::PolygonMode::FILL
+        }
+    }
+
+    //SAMPLE_ZERO是规范要求必须支持的，其他模式需要查询
+    pub fn get_supported_depth_resolve_mode(
+        &self,
+        preferred: vk::ResolveModeFlags,
+    ) -> vk::ResolveModeFlags {
+        let mut resolve_props = vk::PhysicalDeviceDepthStencilResolveProperties::default();
+        let mut props = vk::PhysicalDeviceProperties2::builder().push_next(&mut resolve_props);
+        unsafe {
+            GetPhysicalDeviceProperties2::new(&self.entry, &self.instance)
+                .get_physical_device_properties2(self.physical_device, &mut props)
+        };
+        let supported_modes = resolve_props.supported_depth_resolve_modes;
+        if supported_modes.contains(preferred) {
+            preferred
+        } else {
+            log::warn!("设备不支持{:?}深度resolve，回退到SAMPLE_ZERO", preferred);
+            vk::ResolveModeFlags::SAMPLE_ZERO
+        }
+    }
+
+    //不超过设备上限，设备不支持各向异性过滤或等级不超过1时关闭
+    pub fn get_supported_anisotropy(&self, max_anisotropy: Option<f32>) -> Option<f32> {
+        let max_anisotropy = max_anisotropy.filter(|level| *level > 1.0)?;
+        if !self.supports(DeviceFeature::SamplerAnisotropy) {
+            return None;
+        }
+        let limits = &self.physical_device_properties.limits;
+        Some(max_anisotropy.min(limits.max_sampler_anisotropy))
+    }
+
+    fn get_min_uniform_buffer_offset_alignment(&self) -> u32 {
+        self.physical_device_properties
+            .limits
+            .min_uniform_buffer_offset_alignment as _
     }
 
     pub fn get_ubo_alignment<T>(&self) -> u32 {
@@ -470,6 +808,13 @@ impl SharedContext {
                     .expect("申请command buffer失败！")[0]
             }
         };
+        if self.debug_utils_enabled() {
+            self.set_debug_utils_object_name(
+                vk::Handle::as_raw(command_buffer),
+                CString::new("OneTimeCommands").unwrap(),
+                ObjectType::COMMAND_BUFFER,
+            );
+        }
         let command_buffers = [command_buffer];
 
         {
@@ -496,12 +841,26 @@ impl SharedContext {
             let submit_info = vk::SubmitInfo2::builder()
                 .command_buffer_infos(std::slice::from_ref(&cmd_buffer_submit_info));
 
+            //只等待本次提交的fence，不阻塞其他线程在同一队列上的工作
             unsafe {
-                let queue = self.graphics_compute_queue();
-                self.synchronization2
-                    .queue_submit2(queue, std::slice::from_ref(&submit_info), vk::Fence::null())
-                    .expect("提交失败！");
-                self.device.queue_wait_idle(queue).expect("等待空闲失败！");
+                let fence = self
+                    .device
+                    .create_fence(&vk::FenceCreateInfo::default(), None)
+                    .expect("创建fence失败！");
+                {
+                    let _queue_guard = self.lock_queue();
+                    self.synchronization2
+                        .queue_submit2(
+                            self.graphics_compute_queue(),
+                            std::slice::from_ref(&submit_info),
+                            fence,
+                        )
+                        .expect("提交失败！");
+                }
+                self.device
+                    .wait_for_fences(&[fence], true, u64::MAX)
+                    .expect("等待fence失败！");
+                self.device.destroy_fence(fence, None);
             };
         }
 
@@ -511,6 +870,7 @@ impl SharedContext {
     }
 
     pub fn graphics_queue_wait_idle(&self) {
+        let _queue_guard = self.lock_queue();
         unsafe {
             self.device
                 .queue_wait_idle(self.graphics_compute_queue())
@@ -521,9 +881,12 @@ impl SharedContext {
 
 impl Drop for SharedContext {
     fn drop(&mut self) {
+        self.rendering.destroy(&self.device);
         unsafe {
             self.device.destroy_device(None);
-            self.surface.destroy_surface(self.surface_khr, None);
+            if let Some((surface, surface_khr)) = self.surface.take() {
+                surface.destroy_surface(surface_khr, None);
+            }
             if let Some((utils, messenger)) = self.debug_report_callback.take() {
                 utils.destroy_debug_utils_messenger(messenger, None);
             }
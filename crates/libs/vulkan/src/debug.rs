@@ -1,11 +1,106 @@
+use crate::error::VkError;
 use ash::extensions::ext::DebugUtils;
 use ash::{vk, Entry, Instance};
-use std::{ffi::CStr, os::raw::c_void};
+use std::{collections::VecDeque, ffi::CStr, os::raw::c_void, sync::Mutex};
+
+pub const VALIDATION_CAPTURE_CAPACITY: usize = 1024;
+
+// 未开启捕获时为None，回调只输出到log
+static VALIDATION_CAPTURE: Mutex<Option<VecDeque<ValidationMessage>>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ValidationSeverity {
+    Verbose = 0,
+    Info,
+    Warning,
+    Error,
+}
+
+impl ValidationSeverity {
+    pub fn all() -> [ValidationSeverity; 4] {
+        use ValidationSeverity::*;
+        [Verbose, Info, Warning, Error]
+    }
+
+    fn from_flag(flag: vk::DebugUtilsMessageSeverityFlagsEXT) -> Self {
+        use vk::DebugUtilsMessageSeverityFlagsEXT as Flag;
+        match flag {
+            Flag::VERBOSE => ValidationSeverity::Verbose,
+            Flag::INFO => ValidationSeverity::Info,
+            Flag::WARNING => ValidationSeverity::Warning,
+            _ => ValidationSeverity::Error,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ValidationMessage {
+    pub severity: ValidationSeverity,
+    pub message_type: String,
+    pub message: String,
+}
+
+//开启或关闭验证层消息的内存捕获，关闭时清空已捕获的消息
+pub fn set_validation_capture(enabled: bool) {
+    let mut capture = VALIDATION_CAPTURE.lock().unwrap();
+    match (enabled, capture.is_some()) {
+        (true, false) => *capture = Some(VecDeque::with_capacity(VALIDATION_CAPTURE_CAPACITY)),
+        (false, true) => *capture = None,
+        _ => {}
+    }
+}
+
+pub fn is_validation_capture_enabled() -> bool {
+    VALIDATION_CAPTURE.lock().unwrap().is_some()
+}
+
+//取出自上次调用以来捕获的所有消息
+pub fn drain_validation_messages() -> Vec<ValidationMessage> {
+    VALIDATION_CAPTURE
+        .lock()
+        .unwrap()
+        .as_mut()
+        .map(|messages| messages.drain(..).collect())
+        .unwrap_or_default()
+}
+
+fn capture_validation_message(
+    flag: vk::DebugUtilsMessageSeverityFlagsEXT,
+    typ: vk::DebugUtilsMessageTypeFlagsEXT,
+    message: &CStr,
+) {
+    // 回调可能在任意线程触发，锁被污染时直接放弃捕获
+    let Ok(mut capture) = VALIDATION_CAPTURE.lock() else {
+        return;
+    };
+    if let Some(messages) = capture.as_mut() {
+        if messages.len() >= VALIDATION_CAPTURE_CAPACITY {
+            messages.pop_front();
+        }
+        messages.push_back(ValidationMessage {
+            severity: ValidationSeverity::from_flag(flag),
+            message_type: format!("{:?}", typ),
+            message: message.to_string_lossy().into_owned(),
+        });
+    }
+}
+
+//给Vulkan对象命名，未开启调试扩展时不会格式化名字
+//`debug_name!(context, pipeline, "LightPass.Pipeline")`
+//`debug_name!(context, set, "GBuffer.DescriptorSet[{}]", i)`
+#[macro_export]
+macro_rules! debug_name {
+    ($context:expr, $handle:expr, $($arg:tt)+) => {
+        if $context.debug_utils_enabled() {
+            $context.set_debug_name($handle, &format!($($arg)+));
+        }
+    };
+}
 
 pub fn setup_debug_messenger(
     entry: &Entry,
     instance: &Instance,
-) -> (DebugUtils, vk::DebugUtilsMessengerEXT) {
+) -> Result<(DebugUtils, vk::DebugUtilsMessengerEXT), VkError> {
     use vk::DebugUtilsMessageSeverityFlagsEXT as Severity;
     use vk::DebugUtilsMessageTypeFlagsEXT as MsgType;
 
@@ -15,12 +110,9 @@ pub fn setup_debug_messenger(
         .message_type(MsgType::GENERAL | MsgType::VALIDATION | MsgType::PERFORMANCE)
         .pfn_user_callback(Some(vulkan_debug_callback));
     let debug_utils = DebugUtils::new(entry, instance);
-    let debug_utils_messenger = unsafe {
-        debug_utils
-            .create_debug_utils_messenger(&create_info, None)
-            .expect("创建debug report callback失败！")
-    };
-    (debug_utils, debug_utils_messenger)
+    let debug_utils_messenger =
+        unsafe { debug_utils.create_debug_utils_messenger(&create_info, None)? };
+    Ok((debug_utils, debug_utils_messenger))
 }
 
 unsafe extern "system" fn vulkan_debug_callback(
@@ -38,5 +130,6 @@ unsafe extern "system" fn vulkan_debug_callback(
         Flag::WARNING => log::warn!("{:?} - {:?}", typ, message),
         _ => log::error!("{:?} - {:?}", typ, message),
     }
+    capture_validation_message(flag, typ, message);
     vk::FALSE
 }
@@ -0,0 +1,52 @@
+use ash::{vk, LoadingError};
+use std::{error::Error, fmt};
+
+//设备、surface、交换链和资源创建失败时返回，由调用方决定是否可以恢复
+#[derive(Clone, Debug)]
+pub enum VkError {
+    //vulkan动态库加载失败，LoadingError不能Clone，只保留错误信息
+    Loading(String),
+    Vulkan(vk::Result),
+    //每个物理设备不满足要求的原因
+    NoSuitableDevice(Vec<String>),
+}
+
+impl fmt::Display for VkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VkError::Loading(error) => write!(f, "加载vulkan库失败: {}", error),
+            VkError::Vulkan(result) => write!(f, "vulkan调用失败: {}", result),
+            VkError::NoSuitableDevice(devices) if devices.is_empty() => {
+                write!(f, "没有找到支持vulkan的物理设备，请检查显卡驱动是否安装")
+            }
+            VkError::NoSuitableDevice(devices) => {
+                write!(f, "没有适合的物理设备:")?;
+                for device in devices {
+                    write!(f, "\n  {}", device)?;
+                }
+                write!(f, "\n请更新显卡驱动，或通过--gpu选择其他设备")
+            }
+        }
+    }
+}
+
+impl Error for VkError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            VkError::Vulkan(result) => Some(result),
+            VkError::Loading(_) | VkError::NoSuitableDevice(_) => None,
+        }
+    }
+}
+
+impl From<vk::Result> for VkError {
+    fn from(result: vk::Result) -> Self {
+        VkError::Vulkan(result)
+    }
+}
+
+impl From<LoadingError> for VkError {
+    fn from(error: LoadingError) -> Self {
+        VkError::Loading(error.to_string())
+    }
+}
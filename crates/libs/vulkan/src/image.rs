@@ -1,4 +1,4 @@
-use super::{buffer::*, context::*, swapchain::SwapchainProperties};
+use super::{buffer::*, context::*, error::VkError, swapchain::SwapchainProperties};
 use ash::{
     vk::{self, Handle, ObjectType},
     Device,
@@ -40,9 +40,11 @@ impl Default for ImageParameters {
 pub struct Image {
     context: Arc<Context>,
     pub image: vk::Image,
-    memory: Option<vk::DeviceMemory>,
+    //内存和申请的字节数，交换链图像没有
+    memory: Option<(vk::DeviceMemory, vk::DeviceSize)>,
     pub extent: vk::Extent3D,
     pub format: vk::Format,
+    pub sample_count: vk::SampleCountFlags,
     pub mip_levels: u32,
     pub layers: u32,
     managed: bool,
@@ -52,19 +54,24 @@ impl Image {
     fn new(
         context: Arc<Context>,
         image: vk::Image,
-        memory: Option<vk::DeviceMemory>,
+        memory: Option<(vk::DeviceMemory, vk::DeviceSize)>,
         extent: vk::Extent3D,
         format: vk::Format,
+        sample_count: vk::SampleCountFlags,
         mip_levels: u32,
         layers: u32,
         managed: bool,
     ) -> Self {
+        if let Some((_, size)) = memory {
+            context.track_allocation(size);
+        }
         Self {
             context,
             image,
             memory,
             extent,
             format,
+            sample_count,
             mip_levels,
             layers,
             managed,
@@ -75,7 +82,7 @@ impl Image {
         context: Arc<Context>,
         parameters: ImageParameters,
         image_name: std::ffi::CString,
-    ) -> Self {
+    ) -> Result<Self, VkError> {
         let extent = vk::Extent3D {
             width: parameters.extent.width,
             height: parameters.extent.height,
@@ -96,11 +103,7 @@ impl Image {
             .flags(parameters.create_flags);
 
         let device = context.device();
-        let image = unsafe {
-            device
-                .create_image(&image_info, None)
-                .expect("创建image失败！")
-        };
+        let image = unsafe { device.create_image(&image_info, None)? };
         context.set_debug_utils_object_name(image.as_raw(), image_name, ObjectType::IMAGE);
         let mem_requirements = unsafe { device.get_image_memory_requirements(image) };
         let mem_type_index = find_memory_type(
@@ -112,26 +115,34 @@ impl Image {
         let alloc_info = vk::MemoryAllocateInfo::builder()
             .allocation_size(mem_requirements.size)
             .memory_type_index(mem_type_index);
+        //失败时释放已经创建的对象
         let memory = unsafe {
-            let mem = device
-                .allocate_memory(&alloc_info, None)
-                .expect("分配image memory失败！");
-            device
-                .bind_image_memory(image, mem, 0)
-                .expect("绑定image memory失败！");
+            let mem = match device.allocate_memory(&alloc_info, None) {
+                Ok(mem) => mem,
+                Err(error) => {
+                    device.destroy_image(image, None);
+                    return Err(error.into());
+                }
+            };
+            if let Err(error) = device.bind_image_memory(image, mem, 0) {
+                device.free_memory(mem, None);
+                device.destroy_image(image, None);
+                return Err(error.into());
+            }
             mem
         };
 
-        Image::new(
+        Ok(Image::new(
             context,
             image,
-            Some(memory),
+            Some((memory, mem_requirements.size)),
             extent,
             parameters.format,
+            parameters.sample_count,
             parameters.mip_levels,
             parameters.layers,
             false,
-        )
+        ))
     }
 
     pub fn set_debug_utils_object_name(
@@ -157,6 +168,7 @@ impl Image {
                 depth: 1,
             },
             swapchain_properties.format.format,
+            vk::SampleCountFlags::TYPE_1,
             1,
             1,
             true,
@@ -203,6 +215,22 @@ impl Image {
             .collect()
     }
 
+    //每个layer单独一个2D view，用于渲染到数组纹理的某一层
+    pub fn create_layers_views(&self, aspect_mask: vk::ImageAspectFlags) -> Vec<vk::ImageView> {
+        (0..self.layers)
+            .map(|layer| {
+                create_image_layer_view(
+                    self.context.device(),
+                    self.image,
+                    layer,
+                    self.mip_levels,
+                    self.format,
+                    aspect_mask,
+                )
+            })
+            .collect()
+    }
+
     pub fn transition_image_layout(
         &self,
         old_layout: vk::ImageLayout,
@@ -236,7 +264,42 @@ impl Image {
         old_layout: vk::ImageLayout,
         new_layout: vk::ImageLayout,
     ) {
-        let barrier = self.get_barrier(base_mip_level, level_count, old_layout, new_layout);
+        let barrier = self.get_barrier(
+            base_mip_level,
+            level_count,
+            0,
+            self.layers,
+            old_layout,
+            new_layout,
+        );
+
+        let dependency_info =
+            vk::DependencyInfo::builder().image_memory_barriers(std::slice::from_ref(&barrier));
+
+        unsafe {
+            self.context
+                .synchronization2()
+                .cmd_pipeline_barrier2(command_buffer, &dependency_info)
+        };
+    }
+
+    //只转换部分layer，比如逐面渲染cubemap或者逐级联渲染阴影贴图
+    pub fn cmd_transition_image_layers_layout(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        base_array_layer: u32,
+        layer_count: u32,
+        old_layout: vk::ImageLayout,
+        new_layout: vk::ImageLayout,
+    ) {
+        let barrier = self.get_barrier(
+            0,
+            self.mip_levels,
+            base_array_layer,
+            layer_count,
+            old_layout,
+            new_layout,
+        );
 
         let dependency_info =
             vk::DependencyInfo::builder().image_memory_barriers(std::slice::from_ref(&barrier));
@@ -252,129 +315,14 @@ impl Image {
         &self,
         base_mip_level: u32,
         level_count: u32,
+        base_array_layer: u32,
+        layer_count: u32,
         old_layout: vk::ImageLayout,
         new_layout: vk::ImageLayout,
     ) -> vk::ImageMemoryBarrier2 {
         let (src_access_mask, dst_access_mask, src_stage, dst_stage) =
-            match (old_layout, new_layout) {
-                (vk::ImageLayout::UNDEFINED, vk::ImageLayout::TRANSFER_DST_OPTIMAL) => (
-                    vk::AccessFlags2::NONE,
-                    vk::AccessFlags2::TRANSFER_WRITE,
-                    vk::PipelineStageFlags2::NONE,
-                    vk::PipelineStageFlags2::TRANSFER,
-                ),
-                (
-                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
-                ) => (
-                    vk::AccessFlags2::TRANSFER_WRITE,
-                    vk::AccessFlags2::SHADER_READ,
-                    vk::PipelineStageFlags2::TRANSFER,
-                    vk::PipelineStageFlags2::FRAGMENT_SHADER,
-                ),
-                (vk::ImageLayout::UNDEFINED, vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL) => {
-                    (
-                        vk::AccessFlags2::NONE,
-                        vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_READ
-                            | vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE,
-                        vk::PipelineStageFlags2::NONE,
-                        vk::PipelineStageFlags2::EARLY_FRAGMENT_TESTS,
-                    )
-                }
-                (vk::ImageLayout::UNDEFINED, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL) => (
-                    vk::AccessFlags2::NONE,
-                    vk::AccessFlags2::COLOR_ATTACHMENT_READ
-                        | vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
-                    vk::PipelineStageFlags2::NONE,
-                    vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
-                ),
-                (
-                    vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
-                ) => (
-                    vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
-                    vk::AccessFlags2::SHADER_READ,
-                    vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
-                    vk::PipelineStageFlags2::FRAGMENT_SHADER,
-                ),
-                (
-                    vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-                ) => (
-                    vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
-                    vk::AccessFlags2::TRANSFER_WRITE,
-                    vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
-                    vk::PipelineStageFlags2::TRANSFER,
-                ),
-                (vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL, vk::ImageLayout::PRESENT_SRC_KHR) => (
-                    vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
-                    vk::AccessFlags2::COLOR_ATTACHMENT_READ,
-                    vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
-                    vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
-                ),
-                (
-                    vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
-                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
-                ) => (
-                    vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE,
-                    vk::AccessFlags2::SHADER_READ,
-                    vk::PipelineStageFlags2::EARLY_FRAGMENT_TESTS
-                        | vk::PipelineStageFlags2::LATE_FRAGMENT_TESTS,
-                    vk::PipelineStageFlags2::FRAGMENT_SHADER,
-                ),
-                (vk::ImageLayout::UNDEFINED, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL) => (
-                    vk::AccessFlags2::NONE,
-                    vk::AccessFlags2::SHADER_READ,
-                    vk::PipelineStageFlags2::NONE,
-                    vk::PipelineStageFlags2::FRAGMENT_SHADER,
-                ),
-                (
-                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
-                    vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-                ) => (
-                    vk::AccessFlags2::SHADER_READ,
-                    vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
-                    vk::PipelineStageFlags2::FRAGMENT_SHADER,
-                    vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
-                ),
-                (vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageLayout::TRANSFER_SRC_OPTIMAL) => (
-                    vk::AccessFlags2::TRANSFER_WRITE,
-                    vk::AccessFlags2::TRANSFER_READ,
-                    vk::PipelineStageFlags2::TRANSFER,
-                    vk::PipelineStageFlags2::TRANSFER,
-                ),
-                (
-                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
-                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
-                ) => (
-                    vk::AccessFlags2::TRANSFER_WRITE,
-                    vk::AccessFlags2::SHADER_READ,
-                    vk::PipelineStageFlags2::TRANSFER,
-                    vk::PipelineStageFlags2::FRAGMENT_SHADER,
-                ),
-                _ => {
-                    log::warn!("未定义的布局转换{old_layout:?} -> {new_layout:?}");
-
-                    (
-                        vk::AccessFlags2::NONE,
-                        vk::AccessFlags2::NONE,
-                        vk::PipelineStageFlags2::NONE,
-                        vk::PipelineStageFlags2::NONE,
-                    )
-                }
-            };
-
-        let aspect_mask = if new_layout == vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL
-            || old_layout == vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL
-        {
-            let mut mask = vk::ImageAspectFlags::DEPTH;
-            if has_stencil_component(self.format) {
-                mask |= vk::ImageAspectFlags::STENCIL;
-            }
-            mask
-        } else {
-            vk::ImageAspectFlags::COLOR
-        };
+            layout_transition_masks(old_layout, new_layout);
+        let aspect_mask = transition_aspect_mask(self.format, old_layout, new_layout);
 
         vk::ImageMemoryBarrier2::builder()
             .src_stage_mask(src_stage)
@@ -388,8 +336,8 @@ impl Image {
                 aspect_mask,
                 base_mip_level,
                 level_count,
-                base_array_layer: 0,
-                layer_count: self.layers,
+                base_array_layer,
+                layer_count,
             })
             .build()
     }
@@ -435,6 +383,137 @@ impl Image {
         }
     }
 
+    //image需要处于TRANSFER_SRC_OPTIMAL布局，只拷贝第0层mip
+    pub fn cmd_copy_to_buffer(&self, command_buffer: vk::CommandBuffer, buffer: &Buffer) {
+        let region = vk::BufferImageCopy::builder()
+            .buffer_offset(0)
+            .buffer_row_length(0)
+            .buffer_image_height(0)
+            .image_subresource(vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: self.layers,
+            })
+            .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+            .image_extent(self.extent)
+            .build();
+        let regions = [region];
+        unsafe {
+            self.context.device().cmd_copy_image_to_buffer(
+                command_buffer,
+                self.image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                buffer.buffer,
+                &regions,
+            )
+        }
+    }
+
+    //只拷贝第0层mip上(x, y)处的一个像素，image需要处于TRANSFER_SRC_OPTIMAL布局
+    pub fn cmd_copy_texel_to_buffer(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        buffer: &Buffer,
+        x: u32,
+        y: u32,
+    ) {
+        let region = vk::BufferImageCopy::builder()
+            .buffer_offset(0)
+            .buffer_row_length(0)
+            .buffer_image_height(0)
+            .image_subresource(vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1,
+            })
+            .image_offset(vk::Offset3D {
+                x: x as i32,
+                y: y as i32,
+                z: 0,
+            })
+            .image_extent(vk::Extent3D {
+                width: 1,
+                height: 1,
+                depth: 1,
+            })
+            .build();
+        unsafe {
+            self.context.device().cmd_copy_image_to_buffer(
+                command_buffer,
+                self.image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                buffer.buffer,
+                std::slice::from_ref(&region),
+            )
+        }
+    }
+
+    //拷贝一层mip的所有layer，image需要处于TRANSFER_SRC_OPTIMAL布局
+    pub fn cmd_copy_mip_to_buffer(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        buffer: &Buffer,
+        mip_level: u32,
+        buffer_offset: vk::DeviceSize,
+    ) {
+        let region = self.mip_copy_region(mip_level, buffer_offset);
+        unsafe {
+            self.context.device().cmd_copy_image_to_buffer(
+                command_buffer,
+                self.image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                buffer.buffer,
+                std::slice::from_ref(&region),
+            )
+        }
+    }
+
+    //填充一层mip的所有layer，image需要处于TRANSFER_DST_OPTIMAL布局
+    pub fn cmd_copy_buffer_to_mip(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        buffer: &Buffer,
+        mip_level: u32,
+        buffer_offset: vk::DeviceSize,
+    ) {
+        let region = self.mip_copy_region(mip_level, buffer_offset);
+        unsafe {
+            self.context.device().cmd_copy_buffer_to_image(
+                command_buffer,
+                buffer.buffer,
+                self.image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                std::slice::from_ref(&region),
+            )
+        }
+    }
+
+    fn mip_copy_region(
+        &self,
+        mip_level: u32,
+        buffer_offset: vk::DeviceSize,
+    ) -> vk::BufferImageCopy {
+        vk::BufferImageCopy::builder()
+            .buffer_offset(buffer_offset)
+            .buffer_row_length(0)
+            .buffer_image_height(0)
+            .image_subresource(vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level,
+                base_array_layer: 0,
+                layer_count: self.layers,
+            })
+            .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+            .image_extent(vk::Extent3D {
+                width: (self.extent.width >> mip_level).max(1),
+                height: (self.extent.height >> mip_level).max(1),
+                depth: 1,
+            })
+            .build()
+    }
+
     pub fn cmd_copy(
         &self,
         command_buffer: vk::CommandBuffer,
@@ -592,13 +671,200 @@ impl Drop for Image {
             if !self.managed {
                 self.context.device().destroy_image(self.image, None);
             }
-            if let Some(memory) = self.memory {
+            if let Some((memory, size)) = self.memory {
                 self.context.device().free_memory(memory, None);
+                self.context.track_free(size);
             }
         }
     }
 }
 
+//各种布局转换需要等待的写入和之后的访问
+fn layout_transition_masks(
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+) -> (
+    vk::AccessFlags2,
+    vk::AccessFlags2,
+    vk::PipelineStageFlags2,
+    vk::PipelineStageFlags2,
+) {
+    match (old_layout, new_layout) {
+        (vk::ImageLayout::UNDEFINED, vk::ImageLayout::TRANSFER_DST_OPTIMAL) => (
+            vk::AccessFlags2::NONE,
+            vk::AccessFlags2::TRANSFER_WRITE,
+            vk::PipelineStageFlags2::NONE,
+            vk::PipelineStageFlags2::TRANSFER,
+        ),
+        (vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL) => (
+            vk::AccessFlags2::TRANSFER_WRITE,
+            vk::AccessFlags2::SHADER_READ,
+            vk::PipelineStageFlags2::TRANSFER,
+            vk::PipelineStageFlags2::FRAGMENT_SHADER,
+        ),
+        (vk::ImageLayout::UNDEFINED, vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL) => (
+            vk::AccessFlags2::NONE,
+            vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_READ
+                | vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            vk::PipelineStageFlags2::NONE,
+            vk::PipelineStageFlags2::EARLY_FRAGMENT_TESTS,
+        ),
+        (vk::ImageLayout::UNDEFINED, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL) => (
+            vk::AccessFlags2::NONE,
+            vk::AccessFlags2::COLOR_ATTACHMENT_READ | vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
+            vk::PipelineStageFlags2::NONE,
+            vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+        ),
+        (vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL) => (
+            vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
+            vk::AccessFlags2::SHADER_READ,
+            vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+            vk::PipelineStageFlags2::FRAGMENT_SHADER,
+        ),
+        (vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL, vk::ImageLayout::TRANSFER_DST_OPTIMAL) => (
+            vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
+            vk::AccessFlags2::TRANSFER_WRITE,
+            vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+            vk::PipelineStageFlags2::TRANSFER,
+        ),
+        (vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL, vk::ImageLayout::TRANSFER_SRC_OPTIMAL) => (
+            vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
+            vk::AccessFlags2::TRANSFER_READ,
+            vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+            vk::PipelineStageFlags2::TRANSFER,
+        ),
+        //呈现由提交时的信号量同步，barrier只需要让写入可见
+        (vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL, vk::ImageLayout::PRESENT_SRC_KHR) => (
+            vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
+            vk::AccessFlags2::NONE,
+            vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+            vk::PipelineStageFlags2::NONE,
+        ),
+        (
+            vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        ) => (
+            vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            vk::AccessFlags2::SHADER_READ,
+            vk::PipelineStageFlags2::EARLY_FRAGMENT_TESTS
+                | vk::PipelineStageFlags2::LATE_FRAGMENT_TESTS,
+            vk::PipelineStageFlags2::FRAGMENT_SHADER,
+        ),
+        (
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+        ) => (
+            vk::AccessFlags2::SHADER_READ,
+            vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_READ
+                | vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            vk::PipelineStageFlags2::FRAGMENT_SHADER,
+            vk::PipelineStageFlags2::EARLY_FRAGMENT_TESTS,
+        ),
+        (
+            vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+        ) => (
+            vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_READ | vk::AccessFlags2::SHADER_READ,
+            vk::PipelineStageFlags2::EARLY_FRAGMENT_TESTS
+                | vk::PipelineStageFlags2::LATE_FRAGMENT_TESTS,
+            vk::PipelineStageFlags2::EARLY_FRAGMENT_TESTS
+                | vk::PipelineStageFlags2::FRAGMENT_SHADER,
+        ),
+        (
+            vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        ) => (
+            vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            vk::AccessFlags2::TRANSFER_READ,
+            vk::PipelineStageFlags2::EARLY_FRAGMENT_TESTS
+                | vk::PipelineStageFlags2::LATE_FRAGMENT_TESTS,
+            vk::PipelineStageFlags2::TRANSFER,
+        ),
+        (vk::ImageLayout::UNDEFINED, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL) => (
+            vk::AccessFlags2::NONE,
+            vk::AccessFlags2::SHADER_READ,
+            vk::PipelineStageFlags2::NONE,
+            vk::PipelineStageFlags2::FRAGMENT_SHADER,
+        ),
+        (vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL) => (
+            vk::AccessFlags2::SHADER_READ,
+            vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
+            vk::PipelineStageFlags2::FRAGMENT_SHADER,
+            vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+        ),
+        (vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageLayout::TRANSFER_SRC_OPTIMAL) => (
+            vk::AccessFlags2::TRANSFER_WRITE,
+            vk::AccessFlags2::TRANSFER_READ,
+            vk::PipelineStageFlags2::TRANSFER,
+            vk::PipelineStageFlags2::TRANSFER,
+        ),
+        //之前只读不写，等传输完成即可，没有需要可见的写入
+        (vk::ImageLayout::TRANSFER_SRC_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL) => (
+            vk::AccessFlags2::NONE,
+            vk::AccessFlags2::SHADER_READ,
+            vk::PipelineStageFlags2::TRANSFER,
+            vk::PipelineStageFlags2::FRAGMENT_SHADER,
+        ),
+        (vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, vk::ImageLayout::TRANSFER_SRC_OPTIMAL) => (
+            vk::AccessFlags2::SHADER_READ,
+            vk::AccessFlags2::TRANSFER_READ,
+            vk::PipelineStageFlags2::FRAGMENT_SHADER,
+            vk::PipelineStageFlags2::TRANSFER,
+        ),
+        //截图拷贝完再呈现，同样由信号量同步
+        (vk::ImageLayout::TRANSFER_SRC_OPTIMAL, vk::ImageLayout::PRESENT_SRC_KHR) => (
+            vk::AccessFlags2::NONE,
+            vk::AccessFlags2::NONE,
+            vk::PipelineStageFlags2::TRANSFER,
+            vk::PipelineStageFlags2::NONE,
+        ),
+        //两边都为NONE时没有任何依赖，未知的转换退化为最保守的全屏障
+        _ => {
+            log::warn!("未定义的布局转换{old_layout:?} -> {new_layout:?}");
+
+            (
+                vk::AccessFlags2::MEMORY_READ | vk::AccessFlags2::MEMORY_WRITE,
+                vk::AccessFlags2::MEMORY_READ | vk::AccessFlags2::MEMORY_WRITE,
+                vk::PipelineStageFlags2::ALL_COMMANDS,
+                vk::PipelineStageFlags2::ALL_COMMANDS,
+            )
+        }
+    }
+}
+
+fn transition_aspect_mask(
+    format: vk::Format,
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+) -> vk::ImageAspectFlags {
+    //深度图在SHADER_READ_ONLY等通用布局之间转换时也要用深度aspect
+    if is_depth_format(format)
+        || new_layout == vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL
+        || old_layout == vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL
+    {
+        let mut mask = vk::ImageAspectFlags::DEPTH;
+        if has_stencil_component(format) {
+            mask |= vk::ImageAspectFlags::STENCIL;
+        }
+        mask
+    } else {
+        vk::ImageAspectFlags::COLOR
+    }
+}
+
+fn is_depth_format(format: vk::Format) -> bool {
+    matches!(
+        format,
+        vk::Format::D16_UNORM
+            | vk::Format::X8_D24_UNORM_PACK32
+            | vk::Format::D32_SFLOAT
+            | vk::Format::D16_UNORM_S8_UINT
+            | vk::Format::D24_UNORM_S8_UINT
+            | vk::Format::D32_SFLOAT_S8_UINT
+    )
+}
+
 fn has_stencil_component(format: vk::Format) -> bool {
     format == vk::Format::D32_SFLOAT_S8_UINT || format == vk::Format::D24_UNORM_S8_UINT
 }
@@ -632,6 +898,33 @@ pub fn create_image_view(
     }
 }
 
+pub fn create_image_layer_view(
+    device: &Device,
+    image: vk::Image,
+    layer: u32,
+    mip_levels: u32,
+    format: vk::Format,
+    aspect_mask: vk::ImageAspectFlags,
+) -> vk::ImageView {
+    let create_info = vk::ImageViewCreateInfo::builder()
+        .image(image)
+        .view_type(vk::ImageViewType::TYPE_2D)
+        .format(format)
+        .subresource_range(vk::ImageSubresourceRange {
+            aspect_mask,
+            base_mip_level: 0,
+            level_count: mip_levels,
+            base_array_layer: layer,
+            layer_count: 1,
+        });
+
+    unsafe {
+        device
+            .create_image_view(&create_info, None)
+            .expect("创建image view失败！")
+    }
+}
+
 pub struct LayoutTransition<'a> {
     pub image: &'a Image,
     pub old_layout: vk::ImageLayout,
@@ -680,8 +973,14 @@ pub fn cmd_transition_images_layouts(
             let base_mip_level = t.mips_range.first();
             let level_count = t.mips_range.count().unwrap_or(t.image.mip_levels);
 
-            t.image
-                .get_barrier(base_mip_level, level_count, t.old_layout, t.new_layout)
+            t.image.get_barrier(
+                base_mip_level,
+                level_count,
+                0,
+                t.image.layers,
+                t.old_layout,
+                t.new_layout,
+            )
         })
         .collect::<Vec<_>>();
 
@@ -693,3 +992,193 @@ pub fn cmd_transition_images_layouts(
             .cmd_pipeline_barrier2(command_buffer, &dependency_info)
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::{assert_no_validation_errors, test_context};
+    use std::ffi::CString;
+
+    //表中没有的转换会退化为ALL_COMMANDS全屏障
+    fn is_explicit(old_layout: vk::ImageLayout, new_layout: vk::ImageLayout) -> bool {
+        let (_, _, src_stage, dst_stage) = layout_transition_masks(old_layout, new_layout);
+        src_stage != vk::PipelineStageFlags2::ALL_COMMANDS
+            && dst_stage != vk::PipelineStageFlags2::ALL_COMMANDS
+    }
+
+    #[test]
+    fn depth_transitions_are_in_the_table() {
+        use vk::ImageLayout as L;
+        for (old_layout, new_layout) in [
+            (
+                L::SHADER_READ_ONLY_OPTIMAL,
+                L::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            ),
+            (
+                L::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                L::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+            ),
+            (L::DEPTH_STENCIL_ATTACHMENT_OPTIMAL, L::TRANSFER_SRC_OPTIMAL),
+            (
+                L::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                L::SHADER_READ_ONLY_OPTIMAL,
+            ),
+        ] {
+            assert!(is_explicit(old_layout, new_layout));
+        }
+        assert!(!is_explicit(L::GENERAL, L::PREINITIALIZED));
+    }
+
+    #[test]
+    fn depth_formats_use_depth_aspect_in_any_layout() {
+        use vk::ImageLayout as L;
+        let (read, src) = (L::SHADER_READ_ONLY_OPTIMAL, L::TRANSFER_SRC_OPTIMAL);
+        assert_eq!(
+            transition_aspect_mask(vk::Format::D32_SFLOAT, read, src),
+            vk::ImageAspectFlags::DEPTH
+        );
+        assert_eq!(
+            transition_aspect_mask(vk::Format::D24_UNORM_S8_UINT, read, src),
+            vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+        );
+        assert_eq!(
+            transition_aspect_mask(vk::Format::R8G8B8A8_UNORM, read, src),
+            vk::ImageAspectFlags::COLOR
+        );
+    }
+
+    #[test]
+    fn cubemap_faces_transition_one_layer_at_a_time() {
+        use vk::ImageLayout as L;
+        let Some(context) = test_context() else {
+            return;
+        };
+        let image = Image::create(
+            Arc::clone(&context),
+            ImageParameters {
+                mem_properties: vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                extent: vk::Extent2D {
+                    width: 16,
+                    height: 16,
+                },
+                layers: 6,
+                format: vk::Format::D32_SFLOAT,
+                usage: vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT
+                    | vk::ImageUsageFlags::SAMPLED
+                    | vk::ImageUsageFlags::TRANSFER_SRC,
+                create_flags: vk::ImageCreateFlags::CUBE_COMPATIBLE,
+                ..Default::default()
+            },
+            CString::new("Test.Cubemap").unwrap(),
+        )
+        .unwrap();
+
+        context.execute_one_time_commands(|command_buffer| {
+            image.cmd_transition_image_layout(
+                command_buffer,
+                L::UNDEFINED,
+                L::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            );
+            for face in 0..6 {
+                image.cmd_transition_image_layers_layout(
+                    command_buffer,
+                    face,
+                    1,
+                    L::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                    L::SHADER_READ_ONLY_OPTIMAL,
+                );
+            }
+            image.cmd_transition_image_layout(
+                command_buffer,
+                L::SHADER_READ_ONLY_OPTIMAL,
+                L::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            );
+            image.cmd_transition_image_layout(
+                command_buffer,
+                L::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                L::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+            );
+        });
+        context.graphics_queue_wait_idle();
+
+        drop(image);
+        assert_no_validation_errors();
+    }
+
+    #[test]
+    fn present_and_read_only_transitions_have_tight_masks() {
+        use vk::ImageLayout as L;
+        let (src_access, dst_access, _, dst_stage) =
+            layout_transition_masks(L::COLOR_ATTACHMENT_OPTIMAL, L::PRESENT_SRC_KHR);
+        assert_eq!(src_access, vk::AccessFlags2::COLOR_ATTACHMENT_WRITE);
+        assert_eq!(dst_access, vk::AccessFlags2::NONE);
+        assert_eq!(dst_stage, vk::PipelineStageFlags2::NONE);
+
+        //之前只读，没有需要可见的写入
+        let (src_access, ..) =
+            layout_transition_masks(L::TRANSFER_SRC_OPTIMAL, L::SHADER_READ_ONLY_OPTIMAL);
+        assert_eq!(src_access, vk::AccessFlags2::NONE);
+        let (src_access, dst_access, src_stage, dst_stage) =
+            layout_transition_masks(L::TRANSFER_SRC_OPTIMAL, L::PRESENT_SRC_KHR);
+        assert_eq!(src_access, vk::AccessFlags2::NONE);
+        assert_eq!(dst_access, vk::AccessFlags2::NONE);
+        assert_eq!(src_stage, vk::PipelineStageFlags2::TRANSFER);
+        assert_eq!(dst_stage, vk::PipelineStageFlags2::NONE);
+
+        //未知的转换必须是全屏障，NONE/NONE在Synchronization2中表示没有依赖
+        let (src_access, dst_access, src_stage, dst_stage) =
+            layout_transition_masks(L::GENERAL, L::PREINITIALIZED);
+        assert_eq!(src_stage, vk::PipelineStageFlags2::ALL_COMMANDS);
+        assert_eq!(dst_stage, vk::PipelineStageFlags2::ALL_COMMANDS);
+        assert!(src_access.contains(vk::AccessFlags2::MEMORY_WRITE));
+        assert!(dst_access.contains(vk::AccessFlags2::MEMORY_READ));
+    }
+
+    #[test]
+    fn color_image_walks_through_layouts_without_validation_errors() {
+        use vk::ImageLayout as L;
+        let layouts = [
+            L::UNDEFINED,
+            L::TRANSFER_DST_OPTIMAL,
+            L::TRANSFER_SRC_OPTIMAL,
+            L::SHADER_READ_ONLY_OPTIMAL,
+            L::COLOR_ATTACHMENT_OPTIMAL,
+            L::TRANSFER_SRC_OPTIMAL,
+        ];
+        for pair in layouts.windows(2) {
+            assert!(is_explicit(pair[0], pair[1]), "{:?}", pair);
+        }
+
+        let Some(context) = test_context() else {
+            return;
+        };
+        let image = Image::create(
+            Arc::clone(&context),
+            ImageParameters {
+                mem_properties: vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                extent: vk::Extent2D {
+                    width: 16,
+                    height: 16,
+                },
+                format: vk::Format::R8G8B8A8_UNORM,
+                usage: vk::ImageUsageFlags::COLOR_ATTACHMENT
+                    | vk::ImageUsageFlags::SAMPLED
+                    | vk::ImageUsageFlags::TRANSFER_SRC
+                    | vk::ImageUsageFlags::TRANSFER_DST,
+                ..Default::default()
+            },
+            CString::new("Test.Color").unwrap(),
+        )
+        .unwrap();
+
+        context.execute_one_time_commands(|command_buffer| {
+            for pair in layouts.windows(2) {
+                image.cmd_transition_image_layout(command_buffer, pair[0], pair[1]);
+            }
+        });
+        context.graphics_queue_wait_idle();
+
+        drop(image);
+        assert_no_validation_errors();
+    }
+}
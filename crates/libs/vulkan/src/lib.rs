@@ -2,9 +2,12 @@ mod buffer;
 mod context;
 mod debug;
 mod descriptor;
+mod error;
 mod image;
 mod msaa;
 mod pipeline;
+mod query;
+mod rendering;
 mod shader;
 mod swapchain;
 mod texture;
@@ -12,8 +15,8 @@ mod util;
 mod vertex;
 
 pub use self::{
-    buffer::*, context::*, debug::*, descriptor::*, image::*, msaa::*, pipeline::*, shader::*,
-    swapchain::*, texture::*, util::*, vertex::*,
+    buffer::*, context::*, debug::*, descriptor::*, error::*, image::*, msaa::*, pipeline::*,
+    query::*, rendering::*, shader::*, swapchain::*, texture::*, util::*, vertex::*,
 };
 
 pub use ash;
@@ -1,14 +1,61 @@
-use super::{Context, ShaderModule, Vertex};
-use ash::vk;
+use super::{Context, ShaderModule, Vertex, VkError};
+use ash::{vk, Device};
 use std::{ffi::CString, sync::Arc};
 
+//viewport和scissor在录制时设置，渲染目标尺寸变化时不需要重建pipeline
+pub const DYNAMIC_VIEWPORT_STATES: [vk::DynamicState; 2] =
+    [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+
+//多重采样相关的管线状态，默认关闭sample shading和alpha to coverage
+//sample shading让片元着色器按样本执行，开销约为采样数乘以比例，只在高频着色走样明显时开启
+//alpha to coverage只在MSAA下生效，按alpha生成覆盖掩码，植被等镂空材质边缘更平滑且几乎没有额外开销
+#[derive(Copy, Clone, Debug)]
+pub struct MultisampleParameters {
+    pub samples: vk::SampleCountFlags,
+    //None表示关闭sample shading，Some为最小着色比例（0~1）
+    pub min_sample_shading: Option<f32>,
+    pub alpha_to_coverage: bool,
+}
+
+impl Default for MultisampleParameters {
+    fn default() -> Self {
+        Self {
+            samples: vk::SampleCountFlags::TYPE_1,
+            min_sample_shading: None,
+            alpha_to_coverage: false,
+        }
+    }
+}
+
+impl MultisampleParameters {
+    pub fn new(samples: vk::SampleCountFlags) -> Self {
+        Self {
+            samples,
+            ..Default::default()
+        }
+    }
+
+    pub fn create_info(&self) -> vk::PipelineMultisampleStateCreateInfo {
+        vk::PipelineMultisampleStateCreateInfo::builder()
+            .sample_shading_enable(self.min_sample_shading.is_some())
+            .rasterization_samples(self.samples)
+            .min_sample_shading(self.min_sample_shading.unwrap_or(1.0).clamp(0.0, 1.0))
+            .alpha_to_coverage_enable(self.alpha_to_coverage)
+            .alpha_to_one_enable(false)
+            .build()
+    }
+}
+
 #[derive(Copy, Clone)]
 pub struct PipelineParameters<'a> {
+    pub name: &'a str,
     pub vertex_shader_params: ShaderParameters<'a>,
     pub fragment_shader_params: ShaderParameters<'a>,
     pub multisampling_info: &'a vk::PipelineMultisampleStateCreateInfo,
     pub viewport_info: &'a vk::PipelineViewportStateCreateInfo,
     pub rasterizer_info: &'a vk::PipelineRasterizationStateCreateInfo,
+    pub polygon_mode: vk::PolygonMode,
+    pub topology: vk::PrimitiveTopology,
     pub dynamic_state_info: Option<&'a vk::PipelineDynamicStateCreateInfo>,
     pub depth_stencil_info: Option<&'a vk::PipelineDepthStencilStateCreateInfo>,
     pub color_blend_attachments: &'a [vk::PipelineColorBlendAttachmentState],
@@ -22,7 +69,7 @@ pub struct PipelineParameters<'a> {
 pub fn create_pipeline<V: Vertex>(
     context: &Arc<Context>,
     params: PipelineParameters,
-) -> vk::Pipeline {
+) -> Result<vk::Pipeline, VkError> {
     let entry_point_name = CString::new("main").unwrap();
 
     let (_vertex_shader_module, vertex_shader_state_info) = create_shader_stage_info(
@@ -48,7 +95,7 @@ pub fn create_pipeline<V: Vertex>(
         .vertex_attribute_descriptions(&attributes_descs);
 
     let input_assembly_info = vk::PipelineInputAssemblyStateCreateInfo::builder()
-        .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+        .topology(params.topology)
         .primitive_restart_enable(false);
 
     let color_blending_info = vk::PipelineColorBlendStateCreateInfo::builder()
@@ -57,6 +104,18 @@ pub fn create_pipeline<V: Vertex>(
         .attachments(params.color_blend_attachments)
         .blend_constants([0.0, 0.0, 0.0, 0.0]);
 
+    let mut rasterizer_info = *params.rasterizer_info;
+    rasterizer_info.polygon_mode = context.get_supported_polygon_mode(params.polygon_mode);
+
+    //设备不支持sampleRateShading时关闭sample shading
+    let mut multisampling_info = *params.multisampling_info;
+    if multisampling_info.sample_shading_enable == vk::TRUE
+        && !context.is_sample_rate_shading_supported()
+    {
+        log::warn!("设备不支持sample shading，{}将关闭该选项", params.name);
+        multisampling_info.sample_shading_enable = vk::FALSE;
+    }
+
     let mut dynamic_rendering = vk::PipelineRenderingCreateInfo::builder()
         .color_attachment_formats(params.color_attachment_formats)
         .depth_attachment_format(params.depth_attachment_format.unwrap_or_default());
@@ -66,11 +125,20 @@ pub fn create_pipeline<V: Vertex>(
         .vertex_input_state(&vertex_input_info)
         .input_assembly_state(&input_assembly_info)
         .viewport_state(params.viewport_info)
-        .rasterization_state(params.rasterizer_info)
-        .multisample_state(params.multisampling_info)
+        .rasterization_state(&rasterizer_info)
+        .multisample_state(&multisampling_info)
         .color_blend_state(&color_blending_info)
-        .layout(params.layout)
-        .push_next(&mut dynamic_rendering);
+        .layout(params.layout);
+
+    //没有dynamic rendering时使用格式和采样数相同的render pass，兼容即可在任意同格式的渲染中使用
+    pipeline_info = match context.pipeline_render_pass(
+        params.color_attachment_formats,
+        params.depth_attachment_format,
+        multisampling_info.rasterization_samples,
+    ) {
+        Some(render_pass) => pipeline_info.render_pass(render_pass).subpass(0),
+        None => pipeline_info.push_next(&mut dynamic_rendering),
+    };
 
     if let Some(depth_stencil_info) = params.depth_stencil_info {
         pipeline_info = pipeline_info.depth_stencil_state(depth_stencil_info)
@@ -91,11 +159,71 @@ pub fn create_pipeline<V: Vertex>(
     let pipeline_info = pipeline_info.build();
     let pipeline_infos = [pipeline_info];
 
-    unsafe {
+    let pipeline = unsafe {
         context
             .device()
             .create_graphics_pipelines(vk::PipelineCache::null(), &pipeline_infos, None)
-            .expect("graphics pipeline创建失败！")[0]
+            .map_err(|(_, result)| result)?[0]
+    };
+    context.set_debug_name(pipeline, params.name);
+    Ok(pipeline)
+}
+
+pub fn create_compute_pipeline(
+    context: &Arc<Context>,
+    name: &str,
+    shader_params: ShaderParameters,
+    layout: vk::PipelineLayout,
+) -> Result<vk::Pipeline, VkError> {
+    let entry_point_name = CString::new("main").unwrap();
+
+    let (_shader_module, shader_state_info) = create_shader_stage_info(
+        context,
+        &entry_point_name,
+        vk::ShaderStageFlags::COMPUTE,
+        shader_params,
+    );
+
+    let pipeline_info = vk::ComputePipelineCreateInfo::builder()
+        .stage(shader_state_info)
+        .layout(layout)
+        .build();
+
+    let pipeline = unsafe {
+        context
+            .device()
+            .create_compute_pipelines(vk::PipelineCache::null(), &[pipeline_info], None)
+            .map_err(|(_, result)| result)?[0]
+    };
+    context.set_debug_name(pipeline, name);
+    Ok(pipeline)
+}
+
+//配合DYNAMIC_VIEWPORT_STATES使用，viewport和scissor覆盖整个extent
+pub fn cmd_set_viewport_and_scissor(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    extent: vk::Extent2D,
+) {
+    unsafe {
+        device.cmd_set_viewport(
+            command_buffer,
+            0,
+            &[vk::Viewport {
+                width: extent.width as _,
+                height: extent.height as _,
+                max_depth: 1.0,
+                ..Default::default()
+            }],
+        );
+        device.cmd_set_scissor(
+            command_buffer,
+            0,
+            &[vk::Rect2D {
+                extent,
+                ..Default::default()
+            }],
+        )
     }
 }
 
@@ -106,7 +234,10 @@ fn create_shader_stage_info(
     params: ShaderParameters,
 ) -> (ShaderModule, vk::PipelineShaderStageCreateInfo) {
     let extension = get_shader_file_extension(stage);
-    let shader_path = format!("crates/fate_renderer/shaders/{}.{}.spv", params.name, extension);
+    let shader_path = format!(
+        "crates/fate_renderer/shaders/{}.{}.spv",
+        params.name, extension
+    );
     let module = ShaderModule::new(Arc::clone(context), &shader_path);
 
     let mut stage_info = vk::PipelineShaderStageCreateInfo::builder()
@@ -125,6 +256,7 @@ fn get_shader_file_extension(stage: vk::ShaderStageFlags) -> &'static str {
     match stage {
         vk::ShaderStageFlags::VERTEX => "vert",
         vk::ShaderStageFlags::FRAGMENT => "frag",
+        vk::ShaderStageFlags::COMPUTE => "comp",
         _ => panic!("shader stage不支持！"),
     }
 }
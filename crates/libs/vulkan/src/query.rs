@@ -0,0 +1,200 @@
+use super::context::Context;
+use ash::vk;
+use std::sync::Arc;
+
+pub struct TimestampQueryPool {
+    context: Arc<Context>,
+    pool: vk::QueryPool,
+    query_count: u32,
+    //一个tick对应的纳秒数
+    timestamp_period: f32,
+}
+
+impl TimestampQueryPool {
+    //设备不支持在图形队列上写时间戳时返回None
+    pub fn create(context: Arc<Context>, query_count: u32) -> Option<Self> {
+        let limits = unsafe {
+            context
+                .instance()
+                .get_physical_device_properties(context.physical_device())
+                .limits
+        };
+        if limits.timestamp_compute_and_graphics == vk::FALSE {
+            log::warn!("设备不支持时间戳查询");
+            return None;
+        }
+
+        let create_info = vk::QueryPoolCreateInfo::builder()
+            .query_type(vk::QueryType::TIMESTAMP)
+            .query_count(query_count);
+        let pool = unsafe {
+            context
+                .device()
+                .create_query_pool(&create_info, None)
+                .expect("创建query pool失败！")
+        };
+
+        Some(Self {
+            context,
+            pool,
+            query_count,
+            timestamp_period: limits.timestamp_period,
+        })
+    }
+}
+
+impl TimestampQueryPool {
+    pub fn query_count(&self) -> u32 {
+        self.query_count
+    }
+
+    pub fn cmd_reset(&self, command_buffer: vk::CommandBuffer, first_query: u32, count: u32) {
+        unsafe {
+            self.context.device().cmd_reset_query_pool(
+                command_buffer,
+                self.pool,
+                first_query,
+                count,
+            )
+        };
+    }
+
+    pub fn cmd_write_timestamp(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        stage: vk::PipelineStageFlags2,
+        query: u32,
+    ) {
+        unsafe {
+            self.context.synchronization2().cmd_write_timestamp2(
+                command_buffer,
+                stage,
+                self.pool,
+                query,
+            )
+        };
+    }
+
+    //不等待GPU，还没写入的query返回None，结果单位为毫秒
+    pub fn get_results_ms(&self, first_query: u32, count: u32) -> Vec<Option<f64>> {
+        let mut data = vec![[0u64; 2]; count as usize];
+        let result = unsafe {
+            self.context.device().get_query_pool_results(
+                self.pool,
+                first_query,
+                count,
+                &mut data,
+                vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WITH_AVAILABILITY,
+            )
+        };
+        match result {
+            Ok(_) | Err(vk::Result::NOT_READY) => {}
+            Err(error) => panic!("读取query结果失败，由于{}", error),
+        }
+
+        data.iter()
+            .map(|[ticks, available]| {
+                (*available != 0)
+                    .then(|| *ticks as f64 * self.timestamp_period as f64 / 1_000_000.0)
+            })
+            .collect()
+    }
+}
+
+impl Drop for TimestampQueryPool {
+    fn drop(&mut self) {
+        unsafe { self.context.device().destroy_query_pool(self.pool, None) };
+    }
+}
+
+//遮挡查询，结果为通过深度测试的样本数
+pub struct OcclusionQueryPool {
+    context: Arc<Context>,
+    pool: vk::QueryPool,
+    query_count: u32,
+}
+
+impl OcclusionQueryPool {
+    pub fn create(context: Arc<Context>, query_count: u32) -> Self {
+        let create_info = vk::QueryPoolCreateInfo::builder()
+            .query_type(vk::QueryType::OCCLUSION)
+            .query_count(query_count);
+        let pool = unsafe {
+            context
+                .device()
+                .create_query_pool(&create_info, None)
+                .expect("创建query pool失败！")
+        };
+
+        Self {
+            context,
+            pool,
+            query_count,
+        }
+    }
+}
+
+impl OcclusionQueryPool {
+    pub fn query_count(&self) -> u32 {
+        self.query_count
+    }
+
+    pub fn cmd_reset(&self, command_buffer: vk::CommandBuffer, first_query: u32, count: u32) {
+        unsafe {
+            self.context.device().cmd_reset_query_pool(
+                command_buffer,
+                self.pool,
+                first_query,
+                count,
+            )
+        };
+    }
+
+    //不需要精确样本数时不加PRECISE标志，实现可以只返回是否可见
+    pub fn cmd_begin(&self, command_buffer: vk::CommandBuffer, query: u32) {
+        unsafe {
+            self.context.device().cmd_begin_query(
+                command_buffer,
+                self.pool,
+                query,
+                vk::QueryControlFlags::empty(),
+            )
+        };
+    }
+
+    pub fn cmd_end(&self, command_buffer: vk::CommandBuffer, query: u32) {
+        unsafe {
+            self.context
+                .device()
+                .cmd_end_query(command_buffer, self.pool, query)
+        };
+    }
+
+    //不等待GPU，还没写入的query返回None
+    pub fn get_results(&self, first_query: u32, count: u32) -> Vec<Option<u64>> {
+        let mut data = vec![[0u64; 2]; count as usize];
+        let result = unsafe {
+            self.context.device().get_query_pool_results(
+                self.pool,
+                first_query,
+                count,
+                &mut data,
+                vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WITH_AVAILABILITY,
+            )
+        };
+        match result {
+            Ok(_) | Err(vk::Result::NOT_READY) => {}
+            Err(error) => panic!("读取query结果失败，由于{}", error),
+        }
+
+        data.iter()
+            .map(|[samples, available]| (*available != 0).then_some(*samples))
+            .collect()
+    }
+}
+
+impl Drop for OcclusionQueryPool {
+    fn drop(&mut self) {
+        unsafe { self.context.device().destroy_query_pool(self.pool, None) };
+    }
+}
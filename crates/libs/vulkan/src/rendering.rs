@@ -0,0 +1,504 @@
+use super::Image;
+use ash::{
+    extensions::khr::{CreateRenderPass2, DynamicRendering},
+    vk, Device, Instance,
+};
+use std::{collections::HashMap, sync::Mutex};
+
+//一次渲染中的一个attachment
+//render pass路径需要格式和采样数来创建兼容的render pass，所以比RenderingAttachmentInfo多了这两项
+#[derive(Copy, Clone)]
+pub struct RenderingAttachment {
+    pub view: vk::ImageView,
+    pub format: vk::Format,
+    pub samples: vk::SampleCountFlags,
+    pub layout: vk::ImageLayout,
+    pub load_op: vk::AttachmentLoadOp,
+    pub store_op: vk::AttachmentStoreOp,
+    pub clear_value: vk::ClearValue,
+    pub resolve: Option<RenderingResolve>,
+}
+
+//多重采样attachment在渲染结束时resolve到的单采样view，格式与源attachment相同
+#[derive(Copy, Clone)]
+pub struct RenderingResolve {
+    pub view: vk::ImageView,
+    pub layout: vk::ImageLayout,
+    pub mode: vk::ResolveModeFlags,
+}
+
+impl RenderingAttachment {
+    //默认先清空再保存，清空值为0
+    pub fn new(image: &Image, view: vk::ImageView, layout: vk::ImageLayout) -> Self {
+        Self {
+            view,
+            format: image.format,
+            samples: image.sample_count,
+            layout,
+            load_op: vk::AttachmentLoadOp::CLEAR,
+            store_op: vk::AttachmentStoreOp::STORE,
+            clear_value: vk::ClearValue::default(),
+            resolve: None,
+        }
+    }
+
+    pub fn load_op(mut self, load_op: vk::AttachmentLoadOp) -> Self {
+        self.load_op = load_op;
+        self
+    }
+
+    pub fn store_op(mut self, store_op: vk::AttachmentStoreOp) -> Self {
+        self.store_op = store_op;
+        self
+    }
+
+    pub fn clear_color(mut self, color: [f32; 4]) -> Self {
+        self.clear_value = vk::ClearValue {
+            color: vk::ClearColorValue { float32: color },
+        };
+        self
+    }
+
+    pub fn clear_depth(mut self, depth: f32) -> Self {
+        self.clear_value = vk::ClearValue {
+            depth_stencil: vk::ClearDepthStencilValue { depth, stencil: 0 },
+        };
+        self
+    }
+
+    pub fn resolve(
+        mut self,
+        view: vk::ImageView,
+        layout: vk::ImageLayout,
+        mode: vk::ResolveModeFlags,
+    ) -> Self {
+        self.resolve = Some(RenderingResolve { view, layout, mode });
+        self
+    }
+
+    fn rendering_info(&self) -> vk::RenderingAttachmentInfo {
+        let mut info = vk::RenderingAttachmentInfo::builder()
+            .image_view(self.view)
+            .image_layout(self.layout)
+            .load_op(self.load_op)
+            .store_op(self.store_op)
+            .clear_value(self.clear_value);
+        if let Some(resolve) = self.resolve {
+            info = info
+                .resolve_mode(resolve.mode)
+                .resolve_image_view(resolve.view)
+                .resolve_image_layout(resolve.layout);
+        }
+        info.build()
+    }
+
+    fn key(&self) -> AttachmentKey {
+        AttachmentKey {
+            format: self.format,
+            samples: self.samples,
+            layout: self.layout,
+            load_op: self.load_op,
+            store_op: self.store_op,
+            resolve: self.resolve.map(|r| (r.layout, r.mode)),
+        }
+    }
+}
+
+//一次渲染的目标，对应cmd_begin_rendering或一个单subpass的render pass
+pub struct RenderingDesc<'a> {
+    pub extent: vk::Extent2D,
+    pub color_attachments: &'a [RenderingAttachment],
+    pub depth_attachment: Option<RenderingAttachment>,
+}
+
+impl RenderingDesc<'_> {
+    fn render_area(&self) -> vk::Rect2D {
+        vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent: self.extent,
+        }
+    }
+}
+
+//开始和结束渲染的两种实现，按设备是否支持dynamic rendering在创建设备时选择
+//两种实现都不做布局转换，attachment在开始前和结束后仍由调用方用barrier转换
+pub(crate) trait RenderingBackend: Send + Sync {
+    fn cmd_begin_rendering(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        desc: &RenderingDesc,
+    );
+
+    fn cmd_end_rendering(&self, device: &Device, command_buffer: vk::CommandBuffer);
+
+    //创建pipeline时使用的兼容render pass，dynamic rendering不需要
+    fn pipeline_render_pass(
+        &self,
+        device: &Device,
+        color_formats: &[vk::Format],
+        depth_format: Option<vk::Format>,
+        samples: vk::SampleCountFlags,
+    ) -> Option<vk::RenderPass>;
+
+    //image view销毁前调用，释放引用它的缓存对象
+    fn release_image_view(&self, device: &Device, view: vk::ImageView);
+
+    fn destroy(&self, device: &Device);
+}
+
+pub(crate) struct DynamicRenderingBackend {
+    loader: DynamicRendering,
+}
+
+impl DynamicRenderingBackend {
+    pub(crate) fn new(instance: &Instance, device: &Device) -> Self {
+        Self {
+            loader: DynamicRendering::new(instance, device),
+        }
+    }
+}
+
+impl RenderingBackend for DynamicRenderingBackend {
+    fn cmd_begin_rendering(
+        &self,
+        _device: &Device,
+        command_buffer: vk::CommandBuffer,
+        desc: &RenderingDesc,
+    ) {
+        let color_infos = desc
+            .color_attachments
+            .iter()
+            .map(RenderingAttachment::rendering_info)
+            .collect::<Vec<_>>();
+        let depth_info = desc.depth_attachment.map(|a| a.rendering_info());
+
+        let mut rendering_info = vk::RenderingInfo::builder()
+            .color_attachments(&color_infos)
+            .layer_count(1)
+            .render_area(desc.render_area());
+        if let Some(depth_info) = depth_info.as_ref() {
+            rendering_info = rendering_info.depth_attachment(depth_info);
+        }
+
+        unsafe {
+            self.loader
+                .cmd_begin_rendering(command_buffer, &rendering_info)
+        };
+    }
+
+    fn cmd_end_rendering(&self, _device: &Device, command_buffer: vk::CommandBuffer) {
+        unsafe { self.loader.cmd_end_rendering(command_buffer) };
+    }
+
+    fn pipeline_render_pass(
+        &self,
+        _device: &Device,
+        _color_formats: &[vk::Format],
+        _depth_format: Option<vk::Format>,
+        _samples: vk::SampleCountFlags,
+    ) -> Option<vk::RenderPass> {
+        None
+    }
+
+    fn release_image_view(&self, _device: &Device, _view: vk::ImageView) {}
+
+    fn destroy(&self, _device: &Device) {}
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct AttachmentKey {
+    format: vk::Format,
+    samples: vk::SampleCountFlags,
+    layout: vk::ImageLayout,
+    load_op: vk::AttachmentLoadOp,
+    store_op: vk::AttachmentStoreOp,
+    resolve: Option<(vk::ImageLayout, vk::ResolveModeFlags)>,
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct RenderPassKey {
+    colors: Vec<AttachmentKey>,
+    depth: Option<AttachmentKey>,
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct FramebufferKey {
+    render_pass: vk::RenderPass,
+    views: Vec<vk::ImageView>,
+    extent: (u32, u32),
+}
+
+//没有dynamic rendering时使用单subpass的render pass
+//render pass按attachment格式、采样数和读写方式缓存；framebuffer按view缓存，view销毁时一起销毁
+pub(crate) struct RenderPassBackend {
+    loader: CreateRenderPass2,
+    render_passes: Mutex<HashMap<RenderPassKey, vk::RenderPass>>,
+    framebuffers: Mutex<HashMap<FramebufferKey, vk::Framebuffer>>,
+}
+
+impl RenderPassBackend {
+    pub(crate) fn new(instance: &Instance, device: &Device) -> Self {
+        Self {
+            loader: CreateRenderPass2::new(instance, device),
+            render_passes: Mutex::new(HashMap::new()),
+            framebuffers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn get_render_pass(&self, key: RenderPassKey) -> vk::RenderPass {
+        let mut render_passes = self.render_passes.lock().unwrap_or_else(|e| e.into_inner());
+        *render_passes
+            .entry(key)
+            .or_insert_with_key(|key| self.create_render_pass(key))
+    }
+
+    //attachment顺序: 颜色、颜色resolve、深度、深度resolve，framebuffer和清空值使用相同顺序
+    fn create_render_pass(&self, key: &RenderPassKey) -> vk::RenderPass {
+        let mut descriptions = Vec::new();
+        let mut color_refs = Vec::new();
+        for color in key.colors.iter() {
+            color_refs.push(attachment_reference(
+                descriptions.len(),
+                color.layout,
+                vk::ImageAspectFlags::COLOR,
+            ));
+            descriptions.push(attachment_description(color));
+        }
+
+        let mut resolve_refs = Vec::new();
+        if key.colors.iter().any(|c| c.resolve.is_some()) {
+            for color in key.colors.iter() {
+                resolve_refs.push(match color.resolve {
+                    Some((layout, _)) => {
+                        let reference = attachment_reference(
+                            descriptions.len(),
+                            layout,
+                            vk::ImageAspectFlags::COLOR,
+                        );
+                        descriptions.push(resolve_description(color.format, layout));
+                        reference
+                    }
+                    None => vk::AttachmentReference2::builder()
+                        .attachment(vk::ATTACHMENT_UNUSED)
+                        .build(),
+                });
+            }
+        }
+
+        let depth_ref = key.depth.as_ref().map(|depth| {
+            let reference = attachment_reference(
+                descriptions.len(),
+                depth.layout,
+                vk::ImageAspectFlags::DEPTH,
+            );
+            descriptions.push(attachment_description(depth));
+            reference
+        });
+        let depth_resolve = key.depth.as_ref().and_then(|depth| {
+            depth.resolve.map(|(layout, mode)| {
+                let reference =
+                    attachment_reference(descriptions.len(), layout, vk::ImageAspectFlags::DEPTH);
+                descriptions.push(resolve_description(depth.format, layout));
+                (reference, mode)
+            })
+        });
+
+        let mut depth_resolve_info = depth_resolve.as_ref().map(|(reference, mode)| {
+            vk::SubpassDescriptionDepthStencilResolve::builder()
+                .depth_resolve_mode(*mode)
+                .stencil_resolve_mode(vk::ResolveModeFlags::NONE)
+                .depth_stencil_resolve_attachment(reference)
+                .build()
+        });
+
+        let mut subpass = vk::SubpassDescription2::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .color_attachments(&color_refs);
+        if !resolve_refs.is_empty() {
+            subpass = subpass.resolve_attachments(&resolve_refs);
+        }
+        if let Some(depth_ref) = depth_ref.as_ref() {
+            subpass = subpass.depth_stencil_attachment(depth_ref);
+        }
+        if let Some(depth_resolve_info) = depth_resolve_info.as_mut() {
+            subpass = subpass.push_next(depth_resolve_info);
+        }
+        let subpasses = [subpass.build()];
+
+        let create_info = vk::RenderPassCreateInfo2::builder()
+            .attachments(&descriptions)
+            .subpasses(&subpasses);
+
+        unsafe {
+            self.loader
+                .create_render_pass2(&create_info, None)
+                .expect("创建render pass失败！")
+        }
+    }
+
+    fn get_framebuffer(
+        &self,
+        device: &Device,
+        render_pass: vk::RenderPass,
+        views: Vec<vk::ImageView>,
+        extent: vk::Extent2D,
+    ) -> vk::Framebuffer {
+        let key = FramebufferKey {
+            render_pass,
+            views,
+            extent: (extent.width, extent.height),
+        };
+        let mut framebuffers = self.framebuffers.lock().unwrap_or_else(|e| e.into_inner());
+        *framebuffers.entry(key).or_insert_with_key(|key| {
+            let create_info = vk::FramebufferCreateInfo::builder()
+                .render_pass(render_pass)
+                .attachments(&key.views)
+                .width(extent.width)
+                .height(extent.height)
+                .layers(1);
+            unsafe {
+                device
+                    .create_framebuffer(&create_info, None)
+                    .expect("创建framebuffer失败！")
+            }
+        })
+    }
+}
+
+impl RenderingBackend for RenderPassBackend {
+    fn cmd_begin_rendering(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        desc: &RenderingDesc,
+    ) {
+        let render_pass = self.get_render_pass(RenderPassKey {
+            colors: desc.color_attachments.iter().map(|a| a.key()).collect(),
+            depth: desc.depth_attachment.map(|a| a.key()),
+        });
+
+        let mut views = Vec::new();
+        let mut clear_values = Vec::new();
+        for color in desc.color_attachments.iter() {
+            views.push(color.view);
+            clear_values.push(color.clear_value);
+        }
+        for resolve in desc.color_attachments.iter().filter_map(|c| c.resolve) {
+            views.push(resolve.view);
+            clear_values.push(vk::ClearValue::default());
+        }
+        if let Some(depth) = desc.depth_attachment {
+            views.push(depth.view);
+            clear_values.push(depth.clear_value);
+            if let Some(resolve) = depth.resolve {
+                views.push(resolve.view);
+                clear_values.push(vk::ClearValue::default());
+            }
+        }
+
+        let framebuffer = self.get_framebuffer(device, render_pass, views, desc.extent);
+        let begin_info = vk::RenderPassBeginInfo::builder()
+            .render_pass(render_pass)
+            .framebuffer(framebuffer)
+            .render_area(desc.render_area())
+            .clear_values(&clear_values);
+
+        unsafe {
+            device.cmd_begin_render_pass(command_buffer, &begin_info, vk::SubpassContents::INLINE)
+        };
+    }
+
+    fn cmd_end_rendering(&self, device: &Device, command_buffer: vk::CommandBuffer) {
+        unsafe { device.cmd_end_render_pass(command_buffer) };
+    }
+
+    //单subpass的render pass兼容性只看格式和采样数，读写方式和resolve不影响
+    fn pipeline_render_pass(
+        &self,
+        _device: &Device,
+        color_formats: &[vk::Format],
+        depth_format: Option<vk::Format>,
+        samples: vk::SampleCountFlags,
+    ) -> Option<vk::RenderPass> {
+        let attachment = |format, layout| AttachmentKey {
+            format,
+            samples,
+            layout,
+            load_op: vk::AttachmentLoadOp::DONT_CARE,
+            store_op: vk::AttachmentStoreOp::DONT_CARE,
+            resolve: None,
+        };
+        Some(
+            self.get_render_pass(RenderPassKey {
+                colors: color_formats
+                    .iter()
+                    .map(|format| attachment(*format, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL))
+                    .collect(),
+                depth: depth_format.map(|format| {
+                    attachment(format, vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+                }),
+            }),
+        )
+    }
+
+    fn release_image_view(&self, device: &Device, view: vk::ImageView) {
+        let mut framebuffers = self.framebuffers.lock().unwrap_or_else(|e| e.into_inner());
+        framebuffers.retain(|key, framebuffer| {
+            let used = key.views.contains(&view);
+            if used {
+                unsafe { device.destroy_framebuffer(*framebuffer, None) };
+            }
+            !used
+        });
+    }
+
+    fn destroy(&self, device: &Device) {
+        let mut framebuffers = self.framebuffers.lock().unwrap_or_else(|e| e.into_inner());
+        for (_, framebuffer) in framebuffers.drain() {
+            unsafe { device.destroy_framebuffer(framebuffer, None) };
+        }
+        let mut render_passes = self.render_passes.lock().unwrap_or_else(|e| e.into_inner());
+        for (_, render_pass) in render_passes.drain() {
+            unsafe { device.destroy_render_pass(render_pass, None) };
+        }
+    }
+}
+
+fn attachment_description(attachment: &AttachmentKey) -> vk::AttachmentDescription2 {
+    vk::AttachmentDescription2::builder()
+        .format(attachment.format)
+        .samples(attachment.samples)
+        .load_op(attachment.load_op)
+        .store_op(attachment.store_op)
+        .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+        .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+        .initial_layout(attachment.layout)
+        .final_layout(attachment.layout)
+        .build()
+}
+
+fn resolve_description(format: vk::Format, layout: vk::ImageLayout) -> vk::AttachmentDescription2 {
+    vk::AttachmentDescription2::builder()
+        .format(format)
+        .samples(vk::SampleCountFlags::TYPE_1)
+        .load_op(vk::AttachmentLoadOp::DONT_CARE)
+        .store_op(vk::AttachmentStoreOp::STORE)
+        .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+        .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+        .initial_layout(layout)
+        .final_layout(layout)
+        .build()
+}
+
+fn attachment_reference(
+    index: usize,
+    layout: vk::ImageLayout,
+    aspect_mask: vk::ImageAspectFlags,
+) -> vk::AttachmentReference2 {
+    vk::AttachmentReference2::builder()
+        .attachment(index as u32)
+        .layout(layout)
+        .aspect_mask(aspect_mask)
+        .build()
+}
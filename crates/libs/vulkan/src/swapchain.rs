@@ -1,5 +1,6 @@
 use super::{
     context::Context,
+    error::VkError,
     image::{create_image_view, Image},
 };
 use ash::{
@@ -23,6 +24,8 @@ pub struct Swapchain {
     properties: SwapchainProperties,
     images: Vec<Image>,
     image_views: Vec<vk::ImageView>,
+    //image能否作为拷贝源，用于截图
+    readable: bool,
 }
 
 impl Swapchain {
@@ -31,7 +34,7 @@ impl Swapchain {
         swapchain_support_details: SwapchainSupportDetails,
         dimensions: [u32; 2],
         preferred_vsync: bool,
-    ) -> Self {
+    ) -> Result<Self, VkError> {
         log::debug!("创建swapchain");
 
         let properties =
@@ -41,6 +44,8 @@ impl Swapchain {
         let present_mode = properties.present_mode;
         let extent = properties.extent;
         let min_image_count = properties.min_image_count;
+        let usage =
+            swapchain_image_usage(swapchain_support_details.capabilities.supported_usage_flags);
 
         let queue_families_indices = context.queue_families_indices();
         let graphics = queue_families_indices.graphics_index;
@@ -55,7 +60,7 @@ impl Swapchain {
                 .image_color_space(format.color_space)
                 .image_extent(extent)
                 .image_array_layers(1)
-                .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT);
+                .image_usage(usage);
 
             builder = if graphics != present {
                 builder
@@ -73,24 +78,29 @@ impl Swapchain {
         };
 
         let swapchain = SwapchainLoader::new(context.instance(), context.device());
-        let swapchain_khr = unsafe {
-            swapchain
-                .create_swapchain(&create_info, None)
-                .expect("swapchain创建失败！")
-        };
-        let images = unsafe {
-            swapchain
-                .get_swapchain_images(swapchain_khr)
-                .expect("swapchain images无法获取！")
-                .iter()
-                .map(|image| {
-                    Image::create_swapchain_image(Arc::clone(&context), *image, properties)
-                })
-                .collect::<Vec<_>>()
+        let swapchain_khr = unsafe { swapchain.create_swapchain(&create_info, None)? };
+        let images = match unsafe { swapchain.get_swapchain_images(swapchain_khr) } {
+            Ok(images) => images,
+            Err(error) => {
+                unsafe { swapchain.destroy_swapchain(swapchain_khr, None) };
+                return Err(error.into());
+            }
         };
+        let images = images
+            .iter()
+            .map(|image| Image::create_swapchain_image(Arc::clone(&context), *image, properties))
+            .collect::<Vec<_>>();
         let views = Self::create_views(context.device(), &images, properties);
 
-        let swapchain = Self::new(context, swapchain, swapchain_khr, properties, images, views);
+        let swapchain = Self::new(
+            context,
+            swapchain,
+            swapchain_khr,
+            properties,
+            images,
+            views,
+            usage.contains(vk::ImageUsageFlags::TRANSFER_SRC),
+        );
 
         log::debug!(
             "swapchain创建成功.\n\tFormat: {:?}\n\tColorSpace: {:?}\n\tPresentMode: {:?}\n\tExtent: {:?}\n\tImageCount: {:?}",
@@ -101,7 +111,7 @@ impl Swapchain {
             swapchain.image_count(),
         );
 
-        swapchain
+        Ok(swapchain)
     }
 
     fn create_views(
@@ -133,6 +143,7 @@ impl Swapchain {
         properties: SwapchainProperties,
         images: Vec<Image>,
         image_views: Vec<vk::ImageView>,
+        readable: bool,
     ) -> Self {
         Self {
             context,
@@ -141,6 +152,7 @@ impl Swapchain {
             properties,
             images,
             image_views,
+            readable,
         }
     }
 }
@@ -165,6 +177,11 @@ impl Swapchain {
     pub fn image_views(&self) -> &[vk::ImageView] {
         &self.image_views
     }
+
+    //为false时表面不支持拷贝交换链image，无法截图
+    pub fn is_readable(&self) -> bool {
+        self.readable
+    }
 }
 
 impl Swapchain {
@@ -185,19 +202,22 @@ impl Swapchain {
     }
 
     pub fn present(&self, present_info: &vk::PresentInfoKHR) -> VkResult<bool> {
+        let _queue_guard = self.context.lock_queue();
         unsafe {
             self.swapchain
                 .queue_present(self.context.present_queue(), present_info)
         }
     }
 
+    //可以重复调用，已经销毁的对象不会再次销毁
     pub fn destroy(&mut self) {
+        self.image_views
+            .drain(..)
+            .for_each(|v| self.context.destroy_image_view(v));
         unsafe {
-            self.image_views
-                .iter()
-                .for_each(|v| self.context.device().destroy_image_view(*v, None));
             self.swapchain.destroy_swapchain(self.swapchain_khr, None);
         }
+        self.swapchain_khr = vk::SwapchainKHR::null();
     }
 }
 
@@ -208,30 +228,22 @@ pub struct SwapchainSupportDetails {
 }
 
 impl SwapchainSupportDetails {
-    pub fn new(device: vk::PhysicalDevice, surface: &Surface, surface_khr: vk::SurfaceKHR) -> Self {
-        let capabilities = unsafe {
-            surface
-                .get_physical_device_surface_capabilities(device, surface_khr)
-                .expect("获取physical device surface capabilities失败！")
-        };
-
-        let formats = unsafe {
-            surface
-                .get_physical_device_surface_formats(device, surface_khr)
-                .expect("获取physical device surface formats失败！")
-        };
-
-        let present_modes = unsafe {
-            surface
-                .get_physical_device_surface_present_modes(device, surface_khr)
-                .expect("获取physical device surface present modes失败！")
-        };
-
-        Self {
+    pub fn new(
+        device: vk::PhysicalDevice,
+        surface: &Surface,
+        surface_khr: vk::SurfaceKHR,
+    ) -> Result<Self, VkError> {
+        let capabilities =
+            unsafe { surface.get_physical_device_surface_capabilities(device, surface_khr)? };
+        let formats = unsafe { surface.get_physical_device_surface_formats(device, surface_khr)? };
+        let present_modes =
+            unsafe { surface.get_physical_device_surface_present_modes(device, surface_khr)? };
+
+        Ok(Self {
             capabilities,
             formats,
             present_modes,
-        }
+        })
     }
 
     pub fn get_ideal_swapchain_properties(
@@ -262,13 +274,30 @@ impl SwapchainSupportDetails {
             };
         }
 
-        *available_formats
+        //gamma校正在final pass的shader里做，必须选UNORM格式，否则会校正两次
+        let preferred = [vk::Format::B8G8R8A8_UNORM, vk::Format::R8G8B8A8_UNORM]
             .iter()
-            .find(|format| {
-                format.format == vk::Format::B8G8R8A8_UNORM
-                    && format.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
-            })
-            .unwrap_or(&available_formats[0])
+            .find_map(|&preferred| {
+                available_formats.iter().find(|format| {
+                    format.format == preferred
+                        && format.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
+                })
+            });
+        if let Some(format) = preferred {
+            return *format;
+        }
+
+        //选设备时已经保证格式列表不为空
+        let fallback = available_formats
+            .iter()
+            .find(|format| !is_srgb_format(format.format))
+            .unwrap_or(&available_formats[0]);
+        log::warn!(
+            "表面不支持8位UNORM格式，使用{:?} {:?}，画面颜色可能不准确",
+            fallback.format,
+            fallback.color_space
+        );
+        *fallback
     }
 
     fn choose_swapchain_surface_present_mode(
@@ -316,3 +345,82 @@ impl SwapchainSupportDetails {
         preferred
     }
 }
+
+//表面支持时额外加上TRANSFER_SRC，截图时从交换链image拷贝
+fn swapchain_image_usage(supported: vk::ImageUsageFlags) -> vk::ImageUsageFlags {
+    let readback = supported & vk::ImageUsageFlags::TRANSFER_SRC;
+    vk::ImageUsageFlags::COLOR_ATTACHMENT | readback
+}
+
+//硬件会在写入时做gamma编码的格式
+fn is_srgb_format(format: vk::Format) -> bool {
+    matches!(
+        format,
+        vk::Format::B8G8R8A8_SRGB
+            | vk::Format::R8G8B8A8_SRGB
+            | vk::Format::A8B8G8R8_SRGB_PACK32
+            | vk::Format::B8G8R8_SRGB
+            | vk::Format::R8G8B8_SRGB
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transfer_src_is_requested_only_when_supported() {
+        let usage = swapchain_image_usage(
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+        );
+        assert!(usage.contains(vk::ImageUsageFlags::TRANSFER_SRC));
+
+        //不支持时退回只作为颜色附件，不能请求表面没有的用途
+        let usage = swapchain_image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT);
+        assert_eq!(usage, vk::ImageUsageFlags::COLOR_ATTACHMENT);
+    }
+
+    fn surface_format(format: vk::Format) -> vk::SurfaceFormatKHR {
+        vk::SurfaceFormatKHR {
+            format,
+            color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+        }
+    }
+
+    fn choose(formats: &[vk::Format]) -> vk::Format {
+        let formats = formats
+            .iter()
+            .copied()
+            .map(surface_format)
+            .collect::<Vec<_>>();
+        SwapchainSupportDetails::choose_swapchain_surface_format(&formats).format
+    }
+
+    #[test]
+    fn unorm_formats_are_preferred_over_srgb() {
+        use vk::Format as F;
+        assert_eq!(
+            choose(&[F::B8G8R8A8_SRGB, F::B8G8R8A8_UNORM]),
+            F::B8G8R8A8_UNORM
+        );
+        assert_eq!(
+            choose(&[F::R8G8B8A8_SRGB, F::R8G8B8A8_UNORM]),
+            F::R8G8B8A8_UNORM
+        );
+        assert_eq!(choose(&[F::UNDEFINED]), F::B8G8R8A8_UNORM);
+    }
+
+    //没有首选格式时跳过sRGB格式，都是sRGB时才用第一个
+    #[test]
+    fn fallback_skips_srgb_formats() {
+        use vk::Format as F;
+        assert_eq!(
+            choose(&[F::B8G8R8A8_SRGB, F::A2B10G10R10_UNORM_PACK32]),
+            F::A2B10G10R10_UNORM_PACK32
+        );
+        assert_eq!(
+            choose(&[F::R8G8B8A8_SRGB, F::B8G8R8A8_SRGB]),
+            F::R8G8B8A8_SRGB
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use super::{buffer::*, context::*, image::*, util::*};
+use super::{buffer::*, context::*, error::VkError, image::*, util::*};
 use ash::vk;
 use std::{mem::size_of, sync::Arc};
 
@@ -9,12 +9,15 @@ pub struct Texture {
     pub sampler: Option<vk::Sampler>,
 }
 
+//设备支持时默认使用的各向异性等级
+pub const DEFAULT_MAX_ANISOTROPY: f32 = 16.0;
+
 #[derive(Copy, Clone, Debug)]
 pub struct SamplerParameters {
     pub mag_filter: vk::Filter,
     pub min_filter: vk::Filter,
-    pub anisotropy_enabled: bool,
-    pub max_anisotropy: f32,
+    //None关闭各向异性过滤，创建sampler时限制在设备上限内
+    pub max_anisotropy: Option<f32>,
 }
 
 impl Default for SamplerParameters {
@@ -22,8 +25,7 @@ impl Default for SamplerParameters {
         Self {
             mag_filter: vk::Filter::LINEAR,
             min_filter: vk::Filter::LINEAR,
-            anisotropy_enabled: false,
-            max_anisotropy: 0.0,
+            max_anisotropy: None,
         }
     }
 }
@@ -50,7 +52,7 @@ impl Texture {
         data: &[u8],
         linear: bool,
         image_name: std::ffi::CString,
-    ) -> Self {
+    ) -> Result<Self, VkError> {
         let (texture, _) = context.execute_one_time_commands(|command_buffer| {
             Self::cmd_from_rgba(
                 context,
@@ -61,8 +63,8 @@ impl Texture {
                 linear,
                 image_name,
             )
-        });
-        texture
+        })?;
+        Ok(texture)
     }
 
     pub fn cmd_from_rgba(
@@ -73,7 +75,7 @@ impl Texture {
         data: &[u8],
         linear: bool,
         texture_name: std::ffi::CString,
-    ) -> (Self, Buffer) {
+    ) -> Result<(Self, Buffer), VkError> {
         let max_mip_levels = ((width.min(height) as f32).log2().floor() + 1.0) as u32;
         let extent = vk::Extent2D { width, height };
         let image_size = (data.len() * size_of::<u8>()) as vk::DeviceSize;
@@ -84,6 +86,7 @@ impl Texture {
             image_size,
             vk::BufferUsageFlags::TRANSFER_SRC,
             vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            &format!("{}.Staging", texture_name.to_string_lossy()),
         );
 
         unsafe {
@@ -95,6 +98,29 @@ impl Texture {
             .then_some(vk::Format::R8G8B8A8_UNORM)
             .unwrap_or(vk::Format::R8G8B8A8_SRGB);
 
+        //可能失败的对象都在录制命令之前创建，出错时不会提交引用已销毁image的命令
+        let sampler = {
+            let anisotropy = context.get_supported_anisotropy(Some(DEFAULT_MAX_ANISOTROPY));
+            let sampler_info = vk::SamplerCreateInfo::builder()
+                .mag_filter(vk::Filter::LINEAR)
+                .min_filter(vk::Filter::LINEAR)
+                .address_mode_u(vk::SamplerAddressMode::REPEAT)
+                .address_mode_v(vk::SamplerAddressMode::REPEAT)
+                .address_mode_w(vk::SamplerAddressMode::REPEAT)
+                .anisotropy_enable(anisotropy.is_some())
+                .max_anisotropy(anisotropy.unwrap_or(0.0))
+                .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
+                .unnormalized_coordinates(false)
+                .compare_enable(false)
+                .compare_op(vk::CompareOp::ALWAYS)
+                .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
+                .mip_lod_bias(0.0)
+                .min_lod(0.0)
+                .max_lod(max_mip_levels as _);
+
+            unsafe { device.create_sampler(&sampler_info, None)? }
+        };
+
         let image = Image::create(
             Arc::clone(context),
             ImageParameters {
@@ -108,7 +134,10 @@ impl Texture {
                 ..Default::default()
             },
             texture_name,
-        );
+        )
+        .inspect_err(|_| {
+            unsafe { device.destroy_sampler(sampler, None) };
+        })?;
 
         {
             image.cmd_transition_image_layout(
@@ -124,34 +153,100 @@ impl Texture {
 
         let image_view = image.create_view(vk::ImageViewType::TYPE_2D, vk::ImageAspectFlags::COLOR);
 
-        let sampler = {
-            let sampler_info = vk::SamplerCreateInfo::builder()
-                .mag_filter(vk::Filter::LINEAR)
-                .min_filter(vk::Filter::LINEAR)
-                .address_mode_u(vk::SamplerAddressMode::REPEAT)
-                .address_mode_v(vk::SamplerAddressMode::REPEAT)
-                .address_mode_w(vk::SamplerAddressMode::REPEAT)
-                .anisotropy_enable(true)
-                .max_anisotropy(16.0)
-                .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
-                .unnormalized_coordinates(false)
-                .compare_enable(false)
-                .compare_op(vk::CompareOp::ALWAYS)
-                .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
-                .mip_lod_bias(0.0)
-                .min_lod(0.0)
-                .max_lod(max_mip_levels as _);
+        let texture = Texture::new(Arc::clone(context), image, image_view, Some(sampler));
 
-            unsafe {
-                device
-                    .create_sampler(&sampler_info, None)
-                    .expect("Failed to create sampler")
-            }
-        };
+        Ok((texture, buffer))
+    }
 
-        let texture = Texture::new(Arc::clone(context), image, image_view, Some(sampler));
+    //上传已经生成好的整条mip链，levels[0]为原图，每层RGBA8紧密排列，不再用blit生成mip
+    pub fn from_rgba_mips(
+        context: &Arc<Context>,
+        width: u32,
+        height: u32,
+        levels: &[&[u8]],
+        linear: bool,
+        image_name: std::ffi::CString,
+    ) -> Result<Self, VkError> {
+        let (texture, _) = context.execute_one_time_commands(|command_buffer| {
+            Self::cmd_from_rgba_mips(
+                context,
+                command_buffer,
+                width,
+                height,
+                levels,
+                linear,
+                image_name,
+            )
+        })?;
+        Ok(texture)
+    }
+
+    pub fn cmd_from_rgba_mips(
+        context: &Arc<Context>,
+        command_buffer: vk::CommandBuffer,
+        width: u32,
+        height: u32,
+        levels: &[&[u8]],
+        linear: bool,
+        image_name: std::ffi::CString,
+    ) -> Result<(Self, Buffer), VkError> {
+        let extent = vk::Extent2D { width, height };
+        let image_size = levels.iter().map(|level| level.len()).sum::<usize>();
 
-        (texture, buffer)
+        let mut buffer = Buffer::create(
+            Arc::clone(context),
+            image_size as vk::DeviceSize,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            &format!("{}.Staging", image_name.to_string_lossy()),
+        );
+
+        let ptr = buffer.map_memory() as *mut u8;
+        let mut offset = 0;
+        for level in levels {
+            unsafe { std::ptr::copy_nonoverlapping(level.as_ptr(), ptr.add(offset), level.len()) };
+            offset += level.len();
+        }
+
+        let format = linear
+            .then_some(vk::Format::R8G8B8A8_UNORM)
+            .unwrap_or(vk::Format::R8G8B8A8_SRGB);
+
+        let image = Image::create(
+            Arc::clone(context),
+            ImageParameters {
+                mem_properties: vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                extent,
+                format,
+                mip_levels: levels.len().max(1) as _,
+                usage: vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
+                ..Default::default()
+            },
+            image_name,
+        )?;
+
+        image.cmd_transition_image_layout(
+            command_buffer,
+            vk::ImageLayout::UNDEFINED,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        );
+        let mut offset = 0;
+        for (level, data) in levels.iter().enumerate() {
+            image.cmd_copy_buffer_to_mip(command_buffer, &buffer, level as _, offset as _);
+            offset += data.len();
+        }
+        image.cmd_transition_image_layout(
+            command_buffer,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        );
+
+        let image_view = image.create_view(vk::ImageViewType::TYPE_2D, vk::ImageAspectFlags::COLOR);
+
+        Ok((
+            Texture::new(Arc::clone(context), image, image_view, None),
+            buffer,
+        ))
     }
 
     pub fn from_rgba_32(
@@ -162,7 +257,7 @@ impl Texture {
         data: &[f32],
         sampler_parameters: Option<SamplerParameters>,
         image_name: std::ffi::CString,
-    ) -> Self {
+    ) -> Result<Self, VkError> {
         let max_mip_levels = if with_mipmaps {
             ((width.min(height) as f32).log2().floor() + 1.0) as u32
         } else {
@@ -177,6 +272,7 @@ impl Texture {
             image_size,
             vk::BufferUsageFlags::TRANSFER_SRC,
             vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            &format!("{}.Staging", image_name.to_string_lossy()),
         );
 
         unsafe {
@@ -202,8 +298,8 @@ impl Texture {
                 usage,
                 ..Default::default()
             },
-            image_name
-        );
+            image_name,
+        )?;
 
         {
             image.transition_image_layout(
@@ -227,14 +323,15 @@ impl Texture {
 
         let sampler = {
             let params = sampler_parameters.unwrap_or_default();
+            let anisotropy = context.get_supported_anisotropy(params.max_anisotropy);
             let sampler_info = vk::SamplerCreateInfo::builder()
                 .mag_filter(params.mag_filter)
                 .min_filter(params.min_filter)
                 .address_mode_u(vk::SamplerAddressMode::REPEAT)
                 .address_mode_v(vk::SamplerAddressMode::REPEAT)
                 .address_mode_w(vk::SamplerAddressMode::REPEAT)
-                .anisotropy_enable(params.anisotropy_enabled)
-                .max_anisotropy(params.max_anisotropy)
+                .anisotropy_enable(anisotropy.is_some())
+                .max_anisotropy(anisotropy.unwrap_or(0.0))
                 .border_color(vk::BorderColor::FLOAT_OPAQUE_BLACK)
                 .unnormalized_coordinates(false)
                 .compare_enable(false)
@@ -244,14 +341,22 @@ impl Texture {
                 .min_lod(0.0)
                 .max_lod(max_mip_levels as _);
 
+            //image随作用域释放，视图需要手动销毁
             unsafe {
                 device
                     .create_sampler(&sampler_info, None)
-                    .expect("sampler创建失败！")
+                    .inspect_err(|_| {
+                        device.destroy_image_view(image_view, None);
+                    })?
             }
         };
 
-        Texture::new(Arc::clone(context), image, image_view, Some(sampler))
+        Ok(Texture::new(
+            Arc::clone(context),
+            image,
+            image_view,
+            Some(sampler),
+        ))
     }
 
     pub fn create_renderable_cubemap(
@@ -260,7 +365,7 @@ impl Texture {
         mip_levels: u32,
         format: vk::Format,
         image_name: std::ffi::CString,
-    ) -> Self {
+    ) -> Result<Self, VkError> {
         let extent = vk::Extent2D {
             width: size,
             height: size,
@@ -283,8 +388,8 @@ impl Texture {
                 create_flags: vk::ImageCreateFlags::CUBE_COMPATIBLE,
                 ..Default::default()
             },
-            image_name
-        );
+            image_name,
+        )?;
 
         image.transition_image_layout(
             vk::ImageLayout::UNDEFINED,
@@ -311,14 +416,22 @@ impl Texture {
                 .min_lod(0.0)
                 .max_lod(mip_levels as _);
 
+            //image随作用域释放，视图需要手动销毁
             unsafe {
                 device
                     .create_sampler(&sampler_info, None)
-                    .expect("sampler创建失败！")
+                    .inspect_err(|_| {
+                        device.destroy_image_view(image_view, None);
+                    })?
             }
         };
 
-        Texture::new(Arc::clone(context), image, image_view, Some(sampler))
+        Ok(Texture::new(
+            Arc::clone(context),
+            image,
+            image_view,
+            Some(sampler),
+        ))
     }
 
     pub fn create_renderable_texture(
@@ -327,7 +440,7 @@ impl Texture {
         height: u32,
         format: vk::Format,
         image_name: std::ffi::CString,
-    ) -> Self {
+    ) -> Result<Self, VkError> {
         let extent = vk::Extent2D { width, height };
 
         let device = context.device();
@@ -341,8 +454,8 @@ impl Texture {
                 usage: vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::COLOR_ATTACHMENT,
                 ..Default::default()
             },
-            image_name
-        );
+            image_name,
+        )?;
 
         image.transition_image_layout(
             vk::ImageLayout::UNDEFINED,
@@ -369,14 +482,22 @@ impl Texture {
                 .min_lod(0.0)
                 .max_lod(1.0);
 
+            //image随作用域释放，视图需要手动销毁
             unsafe {
                 device
                     .create_sampler(&sampler_info, None)
-                    .expect("sampler创建失败！")
+                    .inspect_err(|_| {
+                        device.destroy_image_view(image_view, None);
+                    })?
             }
         };
 
-        Texture::new(Arc::clone(context), image, image_view, Some(sampler))
+        Ok(Texture::new(
+            Arc::clone(context),
+            image,
+            image_view,
+            Some(sampler),
+        ))
     }
 }
 
@@ -386,7 +507,7 @@ impl Drop for Texture {
             if let Some(sampler) = self.sampler.take() {
                 self.context.device().destroy_sampler(sampler, None);
             }
-            self.context.device().destroy_image_view(self.view, None);
         }
+        self.context.destroy_image_view(self.view);
     }
 }
@@ -13,3 +13,17 @@ pub unsafe fn mem_copy_aligned<T: Copy>(ptr: *mut c_void, alignment: DeviceSize,
     let mut align = Align::new(ptr, alignment, size);
     align.copy_from_slice(data);
 }
+
+//按对齐把迭代器的元素直接写入映射内存，省去先收集成切片的临时分配，超出size的元素被忽略
+pub unsafe fn mem_write_aligned<T: Copy>(
+    ptr: *mut c_void,
+    alignment: DeviceSize,
+    size: DeviceSize,
+    data: impl IntoIterator<Item = T>,
+) {
+    let mut align = Align::new(ptr, alignment, size);
+    align
+        .iter_mut()
+        .zip(data)
+        .for_each(|(dst, value)| *dst = value);
+}
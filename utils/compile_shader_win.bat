@@ -3,8 +3,22 @@ cd..
 cd crates/fate_renderer/shaders
 glslc.exe model.vert -o model.vert.spv
 glslc.exe model.frag -o model.frag.spv
+glslc.exe gbuffer.vert -o gbuffer.vert.spv
+glslc.exe gbuffer.frag -o gbuffer.frag.spv
 glslc.exe shadowcaster.vert -o shadowcaster.vert.spv
 glslc.exe shadowcaster.frag -o shadowcaster.frag.spv
 glslc.exe final.frag -o final.frag.spv
 glslc.exe ssao.frag -o ssao.frag.spv
-glslc.exe fxaa.frag -o fxaa.frag.spv
\ No newline at end of file
+glslc.exe blur.frag -o blur.frag.spv
+glslc.exe fxaa.frag -o fxaa.frag.spv
+glslc.exe debug_overlay.frag -o debug_overlay.frag.spv
+glslc.exe grid.vert -o grid.vert.spv
+glslc.exe grid.frag -o grid.frag.spv
+glslc.exe skinning.comp -o skinning.comp.spv
+glslc.exe light_culling.comp -o light_culling.comp.spv
+glslc.exe occlusion.vert -o occlusion.vert.spv
+glslc.exe occlusion.frag -o occlusion.frag.spv
+glslc.exe shadow_catcher.vert -o shadow_catcher.vert.spv
+glslc.exe shadow_catcher.frag -o shadow_catcher.frag.spv
+glslc.exe shadow_catcher_gbuffer.frag -o shadow_catcher_gbuffer.frag.spv
+glslc.exe outline.frag -o outline.frag.spv